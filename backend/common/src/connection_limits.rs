@@ -0,0 +1,187 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Keep track of how many connections are open from each address and in total,
+/// so that we can refuse new ones once some limit has been reached.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimits(Arc<ConnectionLimitsInner>);
+
+#[derive(Debug)]
+struct ConnectionLimitsInner {
+    max_per_addr: usize,
+    max_total: usize,
+    exempt: HashSet<IpAddr>,
+    open: Mutex<OpenConnections>,
+}
+
+#[derive(Debug, Default)]
+struct OpenConnections {
+    per_addr: HashMap<IpAddr, usize>,
+    total: usize,
+}
+
+/// Why a connection was refused by [`ConnectionLimits::open`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionRefused {
+    /// The address already has as many connections open as it's allowed.
+    TooManyFromAddr,
+    /// As many connections are open as are allowed in total.
+    TooManyOverall,
+}
+
+impl ConnectionLimits {
+    /// Allow up to `max_per_addr` connections from each address, and up to `max_total`
+    /// connections overall. Any number of connections are allowed from the exempt
+    /// addresses, though they still count towards the total.
+    pub fn new(
+        max_per_addr: usize,
+        max_total: usize,
+        exempt: impl IntoIterator<Item = IpAddr>,
+    ) -> ConnectionLimits {
+        ConnectionLimits(Arc::new(ConnectionLimitsInner {
+            max_per_addr,
+            max_total,
+            exempt: exempt.into_iter().collect(),
+            open: Mutex::new(OpenConnections::default()),
+        }))
+    }
+
+    /// Try to open a new connection from the address given. If there are already too many
+    /// open, this returns why not. Otherwise, the connection counts as open until the returned
+    /// [`OpenConnection`] is dropped.
+    pub fn open(&self, addr: IpAddr) -> Result<OpenConnection, ConnectionRefused> {
+        let exempt = self.0.exempt.contains(&addr);
+
+        let mut open = self.0.open.lock().unwrap();
+        if !exempt {
+            if open.total >= self.0.max_total {
+                return Err(ConnectionRefused::TooManyOverall);
+            }
+            let count = open.per_addr.get(&addr).copied().unwrap_or(0);
+            if count >= self.0.max_per_addr {
+                return Err(ConnectionRefused::TooManyFromAddr);
+            }
+            open.per_addr.insert(addr, count + 1);
+        }
+        open.total += 1;
+
+        Ok(OpenConnection {
+            limits: self.clone(),
+            addr: (!exempt).then_some(addr),
+        })
+    }
+
+    /// How many connections are open right now, including those from exempt addresses.
+    pub fn open_count(&self) -> usize {
+        self.0.open.lock().unwrap().total
+    }
+
+    /// The most connections that are allowed open at once.
+    pub fn max_total(&self) -> usize {
+        self.0.max_total
+    }
+
+    fn close(&self, addr: Option<IpAddr>) {
+        let mut open = self.0.open.lock().unwrap();
+        open.total -= 1;
+        if let Some(addr) = addr {
+            if let Some(count) = open.per_addr.get_mut(&addr) {
+                *count -= 1;
+                if *count == 0 {
+                    open.per_addr.remove(&addr);
+                }
+            }
+        }
+    }
+}
+
+/// A connection that is counted against the limits it was opened from,
+/// until this is dropped.
+#[derive(Debug)]
+pub struct OpenConnection {
+    limits: ConnectionLimits,
+    // `None` for exempt addresses, which only count towards the total.
+    addr: Option<IpAddr>,
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.limits.close(self.addr);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn connections_are_limited_per_addr() {
+        let limits = ConnectionLimits::new(2, usize::MAX, None);
+
+        let a1 = limits.open(ip("1.1.1.1")).unwrap();
+        let _a2 = limits.open(ip("1.1.1.1")).unwrap();
+        assert_eq!(
+            limits.open(ip("1.1.1.1")).unwrap_err(),
+            ConnectionRefused::TooManyFromAddr
+        );
+
+        // Other addresses have their own limit:
+        assert!(limits.open(ip("2.2.2.2")).is_ok());
+
+        // Closing a connection makes room for another:
+        drop(a1);
+        assert!(limits.open(ip("1.1.1.1")).is_ok());
+    }
+
+    #[test]
+    fn connections_are_limited_in_total() {
+        let limits = ConnectionLimits::new(usize::MAX, 2, [ip("9.9.9.9")]);
+
+        let a = limits.open(ip("1.1.1.1")).unwrap();
+        let _b = limits.open(ip("2.2.2.2")).unwrap();
+        assert_eq!(
+            limits.open(ip("3.3.3.3")).unwrap_err(),
+            ConnectionRefused::TooManyOverall
+        );
+
+        // Exempt addresses can still connect, and count towards the total:
+        let _exempt = limits.open(ip("9.9.9.9")).unwrap();
+        assert_eq!(limits.open_count(), 3);
+
+        drop(a);
+        assert_eq!(limits.open_count(), 2);
+        assert!(limits.open(ip("3.3.3.3")).is_err());
+    }
+
+    #[test]
+    fn exempt_addrs_are_not_limited() {
+        let limits = ConnectionLimits::new(1, usize::MAX, [ip("1.1.1.1")]);
+
+        let _conns: Vec<_> = (0..10)
+            .map(|_| limits.open(ip("1.1.1.1")).unwrap())
+            .collect();
+        let _b = limits.open(ip("2.2.2.2")).unwrap();
+        assert!(limits.open(ip("2.2.2.2")).is_err());
+    }
+}
@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use serde::Serialize;
+
 /// This stores items in contiguous memory, making a note of free
 /// slots when items are removed again so that they can be reused.
 ///
@@ -32,6 +34,32 @@ pub struct DenseMap<Id, T> {
     _id_type: std::marker::PhantomData<Id>,
 }
 
+/// How the slots of a [`DenseMap`] are being used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DenseMapStats {
+    /// How many slots there are, whether or not they hold an item.
+    pub slots: usize,
+    /// How many slots hold an item.
+    pub occupied: usize,
+    /// How many slots are empty, having held an item that was removed.
+    pub empty: usize,
+    /// How many of the empty slots come after the last item, and so would be
+    /// freed by [`DenseMap::compact`].
+    pub trailing_empty: usize,
+    /// How many slots the memory backing the map has room for.
+    pub capacity: usize,
+}
+
+impl<Id, T> Default for DenseMap<Id, T>
+where
+    Id: From<usize> + Copy,
+    usize: From<Id>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<Id, T> DenseMap<Id, T>
 where
     Id: From<usize> + Copy,
@@ -108,6 +136,7 @@ where
             .filter_map(|(id, item)| Some((id.into(), item.as_mut()?)))
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> impl Iterator<Item = (Id, T)> {
         self.items
             .into_iter()
@@ -123,6 +152,42 @@ where
         self.len() == 0
     }
 
+    /// Free the memory held by empty slots at the end of the map, which can build up
+    /// after lots of items are added and then removed again. The Ids of the items left
+    /// are unchanged, so empty slots between them are kept. Returns how many slots were
+    /// freed.
+    pub fn compact(&mut self) -> usize {
+        let len = self
+            .items
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(0, |i| i + 1);
+        let freed = self.items.len() - len;
+        self.items.truncate(len);
+        self.retired.retain(|&id| id < len);
+        self.items.shrink_to_fit();
+        self.retired.shrink_to_fit();
+        freed
+    }
+
+    /// How the slots of the map are being used, to see how much memory is held
+    /// by empty slots.
+    pub fn stats(&self) -> DenseMapStats {
+        let trailing_empty = self
+            .items
+            .iter()
+            .rev()
+            .take_while(|item| item.is_none())
+            .count();
+        DenseMapStats {
+            slots: self.items.len(),
+            occupied: self.len(),
+            empty: self.retired.len(),
+            trailing_empty,
+            capacity: self.items.capacity(),
+        }
+    }
+
     /// Return the next Id that will be assigned.
     pub fn next_id(&self) -> usize {
         match self.retired.last() {
@@ -162,4 +227,55 @@ mod test {
 
         assert_eq!(map.len(), 0);
     }
+
+    #[test]
+    fn compacting_frees_empty_slots_at_the_end() {
+        let mut map = DenseMap::<usize, usize>::new();
+        let ids: Vec<_> = (0..5).map(|n| map.add(n)).collect();
+        map.remove(ids[1]);
+        map.remove(ids[3]);
+        map.remove(ids[4]);
+
+        assert_eq!(map.compact(), 2);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(ids[2]), Some(&2));
+        assert_eq!(map.compact(), 0);
+
+        // The empty slot that's left is reused first:
+        assert_eq!(map.add(10), ids[1]);
+        assert_eq!(map.add(11), 3);
+    }
+
+    #[test]
+    fn stats_count_empty_slots() {
+        let mut map = DenseMap::<usize, usize>::new();
+        let ids: Vec<_> = (0..5).map(|n| map.add(n)).collect();
+        map.remove(ids[1]);
+        map.remove(ids[3]);
+        map.remove(ids[4]);
+
+        let stats = map.stats();
+        assert_eq!(
+            (
+                stats.slots,
+                stats.occupied,
+                stats.empty,
+                stats.trailing_empty
+            ),
+            (5, 2, 3, 2)
+        );
+        assert!(stats.capacity >= 5);
+
+        map.compact();
+        let stats = map.stats();
+        assert_eq!(
+            (
+                stats.slots,
+                stats.occupied,
+                stats.empty,
+                stats.trailing_empty
+            ),
+            (3, 2, 1, 0)
+        );
+    }
 }
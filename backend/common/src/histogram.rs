@@ -0,0 +1,148 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Count how many values fall into each of a fixed set of buckets, and keep a
+/// running total of them, in the manner of a Prometheus histogram. Each bucket
+/// is identified by its (inclusive) upper bound.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    bounds: &'static [u64],
+    counts: Box<[u64]>,
+    sum: u64,
+    count: u64,
+}
+
+impl Histogram {
+    /// Create a histogram with the given bucket upper bounds, which are
+    /// expected to be in ascending order. Values above the largest bound
+    /// are only counted in the total.
+    pub fn new(bounds: &'static [u64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()].into_boxed_slice(),
+            sum: 0,
+            count: 0,
+        }
+    }
+
+    pub fn record(&mut self, val: u64) {
+        if let Some(idx) = self.bounds.iter().position(|&bound| val <= bound) {
+            self.counts[idx] += 1;
+        }
+        self.sum = self.sum.saturating_add(val);
+        self.count += 1;
+    }
+
+    /// Each bucket upper bound, along with how many values were less than
+    /// or equal to it.
+    pub fn cumulative_buckets(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.bounds
+            .iter()
+            .zip(self.counts.iter())
+            .scan(0, |total, (&bound, &count)| {
+                *total += count;
+                Some((bound, *total))
+            })
+    }
+
+    /// The sum of all of the values recorded.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// How many values have been recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// A [`Histogram`] that values can be recorded in from several threads at once
+/// without taking a lock.
+#[derive(Debug)]
+pub struct AtomicHistogram {
+    bounds: &'static [u64],
+    counts: Box<[AtomicU64]>,
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl AtomicHistogram {
+    /// Create a histogram with the given bucket upper bounds, which are
+    /// expected to be in ascending order.
+    pub fn new(bounds: &'static [u64]) -> Self {
+        AtomicHistogram {
+            bounds,
+            counts: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, val: u64) {
+        if let Some(idx) = self.bounds.iter().position(|&bound| val <= bound) {
+            self.counts[idx].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum.fetch_add(val, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A copy of what's been recorded so far. Values being recorded at the same time
+    /// may be counted in some parts of it and not others.
+    pub fn snapshot(&self) -> Histogram {
+        Histogram {
+            bounds: self.bounds,
+            counts: self
+                .counts
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .collect(),
+            sum: self.sum.load(Ordering::Relaxed),
+            count: self.count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn buckets_are_cumulative() {
+        let mut histogram = Histogram::new(&[10, 100, 1000]);
+        for val in [5, 10, 50, 500, 5000] {
+            histogram.record(val);
+        }
+
+        let buckets: Vec<_> = histogram.cumulative_buckets().collect();
+        assert_eq!(buckets, vec![(10, 2), (100, 3), (1000, 4)]);
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.sum(), 5565);
+    }
+
+    #[test]
+    fn atomic_histograms_record_the_same() {
+        let mut histogram = Histogram::new(&[10, 100, 1000]);
+        let atomic = AtomicHistogram::new(&[10, 100, 1000]);
+        for val in [5, 10, 50, 500, 5000] {
+            histogram.record(val);
+            atomic.record(val);
+        }
+
+        assert_eq!(atomic.snapshot(), histogram);
+    }
+}
@@ -17,8 +17,12 @@
 use futures::io::{BufReader, BufWriter};
 use hyper::server::conn::AddrStream;
 use hyper::{Body, Request, Response, Server};
+use soketto::extension::deflate::Deflate;
+use soketto::extension::{Extension, Param};
 use std::future::Future;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
 
 /// A convenience function to start up a Hyper server and handle requests.
@@ -32,7 +36,9 @@ where
         let addr = addr.remote_addr();
         async move { Ok::<_, hyper::Error>(hyper::service::service_fn(move |r| handler(addr, r))) }
     });
-    let server = Server::bind(&addr).serve(service);
+    let server = Server::try_bind(&addr)
+        .map_err(|e| anyhow::anyhow!("Cannot listen on {addr}: {e}"))?
+        .serve(service);
 
     log::info!("listening on http://{}", server.local_addr());
     server.await?;
@@ -49,6 +55,71 @@ pub fn upgrade_to_websocket<H, F>(req: Request<Body>, on_upgrade: H) -> hyper::R
 where
     H: 'static + Send + FnOnce(WsSender, WsReceiver) -> F,
     F: Send + Future<Output = ()>,
+{
+    upgrade(req, None, None, false, |sender, receiver, _| {
+        on_upgrade(sender, receiver)
+    })
+}
+
+/// Like [`upgrade_to_websocket`], but if a subprotocol is given, we tell the client that
+/// we've agreed to speak it. See [`requested_protocols`] for what the client asked for.
+pub fn upgrade_to_websocket_with_protocol<H, F>(
+    req: Request<Body>,
+    protocol: Option<&'static str>,
+    on_upgrade: H,
+) -> hyper::Response<Body>
+where
+    H: 'static + Send + FnOnce(WsSender, WsReceiver) -> F,
+    F: Send + Future<Output = ()>,
+{
+    upgrade(req, None, protocol, false, |sender, receiver, _| {
+        on_upgrade(sender, receiver)
+    })
+}
+
+/// Like [`upgrade_to_websocket_with_protocol`], but if `compressible` is true and the client
+/// offers to compress messages with permessage-deflate (RFC 7692), we agree to. The handler
+/// is then given a [`WsCompression`] to choose which of the messages it sends are compressed.
+pub fn upgrade_to_compressible_websocket<H, F>(
+    req: Request<Body>,
+    protocol: Option<&'static str>,
+    compressible: bool,
+    on_upgrade: H,
+) -> hyper::Response<Body>
+where
+    H: 'static + Send + FnOnce(WsSender, WsReceiver, Option<WsCompression>) -> F,
+    F: Send + Future<Output = ()>,
+{
+    upgrade(req, None, protocol, compressible, on_upgrade)
+}
+
+/// Like [`upgrade_to_websocket`], but if a maximum message size (in bytes) is given,
+/// the [`WsReceiver`] will refuse to receive any larger messages (or frames), rather
+/// than buffering them.
+pub fn upgrade_to_websocket_with_max_message_size<H, F>(
+    req: Request<Body>,
+    max_message_size: Option<usize>,
+    on_upgrade: H,
+) -> hyper::Response<Body>
+where
+    H: 'static + Send + FnOnce(WsSender, WsReceiver) -> F,
+    F: Send + Future<Output = ()>,
+{
+    upgrade(req, max_message_size, None, false, |sender, receiver, _| {
+        on_upgrade(sender, receiver)
+    })
+}
+
+fn upgrade<H, F>(
+    req: Request<Body>,
+    max_message_size: Option<usize>,
+    protocol: Option<&'static str>,
+    compressible: bool,
+    on_upgrade: H,
+) -> hyper::Response<Body>
+where
+    H: 'static + Send + FnOnce(WsSender, WsReceiver, Option<WsCompression>) -> F,
+    F: Send + Future<Output = ()>,
 {
     if !is_upgrade_request(&req) {
         return basic_response(400, "Expecting WebSocket upgrade headers");
@@ -81,11 +152,25 @@ where
     let accept_key = generate_websocket_accept_key(key.as_bytes(), &mut accept_key_buf);
 
     // Tell the client that we accept the upgrade-to-WS request:
-    let response = Response::builder()
+    let mut response = Response::builder()
         .status(hyper::StatusCode::SWITCHING_PROTOCOLS)
         .header(hyper::header::CONNECTION, "upgrade")
         .header(hyper::header::UPGRADE, "websocket")
-        .header("Sec-WebSocket-Accept", accept_key)
+        .header("Sec-WebSocket-Accept", accept_key);
+    if let Some(protocol) = protocol {
+        response = response.header(hyper::header::SEC_WEBSOCKET_PROTOCOL, protocol);
+    }
+    let deflate = match compressible {
+        true => negotiate_deflate(&req),
+        false => None,
+    };
+    if let Some(deflate) = &deflate {
+        response = response.header(
+            hyper::header::SEC_WEBSOCKET_EXTENSIONS,
+            extension_header(deflate),
+        );
+    }
+    let response = response
         .body(Body::empty())
         .expect("bug: failed to build response");
 
@@ -105,15 +190,141 @@ where
             soketto::handshake::Server::new(BufReader::new(BufWriter::new(stream.compat())));
 
         // Get hold of a way to send and receive messages:
-        let (sender, receiver) = server.into_builder().finish();
+        let mut builder = server.into_builder();
+        if let Some(max_message_size) = max_message_size {
+            builder.set_max_message_size(max_message_size);
+            builder.set_max_frame_size(max_message_size);
+        }
+        let compression = deflate.map(|deflate| {
+            let compression = WsCompression::default();
+            let extension: Box<dyn Extension + Send> = Box::new(SelectiveDeflate {
+                deflate,
+                compress: compression.clone(),
+            });
+            builder.add_extensions([extension]);
+            compression
+        });
+        let (sender, receiver) = builder.finish();
 
         // Pass these to our when-upgraded handler:
-        on_upgrade(sender, receiver).await;
+        on_upgrade(sender, receiver, compression).await;
     });
 
     response
 }
 
+/// Which of the messages sent over a websocket that's agreed to use permessage-deflate
+/// are compressed. Nothing is compressed until asked for.
+#[derive(Debug, Clone, Default)]
+pub struct WsCompression(Arc<AtomicBool>);
+
+impl WsCompression {
+    /// Compress (or stop compressing) the messages sent from now on.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed);
+    }
+    fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Permessage-deflate, except that only the messages sent while [`WsCompression`] is
+/// enabled are compressed. Each message is compressed on its own, so the rest can be left
+/// alone. Messages from the client are decompressed as usual.
+#[derive(Debug)]
+struct SelectiveDeflate {
+    deflate: Deflate,
+    compress: WsCompression,
+}
+
+impl Extension for SelectiveDeflate {
+    fn is_enabled(&self) -> bool {
+        self.deflate.is_enabled()
+    }
+    fn name(&self) -> &str {
+        self.deflate.name()
+    }
+    fn params(&self) -> &[Param<'_>] {
+        self.deflate.params()
+    }
+    fn configure(&mut self, params: &[Param<'_>]) -> Result<(), soketto::BoxedError> {
+        self.deflate.configure(params)
+    }
+    fn encode(
+        &mut self,
+        header: &mut soketto::base::Header,
+        data: &mut soketto::Storage,
+    ) -> Result<(), soketto::BoxedError> {
+        match self.compress.is_enabled() {
+            true => self.deflate.encode(header, data),
+            false => Ok(()),
+        }
+    }
+    fn decode(
+        &mut self,
+        header: &mut soketto::base::Header,
+        data: &mut Vec<u8>,
+    ) -> Result<(), soketto::BoxedError> {
+        self.deflate.decode(header, data)
+    }
+    fn reserved_bits(&self) -> (bool, bool, bool) {
+        self.deflate.reserved_bits()
+    }
+}
+
+/// Accept the first permessage-deflate offer in a websocket upgrade request whose
+/// parameters we can agree to, if there is one.
+fn negotiate_deflate<B>(request: &hyper::Request<B>) -> Option<Deflate> {
+    request
+        .headers()
+        .get_all(hyper::header::SEC_WEBSOCKET_EXTENSIONS)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(','))
+        .filter_map(parse_extension_offer)
+        .filter(|(name, _)| *name == "permessage-deflate")
+        .find_map(|(_, params)| {
+            let mut deflate = Deflate::new(soketto::Mode::Server);
+            // Parameters we don't understand leave it disabled rather than erroring:
+            deflate.configure(&params).ok()?;
+            deflate.is_enabled().then_some(deflate)
+        })
+}
+
+/// Parse an offer like `permessage-deflate; client_max_window_bits=10` into the name of
+/// the extension and its parameters.
+fn parse_extension_offer(offer: &str) -> Option<(&str, Vec<Param<'_>>)> {
+    let mut parts = offer.split(';').map(|part| part.trim());
+    let name = parts.next().filter(|name| !name.is_empty())?;
+    let params = parts
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (name, value) = match part.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (part, None),
+            };
+            let mut param = Param::new(name);
+            param.set_value(value);
+            param
+        })
+        .collect();
+    Some((name, params))
+}
+
+/// The `Sec-WebSocket-Extensions` header telling the client that we've agreed to an extension.
+fn extension_header(extension: &impl Extension) -> String {
+    let mut header = extension.name().to_owned();
+    for param in extension.params() {
+        header.push_str("; ");
+        header.push_str(param.name());
+        if let Some(value) = param.value() {
+            header.push('=');
+            header.push_str(value);
+        }
+    }
+    header
+}
+
 /// A helper to return a basic HTTP response with a code and text body.
 fn basic_response(code: u16, msg: impl AsRef<str>) -> Response<Body> {
     Response::builder()
@@ -136,11 +347,24 @@ fn generate_websocket_accept_key<'a>(key: &[u8], buf: &'a mut [u8; 32]) -> &'a [
 
     use base64::{engine::general_purpose, Engine as _};
     let n = general_purpose::STANDARD
-        .encode_slice(&d, buf)
+        .encode_slice(d, buf)
         .expect("Sha1 must fit into [u8; 32]");
     &buf[..n]
 }
 
+/// The subprotocols that a websocket upgrade request asks for, most preferred first.
+pub fn requested_protocols<B>(request: &hyper::Request<B>) -> Vec<&str> {
+    request
+        .headers()
+        .get_all(hyper::header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(','))
+        .map(|protocol| protocol.trim())
+        .filter(|protocol| !protocol.is_empty())
+        .collect()
+}
+
 /// Check if a request is a websocket upgrade request.
 fn is_upgrade_request<B>(request: &hyper::Request<B>) -> bool {
     header_contains_value(request.headers(), hyper::header::CONNECTION, b"upgrade")
@@ -173,3 +397,51 @@ fn header_contains_value(
     }
     false
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request_with_extensions(extensions: &[&str]) -> hyper::Request<()> {
+        let mut request = hyper::Request::builder();
+        for extension in extensions {
+            request = request.header(hyper::header::SEC_WEBSOCKET_EXTENSIONS, *extension);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[test]
+    fn deflate_is_agreed_to_if_offered() {
+        let request = request_with_extensions(&[
+            "x-webkit-deflate-frame",
+            "permessage-deflate; client_max_window_bits, permessage-deflate",
+        ]);
+        let deflate = negotiate_deflate(&request).expect("deflate was offered");
+        assert_eq!(extension_header(&deflate), "permessage-deflate");
+
+        let request = request_with_extensions(&[
+            "permessage-deflate; server_no_context_takeover; server_max_window_bits=\"10\"",
+        ]);
+        let deflate = negotiate_deflate(&request).expect("deflate was offered");
+        assert_eq!(
+            extension_header(&deflate),
+            "permessage-deflate; server_no_context_takeover; server_max_window_bits=10"
+        );
+    }
+
+    #[test]
+    fn deflate_offers_we_cannot_agree_to_are_skipped() {
+        assert!(negotiate_deflate(&request_with_extensions(&[])).is_none());
+        let request = request_with_extensions(&["permessage-deflate; some_new_param"]);
+        assert!(negotiate_deflate(&request).is_none());
+        let request = request_with_extensions(&[
+            "permessage-deflate; server_max_window_bits=20",
+            "permessage-deflate; client_no_context_takeover",
+        ]);
+        let deflate = negotiate_deflate(&request).expect("second offer is fine");
+        assert_eq!(
+            extension_header(&deflate),
+            "permessage-deflate; client_no_context_takeover"
+        );
+    }
+}
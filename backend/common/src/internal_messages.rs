@@ -48,6 +48,10 @@ pub enum FromShardAggregator {
     },
     /// Inform the telemetry core that a node has been removed
     RemoveNode { local_id: ShardNodeId },
+    /// A node sent a message that we couldn't make sense of. If the connection
+    /// it came in on has told us about a node, this is the ID of that node, so
+    /// that the message can be counted against its chain.
+    MalformedMessage { local_id: Option<ShardNodeId> },
 }
 
 /// Message sent form the telemetry core to a telemetry shard
@@ -64,4 +68,34 @@ pub enum FromTelemetryCore {
 pub enum MuteReason {
     Overquota,
     ChainNotAllowed,
+    NodeNameNotAllowed,
+    InvalidGenesisHash,
+    Stale,
+    DuplicateNode,
+    OperatorOverCap,
+    OutdatedVersion,
+}
+
+impl MuteReason {
+    /// Was the node refused when it was first added, rather than muted later on (for
+    /// instance for going stale)?
+    pub fn is_rejection(&self) -> bool {
+        !matches!(self, MuteReason::Stale)
+    }
+}
+
+impl std::fmt::Display for MuteReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            MuteReason::Overquota => "chain overquota",
+            MuteReason::ChainNotAllowed => "chain not allowed",
+            MuteReason::NodeNameNotAllowed => "node name not allowed",
+            MuteReason::InvalidGenesisHash => "invalid genesis hash",
+            MuteReason::Stale => "node went stale",
+            MuteReason::DuplicateNode => "duplicate node",
+            MuteReason::OperatorOverCap => "too many nodes from this operator",
+            MuteReason::OutdatedVersion => "node version too old",
+        };
+        f.write_str(reason)
+    }
 }
@@ -21,13 +21,16 @@ pub mod internal_messages;
 pub mod node_message;
 pub mod node_types;
 pub mod ready_chunks_all;
+pub mod real_ip;
 pub mod rolling_total;
 pub mod time;
 pub mod ws_client;
 
 mod assign_id;
+mod connection_limits;
 mod dense_map;
 mod either_sink;
+mod histogram;
 mod mean_list;
 mod most_seen;
 mod multi_map_unique;
@@ -35,8 +38,10 @@ mod num_stats;
 
 // Export a bunch of common bits at the top level for ease of import:
 pub use assign_id::AssignId;
-pub use dense_map::DenseMap;
+pub use connection_limits::{ConnectionLimits, ConnectionRefused, OpenConnection};
+pub use dense_map::{DenseMap, DenseMapStats};
 pub use either_sink::EitherSink;
+pub use histogram::{AtomicHistogram, Histogram};
 pub use mean_list::MeanList;
 pub use most_seen::MostSeen;
 pub use multi_map_unique::MultiMapUnique;
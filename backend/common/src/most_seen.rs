@@ -55,19 +55,44 @@ impl<T> MostSeen<T> {
     pub fn best_count(&self) -> usize {
         self.current_count
     }
+    /// The fraction (from 0 to 1) of everything seen that is the best item. This is 0
+    /// if nothing has been seen.
+    pub fn best_share(&self) -> f64 {
+        let total = self.current_count + self.others.values().sum::<usize>();
+        if total == 0 {
+            0.0
+        } else {
+            self.current_count as f64 / total as f64
+        }
+    }
 }
 
 impl<T: Hash + Eq + Clone> MostSeen<T> {
+    /// How many times the item has been seen.
+    pub fn count(&self, item: &T) -> usize {
+        if &self.current_best == item {
+            self.current_count
+        } else {
+            self.others.get(item).copied().unwrap_or(0)
+        }
+    }
     pub fn insert(&mut self, item: &T) -> ChangeResult {
+        self.insert_weighted(item, 1)
+    }
+    pub fn remove(&mut self, item: &T) -> ChangeResult {
+        self.remove_weighted(item, 1)
+    }
+    /// Count the item as having been seen `weight` times at once.
+    pub fn insert_weighted(&mut self, item: &T, weight: usize) -> ChangeResult {
         if &self.current_best == item {
             // Item already the best one; bump count.
-            self.current_count += 1;
+            self.current_count += weight;
             return ChangeResult::NoChange;
         }
 
         // Item not the best; increment count in map
         let item_count = self.others.entry(item.clone()).or_default();
-        *item_count += 1;
+        *item_count += weight;
 
         // Is item now the best?
         if *item_count > self.current_count {
@@ -85,10 +110,11 @@ impl<T: Hash + Eq + Clone> MostSeen<T> {
             ChangeResult::NoChange
         }
     }
-    pub fn remove(&mut self, item: &T) -> ChangeResult {
+    /// Undo seeing the item `weight` times at once.
+    pub fn remove_weighted(&mut self, item: &T, weight: usize) -> ChangeResult {
         if &self.current_best == item {
             // Item already the best one; reduce count (don't allow to drop below 0)
-            self.current_count = self.current_count.saturating_sub(1);
+            self.current_count = self.current_count.saturating_sub(weight);
 
             // Is there a new best?
             let other_best = self.others.iter().max_by_key(|f| f.1);
@@ -124,7 +150,10 @@ impl<T: Hash + Eq + Clone> MostSeen<T> {
 
         // Item is in the map; not the best anyway. decrement count.
         if let Some(count) = self.others.get_mut(item) {
-            *count += 1;
+            *count = count.saturating_sub(weight);
+            if *count == 0 {
+                self.others.remove(item);
+            }
         }
         ChangeResult::NoChange
     }
@@ -169,6 +198,16 @@ mod test {
         assert_eq!(*a.best(), "Second");
     }
 
+    #[test]
+    fn items_are_counted() {
+        let mut a: MostSeen<&str> = MostSeen::default();
+        a.insert_weighted(&"First", 2);
+        a.insert(&"Second");
+        assert_eq!(a.count(&"First"), 2);
+        assert_eq!(a.count(&"Second"), 1);
+        assert_eq!(a.count(&"Third"), 0);
+    }
+
     #[test]
     fn removing_doesnt_underflow() {
         let mut a: MostSeen<&str> = MostSeen::new("First");
@@ -203,6 +242,23 @@ mod test {
         assert_eq!(a.best_count(), 0);
     }
 
+    #[test]
+    fn keeps_track_of_best_share() {
+        let mut a: MostSeen<&str> = MostSeen::default();
+        assert_eq!(a.best_share(), 0.0);
+
+        a.insert(&"First");
+        assert_eq!(a.best_share(), 1.0);
+
+        a.insert(&"First");
+        a.insert(&"First");
+        a.insert(&"Second");
+        assert_eq!(a.best_share(), 0.75);
+
+        a.remove(&"Second");
+        assert_eq!(a.best_share(), 1.0);
+    }
+
     #[test]
     fn it_tracks_best_on_insert() {
         let mut a: MostSeen<&str> = MostSeen::default();
@@ -249,4 +305,26 @@ mod test {
         assert_eq!(a.best_count(), 2);
         assert_eq!(*a.best(), "First"); // First is now ahead
     }
+
+    #[test]
+    fn it_tracks_best_by_weight() {
+        let mut a: MostSeen<&str> = MostSeen::default();
+        a.insert(&"First");
+        a.insert(&"First");
+        a.insert(&"First");
+
+        let res = a.insert_weighted(&"Second", 5);
+        assert!(res.has_changed());
+        assert_eq!(*a.best(), "Second");
+        assert_eq!(a.best_count(), 5);
+
+        // Taking some of the weight away again:
+        let res = a.remove_weighted(&"Second", 3);
+        assert!(res.has_changed());
+        assert_eq!(*a.best(), "First");
+        assert_eq!(a.best_count(), 3);
+
+        a.remove_weighted(&"Second", 10);
+        assert_eq!(a.best_share(), 1.0);
+    }
 }
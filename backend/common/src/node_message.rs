@@ -54,13 +54,17 @@ impl From<NodeMessage> for Payload {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Payload {
     SystemConnected(SystemConnected),
     SystemInterval(SystemInterval),
-    BlockImport(Block),
+    BlockImport(BlockImport),
     NotifyFinalized(Finalized),
     AfgAuthoritySet(AfgAuthoritySet),
     HwBench(NodeHwBench),
+    /// Not sent by nodes; the shard measures the round trip time (in ms) of a websocket
+    /// ping to the connection that the node is on.
+    PingLatency(u64),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -79,6 +83,41 @@ pub struct SystemInterval {
     pub finalized_hash: Option<BlockHash>,
     pub block: Option<Block>,
     pub used_state_cache_size: Option<f32>,
+    pub import_queue_depth: Option<u64>,
+    /// How many transactions were in the node's best block.
+    pub block_txcount: Option<u64>,
+    /// How many blocks the node has imported since it started.
+    pub block_imports: Option<u64>,
+    /// How many blocks the node has failed to import since it started.
+    pub block_import_failures: Option<u64>,
+    /// How many seconds the node's process has been running for.
+    pub uptime: Option<u64>,
+    /// The index of the session that the node's best block is in.
+    pub session: Option<u64>,
+    /// How many bytes the node's database is taking up on disk.
+    pub disk_used: Option<u64>,
+    /// How many bytes are free on the disk that the node's database is on.
+    pub disk_free: Option<u64>,
+    /// How far (in ms) the node's clock was ahead of the shard's when it sent this,
+    /// going by the time it stamped the message with; negative if it was behind. This
+    /// includes however long the message took to arrive.
+    pub clock_skew: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockImport {
+    pub block: Block,
+    /// Who authored the block, if the node tells us.
+    pub author: Option<Box<str>>,
+}
+
+impl From<Block> for BlockImport {
+    fn from(block: Block) -> Self {
+        BlockImport {
+            block,
+            author: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -103,12 +142,20 @@ pub struct NodeHwBench {
 impl Payload {
     pub fn best_block(&self) -> Option<&Block> {
         match self {
-            Payload::BlockImport(block) => Some(block),
+            Payload::BlockImport(import) => Some(&import.block),
             Payload::SystemInterval(SystemInterval { block, .. }) => block.as_ref(),
             _ => None,
         }
     }
 
+    /// Who authored the best block, if the node told us along with it.
+    pub fn block_author(&self) -> Option<&str> {
+        match self {
+            Payload::BlockImport(import) => import.author.as_deref(),
+            _ => None,
+        }
+    }
+
     pub fn finalized_block(&self) -> Option<Block> {
         match self {
             Payload::SystemInterval(ref interval) => Some(Block {
@@ -134,7 +181,7 @@ mod tests {
     // know whether things can (de)serialize to bincode or not at runtime without failing unless
     // we test the different types we want to (de)serialize ourselves. We just need to test each
     // type, not each variant.
-    fn bincode_can_serialize_and_deserialize<'de, T>(item: T)
+    fn bincode_can_serialize_and_deserialize<T>(item: T)
     where
         T: Serialize + serde::de::DeserializeOwned,
     {
@@ -160,6 +207,11 @@ mod tests {
                     startup_time: None,
                     sysinfo: None,
                     ip: Some("127.0.0.1".into()),
+                    protocol_version: None,
+                    relay_chain_genesis_hash: Some(BlockHash::zero()),
+                    para_id: Some(1000),
+                    role: None,
+                    custom: Default::default(),
                 },
             }),
         });
@@ -177,6 +229,15 @@ mod tests {
                 finalized_hash: None,
                 block: None,
                 used_state_cache_size: None,
+                import_queue_depth: None,
+                block_txcount: None,
+                block_imports: None,
+                block_import_failures: None,
+                uptime: None,
+                session: None,
+                disk_used: None,
+                disk_free: None,
+                clock_skew: None,
             }),
         });
     }
@@ -184,9 +245,12 @@ mod tests {
     #[test]
     fn bincode_can_serialize_and_deserialize_node_message_block_import() {
         bincode_can_serialize_and_deserialize(NodeMessage::V1 {
-            payload: Payload::BlockImport(Block {
-                hash: BlockHash([0; 32]),
-                height: 0,
+            payload: Payload::BlockImport(BlockImport {
+                block: Block {
+                    hash: BlockHash([0; 32]),
+                    height: 0,
+                },
+                author: Some("5GrwvaEF".into()),
             }),
         });
     }
@@ -20,6 +20,7 @@
 use arrayvec::ArrayString;
 use serde::ser::{SerializeTuple, Serializer};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 use crate::{time, MeanList};
 
@@ -43,6 +44,27 @@ pub struct NodeDetails {
     pub target_env: Option<Box<str>>,
     pub sysinfo: Option<NodeSysInfo>,
     pub ip: Option<Box<str>>,
+    /// The version of the telemetry protocol that the node sends messages in, like "v2".
+    /// This is filled in by the shard rather than sent by the node.
+    pub protocol_version: Option<Box<str>>,
+    /// If the node is running a parachain, the genesis hash of its relay chain.
+    pub relay_chain_genesis_hash: Option<BlockHash>,
+    /// If the node is running a parachain, its para ID.
+    pub para_id: Option<u32>,
+    /// Whether the node is a full node or a light client, if it says.
+    pub role: Option<NodeRole>,
+    /// Key/value pairs that the node operator attached to the node. The shard
+    /// limits how many there are and how long they are.
+    pub custom: BTreeMap<Box<str>, Box<str>>,
+}
+
+/// What sort of node a node is.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeRole {
+    /// A node that keeps the full state of the chain, including authorities.
+    Full,
+    /// A light client.
+    Light,
 }
 
 /// Hardware and software information for the node.
@@ -75,11 +97,13 @@ pub struct NodeHwBench {
     pub disk_random_write_score: Option<u64>,
 }
 
-/// A couple of node statistics.
+/// A few node statistics.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct NodeStats {
     pub peers: u64,
     pub txcount: u64,
+    /// How many blocks are waiting in the node's import queue.
+    pub import_queue: u64,
 }
 
 // # A note about serialization/deserialization of types in this file:
@@ -96,9 +120,12 @@ impl Serialize for NodeStats {
     where
         S: Serializer,
     {
-        let mut tup = serializer.serialize_tuple(2)?;
+        // The import queue was added last, in feed version 35, so that older frontends
+        // aren't confused by it:
+        let mut tup = serializer.serialize_tuple(3)?;
         tup.serialize_element(&self.peers)?;
         tup.serialize_element(&self.txcount)?;
+        tup.serialize_element(&self.import_queue)?;
         tup.end()
     }
 }
@@ -108,8 +135,49 @@ impl<'de> Deserialize<'de> for NodeStats {
     where
         D: serde::Deserializer<'de>,
     {
-        let (peers, txcount) = <(u64, u64)>::deserialize(deserializer)?;
-        Ok(NodeStats { peers, txcount })
+        let (peers, txcount, import_queue) = <(u64, u64, u64)>::deserialize(deserializer)?;
+        Ok(NodeStats {
+            peers,
+            txcount,
+            import_queue,
+        })
+    }
+}
+
+/// The node statistics that have changed since feeds were last told about them.
+/// Statistics that haven't changed are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PartialNodeStats {
+    pub peers: Option<u64>,
+    pub txcount: Option<u64>,
+    pub import_queue: Option<u64>,
+}
+
+impl Serialize for PartialNodeStats {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tup = serializer.serialize_tuple(3)?;
+        tup.serialize_element(&self.peers)?;
+        tup.serialize_element(&self.txcount)?;
+        tup.serialize_element(&self.import_queue)?;
+        tup.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for PartialNodeStats {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let (peers, txcount, import_queue) =
+            <(Option<u64>, Option<u64>, Option<u64>)>::deserialize(deserializer)?;
+        Ok(PartialNodeStats {
+            peers,
+            txcount,
+            import_queue,
+        })
     }
 }
 
@@ -117,6 +185,10 @@ impl<'de> Deserialize<'de> for NodeStats {
 #[derive(Default)]
 pub struct NodeIO {
     pub used_state_cache_size: MeanList<f32>,
+    /// How many bytes the node's database last said it's taking up on disk.
+    pub disk_used: Option<u64>,
+    /// How many bytes the node last said are free on the disk its database is on.
+    pub disk_free: Option<u64>,
 }
 
 impl Serialize for NodeIO {
@@ -124,9 +196,11 @@ impl Serialize for NodeIO {
     where
         S: Serializer,
     {
-        let mut tup = serializer.serialize_tuple(1)?;
+        let mut tup = serializer.serialize_tuple(3)?;
         // This is "one-way": we can't deserialize again from this to a MeanList:
         tup.serialize_element(self.used_state_cache_size.slice())?;
+        tup.serialize_element(&self.disk_used)?;
+        tup.serialize_element(&self.disk_free)?;
         tup.end()
     }
 }
@@ -178,6 +252,14 @@ pub struct NodeLocation {
     pub latitude: f32,
     pub longitude: f32,
     pub city: Box<str>,
+    /// ISO 3166-1 alpha-2 code of the country, if known. This isn't sent to feeds.
+    pub country: Option<Box<str>>,
+    /// Number of the autonomous system that the node's IP address belongs to, if known.
+    /// This isn't sent to feeds.
+    pub asn: Option<u32>,
+    /// The organisation behind that autonomous system (usually a hosting provider), if
+    /// known. This isn't sent to feeds.
+    pub provider: Option<Box<str>>,
 }
 
 impl Serialize for NodeLocation {
@@ -203,6 +285,9 @@ impl<'de> Deserialize<'de> for NodeLocation {
             latitude,
             longitude,
             city,
+            country: None,
+            asn: None,
+            provider: None,
         })
     }
 }
@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use num_traits::{Bounded, NumOps, Zero};
+use num_traits::{Bounded, NumOps, ToPrimitive, Zero};
 use std::convert::TryFrom;
 use std::iter::Sum;
 
@@ -57,6 +57,69 @@ impl<T: NumOps + Zero + Bounded + Copy + Sum + TryFrom<usize>> NumStats<T> {
         self.sum / cap
     }
 
+    /// The (population) standard deviation of the numbers that we're currently averaging
+    /// over, or 0 if there aren't any.
+    pub fn std_dev(&self) -> f64
+    where
+        T: ToPrimitive,
+    {
+        let len = self.len();
+        if len == 0 {
+            return 0.0;
+        }
+        let values = || self.stack[..len].iter().filter_map(|val| val.to_f64());
+        let mean = values().sum::<f64>() / len as f64;
+        let variance = values().map(|val| (val - mean).powi(2)).sum::<f64>() / len as f64;
+        variance.sqrt()
+    }
+
+    /// The numbers that we're currently averaging over, oldest first.
+    pub fn samples(&self) -> Vec<T> {
+        let len = self.stack.len();
+        let count = std::cmp::min(self.index, len);
+        (self.index - count..self.index)
+            .map(|i| self.stack[i % len])
+            .collect()
+    }
+
+    /// How many numbers we're currently averaging over.
+    pub fn len(&self) -> usize {
+        std::cmp::min(self.index, self.stack.len())
+    }
+
+    /// How many numbers we average over.
+    pub fn size(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Have we not been given any numbers yet?
+    pub fn is_empty(&self) -> bool {
+        self.index == 0
+    }
+
+    /// Have we been given enough numbers to fill the window?
+    pub fn is_full(&self) -> bool {
+        self.index >= self.stack.len()
+    }
+
+    /// Change how many numbers we average over, keeping as many of
+    /// the most recently pushed numbers as will fit.
+    pub fn resize(&mut self, size: usize) {
+        let len = self.stack.len();
+        let count = std::cmp::min(self.index, len);
+        let keep = std::cmp::min(count, size);
+
+        let mut stack = vec![T::zero(); size].into_boxed_slice();
+        for (i, slot) in stack.iter_mut().take(keep).enumerate() {
+            // Oldest first, so that the next push overwrites the oldest kept value:
+            *slot = self.stack[(self.index - keep + i) % len];
+        }
+
+        self.sum = stack.iter().copied().sum();
+        self.stack = stack;
+        self.index = keep;
+    }
+
     pub fn reset(&mut self) {
         self.index = 0;
         self.sum = T::zero();
@@ -98,6 +161,36 @@ mod tests {
         assert_eq!(stats.average(), 0);
     }
 
+    #[test]
+    fn calculates_std_dev_of_values_in_window() {
+        let mut stats: NumStats<u64> = NumStats::new(4);
+        assert_eq!(stats.std_dev(), 0.0);
+
+        stats.push(5);
+        stats.push(5);
+        assert_eq!(stats.std_dev(), 0.0);
+
+        for n in [2, 4, 4, 4, 5, 5, 7, 9] {
+            stats.push(n);
+        }
+        // Only the last 4 (5, 5, 7, 9) count, whose mean is 6.5:
+        assert_eq!(stats.std_dev(), 2.75f64.sqrt());
+    }
+
+    #[test]
+    fn samples_are_oldest_first() {
+        let mut stats: NumStats<u64> = NumStats::new(3);
+        assert_eq!(stats.samples(), Vec::<u64>::new());
+
+        stats.push(1);
+        stats.push(2);
+        assert_eq!(stats.samples(), vec![1, 2]);
+
+        stats.push(3);
+        stats.push(4);
+        assert_eq!(stats.samples(), vec![2, 3, 4]);
+    }
+
     #[test]
     fn resets_properly() {
         let mut stats: NumStats<u64> = NumStats::new(10);
@@ -111,10 +204,50 @@ mod tests {
         stats.reset();
 
         assert_eq!(stats.average(), 0);
+        assert_eq!(stats.len(), 0);
 
         stats.push(7);
         stats.push(3);
 
         assert_eq!(stats.average(), 5);
+        assert_eq!(stats.len(), 2);
+    }
+
+    #[test]
+    fn shrinking_keeps_most_recent_values() {
+        let mut stats: NumStats<u64> = NumStats::new(4);
+
+        for n in [1, 2, 3, 4, 5, 6] {
+            stats.push(n);
+        }
+
+        stats.resize(2);
+        assert_eq!(stats.size(), 2);
+        assert!(stats.is_full());
+        assert_eq!(stats.average(), 5);
+
+        // The oldest kept value (5) is replaced first:
+        stats.push(10);
+        assert_eq!(stats.average(), 8);
+    }
+
+    #[test]
+    fn growing_keeps_all_values() {
+        let mut stats: NumStats<u64> = NumStats::new(2);
+
+        for n in [1, 2, 3] {
+            stats.push(n);
+        }
+
+        stats.resize(4);
+        assert_eq!(stats.size(), 4);
+        assert!(!stats.is_full());
+        assert_eq!(stats.average(), 2);
+
+        stats.push(6);
+        assert_eq!(stats.average(), 3);
+        stats.push(7);
+        assert!(stats.is_full());
+        assert_eq!(stats.average(), 4);
     }
 }
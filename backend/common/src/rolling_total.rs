@@ -27,6 +27,12 @@ pub struct RollingTotalBuilder<Time: TimeSource = SystemTimeSource> {
     time_source: Time,
 }
 
+impl Default for RollingTotalBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RollingTotalBuilder {
     /// Build a [`RollingTotal`] struct. By default,
     /// the window_size is 10s, the granularity is 1s,
@@ -253,11 +259,7 @@ mod test {
         // Regardless of the exact time that's elapsed, we'll end up with buckets that
         // are exactly granularity spacing (or multiples of) apart.
         assert_eq!(
-            rolling_total
-                .averages()
-                .into_iter()
-                .copied()
-                .collect::<Vec<_>>(),
+            rolling_total.averages().iter().copied().collect::<Vec<_>>(),
             vec![
                 (start_time, 1),
                 (start_time + granularity, 2),
@@ -0,0 +1,67 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Follow the best and finalized blocks of a chain, and the nodes coming and going.
+//!
+//! ```sh
+//! cargo run --example watch_chain -- ws://localhost:8000/feed Polkadot
+//! ```
+//!
+//! Run it without a chain label to list the chains that the core knows about.
+
+use feed_client::{FeedClient, FeedMessage};
+use futures::StreamExt;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let uri = args
+        .next()
+        .unwrap_or_else(|| "ws://localhost:8000/feed".to_owned())
+        .parse()?;
+    let label = args.next();
+
+    let mut client = FeedClient::connect(&uri).await?;
+    println!("Connected to feed version {:?}", client.version());
+
+    let label = match label {
+        Some(label) => label,
+        None => {
+            for chain in client.chains() {
+                println!("{} ({} nodes)", chain.label, chain.node_count);
+            }
+            return Ok(());
+        }
+    };
+    let genesis_hash = client.subscribe_by_label(&label)?;
+    println!("Subscribed to {label} ({genesis_hash:#x})");
+
+    let mut messages = client.messages();
+    while let Some(message) = messages.next().await {
+        match message? {
+            FeedMessage::BestBlock { block_number, .. } => println!("Best block: #{block_number}"),
+            FeedMessage::BestFinalized { block_number, .. } => {
+                println!("Finalized block: #{block_number}")
+            }
+            FeedMessage::AddedNode { node_id, node, .. } => {
+                println!("Node {node_id} added: {}", node.name)
+            }
+            FeedMessage::RemovedNode { node_id } => println!("Node {node_id} removed"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
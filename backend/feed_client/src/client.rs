@@ -0,0 +1,167 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::feed_message::FeedMessage;
+use common::node_types::BlockHash;
+use common::ws_client;
+use futures::{channel::mpsc, stream::BoxStream, StreamExt};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Cannot connect to the feed: {0}")]
+    Connect(#[from] ws_client::ConnectError),
+    #[error("Cannot receive from the feed: {0}")]
+    Recv(#[from] ws_client::RecvError),
+    #[error("Cannot send to the feed: {0}")]
+    Send(#[from] mpsc::SendError),
+    #[error("Cannot decode feed messages: {0}")]
+    Decode(anyhow::Error),
+    #[error("The feed connection has closed")]
+    Closed,
+    #[error("No chain labelled '{0}' is listed")]
+    UnknownLabel(String),
+}
+
+/// A chain that the feed has told us about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chain {
+    pub label: String,
+    pub genesis_hash: BlockHash,
+    pub node_count: usize,
+}
+
+/// A connection to the feed of a telemetry core. It keeps track of the chains that the
+/// core lists as their messages go past, so that they can be subscribed to by label.
+pub struct FeedClient {
+    tx: ws_client::Sender,
+    rx: ws_client::Receiver,
+    /// The feed version that the core told us about when we connected.
+    version: Option<usize>,
+    /// The chains that the core currently lists.
+    chains: HashMap<BlockHash, Chain>,
+    /// Messages that have been received but not yet handed out.
+    buffered: VecDeque<FeedMessage>,
+}
+
+impl FeedClient {
+    /// Connect to the feed at the given URI, eg `ws://localhost:8000/feed`. This waits
+    /// for the core to send its feed version and the chains that it lists, so that chains
+    /// can be subscribed to by label straight away. Those messages are still handed back
+    /// by [`FeedClient::next_message`].
+    pub async fn connect(uri: &http::Uri) -> Result<FeedClient, Error> {
+        let (tx, rx) = ws_client::connect(uri).await?.into_channels();
+        let mut client = FeedClient {
+            tx,
+            rx,
+            version: None,
+            chains: HashMap::new(),
+            buffered: VecDeque::new(),
+        };
+        client.recv_messages().await?;
+        Ok(client)
+    }
+
+    /// The feed version that the core sent when we connected.
+    pub fn version(&self) -> Option<usize> {
+        self.version
+    }
+
+    /// The chains that the core lists, as far as the messages received so far go.
+    pub fn chains(&self) -> impl Iterator<Item = &Chain> {
+        self.chains.values()
+    }
+
+    /// Subscribe to the chain with the given genesis hash. The feed can only be subscribed
+    /// to one chain at a time, so this unsubscribes from any other chain.
+    pub fn subscribe(&self, genesis_hash: BlockHash) -> Result<(), Error> {
+        let command = format!("subscribe:{genesis_hash:#x}");
+        self.tx
+            .unbounded_send(ws_client::SentMessage::Text(command))?;
+        Ok(())
+    }
+
+    /// Subscribe to the listed chain with the given label, handing back its genesis hash.
+    /// If more than one chain has the label, the one with the most nodes is picked.
+    pub fn subscribe_by_label(&self, label: &str) -> Result<BlockHash, Error> {
+        let genesis_hash = self
+            .chains
+            .values()
+            .filter(|chain| chain.label == label)
+            .max_by_key(|chain| chain.node_count)
+            .map(|chain| chain.genesis_hash)
+            .ok_or_else(|| Error::UnknownLabel(label.to_owned()))?;
+        self.subscribe(genesis_hash)?;
+        Ok(genesis_hash)
+    }
+
+    /// Wait for the next message from the feed.
+    pub async fn next_message(&mut self) -> Result<FeedMessage, Error> {
+        loop {
+            if let Some(message) = self.buffered.pop_front() {
+                return Ok(message);
+            }
+            self.recv_messages().await?;
+        }
+    }
+
+    /// A stream of the messages from the feed, which ends once the connection closes.
+    pub fn messages(&mut self) -> BoxStream<'_, Result<FeedMessage, Error>> {
+        futures::stream::unfold(self, |client| async move {
+            match client.next_message().await {
+                Err(Error::Closed) => None,
+                message => Some((message, client)),
+            }
+        })
+        .boxed()
+    }
+
+    /// Receive the next batch of messages from the feed, noting any changes to the
+    /// chains and buffering them up to be handed out.
+    async fn recv_messages(&mut self) -> Result<(), Error> {
+        let messages = match self.rx.next().await.ok_or(Error::Closed)?? {
+            ws_client::RecvMessage::Binary(bytes) => FeedMessage::from_bytes(&bytes),
+            ws_client::RecvMessage::Text(text) => FeedMessage::from_bytes(text.as_bytes()),
+        }
+        .map_err(Error::Decode)?;
+
+        for message in &messages {
+            match message {
+                FeedMessage::Version(version) => self.version = Some(*version),
+                FeedMessage::AddedChain {
+                    name,
+                    genesis_hash,
+                    node_count,
+                } => {
+                    self.chains.insert(
+                        *genesis_hash,
+                        Chain {
+                            label: name.clone(),
+                            genesis_hash: *genesis_hash,
+                            node_count: *node_count,
+                        },
+                    );
+                }
+                FeedMessage::RemovedChain { genesis_hash } => {
+                    self.chains.remove(genesis_hash);
+                }
+                _ => {}
+            }
+        }
+        self.buffered.extend(messages);
+        Ok(())
+    }
+}
@@ -17,17 +17,20 @@
 use anyhow::Context;
 use common::node_types::{
     BlockDetails, BlockHash, BlockNumber, NodeHwBench, NodeLocation, NodeStats, NodeSysInfo,
-    Timestamp,
+    PartialNodeStats, Timestamp,
 };
 use serde_json::value::RawValue;
+use std::collections::BTreeMap;
 
 #[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum FeedMessage {
     Version(usize),
     BestBlock {
         block_number: BlockNumber,
         timestamp: Timestamp,
         avg_block_time: Option<u64>,
+        block_hash: BlockHash,
     },
     BestFinalized {
         block_number: BlockNumber,
@@ -43,6 +46,9 @@ pub enum FeedMessage {
         location: Option<NodeLocation>,
         startup_time: Option<Timestamp>,
         hwbench: Option<NodeHwBench>,
+        last_seen: Timestamp,
+        stable_id: Option<Box<str>>,
+        process_started_at: Option<Timestamp>,
     },
     RemovedNode {
         node_id: usize,
@@ -66,6 +72,10 @@ pub enum FeedMessage {
         node_id: usize,
         stats: NodeStats,
     },
+    NodeStatsDelta {
+        node_id: usize,
+        stats: PartialNodeStats,
+    },
     Hardware {
         node_id: usize,
         // hardware: NodeHardware, // Can't losslessly deserialize
@@ -122,6 +132,114 @@ pub enum FeedMessage {
         node_id: usize,
         // details: NodeIO, // can't losslessly deserialize
     },
+    RecentBlocks {
+        blocks: Vec<RecentBlock>,
+    },
+    NewChain {
+        genesis_hash: BlockHash,
+        name: String,
+    },
+    FinalityGapAlert {
+        gap: BlockNumber,
+    },
+    FinalityGapRecovered {
+        gap: BlockNumber,
+    },
+    ChainRelayParent {
+        genesis_hash: BlockHash,
+        relay_chain_genesis_hash: BlockHash,
+        para_id: Option<u32>,
+    },
+    TimeToFirstBlock {
+        node_id: usize,
+        time_to_first_block: u64,
+    },
+    NodeCountDrop {
+        genesis_hash: BlockHash,
+        from: usize,
+        to: usize,
+        window_ms: u64,
+    },
+    NodesLastSeen {
+        last_seen: Vec<(usize, Timestamp)>,
+    },
+    MostlySyncing {
+        syncing: u64,
+        nodes: u64,
+    },
+    MostlySynced {
+        syncing: u64,
+        nodes: u64,
+    },
+    ValidatorShortfall {
+        expected: u64,
+        observed: u64,
+    },
+    ValidatorShortfallRecovered {
+        expected: u64,
+        observed: u64,
+    },
+    NodePingLatency {
+        node_id: usize,
+        latency: u64,
+    },
+    DuplicateNode {
+        node_id: usize,
+        existing_node_id: usize,
+    },
+    NodeImportSuccess {
+        node_id: usize,
+        ratio: f64,
+    },
+    ImplausibleBlock {
+        node_id: usize,
+        height: BlockNumber,
+    },
+    AveragePeers {
+        average: f64,
+        previous: Option<f64>,
+    },
+    ProcessStarted {
+        node_id: usize,
+        started_at: Timestamp,
+    },
+    FinalizedAheadOfBest {
+        node_id: usize,
+        finalized_height: BlockNumber,
+        best_height: BlockNumber,
+    },
+    ChainTheme {
+        genesis_hash: BlockHash,
+        color: Option<String>,
+        icon: Option<String>,
+    },
+    NameCollision {
+        node_id: usize,
+        other_nodes: u64,
+    },
+    SessionRotation {
+        from: u64,
+        to: u64,
+    },
+    ChainOverviews {
+        chains: Vec<ChainOverview>,
+    },
+    OutdatedVersion {
+        node_id: usize,
+    },
+    NodeAnnotated {
+        node_id: usize,
+        text: String,
+        severity: String,
+        expires_at: u64,
+    },
+    NodeAnnotationCleared {
+        node_id: usize,
+    },
+    FinalityStarted {
+        genesis_hash: BlockHash,
+        height: BlockNumber,
+    },
     /// A "special" case when we don't know how to decode an action:
     UnknownValue {
         action: u8,
@@ -129,15 +247,35 @@ pub enum FeedMessage {
     },
 }
 
+#[derive(Debug, PartialEq)]
+pub struct ChainOverview {
+    pub label: String,
+    pub genesis_hash: BlockHash,
+    pub node_count: usize,
+    pub best_height: BlockNumber,
+    pub finalized_height: BlockNumber,
+    pub average_block_time: Option<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RecentBlock {
+    pub block_number: BlockNumber,
+    pub block_hash: BlockHash,
+    pub timestamp: Timestamp,
+    pub block_time: Option<u64>,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct NodeDetails {
     pub name: String,
-    pub implementation: String,
-    pub version: String,
+    /// `None` if the server isn't allowed to send this to feeds, as with the other details.
+    pub implementation: Option<String>,
+    pub version: Option<String>,
     pub validator: Option<String>,
     pub network_id: Option<String>,
     pub ip: Option<String>,
     pub sysinfo: Option<NodeSysInfo>,
+    pub custom: Option<BTreeMap<String, String>>,
 }
 
 impl FeedMessage {
@@ -169,12 +307,13 @@ impl FeedMessage {
             }
             // BestBlock
             1 => {
-                let (block_number, timestamp, avg_block_time) =
+                let (block_number, timestamp, avg_block_time, block_hash) =
                     serde_json::from_str(raw_val.get())?;
                 FeedMessage::BestBlock {
                     block_number,
                     timestamp,
                     avg_block_time,
+                    block_hash,
                 }
             }
             // BestFinalized
@@ -189,13 +328,26 @@ impl FeedMessage {
             3 => {
                 let (
                     node_id,
-                    (name, implementation, version, validator, network_id, ip, sysinfo, hwbench),
+                    (
+                        name,
+                        implementation,
+                        version,
+                        validator,
+                        network_id,
+                        ip,
+                        sysinfo,
+                        hwbench,
+                        custom,
+                    ),
                     stats,
                     io,
                     hardware,
                     block_details,
                     location,
                     startup_time,
+                    last_seen,
+                    stable_id,
+                    process_started_at,
                 ) = serde_json::from_str(raw_val.get())?;
 
                 // Give these two types but don't use the results:
@@ -211,12 +363,16 @@ impl FeedMessage {
                         network_id,
                         ip,
                         sysinfo,
+                        custom,
                     },
                     stats,
                     block_details,
                     location,
                     startup_time,
                     hwbench,
+                    last_seen,
+                    stable_id,
+                    process_started_at,
                 }
             }
             // RemoveNode
@@ -348,6 +504,216 @@ impl FeedMessage {
                 let (node_id, _node_io): (_, &RawValue) = serde_json::from_str(raw_val.get())?;
                 FeedMessage::NodeIOUpdate { node_id }
             }
+            // RecentBlocks
+            23 => {
+                let blocks: Vec<(_, _, _, _)> = serde_json::from_str(raw_val.get())?;
+                let blocks = blocks
+                    .into_iter()
+                    .map(
+                        |(block_number, block_hash, timestamp, block_time)| RecentBlock {
+                            block_number,
+                            block_hash,
+                            timestamp,
+                            block_time,
+                        },
+                    )
+                    .collect();
+                FeedMessage::RecentBlocks { blocks }
+            }
+            // NewChain
+            24 => {
+                let (genesis_hash, name) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::NewChain { genesis_hash, name }
+            }
+            // FinalityGapAlert
+            25 => {
+                let gap = serde_json::from_str(raw_val.get())?;
+                FeedMessage::FinalityGapAlert { gap }
+            }
+            // FinalityGapRecovered
+            26 => {
+                let gap = serde_json::from_str(raw_val.get())?;
+                FeedMessage::FinalityGapRecovered { gap }
+            }
+            // ChainRelayParent
+            27 => {
+                let (genesis_hash, relay_chain_genesis_hash, para_id) =
+                    serde_json::from_str(raw_val.get())?;
+                FeedMessage::ChainRelayParent {
+                    genesis_hash,
+                    relay_chain_genesis_hash,
+                    para_id,
+                }
+            }
+            // TimeToFirstBlock
+            28 => {
+                let (node_id, time_to_first_block) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::TimeToFirstBlock {
+                    node_id,
+                    time_to_first_block,
+                }
+            }
+            // NodesLastSeen
+            29 => {
+                let last_seen = serde_json::from_str(raw_val.get())?;
+                FeedMessage::NodesLastSeen { last_seen }
+            }
+            // NodeCountDrop
+            30 => {
+                let (genesis_hash, from, to, window_ms) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::NodeCountDrop {
+                    genesis_hash,
+                    from,
+                    to,
+                    window_ms,
+                }
+            }
+            // NodeStatsDelta
+            31 => {
+                let (node_id, stats) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::NodeStatsDelta { node_id, stats }
+            }
+            // MostlySyncing
+            32 => {
+                let (syncing, nodes) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::MostlySyncing { syncing, nodes }
+            }
+            // MostlySynced
+            33 => {
+                let (syncing, nodes) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::MostlySynced { syncing, nodes }
+            }
+            // ValidatorShortfall
+            34 => {
+                let (expected, observed) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::ValidatorShortfall { expected, observed }
+            }
+            // ValidatorShortfallRecovered
+            35 => {
+                let (expected, observed) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::ValidatorShortfallRecovered { expected, observed }
+            }
+            // NodePingLatency
+            36 => {
+                let (node_id, latency) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::NodePingLatency { node_id, latency }
+            }
+            // DuplicateNode
+            37 => {
+                let (node_id, existing_node_id) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::DuplicateNode {
+                    node_id,
+                    existing_node_id,
+                }
+            }
+            // NodeImportSuccess
+            38 => {
+                let (node_id, ratio) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::NodeImportSuccess { node_id, ratio }
+            }
+            // ImplausibleBlock
+            39 => {
+                let (node_id, height) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::ImplausibleBlock { node_id, height }
+            }
+            // AveragePeers
+            40 => {
+                let (average, previous) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::AveragePeers { average, previous }
+            }
+            // ProcessStarted
+            41 => {
+                let (node_id, started_at) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::ProcessStarted {
+                    node_id,
+                    started_at,
+                }
+            }
+            // FinalizedAheadOfBest
+            42 => {
+                let (node_id, finalized_height, best_height) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::FinalizedAheadOfBest {
+                    node_id,
+                    finalized_height,
+                    best_height,
+                }
+            }
+            // ChainTheme
+            43 => {
+                let (genesis_hash, mut theme): (_, BTreeMap<String, Option<String>>) =
+                    serde_json::from_str(raw_val.get())?;
+                FeedMessage::ChainTheme {
+                    genesis_hash,
+                    color: theme.remove("color").flatten(),
+                    icon: theme.remove("icon").flatten(),
+                }
+            }
+            // NameCollision
+            44 => {
+                let (node_id, other_nodes) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::NameCollision {
+                    node_id,
+                    other_nodes,
+                }
+            }
+            // SessionRotation
+            45 => {
+                let (from, to) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::SessionRotation { from, to }
+            }
+            // ChainOverviews
+            46 => {
+                let chains: Vec<(_, _, _, _, _, _)> = serde_json::from_str(raw_val.get())?;
+                let chains = chains
+                    .into_iter()
+                    .map(
+                        |(
+                            label,
+                            genesis_hash,
+                            node_count,
+                            best_height,
+                            finalized_height,
+                            average_block_time,
+                        )| ChainOverview {
+                            label,
+                            genesis_hash,
+                            node_count,
+                            best_height,
+                            finalized_height,
+                            average_block_time,
+                        },
+                    )
+                    .collect();
+                FeedMessage::ChainOverviews { chains }
+            }
+            // OutdatedVersion
+            47 => {
+                let (node_id,) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::OutdatedVersion { node_id }
+            }
+            // NodeAnnotated
+            48 => {
+                let (node_id, text, severity, expires_at) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::NodeAnnotated {
+                    node_id,
+                    text,
+                    severity,
+                    expires_at,
+                }
+            }
+            // NodeAnnotationCleared
+            49 => {
+                let (node_id,) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::NodeAnnotationCleared { node_id }
+            }
+            // FinalityStarted
+            50 => {
+                let (genesis_hash, height) = serde_json::from_str(raw_val.get())?;
+                FeedMessage::FinalityStarted {
+                    genesis_hash,
+                    height,
+                }
+            }
             // A catchall for messages we don't know/care about yet:
             _ => {
                 let value = raw_val.to_string();
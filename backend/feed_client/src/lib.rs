@@ -0,0 +1,28 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! A client for the `/feed` endpoint of the telemetry core. Connect with
+//! [`FeedClient::connect`], subscribe to a chain by its genesis hash or label, and
+//! then receive [`FeedMessage`]s as the core sends them.
+
+mod client;
+
+/// Deserializing feed messages from the telemetry core. This basically is the
+/// slightly-lossy inverse of the custom serialization we do to feed messages.
+pub mod feed_message;
+
+pub use client::{Chain, Error, FeedClient};
+pub use feed_message::FeedMessage;
@@ -0,0 +1,142 @@
+use common::node_types::BlockHash;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use test_utils::feed_message_de::FeedMessage;
+use test_utils::workspace::{start_server, CoreOpts, ServerOpts, ShardOpts};
+use tokio::runtime::Runtime;
+
+const NUMBER_OF_NODES: usize = 1_000;
+const BLOCKS_PER_NODE: u64 = 20;
+
+/// This benchmark roughly times how quickly the core handles best block updates from
+/// nodes, which are by far the most common messages that it sees. Every update for a
+/// chain is handled in turn by the aggregator that owns it, so this is a measure of how
+/// many updates a single aggregator can get through.
+///
+/// Like the subscribe benchmark, this includes the overhead of getting messages from the
+/// shard to the core, so it's worth comparing against a run with the update handling
+/// commented out to get a "baseline".
+pub fn benchmark_node_updates(c: &mut Criterion) {
+    let rt = Runtime::new().expect("tokio runtime should start");
+
+    let mut group = c.benchmark_group("node updates");
+    group.throughput(Throughput::Elements(
+        NUMBER_OF_NODES as u64 * BLOCKS_PER_NODE,
+    ));
+    group.sample_size(10);
+    group.bench_function("best blocks: time till all seen", move |b| {
+        b.to_async(&rt).iter_custom(|iters| async move {
+            let mut total_time = Duration::ZERO;
+            for _n in 0..iters {
+                // Start a server:
+                let mut server = start_server(
+                    ServerOpts {
+                        release_mode: true,
+                        log_output: false,
+                    },
+                    CoreOpts {
+                        worker_threads: Some(16),
+                        num_aggregators: Some(1),
+                        ..Default::default()
+                    },
+                    ShardOpts {
+                        max_nodes_per_connection: Some(usize::MAX),
+                        max_node_data_per_second: Some(usize::MAX),
+                        worker_threads: Some(2),
+                        ..Default::default()
+                    },
+                )
+                .await;
+                let shard_id = server.add_shard().await.unwrap();
+
+                // Connect a shard:
+                let (mut node_tx, _) = server
+                    .get_shard(shard_id)
+                    .unwrap()
+                    .connect_node()
+                    .await
+                    .expect("node can connect");
+
+                // Add a bunch of actual nodes on the same chain:
+                for n in 0..NUMBER_OF_NODES {
+                    node_tx
+                        .send_json_text(json!({
+                            "id":n,
+                            "ts":"2021-07-12T10:37:47.714666+01:00",
+                            "payload": {
+                                "authority":true,
+                                "chain":"Polkadot", // No limit to #nodes on this network.
+                                "config":"",
+                                "genesis_hash": BlockHash::from_low_u64_ne(1),
+                                "implementation":"Substrate Node",
+                                "msg":"system.connected",
+                                "name": format!("Node {}", n),
+                                "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                                "startup_time":"1625565542717",
+                                "version":"2.0.0-07a1af348-aarch64-macos"
+                            }
+                        }))
+                        .unwrap();
+                }
+
+                // Give those messages a chance to be handled, so that we aren't also
+                // timing how long it takes to add the nodes.
+                tokio::time::sleep(Duration::from_millis(250)).await;
+
+                // Subscribe a feed to the chain, so that updates are serialized for it too:
+                let (feed_tx, mut feed_rx) = server
+                    .get_core()
+                    .connect_feed()
+                    .await
+                    .expect("feed can connect");
+                let genesis_hash = format!("{:#x}", BlockHash::from_low_u64_ne(1));
+                feed_tx.send_command("subscribe", &genesis_hash).unwrap();
+                feed_rx.recv_feed_messages().await.unwrap();
+
+                let block = |n: usize, height: u64| {
+                    json!({
+                        "id":n,
+                        "ts":"2021-07-12T10:37:48.714666+01:00",
+                        "payload": {
+                            "best": BlockHash::from_low_u64_ne(height),
+                            "height": height,
+                            "msg":"block.import",
+                            "origin":"Own"
+                        }
+                    })
+                };
+
+                // Every node imports every block. Messages from the shard are handled
+                // in order, so once one last block is seen, all of the others have been too:
+                let start = Instant::now();
+                for height in 1..=BLOCKS_PER_NODE {
+                    for n in 0..NUMBER_OF_NODES {
+                        node_tx.send_json_text(block(n, height)).unwrap();
+                    }
+                }
+                let last_height = BLOCKS_PER_NODE + 1;
+                node_tx.send_json_text(block(0, last_height)).unwrap();
+
+                // Wait and see how long it takes for that last block to reach the feed:
+                loop {
+                    let msgs = feed_rx.recv_feed_messages_once().await.unwrap();
+                    let seen_last = msgs.iter().any(|m| {
+                        matches!(m, FeedMessage::BestBlock { block_number, .. } if *block_number == last_height)
+                    });
+                    if seen_last {
+                        break;
+                    }
+                }
+                total_time += start.elapsed();
+            }
+
+            // The total time spent waiting for updates to be handled:
+            total_time
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_node_updates);
+criterion_main!(benches);
@@ -0,0 +1,202 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// What a token allows its bearer to do with the /admin routes. Tokens with
+/// any scope can also do anything that [`AdminScope::ReadOnly`] allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AdminScope {
+    /// Look at the state of chains and nodes.
+    ReadOnly,
+    /// Act on individual nodes.
+    NodeControl,
+    /// Act on whole chains, eg freezing them.
+    ChainControl,
+}
+
+impl AdminScope {
+    const ALL: [AdminScope; 3] = [
+        AdminScope::ReadOnly,
+        AdminScope::NodeControl,
+        AdminScope::ChainControl,
+    ];
+}
+
+impl FromStr for AdminScope {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-only" => Ok(AdminScope::ReadOnly),
+            "node-control" => Ok(AdminScope::NodeControl),
+            "chain-control" => Ok(AdminScope::ChainControl),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'read-only', 'node-control' or 'chain-control', but got '{s}'"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AdminScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AdminScope::ReadOnly => "read-only",
+            AdminScope::NodeControl => "node-control",
+            AdminScope::ChainControl => "chain-control",
+        })
+    }
+}
+
+/// Why a token wasn't allowed to do something.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminTokenError {
+    /// No token was given, or it isn't one that we know about.
+    UnknownToken,
+    /// The token is known, but doesn't have the scope needed.
+    MissingScope(AdminScope),
+}
+
+impl fmt::Display for AdminTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminTokenError::UnknownToken => f.write_str("Forbidden: missing or unknown token"),
+            AdminTokenError::MissingScope(scope) => {
+                write!(f, "Forbidden: token does not have the '{scope}' scope")
+            }
+        }
+    }
+}
+
+/// The tokens that can be used to access the /admin routes, and the scopes of each.
+#[derive(Debug, Clone, Default)]
+pub struct AdminTokens {
+    tokens: HashMap<String, Vec<AdminScope>>,
+}
+
+impl AdminTokens {
+    /// Load tokens from a file containing a token and a comma separated list of its
+    /// scopes on each line, eg `s3cr3t read-only,chain-control`. Blank lines and lines
+    /// starting with `#` are ignored.
+    pub fn from_file(path: &Path) -> anyhow::Result<AdminTokens> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read admin tokens {path:?}: {e}"))?;
+        let mut tokens = AdminTokens::default();
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (token, scopes) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Line {} of admin tokens {path:?}: expecting format `<token> <scope>[,<scope>..]`",
+                    idx + 1
+                )
+            })?;
+            let scopes = scopes
+                .trim()
+                .split(',')
+                .map(|scope| scope.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|e| anyhow::anyhow!("Line {} of admin tokens {path:?}: {e}", idx + 1))?;
+            tokens.tokens.insert(token.to_owned(), scopes);
+        }
+        Ok(tokens)
+    }
+
+    /// Add a token with every scope.
+    pub fn insert_unscoped(&mut self, token: String) {
+        self.tokens.insert(token, AdminScope::ALL.to_vec());
+    }
+
+    /// Is the token given allowed to do what needs the scope given?
+    pub fn check(&self, token: Option<&str>, needs: AdminScope) -> Result<(), AdminTokenError> {
+        let scopes = token
+            .and_then(|token| self.tokens.get(token))
+            .ok_or(AdminTokenError::UnknownToken)?;
+        if needs == AdminScope::ReadOnly || scopes.contains(&needs) {
+            Ok(())
+        } else {
+            Err(AdminTokenError::MissingScope(needs))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tokens(contents: &str) -> anyhow::Result<AdminTokens> {
+        let path = std::env::temp_dir().join(format!(
+            "admin_tokens_test_{}_{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        let tokens = AdminTokens::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        tokens
+    }
+
+    #[test]
+    fn tokens_only_have_the_scopes_given() {
+        let tokens = tokens(
+            "# Comments and blank lines are ignored\n\
+             \n\
+             reader read-only\n\
+             operator  node-control, chain-control\n",
+        )
+        .unwrap();
+
+        assert_eq!(tokens.check(Some("reader"), AdminScope::ReadOnly), Ok(()));
+        assert_eq!(
+            tokens.check(Some("reader"), AdminScope::ChainControl),
+            Err(AdminTokenError::MissingScope(AdminScope::ChainControl))
+        );
+        // Any scope allows reading:
+        assert_eq!(tokens.check(Some("operator"), AdminScope::ReadOnly), Ok(()));
+        assert_eq!(
+            tokens.check(Some("operator"), AdminScope::ChainControl),
+            Ok(())
+        );
+
+        assert_eq!(
+            tokens.check(Some("nobody"), AdminScope::ReadOnly),
+            Err(AdminTokenError::UnknownToken)
+        );
+        assert_eq!(
+            tokens.check(None, AdminScope::ReadOnly),
+            Err(AdminTokenError::UnknownToken)
+        );
+    }
+
+    #[test]
+    fn unscoped_tokens_can_do_anything() {
+        let mut tokens = AdminTokens::default();
+        tokens.insert_unscoped("root".to_owned());
+        for scope in AdminScope::ALL {
+            assert_eq!(tokens.check(Some("root"), scope), Ok(()));
+        }
+    }
+
+    #[test]
+    fn invalid_lines_are_an_error() {
+        assert!(tokens("no-scopes\n").is_err());
+        assert!(tokens("token read-only,superuser\n").is_err());
+    }
+}
@@ -15,13 +15,32 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use super::inner_loop;
-use crate::find_location::find_location;
-use crate::state::NodeId;
-use common::id_type;
+use super::pending_genesis::NewChainGrace;
+use crate::broker::BrokerSender;
+use crate::feed_message::NodeDetailFields;
+use crate::find_location::{find_location, AsnDatabase, LocatorLimits};
+use crate::state::{
+    BlockTimePoint, BlockedNodeNameAction, CardinalityLimits, ChainEventRecord, CompactSummary,
+    ConnectionTiers, DuplicateNodePolicy, FirstSeenStore, HardwareChanges, HardwareTiers,
+    HealthWeights, LabelDebounce, NetworkDenylist, NodeAnnotation, NodeCountDrops, NodeGroups,
+    NodeId, NodeNameBlocklist, NodeOperatorPattern, OperatorCap, OutdatedVersionPolicy, QuotaBurst,
+    RecentBlock, ReconnectPolicy, RemovedNodeRecord, RollingWindows, StaleTimeouts, StatsTimings,
+    SyncingMajority, Theme, UnparseableVersionPolicy, UnreportedNodeRole, STATS_UPDATE_INTERVAL,
+};
+use crate::webhooks::WebhookAlert;
+use common::node_types::BlockHash;
+use common::{id_type, DenseMapStats};
 use futures::{future, Sink, SinkExt};
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
 use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// How often chains that have had no nodes for too long are looked for and removed.
+const EMPTY_CHAIN_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+/// How often node annotations that have expired are looked for and cleared.
+const NODE_ANNOTATION_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
 
 id_type! {
     /// A unique Id is assigned per websocket connection (or more accurately,
@@ -44,9 +63,178 @@ pub struct AggregatorOpts {
     /// How many nodes from third party chains are allowed to connect
     /// before we prevent connections from them.
     pub max_third_party_nodes: usize,
+    /// How many nodes without a genesis hash are put on a chain of their own, rather
+    /// than being rejected. 0 rejects them all.
+    pub max_unidentified_nodes: usize,
     /// Flag to expose the node's details (IP address, SysInfo, HwBench) of all connected
-    /// nodes to the feed subscribers.
+    /// nodes to the feed subscribers, unless given otherwise for their chain.
     pub expose_node_details: bool,
+    /// Nodes whose name matches this blocklist are handled according
+    /// to `blocked_node_name_action`.
+    pub node_name_blocklist: Arc<NodeNameBlocklist>,
+    /// What to do with nodes whose name is on the blocklist.
+    pub blocked_node_name_action: BlockedNodeNameAction,
+    /// What to do with nodes that report the same network ID as another node on their chain.
+    pub duplicate_node_policy: DuplicateNodePolicy,
+    /// What to do with nodes that connect again while their old connection is still around.
+    pub reconnect_policy: ReconnectPolicy,
+    /// Should feeds be told about nodes with the same name as others on their chain?
+    pub flag_name_collisions: bool,
+    /// The oldest (major, minor) client version that nodes on the chains with these
+    /// genesis hashes should run.
+    pub chain_min_versions: HashMap<BlockHash, (u32, u32)>,
+    /// What to do with nodes running an older version than the minimum for their chain.
+    pub outdated_version_policy: OutdatedVersionPolicy,
+    /// How to treat nodes whose version can't be parsed, on chains with a minimum version.
+    pub unparseable_version_policy: UnparseableVersionPolicy,
+    /// Nodes on networks with these genesis hashes are rejected.
+    pub network_denylist: Arc<NetworkDenylist>,
+    /// The groups that nodes belong to, so that feeds can ask to see only one group.
+    pub node_groups: Arc<NodeGroups>,
+    /// If given, the hosting providers of nodes are looked up in this.
+    pub asn_database: Option<AsnDatabase>,
+    /// How long locating nodes can take, and when to stop for a while if lots fail.
+    pub locator_limits: LocatorLimits,
+    /// The IP addresses of nodes that we expect to connect, to locate ahead of time.
+    pub prewarm_ips: Arc<[IpAddr]>,
+    /// How much history each rolling metric of each chain is worked out over.
+    pub rolling_windows: RollingWindows,
+    /// How many block times each chain needs before it gives out an average block time.
+    pub block_time_warm_up: usize,
+    /// The most that a chain's best block can advance by at once to count as a block time.
+    pub max_block_time_step: Option<u64>,
+    /// When nodes become stale and fresh again.
+    pub stale_timeouts: StaleTimeouts,
+    /// Chains with these genesis hashes always use the given label.
+    pub chain_label_overrides: HashMap<BlockHash, Box<str>>,
+    /// How frontends should show the chains with these genesis hashes.
+    pub chain_themes: HashMap<BlockHash, Theme>,
+    /// Chains with these genesis hashes expose node details (or not) regardless
+    /// of `expose_node_details`.
+    pub chain_expose_node_details: HashMap<BlockHash, bool>,
+    /// Which details of nodes feeds are allowed to be sent. The IP address, sysinfo,
+    /// hwbench and custom fields are only sent if node details are exposed, too.
+    pub node_detail_fields: NodeDetailFields,
+    /// Chains with these genesis hashes allow different node details to be sent, instead
+    /// of `node_detail_fields`.
+    pub chain_node_detail_fields: HashMap<BlockHash, NodeDetailFields>,
+    /// How many of their most recent best blocks each chain keeps hold of.
+    pub recent_blocks_len: usize,
+    /// Chains with these genesis hashes keep hold of a different number of recent best
+    /// blocks to `recent_blocks_len`.
+    pub chain_recent_blocks_len: HashMap<BlockHash, usize>,
+    /// How many of their most recent average block times each chain keeps hold of.
+    pub block_time_history_len: usize,
+    /// How many of the nodes removed from it most recently each chain keeps track of.
+    pub removed_nodes_len: usize,
+    /// How many of their most recent significant events each chain keeps track of.
+    pub recent_events_len: usize,
+    /// Nodes whose process started less than this long ago (in ms) were recently restarted.
+    pub recent_restart_window: u64,
+    /// How long regenerating the stats of a chain should take, and how often to reconcile them.
+    pub stats_timings: StatsTimings,
+    /// Feeds are alerted once the finalized block of a chain falls more than this
+    /// many blocks behind its best block.
+    pub finality_gap_threshold: u64,
+    /// Nodes whose finalized block is more than this many blocks behind that of their
+    /// chain are counted as lagging finality.
+    pub finality_lag_threshold: u64,
+    /// Nodes whose clock is more than this many ms ahead of or behind ours are counted
+    /// as having clock skew.
+    pub clock_skew_threshold: u64,
+    /// Node locations that move less than this many metres are ignored, rather than
+    /// sent to feeds again.
+    pub min_location_change: u64,
+    /// Nodes with fewer than this many bytes free on disk are counted as low on disk space.
+    pub low_disk_threshold: u64,
+    /// What nodes that don't say whether they're full nodes or light clients count as.
+    pub unreported_node_role: UnreportedNodeRole,
+    /// The scores that nodes are split into hardware tiers by.
+    pub hardware_tiers: HardwareTiers,
+    /// How many distinct values of each stat chains count separately.
+    pub cardinality_limits: CardinalityLimits,
+    /// How long nodes need to have been connected for to move up a connection tier.
+    pub connection_tiers: ConnectionTiers,
+    /// How node operators are found in node names.
+    pub operator_pattern: NodeOperatorPattern,
+    /// How many nodes any one operator can have on a chain.
+    pub operator_cap: OperatorCap,
+    /// How far over quota chains go while nodes reconnect after a mass disconnect.
+    pub quota_burst: QuotaBurst,
+    /// When feeds are alerted about chains suddenly losing lots of nodes.
+    pub node_count_drops: NodeCountDrops,
+    /// When the label shown for chains follows the one that their nodes most commonly use.
+    pub label_debounce: LabelDebounce,
+    /// When nodes count as syncing, and when feeds are told that most of a chain's are.
+    pub syncing_majority: SyncingMajority,
+    /// How much each signal counts towards the health scores of chains.
+    pub health_weights: HealthWeights,
+    /// How many validators the chains with these genesis hashes are expected to have.
+    pub chain_expected_validators: HashMap<BlockHash, u64>,
+    /// How long (in ms) blocks are expected to take on the chains with these genesis hashes.
+    pub chain_expected_block_times: HashMap<BlockHash, u64>,
+    /// Chains with these genesis hashes are private, and so never listed to feeds.
+    pub private_chains: HashSet<BlockHash>,
+    /// Feeds are alerted once a chain has more than this many fewer validators than
+    /// it's expected to.
+    pub validator_shortfall_threshold: u64,
+    /// How many hardware, stats and IO messages about its nodes each chain can send
+    /// feeds per second. 0 means that there's no limit.
+    pub feed_rate_limit: u32,
+    /// Nodes' import queues count as backed up in the chain stats once they keep having
+    /// at least this many blocks in them. 0 means that they never do.
+    pub import_queue_backed_up_depth: u64,
+    /// Nodes that import less than this percentage of the blocks they try to are counted
+    /// as unhealthy in the chain stats. 0 means that none are.
+    pub min_import_success_percent: u64,
+    /// The label that chains use while their nodes haven't reported a useful one.
+    pub unlabeled_chain_format: Box<str>,
+    /// How long (in ms) a block author can go without producing a block before it's
+    /// counted as inactive.
+    pub block_author_inactive_after: u64,
+    /// Best blocks this far ahead of a chain's best block are ignored; 0 means none are.
+    pub max_best_block_lead: u64,
+    /// How much (in percent) a chain's average peer count changes before feeds are told.
+    pub average_peers_change_percent: u64,
+    /// Should nodes be given IDs that stay the same when they reconnect, for feeds?
+    pub stable_node_ids: bool,
+    /// If given, when nodes with stable IDs were first ever seen is kept track of here.
+    pub first_seen_store: Option<Arc<parking_lot::Mutex<FirstSeenStore>>>,
+    /// How many votes towards the label of their chain validators get, where every
+    /// other node gets one.
+    pub validator_label_weight: usize,
+    /// How much the bandwidth of nodes has to change by before feeds are told.
+    pub hardware_changes: HardwareChanges,
+    /// How many nodes chains need before feeds are told about them.
+    pub min_chain_nodes: usize,
+    /// How high the best block of chains has to get before feeds are told about them.
+    pub min_chain_height: u64,
+    /// Should chains only advance their best block along the branch that their
+    /// finalized block is on?
+    pub prefer_finalized_branch: bool,
+    /// How many nodes per block height feeds are told have imported a block. 0 means
+    /// that feeds are told about every imported block.
+    pub imported_block_sample: usize,
+    /// Chains with these genesis hashes sample imported blocks differently
+    /// to `imported_block_sample`.
+    pub chain_imported_block_sample: HashMap<BlockHash, usize>,
+    /// Only nodes added while their chain has at most this many nodes are located. 0 means
+    /// that every node is.
+    pub max_located_nodes: usize,
+    /// Chains with these genesis hashes locate a different number of nodes
+    /// to `max_located_nodes`.
+    pub chain_max_located_nodes: HashMap<BlockHash, usize>,
+    /// How nodes on new networks wait before a chain is created for them.
+    pub new_chain_grace: NewChainGrace,
+    /// How long chains are kept once their last node leaves. Zero removes them straight away.
+    pub empty_chain_ttl: Duration,
+    /// If given, alerts about chains are sent here to be passed on to webhooks.
+    pub tx_to_webhooks: Option<flume::Sender<WebhookAlert>>,
+    /// If given, the messages sent to feeds are also published to a message broker.
+    pub broker: Option<BrokerSender>,
+    /// Once more than this many messages are queued up, chains regenerate their stats
+    /// less often until the queue drains. 0 disables this.
+    pub stats_backoff_queue_len: usize,
 }
 
 struct AggregatorInternal {
@@ -68,12 +256,70 @@ impl Aggregator {
         let (tx_to_aggregator, rx_from_external) = flume::unbounded();
 
         // Kick off a locator task to locate nodes, which hands back a channel to make location requests
-        let tx_to_locator =
-            find_location(tx_to_aggregator.clone().into_sink().with(|(node_id, msg)| {
+        let tx_to_locator = find_location(
+            tx_to_aggregator.clone().into_sink().with(|(node_id, msg)| {
                 future::ok::<_, flume::SendError<_>>(inner_loop::ToAggregator::FromFindLocation(
                     node_id, msg,
                 ))
-            }));
+            }),
+            opts.asn_database.clone(),
+            opts.locator_limits,
+            Arc::clone(&opts.prewarm_ips),
+        );
+
+        // Periodically remove chains that have been empty for too long, until the
+        // aggregator goes away:
+        if !opts.empty_chain_ttl.is_zero() {
+            let tx_to_aggregator = tx_to_aggregator.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(EMPTY_CHAIN_SWEEP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    if tx_to_aggregator
+                        .send(inner_loop::ToAggregator::SweepEmptyChains)
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+
+        // Periodically clear node annotations that have expired, until the aggregator goes
+        // away:
+        {
+            let tx_to_aggregator = tx_to_aggregator.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(NODE_ANNOTATION_SWEEP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    if tx_to_aggregator
+                        .send(inner_loop::ToAggregator::ExpireNodeAnnotations)
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+
+        // Send feeds that asked for an overview of every chain the latest one as often as
+        // chains regenerate their stats, until the aggregator goes away:
+        {
+            let tx_to_aggregator = tx_to_aggregator.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(STATS_UPDATE_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    if tx_to_aggregator
+                        .send(inner_loop::ToAggregator::SendChainOverviews)
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
 
         // Handle any incoming messages in our handler loop:
         tokio::spawn(Aggregator::handle_messages(
@@ -114,6 +360,305 @@ impl Aggregator {
         Ok(metrics)
     }
 
+    /// Return the full state of a node, or `None` if it can't be found.
+    pub async fn gather_node_state(
+        &self,
+        genesis_hash: BlockHash,
+        node: inner_loop::NodeLookup,
+    ) -> anyhow::Result<Option<serde_json::Value>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherNodeState {
+            genesis_hash,
+            node,
+            tx,
+        };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let node_state = rx.recv_async().await?;
+        Ok(node_state)
+    }
+
+    /// Return the nodes most recently removed from a chain, oldest first, or `None` if it
+    /// can't be found.
+    pub async fn gather_removed_nodes(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<Vec<RemovedNodeRecord>>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherRemovedNodes { genesis_hash, tx };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let removed_nodes = rx.recv_async().await?;
+        Ok(removed_nodes)
+    }
+
+    /// Return the most recent significant events on a chain, oldest first, or `None` if
+    /// it can't be found.
+    pub async fn gather_recent_events(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<Vec<ChainEventRecord>>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherRecentEvents { genesis_hash, tx };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let recent_events = rx.recv_async().await?;
+        Ok(recent_events)
+    }
+
+    /// Return how the slots that a chain's nodes are stored in are being used, or `None`
+    /// if it can't be found.
+    pub async fn gather_node_slots(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<DenseMapStats>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherNodeSlots { genesis_hash, tx };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let node_slots = rx.recv_async().await?;
+        Ok(node_slots)
+    }
+
+    /// Return the block times that a chain's average block time is worked out from,
+    /// or `None` if it can't be found.
+    pub async fn gather_block_times(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<inner_loop::BlockTimes>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherBlockTimes { genesis_hash, tx };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let block_times = rx.recv_async().await?;
+        Ok(block_times)
+    }
+
+    /// Return a complete snapshot of a chain and the nodes on it that match the filter
+    /// given. Chains that don't expose node details are only exported to admins.
+    pub async fn gather_chain_export(
+        &self,
+        genesis_hash: BlockHash,
+        admin: bool,
+        filter: inner_loop::NodeFilter,
+    ) -> anyhow::Result<inner_loop::ChainExportOutcome> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherChainExport {
+            genesis_hash,
+            admin,
+            filter,
+            tx,
+        };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let outcome = rx.recv_async().await?;
+        Ok(outcome)
+    }
+
+    /// Return the most recent best blocks of a chain, or `None` if it can't be found.
+    pub async fn gather_recent_blocks(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<Vec<RecentBlock>>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherRecentBlocks { genesis_hash, tx };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let recent_blocks = rx.recv_async().await?;
+        Ok(recent_blocks)
+    }
+
+    /// Return the average block time of a chain as of each of its recent best blocks, or
+    /// `None` if it can't be found.
+    pub async fn gather_block_time_history(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<Vec<BlockTimePoint>>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherBlockTimeHistory { genesis_hash, tx };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let block_time_history = rx.recv_async().await?;
+        Ok(block_time_history)
+    }
+
+    /// Return a page of the nodes on a chain that match the filter given, or `None` if
+    /// the chain can't be found.
+    pub async fn gather_nodes(
+        &self,
+        genesis_hash: BlockHash,
+        filter: inner_loop::NodeFilter,
+    ) -> anyhow::Result<Option<inner_loop::NodeSearch>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherNodes {
+            genesis_hash,
+            filter,
+            tx,
+        };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let nodes = rx.recv_async().await?;
+        Ok(nodes)
+    }
+
+    /// Return a summary of every chain.
+    pub async fn gather_chains(&self) -> anyhow::Result<Vec<inner_loop::ChainSummary>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherChains(tx);
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let chains = rx.recv_async().await?;
+        Ok(chains)
+    }
+
+    /// Return current and peak connection counts.
+    pub async fn gather_connection_info(&self) -> anyhow::Result<inner_loop::ConnectionInfo> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherConnectionInfo(tx);
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let info = rx.recv_async().await?;
+        Ok(info)
+    }
+
+    /// Return a summary of every connected shard.
+    pub async fn gather_shards(&self) -> anyhow::Result<Vec<inner_loop::ShardSummary>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherShards(tx);
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let shards = rx.recv_async().await?;
+        Ok(shards)
+    }
+
+    /// Change how many nodes a chain can have, removing the nodes over the new limit if
+    /// `evict` is true. Returns how many nodes are over the limit, or `None` if the chain
+    /// can't be found.
+    pub async fn set_chain_max_nodes(
+        &self,
+        genesis_hash: BlockHash,
+        max_nodes: usize,
+        evict: bool,
+    ) -> anyhow::Result<Option<usize>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::SetChainMaxNodes {
+            genesis_hash,
+            max_nodes,
+            evict,
+            tx,
+        };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let over_quota = rx.recv_async().await?;
+        Ok(over_quota)
+    }
+
+    /// Compact a chain, returning what changed, or `None` if it can't be found.
+    pub async fn compact_chain(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<CompactSummary>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::CompactChain { genesis_hash, tx };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let summary = rx.recv_async().await?;
+        Ok(summary)
+    }
+
+    /// Move a node to the chain with the genesis hash `to`.
+    pub async fn move_node(
+        &self,
+        genesis_hash: BlockHash,
+        node: inner_loop::NodeLookup,
+        to: BlockHash,
+    ) -> anyhow::Result<inner_loop::MoveNodeOutcome> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::MoveNode {
+            genesis_hash,
+            node,
+            to,
+            tx,
+        };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let outcome = rx.recv_async().await?;
+        Ok(outcome)
+    }
+
+    /// Attach an annotation to a node, or clear it, returning `false` if the node can't be
+    /// found.
+    pub async fn annotate_node(
+        &self,
+        genesis_hash: BlockHash,
+        node: inner_loop::NodeLookup,
+        annotation: Option<NodeAnnotation>,
+    ) -> anyhow::Result<bool> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::AnnotateNode {
+            genesis_hash,
+            node,
+            annotation,
+            tx,
+        };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let found = rx.recv_async().await?;
+        Ok(found)
+    }
+
+    /// Freeze or unfreeze a chain, returning `false` if it can't be found.
+    pub async fn set_chain_frozen(
+        &self,
+        genesis_hash: BlockHash,
+        frozen: bool,
+    ) -> anyhow::Result<bool> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::SetChainFrozen {
+            genesis_hash,
+            frozen,
+            tx,
+        };
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let found = rx.recv_async().await?;
+        Ok(found)
+    }
+
+    /// Replace the node name blocklist that newly added nodes are checked against.
+    pub async fn set_node_name_blocklist(
+        &self,
+        blocklist: Arc<NodeNameBlocklist>,
+    ) -> anyhow::Result<()> {
+        let msg = inner_loop::ToAggregator::SetNodeNameBlocklist(blocklist);
+        self.0.tx_to_aggregator.send_async(msg).await?;
+        Ok(())
+    }
+
+    /// Replace the network denylist that newly added nodes are checked against.
+    pub async fn set_network_denylist(&self, denylist: Arc<NetworkDenylist>) -> anyhow::Result<()> {
+        let msg = inner_loop::ToAggregator::SetNetworkDenylist(denylist);
+        self.0.tx_to_aggregator.send_async(msg).await?;
+        Ok(())
+    }
+
     /// Return a sink that a shard can send messages into to be handled by the aggregator.
     pub fn subscribe_shard(
         &self,
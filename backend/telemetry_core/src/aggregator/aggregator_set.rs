@@ -1,6 +1,11 @@
 use super::aggregator::{Aggregator, AggregatorOpts};
 use super::inner_loop;
-use common::EitherSink;
+use crate::state::{
+    BlockTimePoint, ChainEventRecord, CompactSummary, NetworkDenylist, NodeAnnotation,
+    NodeNameBlocklist, RecentBlock, RemovedNodeRecord,
+};
+use common::node_types::BlockHash;
+use common::{DenseMapStats, EitherSink};
 use futures::{Sink, SinkExt};
 use inner_loop::{FromShardWebsocket, Metrics};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -23,9 +28,17 @@ impl AggregatorSet {
     ) -> anyhow::Result<AggregatorSet> {
         assert_ne!(num_aggregators, 0, "You must have 1 or more aggregator");
 
-        let aggregators = futures::future::try_join_all(
-            (0..num_aggregators).map(|_| Aggregator::spawn(opts.clone())),
-        )
+        let aggregators = futures::future::try_join_all((0..num_aggregators).map(|idx| {
+            let mut opts = opts.clone();
+            // Every aggregator hears about every node, so only the first publishes to the
+            // broker and sends webhook alerts; otherwise each would be sent once per
+            // aggregator.
+            if idx > 0 {
+                opts.broker = None;
+                opts.tx_to_webhooks = None;
+            }
+            Aggregator::spawn(opts)
+        }))
         .await?;
 
         let initial_metrics = (0..num_aggregators).map(|_| Metrics::default()).collect();
@@ -82,6 +95,241 @@ impl AggregatorSet {
         self.0.metrics.lock().unwrap().clone()
     }
 
+    /// Return the full state of a node, or `None` if it can't be found. Every
+    /// aggregator knows about every node, so we just ask the first one.
+    pub async fn gather_node_state(
+        &self,
+        genesis_hash: BlockHash,
+        node: inner_loop::NodeLookup,
+    ) -> anyhow::Result<Option<serde_json::Value>> {
+        self.0.aggregators[0]
+            .gather_node_state(genesis_hash, node)
+            .await
+    }
+
+    /// Return the nodes most recently removed from a chain, oldest first, or `None` if it
+    /// can't be found. Every aggregator knows about every chain, so we just ask the first one.
+    pub async fn gather_removed_nodes(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<Vec<RemovedNodeRecord>>> {
+        self.0.aggregators[0]
+            .gather_removed_nodes(genesis_hash)
+            .await
+    }
+
+    /// Return the most recent significant events on a chain, oldest first, or `None` if it
+    /// can't be found. Every aggregator knows about every chain, so we just ask the first one.
+    pub async fn gather_recent_events(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<Vec<ChainEventRecord>>> {
+        self.0.aggregators[0]
+            .gather_recent_events(genesis_hash)
+            .await
+    }
+
+    /// Return how the slots that a chain's nodes are stored in are being used, or `None` if
+    /// it can't be found. Each aggregator stores its own copy of the chain's nodes, and nodes
+    /// come and go from them all alike, so we just ask the first one.
+    pub async fn gather_node_slots(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<DenseMapStats>> {
+        self.0.aggregators[0].gather_node_slots(genesis_hash).await
+    }
+
+    /// Return the block times that a chain's average block time is worked out from, or
+    /// `None` if it can't be found. Every aggregator knows about every chain, so we just
+    /// ask the first one.
+    pub async fn gather_block_times(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<inner_loop::BlockTimes>> {
+        self.0.aggregators[0].gather_block_times(genesis_hash).await
+    }
+
+    /// Return a complete snapshot of a chain and the nodes on it that match the filter
+    /// given. Chains that don't expose node details are only exported to admins. Every
+    /// aggregator knows about every chain, so we just ask the first one.
+    pub async fn gather_chain_export(
+        &self,
+        genesis_hash: BlockHash,
+        admin: bool,
+        filter: inner_loop::NodeFilter,
+    ) -> anyhow::Result<inner_loop::ChainExportOutcome> {
+        self.0.aggregators[0]
+            .gather_chain_export(genesis_hash, admin, filter)
+            .await
+    }
+
+    /// Return the most recent best blocks of a chain, or `None` if it can't be found.
+    /// Every aggregator knows about every chain, so we just ask the first one.
+    pub async fn gather_recent_blocks(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<Vec<RecentBlock>>> {
+        self.0.aggregators[0]
+            .gather_recent_blocks(genesis_hash)
+            .await
+    }
+
+    /// Return the average block time of a chain as of each of its recent best blocks, or
+    /// `None` if it can't be found. Every aggregator knows about every chain, so we just
+    /// ask the first one.
+    pub async fn gather_block_time_history(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<Vec<BlockTimePoint>>> {
+        self.0.aggregators[0]
+            .gather_block_time_history(genesis_hash)
+            .await
+    }
+
+    /// Return a page of the nodes on a chain that match the filter given, or `None` if
+    /// the chain can't be found. Every aggregator knows about every chain, so we just
+    /// ask the first one.
+    pub async fn gather_nodes(
+        &self,
+        genesis_hash: BlockHash,
+        filter: inner_loop::NodeFilter,
+    ) -> anyhow::Result<Option<inner_loop::NodeSearch>> {
+        self.0.aggregators[0]
+            .gather_nodes(genesis_hash, filter)
+            .await
+    }
+
+    /// Return a summary of every chain. Every aggregator knows about every
+    /// chain, so we just ask the first one.
+    pub async fn gather_chains(&self) -> anyhow::Result<Vec<inner_loop::ChainSummary>> {
+        self.0.aggregators[0].gather_chains().await
+    }
+
+    /// Return current and peak connection counts. Every aggregator knows about every
+    /// shard, chain and node, but feeds are spread across them and so are added up.
+    /// This means that the peak feed counts are an upper bound, since each aggregator
+    /// may have seen its peak at a different time.
+    pub async fn gather_connection_info(&self) -> anyhow::Result<inner_loop::ConnectionInfo> {
+        let mut info = self.0.aggregators[0].gather_connection_info().await?;
+        for a in &self.0.aggregators[1..] {
+            let other = a.gather_connection_info().await?;
+            info.current.connected_feeds += other.current.connected_feeds;
+            info.current.subscribed_feeds += other.current.subscribed_feeds;
+            info.peak.connected_feeds += other.peak.connected_feeds;
+            info.peak.subscribed_feeds += other.peak.subscribed_feeds;
+        }
+        Ok(info)
+    }
+
+    /// Return a summary of every connected shard. Every aggregator hears from every
+    /// shard, so we just ask the first one.
+    pub async fn gather_shards(&self) -> anyhow::Result<Vec<inner_loop::ShardSummary>> {
+        self.0.aggregators[0].gather_shards().await
+    }
+
+    /// Freeze or unfreeze a chain in every aggregator, returning `false` if it can't be found.
+    pub async fn set_chain_frozen(
+        &self,
+        genesis_hash: BlockHash,
+        frozen: bool,
+    ) -> anyhow::Result<bool> {
+        let mut found = false;
+        for a in &self.0.aggregators {
+            found |= a.set_chain_frozen(genesis_hash, frozen).await?;
+        }
+        Ok(found)
+    }
+
+    /// Change how many nodes a chain can have in every aggregator, removing the nodes over
+    /// the new limit if `evict` is true. Returns how many nodes are over the limit, or
+    /// `None` if the chain can't be found. Every aggregator knows about every node, so the
+    /// first aggregator's count is returned.
+    pub async fn set_chain_max_nodes(
+        &self,
+        genesis_hash: BlockHash,
+        max_nodes: usize,
+        evict: bool,
+    ) -> anyhow::Result<Option<usize>> {
+        let mut first = None;
+        for (idx, a) in self.0.aggregators.iter().enumerate() {
+            let over_quota = a
+                .set_chain_max_nodes(genesis_hash, max_nodes, evict)
+                .await?;
+            if idx == 0 {
+                first = over_quota;
+            }
+        }
+        Ok(first)
+    }
+
+    /// Compact a chain in every aggregator, returning `None` if it can't be found. Every
+    /// aggregator keeps its own copy of the chain, so what changed may differ between
+    /// them; the first aggregator's summary is returned.
+    pub async fn compact_chain(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<CompactSummary>> {
+        let mut first = None;
+        for (idx, a) in self.0.aggregators.iter().enumerate() {
+            let summary = a.compact_chain(genesis_hash).await?;
+            if idx == 0 {
+                first = summary;
+            }
+        }
+        Ok(first)
+    }
+
+    /// Move a node to the chain with the genesis hash `to` in every aggregator. Every
+    /// aggregator knows about every node, so they should all agree on how it went.
+    pub async fn move_node(
+        &self,
+        genesis_hash: BlockHash,
+        node: inner_loop::NodeLookup,
+        to: BlockHash,
+    ) -> anyhow::Result<inner_loop::MoveNodeOutcome> {
+        let mut outcome = inner_loop::MoveNodeOutcome::NodeNotFound;
+        for a in &self.0.aggregators {
+            outcome = a.move_node(genesis_hash, node.clone(), to).await?;
+        }
+        Ok(outcome)
+    }
+
+    /// Attach an annotation to a node in every aggregator, or clear it. Every aggregator
+    /// knows about every node, so they should all agree on whether it was found.
+    pub async fn annotate_node(
+        &self,
+        genesis_hash: BlockHash,
+        node: inner_loop::NodeLookup,
+        annotation: Option<NodeAnnotation>,
+    ) -> anyhow::Result<bool> {
+        let mut found = false;
+        for a in &self.0.aggregators {
+            found = a
+                .annotate_node(genesis_hash, node.clone(), annotation.clone())
+                .await?;
+        }
+        Ok(found)
+    }
+
+    /// Replace the node name blocklist used by every aggregator.
+    pub async fn set_node_name_blocklist(
+        &self,
+        blocklist: Arc<NodeNameBlocklist>,
+    ) -> anyhow::Result<()> {
+        for a in &self.0.aggregators {
+            a.set_node_name_blocklist(Arc::clone(&blocklist)).await?;
+        }
+        Ok(())
+    }
+
+    /// Replace the network denylist used by every aggregator.
+    pub async fn set_network_denylist(&self, denylist: Arc<NetworkDenylist>) -> anyhow::Result<()> {
+        for a in &self.0.aggregators {
+            a.set_network_denylist(Arc::clone(&denylist)).await?;
+        }
+        Ok(())
+    }
+
     /// Return a sink that a shard can send messages into to be handled by all aggregators.
     pub fn subscribe_shard(
         &self,
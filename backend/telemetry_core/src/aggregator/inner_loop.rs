@@ -15,25 +15,38 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use super::aggregator::ConnId;
-use crate::feed_message::{self, FeedMessageSerializer};
-use crate::state::{self, NodeId, State};
+use super::pending_genesis::{PendingGenesis, PendingNode, ShardNode};
+use crate::broker::BrokerSender;
+use crate::feed_message::{
+    self, FeedMessageSerializer, NodeDetailField, NodeDetailFields, TimeFormat,
+};
+use crate::state::{
+    self, BlockTimePoint, BlockedNodeNameAction, ChainAlert, ChainEventRecord, ChainNodeId,
+    CompactSummary, DuplicateNodePolicy, NetworkDenylist, Node, NodeAnnotation, NodeId,
+    NodeNameBlocklist, RecentBlock, RelayParent, RemovalReason, RemovedNodeRecord, State,
+};
+use crate::webhooks::WebhookAlert;
 use crate::{find_location, AggregatorOpts};
 use bimap::BiMap;
 use common::{
     internal_messages::{self, MuteReason, ShardNodeId},
     node_message,
-    node_types::BlockHash,
-    time, MultiMapUnique,
+    node_types::{Block, BlockHash, NetworkId, NodeDetails, NodeLocation, Timestamp},
+    time, DenseMapStats, Histogram, MultiMapUnique,
 };
-use std::collections::HashMap;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
 use std::sync::{
     atomic::{AtomicU64, Ordering},
     Arc,
 };
-use std::{net::IpAddr, str::FromStr};
+use std::time::{Duration, Instant};
 
 /// Incoming messages come via subscriptions, and end up looking like this.
 #[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum ToAggregator {
     FromShardWebsocket(ConnId, FromShardWebsocket),
     FromFeedWebsocket(ConnId, FromFeedWebsocket),
@@ -41,8 +54,209 @@ pub enum ToAggregator {
     /// Hand back some metrics. The provided sender is expected not to block when
     /// a message is sent into it.
     GatherMetrics(flume::Sender<Metrics>),
+    /// Replace the blocklist that the names of newly added nodes are checked against.
+    SetNodeNameBlocklist(Arc<NodeNameBlocklist>),
+    /// Replace the denylist that the networks of newly added nodes are checked against.
+    SetNetworkDenylist(Arc<NetworkDenylist>),
+    /// Hand back the full state of a node, or `None` if it can't be found. The
+    /// provided sender is expected not to block when a message is sent into it.
+    GatherNodeState {
+        genesis_hash: BlockHash,
+        node: NodeLookup,
+        tx: flume::Sender<Option<serde_json::Value>>,
+    },
+    /// Hand back the most recent best blocks of a chain, or `None` if it can't be found.
+    /// The provided sender is expected not to block when a message is sent into it.
+    GatherRecentBlocks {
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<Vec<RecentBlock>>>,
+    },
+    /// Hand back the average block time of a chain as of each of its recent best blocks,
+    /// oldest first, or `None` if it can't be found. The provided sender is expected not
+    /// to block when a message is sent into it.
+    GatherBlockTimeHistory {
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<Vec<BlockTimePoint>>>,
+    },
+    /// Hand back the nodes most recently removed from a chain, oldest first, or `None` if
+    /// it can't be found. The provided sender is expected not to block when a message is
+    /// sent into it.
+    GatherRemovedNodes {
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<Vec<RemovedNodeRecord>>>,
+    },
+    /// Hand back the most recent significant events on a chain, oldest first, or `None`
+    /// if it can't be found. The provided sender is expected not to block when a message
+    /// is sent into it.
+    GatherRecentEvents {
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<Vec<ChainEventRecord>>>,
+    },
+    /// Hand back how the slots that a chain's nodes are stored in are being used, or `None`
+    /// if it can't be found. The provided sender is expected not to block when a message is
+    /// sent into it.
+    GatherNodeSlots {
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<DenseMapStats>>,
+    },
+    /// Hand back the block times that a chain's average block time is worked out from,
+    /// or `None` if it can't be found. The provided sender is expected not to block when
+    /// a message is sent into it.
+    GatherBlockTimes {
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<BlockTimes>>,
+    },
+    /// Hand back a complete snapshot of a chain and its nodes, or just those nodes that
+    /// match the filter given (which isn't paged). Chains that don't expose node details
+    /// are only exported if `admin` is true. The provided sender is expected not to block
+    /// when a message is sent into it.
+    GatherChainExport {
+        genesis_hash: BlockHash,
+        admin: bool,
+        filter: NodeFilter,
+        tx: flume::Sender<ChainExportOutcome>,
+    },
+    /// Hand back a page of the nodes on a chain that match some filter, or `None` if the
+    /// chain can't be found. The provided sender is expected not to block when a message
+    /// is sent into it.
+    GatherNodes {
+        genesis_hash: BlockHash,
+        filter: NodeFilter,
+        tx: flume::Sender<Option<NodeSearch>>,
+    },
+    /// Hand back a summary of every chain. The provided sender is expected not
+    /// to block when a message is sent into it.
+    GatherChains(flume::Sender<Vec<ChainSummary>>),
+    /// Hand back current and peak connection counts. The provided sender is
+    /// expected not to block when a message is sent into it.
+    GatherConnectionInfo(flume::Sender<ConnectionInfo>),
+    /// Hand back a summary of every connected shard. The provided sender is
+    /// expected not to block when a message is sent into it.
+    GatherShards(flume::Sender<Vec<ShardSummary>>),
+    /// Freeze or unfreeze a chain, handing back whether it was found. The provided
+    /// sender is expected not to block when a message is sent into it.
+    SetChainFrozen {
+        genesis_hash: BlockHash,
+        frozen: bool,
+        tx: flume::Sender<bool>,
+    },
+    /// Change how many nodes a chain can have, removing the most recently connected nodes
+    /// over the new limit if `evict` is true. Hands back how many nodes are over the limit,
+    /// or `None` if the chain can't be found. The provided sender is expected not to block
+    /// when a message is sent into it.
+    SetChainMaxNodes {
+        genesis_hash: BlockHash,
+        max_nodes: usize,
+        evict: bool,
+        tx: flume::Sender<Option<usize>>,
+    },
+    /// Compact a chain, handing back what changed, or `None` if it can't be found. The
+    /// provided sender is expected not to block when a message is sent into it.
+    CompactChain {
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<CompactSummary>>,
+    },
+    /// Move a node to the chain with the genesis hash `to`, handing back how that went.
+    /// The provided sender is expected not to block when a message is sent into it.
+    MoveNode {
+        genesis_hash: BlockHash,
+        node: NodeLookup,
+        to: BlockHash,
+        tx: flume::Sender<MoveNodeOutcome>,
+    },
+    /// Attach an annotation to a node, or clear it, handing back `false` if the node can't
+    /// be found. The provided sender is expected not to block when a message is sent into it.
+    AnnotateNode {
+        genesis_hash: BlockHash,
+        node: NodeLookup,
+        annotation: Option<NodeAnnotation>,
+        tx: flume::Sender<bool>,
+    },
+    /// Clear node annotations that have expired.
+    ExpireNodeAnnotations,
+    /// Remove chains that have had no nodes for longer than the empty chain TTL.
+    SweepEmptyChains,
+    /// Send feeds that asked for an overview of every chain the latest one.
+    SendChainOverviews,
+}
+
+/// How asking to move a node to another chain went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveNodeOutcome {
+    /// The node was moved, and now has this ID on the chain.
+    Moved(ChainNodeId),
+    NodeNotFound,
+    AlreadyOnChain,
+    /// Nodes aren't allowed on the chain.
+    ChainNotAllowed,
+    ChainOverQuota,
+}
+
+/// Identify a node on some chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeLookup {
+    /// The chain local ID that feeds know the node by.
+    Id(ChainNodeId),
+    /// The network ID that the node reported.
+    NetworkId(Box<str>),
+}
+
+// Network IDs are never plain numbers, so anything numeric is taken to be an ID:
+impl From<&str> for NodeLookup {
+    fn from(s: &str) -> Self {
+        match s.parse::<usize>() {
+            Ok(id) => NodeLookup::Id(id.into()),
+            Err(_) => NodeLookup::NetworkId(s.into()),
+        }
+    }
+}
+
+/// Which of the nodes on a chain to hand back, and which page of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeFilter {
+    /// Only nodes whose names contain this, ignoring case.
+    pub name: Option<Box<str>>,
+    /// Only nodes whose best block is at least this high.
+    pub min_height: Option<u64>,
+    /// Only nodes that have told us their validator address.
+    pub validators_only: bool,
+    /// Only nodes in this country (an ISO 3166-1 alpha-2 code, ignoring case), or with
+    /// [`UNKNOWN_COUNTRY`], only nodes that we don't know the country of.
+    pub country: Option<Box<str>>,
+    /// Only nodes that have been connected for at least this many seconds.
+    pub min_uptime_secs: Option<u64>,
+    /// How many matching nodes to skip.
+    pub offset: usize,
+    /// The most matching nodes to hand back.
+    pub limit: usize,
+}
+
+impl NodeFilter {
+    pub fn matches(&self, node: &state::Node) -> bool {
+        let details = node.details();
+        self.name
+            .as_ref()
+            .is_none_or(|name| details.name.to_lowercase().contains(&name.to_lowercase()))
+            && self
+                .min_height
+                .is_none_or(|height| node.best().height >= height)
+            && (!self.validators_only || details.validator.is_some())
+            && self.country.as_ref().is_none_or(|country| {
+                match node.location().and_then(|l| l.country.as_deref()) {
+                    Some(node_country) => node_country.eq_ignore_ascii_case(country),
+                    None => country.eq_ignore_ascii_case(UNKNOWN_COUNTRY),
+                }
+            })
+            && self.min_uptime_secs.is_none_or(|secs| {
+                time::now().saturating_sub(node.connected_at()) >= secs.saturating_mul(1000)
+            })
+    }
 }
 
+/// What a [`NodeFilter`] asks for in place of a country, to match nodes whose country
+/// we don't know.
+pub const UNKNOWN_COUNTRY: &str = "unknown";
+
 /// An incoming shard connection can send these messages to the aggregator.
 #[derive(Clone, Debug)]
 pub enum FromShardWebsocket {
@@ -50,6 +264,7 @@ pub enum FromShardWebsocket {
     /// so that we have a way to communicate back to it.
     Initialize {
         channel: flume::Sender<ToShardWebsocket>,
+        addr: SocketAddr,
     },
     /// Tell the aggregator about a new node.
     Add {
@@ -65,6 +280,9 @@ pub enum FromShardWebsocket {
     },
     /// Tell the aggregator that a node has been removed when it disconnects.
     Remove { local_id: ShardNodeId },
+    /// A node sent the shard a message that it couldn't deserialize. The ID is that
+    /// of a node on the same connection, if there is one.
+    Malformed { local_id: Option<ShardNodeId> },
     /// The shard is disconnected.
     Disconnected,
 }
@@ -90,10 +308,22 @@ pub enum FromFeedWebsocket {
         channel: flume::Sender<ToFeedWebsocket>,
     },
     /// The feed can subscribe to a chain to receive
-    /// messages relating to it.
-    Subscribe { chain: BlockHash },
+    /// messages relating to it, with times in the format given.
+    Subscribe {
+        chain: BlockHash,
+        time_format: TimeFormat,
+    },
+    /// Instead of subscribing to a chain, the feed wants to be sent a summary of every
+    /// chain every so often, with times in the format given.
+    SubscribeOverview { time_format: TimeFormat },
+    /// The feed only wants to be told about the nodes in the given group (or all
+    /// nodes if `None`) from the next time that it subscribes to a chain.
+    SetGroup { group: Option<Box<str>> },
     /// An explicit ping message.
     Ping { value: Box<str> },
+    /// The feed thinks that it's missed something, and wants to be sent everything about
+    /// the chain that it's subscribed to again, as it was when it subscribed.
+    Resync,
     /// The feed is disconnected.
     Disconnected,
 }
@@ -116,12 +346,169 @@ pub struct Metrics {
     pub total_messages_to_aggregator: u64,
     /// How many (non-critical) messages have been dropped by the aggregator because it was overwhelmed.
     pub dropped_messages_to_aggregator: u64,
+    /// How many nodes were left without a location because too many lookups were queued up.
+    pub dropped_location_lookups: u64,
     /// How many nodes are currently known to this aggregator.
     pub connected_nodes: usize,
+    /// How many nodes are being held back until their new network gets a chain.
+    pub pending_nodes: usize,
     /// How many feeds are currently connected to this aggregator.
     pub connected_feeds: usize,
     /// How many shards are currently connected to this aggregator.
     pub connected_shards: usize,
+    /// Metrics for each chain known to this aggregator.
+    pub chains: Vec<ChainMetrics>,
+    /// Metrics for each shard connected to this aggregator.
+    pub shards: Vec<ShardMetrics>,
+}
+
+/// Metrics for a single chain.
+#[derive(Clone, Debug)]
+pub struct ChainMetrics {
+    pub genesis_hash: BlockHash,
+    pub label: Box<str>,
+    /// How many messages per second we're receiving about nodes on the chain.
+    pub messages_per_second: f64,
+    /// How long regenerating the chain stats has taken, in microseconds.
+    pub stats_regeneration_times: Histogram,
+    /// How many messages from nodes on the chain couldn't be deserialized.
+    pub malformed_messages: u64,
+    /// How many nodes are on the chain.
+    pub node_count: usize,
+    /// How many blocks the best block is ahead of the finalized block.
+    pub best_finalized_gap: u64,
+    /// How long it's been between best blocks, in milliseconds.
+    pub block_times: Histogram,
+    /// The most recent best block, if the chain is keeping hold of any.
+    pub latest_block: Option<RecentBlock>,
+}
+
+/// Metrics for a single shard connection.
+#[derive(Clone, Debug)]
+pub struct ShardMetrics {
+    pub conn_id: ConnId,
+    /// How many messages from nodes connected to the shard couldn't be deserialized.
+    pub malformed_messages: u64,
+}
+
+/// A summary of a connected shard and how healthy it looks.
+#[derive(Clone, Debug, Serialize)]
+pub struct ShardSummary {
+    pub conn_id: u64,
+    pub addr: SocketAddr,
+    /// How many nodes the shard has told us about that are still connected.
+    pub node_count: usize,
+    /// When (unix time in ms) the shard connected.
+    pub connected_at: Timestamp,
+    /// How long (in ms) the shard has been connected for.
+    pub uptime: u64,
+    /// When (unix time in ms) the shard last sent us anything.
+    pub last_message_at: Timestamp,
+    pub malformed_messages: u64,
+    /// False if the shard has nodes but hasn't sent anything about them for a while.
+    pub healthy: bool,
+}
+
+/// The block times (in ms) that a chain's average block time is worked out from.
+#[derive(Clone, Debug, Serialize)]
+pub struct BlockTimes {
+    /// The block times in the averaging window, oldest first.
+    pub samples: Vec<u64>,
+    /// The average of those block times, once there is one.
+    pub average: Option<u64>,
+}
+
+/// How asking for a snapshot of a chain went.
+#[derive(Clone, Debug)]
+pub enum ChainExportOutcome {
+    Exported(ChainExport),
+    ChainNotFound,
+    /// The chain doesn't expose node details, and the export would contain them.
+    AdminOnly,
+}
+
+/// Everything that we know about a chain and its nodes at one point in time.
+#[derive(Clone, Debug)]
+pub struct ChainExport {
+    /// The chain itself: its blocks, stats and recent blocks.
+    pub chain: serde_json::Value,
+    /// The full state of each node on the chain, including its ID.
+    pub nodes: Vec<serde_json::Value>,
+}
+
+/// A summary of a chain, including how it relates to other chains.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChainSummary {
+    pub genesis_hash: BlockHash,
+    /// The label of the chain, with the start of its genesis hash appended if another
+    /// chain has the same label.
+    pub label: Box<str>,
+    pub node_count: usize,
+    /// If the chain is a parachain, the relay chain that it belongs to.
+    pub relay_parent: Option<RelayParent>,
+    /// Genesis hashes of the known parachains belonging to this chain.
+    pub parachains: Vec<BlockHash>,
+    /// When (unix time in ms) we first saw a node on the chain.
+    pub first_seen: Timestamp,
+    /// A hex color to show the chain in, if configured.
+    pub color: Option<Box<str>>,
+    /// The URL or ID of an icon to show the chain with, if configured.
+    pub icon: Option<Box<str>>,
+}
+
+/// A summary of a node, as found when searching for them.
+#[derive(Clone, Debug, Serialize)]
+pub struct NodeSummary {
+    /// The chain local ID that feeds know the node by.
+    pub id: usize,
+    pub name: Box<str>,
+    pub network_id: NetworkId,
+    pub validator: Option<Box<str>>,
+    pub best: Block,
+    pub finalized: Block,
+    pub stale: bool,
+    pub last_seen: Timestamp,
+}
+
+/// A page of the nodes that matched some [`NodeFilter`].
+#[derive(Clone, Debug, Serialize)]
+pub struct NodeSearch {
+    /// How many nodes matched in total, across every page.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub nodes: Vec<NodeSummary>,
+}
+
+/// How many of each thing an aggregator is keeping track of.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct ConnectionCounts {
+    pub connected_shards: usize,
+    pub connected_feeds: usize,
+    /// How many feeds are subscribed to some chain.
+    pub subscribed_feeds: usize,
+    pub chains: usize,
+    pub nodes: usize,
+}
+
+impl ConnectionCounts {
+    /// Take the larger of each count.
+    pub fn max(self, other: ConnectionCounts) -> ConnectionCounts {
+        ConnectionCounts {
+            connected_shards: self.connected_shards.max(other.connected_shards),
+            connected_feeds: self.connected_feeds.max(other.connected_feeds),
+            subscribed_feeds: self.subscribed_feeds.max(other.subscribed_feeds),
+            chains: self.chains.max(other.chains),
+            nodes: self.nodes.max(other.nodes),
+        }
+    }
+}
+
+/// Current connection counts, and the highest that each has been since the aggregator started.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct ConnectionInfo {
+    pub current: ConnectionCounts,
+    pub peak: ConnectionCounts,
 }
 
 // The frontend sends text based commands; parse them into these messages:
@@ -136,10 +523,31 @@ impl FromStr for FromFeedWebsocket {
             "ping" => Ok(FromFeedWebsocket::Ping {
                 value: value.into(),
             }),
-            "subscribe" => Ok(FromFeedWebsocket::Subscribe {
-                chain: value.parse()?,
+            // Nothing is expected after the colon, ie `resync:`:
+            "resync" => Ok(FromFeedWebsocket::Resync),
+            // Feeds can optionally ask for a time format here too, eg `overview:s`:
+            "overview" => Ok(FromFeedWebsocket::SubscribeOverview {
+                time_format: match value {
+                    "" => TimeFormat::default(),
+                    time_format => time_format.parse()?,
+                },
             }),
-            _ => return Err(anyhow::anyhow!("Command {} not recognised", cmd)),
+            // An empty group, ie `group:`, goes back to seeing every node:
+            "group" => Ok(FromFeedWebsocket::SetGroup {
+                group: Some(value).filter(|g| !g.is_empty()).map(Into::into),
+            }),
+            // Feeds can optionally ask for a time format, eg `subscribe:0x123..,s`:
+            "subscribe" => {
+                let (chain, time_format) = match value.split_once(',') {
+                    Some((chain, time_format)) => (chain, time_format.parse()?),
+                    None => (value, TimeFormat::default()),
+                };
+                Ok(FromFeedWebsocket::Subscribe {
+                    chain: chain.parse()?,
+                    time_format,
+                })
+            }
+            _ => Err(anyhow::anyhow!("Command {} not recognised", cmd)),
         }
     }
 }
@@ -148,6 +556,27 @@ impl FromStr for FromFeedWebsocket {
 #[derive(Clone, Debug)]
 pub enum ToFeedWebsocket {
     Bytes(bytes::Bytes),
+    /// Part of the state that a feed is sent when it connects or subscribes to a chain.
+    /// These are the largest messages a feed gets, so are worth compressing on their own.
+    Snapshot(bytes::Bytes),
+}
+
+/// Feeds can ask to be resynced at most once in this long; more often is ignored.
+const MIN_RESYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A shard with nodes on it that sends us nothing for this long (in ms) is unhealthy;
+/// its nodes would usually each be sending something every few seconds.
+const SHARD_UNHEALTHY_AFTER_MS: u64 = 60_000;
+
+/// What we know about a connected shard.
+struct ShardInfo {
+    addr: SocketAddr,
+    /// When (unix time in ms) the shard connected.
+    connected_at: Timestamp,
+    /// When (unix time in ms) the shard last sent us anything.
+    last_message_at: Timestamp,
+    /// How many messages that they couldn't deserialize has the shard told us about?
+    malformed_messages: u64,
 }
 
 /// Instances of this are responsible for handling incoming and
@@ -163,34 +592,152 @@ pub struct InnerLoop {
     feed_channels: HashMap<ConnId, flume::Sender<ToFeedWebsocket>>,
     /// Keep track of how to send messages out to shards.
     shard_channels: HashMap<ConnId, flume::Sender<ToShardWebsocket>>,
+    /// What we know about each connected shard.
+    shard_info: HashMap<ConnId, ShardInfo>,
 
     /// Which feeds are subscribed to a given chain?
     chain_to_feed_conn_ids: MultiMapUnique<BlockHash, ConnId>,
 
+    /// Which feeds want times to be sent in seconds rather than milliseconds?
+    seconds_feed_conn_ids: HashSet<ConnId>,
+
+    /// Which feeds want an overview of every chain rather than to subscribe to one?
+    overview_feed_conn_ids: HashSet<ConnId>,
+
+    /// Feeds that only want to hear about the nodes in a group, and the group they
+    /// were subscribed with.
+    feed_groups: HashMap<ConnId, Box<str>>,
+
+    /// The group that feeds will be subscribed with the next time they subscribe.
+    pending_feed_groups: HashMap<ConnId, Box<str>>,
+
+    /// When each feed last asked to be resynced.
+    feed_last_resync: HashMap<ConnId, Instant>,
+
     /// Send messages here to make geographical location requests.
     tx_to_locator: flume::Sender<(NodeId, IpAddr)>,
+    /// How many nodes were left without a location because too many lookups were queued up.
+    dropped_location_lookups: u64,
 
     /// How big can the queue of messages coming in to the aggregator get before messages
     /// are prioritised and dropped to try and get back on track.
     max_queue_len: usize,
 
-    /// Flag to expose the node's details (IP address, SysInfo, HwBench) of all connected
-    /// nodes to the feed subscribers.
-    expose_node_details: bool,
+    /// What to do with nodes whose name is on the blocklist.
+    blocked_node_name_action: BlockedNodeNameAction,
+
+    /// The highest connection counts seen since we started.
+    peak_connection_counts: ConnectionCounts,
+
+    /// Nodes on new networks that are waiting for a chain to be created for them.
+    pending_genesis: PendingGenesis,
+
+    /// If given, alerts about chains are sent here to be passed on to webhooks.
+    tx_to_webhooks: Option<flume::Sender<WebhookAlert>>,
+
+    /// If given, the messages sent to feeds are also published to a message broker.
+    broker: Option<BrokerSender>,
+
+    /// Once more than this many messages are queued up, chains regenerate their stats
+    /// less often until the queue drains. 0 disables this.
+    stats_backoff_queue_len: usize,
 }
 
 impl InnerLoop {
     /// Create a new inner loop handler with the various state it needs.
     pub fn new(tx_to_locator: flume::Sender<(NodeId, IpAddr)>, opts: AggregatorOpts) -> Self {
+        let mut node_state = State::new(opts.denylist, opts.max_third_party_nodes);
+        node_state.set_max_unidentified_nodes(opts.max_unidentified_nodes);
+        node_state.set_node_name_blocklist(opts.node_name_blocklist, opts.blocked_node_name_action);
+        node_state.set_network_denylist(opts.network_denylist);
+        node_state.set_node_groups(opts.node_groups);
+        node_state.set_duplicate_node_policy(opts.duplicate_node_policy);
+        node_state.set_reconnect_policy(opts.reconnect_policy);
+        node_state.set_min_versions(
+            opts.chain_min_versions,
+            opts.outdated_version_policy,
+            opts.unparseable_version_policy,
+        );
+        node_state.set_flag_name_collisions(opts.flag_name_collisions);
+        node_state.set_rolling_windows(opts.rolling_windows);
+        node_state.set_block_time_warm_up(opts.block_time_warm_up);
+        node_state.set_max_block_time_step(opts.max_block_time_step);
+        node_state.set_stale_timeouts(opts.stale_timeouts);
+        node_state.set_chain_label_overrides(opts.chain_label_overrides);
+        node_state.set_chain_themes(opts.chain_themes);
+        node_state
+            .set_expose_node_details(opts.expose_node_details, opts.chain_expose_node_details);
+        node_state.set_node_detail_fields(opts.node_detail_fields, opts.chain_node_detail_fields);
+        node_state.set_recent_blocks_len(opts.recent_blocks_len, opts.chain_recent_blocks_len);
+        node_state.set_block_time_history_len(opts.block_time_history_len);
+        node_state.set_removed_nodes_len(opts.removed_nodes_len);
+        node_state.set_recent_events_len(opts.recent_events_len);
+        node_state.set_recent_restart_window(opts.recent_restart_window);
+        node_state.set_stats_timings(opts.stats_timings);
+        node_state.set_finality_gap_threshold(opts.finality_gap_threshold);
+        node_state.set_finality_lag_threshold(opts.finality_lag_threshold);
+        node_state.set_clock_skew_threshold(opts.clock_skew_threshold);
+        node_state.set_min_location_change(opts.min_location_change);
+        node_state.set_low_disk_threshold(opts.low_disk_threshold);
+        node_state.set_unreported_node_role(opts.unreported_node_role);
+        node_state.set_hardware_tiers(opts.hardware_tiers);
+        node_state.set_cardinality_limits(opts.cardinality_limits);
+        node_state.set_connection_tiers(opts.connection_tiers);
+        node_state.set_operator_pattern(opts.operator_pattern);
+        node_state.set_quota_burst(opts.quota_burst);
+        node_state.set_operator_cap(opts.operator_cap);
+        node_state.set_node_count_drops(opts.node_count_drops);
+        node_state.set_label_debounce(opts.label_debounce);
+        node_state.set_syncing_majority(opts.syncing_majority);
+        node_state.set_health_weights(opts.health_weights);
+        node_state.set_feed_rate_limit(opts.feed_rate_limit);
+        node_state.set_import_queue_backed_up_depth(opts.import_queue_backed_up_depth);
+        node_state.set_min_import_success_percent(opts.min_import_success_percent);
+        node_state.set_validator_label_weight(opts.validator_label_weight);
+        node_state.set_stable_node_ids(opts.stable_node_ids);
+        node_state.set_first_seen_store(opts.first_seen_store);
+        node_state.set_unlabeled_chain_format(opts.unlabeled_chain_format);
+        node_state.set_block_author_inactive_after(opts.block_author_inactive_after);
+        node_state.set_max_best_block_lead(opts.max_best_block_lead);
+        node_state.set_average_peers_change_percent(opts.average_peers_change_percent);
+        node_state.set_expected_validators(
+            opts.chain_expected_validators,
+            opts.validator_shortfall_threshold,
+        );
+        node_state.set_expected_block_times(opts.chain_expected_block_times);
+        node_state.set_private_chains(opts.private_chains);
+        node_state.set_hardware_changes(opts.hardware_changes);
+        node_state.set_min_listed_nodes(opts.min_chain_nodes);
+        node_state.set_min_listed_height(opts.min_chain_height);
+        node_state.set_empty_chain_ttl(opts.empty_chain_ttl);
+        node_state.set_prefer_finalized_branch(opts.prefer_finalized_branch);
+        node_state.set_max_located_nodes(opts.max_located_nodes, opts.chain_max_located_nodes);
+        node_state.set_imported_block_sample(
+            opts.imported_block_sample,
+            opts.chain_imported_block_sample,
+        );
+
         InnerLoop {
-            node_state: State::new(opts.denylist, opts.max_third_party_nodes),
+            node_state,
             node_ids: BiMap::new(),
             feed_channels: HashMap::new(),
             shard_channels: HashMap::new(),
+            shard_info: HashMap::new(),
             chain_to_feed_conn_ids: MultiMapUnique::new(),
+            seconds_feed_conn_ids: HashSet::new(),
+            overview_feed_conn_ids: HashSet::new(),
+            feed_groups: HashMap::new(),
+            pending_feed_groups: HashMap::new(),
+            feed_last_resync: HashMap::new(),
             tx_to_locator,
+            dropped_location_lookups: 0,
             max_queue_len: opts.max_queue_len,
-            expose_node_details: opts.expose_node_details,
+            blocked_node_name_action: opts.blocked_node_name_action,
+            peak_connection_counts: ConnectionCounts::default(),
+            pending_genesis: PendingGenesis::new(opts.new_chain_grace),
+            tx_to_webhooks: opts.tx_to_webhooks,
+            broker: opts.broker,
+            stats_backoff_queue_len: opts.stats_backoff_queue_len,
         }
     }
 
@@ -210,6 +757,7 @@ impl InnerLoop {
         let total_messages2 = Arc::clone(&total_messages);
         tokio::spawn(async move {
             while let Ok(msg) = metered_rx.recv_async().await {
+                self.update_load(metered_rx.len());
                 match msg {
                     ToAggregator::FromFeedWebsocket(feed_conn_id, msg) => {
                         self.handle_from_feed(feed_conn_id, msg)
@@ -226,7 +774,84 @@ impl InnerLoop {
                         dropped_messages2.load(Ordering::Relaxed),
                         total_messages2.load(Ordering::Relaxed),
                     ),
+                    ToAggregator::SetNodeNameBlocklist(blocklist) => self
+                        .node_state
+                        .set_node_name_blocklist(blocklist, self.blocked_node_name_action),
+                    ToAggregator::SetNetworkDenylist(denylist) => {
+                        self.node_state.set_network_denylist(denylist)
+                    }
+                    ToAggregator::GatherNodeState {
+                        genesis_hash,
+                        node,
+                        tx,
+                    } => self.handle_gather_node_state(genesis_hash, node, tx),
+                    ToAggregator::GatherRecentBlocks { genesis_hash, tx } => {
+                        self.handle_gather_recent_blocks(genesis_hash, tx)
+                    }
+                    ToAggregator::GatherBlockTimeHistory { genesis_hash, tx } => {
+                        self.handle_gather_block_time_history(genesis_hash, tx)
+                    }
+                    ToAggregator::GatherRemovedNodes { genesis_hash, tx } => {
+                        self.handle_gather_removed_nodes(genesis_hash, tx)
+                    }
+                    ToAggregator::GatherRecentEvents { genesis_hash, tx } => {
+                        self.handle_gather_recent_events(genesis_hash, tx)
+                    }
+                    ToAggregator::GatherNodeSlots { genesis_hash, tx } => {
+                        self.handle_gather_node_slots(genesis_hash, tx)
+                    }
+                    ToAggregator::GatherBlockTimes { genesis_hash, tx } => {
+                        self.handle_gather_block_times(genesis_hash, tx)
+                    }
+                    ToAggregator::GatherChainExport {
+                        genesis_hash,
+                        admin,
+                        filter,
+                        tx,
+                    } => self.handle_gather_chain_export(genesis_hash, admin, filter, tx),
+                    ToAggregator::GatherNodes {
+                        genesis_hash,
+                        filter,
+                        tx,
+                    } => self.handle_gather_nodes(genesis_hash, filter, tx),
+                    ToAggregator::GatherChains(tx) => self.handle_gather_chains(tx),
+                    ToAggregator::GatherConnectionInfo(tx) => {
+                        self.handle_gather_connection_info(tx)
+                    }
+                    ToAggregator::GatherShards(tx) => self.handle_gather_shards(tx),
+                    ToAggregator::SetChainFrozen {
+                        genesis_hash,
+                        frozen,
+                        tx,
+                    } => self.handle_set_chain_frozen(genesis_hash, frozen, tx),
+                    ToAggregator::SetChainMaxNodes {
+                        genesis_hash,
+                        max_nodes,
+                        evict,
+                        tx,
+                    } => self.handle_set_chain_max_nodes(genesis_hash, max_nodes, evict, tx),
+                    ToAggregator::CompactChain { genesis_hash, tx } => {
+                        self.handle_compact_chain(genesis_hash, tx)
+                    }
+                    ToAggregator::MoveNode {
+                        genesis_hash,
+                        node,
+                        to,
+                        tx,
+                    } => self.handle_move_node(genesis_hash, node, to, tx),
+                    ToAggregator::AnnotateNode {
+                        genesis_hash,
+                        node,
+                        annotation,
+                        tx,
+                    } => self.handle_annotate_node(genesis_hash, node, annotation, tx),
+                    ToAggregator::ExpireNodeAnnotations => self.handle_expire_node_annotations(),
+                    ToAggregator::SweepEmptyChains => self.handle_sweep_empty_chains(),
+                    ToAggregator::SendChainOverviews => self.handle_send_chain_overviews(),
                 }
+
+                self.peak_connection_counts =
+                    self.peak_connection_counts.max(self.connection_counts());
             }
         });
 
@@ -236,16 +861,19 @@ impl InnerLoop {
             // ignore node updates if we have too many messages to handle, in an attempt
             // to reduce the queue length back to something reasonable, lest it get out of
             // control and start consuming a load of memory.
-            if metered_tx.len() > max_queue_len {
-                if matches!(
+            if metered_tx.len() > max_queue_len
+                && matches!(
                     msg,
-                    ToAggregator::FromShardWebsocket(.., FromShardWebsocket::Update { .. })
-                ) {
-                    // Note: this wraps on overflow (which is probably the best
-                    // behaviour for graphing it anyway)
-                    dropped_messages.fetch_add(1, Ordering::Relaxed);
-                    continue;
-                }
+                    ToAggregator::FromShardWebsocket(
+                        ..,
+                        FromShardWebsocket::Update { .. } | FromShardWebsocket::Malformed { .. }
+                    )
+                )
+            {
+                // Note: this wraps on overflow (which is probably the best
+                // behaviour for graphing it anyway)
+                dropped_messages.fetch_add(1, Ordering::Relaxed);
+                continue;
             }
 
             if let Err(e) = metered_tx.send(msg) {
@@ -255,6 +883,22 @@ impl InnerLoop {
         }
     }
 
+    /// Chains regenerate their stats less often while lots of messages are queued up,
+    /// going back to normal once the queue has drained to half of the threshold.
+    fn update_load(&mut self, queue_len: usize) {
+        let threshold = self.stats_backoff_queue_len;
+        if threshold == 0 {
+            return;
+        }
+        if !self.node_state.is_under_load() && queue_len > threshold {
+            self.node_state.set_under_load(true);
+            log::warn!("{queue_len} messages are queued up; regenerating chain stats less often");
+        } else if self.node_state.is_under_load() && queue_len <= threshold / 2 {
+            self.node_state.set_under_load(false);
+            log::info!("Message queue has drained; regenerating chain stats as normal again");
+        }
+    }
+
     /// Gather and return some metrics.
     fn handle_gather_metrics(
         &mut self,
@@ -265,11 +909,38 @@ impl InnerLoop {
     ) {
         let timestamp_unix_ms = time::now();
         let connected_nodes = self.node_ids.len();
+        let pending_nodes = self.pending_genesis.node_count();
         let subscribed_feeds = self.chain_to_feed_conn_ids.num_values();
         let chains_subscribed_to = self.chain_to_feed_conn_ids.num_keys();
         let connected_shards = self.shard_channels.len();
         let connected_feeds = self.feed_channels.len();
         let total_messages_to_feeds: usize = self.feed_channels.values().map(|c| c.len()).sum();
+        let chains = self
+            .node_state
+            .chain_metrics()
+            .map(|chain| ChainMetrics {
+                genesis_hash: chain.genesis_hash,
+                label: chain.label.into(),
+                messages_per_second: chain.messages_per_second,
+                stats_regeneration_times: chain.stats_regeneration_times.clone(),
+                malformed_messages: chain.malformed_messages,
+                node_count: chain.node_count,
+                best_finalized_gap: chain.best_finalized_gap,
+                block_times: chain.block_times.clone(),
+                latest_block: chain.latest_block,
+            })
+            .collect();
+        let shards = self
+            .shard_channels
+            .keys()
+            .map(|&conn_id| ShardMetrics {
+                conn_id,
+                malformed_messages: self
+                    .shard_info
+                    .get(&conn_id)
+                    .map_or(0, |info| info.malformed_messages),
+            })
+            .collect();
 
         // Ignore error sending; assume the receiver stopped caring and dropped the channel:
         let _ = rx.send(Metrics {
@@ -280,299 +951,1214 @@ impl InnerLoop {
             current_messages_to_aggregator,
             total_messages_to_aggregator,
             dropped_messages_to_aggregator,
+            dropped_location_lookups: self.dropped_location_lookups,
             connected_nodes,
+            pending_nodes,
             connected_feeds,
             connected_shards,
+            chains,
+            shards,
         });
     }
 
-    /// Handle messages that come from the node geographical locator.
-    fn handle_from_find_location(&mut self, node_id: NodeId, location: find_location::Location) {
-        self.node_state
-            .update_node_location(node_id, location.clone());
+    /// Find a node and return its full state.
+    fn handle_gather_node_state(
+        &self,
+        genesis_hash: BlockHash,
+        node: NodeLookup,
+        tx: flume::Sender<Option<serde_json::Value>>,
+    ) {
+        let node_state = self
+            .node_state
+            .get_chain_by_genesis_hash(&genesis_hash)
+            .and_then(|chain| match &node {
+                NodeLookup::Id(id) => chain.get_node(*id),
+                NodeLookup::NetworkId(network_id) => chain.get_node_by_network_id(network_id),
+            })
+            .map(|node| node.full_state());
 
-        if let Some(loc) = location {
-            let mut feed_message_serializer = FeedMessageSerializer::new();
-            feed_message_serializer.push(feed_message::LocatedNode(
-                node_id.get_chain_node_id().into(),
-                loc.latitude,
-                loc.longitude,
-                &loc.city,
-            ));
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(node_state);
+    }
 
-            let chain_genesis_hash = self
-                .node_state
-                .get_chain_by_node_id(node_id)
-                .map(|chain| chain.genesis_hash());
+    /// Find a chain and return its most recent best blocks.
+    fn handle_gather_recent_blocks(
+        &self,
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<Vec<RecentBlock>>>,
+    ) {
+        let recent_blocks = self
+            .node_state
+            .get_chain_by_genesis_hash(&genesis_hash)
+            .map(|chain| chain.recent_blocks().iter().copied().collect());
 
-            if let Some(chain_genesis_hash) = chain_genesis_hash {
-                self.finalize_and_broadcast_to_chain_feeds(
-                    &chain_genesis_hash,
-                    feed_message_serializer,
-                );
-            }
-        }
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(recent_blocks);
     }
 
-    /// Handle messages coming from shards.
-    fn handle_from_shard(&mut self, shard_conn_id: ConnId, msg: FromShardWebsocket) {
-        match msg {
-            FromShardWebsocket::Initialize { channel } => {
-                self.shard_channels.insert(shard_conn_id, channel);
-            }
-            FromShardWebsocket::Add {
-                local_id,
-                ip,
-                mut node,
-                genesis_hash,
-            } => {
-                // Conditionally modify the node's details to include the IP address.
-                node.ip = self.expose_node_details.then_some(ip.to_string().into());
-                match self.node_state.add_node(genesis_hash, node) {
-                    state::AddNodeResult::ChainOnDenyList => {
-                        if let Some(shard_conn) = self.shard_channels.get_mut(&shard_conn_id) {
-                            let _ = shard_conn.send(ToShardWebsocket::Mute {
-                                local_id,
-                                reason: MuteReason::ChainNotAllowed,
-                            });
-                        }
-                    }
-                    state::AddNodeResult::ChainOverQuota => {
-                        if let Some(shard_conn) = self.shard_channels.get_mut(&shard_conn_id) {
-                            let _ = shard_conn.send(ToShardWebsocket::Mute {
-                                local_id,
-                                reason: MuteReason::Overquota,
-                            });
-                        }
-                    }
-                    state::AddNodeResult::NodeAddedToChain(details) => {
-                        let node_id = details.id;
+    /// Find a chain and return its average block time as of each recent best block.
+    fn handle_gather_block_time_history(
+        &self,
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<Vec<BlockTimePoint>>>,
+    ) {
+        let block_time_history = self
+            .node_state
+            .get_chain_by_genesis_hash(&genesis_hash)
+            .map(|chain| chain.block_time_history().iter().copied().collect());
 
-                        // Record ID <-> (shardId,localId) for future messages:
-                        self.node_ids.insert(node_id, (shard_conn_id, local_id));
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(block_time_history);
+    }
 
-                        // Don't hold onto details too long because we want &mut self later:
-                        let new_chain_label = details.new_chain_label.to_owned();
-                        let chain_node_count = details.chain_node_count;
-                        let has_chain_label_changed = details.has_chain_label_changed;
+    /// Find a chain and return the nodes most recently removed from it.
+    fn handle_gather_removed_nodes(
+        &self,
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<Vec<RemovedNodeRecord>>>,
+    ) {
+        let removed_nodes = self
+            .node_state
+            .get_chain_by_genesis_hash(&genesis_hash)
+            .map(|chain| chain.removed_nodes().iter().cloned().collect());
 
-                        // Tell chain subscribers about the node we've just added:
-                        let mut feed_messages_for_chain = FeedMessageSerializer::new();
-                        feed_messages_for_chain.push(feed_message::AddedNode(
-                            node_id.get_chain_node_id().into(),
-                            &details.node,
-                            self.expose_node_details,
-                        ));
-                        self.finalize_and_broadcast_to_chain_feeds(
-                            &genesis_hash,
-                            feed_messages_for_chain,
-                        );
-                        // Tell everybody about the new node count and potential rename:
-                        let mut feed_messages_for_all = FeedMessageSerializer::new();
-                        if has_chain_label_changed {
-                            feed_messages_for_all.push(feed_message::RemovedChain(genesis_hash));
-                        }
-                        feed_messages_for_all.push(feed_message::AddedChain(
-                            &new_chain_label,
-                            genesis_hash,
-                            chain_node_count,
-                        ));
-                        self.finalize_and_broadcast_to_all_feeds(feed_messages_for_all);
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(removed_nodes);
+    }
 
-                        // Ask for the geographical location of the node.
-                        let _ = self.tx_to_locator.send((node_id, ip));
-                    }
-                }
-            }
-            FromShardWebsocket::Remove { local_id } => {
-                let node_id = match self.node_ids.remove_by_right(&(shard_conn_id, local_id)) {
-                    Some((node_id, _)) => node_id,
-                    None => {
-                        // It's possible that some race between removing and disconnecting shards might lead to
-                        // more than one remove message for the same node. This isn't really a problem, but we
-                        // hope it won't happen so make a note if it does:
-                        log::debug!(
-                            "Remove: Cannot find ID for node with shard/connectionId of {shard_conn_id:?}/{local_id:?}"
-                        );
-                        return;
-                    }
-                };
-                self.remove_nodes_and_broadcast_result(Some(node_id));
-            }
-            FromShardWebsocket::Update { local_id, payload } => {
-                let node_id = match self.node_ids.get_by_right(&(shard_conn_id, local_id)) {
-                    Some(id) => *id,
-                    None => {
-                        log::error!(
-                            "Update: Cannot find ID for node with shard/connectionId of {shard_conn_id:?}/{local_id:?}"
-                        );
-                        return;
-                    }
-                };
+    /// Find a chain and return its most recent significant events.
+    fn handle_gather_recent_events(
+        &self,
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<Vec<ChainEventRecord>>>,
+    ) {
+        let recent_events = self
+            .node_state
+            .get_chain_by_genesis_hash(&genesis_hash)
+            .map(|chain| chain.recent_events().iter().cloned().collect());
 
-                let mut feed_message_serializer = FeedMessageSerializer::new();
-                self.node_state.update_node(
-                    node_id,
-                    payload,
-                    &mut feed_message_serializer,
-                    self.expose_node_details,
-                );
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(recent_events);
+    }
 
-                if let Some(chain) = self.node_state.get_chain_by_node_id(node_id) {
-                    let genesis_hash = chain.genesis_hash();
-                    self.finalize_and_broadcast_to_chain_feeds(
-                        &genesis_hash,
-                        feed_message_serializer,
-                    );
-                }
-            }
-            FromShardWebsocket::Disconnected => {
-                self.shard_channels.remove(&shard_conn_id);
+    /// Find a chain and return how the slots its nodes are stored in are being used.
+    fn handle_gather_node_slots(
+        &self,
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<DenseMapStats>>,
+    ) {
+        let node_slots = self
+            .node_state
+            .get_chain_by_genesis_hash(&genesis_hash)
+            .map(|chain| chain.node_slots());
 
-                // Find all nodes associated with this shard connection ID:
-                let node_ids_to_remove: Vec<NodeId> = self
-                    .node_ids
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(node_slots);
+    }
+
+    /// Find a chain and return the block times that its average block time comes from.
+    fn handle_gather_block_times(
+        &self,
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<BlockTimes>>,
+    ) {
+        let block_times = self
+            .node_state
+            .get_chain_by_genesis_hash(&genesis_hash)
+            .map(|chain| BlockTimes {
+                samples: chain.block_time_samples(),
+                average: chain.average_block_time(),
+            });
+
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(block_times);
+    }
+
+    /// Find a chain and take a snapshot of it and all of its nodes.
+    fn handle_gather_chain_export(
+        &self,
+        genesis_hash: BlockHash,
+        admin: bool,
+        filter: NodeFilter,
+        tx: flume::Sender<ChainExportOutcome>,
+    ) {
+        let outcome = match self.node_state.get_chain_by_genesis_hash(&genesis_hash) {
+            None => ChainExportOutcome::ChainNotFound,
+            Some(chain) if !admin && !chain.expose_node_details() => ChainExportOutcome::AdminOnly,
+            Some(chain) => ChainExportOutcome::Exported(ChainExport {
+                chain: serde_json::json!({
+                    "genesis_hash": chain.genesis_hash(),
+                    "label": chain.label(),
+                    "exported_at": time::now(),
+                    "best": chain.best_block(),
+                    "best_timestamp": chain.timestamp(),
+                    "average_block_time": chain.average_block_time(),
+                    "finalized": chain.finalized_block(),
+                    "relay_parent": chain.relay_parent(),
+                    "stats": chain.stats(),
+                    "recent_blocks": chain.recent_blocks(),
+                }),
+                nodes: chain
+                    .nodes_slice()
                     .iter()
-                    .filter(|(_, &(this_shard_conn_id, _))| shard_conn_id == this_shard_conn_id)
-                    .map(|(&node_id, _)| node_id)
-                    .collect();
+                    .enumerate()
+                    .filter_map(|(id, node)| {
+                        let node = node.as_ref().filter(|node| filter.matches(node))?;
+                        let mut state = node.full_state();
+                        state["id"] = id.into();
+                        Some(state)
+                    })
+                    .collect(),
+            }),
+        };
 
-                // ... and remove them:
-                self.remove_nodes_and_broadcast_result(node_ids_to_remove);
-            }
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(outcome);
+    }
+
+    /// How many shards and feeds are connected, and how many chains and nodes we know
+    /// about, right now.
+    fn connection_counts(&self) -> ConnectionCounts {
+        ConnectionCounts {
+            connected_shards: self.shard_channels.len(),
+            connected_feeds: self.feed_channels.len(),
+            subscribed_feeds: self.chain_to_feed_conn_ids.num_values(),
+            chains: self.node_state.chain_count(),
+            nodes: self.node_ids.len(),
         }
     }
 
-    /// Handle messages coming from feeds.
-    fn handle_from_feed(&mut self, feed_conn_id: ConnId, msg: FromFeedWebsocket) {
-        match msg {
-            FromFeedWebsocket::Initialize { channel } => {
-                self.feed_channels.insert(feed_conn_id, channel.clone());
+    fn handle_gather_connection_info(&self, tx: flume::Sender<ConnectionInfo>) {
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(ConnectionInfo {
+            current: self.connection_counts(),
+            peak: self.peak_connection_counts,
+        });
+    }
 
-                // Tell the new feed subscription some basic things to get it going:
-                let mut feed_serializer = FeedMessageSerializer::new();
-                feed_serializer.push(feed_message::Version(32));
-                for chain in self.node_state.iter_chains() {
-                    feed_serializer.push(feed_message::AddedChain(
-                        chain.label(),
-                        chain.genesis_hash(),
-                        chain.node_count(),
-                    ));
-                }
+    fn handle_gather_shards(&self, tx: flume::Sender<Vec<ShardSummary>>) {
+        let now = time::now();
+        let mut node_counts: HashMap<ConnId, usize> = HashMap::new();
+        for &(shard_conn_id, _) in self.node_ids.right_values() {
+            *node_counts.entry(shard_conn_id).or_default() += 1;
+        }
 
-                // Send this to the channel that subscribed:
-                if let Some(bytes) = feed_serializer.into_finalized() {
-                    let _ = channel.send(ToFeedWebsocket::Bytes(bytes));
+        let mut shards: Vec<_> = self
+            .shard_info
+            .iter()
+            .map(|(&conn_id, info)| {
+                let node_count = node_counts.get(&conn_id).copied().unwrap_or(0);
+                let quiet_for = now.saturating_sub(info.last_message_at);
+                ShardSummary {
+                    conn_id: conn_id.into(),
+                    addr: info.addr,
+                    node_count,
+                    connected_at: info.connected_at,
+                    uptime: now.saturating_sub(info.connected_at),
+                    last_message_at: info.last_message_at,
+                    malformed_messages: info.malformed_messages,
+                    healthy: node_count == 0 || quiet_for < SHARD_UNHEALTHY_AFTER_MS,
                 }
-            }
-            FromFeedWebsocket::Ping { value } => {
-                let feed_channel = match self.feed_channels.get_mut(&feed_conn_id) {
-                    Some(chan) => chan,
-                    None => return,
-                };
+            })
+            .collect();
+        shards.sort_by_key(|shard| shard.conn_id);
 
-                // Pong!
-                let mut feed_serializer = FeedMessageSerializer::new();
-                feed_serializer.push(feed_message::Pong(&value));
-                if let Some(bytes) = feed_serializer.into_finalized() {
-                    let _ = feed_channel.send(ToFeedWebsocket::Bytes(bytes));
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(shards);
+    }
+
+    /// Find a chain and return a page of its nodes matching the filter given.
+    fn handle_gather_nodes(
+        &self,
+        genesis_hash: BlockHash,
+        filter: NodeFilter,
+        tx: flume::Sender<Option<NodeSearch>>,
+    ) {
+        let search = self
+            .node_state
+            .get_chain_by_genesis_hash(&genesis_hash)
+            .map(|chain| {
+                let mut total = 0;
+                let mut nodes = Vec::new();
+                let matching = chain
+                    .nodes_slice()
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(id, node)| Some((id, node.as_ref()?)))
+                    .filter(|(_, node)| filter.matches(node));
+                for (id, node) in matching {
+                    if total >= filter.offset && nodes.len() < filter.limit {
+                        let details = node.details();
+                        nodes.push(NodeSummary {
+                            id,
+                            name: details.name.clone(),
+                            network_id: details.network_id,
+                            validator: details.validator.clone(),
+                            best: *node.best(),
+                            finalized: *node.finalized(),
+                            stale: node.stale(),
+                            last_seen: node.last_seen(),
+                        });
+                    }
+                    total += 1;
+                }
+                NodeSearch {
+                    total,
+                    offset: filter.offset,
+                    limit: filter.limit,
+                    nodes,
                 }
+            });
+
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(search);
+    }
+
+    fn handle_sweep_empty_chains(&mut self) {
+        for genesis_hash in self.node_state.remove_expired_empty_chains(Instant::now()) {
+            log::debug!("Removing chain {genesis_hash:?}, which has had no nodes for a while");
+        }
+    }
+
+    /// Send feeds that asked for an overview of every chain the latest one.
+    fn handle_send_chain_overviews(&self) {
+        if self.overview_feed_conn_ids.is_empty() {
+            return;
+        }
+        let seconds_wanted = self
+            .overview_feed_conn_ids
+            .iter()
+            .any(|id| self.seconds_feed_conn_ids.contains(id));
+        let mut feed_serializer = FeedMessageSerializer::for_time_formats(seconds_wanted);
+        feed_serializer.push(feed_message::ChainOverviews(&self.chain_overviews()));
+        let Some(messages) = feed_serializer.into_finalized_per_time_format() else {
+            return;
+        };
+        for feed_conn_id in &self.overview_feed_conn_ids {
+            let time_format = match self.seconds_feed_conn_ids.contains(feed_conn_id) {
+                true => TimeFormat::Seconds,
+                false => TimeFormat::Millis,
+            };
+            if let Some(chan) = self.feed_channels.get(feed_conn_id) {
+                let bytes = messages.for_time_format(time_format).clone();
+                let _ = chan.send(ToFeedWebsocket::Bytes(bytes));
             }
-            FromFeedWebsocket::Subscribe { chain } => {
-                let feed_channel = match self.feed_channels.get_mut(&feed_conn_id) {
-                    Some(chan) => chan,
-                    None => return,
+        }
+    }
+
+    /// The headline numbers of every chain that feeds are told about.
+    fn chain_overviews(&self) -> Vec<feed_message::ChainOverview<'_>> {
+        let listed_chains = || self.node_state.iter_chains().filter(|c| c.is_listed());
+        let colliding = colliding_labels(listed_chains().map(|chain| chain.label()));
+        listed_chains()
+            .map(|chain| feed_message::ChainOverview {
+                label: match colliding.contains(chain.label()) {
+                    true => disambiguated_label(chain.label(), chain.genesis_hash()).into(),
+                    false => chain.label().into(),
+                },
+                genesis_hash: chain.genesis_hash(),
+                node_count: chain.node_count(),
+                best_height: chain.best_block().height,
+                finalized_height: chain.finalized_block().height,
+                average_block_time: chain.average_block_time(),
+            })
+            .collect()
+    }
+
+    /// Summarise every chain, and which parachains belong to which relay chains.
+    fn handle_gather_chains(&self, tx: flume::Sender<Vec<ChainSummary>>) {
+        // Chains that feeds aren't told about are left out here too:
+        let mut parachains: HashMap<BlockHash, Vec<BlockHash>> = HashMap::new();
+        for chain in self.node_state.iter_chains().filter(|c| c.is_listed()) {
+            if let Some(relay_parent) = chain.relay_parent() {
+                parachains
+                    .entry(relay_parent.genesis_hash)
+                    .or_default()
+                    .push(chain.genesis_hash());
+            }
+        }
+
+        let colliding = colliding_labels(
+            self.node_state
+                .iter_chains()
+                .filter(|chain| chain.is_listed())
+                .map(|chain| chain.label()),
+        );
+        let chains = self
+            .node_state
+            .iter_chains()
+            .filter(|chain| chain.is_listed())
+            .map(|chain| ChainSummary {
+                genesis_hash: chain.genesis_hash(),
+                label: match colliding.contains(chain.label()) {
+                    true => disambiguated_label(chain.label(), chain.genesis_hash()).into(),
+                    false => chain.label().into(),
+                },
+                node_count: chain.node_count(),
+                relay_parent: chain.relay_parent().copied(),
+                parachains: parachains.remove(&chain.genesis_hash()).unwrap_or_default(),
+                first_seen: chain.first_seen(),
+                color: chain.theme().color.clone(),
+                icon: chain.theme().icon.clone(),
+            })
+            .collect();
+
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(chains);
+    }
+
+    /// Freeze or unfreeze a chain, letting subscribed feeds know if anything changed.
+    fn handle_set_chain_frozen(
+        &mut self,
+        genesis_hash: BlockHash,
+        frozen: bool,
+        tx: flume::Sender<bool>,
+    ) {
+        let changed = self.node_state.set_chain_frozen(&genesis_hash, frozen);
+
+        if changed == Some(true) {
+            if let Some(chain) = self.node_state.get_chain_by_genesis_hash(&genesis_hash) {
+                let mut feed_serializer = FeedMessageSerializer::new();
+                feed_serializer.push(feed_message::ChainStatsUpdate(chain.stats()));
+                self.finalize_and_broadcast_to_chain_feeds(&genesis_hash, feed_serializer);
+            }
+        }
+
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(changed.is_some());
+    }
+
+    /// Change how many nodes a chain can have, muting and removing the nodes over the new
+    /// limit if asked to.
+    fn handle_set_chain_max_nodes(
+        &mut self,
+        genesis_hash: BlockHash,
+        max_nodes: usize,
+        evict: bool,
+        tx: flume::Sender<Option<usize>>,
+    ) {
+        let over_quota = self
+            .node_state
+            .set_chain_max_nodes(&genesis_hash, max_nodes);
+        let over_quota_count = over_quota.as_ref().map(|nodes| nodes.len());
+
+        if let Some(node_ids) = over_quota.filter(|_| evict) {
+            for node_id in &node_ids {
+                let shard_node = self.node_ids.get_by_left(node_id).copied();
+                if let Some((shard_conn_id, local_id)) = shard_node {
+                    if let Some(shard_conn) = self.shard_channels.get_mut(&shard_conn_id) {
+                        let _ = shard_conn.send(ToShardWebsocket::Mute {
+                            local_id,
+                            reason: MuteReason::Overquota,
+                        });
+                    }
+                }
+            }
+            self.remove_nodes_and_broadcast_result(node_ids, RemovalReason::Evicted);
+        }
+
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(over_quota_count);
+    }
+
+    /// Compact a chain, telling subscribed feeds about its regenerated stats.
+    fn handle_compact_chain(
+        &mut self,
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<CompactSummary>>,
+    ) {
+        let mut feed_serializer = FeedMessageSerializer::new();
+        let summary = self
+            .node_state
+            .compact_chain(&genesis_hash, &mut feed_serializer);
+        self.finalize_and_broadcast_to_chain_feeds(&genesis_hash, feed_serializer);
+
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(summary);
+    }
+
+    /// Find a node on a chain.
+    fn lookup_node(&self, genesis_hash: &BlockHash, node: NodeLookup) -> Option<NodeId> {
+        self.node_state
+            .get_chain_by_genesis_hash(genesis_hash)
+            .and_then(|chain| match node {
+                NodeLookup::Id(id) => Some(id),
+                NodeLookup::NetworkId(network_id) => chain.get_node_id_by_network_id(&network_id),
+            })
+            .and_then(|id| self.node_state.get_node_id(genesis_hash, id))
+    }
+
+    /// Attach an annotation to a node, or clear it, telling feeds of its chain about it.
+    fn handle_annotate_node(
+        &mut self,
+        genesis_hash: BlockHash,
+        node: NodeLookup,
+        annotation: Option<NodeAnnotation>,
+        tx: flume::Sender<bool>,
+    ) {
+        let mut feed_serializer = FeedMessageSerializer::new();
+        let found = self
+            .lookup_node(&genesis_hash, node)
+            .is_some_and(|node_id| {
+                self.node_state
+                    .annotate_node(node_id, annotation, &mut feed_serializer)
+            });
+        self.finalize_and_broadcast_to_chain_feeds(&genesis_hash, feed_serializer);
+
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(found);
+    }
+
+    /// Clear node annotations that have expired, telling feeds of their chains.
+    fn handle_expire_node_annotations(&mut self) {
+        let now = time::now();
+        let genesis_hashes: Vec<_> = self
+            .node_state
+            .iter_chains()
+            .map(|chain| chain.genesis_hash())
+            .collect();
+        for genesis_hash in genesis_hashes {
+            let mut feed_serializer = FeedMessageSerializer::new();
+            self.node_state
+                .expire_node_annotations(&genesis_hash, now, &mut feed_serializer);
+            self.finalize_and_broadcast_to_chain_feeds(&genesis_hash, feed_serializer);
+        }
+    }
+
+    /// Move a node to another chain, telling feeds of both chains about it.
+    fn handle_move_node(
+        &mut self,
+        genesis_hash: BlockHash,
+        node: NodeLookup,
+        to: BlockHash,
+        tx: flume::Sender<MoveNodeOutcome>,
+    ) {
+        let node_id = match self.lookup_node(&genesis_hash, node) {
+            Some(node_id) => node_id,
+            None => {
+                let _ = tx.send(MoveNodeOutcome::NodeNotFound);
+                return;
+            }
+        };
+
+        let node_detail_fields = self.node_state.node_detail_fields(&to);
+        let mut feed_messages_for_old_chain = FeedMessageSerializer::new();
+        // This may contain times, so serialize it for seconds too if needed:
+        let mut feed_messages_for_new_chain =
+            FeedMessageSerializer::for_time_formats(!self.seconds_feed_conn_ids.is_empty());
+        let mut feed_messages_for_all = FeedMessageSerializer::new();
+        let mut chain_blocks_changed = false;
+        let outcome = match self.node_state.move_node(node_id, to) {
+            state::MoveNodeResult::NodeNotFound => MoveNodeOutcome::NodeNotFound,
+            state::MoveNodeResult::AlreadyOnChain => MoveNodeOutcome::AlreadyOnChain,
+            state::MoveNodeResult::ChainOnDenyList | state::MoveNodeResult::InvalidGenesisHash => {
+                MoveNodeOutcome::ChainNotAllowed
+            }
+            state::MoveNodeResult::ChainOverQuota => MoveNodeOutcome::ChainOverQuota,
+            state::MoveNodeResult::NodeMoved(moved) => {
+                let new_node_id = moved.added.id;
+
+                // Messages from the node's shard now need to find it on its new chain:
+                if let Some((_, shard_node_id)) = self.node_ids.remove_by_left(&node_id) {
+                    self.node_ids.insert(new_node_id, shard_node_id);
+                }
+
+                push_removed_node(
+                    &moved.removed,
+                    node_id.get_chain_node_id(),
+                    &mut feed_messages_for_old_chain,
+                    &mut feed_messages_for_all,
+                );
+                push_added_node(
+                    &moved.added,
+                    to,
+                    node_detail_fields,
+                    &mut feed_messages_for_new_chain,
+                    &mut feed_messages_for_all,
+                );
+                chain_blocks_changed = moved.added.has_chain_blocks_changed;
+                MoveNodeOutcome::Moved(new_node_id.get_chain_node_id())
+            }
+        };
+        // The node may have been ahead of the chain it's moved to:
+        if let Some(chain) = self
+            .node_state
+            .get_chain_by_genesis_hash(&to)
+            .filter(|_| chain_blocks_changed)
+        {
+            feed_messages_for_new_chain.push(feed_message::BestBlock(
+                chain.best_block().height,
+                chain.timestamp(),
+                chain.average_block_time(),
+                chain.best_block().hash,
+            ));
+            feed_messages_for_new_chain.push(feed_message::BestFinalized(
+                chain.finalized_block().height,
+                chain.finalized_block().hash,
+            ));
+        }
+        self.finalize_and_broadcast_to_chain_feeds(&genesis_hash, feed_messages_for_old_chain);
+        self.finalize_and_broadcast_to_chain_feeds(&to, feed_messages_for_new_chain);
+        self.finalize_and_broadcast_to_all_feeds(feed_messages_for_all);
+
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(outcome);
+    }
+
+    /// Handle messages that come from the node geographical locator.
+    fn handle_from_find_location(&mut self, node_id: NodeId, location: find_location::Location) {
+        let changed = self
+            .node_state
+            .update_node_location(node_id, location.clone());
+
+        if let Some(loc) = location.filter(|_| changed) {
+            if let Some((chain_genesis_hash, feed_message_serializer)) =
+                located_node_message(&self.node_state, node_id, &loc)
+            {
+                self.finalize_and_broadcast_to_chain_feeds(
+                    &chain_genesis_hash,
+                    feed_message_serializer,
+                );
+            }
+        }
+    }
+
+    /// Tell the shard that a node it's just told us about won't be accepted, and why,
+    /// so that it stops sending us the node's messages.
+    fn reject_node(
+        &mut self,
+        shard_conn_id: ConnId,
+        local_id: ShardNodeId,
+        node_name: &str,
+        genesis_hash: BlockHash,
+        reason: MuteReason,
+    ) {
+        // Every aggregator rejects the node, so this is logged once per aggregator:
+        log::debug!(
+            "Rejecting node '{node_name}' from shard {shard_conn_id:?} on {genesis_hash:?}: {reason}"
+        );
+        if let Some(shard_conn) = self.shard_channels.get_mut(&shard_conn_id) {
+            let _ = shard_conn.send(ToShardWebsocket::Mute { local_id, reason });
+        }
+    }
+
+    /// Add a node that a shard has told us about to its chain, telling feeds about it.
+    fn add_node(
+        &mut self,
+        shard_conn_id: ConnId,
+        local_id: ShardNodeId,
+        ip: IpAddr,
+        mut node: NodeDetails,
+        genesis_hash: BlockHash,
+    ) {
+        // Conditionally modify the node's details to include the IP address.
+        let node_detail_fields = self.node_state.node_detail_fields(&genesis_hash);
+        node.ip = node_detail_fields
+            .contains(NodeDetailField::Ip)
+            .then_some(ip.to_string().into());
+        let duplicate_node_policy = self.node_state.duplicate_node_policy();
+        let node_name = node.name.clone();
+        match self.node_state.add_node(genesis_hash, node) {
+            state::AddNodeResult::ChainOnDenyList => {
+                self.reject_node(
+                    shard_conn_id,
+                    local_id,
+                    &node_name,
+                    genesis_hash,
+                    MuteReason::ChainNotAllowed,
+                );
+            }
+            state::AddNodeResult::ChainOverQuota => {
+                self.reject_node(
+                    shard_conn_id,
+                    local_id,
+                    &node_name,
+                    genesis_hash,
+                    MuteReason::Overquota,
+                );
+            }
+            state::AddNodeResult::NodeNameOnBlocklist => {
+                self.reject_node(
+                    shard_conn_id,
+                    local_id,
+                    &node_name,
+                    genesis_hash,
+                    MuteReason::NodeNameNotAllowed,
+                );
+            }
+            state::AddNodeResult::InvalidGenesisHash => {
+                self.reject_node(
+                    shard_conn_id,
+                    local_id,
+                    &node_name,
+                    genesis_hash,
+                    MuteReason::InvalidGenesisHash,
+                );
+            }
+            state::AddNodeResult::DuplicateNode => {
+                self.reject_node(
+                    shard_conn_id,
+                    local_id,
+                    &node_name,
+                    genesis_hash,
+                    MuteReason::DuplicateNode,
+                );
+            }
+            state::AddNodeResult::OperatorOverCap => {
+                self.reject_node(
+                    shard_conn_id,
+                    local_id,
+                    &node_name,
+                    genesis_hash,
+                    MuteReason::OperatorOverCap,
+                );
+            }
+            state::AddNodeResult::OutdatedVersion => {
+                self.reject_node(
+                    shard_conn_id,
+                    local_id,
+                    &node_name,
+                    genesis_hash,
+                    MuteReason::OutdatedVersion,
+                );
+            }
+            state::AddNodeResult::NodeAddedToChain(details) => {
+                let node_id = details.id;
+                let duplicate_of = details.duplicate_of;
+                let is_reconnect = details.is_reconnect;
+                let name_collisions = details.name_collisions;
+                let should_locate = details.should_locate;
+                let is_outdated = details.is_outdated;
+
+                // Record ID <-> (shardId,localId) for future messages:
+                self.node_ids.insert(node_id, (shard_conn_id, local_id));
+
+                // This may contain times, so serialize it for seconds too if needed:
+                let mut feed_messages_for_chain =
+                    FeedMessageSerializer::for_time_formats(!self.seconds_feed_conn_ids.is_empty());
+                let mut feed_messages_for_all = FeedMessageSerializer::new();
+                push_added_node(
+                    &details,
+                    genesis_hash,
+                    node_detail_fields,
+                    &mut feed_messages_for_chain,
+                    &mut feed_messages_for_all,
+                );
+                if let Some(existing_node_id) = duplicate_of.filter(|_| is_reconnect) {
+                    log::info!(
+                        "Node {node_id:?} on {genesis_hash:?} connected again while its old connection (node {existing_node_id:?}) is still open; replacing it"
+                    );
+                } else if let Some(existing_node_id) = duplicate_of {
+                    log::warn!(
+                        "Node {node_id:?} on {genesis_hash:?} has the same network ID as node {existing_node_id:?}"
+                    );
+                    if duplicate_node_policy == DuplicateNodePolicy::Flag {
+                        feed_messages_for_chain.push(feed_message::DuplicateNode(
+                            node_id.get_chain_node_id().into(),
+                            existing_node_id.get_chain_node_id().into(),
+                        ));
+                    }
+                }
+                if name_collisions > 0 {
+                    log::debug!(
+                        "Node {node_id:?} on {genesis_hash:?} has the same name as {name_collisions} other node(s)"
+                    );
+                    feed_messages_for_chain.push(feed_message::NameCollision(
+                        node_id.get_chain_node_id().into(),
+                        name_collisions as u64,
+                    ));
+                }
+                if is_outdated {
+                    feed_messages_for_chain.push(feed_message::OutdatedVersion(
+                        node_id.get_chain_node_id().into(),
+                    ));
+                }
+                self.finalize_and_broadcast_to_chain_feeds(&genesis_hash, feed_messages_for_chain);
+                self.finalize_and_broadcast_to_all_feeds(feed_messages_for_all);
+
+                // Ask for the geographical location of the node, unless the chain has enough
+                // located nodes already or too many lookups are queued up, in which case it
+                // goes without:
+                if !should_locate {
+                    log::trace!(
+                        "Enough nodes on {genesis_hash:?} located; not locating node {node_id:?}"
+                    );
+                } else if let Err(flume::TrySendError::Full(_)) =
+                    self.tx_to_locator.try_send((node_id, ip))
+                {
+                    self.dropped_location_lookups += 1;
+                    log::debug!(
+                        "Too many location lookups queued up; not locating node {node_id:?}"
+                    );
+                }
+
+                // The node that was there first (or its old connection) makes way for the new one:
+                if let Some(existing_node_id) = duplicate_of.filter(|_| {
+                    is_reconnect || duplicate_node_policy == DuplicateNodePolicy::Replace
+                }) {
+                    let shard_node = self.node_ids.get_by_left(&existing_node_id).copied();
+                    if let Some((shard_conn_id, local_id)) = shard_node {
+                        if let Some(shard_conn) = self.shard_channels.get_mut(&shard_conn_id) {
+                            let _ = shard_conn.send(ToShardWebsocket::Mute {
+                                local_id,
+                                reason: MuteReason::DuplicateNode,
+                            });
+                        }
+                    }
+                    self.remove_nodes_and_broadcast_result(
+                        Some(existing_node_id),
+                        RemovalReason::Replaced,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Add nodes that are no longer being held back while their chain is pending.
+    fn add_released_nodes(&mut self, released: Vec<(ShardNode, BlockHash, PendingNode)>) {
+        for ((shard_conn_id, local_id), genesis_hash, pending) in released {
+            self.add_node(
+                shard_conn_id,
+                local_id,
+                pending.ip,
+                pending.details,
+                genesis_hash,
+            );
+        }
+    }
+
+    /// Handle messages coming from shards.
+    fn handle_from_shard(&mut self, shard_conn_id: ConnId, msg: FromShardWebsocket) {
+        let now = time::now();
+        if let Some(info) = self.shard_info.get_mut(&shard_conn_id) {
+            info.last_message_at = now;
+        }
+
+        match msg {
+            FromShardWebsocket::Initialize { channel, addr } => {
+                self.shard_channels.insert(shard_conn_id, channel);
+                self.shard_info.insert(
+                    shard_conn_id,
+                    ShardInfo {
+                        addr,
+                        connected_at: now,
+                        last_message_at: now,
+                        malformed_messages: 0,
+                    },
+                );
+            }
+            FromShardWebsocket::Add {
+                local_id,
+                ip,
+                node,
+                genesis_hash,
+            } => {
+                let chain_exists = self
+                    .node_state
+                    .get_chain_by_genesis_hash(&genesis_hash)
+                    .is_some();
+                if !self
+                    .pending_genesis
+                    .should_hold(&genesis_hash, chain_exists)
+                {
+                    self.add_node(shard_conn_id, local_id, ip, node, genesis_hash);
+                    return;
+                }
+
+                log::debug!("Holding back node on new network {genesis_hash:?} for now");
+                let released = self.pending_genesis.hold(
+                    genesis_hash,
+                    (shard_conn_id, local_id),
+                    ip,
+                    node,
+                    Instant::now(),
+                );
+                self.add_released_nodes(released);
+            }
+            FromShardWebsocket::Remove { local_id } => {
+                if self.pending_genesis.remove(&(shard_conn_id, local_id)) {
+                    return;
+                }
+                let node_id = match self.node_ids.remove_by_right(&(shard_conn_id, local_id)) {
+                    Some((node_id, _)) => node_id,
+                    None => {
+                        // It's possible that some race between removing and disconnecting shards might lead to
+                        // more than one remove message for the same node. This isn't really a problem, but we
+                        // hope it won't happen so make a note if it does:
+                        log::debug!(
+                            "Remove: Cannot find ID for node with shard/connectionId of {shard_conn_id:?}/{local_id:?}"
+                        );
+                        return;
+                    }
                 };
+                self.remove_nodes_and_broadcast_result(Some(node_id), RemovalReason::Disconnected);
+            }
+            FromShardWebsocket::Update { local_id, payload } => {
+                // Nodes waiting on their chain may have waited long enough by now:
+                if self.pending_genesis.contains(&(shard_conn_id, local_id)) {
+                    let released = self
+                        .pending_genesis
+                        .poll(&(shard_conn_id, local_id), Instant::now());
+                    self.add_released_nodes(released);
+                }
 
-                // Unsubscribe from previous chain if subscribed to one:
-                let old_genesis_hash = self.chain_to_feed_conn_ids.remove_value(&feed_conn_id);
+                let node_id = match self.node_ids.get_by_right(&(shard_conn_id, local_id)) {
+                    Some(id) => *id,
+                    None => {
+                        // Updates from nodes that are still being held back are ignored:
+                        if self.pending_genesis.contains(&(shard_conn_id, local_id)) {
+                            return;
+                        }
+                        log::error!(
+                            "Update: Cannot find ID for node with shard/connectionId of {shard_conn_id:?}/{local_id:?}"
+                        );
+                        return;
+                    }
+                };
+
+                // This may contain times, so serialize it for seconds too if needed:
+                let mut feed_message_serializer =
+                    FeedMessageSerializer::for_time_formats(!self.seconds_feed_conn_ids.is_empty());
+                self.node_state
+                    .update_node(node_id, payload, &mut feed_message_serializer);
+
+                if let Some(chain) = self.node_state.get_chain_by_node_id(node_id) {
+                    let genesis_hash = chain.genesis_hash();
+                    self.finalize_and_broadcast_to_chain_feeds(
+                        &genesis_hash,
+                        feed_message_serializer,
+                    );
+                }
+
+                // Alerts that feeds were just sent are passed on to webhooks too:
+                let alerts = self.node_state.take_chain_alerts(node_id);
+                if let Some(chain) = self.node_state.get_chain_by_node_id(node_id) {
+                    self.send_to_webhooks(chain.genesis_hash(), chain.label(), alerts);
+                }
+
+                // Nodes becoming validators can change the label of their chain:
+                if self.node_state.take_chain_label_changed(node_id) {
+                    if let Some(chain) = self
+                        .node_state
+                        .get_chain_by_node_id(node_id)
+                        .filter(|chain| chain.is_listed())
+                    {
+                        let mut feed_messages_for_all = FeedMessageSerializer::new();
+                        feed_messages_for_all
+                            .push(feed_message::RemovedChain(chain.genesis_hash()));
+                        feed_messages_for_all.push(feed_message::AddedChain(
+                            chain.label(),
+                            chain.genesis_hash(),
+                            chain.node_count(),
+                        ));
+                        self.finalize_and_broadcast_to_all_feeds(feed_messages_for_all);
+                    }
+                }
+
+                // Chains whose best block just got high enough are now shown to everyone:
+                if self.node_state.take_chain_listed_by_height(node_id) {
+                    if let Some(chain) = self.node_state.get_chain_by_node_id(node_id) {
+                        let mut feed_messages_for_all = FeedMessageSerializer::new();
+                        feed_messages_for_all.push(feed_message::AddedChain(
+                            chain.label(),
+                            chain.genesis_hash(),
+                            chain.node_count(),
+                        ));
+                        self.finalize_and_broadcast_to_all_feeds(feed_messages_for_all);
+                    }
+                }
 
-                // Get old chain if there was one:
-                let node_state = &self.node_state;
-                let old_chain =
-                    old_genesis_hash.and_then(|hash| node_state.get_chain_by_genesis_hash(&hash));
+                // Nodes that have been stale for too long are muted and removed:
+                let expired_node_ids = self.node_state.take_expired_nodes(node_id);
+                for expired_node_id in &expired_node_ids {
+                    let shard_node = self.node_ids.get_by_left(expired_node_id).copied();
+                    if let Some((shard_conn_id, local_id)) = shard_node {
+                        if let Some(shard_conn) = self.shard_channels.get_mut(&shard_conn_id) {
+                            let _ = shard_conn.send(ToShardWebsocket::Mute {
+                                local_id,
+                                reason: MuteReason::Stale,
+                            });
+                        }
+                    }
+                }
+                if !expired_node_ids.is_empty() {
+                    self.remove_nodes_and_broadcast_result(expired_node_ids, RemovalReason::Stale);
+                }
+            }
+            FromShardWebsocket::Malformed { local_id } => {
+                if let Some(info) = self.shard_info.get_mut(&shard_conn_id) {
+                    info.malformed_messages += 1;
+                }
 
-                // Get new chain, ignoring the rest if it doesn't exist.
-                let new_chain = match self.node_state.get_chain_by_genesis_hash(&chain) {
-                    Some(chain) => chain,
+                let node_id = local_id
+                    .and_then(|local_id| self.node_ids.get_by_right(&(shard_conn_id, local_id)));
+                if let Some(&node_id) = node_id {
+                    self.node_state.note_malformed_message(node_id);
+                }
+            }
+            FromShardWebsocket::Disconnected => {
+                self.shard_channels.remove(&shard_conn_id);
+                self.shard_info.remove(&shard_conn_id);
+                self.pending_genesis.remove_conn(shard_conn_id);
+
+                // Find all nodes associated with this shard connection ID:
+                let node_ids_to_remove: Vec<NodeId> = self
+                    .node_ids
+                    .iter()
+                    .filter(|(_, &(this_shard_conn_id, _))| shard_conn_id == this_shard_conn_id)
+                    .map(|(&node_id, _)| node_id)
+                    .collect();
+
+                // ... and remove them:
+                self.remove_nodes_and_broadcast_result(
+                    node_ids_to_remove,
+                    RemovalReason::ShardDisconnected,
+                );
+            }
+        }
+    }
+
+    /// Handle messages coming from feeds.
+    fn handle_from_feed(&mut self, feed_conn_id: ConnId, msg: FromFeedWebsocket) {
+        match msg {
+            FromFeedWebsocket::Initialize { channel } => {
+                self.feed_channels.insert(feed_conn_id, channel.clone());
+
+                // Tell the new feed subscription some basic things to get it going:
+                let mut feed_serializer = FeedMessageSerializer::new();
+                feed_serializer.push(feed_message::Version(feed_message::FEED_VERSION));
+                for chain in self.node_state.iter_chains().filter(|c| c.is_listed()) {
+                    feed_serializer.push(feed_message::AddedChain(
+                        chain.label(),
+                        chain.genesis_hash(),
+                        chain.node_count(),
+                    ));
+                    if let Some(relay_parent) = chain.relay_parent() {
+                        feed_serializer.push(feed_message::ChainRelayParent(
+                            chain.genesis_hash(),
+                            relay_parent.genesis_hash,
+                            relay_parent.para_id,
+                        ));
+                    }
+                    if !chain.theme().is_empty() {
+                        feed_serializer.push(feed_message::ChainTheme(
+                            chain.genesis_hash(),
+                            chain.theme(),
+                        ));
+                    }
+                }
+
+                // Send this to the channel that subscribed:
+                if let Some(bytes) = feed_serializer.into_finalized() {
+                    let _ = channel.send(ToFeedWebsocket::Snapshot(bytes));
+                }
+            }
+            FromFeedWebsocket::SetGroup { group } => {
+                match group {
+                    Some(group) => self.pending_feed_groups.insert(feed_conn_id, group),
+                    None => self.pending_feed_groups.remove(&feed_conn_id),
+                };
+            }
+            FromFeedWebsocket::Ping { value } => {
+                let feed_channel = match self.feed_channels.get_mut(&feed_conn_id) {
+                    Some(chan) => chan,
                     None => return,
                 };
 
-                // Send messages to the feed about this subscription:
+                // Pong!
                 let mut feed_serializer = FeedMessageSerializer::new();
-                if let Some(old_chain) = old_chain {
-                    feed_serializer.push(feed_message::UnsubscribedFrom(old_chain.genesis_hash()));
-                }
-                feed_serializer.push(feed_message::SubscribedTo(new_chain.genesis_hash()));
-                feed_serializer.push(feed_message::TimeSync(time::now()));
-                feed_serializer.push(feed_message::BestBlock(
-                    new_chain.best_block().height,
-                    new_chain.timestamp(),
-                    new_chain.average_block_time(),
-                ));
-                feed_serializer.push(feed_message::BestFinalized(
-                    new_chain.finalized_block().height,
-                    new_chain.finalized_block().hash,
-                ));
-                feed_serializer.push(feed_message::ChainStatsUpdate(new_chain.stats()));
+                feed_serializer.push(feed_message::Pong(&value));
                 if let Some(bytes) = feed_serializer.into_finalized() {
                     let _ = feed_channel.send(ToFeedWebsocket::Bytes(bytes));
                 }
+            }
+            FromFeedWebsocket::Subscribe { chain, time_format } => {
+                let group = self.pending_feed_groups.get(&feed_conn_id).cloned();
+                self.subscribe_feed(feed_conn_id, chain, time_format, group);
+            }
+            FromFeedWebsocket::SubscribeOverview { time_format } => {
+                let Some(feed_channel) = self.feed_channels.get(&feed_conn_id) else {
+                    return;
+                };
 
-                // If many (eg 10k) nodes are connected, serializing all of their info takes time.
-                // So, parallelise this with Rayon, but we still send out messages for each node in order
-                // (which is helpful for the UI as it tries to maintain a sorted list of nodes). The chunk
-                // size is the max number of node info we fit into 1 message; smaller messages allow the UI
-                // to react a little faster and not have to wait for a larger update to come in. A chunk size
-                // of 64 means each message is ~32k.
-                use rayon::prelude::*;
-                let all_feed_messages: Vec<_> = new_chain
-                    .nodes_slice()
-                    .par_iter()
-                    .enumerate()
-                    .chunks(64)
-                    .filter_map(|nodes| {
-                        let mut feed_serializer = FeedMessageSerializer::new();
-                        for (node_id, node) in nodes
-                            .iter()
-                            .filter_map(|&(idx, n)| n.as_ref().map(|n| (idx, n)))
-                        {
-                            feed_serializer.push(feed_message::AddedNode(
-                                node_id,
-                                node,
-                                self.expose_node_details,
-                            ));
-                            feed_serializer.push(feed_message::FinalizedBlock(
-                                node_id,
-                                node.finalized().height,
-                                node.finalized().hash,
-                            ));
-                            if node.stale() {
-                                feed_serializer.push(feed_message::StaleNode(node_id));
-                            }
-                        }
-                        feed_serializer.into_finalized()
-                    })
-                    .collect();
-                for bytes in all_feed_messages {
+                // Feeds that want the overview aren't subscribed to any one chain, and
+                // aren't kept waiting for the first overview:
+                let old_genesis_hash = self.chain_to_feed_conn_ids.remove_value(&feed_conn_id);
+                let mut feed_serializer = FeedMessageSerializer::with_time_format(time_format);
+                if let Some(old_genesis_hash) = old_genesis_hash {
+                    feed_serializer.push(feed_message::UnsubscribedFrom(old_genesis_hash));
+                }
+                feed_serializer.push(feed_message::ChainOverviews(&self.chain_overviews()));
+                if let Some(bytes) = feed_serializer.into_finalized() {
                     let _ = feed_channel.send(ToFeedWebsocket::Bytes(bytes));
                 }
 
-                // Actually make a note of the new chain subscription:
-                let new_genesis_hash = new_chain.genesis_hash();
-                self.chain_to_feed_conn_ids
-                    .insert(new_genesis_hash, feed_conn_id);
+                self.overview_feed_conn_ids.insert(feed_conn_id);
+                self.feed_groups.remove(&feed_conn_id);
+                match time_format {
+                    TimeFormat::Seconds => self.seconds_feed_conn_ids.insert(feed_conn_id),
+                    TimeFormat::Millis => self.seconds_feed_conn_ids.remove(&feed_conn_id),
+                };
+            }
+            FromFeedWebsocket::Resync => {
+                let Some(&chain) = self.chain_to_feed_conn_ids.get_key(&feed_conn_id) else {
+                    return;
+                };
+                let now = Instant::now();
+                if let Some(last_resync) = self.feed_last_resync.get(&feed_conn_id) {
+                    if now.duration_since(*last_resync) < MIN_RESYNC_INTERVAL {
+                        log::debug!("Ignoring resync from feed {feed_conn_id:?}; too soon");
+                        return;
+                    }
+                }
+                self.feed_last_resync.insert(feed_conn_id, now);
+
+                // Subscribing again sends everything, just as it did the first time:
+                let time_format = if self.seconds_feed_conn_ids.contains(&feed_conn_id) {
+                    TimeFormat::Seconds
+                } else {
+                    TimeFormat::Millis
+                };
+                let group = self.feed_groups.get(&feed_conn_id).cloned();
+                self.subscribe_feed(feed_conn_id, chain, time_format, group);
             }
             FromFeedWebsocket::Disconnected => {
                 // The feed has disconnected; clean up references to it:
                 self.chain_to_feed_conn_ids.remove_value(&feed_conn_id);
+                self.seconds_feed_conn_ids.remove(&feed_conn_id);
+                self.overview_feed_conn_ids.remove(&feed_conn_id);
+                self.feed_groups.remove(&feed_conn_id);
+                self.pending_feed_groups.remove(&feed_conn_id);
+                self.feed_last_resync.remove(&feed_conn_id);
                 self.feed_channels.remove(&feed_conn_id);
             }
         }
     }
 
+    /// Subscribe a feed to a chain, unsubscribing it from any chain that it was subscribed
+    /// to before, and send it everything that it needs to know about the chain.
+    fn subscribe_feed(
+        &mut self,
+        feed_conn_id: ConnId,
+        chain: BlockHash,
+        time_format: TimeFormat,
+        group: Option<Box<str>>,
+    ) {
+        let feed_channel = match self.feed_channels.get_mut(&feed_conn_id) {
+            Some(chan) => chan,
+            None => return,
+        };
+
+        // Unsubscribe from previous chain if subscribed to one:
+        let old_genesis_hash = self.chain_to_feed_conn_ids.remove_value(&feed_conn_id);
+
+        // Get old chain if there was one:
+        let node_state = &self.node_state;
+        let old_chain =
+            old_genesis_hash.and_then(|hash| node_state.get_chain_by_genesis_hash(&hash));
+
+        // Get new chain, ignoring the rest if it doesn't exist.
+        let new_chain = match self.node_state.get_chain_by_genesis_hash(&chain) {
+            Some(chain) => chain,
+            None => return,
+        };
+
+        // Feeds that asked for a group only hear about the nodes in it:
+        let node_groups = self.node_state.node_groups();
+        let can_see = |node: &Node| group.as_ref().is_none_or(|g| node_groups.can_see(g, node));
+
+        // Send messages to the feed about this subscription:
+        let mut feed_serializer = FeedMessageSerializer::with_time_format(time_format);
+        if let Some(old_chain) = old_chain {
+            feed_serializer.push(feed_message::UnsubscribedFrom(old_chain.genesis_hash()));
+        }
+        feed_serializer.push(feed_message::SubscribedTo(new_chain.genesis_hash()));
+        feed_serializer.push(feed_message::TimeSync(time::now()));
+        feed_serializer.push(feed_message::BestBlock(
+            new_chain.best_block().height,
+            new_chain.timestamp(),
+            new_chain.average_block_time(),
+            new_chain.best_block().hash,
+        ));
+        feed_serializer.push(feed_message::BestFinalized(
+            new_chain.finalized_block().height,
+            new_chain.finalized_block().hash,
+        ));
+        feed_serializer.push(feed_message::RecentBlocks(new_chain.recent_blocks()));
+        feed_serializer.push(feed_message::ChainStatsUpdate(new_chain.stats()));
+        if let Some(bytes) = feed_serializer.into_finalized() {
+            let _ = feed_channel.send(ToFeedWebsocket::Snapshot(bytes));
+        }
+
+        // If many (eg 10k) nodes are connected, serializing all of their info takes time.
+        // So, parallelise this with Rayon, but we still send out messages for each node in order
+        // (which is helpful for the UI as it tries to maintain a sorted list of nodes). The chunk
+        // size is the max number of node info we fit into 1 message; smaller messages allow the UI
+        // to react a little faster and not have to wait for a larger update to come in. A chunk size
+        // of 64 means each message is ~32k.
+        use rayon::prelude::*;
+        let all_feed_messages: Vec<_> = new_chain
+            .nodes_slice()
+            .par_iter()
+            .enumerate()
+            .chunks(64)
+            .filter_map(|nodes| {
+                let mut feed_serializer = FeedMessageSerializer::with_time_format(time_format);
+                for (node_id, node) in nodes
+                    .iter()
+                    .filter_map(|&(idx, n)| n.as_ref().map(|n| (idx, n)))
+                    .filter(|(_, node)| can_see(node))
+                {
+                    feed_serializer.push(feed_message::AddedNode(
+                        node_id,
+                        node,
+                        new_chain.node_detail_fields(),
+                    ));
+                    feed_serializer.push(feed_message::FinalizedBlock(
+                        node_id,
+                        node.finalized().height,
+                        node.finalized().hash,
+                    ));
+                    if node.stale() {
+                        feed_serializer.push(feed_message::StaleNode(node_id));
+                    }
+                    if let Some(annotation) = node.annotation() {
+                        feed_serializer.push(feed_message::NodeAnnotated(
+                            node_id,
+                            &annotation.text,
+                            annotation.severity,
+                            annotation.expires_at,
+                        ));
+                    }
+                }
+                feed_serializer.into_finalized()
+            })
+            .collect();
+        for bytes in all_feed_messages {
+            let _ = feed_channel.send(ToFeedWebsocket::Snapshot(bytes));
+        }
+
+        // Actually make a note of the new chain subscription:
+        self.overview_feed_conn_ids.remove(&feed_conn_id);
+        let new_genesis_hash = new_chain.genesis_hash();
+        self.chain_to_feed_conn_ids
+            .insert(new_genesis_hash, feed_conn_id);
+        match time_format {
+            TimeFormat::Seconds => self.seconds_feed_conn_ids.insert(feed_conn_id),
+            TimeFormat::Millis => self.seconds_feed_conn_ids.remove(&feed_conn_id),
+        };
+        match group {
+            Some(group) => self.feed_groups.insert(feed_conn_id, group),
+            None => self.feed_groups.remove(&feed_conn_id),
+        };
+    }
+
     /// Remove all of the node IDs provided and broadcast messages to feeds as needed.
-    fn remove_nodes_and_broadcast_result(&mut self, node_ids: impl IntoIterator<Item = NodeId>) {
+    fn remove_nodes_and_broadcast_result(
+        &mut self,
+        node_ids: impl IntoIterator<Item = NodeId>,
+        reason: RemovalReason,
+    ) {
         // Group by chain to simplify the handling of feed messages:
         let mut node_ids_per_chain: HashMap<BlockHash, Vec<NodeId>> = HashMap::new();
         for node_id in node_ids.into_iter() {
@@ -591,6 +2177,7 @@ impl InnerLoop {
             for node_id in node_ids {
                 self.remove_node(
                     node_id,
+                    reason,
                     &mut feed_messages_for_chain,
                     &mut feed_messages_for_all,
                 );
@@ -606,13 +2193,14 @@ impl InnerLoop {
     fn remove_node(
         &mut self,
         node_id: NodeId,
+        reason: RemovalReason,
         feed_for_chain: &mut FeedMessageSerializer,
         feed_for_all: &mut FeedMessageSerializer,
     ) {
         // Remove our top level association (this may already have been done).
         self.node_ids.remove_by_left(&node_id);
 
-        let removed_details = match self.node_state.remove_node(node_id) {
+        let removed_details = match self.node_state.remove_node(node_id, reason) {
             Some(remove_details) => remove_details,
             None => {
                 log::error!("Could not find node {node_id:?}");
@@ -620,47 +2208,89 @@ impl InnerLoop {
             }
         };
 
-        // The chain has been removed (no nodes left in it, or it was renamed):
-        if removed_details.chain_node_count == 0 || removed_details.has_chain_label_changed {
-            feed_for_all.push(feed_message::RemovedChain(
-                removed_details.chain_genesis_hash,
-            ));
-        }
+        push_removed_node(
+            &removed_details,
+            node_id.get_chain_node_id(),
+            feed_for_chain,
+            feed_for_all,
+        );
 
-        // If the chain still exists, tell everybody about the new label or updated node count:
-        if removed_details.chain_node_count != 0 {
-            feed_for_all.push(feed_message::AddedChain(
-                &removed_details.new_chain_label,
+        if let Some(drop) = removed_details
+            .node_count_drop
+            .filter(|_| removed_details.was_chain_listed)
+        {
+            self.send_to_webhooks(
                 removed_details.chain_genesis_hash,
-                removed_details.chain_node_count,
-            ));
+                &removed_details.new_chain_label,
+                Some(ChainAlert::NodeCountDrop(drop)),
+            );
         }
+    }
 
-        // Assuming the chain hasn't gone away, tell chain subscribers about the node removal
-        if removed_details.chain_node_count != 0 {
-            feed_for_chain.push(feed_message::RemovedNode(
-                node_id.get_chain_node_id().into(),
-            ));
+    /// Pass alerts about the chain with the given genesis hash on to webhooks, if there are any.
+    fn send_to_webhooks(
+        &self,
+        genesis_hash: BlockHash,
+        chain_label: &str,
+        alerts: impl IntoIterator<Item = ChainAlert>,
+    ) {
+        let Some(tx_to_webhooks) = &self.tx_to_webhooks else {
+            return;
+        };
+        for alert in alerts {
+            let _ = tx_to_webhooks.send(WebhookAlert::new(genesis_hash, chain_label, alert));
         }
     }
 
-    /// Finalize a [`FeedMessageSerializer`] and broadcast the result to feeds for the chain.
+    /// Finalize a [`FeedMessageSerializer`] and broadcast the result to feeds for the chain,
+    /// using whichever time format each feed asked for. Feeds that asked for a group aren't
+    /// sent messages about nodes outside of it.
     fn finalize_and_broadcast_to_chain_feeds(
         &mut self,
         genesis_hash: &BlockHash,
         serializer: FeedMessageSerializer,
     ) {
-        if let Some(bytes) = serializer.into_finalized() {
-            self.broadcast_to_chain_feeds(genesis_hash, ToFeedWebsocket::Bytes(bytes));
+        let messages = match serializer.into_finalized_per_time_format() {
+            Some(messages) => messages,
+            None => return,
+        };
+        let chain = self.node_state.get_chain_by_genesis_hash(genesis_hash);
+        let node_groups = self.node_state.node_groups();
+        // Anyone can subscribe to the broker, so private chains aren't published there, and
+        // nor are messages about nodes that only feeds asking for their group can see:
+        if let (Some(broker), Some(chain)) = (&self.broker, &chain) {
+            let bytes = match chain.is_private() {
+                true => None,
+                false => messages.for_time_format_and_nodes(TimeFormat::Millis, |nid| {
+                    chain
+                        .get_node(nid.into())
+                        .is_none_or(|node| node_groups.group_of(node).is_none())
+                }),
+            };
+            if let Some(bytes) = bytes {
+                broker.publish(Some(*genesis_hash), bytes);
+            }
         }
-    }
-
-    /// Send a message to all chain feeds.
-    fn broadcast_to_chain_feeds(&mut self, genesis_hash: &BlockHash, message: ToFeedWebsocket) {
         if let Some(feeds) = self.chain_to_feed_conn_ids.get_values(genesis_hash) {
             for &feed_id in feeds {
-                if let Some(chan) = self.feed_channels.get_mut(&feed_id) {
-                    let _ = chan.send(message.clone());
+                let time_format = match self.seconds_feed_conn_ids.contains(&feed_id) {
+                    true => TimeFormat::Seconds,
+                    false => TimeFormat::Millis,
+                };
+                let bytes = match (self.feed_groups.get(&feed_id), &chain) {
+                    // Nodes that are no longer on the chain (eg because they were just
+                    // removed) are unknown to feeds that couldn't see them anyway:
+                    (Some(group), Some(chain)) => {
+                        messages.for_time_format_and_nodes(time_format, |nid| {
+                            chain
+                                .get_node(nid.into())
+                                .is_none_or(|node| node_groups.can_see(group, node))
+                        })
+                    }
+                    _ => Some(messages.for_time_format(time_format).clone()),
+                };
+                if let (Some(chan), Some(bytes)) = (self.feed_channels.get_mut(&feed_id), bytes) {
+                    let _ = chan.send(ToFeedWebsocket::Bytes(bytes));
                 }
             }
         }
@@ -669,6 +2299,9 @@ impl InnerLoop {
     /// Finalize a [`FeedMessageSerializer`] and broadcast the result to all feeds
     fn finalize_and_broadcast_to_all_feeds(&mut self, serializer: FeedMessageSerializer) {
         if let Some(bytes) = serializer.into_finalized() {
+            if let Some(broker) = &self.broker {
+                broker.publish(None, bytes.clone());
+            }
             self.broadcast_to_all_feeds(ToFeedWebsocket::Bytes(bytes));
         }
     }
@@ -680,3 +2313,341 @@ impl InnerLoop {
         }
     }
 }
+
+/// Push the messages that feeds need to hear about a node being added to a chain.
+/// Chain subscribers are told about the node, and everybody about the new node count,
+/// a potential rename and the chain's relay chain, once the chain is listed.
+fn push_added_node(
+    added: &state::NodeAddedToChain,
+    genesis_hash: BlockHash,
+    node_detail_fields: NodeDetailFields,
+    feed_for_chain: &mut FeedMessageSerializer,
+    feed_for_all: &mut FeedMessageSerializer,
+) {
+    feed_for_chain.push(feed_message::AddedNode(
+        added.id.get_chain_node_id().into(),
+        added.node,
+        node_detail_fields,
+    ));
+
+    if !added.is_chain_listed {
+        return;
+    }
+    // As far as feeds are concerned, a chain is new once it's listed:
+    let newly_listed = !added.was_chain_listed;
+    if newly_listed {
+        feed_for_all.push(feed_message::NewChain(genesis_hash, added.new_chain_label));
+    }
+    if added.has_chain_label_changed {
+        feed_for_all.push(feed_message::RemovedChain(genesis_hash));
+    }
+    feed_for_all.push(feed_message::AddedChain(
+        added.new_chain_label,
+        genesis_hash,
+        added.chain_node_count,
+    ));
+    if let Some(relay_parent) = added
+        .relay_parent
+        .filter(|_| added.has_relay_parent_changed || newly_listed)
+    {
+        feed_for_all.push(feed_message::ChainRelayParent(
+            genesis_hash,
+            relay_parent.genesis_hash,
+            relay_parent.para_id,
+        ));
+    }
+    if newly_listed && !added.chain_theme.is_empty() {
+        feed_for_all.push(feed_message::ChainTheme(genesis_hash, added.chain_theme));
+    }
+}
+
+/// The labels that more than one of the given chains go by. Chains with these labels are
+/// told apart in overviews using [`disambiguated_label`].
+fn colliding_labels<'a>(labels: impl IntoIterator<Item = &'a str>) -> HashSet<&'a str> {
+    let mut seen = HashSet::new();
+    labels
+        .into_iter()
+        .filter(|&label| !seen.insert(label))
+        .collect()
+}
+
+/// A chain's label with the start of its genesis hash appended, like `Polkadot (0x91b1)`.
+fn disambiguated_label(label: &str, genesis_hash: BlockHash) -> String {
+    let hash = genesis_hash.as_bytes();
+    format!("{label} (0x{:02x}{:02x})", hash[0], hash[1])
+}
+
+/// Push the messages that feeds need to hear about a node being removed from a chain.
+fn push_removed_node(
+    removed: &state::RemovedNode,
+    chain_node_id: ChainNodeId,
+    feed_for_chain: &mut FeedMessageSerializer,
+    feed_for_all: &mut FeedMessageSerializer,
+) {
+    // The chain has been removed (no nodes left in it, too few to be listed, or it was renamed):
+    if removed.was_chain_listed && (!removed.is_chain_listed || removed.has_chain_label_changed) {
+        feed_for_all.push(feed_message::RemovedChain(removed.chain_genesis_hash));
+    }
+
+    // If the chain is still listed, tell everybody about the new label or updated node count:
+    if removed.is_chain_listed {
+        feed_for_all.push(feed_message::AddedChain(
+            &removed.new_chain_label,
+            removed.chain_genesis_hash,
+            removed.chain_node_count,
+        ));
+    }
+
+    // Tell chain subscribers about the node removal. Empty chains can be kept around for
+    // a while, so they need to hear about the last node going too:
+    feed_for_chain.push(feed_message::RemovedNode(chain_node_id.into()));
+
+    if let Some(drop) = removed.node_count_drop.filter(|_| removed.was_chain_listed) {
+        feed_for_all.push(feed_message::NodeCountDrop(
+            removed.chain_genesis_hash,
+            drop.from,
+            drop.to,
+            drop.window.as_millis() as u64,
+        ));
+    }
+}
+
+/// The message telling feeds subscribed to a node's chain where the node is, along with
+/// the genesis hash of the chain, unless the chain's feeds aren't sent node locations.
+fn located_node_message(
+    node_state: &State,
+    node_id: NodeId,
+    location: &NodeLocation,
+) -> Option<(BlockHash, FeedMessageSerializer)> {
+    let chain_genesis_hash = node_state.get_chain_by_node_id(node_id)?.genesis_hash();
+    if !node_state
+        .node_detail_fields(&chain_genesis_hash)
+        .contains(NodeDetailField::Location)
+    {
+        return None;
+    }
+
+    let mut feed_message_serializer = FeedMessageSerializer::new();
+    feed_message_serializer.push(feed_message::LocatedNode(
+        node_id.get_chain_node_id().into(),
+        location.latitude,
+        location.longitude,
+        &location.city,
+    ));
+    Some((chain_genesis_hash, feed_message_serializer))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn subscribe_can_ask_for_a_time_format() {
+        let hash = "0x0000000000000000000000000000000000000000000000000000000000000001";
+
+        let msg: FromFeedWebsocket = format!("subscribe:{hash}").parse().unwrap();
+        assert!(matches!(
+            msg,
+            FromFeedWebsocket::Subscribe {
+                time_format: TimeFormat::Millis,
+                ..
+            }
+        ));
+
+        let msg: FromFeedWebsocket = format!("subscribe:{hash},s").parse().unwrap();
+        assert!(matches!(
+            msg,
+            FromFeedWebsocket::Subscribe {
+                time_format: TimeFormat::Seconds,
+                ..
+            }
+        ));
+
+        assert!(format!("subscribe:{hash},hours")
+            .parse::<FromFeedWebsocket>()
+            .is_err());
+    }
+    #[test]
+    fn feeds_can_ask_for_a_group() {
+        let msg: FromFeedWebsocket = "group:team-a".parse().unwrap();
+        assert!(matches!(
+            msg,
+            FromFeedWebsocket::SetGroup { group: Some(group) } if &*group == "team-a"
+        ));
+
+        let msg: FromFeedWebsocket = "group:".parse().unwrap();
+        assert!(matches!(msg, FromFeedWebsocket::SetGroup { group: None }));
+    }
+    #[test]
+    fn feeds_can_ask_for_the_overview() {
+        let msg: FromFeedWebsocket = "overview:".parse().unwrap();
+        assert!(matches!(
+            msg,
+            FromFeedWebsocket::SubscribeOverview {
+                time_format: TimeFormat::Millis
+            }
+        ));
+
+        let msg: FromFeedWebsocket = "overview:s".parse().unwrap();
+        assert!(matches!(
+            msg,
+            FromFeedWebsocket::SubscribeOverview {
+                time_format: TimeFormat::Seconds
+            }
+        ));
+
+        assert!("overview:hours".parse::<FromFeedWebsocket>().is_err());
+    }
+    #[test]
+    fn feeds_can_ask_to_resync() {
+        let msg: FromFeedWebsocket = "resync:".parse().unwrap();
+        assert!(matches!(msg, FromFeedWebsocket::Resync));
+    }
+
+    #[test]
+    fn chains_with_the_same_label_are_told_apart() {
+        let colliding = colliding_labels(["Polkadot", "Kusama", "Polkadot", "Westend"]);
+        assert_eq!(colliding, HashSet::from(["Polkadot"]));
+
+        let genesis_hash = BlockHash::from_slice(&[0x91; 32]);
+        assert_eq!(
+            disambiguated_label("Polkadot", genesis_hash),
+            "Polkadot (0x9191)"
+        );
+    }
+
+    #[test]
+    fn node_locations_are_only_sent_when_chains_allow() {
+        let hidden_genesis = BlockHash::from_low_u64_be(1);
+        let shown_genesis = BlockHash::from_low_u64_be(2);
+        let mut state = State::new(None, 1000);
+        state.set_node_detail_fields(
+            NodeDetailFields::default(),
+            [(hidden_genesis, "version,network_id".parse().unwrap())].into(),
+        );
+        let mut add_node = |genesis_hash, network_id| {
+            let details = NodeDetails {
+                chain: "Chain One".into(),
+                name: "Alice".into(),
+                implementation: "Bar".into(),
+                target_arch: None,
+                target_os: None,
+                target_env: None,
+                version: "0.1".into(),
+                validator: None,
+                network_id: NetworkId::from(network_id).unwrap(),
+                startup_time: None,
+                sysinfo: None,
+                ip: None,
+                protocol_version: None,
+                relay_chain_genesis_hash: None,
+                para_id: None,
+                role: None,
+                custom: Default::default(),
+            };
+            match state.add_node(genesis_hash, details) {
+                state::AddNodeResult::NodeAddedToChain(added) => added.id,
+                _ => panic!("node should have been added"),
+            }
+        };
+        let hidden = add_node(hidden_genesis, "network-a");
+        let shown = add_node(shown_genesis, "network-b");
+        let location = NodeLocation {
+            latitude: 52.5,
+            longitude: 13.4,
+            city: "Berlin".into(),
+            country: None,
+            asn: None,
+            provider: None,
+        };
+
+        assert!(located_node_message(&state, hidden, &location).is_none());
+
+        let (genesis_hash, serializer) = located_node_message(&state, shown, &location).unwrap();
+        assert_eq!(genesis_hash, shown_genesis);
+        let bytes = serializer.into_finalized().unwrap();
+        assert!(std::str::from_utf8(&bytes).unwrap().contains("Berlin"));
+    }
+
+    #[test]
+    fn nodes_can_be_filtered_by_country() {
+        let node_in = |country: Option<&str>| {
+            let mut node = Node::new(NodeDetails {
+                chain: "Chain One".into(),
+                name: "Alice".into(),
+                implementation: "Bar".into(),
+                target_arch: None,
+                target_os: None,
+                target_env: None,
+                version: "0.1".into(),
+                validator: None,
+                network_id: NetworkId::from("network-a").unwrap(),
+                startup_time: None,
+                sysinfo: None,
+                ip: None,
+                protocol_version: None,
+                relay_chain_genesis_hash: None,
+                para_id: None,
+                role: None,
+                custom: Default::default(),
+            });
+            node.update_location(Some(std::sync::Arc::new(
+                common::node_types::NodeLocation {
+                    latitude: 0.0,
+                    longitude: 0.0,
+                    city: "Somewhere".into(),
+                    country: country.map(Into::into),
+                    asn: None,
+                    provider: None,
+                },
+            )));
+            node
+        };
+        let filter = |country: &str| NodeFilter {
+            country: Some(country.into()),
+            ..NodeFilter::default()
+        };
+
+        assert!(filter("de").matches(&node_in(Some("DE"))));
+        assert!(!filter("DE").matches(&node_in(Some("FR"))));
+        assert!(!filter("DE").matches(&node_in(None)));
+        assert!(filter(UNKNOWN_COUNTRY).matches(&node_in(None)));
+        assert!(!filter(UNKNOWN_COUNTRY).matches(&node_in(Some("DE"))));
+
+        // Nodes we haven't located yet have no country either:
+        let mut unlocated = node_in(Some("DE"));
+        unlocated.update_location(None);
+        assert!(filter(UNKNOWN_COUNTRY).matches(&unlocated));
+    }
+
+    #[test]
+    fn nodes_can_be_filtered_by_uptime() {
+        let node = Node::new(NodeDetails {
+            chain: "Chain One".into(),
+            name: "Alice".into(),
+            implementation: "Bar".into(),
+            target_arch: None,
+            target_os: None,
+            target_env: None,
+            version: "0.1".into(),
+            validator: None,
+            network_id: NetworkId::from("network-a").unwrap(),
+            startup_time: None,
+            sysinfo: None,
+            ip: None,
+            protocol_version: None,
+            relay_chain_genesis_hash: None,
+            para_id: None,
+            role: None,
+            custom: Default::default(),
+        });
+        let uptime = |secs| NodeFilter {
+            min_uptime_secs: Some(secs),
+            ..NodeFilter::default()
+        };
+
+        // Nodes that have only just connected haven't been up for long:
+        assert!(uptime(0).matches(&node));
+        assert!(!uptime(60).matches(&node));
+    }
+}
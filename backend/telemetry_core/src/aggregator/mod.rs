@@ -14,12 +14,18 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+#[allow(clippy::module_inception)]
 mod aggregator;
 mod aggregator_set;
 mod inner_loop;
+mod pending_genesis;
 
 // Expose the various message types that can be worked with externally:
 pub use aggregator::AggregatorOpts;
-pub use inner_loop::{FromFeedWebsocket, FromShardWebsocket, ToFeedWebsocket, ToShardWebsocket};
+pub use inner_loop::{
+    ChainExportOutcome, FromFeedWebsocket, FromShardWebsocket, Metrics, MoveNodeOutcome,
+    NodeFilter, NodeLookup, ToFeedWebsocket, ToShardWebsocket,
+};
+pub use pending_genesis::NewChainGrace;
 
 pub use aggregator_set::*;
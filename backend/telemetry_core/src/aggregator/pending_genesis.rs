@@ -0,0 +1,296 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Nodes reporting a genesis hash that we've not seen before can be held back here
+//! for a while, rather than creating a chain for them straight away, so that one-off
+//! bad reports don't leave junk chains lying around.
+
+use super::aggregator::ConnId;
+use crate::state::is_first_party_network;
+use common::internal_messages::ShardNodeId;
+use common::node_types::{BlockHash, NodeDetails};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// How a new genesis hash earns a chain of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NewChainGrace {
+    /// Create the chain once this many nodes are reporting the genesis hash. 1 or
+    /// less creates chains straight away, and turns the grace period off.
+    pub min_nodes: usize,
+    /// Or create it once any one node has reported it for this long. Zero means
+    /// that only `min_nodes` counts.
+    pub min_duration: Duration,
+}
+
+impl Default for NewChainGrace {
+    fn default() -> Self {
+        NewChainGrace {
+            min_nodes: 1,
+            min_duration: Duration::ZERO,
+        }
+    }
+}
+
+/// Which node on which shard connection.
+pub type ShardNode = (ConnId, ShardNodeId);
+
+/// A node that's waiting for its chain to be created.
+#[derive(Debug, Clone)]
+pub struct PendingNode {
+    pub ip: IpAddr,
+    pub details: NodeDetails,
+    since: Instant,
+}
+
+/// Keeps hold of the nodes reporting genesis hashes that don't have a chain yet.
+#[derive(Debug, Default)]
+pub struct PendingGenesis {
+    grace: NewChainGrace,
+    nodes_by_genesis_hash: HashMap<BlockHash, HashMap<ShardNode, PendingNode>>,
+    genesis_hash_by_node: HashMap<ShardNode, BlockHash>,
+}
+
+impl PendingGenesis {
+    pub fn new(grace: NewChainGrace) -> Self {
+        PendingGenesis {
+            grace,
+            ..Default::default()
+        }
+    }
+
+    /// Should a node with this genesis hash be held back rather than added? Chains
+    /// that already exist and first party chains never wait.
+    pub fn should_hold(&self, genesis_hash: &BlockHash, chain_exists: bool) -> bool {
+        self.grace.min_nodes > 1
+            && !chain_exists
+            && !genesis_hash.is_zero()
+            && !is_first_party_network(genesis_hash)
+    }
+
+    /// Is this node being held back?
+    pub fn contains(&self, node: &ShardNode) -> bool {
+        self.genesis_hash_by_node.contains_key(node)
+    }
+
+    /// How many nodes are being held back.
+    pub fn node_count(&self) -> usize {
+        self.genesis_hash_by_node.len()
+    }
+
+    /// Hold back a node. If its genesis hash has now been reported by enough nodes,
+    /// every node waiting on it is handed back to be added to a new chain.
+    pub fn hold(
+        &mut self,
+        genesis_hash: BlockHash,
+        node: ShardNode,
+        ip: IpAddr,
+        details: NodeDetails,
+        now: Instant,
+    ) -> Vec<(ShardNode, BlockHash, PendingNode)> {
+        // A node reporting a different genesis hash now is no longer waiting on the old one:
+        self.remove(&node);
+
+        let nodes = self.nodes_by_genesis_hash.entry(genesis_hash).or_default();
+        nodes.insert(
+            node,
+            PendingNode {
+                ip,
+                details,
+                since: now,
+            },
+        );
+        self.genesis_hash_by_node.insert(node, genesis_hash);
+
+        if nodes.len() >= self.grace.min_nodes {
+            self.release(&genesis_hash)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Check on a node that's being held back. If it has been reporting its genesis
+    /// hash for long enough, every node waiting on it is handed back to be added to
+    /// a new chain.
+    pub fn poll(
+        &mut self,
+        node: &ShardNode,
+        now: Instant,
+    ) -> Vec<(ShardNode, BlockHash, PendingNode)> {
+        if self.grace.min_duration.is_zero() {
+            return Vec::new();
+        }
+        let Some(&genesis_hash) = self.genesis_hash_by_node.get(node) else {
+            return Vec::new();
+        };
+        let waited_long_enough = self
+            .nodes_by_genesis_hash
+            .get(&genesis_hash)
+            .and_then(|nodes| nodes.get(node))
+            .is_some_and(|pending| {
+                now.saturating_duration_since(pending.since) >= self.grace.min_duration
+            });
+
+        if waited_long_enough {
+            self.release(&genesis_hash)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Stop holding back a node, returning whether it was being held.
+    pub fn remove(&mut self, node: &ShardNode) -> bool {
+        let Some(genesis_hash) = self.genesis_hash_by_node.remove(node) else {
+            return false;
+        };
+        if let Some(nodes) = self.nodes_by_genesis_hash.get_mut(&genesis_hash) {
+            nodes.remove(node);
+            if nodes.is_empty() {
+                self.nodes_by_genesis_hash.remove(&genesis_hash);
+            }
+        }
+        true
+    }
+
+    /// Stop holding back every node on a shard connection.
+    pub fn remove_conn(&mut self, conn_id: ConnId) {
+        let nodes: Vec<ShardNode> = self
+            .genesis_hash_by_node
+            .keys()
+            .filter(|(node_conn_id, _)| *node_conn_id == conn_id)
+            .copied()
+            .collect();
+        for node in nodes {
+            self.remove(&node);
+        }
+    }
+
+    fn release(&mut self, genesis_hash: &BlockHash) -> Vec<(ShardNode, BlockHash, PendingNode)> {
+        let nodes = self
+            .nodes_by_genesis_hash
+            .remove(genesis_hash)
+            .unwrap_or_default();
+        let mut released: Vec<_> = nodes
+            .into_iter()
+            .map(|(node, pending)| {
+                self.genesis_hash_by_node.remove(&node);
+                (node, *genesis_hash, pending)
+            })
+            .collect();
+        // Add nodes in the order that they arrived in, as they would have been otherwise:
+        released.sort_by_key(|(_, _, pending)| pending.since);
+        released
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::node_types::NetworkId;
+
+    fn details(name: &str) -> NodeDetails {
+        NodeDetails {
+            chain: "Junk".into(),
+            name: name.into(),
+            implementation: "Substrate".into(),
+            version: "0.1.0".into(),
+            validator: None,
+            network_id: NetworkId::from("network").unwrap(),
+            startup_time: None,
+            target_os: None,
+            target_arch: None,
+            target_env: None,
+            sysinfo: None,
+            ip: None,
+            protocol_version: None,
+            relay_chain_genesis_hash: None,
+            para_id: None,
+            role: None,
+            custom: Default::default(),
+        }
+    }
+
+    fn node(id: usize) -> ShardNode {
+        (ConnId::new(1), ShardNodeId::new(id))
+    }
+
+    fn ip() -> IpAddr {
+        IpAddr::from([127, 0, 0, 1])
+    }
+
+    #[test]
+    fn chains_are_not_held_back_by_default() {
+        let pending = PendingGenesis::new(NewChainGrace::default());
+        assert!(!pending.should_hold(&BlockHash::from_low_u64_be(1), false));
+    }
+
+    #[test]
+    fn nodes_are_released_once_enough_report_the_genesis_hash() {
+        let mut pending = PendingGenesis::new(NewChainGrace {
+            min_nodes: 2,
+            min_duration: Duration::ZERO,
+        });
+        let genesis_hash = BlockHash::from_low_u64_be(1);
+        let now = Instant::now();
+        assert!(pending.should_hold(&genesis_hash, false));
+        assert!(!pending.should_hold(&genesis_hash, true));
+
+        assert!(pending
+            .hold(genesis_hash, node(1), ip(), details("A"), now)
+            .is_empty());
+        assert!(pending.contains(&node(1)));
+        let released = pending.hold(genesis_hash, node(2), ip(), details("B"), now);
+        assert_eq!(released.len(), 2);
+        assert_eq!(pending.node_count(), 0);
+
+        // A node leaving no longer counts towards the genesis hash:
+        pending.hold(genesis_hash, node(3), ip(), details("C"), now);
+        assert!(pending.remove(&node(3)));
+        assert!(pending
+            .hold(genesis_hash, node(4), ip(), details("D"), now)
+            .is_empty());
+    }
+
+    #[test]
+    fn nodes_are_released_once_one_has_waited_long_enough() {
+        let mut pending = PendingGenesis::new(NewChainGrace {
+            min_nodes: 3,
+            min_duration: Duration::from_secs(10),
+        });
+        let genesis_hash = BlockHash::from_low_u64_be(1);
+        let now = Instant::now();
+        pending.hold(genesis_hash, node(1), ip(), details("A"), now);
+        pending.hold(
+            genesis_hash,
+            node(2),
+            ip(),
+            details("B"),
+            now + Duration::from_secs(5),
+        );
+
+        assert!(pending
+            .poll(&node(2), now + Duration::from_secs(10))
+            .is_empty());
+        let released = pending.poll(&node(1), now + Duration::from_secs(10));
+        let names: Vec<_> = released
+            .iter()
+            .map(|(_, _, pending)| &*pending.details.name)
+            .collect();
+        assert_eq!(names, vec!["A", "B"]);
+        assert!(!pending.contains(&node(2)));
+    }
+}
@@ -0,0 +1,278 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Publish the messages that feeds are sent to a NATS server, so that telemetry can be
+//! archived and processed by existing stream infrastructure. See:
+//!
+//! - <https://docs.nats.io/reference/reference-protocols/nats-protocol>
+//!
+//! Messages about a chain are published to `<prefix>.<genesis hash>`, and those that every
+//! feed is sent to `<prefix>.all`, exactly as the feed would have been sent them. Kafka isn't
+//! spoken directly; NATS can be bridged to it with a connector if need be.
+//!
+//! The broker is kept well away from the live feed: messages are queued up to a limit while
+//! we (re)connect, and anything beyond that is dropped rather than holding up the aggregator.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use common::node_types::BlockHash;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpStream;
+
+/// We wait this long before reconnecting the first time. This doubles with each attempt.
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+/// We never wait longer than this before reconnecting.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+pub struct BrokerOpts {
+    /// The `host:port` of the NATS server.
+    pub addr: String,
+    /// Put in front of the subject of every message, followed by a `.`.
+    pub subject_prefix: String,
+    /// Only publish messages about chains with these genesis hashes. Empty means every chain.
+    pub chains: HashSet<BlockHash>,
+    /// How many messages can wait to be published before we start dropping them.
+    pub queue_len: usize,
+}
+
+/// A message on its way to the broker. `None` is for messages that every feed is sent.
+type BrokerMessage = (Option<BlockHash>, Bytes);
+
+/// Hands feed messages to the task publishing them to the broker, without ever waiting on it.
+#[derive(Debug, Clone)]
+pub struct BrokerSender {
+    tx: flume::Sender<BrokerMessage>,
+    chains: Arc<HashSet<BlockHash>>,
+    /// How many messages were dropped because the queue was full.
+    dropped: Arc<AtomicU64>,
+}
+
+impl BrokerSender {
+    /// Publish messages sent to feeds for the chain with the given genesis hash, or
+    /// to every feed if `None`.
+    pub fn publish(&self, genesis_hash: Option<BlockHash>, bytes: Bytes) {
+        if let Some(genesis_hash) = &genesis_hash {
+            if !self.chains.is_empty() && !self.chains.contains(genesis_hash) {
+                return;
+            }
+        }
+        if self.tx.try_send((genesis_hash, bytes)).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The subject that a message is published to.
+fn subject(prefix: &str, genesis_hash: Option<BlockHash>) -> String {
+    let key = match genesis_hash {
+        Some(genesis_hash) => format!("{genesis_hash:?}"),
+        None => "all".to_owned(),
+    };
+    match prefix.is_empty() {
+        true => key,
+        false => format!("{prefix}.{key}"),
+    }
+}
+
+/// Spawn a task that publishes the messages it's handed to the broker, reconnecting
+/// whenever it needs to, and hand back something to hand it messages with.
+pub fn spawn_broker_publisher(opts: BrokerOpts) -> BrokerSender {
+    let (tx, rx) = flume::bounded(opts.queue_len.max(1));
+    let dropped = Arc::new(AtomicU64::new(0));
+    let sender = BrokerSender {
+        tx,
+        chains: Arc::new(opts.chains),
+        dropped: Arc::clone(&dropped),
+    };
+
+    log::info!(
+        "Publishing feed messages to NATS at {} under '{}'",
+        opts.addr,
+        opts.subject_prefix
+    );
+    tokio::spawn(async move {
+        let mut backoff = INITIAL_RECONNECT_BACKOFF;
+        loop {
+            match publish(
+                &opts.addr,
+                &opts.subject_prefix,
+                &rx,
+                &dropped,
+                &mut backoff,
+            )
+            .await
+            {
+                // Every sender has gone, so there's nothing left to do:
+                Ok(()) => return,
+                Err(e) => log::warn!(
+                    "Error publishing to NATS at {}; reconnecting in {backoff:?}: {e}",
+                    opts.addr
+                ),
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+        }
+    });
+
+    sender
+}
+
+/// Connect to the broker and publish messages until either all senders are gone
+/// (`Ok`) or something goes wrong with the connection (`Err`). The backoff is reset
+/// once we're connected.
+async fn publish(
+    addr: &str,
+    prefix: &str,
+    rx: &flume::Receiver<BrokerMessage>,
+    dropped: &AtomicU64,
+    backoff: &mut Duration,
+) -> anyhow::Result<()> {
+    let stream = TcpStream::connect(addr).await?;
+    let (read, write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let mut write = BufWriter::new(write);
+
+    // The server introduces itself before anything else:
+    let info = lines
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Connection closed before INFO"))?;
+    if !info.starts_with("INFO ") {
+        anyhow::bail!("Expecting INFO from server, but got '{info}'");
+    }
+    let connect = serde_json::json!({
+        "verbose": false,
+        "pedantic": false,
+        "name": "telemetry_core",
+        "lang": "rust",
+        "version": env!("CARGO_PKG_VERSION"),
+    });
+    write
+        .write_all(format!("CONNECT {connect}\r\n").as_bytes())
+        .await?;
+    write.flush().await?;
+    log::info!("Connected to NATS at {addr}");
+    *backoff = INITIAL_RECONNECT_BACKOFF;
+
+    let dropped_before = dropped.swap(0, Ordering::Relaxed);
+    if dropped_before > 0 {
+        log::warn!("Dropped {dropped_before} feed messages while NATS was unavailable");
+    }
+
+    loop {
+        tokio::select! {
+            msg = rx.recv_async() => {
+                let Ok((genesis_hash, bytes)) = msg else {
+                    return Ok(());
+                };
+                let header = format!("PUB {} {}\r\n", subject(prefix, genesis_hash), bytes.len());
+                write.write_all(header.as_bytes()).await?;
+                write.write_all(&bytes).await?;
+                write.write_all(b"\r\n").await?;
+                // Flush once the queue is empty, so that messages are published in batches:
+                if rx.is_empty() {
+                    write.flush().await?;
+                }
+            }
+            line = lines.next_line() => {
+                let line = line?.ok_or_else(|| anyhow::anyhow!("Connection closed"))?;
+                // The server pings us every so often to check that we're still alive:
+                if line == "PING" {
+                    write.write_all(b"PONG\r\n").await?;
+                    write.flush().await?;
+                } else if let Some(err) = line.strip_prefix("-ERR ") {
+                    log::warn!("NATS at {addr} reported an error: {err}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn messages_are_published_by_genesis_hash() {
+        let genesis_hash = BlockHash::from_low_u64_be(1);
+        assert_eq!(
+            subject("telemetry", Some(genesis_hash)),
+            format!("telemetry.{genesis_hash:?}")
+        );
+        assert_eq!(subject("telemetry", None), "telemetry.all");
+        assert_eq!(subject("", None), "all");
+    }
+
+    #[test]
+    fn only_the_chains_asked_for_are_published() {
+        let (tx, rx) = flume::bounded(1);
+        let sender = BrokerSender {
+            tx,
+            chains: Arc::new([BlockHash::from_low_u64_be(1)].into_iter().collect()),
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+
+        sender.publish(
+            Some(BlockHash::from_low_u64_be(2)),
+            Bytes::from_static(b"[]"),
+        );
+        assert!(rx.is_empty());
+
+        sender.publish(
+            Some(BlockHash::from_low_u64_be(1)),
+            Bytes::from_static(b"[1]"),
+        );
+        assert_eq!(rx.len(), 1);
+
+        // The queue is full now, so this is dropped rather than waited on:
+        sender.publish(None, Bytes::from_static(b"[2]"));
+        assert_eq!(rx.len(), 1);
+        assert_eq!(sender.dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn messages_are_published_to_nats() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sender = spawn_broker_publisher(BrokerOpts {
+            addr: addr.to_string(),
+            subject_prefix: "telemetry".to_owned(),
+            chains: HashSet::new(),
+            queue_len: 10,
+        });
+        sender.publish(None, Bytes::from_static(b"[0,32]"));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write.write_all(b"INFO {}\r\nPING\r\n").await.unwrap();
+
+        let connect = lines.next_line().await.unwrap().unwrap();
+        assert!(connect.starts_with("CONNECT {"));
+        let mut rest = Vec::new();
+        for _ in 0..3 {
+            rest.push(lines.next_line().await.unwrap().unwrap());
+        }
+        // PONG can come before or after the message, depending on what's handled first:
+        rest.retain(|line| line != "PONG");
+        assert_eq!(rest[..2], ["PUB telemetry.all 6", "[0,32]"]);
+    }
+}
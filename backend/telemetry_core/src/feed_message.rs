@@ -17,18 +17,36 @@
 //! This module provides a way of encoding the various messages that we'll
 //! send to subscribed feeds (browsers).
 
-use serde::Serialize;
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 
-use crate::state::Node;
+use crate::state::{
+    AnnotationSeverity, BlockAuthor, ChainBandwidth, ClockSkewCounts, ConnectionDurations,
+    HardwareTier, Node, NodeRoleCounts, RecentBlock, Theme,
+};
 use common::node_types::{
-    BlockDetails, BlockHash, BlockNumber, NodeHardware, NodeIO, NodeStats, Timestamp,
+    BlockDetails, BlockHash, BlockNumber, NodeHardware, NodeIO, NodeStats, PartialNodeStats,
+    Timestamp,
 };
+use common::{AtomicHistogram, Histogram};
+use once_cell::sync::Lazy;
 use serde_json::to_writer;
 
 type FeedNodeId = usize;
 
 pub trait FeedMessage {
     const ACTION: u8;
+
+    /// The node that the message is about, if it's about a single node. Feeds that
+    /// can't see the node aren't sent the message.
+    fn node_id(&self) -> Option<FeedNodeId> {
+        None
+    }
 }
 
 pub trait FeedMessageWrite: FeedMessage {
@@ -44,21 +62,135 @@ where
     }
 }
 
+/// How are block and propagation times written in feed messages? Feeds
+/// can ask for a format when they subscribe to a chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeFormat {
+    /// Integer milliseconds. This is what feeds get unless they ask otherwise.
+    #[default]
+    Millis,
+    /// Floating point seconds.
+    Seconds,
+}
+
+impl FromStr for TimeFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ms" => Ok(TimeFormat::Millis),
+            "s" => Ok(TimeFormat::Seconds),
+            _ => Err(anyhow::anyhow!("Time format {s} not recognised")),
+        }
+    }
+}
+
+/// A duration in milliseconds, which is serialized according to the [`TimeFormat`] given.
+pub struct FeedTime(pub u64, pub TimeFormat);
+
+impl Serialize for FeedTime {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.1 {
+            TimeFormat::Millis => serializer.serialize_u64(self.0),
+            TimeFormat::Seconds => serializer.serialize_f64(self.0 as f64 / 1000.0),
+        }
+    }
+}
+
+/// How many messages of each type have been pushed to feed serializers, by action ID.
+static MESSAGE_COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+/// Only one in this many feed messages of each type are timed as they're serialized,
+/// to keep the cost of timing them down.
+const SERIALIZATION_SAMPLE_RATE: u64 = 16;
+
+/// Histogram buckets for how long serializing a feed message takes, in nanoseconds.
+const SERIALIZATION_BUCKETS: &[u64] = &[
+    250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 1_000_000,
+];
+
+/// How long the feed messages that were timed took to serialize, in nanoseconds.
+static SERIALIZATION_TIMES: Lazy<AtomicHistogram> =
+    Lazy::new(|| AtomicHistogram::new(SERIALIZATION_BUCKETS));
+
+/// How long the feed messages that have been timed so far took to serialize (in every
+/// time format wanted), in nanoseconds.
+pub fn serialization_times() -> Histogram {
+    SERIALIZATION_TIMES.snapshot()
+}
+
+/// The name of each type of feed message, and how many of them have been pushed to
+/// feed serializers so far.
+pub fn message_counts() -> impl Iterator<Item = (&'static str, u64)> {
+    (0..=u8::MAX).filter_map(|action| {
+        let name = action_name(action)?;
+        Some((
+            name,
+            MESSAGE_COUNTS[action as usize].load(Ordering::Relaxed),
+        ))
+    })
+}
+
 pub struct FeedMessageSerializer {
     /// Current buffer.
     buffer: Vec<u8>,
+    /// The format that times are written to the buffer in.
+    time_format: TimeFormat,
+    /// If some feeds want times in seconds, messages are also serialized here.
+    seconds: Option<Box<FeedMessageSerializer>>,
+    /// Where each message is in the buffer, and the node it's about (if any), so that
+    /// messages can be left out for feeds that can't see every node.
+    spans: Vec<(Range<usize>, Option<FeedNodeId>)>,
 }
 
 const BUFCAP: usize = 128;
 
 impl FeedMessageSerializer {
     pub fn new() -> Self {
+        Self::with_time_format(TimeFormat::Millis)
+    }
+
+    /// Serialize messages for a feed that wants times in the given format.
+    pub fn with_time_format(time_format: TimeFormat) -> Self {
         Self {
             buffer: Vec::with_capacity(BUFCAP),
+            time_format,
+            seconds: None,
+            spans: Vec::new(),
         }
     }
 
+    /// Serialize messages for feeds that want times in milliseconds and, if
+    /// `seconds_wanted` is true, separately for feeds that want times in seconds.
+    pub fn for_time_formats(seconds_wanted: bool) -> Self {
+        let mut ser = Self::new();
+        if seconds_wanted {
+            ser.seconds = Some(Box::new(Self::with_time_format(TimeFormat::Seconds)));
+        }
+        ser
+    }
+
     pub fn push<Message>(&mut self, msg: Message)
+    where
+        Message: FeedMessageWrite,
+    {
+        let count = MESSAGE_COUNTS[Message::ACTION as usize].fetch_add(1, Ordering::Relaxed);
+        let started = count
+            .is_multiple_of(SERIALIZATION_SAMPLE_RATE)
+            .then(Instant::now);
+        if let Some(seconds) = &mut self.seconds {
+            seconds.push_ref(&msg);
+        }
+        self.push_ref(&msg);
+        if let Some(started) = started {
+            let nanos = started.elapsed().as_nanos().try_into().unwrap_or(u64::MAX);
+            SERIALIZATION_TIMES.record(nanos);
+        }
+    }
+
+    fn push_ref<Message>(&mut self, msg: &Message)
     where
         Message: FeedMessageWrite,
     {
@@ -68,9 +200,11 @@ impl FeedMessageSerializer {
         };
 
         self.buffer.push(glue);
+        let start = self.buffer.len();
         self.write(&Message::ACTION);
         self.buffer.push(b',');
         msg.write_to_feed(self);
+        self.spans.push((start..self.buffer.len(), msg.node_id()));
     }
 
     fn write<S>(&mut self, value: &S)
@@ -80,6 +214,22 @@ impl FeedMessageSerializer {
         let _ = to_writer(&mut self.buffer, value);
     }
 
+    /// A duration in milliseconds, to be written in the format this serializer uses.
+    fn time(&self, ms: u64) -> FeedTime {
+        FeedTime(ms, self.time_format)
+    }
+
+    /// Block details, with times written in the format this serializer uses.
+    fn block_details(&self, details: &BlockDetails) -> impl Serialize {
+        (
+            details.block.height,
+            details.block.hash,
+            self.time(details.block_time),
+            details.block_timestamp,
+            details.propagation_time.map(|t| self.time(t)),
+        )
+    }
+
     /// Return the bytes that we've serialized so far, consuming the serializer.
     pub fn into_finalized(mut self) -> Option<bytes::Bytes> {
         if self.buffer.is_empty() {
@@ -89,52 +239,406 @@ impl FeedMessageSerializer {
         self.buffer.push(b']');
         Some(self.buffer.into())
     }
+
+    /// Return the bytes that we've serialized so far for each time format, consuming
+    /// the serializer. This is expected to be used along with [`Self::for_time_formats`].
+    pub fn into_finalized_per_time_format(mut self) -> Option<FinalizedFeedMessages> {
+        let seconds = self
+            .seconds
+            .take()
+            .and_then(|s| s.into_finalized_with_spans());
+        let millis = self.into_finalized_with_spans()?;
+        Some(FinalizedFeedMessages { millis, seconds })
+    }
+
+    fn into_finalized_with_spans(mut self) -> Option<FinalizedBuffer> {
+        let spans = std::mem::take(&mut self.spans);
+        let bytes = self.into_finalized()?;
+        Some(FinalizedBuffer { bytes, spans })
+    }
+}
+
+/// Serialized feed messages, for each time format that they were serialized in.
+pub struct FinalizedFeedMessages {
+    millis: FinalizedBuffer,
+    seconds: Option<FinalizedBuffer>,
+}
+
+struct FinalizedBuffer {
+    bytes: bytes::Bytes,
+    spans: Vec<(Range<usize>, Option<FeedNodeId>)>,
+}
+
+impl FinalizedFeedMessages {
+    /// The bytes to send to a feed that wants the given time format. If the messages weren't
+    /// serialized in that format, they contain no times and are the same in either format.
+    pub fn for_time_format(&self, time_format: TimeFormat) -> &bytes::Bytes {
+        &self.buffer_for_time_format(time_format).bytes
+    }
+
+    /// The bytes to send to a feed that wants the given time format, leaving out messages
+    /// about nodes that the feed can't see. `None` if that leaves nothing to send.
+    pub fn for_time_format_and_nodes(
+        &self,
+        time_format: TimeFormat,
+        can_see: impl Fn(FeedNodeId) -> bool,
+    ) -> Option<bytes::Bytes> {
+        let FinalizedBuffer { bytes, spans } = self.buffer_for_time_format(time_format);
+        let mut buffer = Vec::with_capacity(bytes.len());
+        for (span, _) in spans
+            .iter()
+            .filter(|(_, node_id)| node_id.is_none_or(&can_see))
+        {
+            buffer.push(if buffer.is_empty() { b'[' } else { b',' });
+            buffer.extend_from_slice(&bytes[span.clone()]);
+        }
+        if buffer.is_empty() {
+            return None;
+        }
+        buffer.push(b']');
+        Some(buffer.into())
+    }
+
+    fn buffer_for_time_format(&self, time_format: TimeFormat) -> &FinalizedBuffer {
+        match time_format {
+            TimeFormat::Seconds => self.seconds.as_ref().unwrap_or(&self.millis),
+            TimeFormat::Millis => &self.millis,
+        }
+    }
 }
 
+/// Combine batches of serialized feed messages into as few batches as possible, so that
+/// they can be sent to a feed in fewer frames. Combined batches stop growing once they're
+/// `max_bytes` long, though a batch that's longer than that on its own is left as is.
+pub fn coalesce(batches: Vec<bytes::Bytes>, max_bytes: usize) -> Vec<bytes::Bytes> {
+    let mut coalesced = Vec::new();
+    let mut buffer: Vec<u8> = Vec::new();
+    for batch in batches {
+        // Each batch is a JSON array of messages; its contents are what we combine:
+        let contents = match batch.strip_prefix(b"[").and_then(|b| b.strip_suffix(b"]")) {
+            Some(contents) if !contents.is_empty() => contents,
+            _ => {
+                coalesced.push(batch);
+                continue;
+            }
+        };
+        if !buffer.is_empty() && buffer.len() + contents.len() + 1 > max_bytes {
+            buffer.push(b']');
+            coalesced.push(std::mem::take(&mut buffer).into());
+        }
+        buffer.push(if buffer.is_empty() { b'[' } else { b',' });
+        buffer.extend_from_slice(contents);
+    }
+    if !buffer.is_empty() {
+        buffer.push(b']');
+        coalesced.push(buffer.into());
+    }
+    coalesced
+}
+
+/// The version of the feed protocol, which feeds are sent when they connect.
+pub const FEED_VERSION: usize = 35;
+
+/// A description of the fields of each type of feed message, as they're written to feeds.
+#[derive(Serialize, Debug)]
+pub struct FeedSchema {
+    pub version: usize,
+    pub messages: Vec<FeedMessageSchema>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FeedMessageSchema {
+    pub action: u8,
+    pub name: &'static str,
+    /// Is the message about the node whose ID is its first field? Feeds that can't
+    /// see the node aren't sent it.
+    pub about_node: bool,
+    pub fields: Vec<FeedFieldSchema>,
+}
+
+/// A field of a feed message. Times given as `FeedTime` are written in milliseconds or
+/// seconds, depending on what the feed asked for.
+#[derive(Serialize, Debug)]
+pub struct FeedFieldSchema {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+// Messages marked `=> node` are about the node whose ID is their first field. The fields
+// listed are those written to feeds, which the schema is generated from.
 macro_rules! actions {
-    ($($action:literal: $t:ty,)*) => {
+    (@about_node $node:ident) => { true };
+    (@about_node) => { false };
+    ($($action:literal: $t:ident $(<$lt:lifetime>)? ($($field:ident: $ty:ty),* $(,)?) $(=> $node:ident)?,)*) => {
         $(
-            impl FeedMessage for $t {
+            impl FeedMessage for $t $(<$lt>)? {
                 const ACTION: u8 = $action;
+                $(
+                    fn node_id(&self) -> Option<FeedNodeId> {
+                        let $node = self.0;
+                        Some($node)
+                    }
+                )?
             }
         )*
+
+        /// The name of the type of feed message with the given action ID.
+        fn action_name(action: u8) -> Option<&'static str> {
+            match action {
+                $($action => Some(stringify!($t)),)*
+                _ => None,
+            }
+        }
+
+        /// Describe every type of feed message, in order of action ID.
+        pub fn schema() -> FeedSchema {
+            let messages = vec![$(
+                FeedMessageSchema {
+                    action: $action,
+                    name: stringify!($t),
+                    about_node: actions!(@about_node $($node)?),
+                    fields: vec![$(
+                        FeedFieldSchema {
+                            name: stringify!($field),
+                            // Long types are wrapped over several lines when stringified:
+                            ty: stringify!($ty)
+                                .split_whitespace()
+                                .collect::<Vec<_>>()
+                                .join(" "),
+                        },
+                    )*],
+                },
+            )*];
+            FeedSchema {
+                version: FEED_VERSION,
+                messages,
+            }
+        }
     }
 }
 
 actions! {
-     0: Version,
-     1: BestBlock,
-     2: BestFinalized,
-     3: AddedNode<'_>,
-     4: RemovedNode,
-     5: LocatedNode<'_>,
-     6: ImportedBlock<'_>,
-     7: FinalizedBlock,
-     8: NodeStatsUpdate<'_>,
-     9: Hardware<'_>,
-    10: TimeSync,
-    11: AddedChain<'_>,
-    12: RemovedChain,
-    13: SubscribedTo,
-    14: UnsubscribedFrom,
-    15: Pong<'_>,
+     0: Version(version: usize),
+     1: BestBlock(
+        height: BlockNumber,
+        timestamp: Timestamp,
+        average_block_time: Option<FeedTime>,
+        hash: BlockHash,
+    ),
+     2: BestFinalized(height: BlockNumber, hash: BlockHash),
+     3: AddedNode<'_>(
+        node: FeedNodeId,
+        details: (
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<NodeSysInfo>,
+            Option<NodeHwBench>,
+            Option<Map<String, String>>
+        ),
+        stats: NodeStats,
+        io: NodeIO,
+        hardware: NodeHardware,
+        block_details: (BlockNumber, BlockHash, FeedTime, Timestamp, Option<FeedTime>),
+        location: Option<NodeLocation>,
+        startup_time: Option<Timestamp>,
+        last_seen: Timestamp,
+        stable_id: Option<String>,
+        process_started_at: Option<Timestamp>,
+    ) => node,
+     4: RemovedNode(node: FeedNodeId) => node,
+     5: LocatedNode<'_>(node: FeedNodeId, latitude: f32, longitude: f32, city: String) => node,
+     6: ImportedBlock<'_>(
+        node: FeedNodeId,
+        block_details: (BlockNumber, BlockHash, FeedTime, Timestamp, Option<FeedTime>),
+    ) => node,
+     7: FinalizedBlock(node: FeedNodeId, height: BlockNumber, hash: BlockHash) => node,
+     8: NodeStatsUpdate<'_>(node: FeedNodeId, stats: NodeStats) => node,
+     9: Hardware<'_>(node: FeedNodeId, hardware: NodeHardware) => node,
+    10: TimeSync(now: Timestamp),
+    11: AddedChain<'_>(label: String, genesis_hash: BlockHash, node_count: usize),
+    12: RemovedChain(genesis_hash: BlockHash),
+    13: SubscribedTo(genesis_hash: BlockHash),
+    14: UnsubscribedFrom(genesis_hash: BlockHash),
+    15: Pong<'_>(message: String),
     // Note; some now-unused messages were removed between IDs 15 and 20.
     // We maintain existing IDs for backward compatibility.
-    20: StaleNode,
-    21: NodeIOUpdate<'_>,
-    22: ChainStatsUpdate<'_>,
+    20: StaleNode(node: FeedNodeId) => node,
+    21: NodeIOUpdate<'_>(node: FeedNodeId, io: NodeIO) => node,
+    22: ChainStatsUpdate<'_>(stats: ChainStats),
+    23: RecentBlocks<'_>(blocks: Vec<(BlockNumber, BlockHash, Timestamp, Option<FeedTime>)>),
+    24: NewChain<'_>(genesis_hash: BlockHash, label: String),
+    25: FinalityGapAlert(gap: BlockNumber),
+    26: FinalityGapRecovered(gap: BlockNumber),
+    27: ChainRelayParent(genesis_hash: BlockHash, relay_genesis_hash: BlockHash, para_id: Option<u32>),
+    28: TimeToFirstBlock(node: FeedNodeId, time_to_first_block: FeedTime) => node,
+    29: NodesLastSeen(last_seen: Vec<(FeedNodeId, Timestamp)>),
+    30: NodeCountDrop(genesis_hash: BlockHash, recent_max: usize, node_count: usize, window_ms: u64),
+    31: NodeStatsDelta(node: FeedNodeId, stats: PartialNodeStats) => node,
+    32: MostlySyncing(syncing: u64, node_count: u64),
+    33: MostlySynced(syncing: u64, node_count: u64),
+    34: ValidatorShortfall(expected: u64, validators: u64),
+    35: ValidatorShortfallRecovered(expected: u64, validators: u64),
+    36: NodePingLatency(node: FeedNodeId, latency_ms: u64) => node,
+    37: DuplicateNode(node: FeedNodeId, existing_node: FeedNodeId) => node,
+    38: NodeImportSuccess(node: FeedNodeId, import_success: f64) => node,
+    39: ImplausibleBlock(node: FeedNodeId, height: BlockNumber) => node,
+    40: AveragePeers(average: f64, previous_average: Option<f64>),
+    41: ProcessStarted(node: FeedNodeId, started_at: Timestamp) => node,
+    42: FinalizedAheadOfBest(node: FeedNodeId, finalized_height: BlockNumber, best_height: BlockNumber) => node,
+    43: ChainTheme<'_>(genesis_hash: BlockHash, theme: Theme),
+    44: NameCollision(node: FeedNodeId, other_nodes: u64) => node,
+    45: SessionRotation(from: u64, to: u64),
+    46: ChainOverviews<'_>(
+        chains: Vec<(String, BlockHash, usize, BlockNumber, BlockNumber, Option<FeedTime>)>,
+    ),
+    47: OutdatedVersion(node: FeedNodeId) => node,
+    48: NodeAnnotated<'_>(
+        node: FeedNodeId,
+        text: String,
+        severity: AnnotationSeverity,
+        expires_at: Timestamp,
+    ) => node,
+    49: NodeAnnotationCleared(node: FeedNodeId) => node,
+    50: FinalityStarted(genesis_hash: BlockHash, height: BlockNumber),
 }
 
 #[derive(Serialize)]
 pub struct Version(pub usize);
 
-#[derive(Serialize)]
-pub struct BestBlock(pub BlockNumber, pub Timestamp, pub Option<u64>);
+/// The best block height, when it arrived, the average block time and the best block hash.
+/// The hash was added last, in feed version 33, so that older frontends aren't confused by it.
+pub struct BestBlock(
+    pub BlockNumber,
+    pub Timestamp,
+    pub Option<u64>,
+    pub BlockHash,
+);
 
 #[derive(Serialize)]
 pub struct BestFinalized(pub BlockNumber, pub BlockHash);
 
-pub struct AddedNode<'a>(pub FeedNodeId, pub &'a Node, pub bool);
+/// A node being added, along with which of its details feeds are sent.
+pub struct AddedNode<'a>(pub FeedNodeId, pub &'a Node, pub NodeDetailFields);
+
+/// The details of a node that [`AddedNode`] messages can leave out. Its name is always sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeDetailField {
+    Implementation,
+    Version,
+    Validator,
+    NetworkId,
+    Ip,
+    Sysinfo,
+    Hwbench,
+    Custom,
+    Location,
+    StartupTime,
+}
+
+impl NodeDetailField {
+    pub const ALL: [NodeDetailField; 10] = [
+        NodeDetailField::Implementation,
+        NodeDetailField::Version,
+        NodeDetailField::Validator,
+        NodeDetailField::NetworkId,
+        NodeDetailField::Ip,
+        NodeDetailField::Sysinfo,
+        NodeDetailField::Hwbench,
+        NodeDetailField::Custom,
+        NodeDetailField::Location,
+        NodeDetailField::StartupTime,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NodeDetailField::Implementation => "implementation",
+            NodeDetailField::Version => "version",
+            NodeDetailField::Validator => "validator",
+            NodeDetailField::NetworkId => "network_id",
+            NodeDetailField::Ip => "ip",
+            NodeDetailField::Sysinfo => "sysinfo",
+            NodeDetailField::Hwbench => "hwbench",
+            NodeDetailField::Custom => "custom",
+            NodeDetailField::Location => "location",
+            NodeDetailField::StartupTime => "startup_time",
+        }
+    }
+
+    /// Is this only sent to feeds when node details are exposed?
+    pub fn is_private(self) -> bool {
+        matches!(
+            self,
+            NodeDetailField::Ip
+                | NodeDetailField::Sysinfo
+                | NodeDetailField::Hwbench
+                | NodeDetailField::Custom
+        )
+    }
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// A set of [`NodeDetailField`]s, such as the ones that feeds are allowed to be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeDetailFields(u16);
+
+impl NodeDetailFields {
+    pub fn contains(self, field: NodeDetailField) -> bool {
+        self.0 & field.bit() != 0
+    }
+
+    /// These fields, less the ones that are only sent when node details are exposed.
+    pub fn public(self) -> NodeDetailFields {
+        NodeDetailField::ALL
+            .into_iter()
+            .filter(|&field| self.contains(field) && !field.is_private())
+            .collect()
+    }
+}
+
+// Every field is allowed unless we're told otherwise.
+impl Default for NodeDetailFields {
+    fn default() -> Self {
+        NodeDetailField::ALL.into_iter().collect()
+    }
+}
+
+impl FromIterator<NodeDetailField> for NodeDetailFields {
+    fn from_iter<I: IntoIterator<Item = NodeDetailField>>(iter: I) -> Self {
+        NodeDetailFields(iter.into_iter().fold(0, |bits, field| bits | field.bit()))
+    }
+}
+
+/// Parse a comma separated list of field names, eg `implementation,version`. An
+/// empty list allows none of the fields.
+impl FromStr for NodeDetailFields {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                NodeDetailField::ALL
+                    .into_iter()
+                    .find(|field| field.name() == name)
+                    .ok_or_else(|| {
+                        let names: Vec<_> = NodeDetailField::ALL.iter().map(|f| f.name()).collect();
+                        anyhow::anyhow!(
+                            "Node detail field '{name}' not recognised; expecting some of {}",
+                            names.join(", ")
+                        )
+                    })
+            })
+            .collect()
+    }
+}
 
 #[derive(Serialize)]
 pub struct RemovedNode(pub FeedNodeId);
@@ -142,7 +646,6 @@ pub struct RemovedNode(pub FeedNodeId);
 #[derive(Serialize)]
 pub struct LocatedNode<'a>(pub FeedNodeId, pub f32, pub f32, pub &'a str);
 
-#[derive(Serialize)]
 pub struct ImportedBlock<'a>(pub FeedNodeId, pub &'a BlockDetails);
 
 #[derive(Serialize)]
@@ -151,6 +654,88 @@ pub struct FinalizedBlock(pub FeedNodeId, pub BlockNumber, pub BlockHash);
 #[derive(Serialize)]
 pub struct NodeStatsUpdate<'a>(pub FeedNodeId, pub &'a NodeStats);
 
+/// Only the stats of a node that have changed, with `null` for those that haven't.
+/// Sent instead of most [`NodeStatsUpdate`]s since feed version 34.
+#[derive(Serialize)]
+pub struct NodeStatsDelta(pub FeedNodeId, pub PartialNodeStats);
+
+/// The average round trip time (in ms) of pings from the shard to a node's connection.
+#[derive(Serialize)]
+pub struct NodePingLatency(pub FeedNodeId, pub u64);
+
+/// The fraction of the blocks it's tried to import that a node has imported. Only sent
+/// once that's changed by a percentage point or more.
+#[derive(Serialize)]
+pub struct NodeImportSuccess(pub FeedNodeId, pub f64);
+
+/// Sent when a node is added that reports the same network ID as a node that's already
+/// on the chain: the ID of the new node, and then of the existing one.
+#[derive(Serialize)]
+pub struct DuplicateNode(pub FeedNodeId, pub FeedNodeId);
+
+/// Sent when a node is added with the same name as other nodes already on the chain: the
+/// ID of the new node, and then how many other nodes have its name.
+#[derive(Serialize)]
+pub struct NameCollision(pub FeedNodeId, pub u64);
+
+/// Sent when most nodes on the chain have moved on to a later session: the session that
+/// the chain was in, and then the one that it's in now.
+#[derive(Serialize)]
+pub struct SessionRotation(pub u64, pub u64);
+
+/// Sent when a node is added that runs an older client version than the minimum
+/// configured for its chain: the ID of the node.
+#[derive(Serialize)]
+pub struct OutdatedVersion(pub FeedNodeId);
+
+/// Sent when a note from some external system is attached to a node, and for annotated
+/// nodes when subscribing: the ID of the node, the text of the note, how serious it is,
+/// and then when (unix time in ms) it expires.
+#[derive(Serialize)]
+pub struct NodeAnnotated<'a>(
+    pub FeedNodeId,
+    pub &'a str,
+    pub AnnotationSeverity,
+    pub Timestamp,
+);
+
+/// Sent when the note attached to a node is cleared or expires: the ID of the node.
+#[derive(Serialize)]
+pub struct NodeAnnotationCleared(pub FeedNodeId);
+
+/// Sent every so often to feeds that asked for an overview of every chain rather than
+/// subscribing to one. For each chain: its label, genesis hash, node count, best and
+/// finalized block heights, and then its average block time.
+pub struct ChainOverviews<'a>(pub &'a [ChainOverview<'a>]);
+
+/// The headline numbers of a chain, as sent in [`ChainOverviews`].
+pub struct ChainOverview<'a> {
+    /// The label of the chain, with the start of its genesis hash appended if another
+    /// chain has the same label.
+    pub label: Cow<'a, str>,
+    pub genesis_hash: BlockHash,
+    pub node_count: usize,
+    pub best_height: BlockNumber,
+    pub finalized_height: BlockNumber,
+    pub average_block_time: Option<u64>,
+}
+
+/// When (unix time in ms) a node's process started, going by the uptime it reports. Sent
+/// when we first find out, and again if the node restarts.
+#[derive(Serialize)]
+pub struct ProcessStarted(pub FeedNodeId, pub Timestamp);
+
+/// Sent the first time that a node reports a best block which is ignored for being
+/// implausible: the ID of the node, and then the height of the block.
+#[derive(Serialize)]
+pub struct ImplausibleBlock(pub FeedNodeId, pub BlockNumber);
+
+/// Sent the first time that a node reports a finalized block ahead of its best block,
+/// which is clamped to its best block: the ID of the node, the height of the finalized
+/// block it reported, and then the height of its best block.
+#[derive(Serialize)]
+pub struct FinalizedAheadOfBest(pub FeedNodeId, pub BlockNumber, pub BlockNumber);
+
 #[derive(Serialize)]
 pub struct NodeIOUpdate<'a>(pub FeedNodeId, pub &'a NodeIO);
 
@@ -166,6 +751,22 @@ pub struct AddedChain<'a>(pub &'a str, pub BlockHash, pub usize);
 #[derive(Serialize)]
 pub struct RemovedChain(pub BlockHash);
 
+/// Sent once, when the first node of a chain connects and the chain is created. Unlike
+/// [`AddedChain`], this isn't repeated as the node count or label of the chain changes.
+#[derive(Serialize)]
+pub struct NewChain<'a>(pub BlockHash, pub &'a str);
+
+/// Tells feeds which relay chain a parachain belongs to: the genesis hash of the
+/// parachain, the genesis hash of its relay chain and its para ID, if known.
+#[derive(Serialize)]
+pub struct ChainRelayParent(pub BlockHash, pub BlockHash, pub Option<u32>);
+
+/// Tells feeds how a chain should be shown, for dashboards branded per network: its
+/// genesis hash, and then an object with its `color` and `icon`, either of which can be
+/// `null`. Only sent for chains that have either configured.
+#[derive(Serialize)]
+pub struct ChainTheme<'a>(pub BlockHash, pub &'a Theme);
+
 #[derive(Serialize)]
 pub struct SubscribedTo(pub BlockHash);
 
@@ -178,39 +779,163 @@ pub struct Pong<'a>(pub &'a str);
 #[derive(Serialize)]
 pub struct StaleNode(pub FeedNodeId);
 
+/// Sent when the finalized block of the chain falls more than the configured threshold
+/// behind the best block. Contains the number of blocks between the two.
+#[derive(Serialize)]
+pub struct FinalityGapAlert(pub BlockNumber);
+
+/// Sent when the gap between the best and finalized blocks of a chain that we've
+/// alerted about falls back within the threshold.
+#[derive(Serialize)]
+pub struct FinalityGapRecovered(pub BlockNumber);
+
+/// Sent once, when a chain first finalizes a block after genesis: its genesis hash,
+/// and the height of that block.
+#[derive(Serialize)]
+pub struct FinalityStarted(pub BlockHash, pub BlockNumber);
+
+pub struct RecentBlocks<'a>(pub &'a VecDeque<RecentBlock>);
+
+/// Sent when a node reports its first best block, with how long (in ms) after
+/// connecting it did so.
+pub struct TimeToFirstBlock(pub FeedNodeId, pub u64);
+
+/// When (unix time in ms) each of the nodes we've heard from since this was
+/// last sent was last seen.
+#[derive(Serialize)]
+pub struct NodesLastSeen(pub Vec<(FeedNodeId, Timestamp)>);
+
+/// Sent when a chain suddenly loses lots of nodes: its genesis hash, the highest node
+/// count seen recently, the node count now, and how far back (in ms) we looked.
+#[derive(Serialize)]
+pub struct NodeCountDrop(pub BlockHash, pub usize, pub usize, pub u64);
+
+/// Sent when most of a chain's nodes are syncing: how many are, and how many nodes
+/// there are in all.
+#[derive(Serialize)]
+pub struct MostlySyncing(pub u64, pub u64);
+
+/// Sent when the average peer count of a chain's nodes has changed noticeably: the new
+/// average, and then the one that feeds were last told about, if any.
+#[derive(Serialize)]
+pub struct AveragePeers(pub f64, pub Option<f64>);
+
+/// Sent when most of a chain's nodes are no longer syncing, having been before.
+#[derive(Serialize)]
+pub struct MostlySynced(pub u64, pub u64);
+
+/// Sent when a chain has more than the configured threshold fewer validators than it's
+/// expected to: how many are expected, and how many there are.
+#[derive(Serialize)]
+pub struct ValidatorShortfall(pub u64, pub u64);
+
+/// Sent when the validator count of a chain that we've alerted about comes back to
+/// within the threshold of what's expected.
+#[derive(Serialize)]
+pub struct ValidatorShortfallRecovered(pub u64, pub u64);
+
+impl FeedMessageWrite for BestBlock {
+    fn write_to_feed(&self, ser: &mut FeedMessageSerializer) {
+        let BestBlock(height, timestamp, average_block_time, hash) = self;
+        let average_block_time = average_block_time.map(|t| ser.time(t));
+        ser.write(&(height, timestamp, average_block_time, hash));
+    }
+}
+
+impl FeedMessageWrite for ChainOverviews<'_> {
+    fn write_to_feed(&self, ser: &mut FeedMessageSerializer) {
+        let ChainOverviews(chains) = self;
+        let chains: Vec<_> = chains
+            .iter()
+            .map(|c| {
+                (
+                    &c.label,
+                    c.genesis_hash,
+                    c.node_count,
+                    c.best_height,
+                    c.finalized_height,
+                    c.average_block_time.map(|t| ser.time(t)),
+                )
+            })
+            .collect();
+        ser.write(&chains);
+    }
+}
+
+impl FeedMessageWrite for TimeToFirstBlock {
+    fn write_to_feed(&self, ser: &mut FeedMessageSerializer) {
+        let TimeToFirstBlock(nid, time_to_first_block) = self;
+        let time_to_first_block = ser.time(*time_to_first_block);
+        ser.write(&(nid, time_to_first_block));
+    }
+}
+
+impl FeedMessageWrite for ImportedBlock<'_> {
+    fn write_to_feed(&self, ser: &mut FeedMessageSerializer) {
+        let ImportedBlock(nid, block_details) = self;
+        let block_details = ser.block_details(block_details);
+        ser.write(&(nid, block_details));
+    }
+}
+
+impl FeedMessageWrite for RecentBlocks<'_> {
+    fn write_to_feed(&self, ser: &mut FeedMessageSerializer) {
+        let RecentBlocks(blocks) = self;
+        let blocks: Vec<_> = blocks
+            .iter()
+            .map(|b| {
+                (
+                    b.height,
+                    b.hash,
+                    b.timestamp,
+                    b.block_time.map(|t| ser.time(t)),
+                )
+            })
+            .collect();
+        ser.write(&blocks);
+    }
+}
+
 impl FeedMessageWrite for AddedNode<'_> {
     fn write_to_feed(&self, ser: &mut FeedMessageSerializer) {
-        let AddedNode(nid, node, expose_node_details) = self;
+        let AddedNode(nid, node, fields) = self;
 
+        // Fields that feeds aren't allowed to see are sent as `null`:
         let details = node.details();
-        // Hide the ip, sysinfo and hwbench if the `expose_node_details` flag was not specified.
-        let node_hwbench = node.hwbench();
-        let (ip, sys_info, hwbench) = if *expose_node_details {
-            (&details.ip, &details.sysinfo, &node_hwbench)
-        } else {
-            (&None, &None, &None)
-        };
-
+        let show = |field| fields.contains(field);
         let details = (
             &details.name,
-            &details.implementation,
-            &details.version,
-            &details.validator,
-            &details.network_id,
-            &ip,
-            &sys_info,
-            &hwbench,
+            Some(&details.implementation).filter(|_| show(NodeDetailField::Implementation)),
+            Some(&details.version).filter(|_| show(NodeDetailField::Version)),
+            details
+                .validator
+                .as_ref()
+                .filter(|_| show(NodeDetailField::Validator)),
+            Some(&details.network_id).filter(|_| show(NodeDetailField::NetworkId)),
+            details.ip.as_ref().filter(|_| show(NodeDetailField::Ip)),
+            details
+                .sysinfo
+                .as_ref()
+                .filter(|_| show(NodeDetailField::Sysinfo)),
+            node.hwbench().filter(|_| show(NodeDetailField::Hwbench)),
+            Some(&details.custom).filter(|_| show(NodeDetailField::Custom)),
         );
 
+        let block_details = ser.block_details(node.block_details());
         ser.write(&(
             nid,
             details,
             node.stats(),
             node.io(),
             node.hardware(),
-            node.block_details(),
-            &node.location(),
-            &node.startup_time(),
+            block_details,
+            node.location().filter(|_| show(NodeDetailField::Location)),
+            node.startup_time()
+                .filter(|_| show(NodeDetailField::StartupTime)),
+            node.last_seen(),
+            node.stable_id(),
+            node.process_started_at()
+                .filter(|_| show(NodeDetailField::StartupTime)),
         ));
     }
 }
@@ -218,18 +943,37 @@ impl FeedMessageWrite for AddedNode<'_> {
 #[derive(Serialize)]
 pub struct ChainStatsUpdate<'a>(pub &'a ChainStats);
 
-#[derive(Serialize, PartialEq, Eq, Default)]
+#[derive(Serialize, PartialEq, Eq)]
 pub struct Ranking<K> {
     pub list: Vec<(K, u64)>,
     pub other: u64,
     pub unknown: u64,
 }
 
-#[derive(Serialize, PartialEq, Eq, Default)]
+// Derived, this would needlessly require `K: Default`.
+impl<K> Default for Ranking<K> {
+    fn default() -> Self {
+        Ranking {
+            list: Vec::new(),
+            other: 0,
+            unknown: 0,
+        }
+    }
+}
+
+#[derive(Serialize, PartialEq, Default)]
 pub struct ChainStats {
     pub version: Ranking<String>,
+    /// How many nodes are running each major.minor client version, oldest version first.
+    /// Versions that can't be parsed are counted as unknown.
+    pub major_minor_version: Ranking<(u32, u32)>,
     pub target_os: Ranking<String>,
     pub target_arch: Ranking<String>,
+    /// How many nodes were built for each OS and architecture, like "linux/x86_64".
+    /// Nodes that don't report both are counted as unknown.
+    pub platform: Ranking<String>,
+    /// How many nodes send their telemetry in each version of the protocol, like "v2".
+    pub protocol_version: Ranking<String>,
     pub cpu: Ranking<String>,
     pub memory: Ranking<(u32, Option<u32>)>,
     pub core_count: Ranking<u32>,
@@ -241,4 +985,529 @@ pub struct ChainStats {
     pub disk_sequential_write_score: Ranking<(u32, Option<u32>)>,
     pub disk_random_write_score: Ranking<(u32, Option<u32>)>,
     pub cpu_vendor: Ranking<String>,
+    /// How many nodes are in each country, by ISO 3166-1 alpha-2 code.
+    pub country: Ranking<String>,
+    /// How many nodes are run with each hosting provider, going by the organisation behind
+    /// the autonomous system of their IP address. Only known given an ASN database.
+    pub provider: Ranking<String>,
+    /// The smallest number of countries that have more than half of the nodes whose
+    /// country is known between them.
+    pub geo_nakamoto_coefficient: Option<u32>,
+    /// How many nodes are on low, mid or high tier hardware. Nodes that haven't sent
+    /// any benchmarks are counted as unknown.
+    pub hardware_tier: Ranking<HardwareTier>,
+    /// How many nodes have been freshly connected, connected for a medium time or
+    /// connected for a long time.
+    pub connection_durations: ConnectionDurations,
+    /// How much bandwidth the nodes that aren't stale are using between them. Nodes
+    /// that haven't reported their bandwidth are left out.
+    pub bandwidth: ChainBandwidth,
+    /// Average time (in ms) between the first node reporting a new finalized
+    /// block and the other nodes on the chain reporting the same block.
+    pub average_finalized_propagation_time: Option<u64>,
+    /// Average time (in ms) between the finalized block of the chain advancing.
+    pub average_finalization_time: Option<u64>,
+    /// How many block times the average block time is calculated over.
+    pub block_time_window: usize,
+    /// How much the block times that the average block time is worked out over vary, as
+    /// their standard deviation over their mean. Lower means steadier block production.
+    pub block_time_variation: Option<f64>,
+    /// How long (in ms) blocks are expected to take, for comparing the average block time
+    /// against. Unless configured for the chain, this is the long run average block time,
+    /// once enough blocks have been seen.
+    pub expected_block_time: Option<u64>,
+    /// The session that most nodes are in, if they report one.
+    pub session: Option<u64>,
+    /// Average number of transactions in the best block of the nodes that report it.
+    pub average_block_txcount: Option<u64>,
+    /// Roughly how many transactions per second the chain is handling, going by the
+    /// average block time and number of transactions per block.
+    pub transactions_per_second: Option<f64>,
+    /// The average peer count of the nodes that aren't stale.
+    pub average_peers: Option<f64>,
+    /// How many messages per second we're receiving about nodes on the chain.
+    pub messages_per_second: f64,
+    /// Is the chain frozen, ignoring any updates from its nodes?
+    pub frozen: bool,
+    /// When (unix time in ms) the counters behind these stats were last recounted
+    /// from the nodes that are connected.
+    pub last_reconciled: Timestamp,
+    /// When (unix time in ms) we first saw a node on the chain.
+    pub first_seen: Timestamp,
+    /// How long (in ms) we've been watching the chain for.
+    pub chain_age: u64,
+    /// How many blocks the finalized block is behind the best block.
+    pub best_finalized_gap: BlockNumber,
+    /// Median time (in ms) between nodes connecting and reporting their first best block.
+    pub median_time_to_first_block: Option<u64>,
+    /// How many nodes haven't reported a best block since connecting.
+    pub pending_first_block: u64,
+    /// The longest ago (unix time in ms) that we last heard from any of the nodes.
+    pub oldest_last_seen: Option<Timestamp>,
+    /// Median best block height of the nodes that aren't stale and have reported a best
+    /// block. Unlike the best block, a few nodes that are far ahead won't move this.
+    pub median_best_block: Option<BlockNumber>,
+    /// Roughly how many different operators run the nodes, going by the node names.
+    pub distinct_operators: u64,
+    /// How many nodes are well behind the best block, and so probably syncing.
+    pub syncing_nodes: u64,
+    /// Are most of the nodes syncing?
+    pub mostly_syncing: bool,
+    /// A score from 0 to 100 of how healthy the chain looks at a glance, where 100 is
+    /// perfectly healthy. This combines the finality gap, how many nodes are stale or
+    /// syncing, how far the block time is from what's expected and any validator shortfall.
+    pub health_score: u8,
+    /// How many nodes have a finalized block well behind that of the chain. Unlike a
+    /// finality gap, which means that the chain's finality has stalled, this means that
+    /// some of the nodes are behind.
+    pub nodes_lagging_finality: u64,
+    /// How many of the nodes that aren't stale have a clock that's well ahead of or behind
+    /// ours. Propagation times are worked out from the times that nodes report, so these
+    /// are less trustworthy when many nodes are off.
+    pub nodes_with_clock_skew: ClockSkewCounts,
+    /// Median number of bytes that the databases of the nodes that aren't stale take up on
+    /// disk, out of those that report it.
+    pub median_disk_used: Option<u64>,
+    /// How many nodes that aren't stale are running out of space on the disk that their
+    /// database is on, and so might soon fall over.
+    pub nodes_low_on_disk: u64,
+    /// How long ago (in ms) the node whose finalized block advanced most recently saw it
+    /// advance, out of the nodes that aren't stale.
+    pub min_finalized_age: Option<u64>,
+    /// How long ago (in ms) the node whose finalized block advanced least recently saw it
+    /// advance. A big spread between this and the minimum means that finality is reaching
+    /// some nodes well before others.
+    pub max_finalized_age: Option<u64>,
+    /// The authors that nodes have told us produced the most best blocks.
+    pub top_block_authors: Vec<BlockAuthor>,
+    /// How many of the authors that we know about haven't produced a block for a while.
+    pub inactive_block_authors: u64,
+    /// How many nodes have reported best blocks that were ignored for being implausible.
+    pub implausible_block_nodes: u64,
+    /// How many nodes that aren't stale say that their process started recently.
+    pub recently_restarted: u64,
+    /// How many nodes have reported a validator address.
+    pub validators: u64,
+    /// How many nodes are full nodes, and how many are light clients. Nodes that don't say
+    /// are counted as configured.
+    pub node_roles: NodeRoleCounts,
+    /// How many full nodes there are for each light client, if there are any light clients.
+    pub full_to_light_ratio: Option<f64>,
+    /// How many nodes have had lots of blocks waiting in their import queue for a while.
+    pub backed_up_import_queues: u64,
+    /// How many nodes have failed to import lots of the blocks that they tried to, and
+    /// so might be on a bad fork or have a corrupted database.
+    pub unhealthy_imports: u64,
+    /// The fraction of nodes that report the chain's label; lower means that nodes
+    /// disagree about what the chain is called.
+    pub label_confidence: f64,
+    /// How many nodes have values of each stat that are counted as "other" because
+    /// the stat has too many distinct values. Stats without any are left out.
+    pub folded_values: BTreeMap<&'static str, u64>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ZERO_HASH: &str =
+        "\"0x0000000000000000000000000000000000000000000000000000000000000000\"";
+
+    fn to_string(bytes: Option<&bytes::Bytes>) -> &str {
+        std::str::from_utf8(bytes.expect("some bytes expected")).unwrap()
+    }
+
+    fn node() -> Node {
+        Node::new(common::node_types::NodeDetails {
+            chain: "Chain".into(),
+            name: "Alice".into(),
+            implementation: "Substrate".into(),
+            version: "1.0".into(),
+            validator: None,
+            network_id: "12D3Koo".parse().unwrap(),
+            startup_time: None,
+            target_os: None,
+            target_arch: None,
+            target_env: None,
+            sysinfo: None,
+            ip: Some("127.0.0.1".into()),
+            protocol_version: None,
+            relay_chain_genesis_hash: None,
+            para_id: None,
+            role: None,
+            custom: Default::default(),
+        })
+    }
+
+    /// Split the stringified types in a tuple type, eg `(A, Option<(B, C)>)`, into
+    /// `A` and `Option<(B, C)>`.
+    fn tuple_types(ty: &str) -> Vec<&str> {
+        let inner = &ty[1..ty.len() - 1];
+        let mut types = Vec::new();
+        let (mut depth, mut start) = (0, 0);
+        for (idx, c) in inner.char_indices() {
+            match c {
+                '(' | '<' => depth += 1,
+                ')' | '>' => depth -= 1,
+                ',' if depth == 0 => {
+                    types.push(inner[start..idx].trim());
+                    start = idx + 1;
+                }
+                _ => {}
+            }
+        }
+        types.push(inner[start..].trim());
+        types.retain(|ty| !ty.is_empty());
+        types
+    }
+
+    /// Check that the tuples in a value have as many elements as the schema type says.
+    /// Other types aren't looked into.
+    fn check_shape(ty: &str, value: &serde_json::Value, message: &str) {
+        let ty = ty.replace(' ', "");
+        if let Some(inner) = ty.strip_prefix("Option<").and_then(|t| t.strip_suffix('>')) {
+            if !value.is_null() {
+                check_shape(inner, value, message);
+            }
+        } else if let Some(inner) = ty.strip_prefix("Vec<").and_then(|t| t.strip_suffix('>')) {
+            let values = value.as_array().expect("Vec should be an array");
+            for value in values {
+                check_shape(inner, value, message);
+            }
+        } else if ty.starts_with('(') {
+            let types = tuple_types(&ty);
+            let values = value.as_array().expect("tuple should be an array");
+            assert_eq!(values.len(), types.len(), "{message}: {ty} vs {value}");
+            for (ty, value) in types.into_iter().zip(values) {
+                check_shape(ty, value, message);
+            }
+        }
+    }
+
+    #[test]
+    fn schema_matches_what_is_sent() {
+        let node = node();
+        let recent_blocks = VecDeque::from([RecentBlock {
+            height: 1,
+            hash: BlockHash::zero(),
+            timestamp: 1000,
+            block_time: Some(6000),
+            propagation_time: None,
+        }]);
+        let chain_overviews = [ChainOverview {
+            label: "Chain".into(),
+            genesis_hash: BlockHash::zero(),
+            node_count: 1,
+            best_height: 10,
+            finalized_height: 8,
+            average_block_time: Some(6000),
+        }];
+        let chain_stats = ChainStats::default();
+        let theme = Theme::default();
+
+        // One of every type of message:
+        let mut ser = FeedMessageSerializer::new();
+        ser.push(Version(FEED_VERSION));
+        ser.push(BestBlock(10, 1234, Some(6000), BlockHash::zero()));
+        ser.push(BestFinalized(8, BlockHash::zero()));
+        ser.push(AddedNode(1, &node, NodeDetailFields::default()));
+        ser.push(RemovedNode(1));
+        ser.push(LocatedNode(1, 52.5, 13.4, "Berlin"));
+        ser.push(ImportedBlock(1, node.block_details()));
+        ser.push(FinalizedBlock(1, 8, BlockHash::zero()));
+        ser.push(NodeStatsUpdate(1, node.stats()));
+        ser.push(Hardware(1, node.hardware()));
+        ser.push(TimeSync(1234));
+        ser.push(AddedChain("Chain", BlockHash::zero(), 1));
+        ser.push(RemovedChain(BlockHash::zero()));
+        ser.push(SubscribedTo(BlockHash::zero()));
+        ser.push(UnsubscribedFrom(BlockHash::zero()));
+        ser.push(Pong("ping"));
+        ser.push(StaleNode(1));
+        ser.push(NodeIOUpdate(1, node.io()));
+        ser.push(ChainStatsUpdate(&chain_stats));
+        ser.push(RecentBlocks(&recent_blocks));
+        ser.push(NewChain(BlockHash::zero(), "Chain"));
+        ser.push(FinalityGapAlert(10));
+        ser.push(FinalityGapRecovered(1));
+        ser.push(ChainRelayParent(
+            BlockHash::zero(),
+            BlockHash::zero(),
+            Some(1000),
+        ));
+        ser.push(TimeToFirstBlock(1, 500));
+        ser.push(NodesLastSeen(vec![(1, 1234)]));
+        ser.push(NodeCountDrop(BlockHash::zero(), 10, 2, 60_000));
+        ser.push(NodeStatsDelta(1, PartialNodeStats::default()));
+        ser.push(MostlySyncing(8, 10));
+        ser.push(MostlySynced(1, 10));
+        ser.push(ValidatorShortfall(10, 5));
+        ser.push(ValidatorShortfallRecovered(10, 9));
+        ser.push(NodePingLatency(1, 40));
+        ser.push(DuplicateNode(2, 1));
+        ser.push(NodeImportSuccess(1, 0.5));
+        ser.push(ImplausibleBlock(1, 1_000_000));
+        ser.push(AveragePeers(10.5, None));
+        ser.push(ProcessStarted(1, 1234));
+        ser.push(FinalizedAheadOfBest(1, 12, 10));
+        ser.push(ChainTheme(BlockHash::zero(), &theme));
+        ser.push(NameCollision(1, 2));
+        ser.push(SessionRotation(1, 2));
+        ser.push(ChainOverviews(&chain_overviews));
+        ser.push(OutdatedVersion(1));
+        ser.push(NodeAnnotated(1, "note", AnnotationSeverity::Info, 1234));
+        ser.push(NodeAnnotationCleared(1));
+        ser.push(FinalityStarted(BlockHash::zero(), 1));
+
+        let json: serde_json::Value =
+            serde_json::from_str(to_string(ser.into_finalized().as_ref())).unwrap();
+        let json = json.as_array().unwrap();
+        let schema = schema();
+        let mut sent = Vec::new();
+        for pair in json.chunks(2) {
+            let action = pair[0].as_u64().unwrap() as u8;
+            let message = schema.messages.iter().find(|m| m.action == action).unwrap();
+            sent.push(action);
+
+            // Messages with one field send it as is, and others send an array of them:
+            match &*message.fields {
+                [field] => check_shape(&field.ty, &pair[1], message.name),
+                fields => {
+                    let values = pair[1].as_array().expect("fields should be an array");
+                    assert_eq!(values.len(), fields.len(), "{}", message.name);
+                    for (field, value) in fields.iter().zip(values) {
+                        check_shape(&field.ty, value, message.name);
+                    }
+                }
+            }
+        }
+
+        let actions: Vec<_> = schema.messages.iter().map(|m| m.action).collect();
+        assert_eq!(sent, actions, "every type of message should be checked");
+    }
+
+    #[test]
+    fn pushed_messages_are_counted_by_type() {
+        let count = |name| {
+            message_counts()
+                .find(|&(n, _)| n == name)
+                .map(|(_, count)| count)
+                .unwrap()
+        };
+        let before = count("TimeSync");
+
+        let mut ser = FeedMessageSerializer::for_time_formats(true);
+        ser.push(TimeSync(1));
+        ser.push(TimeSync(2));
+
+        // Other tests may push messages at the same time, so we can't be exact:
+        assert!(count("TimeSync") >= before + 2);
+        assert_eq!(message_counts().count(), schema().messages.len());
+    }
+
+    #[test]
+    fn some_pushed_messages_are_timed() {
+        let before = serialization_times().count();
+
+        let mut ser = FeedMessageSerializer::new();
+        for n in 0..SERIALIZATION_SAMPLE_RATE * 4 {
+            ser.push(TimeSync(n));
+        }
+
+        // Even if other tests push the same messages at the same time, some of these are timed:
+        assert!(serialization_times().count() >= before + 2);
+    }
+
+    #[test]
+    fn frontend_expects_the_same_feed_version() {
+        let index = include_str!("../../../frontend/src/common/index.ts");
+        let version: usize = index
+            .lines()
+            .find_map(|line| line.strip_prefix("export const VERSION: Types.FeedVersion = "))
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|version| version.parse().ok())
+            .expect("frontend should declare its feed version");
+        assert_eq!(version, FEED_VERSION);
+    }
+
+    #[test]
+    fn schema_describes_every_type_of_message() {
+        let schema = schema();
+        assert_eq!(schema.version, FEED_VERSION);
+        let names: Vec<_> = schema.messages.iter().map(|m| m.name).collect();
+        let counted: Vec<_> = message_counts().map(|(name, _)| name).collect();
+        assert_eq!(names, counted);
+
+        let best_block = schema.messages.iter().find(|m| m.action == 1).unwrap();
+        assert_eq!(best_block.name, "BestBlock");
+        assert!(!best_block.about_node);
+        let fields: Vec<_> = best_block
+            .fields
+            .iter()
+            .map(|f| (f.name, f.ty.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("height", "BlockNumber"),
+                ("timestamp", "Timestamp"),
+                ("average_block_time", "Option<FeedTime>"),
+                ("hash", "BlockHash"),
+            ]
+        );
+
+        // Messages about a node have its ID first:
+        for message in schema.messages.iter().filter(|m| m.about_node) {
+            assert_eq!(message.fields[0].ty, "FeedNodeId", "{}", message.name);
+        }
+    }
+
+    #[test]
+    fn times_are_written_in_millis_by_default() {
+        let mut ser = FeedMessageSerializer::new();
+        ser.push(BestBlock(10, 1234, Some(6000), BlockHash::zero()));
+
+        assert_eq!(
+            to_string(ser.into_finalized().as_ref()),
+            format!("[1,[10,1234,6000,{ZERO_HASH}]]")
+        );
+    }
+
+    #[test]
+    fn times_can_be_written_in_seconds() {
+        let mut ser = FeedMessageSerializer::with_time_format(TimeFormat::Seconds);
+        ser.push(BestBlock(10, 1234, Some(6500), BlockHash::zero()));
+
+        assert_eq!(
+            to_string(ser.into_finalized().as_ref()),
+            format!("[1,[10,1234,6.5,{ZERO_HASH}]]")
+        );
+    }
+
+    #[test]
+    fn messages_can_be_serialized_in_both_formats() {
+        let mut ser = FeedMessageSerializer::for_time_formats(true);
+        ser.push(BestBlock(10, 1234, Some(250), BlockHash::zero()));
+        ser.push(StaleNode(1));
+
+        let messages = ser.into_finalized_per_time_format().unwrap();
+        assert_eq!(
+            to_string(Some(messages.for_time_format(TimeFormat::Millis))),
+            format!("[1,[10,1234,250,{ZERO_HASH}],20,1]")
+        );
+        assert_eq!(
+            to_string(Some(messages.for_time_format(TimeFormat::Seconds))),
+            format!("[1,[10,1234,0.25,{ZERO_HASH}],20,1]")
+        );
+    }
+
+    #[test]
+    fn batches_of_messages_can_be_coalesced() {
+        let batches = |batches: &[&'static str]| {
+            batches
+                .iter()
+                .map(|b| bytes::Bytes::from_static(b.as_bytes()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            coalesce(batches(&["[20,1]", "[20,2,20,3]", "[20,4]"]), 1024),
+            batches(&["[20,1,20,2,20,3,20,4]"])
+        );
+        // Batches stop growing at the size given:
+        assert_eq!(
+            coalesce(batches(&["[20,1]", "[20,2,20,3]", "[20,4]"]), 16),
+            batches(&["[20,1,20,2,20,3]", "[20,4]"])
+        );
+        assert_eq!(
+            coalesce(batches(&["[20,1]", "[20,2,20,3]"]), 1),
+            batches(&["[20,1]", "[20,2,20,3]"])
+        );
+        assert_eq!(coalesce(batches(&[]), 1024), batches(&[]));
+    }
+
+    #[test]
+    fn messages_about_nodes_can_be_left_out() {
+        let mut ser = FeedMessageSerializer::for_time_formats(true);
+        ser.push(StaleNode(1));
+        ser.push(BestBlock(10, 1234, Some(250), BlockHash::zero()));
+        ser.push(NodePingLatency(2, 40));
+
+        let messages = ser.into_finalized_per_time_format().unwrap();
+        assert_eq!(
+            to_string(
+                messages
+                    .for_time_format_and_nodes(TimeFormat::Millis, |nid| nid == 2)
+                    .as_ref()
+            ),
+            format!("[1,[10,1234,250,{ZERO_HASH}],36,[2,40]]")
+        );
+        assert_eq!(
+            to_string(
+                messages
+                    .for_time_format_and_nodes(TimeFormat::Seconds, |nid| nid == 1)
+                    .as_ref()
+            ),
+            format!("[20,1,1,[10,1234,0.25,{ZERO_HASH}]]")
+        );
+        assert_eq!(
+            messages.for_time_format_and_nodes(TimeFormat::Millis, |_| true),
+            Some(messages.for_time_format(TimeFormat::Millis).clone())
+        );
+
+        let mut ser = FeedMessageSerializer::new();
+        ser.push(StaleNode(1));
+        let messages = ser.into_finalized_per_time_format().unwrap();
+        assert_eq!(
+            messages.for_time_format_and_nodes(TimeFormat::Millis, |_| false),
+            None
+        );
+    }
+
+    #[test]
+    fn node_details_that_arent_allowed_are_null() {
+        let node = node();
+        let details = |fields: &str| {
+            let mut ser = FeedMessageSerializer::new();
+            ser.push(AddedNode(1, &node, fields.parse().unwrap()));
+            let json: serde_json::Value =
+                serde_json::from_str(to_string(ser.into_finalized().as_ref())).unwrap();
+            json[1][1].clone()
+        };
+
+        assert_eq!(
+            details("version,ip"),
+            serde_json::json!([
+                "Alice",
+                null,
+                "1.0",
+                null,
+                null,
+                "127.0.0.1",
+                null,
+                null,
+                null
+            ])
+        );
+        // The name is always sent:
+        assert_eq!(details("")[0], "Alice");
+        assert_eq!(details("implementation")[1], "Substrate");
+
+        let fields: NodeDetailFields = "version,ip".parse().unwrap();
+        assert!(fields.contains(NodeDetailField::Ip));
+        assert!(!fields.public().contains(NodeDetailField::Ip));
+        assert!(fields.public().contains(NodeDetailField::Version));
+        assert!("version,colour".parse::<NodeDetailFields>().is_err());
+    }
+
+    #[test]
+    fn seconds_fall_back_to_millis_if_not_serialized() {
+        let mut ser = FeedMessageSerializer::new();
+        ser.push(StaleNode(1));
+
+        let messages = ser.into_finalized_per_time_format().unwrap();
+        assert_eq!(
+            messages.for_time_format(TimeFormat::Seconds),
+            messages.for_time_format(TimeFormat::Millis)
+        );
+    }
 }
@@ -0,0 +1,105 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Feeds can ask for a version of the feed protocol via the websocket subprotocol,
+//! so that we can change what we send them and what they send us without breaking
+//! frontends that expect an older version.
+
+use std::str::FromStr;
+
+use crate::aggregator::FromFeedWebsocket;
+
+/// A version of the protocol that we speak to feeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FeedProtocol {
+    /// JSON feed messages, and `CMD:VALUE` text commands. Feeds that don't ask
+    /// for a subprotocol get this.
+    #[default]
+    V1,
+}
+
+impl FeedProtocol {
+    /// Every version that we support, newest first.
+    pub const ALL: [FeedProtocol; 1] = [FeedProtocol::V1];
+
+    /// The websocket subprotocol that feeds ask for this version by.
+    pub fn name(self) -> &'static str {
+        match self {
+            FeedProtocol::V1 => "substrate-telemetry-feed-v1",
+        }
+    }
+
+    /// Pick the version to speak given the subprotocols that a feed asked for, most
+    /// preferred first. If it didn't ask for any, we speak [`FeedProtocol::default()`]
+    /// without agreeing to a subprotocol, as older frontends expect. It's an error if
+    /// it asked only for versions that we don't support.
+    pub fn negotiate(requested: &[&str]) -> anyhow::Result<Option<FeedProtocol>> {
+        if requested.is_empty() {
+            return Ok(None);
+        }
+        requested
+            .iter()
+            .find_map(|&name| FeedProtocol::ALL.into_iter().find(|p| p.name() == name))
+            .map(Some)
+            .ok_or_else(|| {
+                let supported: Vec<_> = FeedProtocol::ALL.iter().map(|p| p.name()).collect();
+                anyhow::anyhow!(
+                    "Unsupported feed protocol(s) {}; expecting one of {}",
+                    requested.join(", "),
+                    supported.join(", ")
+                )
+            })
+    }
+
+    /// Parse a command sent to us by a feed.
+    pub fn parse_command(self, text: &str) -> anyhow::Result<FromFeedWebsocket> {
+        match self {
+            FeedProtocol::V1 => FromFeedWebsocket::from_str(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn feeds_not_asking_for_a_protocol_get_the_default() {
+        assert_eq!(FeedProtocol::negotiate(&[]).unwrap(), None);
+    }
+
+    #[test]
+    fn first_supported_protocol_asked_for_is_used() {
+        assert_eq!(
+            FeedProtocol::negotiate(&[
+                "substrate-telemetry-feed-v99",
+                "substrate-telemetry-feed-v1"
+            ])
+            .unwrap(),
+            Some(FeedProtocol::V1)
+        );
+    }
+
+    #[test]
+    fn unsupported_protocols_are_rejected() {
+        let err = FeedProtocol::negotiate(&["substrate-telemetry-feed-v99"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unsupported feed protocol(s) substrate-telemetry-feed-v99; \
+             expecting one of substrate-telemetry-feed-v1"
+        );
+    }
+}
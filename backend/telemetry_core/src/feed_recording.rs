@@ -0,0 +1,201 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Record the messages that a feed is sent to a file, and replay them to feeds
+//! later on, for demos, frontend development and reproducing bugs.
+//!
+//! A recording has one message per line, each line being the number of milliseconds
+//! since the recording started, a tab, and then the message exactly as it was sent
+//! to the feed.
+
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use common::http_utils;
+use common::node_types::BlockHash;
+use futures::SinkExt;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{Duration, Instant};
+
+use crate::aggregator::{AggregatorSet, FromFeedWebsocket, ToFeedWebsocket};
+use crate::feed_message::TimeFormat;
+
+/// A single message in a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMessage {
+    /// How long after the recording started the message was sent.
+    pub elapsed: Duration,
+    /// The message, as it was sent to the feed.
+    pub bytes: Bytes,
+}
+
+impl RecordedMessage {
+    fn to_line(&self) -> Vec<u8> {
+        let elapsed = self.elapsed.as_millis().to_string();
+        let mut line = Vec::with_capacity(elapsed.len() + self.bytes.len() + 2);
+        line.extend_from_slice(elapsed.as_bytes());
+        line.push(b'\t');
+        // Feed messages are JSON, so they won't contain any newlines of their own:
+        line.extend_from_slice(&self.bytes);
+        line.push(b'\n');
+        line
+    }
+
+    fn from_line(line: &str) -> anyhow::Result<Self> {
+        let (elapsed, message) = line
+            .split_once('\t')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<elapsed_ms>\\t<message>`"))?;
+        Ok(RecordedMessage {
+            elapsed: Duration::from_millis(elapsed.parse()?),
+            bytes: Bytes::copy_from_slice(message.as_bytes()),
+        })
+    }
+}
+
+/// Subscribe to the aggregator as a feed would, and write every message that we're sent
+/// to the file at `path`. If a chain is given, we subscribe to it once it exists and so
+/// record the messages about its nodes as well.
+pub async fn spawn_feed_recorder(
+    path: PathBuf,
+    chain: Option<BlockHash>,
+    aggregator: AggregatorSet,
+) -> anyhow::Result<()> {
+    let mut file = tokio::io::BufWriter::new(tokio::fs::File::create(&path).await?);
+    let (_feed_id, mut tx_to_aggregator) = aggregator.subscribe_feed();
+    let (tx_to_recorder, rx_from_aggregator) = flume::unbounded();
+
+    tx_to_aggregator
+        .send(FromFeedWebsocket::Initialize {
+            channel: tx_to_recorder,
+        })
+        .await?;
+    if let Some(chain) = chain {
+        tokio::spawn(async move {
+            // Chains only exist once a node on them connects, so wait for that first:
+            loop {
+                match aggregator.gather_chains().await {
+                    Ok(chains) if chains.iter().any(|c| c.genesis_hash == chain) => break,
+                    Ok(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+                    Err(e) => {
+                        log::error!("Error waiting for chain {chain} to record: {e}");
+                        return;
+                    }
+                }
+            }
+            let subscribe = FromFeedWebsocket::Subscribe {
+                chain,
+                time_format: TimeFormat::default(),
+            };
+            if let Err(e) = tx_to_aggregator.send(subscribe).await {
+                log::error!("Error subscribing to chain {chain} to record: {e}");
+            }
+        });
+    }
+
+    log::info!("Recording feed messages to {path:?}");
+    tokio::spawn(async move {
+        let started = Instant::now();
+        while let Ok(msg) = rx_from_aggregator.recv_async().await {
+            let bytes = match msg {
+                ToFeedWebsocket::Bytes(bytes) | ToFeedWebsocket::Snapshot(bytes) => bytes,
+            };
+            let msg = RecordedMessage {
+                elapsed: started.elapsed(),
+                bytes,
+            };
+            let res = async {
+                file.write_all(&msg.to_line()).await?;
+                // Flush if there's nothing else waiting, so that the recording is
+                // usable even if we're stopped abruptly:
+                if rx_from_aggregator.is_empty() {
+                    file.flush().await?;
+                }
+                Ok::<_, std::io::Error>(())
+            };
+            if let Err(e) = res.await {
+                log::error!("Error writing to feed recording {path:?}; stopping recording: {e}");
+                return;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Load a recording made by [`spawn_feed_recorder`].
+pub fn read_recording(path: &Path) -> anyhow::Result<Vec<RecordedMessage>> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut messages = Vec::new();
+    for (idx, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let msg = RecordedMessage::from_line(&line)
+            .map_err(|e| anyhow::anyhow!("Line {} of feed recording {path:?}: {e}", idx + 1))?;
+        messages.push(msg);
+    }
+    Ok(messages)
+}
+
+/// Send a recording to a feed, with the same timings between messages as when it was recorded.
+pub async fn replay_to_feed(
+    recording: Arc<Vec<RecordedMessage>>,
+    mut ws_send: http_utils::WsSender,
+) -> Result<(), soketto::connection::Error> {
+    let started = Instant::now();
+    for msg in recording.iter() {
+        let send_at = started + msg.elapsed;
+        if send_at > Instant::now() {
+            // Make sure the feed has everything up to now before we wait:
+            ws_send.flush().await?;
+            tokio::time::sleep_until(send_at).await;
+        }
+        ws_send.send_binary(&msg.bytes).await?;
+    }
+    ws_send.flush().await?;
+    ws_send.close().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recorded_messages_round_trip() {
+        let msg = RecordedMessage {
+            elapsed: Duration::from_millis(1234),
+            bytes: Bytes::from_static(br#"[0,32,1,["Polkadot","0x1234",10]]"#),
+        };
+        let line = String::from_utf8(msg.to_line()).unwrap();
+        assert_eq!(line, "1234\t[0,32,1,[\"Polkadot\",\"0x1234\",10]]\n");
+        assert_eq!(RecordedMessage::from_line(line.trim_end()).unwrap(), msg);
+    }
+
+    #[test]
+    fn messages_can_contain_tabs() {
+        let msg = RecordedMessage::from_line("5\t[\"a\tb\"]").unwrap();
+        assert_eq!(msg.elapsed, Duration::from_millis(5));
+        assert_eq!(&msg.bytes[..], b"[\"a\tb\"]");
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        assert!(RecordedMessage::from_line("[0,32]").is_err());
+        assert!(RecordedMessage::from_line("soon\t[0,32]").is_err());
+    }
+}
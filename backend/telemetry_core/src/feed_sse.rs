@@ -0,0 +1,167 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Stream the feed as server-sent events, for clients that can't use websockets. Each
+//! batch of feed messages is sent exactly as a websocket feed would get it, as the data
+//! of one event. Since clients can't send us anything, the commands that they'd otherwise
+//! send are given in the query string instead.
+
+use bytes::Bytes;
+use futures::SinkExt;
+use tokio::time::{Duration, Instant};
+
+use crate::aggregator::{FromFeedWebsocket, ToFeedWebsocket};
+use crate::feed_protocol::FeedProtocol;
+
+/// Sent when nothing else has been for a while, so that the connection isn't closed for
+/// being idle. Lines starting with a colon are ignored by clients.
+const KEEPALIVE: &[u8] = b":\n\n";
+
+/// Parse a query string like `subscribe=0x123..,s&group=team-a` into the commands that a
+/// websocket feed would send to do the same, in the order that they need sending in.
+pub fn parse_query(query: &str) -> Result<Vec<FromFeedWebsocket>, String> {
+    let mut group = None;
+    let mut subscribe = None;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        let command = || {
+            FeedProtocol::default()
+                .parse_command(&format!("{key}:{value}"))
+                .map_err(|e| format!("Invalid value for '{key}': {e}"))
+        };
+        match &*key {
+            "group" => group = Some(command()?),
+            "subscribe" => subscribe = Some(command()?),
+            // Checked before the connection is opened:
+            "token" => {}
+            _ => return Err(format!("Unknown query parameter '{key}'")),
+        }
+    }
+    // The group only applies to subscriptions made after it's set:
+    Ok(group.into_iter().chain(subscribe).collect())
+}
+
+/// Wrap feed messages up as an event, with a `data:` field for each line of them.
+pub fn to_event(bytes: &[u8]) -> Bytes {
+    let mut event = Vec::with_capacity(bytes.len() + 8);
+    for line in bytes.split(|&b| b == b'\n') {
+        event.extend_from_slice(b"data: ");
+        event.extend_from_slice(line);
+        event.push(b'\n');
+    }
+    event.push(b'\n');
+    event.into()
+}
+
+/// Send the commands given to the aggregator, and then stream the feed messages that it
+/// sends back as events until the client goes away or is too slow to keep up. Hands back
+/// the sink to the aggregator, so that it can be told that the connection has closed.
+pub async fn handle_connection<S>(
+    mut body_tx: hyper::body::Sender,
+    mut tx_to_aggregator: S,
+    commands: Vec<FromFeedWebsocket>,
+    feed_timeout: u64,
+    feed_keepalive: Duration,
+    _feed_id: u64, // <- can be useful for debugging purposes.
+) -> S
+where
+    S: futures::Sink<FromFeedWebsocket, Error = anyhow::Error> + Unpin + Send + 'static,
+{
+    // unbounded channel so that slow feeds don't block aggregator progress:
+    let (tx_to_feed_conn, rx_from_aggregator) = flume::unbounded();
+
+    let init_msg = FromFeedWebsocket::Initialize {
+        channel: tx_to_feed_conn,
+    };
+    for msg in std::iter::once(init_msg).chain(commands) {
+        if let Err(e) = tx_to_aggregator.send(msg).await {
+            log::error!("Error sending message to aggregator: {e}");
+            return tx_to_aggregator;
+        }
+    }
+
+    // Periodically check whether the feed has gone quiet. This is also how we notice that
+    // clients have gone away while there's nothing to send them.
+    let mut keepalive = (!feed_keepalive.is_zero())
+        .then(|| tokio::time::interval_at(Instant::now() + feed_keepalive, feed_keepalive));
+
+    let mut last_sent = Instant::now();
+    loop {
+        let keepalive_tick = async {
+            match &mut keepalive {
+                Some(interval) => interval.tick().await,
+                None => std::future::pending().await,
+            }
+        };
+        let event = tokio::select! {
+            msg = rx_from_aggregator.recv_async() => match msg {
+                Ok(ToFeedWebsocket::Bytes(bytes) | ToFeedWebsocket::Snapshot(bytes)) => {
+                    to_event(&bytes)
+                }
+                Err(_) => break,
+            },
+            _ = keepalive_tick => match last_sent.elapsed() >= feed_keepalive {
+                true => Bytes::from_static(KEEPALIVE),
+                false => continue,
+            },
+        };
+
+        // If the feed is too slow to receive the event, we'll drop it.
+        let deadline = Instant::now() + Duration::from_secs(feed_timeout);
+        match tokio::time::timeout_at(deadline, body_tx.send_data(event)).await {
+            Err(_) => {
+                log::debug!("Closing feed SSE stream that was too slow to keep up");
+                break;
+            }
+            // The client has gone away:
+            Ok(Err(_)) => break,
+            Ok(Ok(())) => {}
+        }
+        last_sent = Instant::now();
+    }
+
+    tx_to_aggregator
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn query_is_turned_into_commands() {
+        let commands = parse_query(
+            "subscribe=0x0000000000000000000000000000000000000000000000000000000000000001,s&group=team-a",
+        )
+        .unwrap();
+        // The group is always set first, so that it applies to the subscription:
+        assert!(matches!(
+            &commands[..],
+            [
+                FromFeedWebsocket::SetGroup { group: Some(group) },
+                FromFeedWebsocket::Subscribe { .. },
+            ] if &**group == "team-a"
+        ));
+
+        assert!(parse_query("").unwrap().is_empty());
+        assert!(parse_query("subscribe=nope").is_err());
+        assert!(parse_query("ping=1").is_err());
+    }
+
+    #[test]
+    fn each_line_of_an_event_is_data() {
+        assert_eq!(&to_event(b"[0,32]")[..], b"data: [0,32]\n\n");
+        assert_eq!(&to_event(b"[0,\n32]")[..], b"data: [0,\ndata: 32]\n\n");
+    }
+}
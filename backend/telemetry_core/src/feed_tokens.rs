@@ -0,0 +1,130 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use common::node_types::BlockHash;
+
+/// Which chains feeds can subscribe to. Chains are public unless they're private,
+/// in which case feeds need a token that grants them access to subscribe.
+#[derive(Debug)]
+pub struct FeedTokens {
+    private_chains: HashSet<BlockHash>,
+    tokens: HashMap<String, HashSet<BlockHash>>,
+}
+
+impl FeedTokens {
+    /// Only tokens that grant access to the private chains given can subscribe to them.
+    pub fn new(private_chains: HashSet<BlockHash>) -> FeedTokens {
+        FeedTokens {
+            private_chains,
+            tokens: HashMap::new(),
+        }
+    }
+
+    /// Load tokens from a file containing a token and a comma separated list of the
+    /// genesis hashes of the chains that it grants access to on each line. Blank lines
+    /// and lines starting with `#` are ignored.
+    pub fn from_file(
+        path: &Path,
+        private_chains: HashSet<BlockHash>,
+    ) -> anyhow::Result<FeedTokens> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read feed tokens {path:?}: {e}"))?;
+        let mut tokens = FeedTokens::new(private_chains);
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (token, chains) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Line {} of feed tokens {path:?}: expecting format `<token> <genesis_hash>[,<genesis_hash>..]`",
+                    idx + 1
+                )
+            })?;
+            let chains = chains
+                .split(',')
+                .map(|hash| hash.trim().parse::<BlockHash>())
+                .collect::<Result<HashSet<_>, _>>()
+                .map_err(|e| anyhow::anyhow!("Line {} of feed tokens {path:?}: {e}", idx + 1))?;
+            tokens.tokens.insert(token.to_owned(), chains);
+        }
+        Ok(tokens)
+    }
+
+    /// Can a feed with the token given (if any) subscribe to the chain given?
+    pub fn can_subscribe(&self, token: Option<&str>, genesis_hash: &BlockHash) -> bool {
+        if !self.private_chains.contains(genesis_hash) {
+            return true;
+        }
+        token
+            .and_then(|token| self.tokens.get(token))
+            .is_some_and(|chains| chains.contains(genesis_hash))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hash(n: u64) -> BlockHash {
+        BlockHash::from_low_u64_be(n)
+    }
+
+    fn tokens(contents: &str) -> anyhow::Result<FeedTokens> {
+        let path = std::env::temp_dir().join(format!(
+            "feed_tokens_test_{}_{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        let tokens = FeedTokens::from_file(&path, [hash(1), hash(2)].into_iter().collect());
+        std::fs::remove_file(&path).unwrap();
+        tokens
+    }
+
+    #[test]
+    fn private_chains_need_a_token_that_grants_them() {
+        let tokens = tokens(&format!(
+            "# Comments and blank lines are ignored\n\
+             \n\
+             one {:?}\n\
+             both  {:?}, {:?}\n",
+            hash(1),
+            hash(1),
+            hash(2)
+        ))
+        .unwrap();
+
+        assert!(tokens.can_subscribe(Some("one"), &hash(1)));
+        assert!(!tokens.can_subscribe(Some("one"), &hash(2)));
+        assert!(tokens.can_subscribe(Some("both"), &hash(2)));
+        assert!(!tokens.can_subscribe(Some("nobody"), &hash(1)));
+        assert!(!tokens.can_subscribe(None, &hash(1)));
+
+        // Public chains don't need a token at all:
+        assert!(tokens.can_subscribe(None, &hash(3)));
+        assert!(tokens.can_subscribe(Some("nobody"), &hash(3)));
+    }
+
+    #[test]
+    fn invalid_lines_are_an_error() {
+        assert!(tokens("no-chains\n").is_err());
+        assert!(tokens("token 0x1234\n").is_err());
+    }
+}
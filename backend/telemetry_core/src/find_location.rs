@@ -15,68 +15,253 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use anyhow::Context;
 use futures::{Sink, SinkExt};
-use maxminddb::{geoip2::City, Reader as GeoIpReader};
-use parking_lot::RwLock;
+use maxminddb::{
+    geoip2::{Asn, City},
+    Reader as GeoIpReader,
+};
+use parking_lot::{Mutex, RwLock};
 use rustc_hash::FxHashMap;
+use tokio::sync::Semaphore;
 
 use common::node_types::NodeLocation;
 
 /// The returned location is optional; it may be None if not found.
 pub type Location = Option<Arc<NodeLocation>>;
 
+/// How many lookups can be in progress at once. Any more wait in the queue.
+const MAX_CONCURRENT_LOOKUPS: usize = 32;
+
+/// A database (such as MaxMind's GeoLite2 ASN database) to find the autonomous
+/// systems, and thus hosting providers, of IP addresses in.
+#[derive(Debug, Clone)]
+pub struct AsnDatabase(Arc<GeoIpReader<Vec<u8>>>);
+
+impl AsnDatabase {
+    pub fn from_file(path: &Path) -> anyhow::Result<AsnDatabase> {
+        let reader = GeoIpReader::open_readfile(path)
+            .with_context(|| format!("Could not open ASN database {}", path.display()))?;
+        Ok(AsnDatabase(Arc::new(reader)))
+    }
+
+    /// The number and organisation of the autonomous system that an IP address belongs to.
+    fn lookup(&self, ip: IpAddr) -> Option<(Option<u32>, Option<Box<str>>)> {
+        let Asn {
+            autonomous_system_number,
+            autonomous_system_organization,
+        } = self.0.lookup(ip).ok()?;
+        Some((
+            autonomous_system_number,
+            autonomous_system_organization.map(Into::into),
+        ))
+    }
+}
+
+/// Read the IP addresses of nodes that we expect to connect from a file, one per line,
+/// to locate ahead of time. Blank lines and lines starting with `#` are ignored, and lines
+/// that aren't IP addresses are logged and skipped.
+pub fn read_prewarm_ips(path: &Path) -> anyhow::Result<Vec<IpAddr>> {
+    let contents = std::fs::read_to_string(path).with_context(|| {
+        format!(
+            "Could not read IP addresses to locate from {}",
+            path.display()
+        )
+    })?;
+    let ips = contents
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|(idx, line)| match line.parse() {
+            Ok(ip) => Some(ip),
+            Err(e) => {
+                log::warn!("Skipping line {} of {}: {e}", idx + 1, path.display());
+                None
+            }
+        })
+        .collect();
+    Ok(ips)
+}
+
+/// How long lookups can take, and how we back off from making them when lots fail.
+#[derive(Debug, Clone, Copy)]
+pub struct LocatorLimits {
+    /// Lookups that take longer than this are given up on.
+    pub timeout: Duration,
+    /// Once this many lookups in a row have failed, no more are made for `cooldown`.
+    /// 0 means that we never stop making them.
+    pub max_failures: u32,
+    /// How long to stop making lookups for once too many have failed.
+    pub cooldown: Duration,
+    /// How many lookups can wait to be made. Once this many are, requests for more
+    /// can't be sent, and should be dropped.
+    pub queue_len: usize,
+}
+
 /// This is responsible for taking an IP address and attempting
-/// to find a geographical location from this
-pub fn find_location<Id, R>(response_chan: R) -> flume::Sender<(Id, IpAddr)>
+/// to find a geographical location (and, given an ASN database,
+/// a hosting provider) from this. Locating is best effort: nodes are
+/// located whenever a lookup completes, and lookups that are slow or
+/// come while lots are failing are given up on, leaving the node
+/// without a location. Requests are queued up to `limits.queue_len`,
+/// so they should be sent with `try_send`, and dropped if the queue is full.
+///
+/// The locations of `prewarm_ips` are looked up in the background straight away, so that
+/// nodes connecting from them are located without waiting on a lookup.
+pub fn find_location<Id, R>(
+    response_chan: R,
+    asn_database: Option<AsnDatabase>,
+    limits: LocatorLimits,
+    prewarm_ips: Arc<[IpAddr]>,
+) -> flume::Sender<(Id, IpAddr)>
 where
     R: Sink<(Id, Option<Arc<NodeLocation>>)> + Unpin + Send + Clone + 'static,
     Id: Clone + Send + 'static,
 {
-    let (tx, rx) = flume::unbounded();
+    let (tx, rx) = flume::bounded(limits.queue_len.max(1));
 
     // cache entries
     let mut cache: FxHashMap<IpAddr, Arc<NodeLocation>> = FxHashMap::default();
 
     // Default entry for localhost
+    #[allow(clippy::excessive_precision, clippy::inconsistent_digit_grouping)]
     cache.insert(
         Ipv4Addr::new(127, 0, 0, 1).into(),
         Arc::new(NodeLocation {
             latitude: 52.516_6667,
             longitude: 13.4,
             city: "Berlin".into(),
+            country: Some("DE".into()),
+            asn: None,
+            provider: None,
         }),
     );
 
     // Create a locator with our cache. This is used to obtain locations.
-    let locator = Locator::new(cache);
+    let locator = Locator::new(cache, asn_database);
+    if !prewarm_ips.is_empty() {
+        let locator = locator.clone();
+        tokio::task::spawn_blocking(move || locator.prewarm(&prewarm_ips));
+    }
+    let breaker = Arc::new(Mutex::new(CircuitBreaker::new(
+        limits.max_failures,
+        limits.cooldown,
+    )));
+
+    let lookups = Arc::new(Semaphore::new(MAX_CONCURRENT_LOOKUPS));
 
     // Spawn a loop to handle location requests
     tokio::spawn(async move {
         loop {
-            while let Ok((id, ip_address)) = rx.recv_async().await {
-                let mut response_chan = response_chan.clone();
-                let locator = locator.clone();
-
-                tokio::spawn(async move {
-                    let location = tokio::task::spawn_blocking(move || locator.locate(ip_address))
-                        .await
-                        .expect("Locate never panics");
-                    let _ = response_chan.send((id, location)).await;
-                });
+            // Requests are only taken off the queue once there's room for another lookup,
+            // so while lookups are slow, the queue fills up rather than the lookups piling up:
+            let permit = Arc::clone(&lookups)
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed");
+            let Ok((id, ip_address)) = rx.recv_async().await else {
+                break;
+            };
+            if !breaker.lock().allow(Instant::now()) {
+                continue;
             }
+
+            let mut response_chan = response_chan.clone();
+            let locator = locator.clone();
+            let breaker = Arc::clone(&breaker);
+
+            tokio::spawn(async move {
+                let _permit = permit;
+                let lookup = tokio::task::spawn_blocking(move || locator.locate(ip_address));
+                let location = match tokio::time::timeout(limits.timeout, lookup).await {
+                    Ok(Ok(location)) => location,
+                    Ok(Err(e)) => {
+                        log::warn!("Locating {ip_address} failed: {e}");
+                        breaker.lock().record(false, Instant::now());
+                        return;
+                    }
+                    Err(_) => {
+                        log::warn!("Locating {ip_address} took over {:?}", limits.timeout);
+                        breaker.lock().record(false, Instant::now());
+                        return;
+                    }
+                };
+                breaker.lock().record(true, Instant::now());
+                let _ = response_chan.send((id, location)).await;
+            });
         }
     });
 
     tx
 }
 
+/// Stops lookups from being made for a while once lots of them in a row have failed,
+/// so that we don't keep piling work onto a locator that can't keep up.
+#[derive(Debug)]
+struct CircuitBreaker {
+    max_failures: u32,
+    cooldown: Duration,
+    /// How many lookups in a row have failed.
+    failures: u32,
+    /// If lookups have been stopped, when they can be made again.
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new(max_failures: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            max_failures,
+            cooldown,
+            failures: 0,
+            open_until: None,
+        }
+    }
+
+    /// Can a lookup be made now?
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.open_until {
+            Some(open_until) if now < open_until => false,
+            Some(_) => {
+                log::info!("Trying to locate nodes again");
+                self.open_until = None;
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Make a note of whether a lookup succeeded, stopping lookups for a while
+    /// if too many in a row have failed.
+    fn record(&mut self, succeeded: bool, now: Instant) {
+        if succeeded {
+            self.failures = 0;
+            return;
+        }
+        self.failures += 1;
+        if self.max_failures > 0 && self.failures >= self.max_failures && self.open_until.is_none()
+        {
+            log::warn!(
+                "{} node lookups in a row have failed; not locating nodes for {:?}",
+                self.failures,
+                self.cooldown
+            );
+            self.failures = 0;
+            self.open_until = Some(now + self.cooldown);
+        }
+    }
+}
+
 /// This struct can be used to make location requests, given
 /// an IPV4 or IPV6 address.
 #[derive(Debug, Clone)]
 struct Locator {
     city: Arc<maxminddb::Reader<&'static [u8]>>,
+    asn: Option<AsnDatabase>,
     cache: Arc<RwLock<FxHashMap<IpAddr, Arc<NodeLocation>>>>,
 }
 
@@ -92,15 +277,31 @@ impl Locator {
     /// To view a copy of this license, visit https://creativecommons.org/licenses/by/4.0/.
     const CITY_DATA: &'static [u8] = include_bytes!("GeoLite2-City.mmdb");
 
-    pub fn new(cache: FxHashMap<IpAddr, Arc<NodeLocation>>) -> Self {
+    pub fn new(cache: FxHashMap<IpAddr, Arc<NodeLocation>>, asn: Option<AsnDatabase>) -> Self {
         Self {
             city: GeoIpReader::from_source(Self::CITY_DATA)
                 .map(Arc::new)
                 .expect("City data is always valid"),
+            asn,
             cache: Arc::new(RwLock::new(cache)),
         }
     }
 
+    /// Locate each of the IP addresses given, so that they're cached for later.
+    pub fn prewarm(&self, ips: &[IpAddr]) {
+        let mut located = 0;
+        for &ip in ips {
+            match self.locate(ip) {
+                Some(_) => located += 1,
+                None => log::debug!("Could not locate {ip} ahead of time; skipping it"),
+            }
+        }
+        log::info!(
+            "Located {located} of {} IP addresses ahead of time",
+            ips.len()
+        );
+    }
+
     pub fn locate(&self, ip: IpAddr) -> Option<Arc<NodeLocation>> {
         // Return location quickly if it's cached:
         let cached_loc = {
@@ -111,7 +312,15 @@ impl Locator {
             return cached_loc;
         }
 
-        let City { city, location, .. } = self.city.lookup(ip.into()).ok()?;
+        let City {
+            city,
+            location,
+            country,
+            ..
+        } = self.city.lookup(ip).ok()?;
+        let country = country
+            .and_then(|country| country.iso_code)
+            .map(|iso_code| iso_code.into());
         let city = city
             .as_ref()?
             .names
@@ -121,11 +330,19 @@ impl Locator {
             .into_boxed_str();
         let latitude = location.as_ref()?.latitude? as f32;
         let longitude = location?.longitude? as f32;
+        let (asn, provider) = self
+            .asn
+            .as_ref()
+            .and_then(|asn| asn.lookup(ip))
+            .unwrap_or_default();
 
         let location = Arc::new(NodeLocation {
             city,
             latitude,
             longitude,
+            country,
+            asn,
+            provider,
         });
         self.cache.write().insert(ip, Arc::clone(&location));
 
@@ -139,13 +356,92 @@ mod tests {
 
     #[test]
     fn locator_construction() {
-        Locator::new(Default::default());
+        Locator::new(Default::default(), None);
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_the_queue_length_are_refused() {
+        let (tx, _rx) = flume::unbounded::<(u32, Location)>();
+        let limits = LocatorLimits {
+            timeout: Duration::from_secs(5),
+            max_failures: 0,
+            cooldown: Duration::ZERO,
+            queue_len: 2,
+        };
+        let requests = find_location(tx.into_sink(), None, limits, Arc::from([]));
+
+        // The lookup loop doesn't get to run until we yield, so nothing is taken off the queue:
+        let ip: IpAddr = Ipv4Addr::LOCALHOST.into();
+        assert!(requests.try_send((1, ip)).is_ok());
+        assert!(requests.try_send((2, ip)).is_ok());
+        assert!(matches!(
+            requests.try_send((3, ip)),
+            Err(flume::TrySendError::Full(_))
+        ));
+    }
+
+    #[test]
+    fn lookups_stop_for_a_while_once_lots_fail() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+
+        // A success resets the count of failures in a row:
+        breaker.record(false, now);
+        breaker.record(false, now);
+        breaker.record(true, now);
+        breaker.record(false, now);
+        breaker.record(false, now);
+        assert!(breaker.allow(now));
+
+        breaker.record(false, now);
+        assert!(!breaker.allow(now));
+        assert!(!breaker.allow(now + Duration::from_secs(59)));
+        assert!(breaker.allow(now + Duration::from_secs(60)));
+        assert!(breaker.allow(now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn lookups_never_stop_if_no_failures_are_allowed() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::new(0, Duration::from_secs(60));
+        for _ in 0..100 {
+            breaker.record(false, now);
+        }
+        assert!(breaker.allow(now));
+    }
+
+    #[test]
+    fn prewarmed_ips_are_cached() {
+        let locator = Locator::new(Default::default(), None);
+        let located: IpAddr = "12.5.56.25".parse().unwrap();
+        let unlocatable: IpAddr = "10.0.0.1".parse().unwrap();
+        locator.prewarm(&[located, unlocatable]);
+
+        let cache = locator.cache.read();
+        assert_eq!(&*cache[&located].city, "Gardena");
+        assert!(!cache.contains_key(&unlocatable));
+    }
+
+    #[test]
+    fn prewarm_files_skip_lines_that_are_not_ips() {
+        let path = std::env::temp_dir().join(format!(
+            "prewarm_ips_test_{}_{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, "# Comment\n\n12.5.56.25\nnot-an-ip\n::1\n").unwrap();
+        let ips = read_prewarm_ips(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let expected: Vec<IpAddr> = vec!["12.5.56.25".parse().unwrap(), "::1".parse().unwrap()];
+        assert_eq!(ips.unwrap(), expected);
     }
 
     #[test]
     fn locate_random_ip() {
         let ip = "12.5.56.25".parse().unwrap();
-        let node_location = Locator::new(Default::default()).locate(ip).unwrap();
+        let node_location = Locator::new(Default::default(), None).locate(ip).unwrap();
         assert_eq!(&*node_location.city, "Gardena");
+        assert_eq!(node_location.country.as_deref(), Some("US"));
     }
 }
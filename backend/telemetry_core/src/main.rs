@@ -14,25 +14,61 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+mod admin_tokens;
 mod aggregator;
+mod broker;
 mod feed_message;
+mod feed_protocol;
+mod feed_recording;
+mod feed_sse;
+mod feed_tokens;
 mod find_location;
+mod metrics_export;
 mod state;
+mod statsd;
+mod webhooks;
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 use tokio::time::{Duration, Instant};
 
+use admin_tokens::{AdminScope, AdminTokenError, AdminTokens};
 use aggregator::{
-    AggregatorOpts, AggregatorSet, FromFeedWebsocket, FromShardWebsocket, ToFeedWebsocket,
-    ToShardWebsocket,
+    AggregatorOpts, AggregatorSet, ChainExportOutcome, FromFeedWebsocket, FromShardWebsocket,
+    MoveNodeOutcome, NewChainGrace, NodeFilter, NodeLookup, ToFeedWebsocket, ToShardWebsocket,
 };
 use bincode::Options;
+use broker::BrokerOpts;
+use common::byte_size::ByteSize;
 use common::http_utils;
 use common::internal_messages;
+use common::node_types::BlockHash;
 use common::ready_chunks_all::ReadyChunksAll;
+use common::real_ip;
+use common::{ConnectionLimits, ConnectionRefused};
+use feed_message::NodeDetailFields;
+use feed_protocol::FeedProtocol;
+use feed_tokens::FeedTokens;
+use find_location::{read_prewarm_ips, AsnDatabase, LocatorLimits};
 use futures::{SinkExt, StreamExt};
-use hyper::{Method, Response};
+use hyper::{Body, Method, Request, Response};
+use metrics_export::{Exemplar, MetricsFormat, MetricsWriter};
+use parking_lot::RwLock;
 use simple_logger::SimpleLogger;
+use state::{
+    AnnotationSeverity, BlockTimeWindow, BlockedNodeNameAction, CardinalityLimits, ConnectionTiers,
+    DuplicateNodePolicy, FirstSeenStore, HardwareChanges, HardwareTiers, HealthWeights,
+    LabelDebounce, NetworkDenylist, NodeAnnotation, NodeCountDrops, NodeGroups, NodeNameBlocklist,
+    NodeOperatorPattern, OperatorCap, OutdatedVersionPolicy, QuotaBurst, RecentBlock,
+    ReconnectPolicy, RollingWindows, StaleNodePolicy, StaleTimeouts, StatsTimings, SyncingMajority,
+    Theme, UnparseableVersionPolicy, UnreportedNodeRole,
+};
+use statsd::{StatsdFormat, StatsdOpts};
 use structopt::StructOpt;
+use webhooks::{Webhook, WebhookOpts};
 
 #[cfg(not(target_env = "msvc"))]
 use jemallocator::Jemalloc;
@@ -42,8 +78,24 @@ use jemallocator::Jemalloc;
 static GLOBAL: Jemalloc = Jemalloc;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+const GIT_HASH: &str = env!("GIT_HASH");
 const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
 const NAME: &str = "Substrate Telemetry Backend Core";
+/// How many nodes are handed back per page when searching, unless asked otherwise.
+const DEFAULT_NODE_PAGE_SIZE: usize = 100;
+/// The most nodes that can be handed back per page when searching.
+const MAX_NODE_PAGE_SIZE: usize = 1000;
+/// The longest (in characters) that the text of a node annotation can be.
+const MAX_NODE_ANNOTATION_LEN: usize = 200;
+/// How long node annotations last for, unless asked otherwise.
+const DEFAULT_NODE_ANNOTATION_TTL: Duration = Duration::from_secs(60 * 60);
+/// The longest that node annotations can last for.
+const MAX_NODE_ANNOTATION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// The most recent best blocks that any chain can be made to keep hold of, to bound
+/// how much memory they take up.
+const MAX_RECENT_BLOCKS: usize = 1000;
+/// How often when nodes were first seen is saved, if it's changed.
+const FIRST_SEEN_SAVE_INTERVAL: Duration = Duration::from_secs(60);
 const ABOUT: &str = "This is the Telemetry Backend Core that receives telemetry messages \
                      from Substrate/Polkadot nodes and provides the data to a subsribed feed";
 
@@ -55,6 +107,22 @@ struct Opts {
     /// you are using Telemetry in a container, you likely want to set this to '0.0.0.0:8000'
     #[structopt(short = "l", long = "listen", default_value = "127.0.0.1:8000")]
     socket: std::net::SocketAddr,
+    /// If given, shards connect to '/shard_submit' on this address instead of the '--listen'
+    /// address, so that it can be kept on an internal interface.
+    #[structopt(long)]
+    shard_listen: Option<std::net::SocketAddr>,
+    /// If given, feeds and everything other than '/shard_submit' are served on this address
+    /// instead of the '--listen' address.
+    #[structopt(long)]
+    feed_listen: Option<std::net::SocketAddr>,
+    /// Space delimited list of namespaces to serve on addresses of their own, in the form
+    /// '<name>=<address>'. Each namespace is kept entirely separate from the others: shards
+    /// and feeds that connect to its address only see its chains, and it has its own chain
+    /// list, overviews, metrics and feed connection limits. The default namespace is served on
+    /// the other listen addresses. Namespaces can't be used alongside recording, statsd,
+    /// webhooks, the first seen store or publishing to NATS.
+    #[structopt(long = "namespace", required = false)]
+    namespaces: Vec<Namespace>,
     /// The desired log level; one of 'error', 'warn', 'info', 'debug' or 'trace', where
     /// 'error' only logs errors and 'trace' logs everything.
     #[structopt(long = "log", default_value = "info")]
@@ -67,6 +135,41 @@ struct Opts {
     /// to a feed, the feed connection will be closed.
     #[structopt(long, default_value = "10")]
     feed_timeout: u64,
+    /// If nothing has been sent to a feed for this number of seconds, send it a websocket
+    /// ping (or a comment, to '/sse' feeds) so that proxies in between don't close the
+    /// connection for being idle. "0" disables this.
+    #[structopt(long, default_value = "30")]
+    feed_keepalive_secs: u64,
+    /// Wait this many milliseconds between sends to each feed, combining the messages that
+    /// arrive in the meantime into as few websocket frames as possible, so that busy feeds
+    /// need fewer syscalls. "0" sends each batch of messages in its own frame, as it
+    /// arrives.
+    #[structopt(long, default_value = "0")]
+    feed_coalesce_ms: u64,
+    /// The most bytes that '--feed-coalesce-ms' combines into one frame.
+    #[structopt(long, default_value = "65536")]
+    feed_coalesce_max_bytes: usize,
+    /// Compress every message sent to /feed connections that support it (permessage-deflate).
+    /// This costs CPU for every message sent to every feed.
+    #[structopt(long)]
+    feed_compression: bool,
+    /// Compress the state that /feed connections are sent when they connect or subscribe to
+    /// a chain, for those that support it, even if '--feed-compression' isn't given. This is
+    /// by far the largest thing a feed receives, so compressing it speeds up opening the page.
+    #[structopt(long)]
+    snapshot_compression: bool,
+    /// If given, /feed connections from an IP address that already has this many open are
+    /// refused. Behind a proxy, the address is taken from the headers that it sets.
+    #[structopt(long)]
+    max_feed_connections_per_ip: Option<usize>,
+    /// If given, new /feed and /sse connections are refused while this many are open, so
+    /// that a surge of viewers can't starve the aggregators of time to handle shard messages.
+    #[structopt(long)]
+    max_feed_subscribers: Option<usize>,
+    /// Space delimited list of IP addresses that any number of /feed connections are allowed
+    /// from, regardless of --max-feed-connections-per-ip and --max-feed-subscribers.
+    #[structopt(long, required = false)]
+    connection_limit_exempt_ip: Vec<std::net::IpAddr>,
     /// Number of worker threads to spawn. If "0" is given, use the number of CPUs available
     /// on the machine. If no value is given, use an internal default that we have deemed sane.
     #[structopt(long)]
@@ -82,21 +185,891 @@ struct Opts {
     /// How many nodes from third party chains are allowed to connect before we prevent connections from them.
     #[structopt(long, default_value = "1000")]
     max_third_party_nodes: usize,
+    /// Rather than rejecting nodes that report no genesis hash (or a zero one) but are
+    /// otherwise fine, put up to this many of them on a clearly labelled "unidentified"
+    /// chain, so that misconfigured nodes can be seen. 0 rejects them.
+    #[structopt(long, default_value = "0")]
+    max_unidentified_nodes: usize,
     /// Flag to expose the node's details (IP address, SysInfo, HwBench) of all connected
     /// nodes to the feed subscribers.
     #[structopt(long)]
     pub expose_node_details: bool,
+    /// Space delimited list of chains that expose node details (or not) regardless of
+    /// '--expose-node-details', in the form '<genesis_hash>=<true|false>'.
+    #[structopt(long, required = false)]
+    chain_expose_node_details: Vec<ChainExposeNodeDetails>,
+    /// Comma separated list of the node details that feeds are sent, out of implementation,
+    /// version, validator, network_id, ip, sysinfo, hwbench, custom, location and
+    /// startup_time. Node names are always sent. The ip, sysinfo, hwbench and custom
+    /// details also need '--expose-node-details'. Every detail is allowed by default.
+    #[structopt(long)]
+    node_detail_fields: Option<NodeDetailFields>,
+    /// Space delimited list of chains that allow different node details to be sent to
+    /// feeds than '--node-detail-fields', in the form '<genesis_hash>=<fields>'.
+    #[structopt(long, required = false)]
+    chain_node_detail_fields: Vec<ChainNodeDetailFields>,
+    /// Path to a file of node name patterns, one per line. Each pattern is a case
+    /// insensitive regular expression (so a plain word matches anywhere in the name).
+    /// Blank lines and lines starting with '#' are ignored. On unix systems, the file
+    /// is reloaded when the process receives SIGHUP.
+    #[structopt(long)]
+    node_name_blocklist: Option<PathBuf>,
+    /// What to do with nodes whose name matches the node name blocklist; one of 'reject',
+    /// to mute the node so that it doesn't appear at all, or 'replace', to show the node
+    /// with a placeholder name.
+    #[structopt(long, default_value = "replace")]
+    blocked_node_name_action: BlockedNodeNameAction,
+    /// What to do with a node that reports the same network ID as a node already on its
+    /// chain, which usually means that a node key has been copied; one of 'flag', to show
+    /// both nodes and tell feeds that they're duplicates, 'reject', to mute the new node, or
+    /// 'replace', to mute and remove the existing node.
+    #[structopt(long, default_value = "flag")]
+    duplicate_node_policy: DuplicateNodePolicy,
+    /// What to do with a node that connects again while its old connection is still open, which
+    /// is spotted by it having the same network ID, name and startup time as a node already on
+    /// its chain; one of 'reject', to mute the new connection, or 'replace', to mute and remove
+    /// the old one. These nodes aren't subject to --duplicate-node-policy.
+    #[structopt(long, default_value = "replace")]
+    reconnect_policy: ReconnectPolicy,
+    /// Tell feeds when a node is added with the same name as other nodes on its chain, which
+    /// is often a sign of a misconfigured fleet. Leave this off if nodes share names on purpose.
+    #[structopt(long)]
+    flag_name_collisions: bool,
+    /// Space delimited list of chains whose nodes should run at least a given client version,
+    /// in the form '<genesis_hash>=<major>.<minor>'. Versions are compared on their major and
+    /// minor parts, as in the chain stats.
+    #[structopt(long, required = false)]
+    chain_min_version: Vec<ChainMinVersion>,
+    /// What to do with nodes running an older version than '--chain-min-version' for their
+    /// chain; one of 'flag', to show the node and tell feeds that it's outdated, or 'reject',
+    /// to mute the node so that it doesn't appear at all.
+    #[structopt(long, default_value = "flag")]
+    outdated_version_policy: OutdatedVersionPolicy,
+    /// How to treat nodes whose version can't be parsed, on chains given a
+    /// '--chain-min-version'; one of 'allow', to treat them as recent enough, or 'outdated'.
+    #[structopt(long, default_value = "allow")]
+    unparseable_version_policy: UnparseableVersionPolicy,
+    /// Path to a file of genesis hashes, one per line, of networks whose nodes are always
+    /// rejected. Blank lines and lines starting with '#' are ignored. On unix systems, the
+    /// file is reloaded when the process receives SIGHUP.
+    #[structopt(long)]
+    deny_networks: Option<PathBuf>,
+    /// Path to a file giving the group that nodes belong to by their network ID, with a
+    /// network ID and then a group on each line. Feeds can send `group:<group>` before
+    /// subscribing to a chain to only be told about the nodes in that group. Blank lines
+    /// and lines starting with '#' are ignored.
+    #[structopt(long)]
+    node_groups: Option<PathBuf>,
+    /// The group that nodes not in `--node-groups` belong to. If not given, every group
+    /// can see them.
+    #[structopt(long)]
+    untagged_node_group: Option<String>,
+    /// Path to a MaxMind GeoLite2 ASN (or compatible) database. If given, nodes are counted
+    /// by the hosting provider of their IP address in the chain stats.
+    #[structopt(long)]
+    asn_database: Option<PathBuf>,
+    /// Path to a file of the IP addresses of nodes that are expected to connect, one per line,
+    /// to locate at startup. Nodes connecting from them are then located straight away,
+    /// rather than waiting on lookups while lots of nodes connect at once. Blank lines and
+    /// lines starting with '#' are ignored, as are addresses that can't be located.
+    #[structopt(long)]
+    prewarm_locations: Option<PathBuf>,
+    /// Give up on locating a node if it takes longer than this many milliseconds. Nodes
+    /// are added straight away either way, and located once a lookup completes.
+    #[structopt(long, default_value = "5000")]
+    locate_timeout_ms: u64,
+    /// Once this many node lookups in a row have failed or been given up on, stop
+    /// locating nodes for `--locate-cooldown-secs`. 0 means never stop.
+    #[structopt(long, default_value = "10")]
+    locate_max_failures: u32,
+    /// How many seconds to stop locating nodes for once too many lookups have failed.
+    #[structopt(long, default_value = "60")]
+    locate_cooldown_secs: u64,
+    /// How many node lookups can be queued up waiting to be made. Nodes that connect while
+    /// the queue is full aren't located, which keeps memory in check during reconnect storms.
+    #[structopt(long, default_value = "10000")]
+    locate_queue_len: usize,
+    /// How many block times each chain's average block time is calculated over. If
+    /// --block-time-window-secs is given, this is just the initial size of the window.
+    #[structopt(long, default_value = "50")]
+    block_time_window: usize,
+    /// If given, once a chain's block time window has filled up, it is resized so that it
+    /// covers roughly this many seconds of blocks, whatever the chain's block time is.
+    #[structopt(long)]
+    block_time_window_secs: Option<u64>,
+    /// How many finalized block propagation times each chain's average is calculated over.
+    #[structopt(long, default_value = "50")]
+    propagation_time_window: usize,
+    /// How many seconds of messages from nodes each chain's message rate is worked out over.
+    #[structopt(long, default_value = "10")]
+    message_rate_window_secs: u64,
+    /// How many seconds of disconnects are counted to spot a mass disconnect.
+    #[structopt(long, default_value = "10")]
+    mass_disconnect_window_secs: u64,
+    /// How many block times a chain has to see, once it's created or has come back from
+    /// being stale, before it gives out an average block time. The first few give an
+    /// unreliable average. 0 gives one out straight away.
+    #[structopt(long, default_value = "0")]
+    block_time_warm_up: usize,
+    /// The most blocks that a chain's best block can advance by at once for the time it took
+    /// to count as a block time. Bigger jumps (eg from a node catching up) would give one
+    /// huge block time, so are treated as catching up instead. If not given, any advance
+    /// counts.
+    #[structopt(long)]
+    max_block_time_step: Option<u64>,
+    /// If provided, the /admin routes are enabled. Requests to them must provide this
+    /// token in an 'Authorization: Bearer <token>' header.
+    #[structopt(long)]
+    admin_token: Option<String>,
+    /// Path to a file of tokens that can be used with the /admin routes (enabling them), one
+    /// per line in the form '<token> <scope>[,<scope>..]', where each scope is one of
+    /// 'read-only', 'node-control' or 'chain-control'. Blank lines and lines starting with '#'
+    /// are ignored. On unix systems, the file is reloaded when the process receives SIGHUP.
+    /// Any '--admin-token' is allowed every scope on top of these.
+    #[structopt(long)]
+    admin_tokens: Option<PathBuf>,
+    /// Space delimited list of the genesis hashes of private chains. These are never listed
+    /// to feeds, and feeds can only subscribe to them with a token from '--feed-tokens' that
+    /// grants access to them.
+    #[structopt(long, required = false)]
+    private_chain: Vec<BlockHash>,
+    /// Path to a file of tokens that feeds can use to subscribe to private chains, one per
+    /// line in the form '<token> <genesis_hash>[,<genesis_hash>..]'. Blank lines and lines
+    /// starting with '#' are ignored. Feeds provide their token in an 'Authorization: Bearer
+    /// <token>' header or a 'token' query parameter when connecting. On unix systems, the
+    /// file is reloaded when the process receives SIGHUP.
+    #[structopt(long)]
+    feed_tokens: Option<PathBuf>,
+    /// If a chain hasn't seen a new best block for this many seconds, any of its nodes
+    /// that haven't reported a new block for this long are marked as stale.
+    #[structopt(long, default_value = "120")]
+    stale_node_secs: u64,
+    /// A stale node is only considered fresh again once it reports two blocks within this
+    /// many seconds of each other. This stops nodes flapping between stale and fresh.
+    #[structopt(long, default_value = "60")]
+    stale_node_recover_secs: u64,
+    /// What to do with nodes that stay stale; one of 'keep', to keep showing them for as
+    /// long as they're connected, or 'remove', to remove them (and stop listening to them)
+    /// once they've gone '--stale-removal-timeout' seconds without a new block.
+    #[structopt(long, default_value = "keep")]
+    stale_node_policy: StaleNodePolicy,
+    /// With '--stale-node-policy remove', how many seconds a stale node can go without
+    /// reporting a new block before it's removed.
+    #[structopt(long, default_value = "600")]
+    stale_removal_timeout: u64,
+    /// Space delimited list of labels to always use for the chains with the given genesis
+    /// hashes, in the form '<genesis_hash>=<label>', regardless of the labels that their
+    /// nodes report.
+    #[structopt(long, required = false)]
+    chain_label_override: Vec<ChainLabelOverride>,
+    /// Space delimited list of colors for frontends to show chains in, in the form
+    /// '<genesis_hash>=<color>', where colors are hex like '#e6007a'. These are given in
+    /// the /chains list and sent to feeds.
+    #[structopt(long, required = false)]
+    chain_color: Vec<ChainColor>,
+    /// Space delimited list of icons for frontends to show chains with, in the form
+    /// '<genesis_hash>=<icon>', where icons are an http(s) URL or an ID made up of letters,
+    /// digits, '-' and '_'. These are given in the /chains list and sent to feeds.
+    #[structopt(long, required = false)]
+    chain_icon: Vec<ChainIcon>,
+    /// How many of its most recent best blocks each chain keeps hold of. These are sent to
+    /// feeds when they subscribe to the chain, and are available at /recent_blocks/<genesis_hash>.
+    #[structopt(long, default_value = "20")]
+    recent_blocks: usize,
+    /// Space delimited list of chains that keep hold of a different number of recent best
+    /// blocks to '--recent-blocks', in the form '<genesis_hash>=<count>'. Chains with a short
+    /// block time need more of them to cover the same stretch of time.
+    #[structopt(long, required = false)]
+    chain_recent_blocks: Vec<ChainRecentBlocks>,
+    /// How many of its most recent average block times (one as of each new best block) each
+    /// chain keeps hold of, for charting at /chains/<genesis_hash>/block-times.
+    #[structopt(long, default_value = "300")]
+    block_time_history: usize,
+    /// How many of the nodes removed from it most recently each chain keeps track of, with
+    /// why each was removed. These are available at /admin/chain/<genesis_hash>/removed_nodes.
+    #[structopt(long, default_value = "100")]
+    removed_nodes: usize,
+    /// How many of its most recent significant events (reorgs, finality stalling and
+    /// recovering, sudden drops in node count, recoveries from stale best blocks and label
+    /// changes) each chain keeps track of. These are available at
+    /// /admin/chain/<genesis_hash>/events.
+    #[structopt(long, default_value = "100")]
+    recent_events: usize,
+    /// Nodes that report their process started less than this many minutes ago are counted
+    /// as recently restarted in the chain stats.
+    #[structopt(long, default_value = "10")]
+    recent_restart_mins: u64,
+    /// Log a warning if regenerating the stats of a chain takes longer than this many
+    /// milliseconds.
+    #[structopt(long, default_value = "50")]
+    stats_regeneration_budget_ms: u64,
+    /// Every this many minutes, the counters behind each chain's stats are recounted from
+    /// the nodes that are connected, correcting any drift.
+    #[structopt(long, default_value = "10")]
+    stats_reconcile_mins: u64,
+    /// Once more than this many messages are queued up for an aggregator, its chains
+    /// regenerate their stats only every `--stats-under-load-secs` until the queue has
+    /// drained to half of this. 0 disables this.
+    #[structopt(long, default_value = "5000")]
+    stats_backoff_queue_len: usize,
+    /// How many seconds apart chains regenerate their stats while their aggregator has
+    /// more than `--stats-backoff-queue-len` messages queued up.
+    #[structopt(long, default_value = "30")]
+    stats_under_load_secs: u64,
+    /// Feeds subscribed to a chain are alerted once its finalized block falls more than
+    /// this many blocks behind its best block, and again once it catches back up.
+    #[structopt(long, default_value = "50")]
+    finality_gap_threshold: u64,
+    /// Nodes whose finalized block is more than this many blocks behind that of their
+    /// chain are counted as lagging finality in the chain stats.
+    #[structopt(long, default_value = "10")]
+    finality_lag_threshold: u64,
+    /// Nodes whose clock is more than this many milliseconds ahead of or behind that of the
+    /// shard they connect to are counted as having clock skew in the chain stats. This is
+    /// worked out from the time nodes stamp their messages with, so includes the time those
+    /// messages take to arrive.
+    #[structopt(long, default_value = "1000")]
+    clock_skew_threshold_ms: u64,
+    /// When a node is located again (eg once its cached location expires), the new location
+    /// is ignored, and not sent to feeds, if it's less than this many metres from the old one.
+    /// 0 sends every new location.
+    #[structopt(long, default_value = "0")]
+    min_location_change_m: u64,
+    /// Nodes reporting less than this much free space on the disk that their database is
+    /// on are counted as low on disk space in the chain stats.
+    #[structopt(long, default_value = "10GiB")]
+    low_disk_threshold: ByteSize,
+    /// What nodes that don't say whether they're full nodes or light clients are counted as
+    /// in the chain stats; one of 'full', 'light' or 'unknown'.
+    #[structopt(long, default_value = "full")]
+    unreported_node_role: UnreportedNodeRole,
+    /// Space delimited list of chains that are expected to have some number of validators,
+    /// in the form '<genesis_hash>=<count>'. Feeds subscribed to one of these chains are
+    /// alerted when fewer of its nodes report a validator address than expected.
+    #[structopt(long, required = false)]
+    chain_expected_validators: Vec<ChainExpectedValidators>,
+    /// How many fewer validators than expected a chain can have before feeds are alerted.
+    #[structopt(long, default_value = "0")]
+    validator_shortfall_threshold: u64,
+    /// Space delimited list of how long blocks are expected to take on some chains, in the
+    /// form '<genesis_hash>=<ms>'. This is sent to feeds in the chain stats, to compare the
+    /// average block time against. Other chains use their long run average block time.
+    #[structopt(long, required = false)]
+    chain_expected_block_time: Vec<ChainExpectedBlockTime>,
+    /// The most hardware, stats and IO messages about its nodes that each chain sends to
+    /// feeds per second; any more are dropped. Best and finalized blocks are always sent.
+    /// 0 means that there's no limit.
+    #[structopt(long, default_value = "0")]
+    feed_rate_limit: u32,
+    /// Nodes are counted as having mid tier hardware in the chain stats if the average of
+    /// their benchmark scores is at least this percentage of our reference hardware's.
+    #[structopt(long, default_value = "50")]
+    hardware_tier_mid_score: u32,
+    /// Nodes are counted as having high tier hardware in the chain stats if the average of
+    /// their benchmark scores is at least this percentage of our reference hardware's.
+    #[structopt(long, default_value = "100")]
+    hardware_tier_high_score: u32,
+    /// How many distinct values of each chain stat (like the node version or CPU) are
+    /// counted separately. Nodes with any other values are counted as "other", keeping the
+    /// most common values. Operators are limited to 10000 and platforms to 50 unless given
+    /// a limit below.
+    #[structopt(long, default_value = "1000")]
+    stats_cardinality_limit: usize,
+    /// Space delimited list of `<stat>=<count>` limits for particular stats, overriding
+    /// '--stats-cardinality-limit'. Stats are named as they are in the chain stats.
+    #[structopt(long, required = false)]
+    stats_dimension_limit: Vec<StatsDimensionLimit>,
+    /// Nodes that have been connected for at least this many minutes are counted as
+    /// connected for a medium time in the chain stats, rather than freshly connected.
+    #[structopt(long, default_value = "60")]
+    connection_tier_medium_mins: u64,
+    /// Nodes that have been connected for at least this many minutes are counted as
+    /// connected for a long time in the chain stats.
+    #[structopt(long, default_value = "1440")]
+    connection_tier_long_mins: u64,
+    /// A regular expression that finds the operator of a node in its name, to count how
+    /// many distinct operators each chain has. The first capture group is the operator if
+    /// there is one, or else the whole match. Names that don't match are their own operator.
+    #[structopt(long, default_value = "^([^/]+)/")]
+    node_operator_pattern: NodeOperatorPattern,
+    /// Reject nodes whose operator (see '--node-operator-pattern') already has this many
+    /// nodes on the chain, so that no one operator dominates the node list. 0 disables this.
+    #[structopt(long, default_value = "0")]
+    max_nodes_per_operator: usize,
+    /// Space delimited list of operators that can have any number of nodes on a chain.
+    #[structopt(long, required = false)]
+    operator_cap_exempt: Vec<String>,
+    /// For a while after lots of nodes on a chain disconnect at once (eg because a shard
+    /// restarted), the chain lets in this percentage more nodes than its maximum so that
+    /// they can all reconnect. 0 disables this.
+    #[structopt(long, default_value = "10")]
+    quota_burst_percent: usize,
+    /// How many seconds a chain lets in extra nodes for after a mass disconnect.
+    #[structopt(long, default_value = "120")]
+    quota_burst_secs: u64,
+    /// At least this percentage of a chain's maximum number of nodes disconnecting within
+    /// '--mass-disconnect-window-secs' counts as a mass disconnect.
+    #[structopt(long, default_value = "10")]
+    mass_disconnect_percent: usize,
+    /// Alert feeds when a chain loses at least this percentage of the most nodes that it
+    /// had in the last '--node-count-drop-secs' seconds. 0 disables this.
+    #[structopt(long, default_value = "50")]
+    node_count_drop_percent: usize,
+    /// How many seconds back to look when spotting chains suddenly losing lots of nodes.
+    #[structopt(long, default_value = "30")]
+    node_count_drop_secs: u64,
+    /// Only change the label shown for a chain once another label is used by at least
+    /// this many more nodes than the one shown (counting validators as their label weight).
+    #[structopt(long, default_value = "0")]
+    label_debounce_margin: usize,
+    /// Only change the label shown for a chain once another label has been far enough
+    /// ahead for this many seconds, so that it doesn't flap between two close labels.
+    #[structopt(long, default_value = "0")]
+    label_debounce_secs: u64,
+    /// Nodes more than this many blocks behind the best block of their chain are counted
+    /// as syncing in the chain stats.
+    #[structopt(long, default_value = "10")]
+    syncing_blocks_behind: u64,
+    /// Tell feeds when more than this percentage of a chain's nodes are syncing, and again
+    /// once they're not.
+    #[structopt(long, default_value = "50")]
+    syncing_majority_percent: u64,
+    /// How much the finality gap, as a fraction of '--finality-gap-threshold', counts
+    /// towards the health score of chains. The score is 100 less the weighted average of
+    /// each signal, as a percentage of how bad it can get.
+    #[structopt(long, default_value = "3")]
+    health_weight_finality_gap: u32,
+    /// How much the fraction of nodes that are stale counts towards the health score of chains.
+    #[structopt(long, default_value = "2")]
+    health_weight_stale_nodes: u32,
+    /// How much the fraction of nodes that are syncing counts towards the health score of
+    /// chains.
+    #[structopt(long, default_value = "2")]
+    health_weight_syncing_nodes: u32,
+    /// How much the difference between the average and expected block times, as a fraction
+    /// of the expected block time, counts towards the health score of chains.
+    #[structopt(long, default_value = "2")]
+    health_weight_block_time: u32,
+    /// How much the shortfall in validators, as a fraction of those expected, counts towards
+    /// the health score of chains.
+    #[structopt(long, default_value = "1")]
+    health_weight_validator_shortfall: u32,
+    /// Nodes whose import queue keeps having at least this many blocks in it are counted
+    /// as backed up in the chain stats. 0 means that they never are.
+    #[structopt(long, default_value = "64")]
+    import_queue_backed_up_depth: u64,
+    /// Nodes that have imported less than this percentage of the blocks they've tried to
+    /// are counted as unhealthy in the chain stats, since they might be on a bad fork or
+    /// have a corrupted database. 0 means that none are.
+    #[structopt(long, default_value = "90")]
+    min_import_success_percent: u64,
+    /// How many votes towards the label of their chain nodes that have reported a validator
+    /// address get, so that validators can outvote lots of full nodes on which name is
+    /// shown. Every other node gets one vote, so 1 weights every node the same.
+    #[structopt(long, default_value = "1")]
+    validator_label_weight: usize,
+    /// The label shown for chains whose nodes report a blank one. '{hash}' is replaced by
+    /// the start of the chain's genesis hash, and '{genesis_hash}' by all of it.
+    #[structopt(long, default_value = "chain-{hash}")]
+    unlabeled_chain_format: String,
+    /// Block authors that nodes tell us about are shown as inactive in the chain stats
+    /// once they've gone this many minutes without producing a best block.
+    #[structopt(long, default_value = "60")]
+    block_author_inactive_mins: u64,
+    /// Best blocks that nodes report more than this many blocks ahead of the chain's best
+    /// block are ignored as implausible, and the node is flagged to feeds. Nodes can still
+    /// move the best block along by reporting blocks closer to it. 0 means no limit.
+    #[structopt(long, default_value = "0")]
+    max_best_block_lead: u64,
+    /// Feeds are told about the average peer count of each chain's nodes when it changes
+    /// by at least this percentage since they were last told.
+    #[structopt(long, default_value = "10")]
+    average_peers_change_percent: u64,
+    /// Tell feeds about an ID for each node, derived from a hash of its network ID, that
+    /// stays the same when it reconnects (unlike the ID that feeds otherwise know it by).
+    /// Nodes that don't report a network ID don't get one.
+    #[structopt(long)]
+    feed_stable_node_ids: bool,
+    /// Keep track of when each node was first ever seen, by its stable ID, in this file,
+    /// so that it survives restarts. Needs '--feed-stable-node-ids'.
+    #[structopt(long, parse(from_os_str), requires = "feed-stable-node-ids")]
+    first_seen_store: Option<PathBuf>,
+    /// How many nodes to remember when they were first seen. Beyond this, those that
+    /// were last seen the longest ago are forgotten.
+    #[structopt(long, default_value = "100000")]
+    first_seen_store_max_nodes: usize,
+    /// Only tell feeds about a node's bandwidth again once it moves by more than this
+    /// percentage of what they were last told. 0 tells them about every change.
+    #[structopt(long, default_value = "0")]
+    hardware_change_percent: u64,
+    /// Always tell feeds about a node's bandwidth once it moves by at least this many
+    /// bytes per second, whatever '--hardware-change-percent' is. 0 disables this.
+    #[structopt(long, default_value = "0")]
+    hardware_change_bytes: u64,
+    /// Hide chains with fewer nodes than this from feeds and the /chains list until they
+    /// get enough. First party chains (Polkadot, Kusama, Westend and Rococo) are always shown.
+    #[structopt(long, default_value = "1")]
+    min_chain_nodes: usize,
+    /// Hide chains from feeds and the /chains list until their best block reaches this
+    /// height. First party chains are always shown.
+    #[structopt(long, default_value = "0")]
+    min_chain_height: u64,
+    /// When nodes disagree about the best block during a fork, ignore those whose recent
+    /// best blocks conflict with the finalized block, rather than always taking the
+    /// highest block. This remembers the last few best blocks of every node.
+    #[structopt(long)]
+    prefer_finalized_branch: bool,
+    /// To cap how many messages feeds are sent about busy chains, only tell them about the
+    /// first this many nodes to import a block at each height. Best and finalized blocks
+    /// are always sent. 0 tells feeds about every imported block.
+    #[structopt(long, default_value = "0")]
+    imported_block_sample: usize,
+    /// Space delimited list of chains that sample imported blocks differently to
+    /// '--imported-block-sample', in the form '<genesis_hash>=<count>'.
+    #[structopt(long, required = false)]
+    chain_imported_block_sample: Vec<ChainImportedBlockSample>,
+    /// To cap how many location lookups big chains make, only locate nodes that join a
+    /// chain while it has at most this many nodes. Other nodes are tracked as usual, but
+    /// have no location. 0 locates every node.
+    #[structopt(long, default_value = "0")]
+    max_located_nodes: usize,
+    /// Space delimited list of chains that locate a different number of nodes to
+    /// '--max-located-nodes', in the form '<genesis_hash>=<count>'.
+    #[structopt(long, required = false)]
+    chain_max_located_nodes: Vec<ChainMaxLocatedNodes>,
+    /// Hold back nodes reporting a genesis hash that we have no chain for until this many
+    /// nodes report it, rather than creating a chain straight away, so that one-off bad
+    /// reports don't clutter the chain list. First party chains never wait. 1 disables this.
+    #[structopt(long, default_value = "1")]
+    new_chain_min_nodes: usize,
+    /// Also create a chain for a new genesis hash once any one node has reported it for
+    /// this many seconds, however many nodes there are. 0 disables this.
+    #[structopt(long, default_value = "0")]
+    new_chain_grace_secs: u64,
+    /// Keep a chain around for this many seconds after its last node leaves, so that its
+    /// history survives nodes briefly disconnecting. 0 removes chains straight away.
+    #[structopt(long, default_value = "30")]
+    empty_chain_ttl_secs: u64,
+    /// Write every message sent to feeds to this file, so that it can be replayed later
+    /// with '--replay'.
+    #[structopt(long, parse(from_os_str))]
+    record: Option<PathBuf>,
+    /// When recording, also subscribe to the chain with this genesis hash, so that
+    /// messages about its nodes are recorded too.
+    #[structopt(long, requires = "record")]
+    record_chain: Option<BlockHash>,
+    /// Don't accept any shards, and instead replay the feed recorded to this file with
+    /// '--record' to each feed that connects, with the original timings.
+    #[structopt(long, parse(from_os_str), conflicts_with = "record")]
+    replay: Option<PathBuf>,
+    /// Space delimited list of webhooks to POST alerts about chains to as JSON, in the form
+    /// '<event>=<url>', where the event is one of 'finality_gap', 'validator_shortfall'
+    /// or 'node_count_drop'. Give an event more than once to alert more than one URL.
+    #[structopt(long, required = false)]
+    webhook: Vec<Webhook>,
+    /// How many times to try POSTing each alert to a webhook before giving up on it.
+    #[structopt(long, default_value = "5")]
+    webhook_max_attempts: u32,
+    /// How many milliseconds to wait before trying a failed webhook again. This doubles
+    /// with each attempt.
+    #[structopt(long, default_value = "1000")]
+    webhook_backoff: u64,
+    /// Append the alerts that webhooks couldn't be sent to this file, one JSON object per
+    /// line. They're logged either way.
+    #[structopt(long, parse(from_os_str))]
+    webhook_dead_letter_log: Option<PathBuf>,
+    /// Send the same metrics that /metrics serves to a StatsD server at this 'host:port'
+    /// over UDP, as well as serving them. Nothing is sent if this isn't given.
+    #[structopt(long)]
+    statsd: Option<String>,
+    /// How many seconds to wait between sending metrics to the StatsD server.
+    #[structopt(long, default_value = "10")]
+    statsd_interval_secs: u64,
+    /// Put this, followed by a '.', in front of the name of every metric sent to StatsD.
+    #[structopt(long, default_value = "telemetry_core")]
+    statsd_prefix: String,
+    /// Which flavour of StatsD to send metrics in: 'statsd', where the values of labels like
+    /// the chain are added to the metric name, or 'dogstatsd', where they're sent as tags.
+    #[structopt(long, default_value = "statsd")]
+    statsd_format: StatsdFormat,
+    /// Publish every message sent to feeds to the NATS server at this 'host:port' too, under
+    /// '<prefix>.<genesis hash>' for messages about a chain and '<prefix>.all' for those that
+    /// every feed is sent. Messages about private chains, and about nodes in a group (see
+    /// `--node-groups`), are left out. Nothing is published if this isn't given.
+    #[structopt(long)]
+    publish_nats: Option<String>,
+    /// The prefix of the subjects that feed messages are published to NATS under.
+    #[structopt(long, default_value = "telemetry")]
+    publish_nats_prefix: String,
+    /// Space delimited list of the genesis hashes of chains to publish messages about to
+    /// NATS. Messages about every chain are published if none are given.
+    #[structopt(long, required = false)]
+    publish_nats_chain: Vec<BlockHash>,
+    /// How many messages can wait to be published to NATS (for instance while reconnecting)
+    /// before further ones are dropped. Feeds are never held up by NATS.
+    #[structopt(long, default_value = "10000")]
+    publish_nats_queue_len: usize,
+    /// Don't start the server; instead load every file given and check the options for
+    /// mistakes, listing any found and exiting with a non-zero status if there are any.
+    #[structopt(long)]
+    check_config: bool,
+    /// Don't start the server; instead print a JSON description of every type of feed
+    /// message (its action ID and fields) for those building feed clients, and exit.
+    #[structopt(long)]
+    print_feed_schema: bool,
+}
+
+/// A set of chains kept apart from every other, whose shards and feeds connect to
+/// its own address.
+#[derive(Debug, Clone)]
+struct Namespace {
+    name: Box<str>,
+    addr: SocketAddr,
+}
+
+impl FromStr for Namespace {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, addr) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<name>=<address>`"))?;
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            anyhow::bail!("Namespace names can only contain letters, digits, '-' and '_'");
+        }
+        Ok(Namespace {
+            name: name.into(),
+            addr: addr.parse()?,
+        })
+    }
+}
+
+/// A label to always use for the chain with the given genesis hash.
+#[derive(Debug, Clone)]
+struct ChainLabelOverride {
+    genesis_hash: BlockHash,
+    label: Box<str>,
+}
+
+impl FromStr for ChainLabelOverride {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, label) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<label>`"))?;
+        Ok(ChainLabelOverride {
+            genesis_hash: genesis_hash.parse()?,
+            label: label.into(),
+        })
+    }
+}
+
+/// The color that frontends should show the chain with the given genesis hash in.
+#[derive(Debug, Clone)]
+struct ChainColor {
+    genesis_hash: BlockHash,
+    color: Box<str>,
+}
+
+impl FromStr for ChainColor {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, color) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<color>`"))?;
+        let digits = color.strip_prefix('#').unwrap_or("");
+        if !matches!(digits.len(), 3 | 6) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!("Expecting a hex color like `#e6007a`, but got '{color}'");
+        }
+        Ok(ChainColor {
+            genesis_hash: genesis_hash.parse()?,
+            color: color.into(),
+        })
+    }
+}
+
+/// The icon that frontends should show the chain with the given genesis hash with.
+#[derive(Debug, Clone)]
+struct ChainIcon {
+    genesis_hash: BlockHash,
+    icon: Box<str>,
+}
+
+impl FromStr for ChainIcon {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, icon) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<icon>`"))?;
+        let is_url = (icon.starts_with("https://") || icon.starts_with("http://"))
+            && icon.chars().all(|c| c.is_ascii_graphic());
+        let is_id = !icon.is_empty()
+            && icon
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if icon.len() > 256 || !(is_url || is_id) {
+            anyhow::bail!("Expecting an http(s) URL or an ID for the icon, but got '{icon}'");
+        }
+        Ok(ChainIcon {
+            genesis_hash: genesis_hash.parse()?,
+            icon: icon.into(),
+        })
+    }
+}
+
+/// How frontends should show each chain that a color or icon is given for.
+fn chain_themes(opts: &Opts) -> HashMap<BlockHash, Theme> {
+    let mut themes: HashMap<BlockHash, Theme> = HashMap::new();
+    for o in &opts.chain_color {
+        themes.entry(o.genesis_hash).or_default().color = Some(o.color.clone());
+    }
+    for o in &opts.chain_icon {
+        themes.entry(o.genesis_hash).or_default().icon = Some(o.icon.clone());
+    }
+    themes
+}
+
+/// Whether to expose node details for the chain with the given genesis hash.
+#[derive(Debug, Clone)]
+struct ChainExposeNodeDetails {
+    genesis_hash: BlockHash,
+    expose: bool,
+}
+
+impl FromStr for ChainExposeNodeDetails {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, expose) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<true|false>`"))?;
+        Ok(ChainExposeNodeDetails {
+            genesis_hash: genesis_hash.parse()?,
+            expose: expose.parse()?,
+        })
+    }
+}
+
+/// Which node details feeds are sent for the chain with the given genesis hash.
+#[derive(Debug, Clone)]
+struct ChainNodeDetailFields {
+    genesis_hash: BlockHash,
+    fields: NodeDetailFields,
+}
+
+impl FromStr for ChainNodeDetailFields {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, fields) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<fields>`"))?;
+        Ok(ChainNodeDetailFields {
+            genesis_hash: genesis_hash.parse()?,
+            fields: fields.parse()?,
+        })
+    }
+}
+
+/// How many nodes per block height feeds are told have imported a block on the
+/// chain with the given genesis hash.
+#[derive(Debug, Clone)]
+struct ChainImportedBlockSample {
+    genesis_hash: BlockHash,
+    sample: usize,
+}
+
+impl FromStr for ChainImportedBlockSample {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, sample) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<count>`"))?;
+        Ok(ChainImportedBlockSample {
+            genesis_hash: genesis_hash.parse()?,
+            sample: sample.parse()?,
+        })
+    }
+}
+
+/// How many nodes the chain with the given genesis hash can have for those joining it
+/// to be located.
+#[derive(Debug, Clone)]
+struct ChainMaxLocatedNodes {
+    genesis_hash: BlockHash,
+    count: usize,
+}
+
+impl FromStr for ChainMaxLocatedNodes {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, count) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<count>`"))?;
+        Ok(ChainMaxLocatedNodes {
+            genesis_hash: genesis_hash.parse()?,
+            count: count.parse()?,
+        })
+    }
+}
+
+/// How many of its most recent best blocks the chain with the given genesis hash keeps
+/// hold of.
+#[derive(Debug, Clone)]
+struct ChainRecentBlocks {
+    genesis_hash: BlockHash,
+    count: usize,
+}
+
+impl FromStr for ChainRecentBlocks {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, count) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<count>`"))?;
+        Ok(ChainRecentBlocks {
+            genesis_hash: genesis_hash.parse()?,
+            count: count.parse()?,
+        })
+    }
+}
+
+/// The oldest (major, minor) client version that nodes on the chain with the given genesis
+/// hash should run.
+#[derive(Debug, Clone)]
+struct ChainMinVersion {
+    genesis_hash: BlockHash,
+    version: (u32, u32),
+}
+
+impl FromStr for ChainMinVersion {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, version) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<major>.<minor>`"))?;
+        let (major, minor) = version.split_once('.').ok_or_else(|| {
+            anyhow::anyhow!("Expecting a version like `1.2`, but got '{version}'")
+        })?;
+        Ok(ChainMinVersion {
+            genesis_hash: genesis_hash.parse()?,
+            version: (major.parse()?, minor.parse()?),
+        })
+    }
+}
+
+/// How many validators the chain with the given genesis hash is expected to have.
+#[derive(Debug, Clone)]
+struct ChainExpectedValidators {
+    genesis_hash: BlockHash,
+    count: u64,
+}
+
+impl FromStr for ChainExpectedValidators {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, count) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<count>`"))?;
+        Ok(ChainExpectedValidators {
+            genesis_hash: genesis_hash.parse()?,
+            count: count.parse()?,
+        })
+    }
+}
+
+/// How long (in ms) blocks are expected to take on the chain with the given genesis hash.
+#[derive(Debug, Clone)]
+struct ChainExpectedBlockTime {
+    genesis_hash: BlockHash,
+    ms: u64,
+}
+
+impl FromStr for ChainExpectedBlockTime {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (genesis_hash, ms) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<genesis_hash>=<ms>`"))?;
+        Ok(ChainExpectedBlockTime {
+            genesis_hash: genesis_hash.parse()?,
+            ms: ms.parse()?,
+        })
+    }
+}
+
+/// How many distinct values of the given chain stat are counted separately.
+#[derive(Debug, Clone)]
+struct StatsDimensionLimit {
+    dimension: String,
+    limit: usize,
+}
+
+impl FromStr for StatsDimensionLimit {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (dimension, limit) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<stat>=<count>`"))?;
+        if !state::CARDINALITY_DIMENSIONS.contains(&dimension) {
+            anyhow::bail!(
+                "Unknown stat '{dimension}'; expecting one of {}",
+                state::CARDINALITY_DIMENSIONS.join(", ")
+            );
+        }
+        Ok(StatsDimensionLimit {
+            dimension: dimension.to_owned(),
+            limit: limit.parse()?,
+        })
+    }
+}
+
+/// The limits on how many distinct values of each chain stat are counted separately.
+fn cardinality_limits(opts: &Opts) -> CardinalityLimits {
+    let mut limits = CardinalityLimits {
+        default: opts.stats_cardinality_limit,
+        ..CardinalityLimits::default()
+    };
+    for o in &opts.stats_dimension_limit {
+        limits.dimensions.insert(o.dimension.clone(), o.limit);
+    }
+    limits
 }
 
 fn main() {
     let opts = Opts::from_args();
 
+    if opts.print_feed_schema {
+        let schema = serde_json::to_string_pretty(&feed_message::schema())
+            .expect("feed schema should serialize");
+        println!("{schema}");
+        return;
+    }
+
+    if opts.check_config {
+        let problems = check_config(&opts);
+        if problems.is_empty() {
+            println!("Config OK");
+            return;
+        }
+        for problem in &problems {
+            eprintln!("error: {problem}");
+        }
+        eprintln!("Found {} problem(s) with the config", problems.len());
+        std::process::exit(1);
+    }
+
     SimpleLogger::new()
         .with_level(opts.log_level)
         .init()
         .expect("Must be able to start a logger");
 
-    log::info!("Starting Telemetry Core version: {}", VERSION);
+    log::info!(
+        "Starting Telemetry Core version: {} ({})",
+        VERSION,
+        GIT_HASH
+    );
 
     let worker_threads = match opts.worker_threads {
         Some(0) => num_cpus::get(),
@@ -127,95 +1100,1048 @@ fn main() {
         });
 }
 
+/// Check that each tier starts no higher than the one above it.
+fn check_tiers(opts: &Opts) -> anyhow::Result<()> {
+    if opts.hardware_tier_mid_score > opts.hardware_tier_high_score {
+        anyhow::bail!("--hardware-tier-mid-score can't be higher than --hardware-tier-high-score");
+    }
+    if opts.connection_tier_medium_mins > opts.connection_tier_long_mins {
+        anyhow::bail!(
+            "--connection-tier-medium-mins can't be higher than --connection-tier-long-mins"
+        );
+    }
+    Ok(())
+}
+
+/// How much history each of the rolling metrics that chains keep is worked out over.
+fn rolling_windows(opts: &Opts) -> RollingWindows {
+    RollingWindows {
+        block_times: BlockTimeWindow {
+            size: opts.block_time_window,
+            duration: opts.block_time_window_secs.map(Duration::from_secs),
+        },
+        propagation_times: opts.propagation_time_window,
+        message_rate: Duration::from_secs(opts.message_rate_window_secs),
+        mass_disconnect: Duration::from_secs(opts.mass_disconnect_window_secs),
+        node_count_drop: Duration::from_secs(opts.node_count_drop_secs),
+    }
+}
+
+/// Check that no chain is made to keep hold of more recent blocks than we allow.
+fn check_recent_blocks(opts: &Opts) -> anyhow::Result<()> {
+    if opts.recent_blocks > MAX_RECENT_BLOCKS {
+        anyhow::bail!("--recent-blocks can't be more than {MAX_RECENT_BLOCKS}");
+    }
+    if let Some(o) = opts
+        .chain_recent_blocks
+        .iter()
+        .find(|o| o.count > MAX_RECENT_BLOCKS)
+    {
+        anyhow::bail!(
+            "--chain-recent-blocks can't be more than {MAX_RECENT_BLOCKS}, but is {} for {:?}",
+            o.count,
+            o.genesis_hash
+        );
+    }
+    Ok(())
+}
+
+/// Load every file given in the options, the same way that the server does when it
+/// starts, and look for options that contradict each other. Returns each problem found.
+fn check_config(opts: &Opts) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut check = |what: &str, res: anyhow::Result<()>| {
+        if let Err(e) = res {
+            problems.push(format!("{what}: {e}"));
+        }
+    };
+
+    check("tiers", check_tiers(opts));
+    check("recent blocks", check_recent_blocks(opts));
+    check("rolling windows", rolling_windows(opts).check());
+    if let Some(path) = &opts.node_name_blocklist {
+        check(
+            "--node-name-blocklist",
+            NodeNameBlocklist::from_file(path).map(drop),
+        );
+    }
+    if let Some(path) = &opts.deny_networks {
+        check(
+            "--deny-networks",
+            NetworkDenylist::from_file(path).map(drop),
+        );
+    }
+    if let Some(path) = &opts.node_groups {
+        check("--node-groups", NodeGroups::from_file(path).map(drop));
+    }
+    if let Some(path) = &opts.first_seen_store {
+        check(
+            "--first-seen-store",
+            FirstSeenStore::load(path, opts.first_seen_store_max_nodes).map(drop),
+        );
+    }
+    if let Some(path) = &opts.asn_database {
+        check("--asn-database", AsnDatabase::from_file(path).map(drop));
+    }
+    if let Some(path) = &opts.prewarm_locations {
+        check("--prewarm-locations", read_prewarm_ips(path).map(drop));
+    }
+    if let Some(path) = &opts.admin_tokens {
+        check(
+            "--admin-tokens",
+            load_admin_tokens(Some(path), opts.admin_token.as_deref()).map(drop),
+        );
+    }
+    if let Some(path) = &opts.feed_tokens {
+        check(
+            "--feed-tokens",
+            FeedTokens::from_file(path, opts.private_chain.iter().copied().collect()).map(drop),
+        );
+    }
+    if let Some(path) = &opts.replay {
+        check("--replay", feed_recording::read_recording(path).map(drop));
+    }
+    check(
+        "listen addresses",
+        listeners(opts.socket, opts.shard_listen, opts.feed_listen).and_then(|listeners| {
+            check_namespaces(
+                &opts.namespaces,
+                &listeners,
+                &default_namespace_only_opts(opts),
+            )
+        }),
+    );
+
+    // Giving the same chain more than once is fine, but not with different values:
+    check(
+        "--chain-label-override",
+        check_no_conflicts(
+            opts.chain_label_override
+                .iter()
+                .map(|o| (o.genesis_hash, &o.label)),
+        ),
+    );
+    check(
+        "--chain-color",
+        check_no_conflicts(opts.chain_color.iter().map(|o| (o.genesis_hash, &o.color))),
+    );
+    check(
+        "--chain-icon",
+        check_no_conflicts(opts.chain_icon.iter().map(|o| (o.genesis_hash, &o.icon))),
+    );
+    check(
+        "--chain-expose-node-details",
+        check_no_conflicts(
+            opts.chain_expose_node_details
+                .iter()
+                .map(|o| (o.genesis_hash, o.expose)),
+        ),
+    );
+    check(
+        "--chain-node-detail-fields",
+        check_no_conflicts(
+            opts.chain_node_detail_fields
+                .iter()
+                .map(|o| (o.genesis_hash, o.fields)),
+        ),
+    );
+    check(
+        "--chain-expected-validators",
+        check_no_conflicts(
+            opts.chain_expected_validators
+                .iter()
+                .map(|o| (o.genesis_hash, o.count)),
+        ),
+    );
+    check(
+        "--chain-expected-block-time",
+        check_no_conflicts(
+            opts.chain_expected_block_time
+                .iter()
+                .map(|o| (o.genesis_hash, o.ms)),
+        ),
+    );
+    check(
+        "--chain-imported-block-sample",
+        check_no_conflicts(
+            opts.chain_imported_block_sample
+                .iter()
+                .map(|o| (o.genesis_hash, o.sample)),
+        ),
+    );
+    check(
+        "--chain-min-version",
+        check_no_conflicts(
+            opts.chain_min_version
+                .iter()
+                .map(|o| (o.genesis_hash, o.version)),
+        ),
+    );
+    check(
+        "--chain-recent-blocks",
+        check_no_conflicts(
+            opts.chain_recent_blocks
+                .iter()
+                .map(|o| (o.genesis_hash, o.count)),
+        ),
+    );
+    check(
+        "--chain-max-located-nodes",
+        check_no_conflicts(
+            opts.chain_max_located_nodes
+                .iter()
+                .map(|o| (o.genesis_hash, o.count)),
+        ),
+    );
+
+    problems
+}
+
+/// Check that no genesis hash is given more than one different value.
+fn check_no_conflicts<V: PartialEq + Debug>(
+    values: impl IntoIterator<Item = (BlockHash, V)>,
+) -> anyhow::Result<()> {
+    let mut seen = HashMap::new();
+    for (genesis_hash, value) in values {
+        match seen.entry(genesis_hash) {
+            Entry::Occupied(e) if *e.get() != value => {
+                anyhow::bail!("{genesis_hash:?} is given both {:?} and {value:?}", e.get())
+            }
+            Entry::Occupied(_) => {}
+            Entry::Vacant(e) => {
+                e.insert(value);
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Declare our routes and start the server.
 async fn start_server(num_aggregators: usize, opts: Opts) -> anyhow::Result<()> {
+    if let Some(path) = &opts.replay {
+        let feed_addr = opts.feed_listen.unwrap_or(opts.socket);
+        return start_replay_server(feed_addr, path).await;
+    }
+
     let aggregator_queue_len = opts.aggregator_queue_len.unwrap_or(10_000);
-    let aggregator = AggregatorSet::spawn(
-        num_aggregators,
-        AggregatorOpts {
-            max_queue_len: aggregator_queue_len,
-            denylist: opts.denylist,
-            max_third_party_nodes: opts.max_third_party_nodes,
-            expose_node_details: opts.expose_node_details,
+    check_tiers(&opts)?;
+    check_recent_blocks(&opts)?;
+    let listeners = listeners(opts.socket, opts.shard_listen, opts.feed_listen)?;
+    check_namespaces(
+        &opts.namespaces,
+        &listeners,
+        &default_namespace_only_opts(&opts),
+    )?;
+    let rolling_windows = rolling_windows(&opts);
+    rolling_windows.check()?;
+    let cardinality_limits = cardinality_limits(&opts);
+    let chain_themes = chain_themes(&opts);
+    let node_name_blocklist = match &opts.node_name_blocklist {
+        Some(path) => NodeNameBlocklist::from_file(path)?,
+        None => NodeNameBlocklist::default(),
+    };
+    let network_denylist = match &opts.deny_networks {
+        Some(path) => NetworkDenylist::from_file(path)?,
+        None => NetworkDenylist::default(),
+    };
+    let mut node_groups = match &opts.node_groups {
+        Some(path) => NodeGroups::from_file(path)?,
+        None => NodeGroups::default(),
+    };
+    node_groups.set_untagged_group(opts.untagged_node_group.map(Into::into));
+    let first_seen_store = match &opts.first_seen_store {
+        Some(path) => {
+            let store = FirstSeenStore::load(path, opts.first_seen_store_max_nodes)?;
+            log::info!(
+                "Loaded when {} nodes were first seen from {path:?}",
+                store.len()
+            );
+            let store = Arc::new(parking_lot::Mutex::new(store));
+            spawn_first_seen_saver(path.clone(), Arc::clone(&store));
+            Some(store)
+        }
+        None => None,
+    };
+    let asn_database = opts
+        .asn_database
+        .as_deref()
+        .map(AsnDatabase::from_file)
+        .transpose()?;
+    let prewarm_ips = match &opts.prewarm_locations {
+        Some(path) => read_prewarm_ips(path)?,
+        None => Vec::new(),
+    };
+    let tx_to_webhooks = webhooks::spawn_webhook_sender(WebhookOpts {
+        webhooks: opts.webhook,
+        max_attempts: opts.webhook_max_attempts.max(1),
+        initial_backoff: Duration::from_millis(opts.webhook_backoff),
+        dead_letter_log: opts.webhook_dead_letter_log,
+    });
+    let broker = opts.publish_nats.map(|addr| {
+        broker::spawn_broker_publisher(BrokerOpts {
+            addr,
+            subject_prefix: opts.publish_nats_prefix,
+            chains: opts.publish_nats_chain.into_iter().collect(),
+            queue_len: opts.publish_nats_queue_len,
+        })
+    });
+    let aggregator_opts = AggregatorOpts {
+        max_queue_len: aggregator_queue_len,
+        denylist: opts.denylist,
+        max_third_party_nodes: opts.max_third_party_nodes,
+        max_unidentified_nodes: opts.max_unidentified_nodes,
+        expose_node_details: opts.expose_node_details,
+        node_name_blocklist: Arc::new(node_name_blocklist),
+        blocked_node_name_action: opts.blocked_node_name_action,
+        duplicate_node_policy: opts.duplicate_node_policy,
+        reconnect_policy: opts.reconnect_policy,
+        flag_name_collisions: opts.flag_name_collisions,
+        chain_min_versions: opts
+            .chain_min_version
+            .into_iter()
+            .map(|o| (o.genesis_hash, o.version))
+            .collect(),
+        outdated_version_policy: opts.outdated_version_policy,
+        unparseable_version_policy: opts.unparseable_version_policy,
+        network_denylist: Arc::new(network_denylist),
+        node_groups: Arc::new(node_groups),
+        asn_database,
+        locator_limits: LocatorLimits {
+            timeout: Duration::from_millis(opts.locate_timeout_ms),
+            max_failures: opts.locate_max_failures,
+            cooldown: Duration::from_secs(opts.locate_cooldown_secs),
+            queue_len: opts.locate_queue_len,
+        },
+        prewarm_ips: prewarm_ips.into(),
+        rolling_windows,
+        block_time_warm_up: opts.block_time_warm_up,
+        max_block_time_step: opts.max_block_time_step,
+        stale_timeouts: StaleTimeouts {
+            stale: opts.stale_node_secs * 1000,
+            recover: opts.stale_node_recover_secs * 1000,
+            policy: opts.stale_node_policy,
+            removal: opts.stale_removal_timeout * 1000,
+        },
+        chain_label_overrides: opts
+            .chain_label_override
+            .into_iter()
+            .map(|o| (o.genesis_hash, o.label))
+            .collect(),
+        chain_themes,
+        chain_expose_node_details: opts
+            .chain_expose_node_details
+            .into_iter()
+            .map(|o| (o.genesis_hash, o.expose))
+            .collect(),
+        node_detail_fields: opts.node_detail_fields.unwrap_or_default(),
+        chain_node_detail_fields: opts
+            .chain_node_detail_fields
+            .into_iter()
+            .map(|o| (o.genesis_hash, o.fields))
+            .collect(),
+        recent_blocks_len: opts.recent_blocks,
+        chain_recent_blocks_len: opts
+            .chain_recent_blocks
+            .into_iter()
+            .map(|o| (o.genesis_hash, o.count))
+            .collect(),
+        block_time_history_len: opts.block_time_history,
+        removed_nodes_len: opts.removed_nodes,
+        recent_events_len: opts.recent_events,
+        recent_restart_window: opts.recent_restart_mins * 60 * 1000,
+        stats_timings: StatsTimings {
+            regeneration_budget: Duration::from_millis(opts.stats_regeneration_budget_ms),
+            reconcile_interval: Duration::from_secs(opts.stats_reconcile_mins * 60),
+            under_load_interval: Duration::from_secs(opts.stats_under_load_secs),
+        },
+        finality_gap_threshold: opts.finality_gap_threshold,
+        finality_lag_threshold: opts.finality_lag_threshold,
+        clock_skew_threshold: opts.clock_skew_threshold_ms,
+        min_location_change: opts.min_location_change_m,
+        low_disk_threshold: opts.low_disk_threshold.num_bytes() as u64,
+        unreported_node_role: opts.unreported_node_role,
+        hardware_tiers: HardwareTiers {
+            mid: opts.hardware_tier_mid_score,
+            high: opts.hardware_tier_high_score,
+        },
+        cardinality_limits,
+        connection_tiers: ConnectionTiers {
+            medium: opts.connection_tier_medium_mins * 60 * 1000,
+            long: opts.connection_tier_long_mins * 60 * 1000,
+        },
+        operator_pattern: opts.node_operator_pattern,
+        operator_cap: OperatorCap {
+            max_nodes: opts.max_nodes_per_operator,
+            exempt: Arc::new(
+                opts.operator_cap_exempt
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+            ),
+        },
+        quota_burst: QuotaBurst {
+            allowance_percent: opts.quota_burst_percent,
+            window: Duration::from_secs(opts.quota_burst_secs),
+            mass_disconnect_percent: opts.mass_disconnect_percent,
         },
-    )
-    .await?;
-    let socket_addr = opts.socket;
+        node_count_drops: NodeCountDrops {
+            percent: opts.node_count_drop_percent,
+        },
+        label_debounce: LabelDebounce {
+            margin: opts.label_debounce_margin,
+            window: Duration::from_secs(opts.label_debounce_secs),
+        },
+        syncing_majority: SyncingMajority {
+            blocks_behind: opts.syncing_blocks_behind,
+            percent: opts.syncing_majority_percent,
+        },
+        health_weights: HealthWeights {
+            finality_gap: opts.health_weight_finality_gap,
+            stale_nodes: opts.health_weight_stale_nodes,
+            syncing_nodes: opts.health_weight_syncing_nodes,
+            block_time: opts.health_weight_block_time,
+            validator_shortfall: opts.health_weight_validator_shortfall,
+        },
+        chain_expected_validators: opts
+            .chain_expected_validators
+            .into_iter()
+            .map(|o| (o.genesis_hash, o.count))
+            .collect(),
+        private_chains: opts.private_chain.iter().copied().collect(),
+        chain_expected_block_times: opts
+            .chain_expected_block_time
+            .into_iter()
+            .map(|o| (o.genesis_hash, o.ms))
+            .collect(),
+        validator_shortfall_threshold: opts.validator_shortfall_threshold,
+        feed_rate_limit: opts.feed_rate_limit,
+        import_queue_backed_up_depth: opts.import_queue_backed_up_depth,
+        min_import_success_percent: opts.min_import_success_percent,
+        validator_label_weight: opts.validator_label_weight,
+        stable_node_ids: opts.feed_stable_node_ids,
+        first_seen_store,
+        unlabeled_chain_format: opts.unlabeled_chain_format.into(),
+        block_author_inactive_after: opts.block_author_inactive_mins * 60 * 1000,
+        max_best_block_lead: opts.max_best_block_lead,
+        average_peers_change_percent: opts.average_peers_change_percent,
+        hardware_changes: HardwareChanges {
+            percent: opts.hardware_change_percent,
+            bytes_per_second: opts.hardware_change_bytes,
+        },
+        min_chain_nodes: opts.min_chain_nodes,
+        min_chain_height: opts.min_chain_height,
+        prefer_finalized_branch: opts.prefer_finalized_branch,
+        imported_block_sample: opts.imported_block_sample,
+        chain_imported_block_sample: opts
+            .chain_imported_block_sample
+            .into_iter()
+            .map(|o| (o.genesis_hash, o.sample))
+            .collect(),
+        max_located_nodes: opts.max_located_nodes,
+        chain_max_located_nodes: opts
+            .chain_max_located_nodes
+            .into_iter()
+            .map(|o| (o.genesis_hash, o.count))
+            .collect(),
+        new_chain_grace: NewChainGrace {
+            min_nodes: opts.new_chain_min_nodes,
+            min_duration: Duration::from_secs(opts.new_chain_grace_secs),
+        },
+        empty_chain_ttl: Duration::from_secs(opts.empty_chain_ttl_secs),
+        tx_to_webhooks,
+        broker,
+        stats_backoff_queue_len: opts.stats_backoff_queue_len,
+    };
+    // Every namespace has feed connection limits of its own:
+    let feed_connection_limits = || {
+        ConnectionLimits::new(
+            opts.max_feed_connections_per_ip.unwrap_or(usize::MAX),
+            opts.max_feed_subscribers.unwrap_or(usize::MAX),
+            opts.connection_limit_exempt_ip.iter().copied(),
+        )
+    };
+    let mut namespaced_listeners = Vec::new();
+    for namespace in &opts.namespaces {
+        let aggregator = AggregatorSet::spawn(num_aggregators, aggregator_opts.clone()).await?;
+        log::info!(
+            "Serving namespace '{}' on {}",
+            namespace.name,
+            namespace.addr
+        );
+        namespaced_listeners.push((
+            namespace.addr,
+            Routes::All,
+            aggregator,
+            feed_connection_limits(),
+        ));
+    }
+    let aggregator = AggregatorSet::spawn(num_aggregators, aggregator_opts).await?;
+
+    if let Some(path) = opts.record {
+        feed_recording::spawn_feed_recorder(path, opts.record_chain, aggregator.clone()).await?;
+    }
+
+    if let Some(addr) = opts.statsd {
+        let statsd_opts = StatsdOpts {
+            addr,
+            prefix: opts.statsd_prefix,
+            format: opts.statsd_format,
+            interval: Duration::from_secs(opts.statsd_interval_secs.max(1)),
+        };
+        statsd::spawn_statsd_sender(statsd_opts, aggregator.clone()).await?;
+    }
+
+    // Every namespace shares the same blocklist and denylist:
+    #[cfg(unix)]
+    let aggregators: Vec<_> = std::iter::once(aggregator.clone())
+        .chain(namespaced_listeners.iter().map(|(_, _, a, _)| a.clone()))
+        .collect();
+    #[cfg(unix)]
+    if let Some(path) = opts.node_name_blocklist {
+        spawn_node_name_blocklist_reloader(path, aggregators.clone())?;
+    }
+    #[cfg(unix)]
+    if let Some(path) = opts.deny_networks {
+        spawn_network_denylist_reloader(path, aggregators)?;
+    }
+
+    let started_unix_ms = common::time::now();
     let feed_timeout = opts.feed_timeout;
+    let feed_keepalive = Duration::from_secs(opts.feed_keepalive_secs);
+    let feed_coalescing = (opts.feed_coalesce_ms > 0).then(|| FeedCoalescing {
+        window: Duration::from_millis(opts.feed_coalesce_ms),
+        max_bytes: opts.feed_coalesce_max_bytes,
+    });
+    let feed_compression = FeedCompression {
+        all: opts.feed_compression,
+        snapshots: opts.snapshot_compression,
+    };
+    let feed_connection_limits = feed_connection_limits();
+    let admin_tokens = match (&opts.admin_tokens, opts.admin_token) {
+        (None, None) => None,
+        (path, admin_token) => {
+            let tokens = Arc::new(RwLock::new(load_admin_tokens(
+                path.as_deref(),
+                admin_token.as_deref(),
+            )?));
+            #[cfg(unix)]
+            if let Some(path) = path {
+                spawn_admin_tokens_reloader(path.clone(), admin_token, tokens.clone())?;
+            }
+            Some(tokens)
+        }
+    };
+    let private_chains: std::collections::HashSet<_> = opts.private_chain.into_iter().collect();
+    let feed_tokens = Arc::new(RwLock::new(match &opts.feed_tokens {
+        Some(path) => FeedTokens::from_file(path, private_chains.clone())?,
+        None => FeedTokens::new(private_chains.clone()),
+    }));
+    #[cfg(unix)]
+    if let Some(path) = opts.feed_tokens {
+        spawn_feed_tokens_reloader(path, private_chains, feed_tokens.clone())?;
+    }
 
-    let server = http_utils::start_server(socket_addr, move |addr, req| {
-        let aggregator = aggregator.clone();
-        async move {
-            match (req.method(), req.uri().path().trim_end_matches('/')) {
-                // Check that the server is up and running:
-                (&Method::GET, "/health") => Ok(Response::new("OK".into())),
-                // Subscribe to feed messages:
-                (&Method::GET, "/feed") => {
-                    log::info!("Opening /feed connection from {:?}", addr);
-                    Ok(http_utils::upgrade_to_websocket(
-                        req,
-                        move |ws_send, ws_recv| async move {
+    let handler = move |routes: Routes,
+                        aggregator: AggregatorSet,
+                        feed_connection_limits: ConnectionLimits| {
+        let admin_tokens = admin_tokens.clone();
+        let feed_tokens = feed_tokens.clone();
+        move |addr: SocketAddr, req: Request<Body>| {
+            let aggregator = aggregator.clone();
+            let admin_tokens = admin_tokens.clone();
+            let feed_tokens = feed_tokens.clone();
+            let feed_connection_limits = feed_connection_limits.clone();
+            async move {
+                match (req.method(), req.uri().path().trim_end_matches('/')) {
+                    // Check that the server is up and running:
+                    (&Method::GET, "/health") => Ok(Response::new("OK".into())),
+                    // Shards can only connect to listeners that serve them, and feeds et al the same:
+                    (_, "/shard_submit") if !routes.serves_shards() => {
+                        Ok(text_response(404, "Not found"))
+                    }
+                    (_, path) if path != "/shard_submit" && !routes.serves_feeds() => {
+                        Ok(text_response(404, "Not found"))
+                    }
+                    // Subscribe to feed messages:
+                    (&Method::GET, "/feed") => {
+                        let protocol =
+                            match FeedProtocol::negotiate(&http_utils::requested_protocols(&req)) {
+                                Ok(protocol) => protocol,
+                                Err(e) => return Ok(text_response(400, e.to_string())),
+                            };
+                        // Behind a proxy, every feed would otherwise share its IP:
+                        let (real_addr, _) = real_ip::real_ip(addr, req.headers());
+                        let open_connection = match feed_connection_limits.open(real_addr) {
+                            Ok(open_connection) => open_connection,
+                            Err(refused) => {
+                                log::info!(
+                                    "Refusing /feed connection from {:?}; {:?}",
+                                    real_addr,
+                                    refused
+                                );
+                                return Ok(connection_refused_response(refused));
+                            }
+                        };
+                        let feed_token = feed_token(&req);
+                        log::info!("Opening /feed connection from {:?}", addr);
+                        Ok(http_utils::upgrade_to_compressible_websocket(
+                            req,
+                            protocol.map(FeedProtocol::name),
+                            feed_compression.all || feed_compression.snapshots,
+                            move |ws_send, ws_recv, ws_compression| async move {
+                                // Counts against the limits until the connection closes:
+                                let _open_connection = open_connection;
+                                let (feed_id, tx_to_aggregator) = aggregator.subscribe_feed();
+                                let (mut tx_to_aggregator, mut ws_send) =
+                                    handle_feed_websocket_connection(
+                                        ws_send,
+                                        ws_recv,
+                                        tx_to_aggregator,
+                                        feed_timeout,
+                                        feed_keepalive,
+                                        feed_coalescing,
+                                        ws_compression.map(|ws| (ws, feed_compression)),
+                                        protocol.unwrap_or_default(),
+                                        FeedAccess {
+                                            tokens: feed_tokens,
+                                            token: feed_token,
+                                        },
+                                        feed_id,
+                                    )
+                                    .await;
+                                log::info!("Closing /feed connection from {:?}", addr);
+                                // Tell the aggregator that this connection has closed, so it can tidy up.
+                                let _ =
+                                    tx_to_aggregator.send(FromFeedWebsocket::Disconnected).await;
+                                let _ = ws_send.close().await;
+                            },
+                        ))
+                    }
+                    // Subscribe to feed messages as server-sent events, eg from curl:
+                    (&Method::GET, "/sse") => {
+                        let commands =
+                            match feed_sse::parse_query(req.uri().query().unwrap_or_default()) {
+                                Ok(commands) => commands,
+                                Err(e) => return Ok(text_response(400, e)),
+                            };
+                        let feed_token = feed_token(&req);
+                        let forbidden_chain = commands.iter().any(|cmd| {
+                            matches!(cmd, FromFeedWebsocket::Subscribe { chain, .. }
+                                if !feed_tokens.read().can_subscribe(feed_token.as_deref(), chain))
+                        });
+                        if forbidden_chain {
+                            return Ok(text_response(403, "Forbidden: missing or unknown token"));
+                        }
+                        let (real_addr, _) = real_ip::real_ip(addr, req.headers());
+                        let open_connection = match feed_connection_limits.open(real_addr) {
+                            Ok(open_connection) => open_connection,
+                            Err(refused) => {
+                                log::info!(
+                                    "Refusing /sse connection from {:?}; {:?}",
+                                    real_addr,
+                                    refused
+                                );
+                                return Ok(connection_refused_response(refused));
+                            }
+                        };
+                        log::info!("Opening /sse connection from {:?}", addr);
+                        let (body_tx, body) = Body::channel();
+                        tokio::spawn(async move {
+                            // Counts against the limits until the connection closes:
+                            let _open_connection = open_connection;
                             let (feed_id, tx_to_aggregator) = aggregator.subscribe_feed();
-                            let (mut tx_to_aggregator, mut ws_send) =
-                                handle_feed_websocket_connection(
-                                    ws_send,
-                                    ws_recv,
-                                    tx_to_aggregator,
-                                    feed_timeout,
-                                    feed_id,
-                                )
-                                .await;
-                            log::info!("Closing /feed connection from {:?}", addr);
+                            let mut tx_to_aggregator = feed_sse::handle_connection(
+                                body_tx,
+                                tx_to_aggregator,
+                                commands,
+                                feed_timeout,
+                                feed_keepalive,
+                                feed_id,
+                            )
+                            .await;
+                            log::info!("Closing /sse connection from {:?}", addr);
                             // Tell the aggregator that this connection has closed, so it can tidy up.
                             let _ = tx_to_aggregator.send(FromFeedWebsocket::Disconnected).await;
-                            let _ = ws_send.close().await;
-                        },
-                    ))
-                }
-                // Subscribe to shard messages:
-                (&Method::GET, "/shard_submit") => {
-                    Ok(http_utils::upgrade_to_websocket(
-                        req,
-                        move |ws_send, ws_recv| async move {
-                            log::info!("Opening /shard_submit connection from {:?}", addr);
-                            let tx_to_aggregator = aggregator.subscribe_shard();
-                            let (mut tx_to_aggregator, mut ws_send) =
-                                handle_shard_websocket_connection(
-                                    ws_send,
-                                    ws_recv,
-                                    tx_to_aggregator,
-                                )
-                                .await;
-                            log::info!("Closing /shard_submit connection from {:?}", addr);
-                            // Tell the aggregator that this connection has closed, so it can tidy up.
-                            let _ = tx_to_aggregator
-                                .send(FromShardWebsocket::Disconnected)
-                                .await;
-                            let _ = ws_send.close().await;
-                        },
-                    ))
+                        });
+                        Ok(Response::builder()
+                            .header(http::header::CONTENT_TYPE, "text/event-stream")
+                            .header(http::header::CACHE_CONTROL, "no-cache")
+                            .body(body)
+                            .unwrap())
+                    }
+                    // Subscribe to shard messages:
+                    (&Method::GET, "/shard_submit") => {
+                        Ok(http_utils::upgrade_to_websocket(
+                            req,
+                            move |ws_send, ws_recv| async move {
+                                log::info!("Opening /shard_submit connection from {:?}", addr);
+                                let tx_to_aggregator = aggregator.subscribe_shard();
+                                let (mut tx_to_aggregator, mut ws_send) =
+                                    handle_shard_websocket_connection(
+                                        addr,
+                                        ws_send,
+                                        ws_recv,
+                                        tx_to_aggregator,
+                                    )
+                                    .await;
+                                log::info!("Closing /shard_submit connection from {:?}", addr);
+                                // Tell the aggregator that this connection has closed, so it can tidy up.
+                                let _ = tx_to_aggregator
+                                    .send(FromShardWebsocket::Disconnected)
+                                    .await;
+                                let _ = ws_send.close().await;
+                            },
+                        ))
+                    }
+                    // Return metrics in a prometheus-friendly text based format:
+                    // (or OpenMetrics, if the scraper asks for it):
+                    (&Method::GET, "/metrics") => {
+                        let accept = req
+                            .headers()
+                            .get(http::header::ACCEPT)
+                            .and_then(|v| v.to_str().ok());
+                        let format = MetricsFormat::from_accept_header(accept);
+                        Ok(
+                            return_prometheus_metrics(aggregator, &feed_connection_limits, format)
+                                .await,
+                        )
+                    }
+                    // Every chain, and which parachains belong to which relay chains:
+                    (&Method::GET, "/chains") => Ok(return_chains(aggregator).await),
+                    // The version, uptime and connection counts of this core:
+                    (&Method::GET, "/info") => Ok(return_info(aggregator, started_unix_ms).await),
+                    // Everything about a chain and its nodes, in one go:
+                    (&Method::GET, path)
+                        if path.starts_with("/chains/") && path.ends_with("/export") =>
+                    {
+                        let access = FeedAccess {
+                            tokens: feed_tokens,
+                            token: feed_token(&req),
+                        };
+                        Ok(return_chain_export(
+                            &req,
+                            path,
+                            aggregator,
+                            admin_tokens.as_deref(),
+                            &access,
+                        )
+                        .await)
+                    }
+                    // The recent average block times of a chain, for charting:
+                    (&Method::GET, path)
+                        if path.starts_with("/chains/") && path.ends_with("/block-times") =>
+                    {
+                        let access = FeedAccess {
+                            tokens: feed_tokens,
+                            token: feed_token(&req),
+                        };
+                        Ok(return_block_time_history(path, aggregator, &access).await)
+                    }
+                    // Search the nodes on a chain:
+                    (&Method::GET, path) if path.starts_with("/chains/") => {
+                        let access = FeedAccess {
+                            tokens: feed_tokens,
+                            token: feed_token(&req),
+                        };
+                        Ok(return_nodes(path, req.uri().query(), aggregator, &access).await)
+                    }
+                    // The most recent best blocks of a chain:
+                    (&Method::GET, path) if path.starts_with("/recent_blocks/") => {
+                        let access = FeedAccess {
+                            tokens: feed_tokens,
+                            token: feed_token(&req),
+                        };
+                        Ok(
+                            return_recent_blocks(path, req.uri().query(), aggregator, &access)
+                                .await,
+                        )
+                    }
+                    // Debugging information, if any admin tokens were provided:
+                    (_, path) if path.starts_with("/admin/") => {
+                        Ok(
+                            return_admin_response(&req, path, aggregator, admin_tokens.as_deref())
+                                .await,
+                        )
+                    }
+                    // 404 for anything else:
+                    _ => Ok(Response::builder()
+                        .status(404)
+                        .body("Not found".into())
+                        .unwrap()),
                 }
-                // Return metrics in a prometheus-friendly text based format:
-                (&Method::GET, "/metrics") => Ok(return_prometheus_metrics(aggregator).await),
-                // 404 for anything else:
-                _ => Ok(Response::builder()
-                    .status(404)
-                    .body("Not found".into())
-                    .unwrap()),
             }
         }
-    });
+    };
 
-    server.await?;
+    let servers = listeners
+        .into_iter()
+        .map(|(addr, routes)| {
+            (
+                addr,
+                routes,
+                aggregator.clone(),
+                feed_connection_limits.clone(),
+            )
+        })
+        .chain(namespaced_listeners)
+        .map(|(addr, routes, aggregator, feed_connection_limits)| {
+            http_utils::start_server(addr, handler(routes, aggregator, feed_connection_limits))
+        });
+    futures::future::try_join_all(servers).await?;
     Ok(())
 }
 
-/// This handles messages coming to/from a shard connection
-async fn handle_shard_websocket_connection<S>(
-    mut ws_send: http_utils::WsSender,
-    mut ws_recv: http_utils::WsReceiver,
-    mut tx_to_aggregator: S,
-) -> (S, http_utils::WsSender)
+/// Serve a recorded feed to each feed that connects, rather than anything live.
+async fn start_replay_server(addr: SocketAddr, path: &std::path::Path) -> anyhow::Result<()> {
+    let recording = Arc::new(feed_recording::read_recording(path)?);
+    log::info!(
+        "Replaying {} recorded feed messages from {path:?}",
+        recording.len()
+    );
+
+    http_utils::start_server(addr, move |addr, req| {
+        let recording = recording.clone();
+        async move {
+            match (req.method(), req.uri().path().trim_end_matches('/')) {
+                (&Method::GET, "/health") => Ok(Response::new("OK".into())),
+                (&Method::GET, "/feed") => {
+                    // Recordings are of what v1 feeds were sent:
+                    let protocol =
+                        match FeedProtocol::negotiate(&http_utils::requested_protocols(&req)) {
+                            Ok(protocol) => protocol,
+                            Err(e) => return Ok(text_response(400, e.to_string())),
+                        };
+                    log::info!("Replaying feed to {:?}", addr);
+                    Ok(http_utils::upgrade_to_websocket_with_protocol(
+                        req,
+                        protocol.map(FeedProtocol::name),
+                        move |ws_send, mut ws_recv| async move {
+                            // Nothing the feed says changes what we send, but we still need to
+                            // notice if it goes away:
+                            let recv = async {
+                                let mut bytes = Vec::new();
+                                while ws_recv.receive_data(&mut bytes).await.is_ok() {
+                                    bytes.clear();
+                                }
+                            };
+                            tokio::select! {
+                                res = feed_recording::replay_to_feed(recording, ws_send) => {
+                                    if let Err(e) = res {
+                                        log::debug!("Error replaying feed to {:?}: {e}", addr);
+                                    }
+                                }
+                                _ = recv => {}
+                            }
+                            log::info!("Closing replayed /feed connection from {:?}", addr);
+                        },
+                    ))
+                }
+                _ => Ok(text_response(404, "Not found")),
+            }
+        }
+    })
+    .await
+}
+
+/// Which routes a listener serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Routes {
+    /// Everything.
+    All,
+    /// Just '/shard_submit'.
+    Shard,
+    /// Everything but '/shard_submit'.
+    Feed,
+}
+
+impl Routes {
+    fn serves_shards(self) -> bool {
+        self != Routes::Feed
+    }
+    fn serves_feeds(self) -> bool {
+        self != Routes::Shard
+    }
+}
+
+/// Work out which addresses to listen on, and what to serve on each. Shards and feeds
+/// share the '--listen' address unless given addresses of their own.
+fn listeners(
+    listen: SocketAddr,
+    shard_listen: Option<SocketAddr>,
+    feed_listen: Option<SocketAddr>,
+) -> anyhow::Result<Vec<(SocketAddr, Routes)>> {
+    let shard_addr = shard_listen.unwrap_or(listen);
+    let feed_addr = feed_listen.unwrap_or(listen);
+    if shard_addr == feed_addr {
+        return Ok(vec![(shard_addr, Routes::All)]);
+    }
+
+    // Binding to both of these would fail, so say why up front:
+    if addrs_clash(shard_addr, feed_addr) {
+        anyhow::bail!(
+            "Shard listen address {shard_addr} and feed listen address {feed_addr} can't both be bound"
+        );
+    }
+
+    Ok(vec![(shard_addr, Routes::Shard), (feed_addr, Routes::Feed)])
+}
+
+/// Would binding to one address stop us from binding to the other?
+fn addrs_clash(a: SocketAddr, b: SocketAddr) -> bool {
+    let overlapping_ips = a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified();
+    a.port() == b.port() && a.port() != 0 && overlapping_ips
+}
+
+/// The options given that can only apply to the default namespace, so can't be used
+/// alongside namespaces.
+fn default_namespace_only_opts(opts: &Opts) -> Vec<&'static str> {
+    [
+        ("--first-seen-store", opts.first_seen_store.is_some()),
+        ("--webhook", !opts.webhook.is_empty()),
+        ("--publish-nats", opts.publish_nats.is_some()),
+        ("--statsd", opts.statsd.is_some()),
+        ("--record", opts.record.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(name, given)| given.then_some(name))
+    .collect()
+}
+
+/// Check that namespaces have different names, and addresses that can be bound alongside
+/// each other and the listen addresses of the default namespace, and that none of the
+/// options that only apply to the default namespace were given alongside them.
+fn check_namespaces(
+    namespaces: &[Namespace],
+    listeners: &[(SocketAddr, Routes)],
+    default_only_opts: &[&str],
+) -> anyhow::Result<()> {
+    if let (Some(namespace), Some(opt)) = (namespaces.first(), default_only_opts.first()) {
+        anyhow::bail!(
+            "{opt} can't be used alongside namespaces, since it would only apply to the \
+             default namespace and not to '{}'",
+            namespace.name
+        );
+    }
+    for (idx, namespace) in namespaces.iter().enumerate() {
+        for other in &namespaces[..idx] {
+            if other.name == namespace.name {
+                anyhow::bail!("Namespace '{}' is given more than once", namespace.name);
+            }
+            if addrs_clash(other.addr, namespace.addr) {
+                anyhow::bail!(
+                    "Namespaces '{}' and '{}' can't both be bound to {}",
+                    other.name,
+                    namespace.name,
+                    namespace.addr
+                );
+            }
+        }
+        if let Some((addr, _)) = listeners
+            .iter()
+            .find(|(addr, _)| addrs_clash(*addr, namespace.addr))
+        {
+            anyhow::bail!(
+                "Namespace '{}' address {} can't be bound alongside listen address {addr}",
+                namespace.name,
+                namespace.addr
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reload the node name blocklist from the path given each time we receive SIGHUP.
+/// If the file can't be loaded, the current blocklist is kept.
+#[cfg(unix)]
+fn spawn_node_name_blocklist_reloader(
+    path: PathBuf,
+    aggregators: Vec<AggregatorSet>,
+) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            let blocklist = match NodeNameBlocklist::from_file(&path) {
+                Ok(blocklist) => blocklist,
+                Err(e) => {
+                    log::error!("Keeping current node name blocklist: {e}");
+                    continue;
+                }
+            };
+            let blocklist = Arc::new(blocklist);
+            for aggregator in &aggregators {
+                if let Err(e) = aggregator.set_node_name_blocklist(blocklist.clone()).await {
+                    log::error!("Error updating node name blocklist (bailing): {e}");
+                    return;
+                }
+            }
+            log::info!("Reloaded node name blocklist from {path:?}");
+        }
+    });
+    Ok(())
+}
+
+/// Save when nodes were first seen to the path given every so often, if anything's changed.
+fn spawn_first_seen_saver(path: PathBuf, store: Arc<parking_lot::Mutex<FirstSeenStore>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FIRST_SEEN_SAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            // Only hold the lock for as long as it takes to serialize the store:
+            let Some(contents) = store.lock().take_changed_contents() else {
+                continue;
+            };
+            let path = path.clone();
+            let res = tokio::task::spawn_blocking(move || state::save_first_seen(&path, &contents));
+            match res.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => log::error!("Error saving when nodes were first seen: {e}"),
+                Err(e) => log::error!("Error saving when nodes were first seen: {e}"),
+            }
+        }
+    });
+}
+
+/// Reload the network denylist from the path given each time we receive SIGHUP.
+/// If the file can't be loaded, the current denylist is kept.
+#[cfg(unix)]
+fn spawn_network_denylist_reloader(
+    path: PathBuf,
+    aggregators: Vec<AggregatorSet>,
+) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            let denylist = match NetworkDenylist::from_file(&path) {
+                Ok(denylist) => denylist,
+                Err(e) => {
+                    log::error!("Keeping current network denylist: {e}");
+                    continue;
+                }
+            };
+            let denylist = Arc::new(denylist);
+            for aggregator in &aggregators {
+                if let Err(e) = aggregator.set_network_denylist(denylist.clone()).await {
+                    log::error!("Error updating network denylist (bailing): {e}");
+                    return;
+                }
+            }
+            log::info!("Reloaded network denylist from {path:?}");
+        }
+    });
+    Ok(())
+}
+
+/// This handles messages coming to/from a shard connection
+async fn handle_shard_websocket_connection<S>(
+    addr: SocketAddr,
+    mut ws_send: http_utils::WsSender,
+    mut ws_recv: http_utils::WsReceiver,
+    mut tx_to_aggregator: S,
+) -> (S, http_utils::WsSender)
 where
     S: futures::Sink<FromShardWebsocket, Error = anyhow::Error> + Unpin + Send + 'static,
 {
@@ -224,6 +2150,7 @@ where
     // Tell the aggregator about this new connection, and give it a way to send messages to us:
     let init_msg = FromShardWebsocket::Initialize {
         channel: tx_to_shard_conn,
+        addr,
     };
     if let Err(e) = tx_to_aggregator.send(init_msg).await {
         log::error!("Error sending message to aggregator: {}", e);
@@ -284,6 +2211,9 @@ where
                 internal_messages::FromShardAggregator::RemoveNode { local_id } => {
                     FromShardWebsocket::Remove { local_id }
                 }
+                internal_messages::FromShardAggregator::MalformedMessage { local_id } => {
+                    FromShardWebsocket::Malformed { local_id }
+                }
             };
 
             if let Err(e) = tx_to_aggregator.send(aggregator_msg).await {
@@ -340,12 +2270,36 @@ where
     (tx_to_aggregator, ws_send)
 }
 
+/// Which of the messages sent to a feed are compressed, if it supports compression.
+#[derive(Debug, Clone, Copy)]
+struct FeedCompression {
+    /// Compress everything.
+    all: bool,
+    /// Compress the state it's sent when it connects or subscribes to a chain.
+    snapshots: bool,
+}
+
+/// How the messages sent to a feed are combined into fewer websocket frames.
+#[derive(Debug, Clone, Copy)]
+struct FeedCoalescing {
+    /// How long to wait between sends, collecting up messages to combine.
+    window: Duration,
+    /// Combined frames stop growing once they're this many bytes.
+    max_bytes: usize,
+}
+
 /// This handles messages coming from a feed connection
+#[allow(clippy::too_many_arguments)]
 async fn handle_feed_websocket_connection<S>(
     mut ws_send: http_utils::WsSender,
     mut ws_recv: http_utils::WsReceiver,
     mut tx_to_aggregator: S,
     feed_timeout: u64,
+    feed_keepalive: Duration,
+    feed_coalescing: Option<FeedCoalescing>,
+    compression: Option<(http_utils::WsCompression, FeedCompression)>,
+    protocol: FeedProtocol,
+    access: FeedAccess,
     _feed_id: u64, // <- can be useful for debugging purposes.
 ) -> (S, http_utils::WsSender)
 where
@@ -402,13 +2356,19 @@ where
             };
 
             // Parse the message into a command we understand and send it to the aggregator:
-            let cmd = match FromFeedWebsocket::from_str(&text) {
+            let cmd = match protocol.parse_command(&text) {
                 Ok(cmd) => cmd,
                 Err(e) => {
                     log::warn!("Ignoring invalid command '{text}' from the frontend: {e}");
                     continue;
                 }
             };
+            if let FromFeedWebsocket::Subscribe { chain, .. } = &cmd {
+                if !access.can_subscribe(chain) {
+                    log::info!("Ignoring subscription to private chain {chain:?} without a token granting it");
+                    continue;
+                }
+            }
             if let Err(e) = tx_to_aggregator.send(cmd).await {
                 log::error!("Failed to send message to aggregator; closing feed: {e}");
                 break;
@@ -419,32 +2379,91 @@ where
         tx_to_aggregator
     });
 
+    // Periodically check whether the feed has gone quiet, and needs pinging. This only
+    // wakes the send loop below every so often, so we don't poll `rx_from_aggregator_chunks`
+    // much more than we would anyway (see above), and it keeps any messages it's collected.
+    let mut keepalive = (!feed_keepalive.is_zero())
+        .then(|| tokio::time::interval_at(Instant::now() + feed_keepalive, feed_keepalive));
+
     // Send messages to the feed:
     let send_handle = tokio::spawn(async move {
+        let mut last_sent = Instant::now();
         'outer: loop {
-            let debounce = tokio::time::sleep_until(Instant::now() + Duration::from_millis(75));
+            let debounce_time = feed_coalescing.map_or(Duration::from_millis(75), |c| c.window);
+            let debounce = tokio::time::sleep_until(Instant::now() + debounce_time);
 
-            let msgs = tokio::select! {
-                msgs = rx_from_aggregator_chunks.next() => msgs,
+            let keepalive_tick = async {
+                match &mut keepalive {
+                    Some(interval) => interval.tick().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let (msgs, keepalive_requested) = tokio::select! {
+                msgs = rx_from_aggregator_chunks.next() => match msgs {
+                    Some(msgs) => (msgs, false),
+                    // End the loop when connection from aggregator ends:
+                    None => break,
+                },
+                _ = keepalive_tick => (Vec::new(), true),
                 _ = &mut send_closer_rx => { break }
             };
 
-            // End the loop when connection from aggregator ends:
-            let msgs = match msgs {
-                Some(msgs) => msgs,
-                None => break,
+            // Collect up all of the bytes to send to the websocket to dispatch in one shot,
+            // noting which are snapshots.
+            let all_msg_bytes: Vec<_> = msgs
+                .into_iter()
+                .map(|msg| match msg {
+                    ToFeedWebsocket::Bytes(bytes) => (bytes, false),
+                    ToFeedWebsocket::Snapshot(bytes) => (bytes, true),
+                })
+                .collect();
+            let all_msg_bytes = match feed_coalescing {
+                // Snapshots aren't combined with anything else, so they can be compressed
+                // on their own:
+                Some(c) => all_msg_bytes
+                    .chunk_by(|(_, a), (_, b)| a == b)
+                    .flat_map(|run| {
+                        let is_snapshot = run[0].1;
+                        let run = run.iter().map(|(bytes, _)| bytes.clone()).collect();
+                        feed_message::coalesce(run, c.max_bytes)
+                            .into_iter()
+                            .map(move |bytes| (bytes, is_snapshot))
+                    })
+                    .collect(),
+                None => all_msg_bytes,
             };
 
-            // There is only one message type at the mo; bytes to send
-            // to the websocket. collect them all up to dispatch in one shot.
-            let all_msg_bytes = msgs.into_iter().map(|msg| match msg {
-                ToFeedWebsocket::Bytes(bytes) => bytes,
-            });
-
             // If the feed is too slow to receive the current batch of messages, we'll drop it.
             let message_send_deadline = Instant::now() + Duration::from_secs(feed_timeout);
 
-            for bytes in all_msg_bytes {
+            if !all_msg_bytes.is_empty() {
+                last_sent = Instant::now();
+            } else if keepalive_requested && last_sent.elapsed() >= feed_keepalive {
+                // Nothing has been sent to the feed for a while, so ping it to keep it alive:
+                let ping =
+                    ws_send.send_ping(soketto::data::ByteSlice125::try_from(&[][..]).unwrap());
+                match tokio::time::timeout_at(message_send_deadline, ping).await {
+                    Err(_) => {
+                        log::debug!("Closing feed websocket that was too slow to keep up (too slow to send ping)");
+                        break 'outer;
+                    }
+                    Ok(Err(soketto::connection::Error::Closed)) => {
+                        break 'outer;
+                    }
+                    Ok(Err(e)) => {
+                        log::debug!("Closing feed websocket due to error sending ping: {}", e);
+                        break 'outer;
+                    }
+                    Ok(_) => {}
+                }
+                last_sent = Instant::now();
+            }
+
+            for (bytes, is_snapshot) in all_msg_bytes {
+                if let Some((ws_compression, compression)) = &compression {
+                    ws_compression
+                        .set_enabled(compression.all || (compression.snapshots && is_snapshot));
+                }
                 match tokio::time::timeout_at(message_send_deadline, ws_send.send_binary(&bytes))
                     .await
                 {
@@ -494,72 +2513,1192 @@ where
     (tx_to_aggregator, ws_send)
 }
 
-async fn return_prometheus_metrics(aggregator: AggregatorSet) -> Response<hyper::Body> {
+/// Load the admin tokens from the file given, if any, allowing the single
+/// `admin_token` (if given) every scope.
+fn load_admin_tokens(
+    path: Option<&Path>,
+    admin_token: Option<&str>,
+) -> anyhow::Result<AdminTokens> {
+    let mut tokens = match path {
+        Some(path) => AdminTokens::from_file(path)?,
+        None => AdminTokens::default(),
+    };
+    if let Some(admin_token) = admin_token {
+        tokens.insert_unscoped(admin_token.to_owned());
+    }
+    Ok(tokens)
+}
+
+/// Reload the admin tokens from the path given each time we receive SIGHUP.
+/// If the file can't be loaded, the current tokens are kept.
+#[cfg(unix)]
+fn spawn_admin_tokens_reloader(
+    path: PathBuf,
+    admin_token: Option<String>,
+    tokens: Arc<RwLock<AdminTokens>>,
+) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            match load_admin_tokens(Some(&path), admin_token.as_deref()) {
+                Ok(new_tokens) => {
+                    *tokens.write() = new_tokens;
+                    log::info!("Reloaded admin tokens from {path:?}");
+                }
+                Err(e) => log::error!("Keeping current admin tokens: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// What a feed has access to: the feed tokens, and the token that it provided (if any).
+struct FeedAccess {
+    tokens: Arc<RwLock<FeedTokens>>,
+    token: Option<String>,
+}
+
+impl FeedAccess {
+    fn can_subscribe(&self, genesis_hash: &BlockHash) -> bool {
+        self.tokens
+            .read()
+            .can_subscribe(self.token.as_deref(), genesis_hash)
+    }
+}
+
+/// Reload the feed tokens from the path given each time we receive SIGHUP.
+/// If the file can't be loaded, the current tokens are kept.
+#[cfg(unix)]
+fn spawn_feed_tokens_reloader(
+    path: PathBuf,
+    private_chains: std::collections::HashSet<BlockHash>,
+    tokens: Arc<RwLock<FeedTokens>>,
+) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            match FeedTokens::from_file(&path, private_chains.clone()) {
+                Ok(new_tokens) => {
+                    *tokens.write() = new_tokens;
+                    log::info!("Reloaded feed tokens from {path:?}");
+                }
+                Err(e) => log::error!("Keeping current feed tokens: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Handle requests to the /admin routes. These only exist if admin tokens were given,
+/// and each request must provide one with the scope needed. Currently supported are:
+///
+/// - `GET /admin/chain/{genesis_hash}/node/{id}` (`read-only`): the full state of a node as
+///   JSON, where `id` is either the ID that feeds know the node by, or the network ID that
+///   the node reported.
+/// - `GET /admin/chain/{genesis_hash}/block_times` (`read-only`): the block times that the
+///   chain's average block time is worked out from, oldest first, and that average, as JSON.
+/// - `GET /admin/chain/{genesis_hash}/removed_nodes` (`read-only`): the nodes removed from
+///   the chain most recently, oldest first, with why and when each was removed, as JSON.
+/// - `GET /admin/chain/{genesis_hash}/events` (`read-only`): the most recent significant
+///   events on the chain, oldest first, with when each happened, as JSON.
+/// - `GET /admin/chain/{genesis_hash}/node_slots` (`read-only`): how many of the slots that
+///   the chain's nodes are stored in are occupied or empty, and how many of the empty ones
+///   compacting the chain would free, as JSON.
+/// - `POST /admin/chain/{genesis_hash}/freeze` and `POST /admin/chain/{genesis_hash}/unfreeze`
+///   (`chain-control`): stop or start applying updates from the chain's nodes, so that its
+///   state can be inspected.
+/// - `POST /admin/chain/{genesis_hash}/node/{id}/move/{to_genesis_hash}` (`node-control`):
+///   move a node that has ended up on the wrong chain to another one, keeping its state.
+/// - `POST /admin/chain/{genesis_hash}/node/{id}/annotation?text=..&severity=..&ttl_secs=..`
+///   (`node-control`): attach a note from some external system (eg "under maintenance") to
+///   a node, which feeds are shown until it expires or the node disconnects. `severity` is
+///   one of `info` (the default), `warning` or `critical`, and `ttl_secs` defaults to an hour.
+///   `DELETE` clears it again.
+/// - `POST /admin/chain/{genesis_hash}/max-nodes?value=..&evict=..` (`chain-control`): change
+///   how many nodes the chain can have until the core restarts. If it has more nodes than
+///   that, new ones aren't let in until enough have gone, unless `evict` is `true`, in which
+///   case the most recently connected nodes over the limit are removed straight away.
+/// - `POST /admin/chain/{genesis_hash}/compact` (`chain-control`): recount the chain's stats
+///   from its nodes, free memory left behind by nodes that have gone and regenerate the
+///   stats straight away, returning what changed as JSON.
+/// - `GET /admin/shards` (`read-only`): the shards that are connected, as JSON, with how
+///   many nodes each has, when each connected and last sent anything, and whether each
+///   looks healthy (ie hasn't gone quiet while it still has nodes).
+/// - `GET /admin/first-party-networks` (`read-only`): the genesis hashes of the chains that
+///   are treated as first party (so are always listed and have no node limit), as JSON.
+async fn return_admin_response(
+    req: &Request<Body>,
+    path: &str,
+    aggregator: AggregatorSet,
+    admin_tokens: Option<&RwLock<AdminTokens>>,
+) -> Response<Body> {
+    let admin_tokens = match admin_tokens {
+        Some(tokens) => tokens,
+        None => return text_response(404, "Not found"),
+    };
+
+    let provided_token = bearer_token(req);
+    let authorize = |needs| admin_tokens.read().check(provided_token, needs);
+    // Every scope can read, so this weeds out unknown tokens before anything else:
+    if let Err(e) = authorize(AdminScope::ReadOnly) {
+        return forbidden(e);
+    }
+
+    let parts: Vec<&str> = path.trim_start_matches("/admin/").split('/').collect();
+    let genesis_hash = match parts[..] {
+        ["shards"] if req.method() == Method::GET => {
+            return match aggregator.gather_shards().await {
+                Ok(shards) => Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_string(&shards).unwrap().into())
+                    .unwrap(),
+                Err(e) => {
+                    log::error!("Error obtaining shards: {e}");
+                    text_response(500, "Error obtaining shards")
+                }
+            };
+        }
+        ["first-party-networks"] if req.method() == Method::GET => {
+            let genesis_hashes = state::first_party_networks();
+            return Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(&genesis_hashes).unwrap().into())
+                .unwrap();
+        }
+        ["chain", genesis_hash, ..] => match genesis_hash.parse::<BlockHash>() {
+            Ok(hash) => hash,
+            Err(_) => return text_response(400, "Invalid genesis hash"),
+        },
+        _ => return text_response(404, "Not found"),
+    };
+    match (req.method(), &parts[2..]) {
+        (&Method::GET, ["node", node]) => {
+            let node = NodeLookup::from(*node);
+            match aggregator.gather_node_state(genesis_hash, node).await {
+                Ok(Some(node_state)) => Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(node_state.to_string().into())
+                    .unwrap(),
+                Ok(None) => text_response(404, "Node not found"),
+                Err(e) => {
+                    log::error!("Error obtaining node state: {e}");
+                    text_response(500, "Error obtaining node state")
+                }
+            }
+        }
+        (&Method::GET, ["block_times"]) => {
+            match aggregator.gather_block_times(genesis_hash).await {
+                Ok(Some(block_times)) => Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_string(&block_times).unwrap().into())
+                    .unwrap(),
+                Ok(None) => text_response(404, "Chain not found"),
+                Err(e) => {
+                    log::error!("Error obtaining block times: {e}");
+                    text_response(500, "Error obtaining block times")
+                }
+            }
+        }
+        (&Method::GET, ["removed_nodes"]) => {
+            match aggregator.gather_removed_nodes(genesis_hash).await {
+                Ok(Some(removed_nodes)) => Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_string(&removed_nodes).unwrap().into())
+                    .unwrap(),
+                Ok(None) => text_response(404, "Chain not found"),
+                Err(e) => {
+                    log::error!("Error obtaining removed nodes: {e}");
+                    text_response(500, "Error obtaining removed nodes")
+                }
+            }
+        }
+        (&Method::GET, ["events"]) => match aggregator.gather_recent_events(genesis_hash).await {
+            Ok(Some(recent_events)) => Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(&recent_events).unwrap().into())
+                .unwrap(),
+            Ok(None) => text_response(404, "Chain not found"),
+            Err(e) => {
+                log::error!("Error obtaining chain events: {e}");
+                text_response(500, "Error obtaining chain events")
+            }
+        },
+        (&Method::GET, ["node_slots"]) => match aggregator.gather_node_slots(genesis_hash).await {
+            Ok(Some(node_slots)) => Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(&node_slots).unwrap().into())
+                .unwrap(),
+            Ok(None) => text_response(404, "Chain not found"),
+            Err(e) => {
+                log::error!("Error obtaining node slots: {e}");
+                text_response(500, "Error obtaining node slots")
+            }
+        },
+        (&Method::POST, ["node", node, "move", to]) => {
+            if let Err(e) = authorize(AdminScope::NodeControl) {
+                return forbidden(e);
+            }
+            let to = match to.parse::<BlockHash>() {
+                Ok(hash) => hash,
+                Err(_) => return text_response(400, "Invalid genesis hash"),
+            };
+            let node = NodeLookup::from(*node);
+            match aggregator.move_node(genesis_hash, node, to).await {
+                Ok(MoveNodeOutcome::Moved(id)) => text_response(
+                    200,
+                    format!("Node moved; its ID is now {}", usize::from(id)),
+                ),
+                Ok(MoveNodeOutcome::NodeNotFound) => text_response(404, "Node not found"),
+                Ok(MoveNodeOutcome::AlreadyOnChain) => {
+                    text_response(400, "Node is already on that chain")
+                }
+                Ok(MoveNodeOutcome::ChainNotAllowed) => {
+                    text_response(403, "Nodes are not allowed on that chain")
+                }
+                Ok(MoveNodeOutcome::ChainOverQuota) => {
+                    text_response(409, "That chain has too many nodes")
+                }
+                Err(e) => {
+                    log::error!("Error moving node: {e}");
+                    text_response(500, "Error moving node")
+                }
+            }
+        }
+        (&Method::POST | &Method::DELETE, ["node", node, "annotation"]) => {
+            if let Err(e) = authorize(AdminScope::NodeControl) {
+                return forbidden(e);
+            }
+            let annotation = match req.method() {
+                &Method::DELETE => None,
+                _ => match parse_node_annotation(req.uri().query().unwrap_or_default()) {
+                    Ok(annotation) => Some(annotation),
+                    Err(e) => return text_response(400, e),
+                },
+            };
+            let node = NodeLookup::from(*node);
+            match aggregator
+                .annotate_node(genesis_hash, node, annotation)
+                .await
+            {
+                Ok(true) => text_response(200, "Node annotation updated"),
+                Ok(false) => text_response(404, "Node not found"),
+                Err(e) => {
+                    log::error!("Error annotating node: {e}");
+                    text_response(500, "Error annotating node")
+                }
+            }
+        }
+        (&Method::POST, ["max-nodes"]) => {
+            if let Err(e) = authorize(AdminScope::ChainControl) {
+                return forbidden(e);
+            }
+            let (max_nodes, evict) = match parse_max_nodes(req.uri().query().unwrap_or_default()) {
+                Ok(max_nodes) => max_nodes,
+                Err(e) => return text_response(400, e),
+            };
+            match aggregator
+                .set_chain_max_nodes(genesis_hash, max_nodes, evict)
+                .await
+            {
+                Ok(Some(over_quota)) => {
+                    let message = match (over_quota, evict) {
+                        (0, _) => format!("Node limit set to {max_nodes}"),
+                        (n, true) => format!("Node limit set to {max_nodes}; evicted {n} node(s)"),
+                        (n, false) => format!("Node limit set to {max_nodes}; {n} node(s) over it"),
+                    };
+                    log::info!("Chain {genesis_hash:?}: {message}");
+                    text_response(200, message)
+                }
+                Ok(None) => text_response(404, "Chain not found"),
+                Err(e) => {
+                    log::error!("Error setting chain node limit: {e}");
+                    text_response(500, "Error setting chain node limit")
+                }
+            }
+        }
+        (&Method::POST, ["compact"]) => {
+            if let Err(e) = authorize(AdminScope::ChainControl) {
+                return forbidden(e);
+            }
+            match aggregator.compact_chain(genesis_hash).await {
+                Ok(Some(summary)) => Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_string(&summary).unwrap().into())
+                    .unwrap(),
+                Ok(None) => text_response(404, "Chain not found"),
+                Err(e) => {
+                    log::error!("Error compacting chain: {e}");
+                    text_response(500, "Error compacting chain")
+                }
+            }
+        }
+        (&Method::POST, [action @ ("freeze" | "unfreeze")]) => {
+            if let Err(e) = authorize(AdminScope::ChainControl) {
+                return forbidden(e);
+            }
+            let frozen = *action == "freeze";
+            match aggregator.set_chain_frozen(genesis_hash, frozen).await {
+                Ok(true) if frozen => text_response(200, "Chain frozen"),
+                Ok(true) => text_response(200, "Chain unfrozen"),
+                Ok(false) => text_response(404, "Chain not found"),
+                Err(e) => {
+                    log::error!("Error freezing or unfreezing chain: {e}");
+                    text_response(500, "Error freezing or unfreezing chain")
+                }
+            }
+        }
+        _ => text_response(404, "Not found"),
+    }
+}
+
+/// Handle requests to `/chains/{genesis_hash}/nodes`, returning a page of the nodes on
+/// the chain that match the query, and how many matched in total.
+async fn return_nodes(
+    path: &str,
+    query: Option<&str>,
+    aggregator: AggregatorSet,
+    access: &FeedAccess,
+) -> Response<Body> {
+    let genesis_hash = match path.trim_start_matches("/chains/").strip_suffix("/nodes") {
+        Some(hash) => hash,
+        None => return text_response(404, "Not found"),
+    };
+    let genesis_hash = match genesis_hash.parse() {
+        Ok(hash) => hash,
+        Err(_) => return text_response(400, "Invalid genesis hash"),
+    };
+    if !access.can_subscribe(&genesis_hash) {
+        return text_response(404, "Chain not found");
+    }
+    let filter = match parse_node_filter(query.unwrap_or_default()) {
+        Ok(filter) => filter,
+        Err(e) => return text_response(400, e),
+    };
+    match aggregator.gather_nodes(genesis_hash, filter).await {
+        Ok(Some(nodes)) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&nodes).unwrap().into())
+            .unwrap(),
+        Ok(None) => text_response(404, "Chain not found"),
+        Err(e) => {
+            log::error!("Error obtaining nodes: {e}");
+            text_response(500, "Error obtaining nodes")
+        }
+    }
+}
+
+/// Handle requests to `/chains/{genesis_hash}/export`, returning everything we know about
+/// the chain and its nodes at one point in time as a single JSON document. The nodes are
+/// streamed out one at a time. The nodes can be filtered with the same query parameters as
+/// `/chains/{genesis_hash}/nodes`, except that every matching node is exported rather than a
+/// page of them. If the chain doesn't expose node details to feeds, a (`read-only`) admin
+/// token is needed, since the export contains them regardless.
+async fn return_chain_export(
+    req: &Request<Body>,
+    path: &str,
+    aggregator: AggregatorSet,
+    admin_tokens: Option<&RwLock<AdminTokens>>,
+    access: &FeedAccess,
+) -> Response<Body> {
+    let genesis_hash = match path.trim_start_matches("/chains/").strip_suffix("/export") {
+        Some(hash) => hash,
+        None => return text_response(404, "Not found"),
+    };
+    let genesis_hash = match genesis_hash.parse() {
+        Ok(hash) => hash,
+        Err(_) => return text_response(400, "Invalid genesis hash"),
+    };
+    if !access.can_subscribe(&genesis_hash) {
+        return text_response(404, "Chain not found");
+    }
+    let filter = match parse_node_filter(req.uri().query().unwrap_or_default()) {
+        Ok(filter) => NodeFilter {
+            offset: 0,
+            limit: usize::MAX,
+            ..filter
+        },
+        Err(e) => return text_response(400, e),
+    };
+    let admin_check =
+        admin_tokens.map(|tokens| tokens.read().check(bearer_token(req), AdminScope::ReadOnly));
+    let admin = matches!(admin_check, Some(Ok(())));
+    let export = match aggregator
+        .gather_chain_export(genesis_hash, admin, filter)
+        .await
+    {
+        Ok(ChainExportOutcome::Exported(export)) => export,
+        Ok(ChainExportOutcome::ChainNotFound) => return text_response(404, "Chain not found"),
+        Ok(ChainExportOutcome::AdminOnly) => {
+            return match admin_check {
+                Some(Err(e)) => forbidden(e),
+                _ => text_response(403, "This chain can only be exported by admins"),
+            }
+        }
+        Err(e) => {
+            log::error!("Error exporting chain: {e}");
+            return text_response(500, "Error exporting chain");
+        }
+    };
+
+    let head = format!(r#"{{"chain":{},"nodes":["#, export.chain);
+    let nodes = export
+        .nodes
+        .into_iter()
+        .enumerate()
+        .map(|(idx, node)| match idx {
+            0 => node.to_string(),
+            _ => format!(",{node}"),
+        });
+    let chunks = std::iter::once(head)
+        .chain(nodes)
+        .chain(std::iter::once("]}".to_owned()))
+        .map(Ok::<_, std::convert::Infallible>);
+    Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::wrap_stream(futures::stream::iter(chunks)))
+        .unwrap()
+}
+
+/// Parse a query string like `name=alice&min_height=100&validators_only=true&offset=0&limit=50`
+/// into a filter. `country=DE` only matches nodes in that country, and `country=unknown` only
+/// those whose country we don't know, and `min_uptime_secs=3600` only those that have been
+/// connected for at least an hour. Pages are capped at [`MAX_NODE_PAGE_SIZE`] nodes.
+fn parse_node_filter(query: &str) -> Result<NodeFilter, String> {
+    let mut filter = NodeFilter {
+        limit: DEFAULT_NODE_PAGE_SIZE,
+        ..NodeFilter::default()
+    };
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        let invalid = || format!("Invalid value for '{key}': {value}");
+        match &*key {
+            "name" if !value.is_empty() => filter.name = Some(value.as_ref().into()),
+            "name" => {}
+            "min_height" => filter.min_height = Some(value.parse().map_err(|_| invalid())?),
+            "validators_only" => filter.validators_only = value.parse().map_err(|_| invalid())?,
+            "country" if !value.is_empty() => filter.country = Some(value.as_ref().into()),
+            "country" => {}
+            "min_uptime_secs" => {
+                filter.min_uptime_secs = Some(value.parse().map_err(|_| invalid())?)
+            }
+            "offset" => filter.offset = value.parse().map_err(|_| invalid())?,
+            "limit" => filter.limit = value.parse::<usize>().map_err(|_| invalid())?,
+            // Checked along with the chain being asked about:
+            "token" => {}
+            _ => return Err(format!("Unknown query parameter '{key}'")),
+        }
+    }
+    filter.limit = filter.limit.min(MAX_NODE_PAGE_SIZE);
+    Ok(filter)
+}
+
+/// Parse a query string like `value=500&evict=true` into a new node limit for a chain, and
+/// whether nodes over it should be removed.
+fn parse_max_nodes(query: &str) -> Result<(usize, bool), String> {
+    let mut max_nodes = None;
+    let mut evict = false;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        let invalid = || format!("Invalid value for '{key}': {value}");
+        match &*key {
+            "value" => max_nodes = Some(value.parse().map_err(|_| invalid())?),
+            "evict" => evict = value.parse().map_err(|_| invalid())?,
+            _ => return Err(format!("Unknown query parameter '{key}'")),
+        }
+    }
+    let max_nodes = max_nodes.ok_or("Expecting a 'value'")?;
+    Ok((max_nodes, evict))
+}
+
+/// Parse a query string like `text=Under+maintenance&severity=warning&ttl_secs=3600` into
+/// an annotation for a node. Annotations last for at most [`MAX_NODE_ANNOTATION_TTL`].
+fn parse_node_annotation(query: &str) -> Result<NodeAnnotation, String> {
+    let mut text = None;
+    let mut severity = AnnotationSeverity::Info;
+    let mut ttl = DEFAULT_NODE_ANNOTATION_TTL;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        let invalid = || format!("Invalid value for '{key}': {value}");
+        match &*key {
+            "text" => text = Some(value.trim().to_owned()),
+            "severity" => severity = value.parse().map_err(|_| invalid())?,
+            "ttl_secs" => ttl = Duration::from_secs(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("Unknown query parameter '{key}'")),
+        }
+    }
+    let text = text
+        .filter(|text| !text.is_empty())
+        .ok_or("Expecting some 'text'")?;
+    if text.chars().count() > MAX_NODE_ANNOTATION_LEN {
+        return Err(format!(
+            "'text' can be at most {MAX_NODE_ANNOTATION_LEN} characters long"
+        ));
+    }
+    let ttl = ttl.min(MAX_NODE_ANNOTATION_TTL);
+    Ok(NodeAnnotation {
+        text: text.into(),
+        severity,
+        expires_at: common::time::now() + ttl.as_millis() as u64,
+    })
+}
+
+/// Handle requests to `/chains/{genesis_hash}/block-times`, returning the average block
+/// time of the chain as of each of its recent best blocks, oldest first, as JSON.
+async fn return_block_time_history(
+    path: &str,
+    aggregator: AggregatorSet,
+    access: &FeedAccess,
+) -> Response<Body> {
+    let genesis_hash = match path
+        .trim_start_matches("/chains/")
+        .strip_suffix("/block-times")
+    {
+        Some(hash) => hash,
+        None => return text_response(404, "Not found"),
+    };
+    let genesis_hash = match genesis_hash.parse() {
+        Ok(hash) => hash,
+        Err(_) => return text_response(400, "Invalid genesis hash"),
+    };
+    if !access.can_subscribe(&genesis_hash) {
+        return text_response(404, "Chain not found");
+    }
+    match aggregator.gather_block_time_history(genesis_hash).await {
+        Ok(Some(block_time_history)) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&block_time_history).unwrap().into())
+            .unwrap(),
+        Ok(None) => text_response(404, "Chain not found"),
+        Err(e) => {
+            log::error!("Error obtaining block time history: {e}");
+            text_response(500, "Error obtaining block time history")
+        }
+    }
+}
+
+/// Handle requests to `/recent_blocks/{genesis_hash}`, returning the most recent best
+/// blocks of the chain, oldest first. These are JSON, or CSV given `?format=csv`.
+async fn return_recent_blocks(
+    path: &str,
+    query: Option<&str>,
+    aggregator: AggregatorSet,
+    access: &FeedAccess,
+) -> Response<Body> {
+    let genesis_hash = match path.trim_start_matches("/recent_blocks/").parse() {
+        Ok(hash) => hash,
+        Err(_) => return text_response(400, "Invalid genesis hash"),
+    };
+    if !access.can_subscribe(&genesis_hash) {
+        return text_response(404, "Chain not found");
+    }
+    let csv = match parse_recent_blocks_format(query.unwrap_or_default()) {
+        Ok(csv) => csv,
+        Err(e) => return text_response(400, e),
+    };
+    match aggregator.gather_recent_blocks(genesis_hash).await {
+        Ok(Some(recent_blocks)) if csv => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "text/csv")
+            .body(recent_blocks_csv(&recent_blocks).into())
+            .unwrap(),
+        Ok(Some(recent_blocks)) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&recent_blocks).unwrap().into())
+            .unwrap(),
+        Ok(None) => text_response(404, "Chain not found"),
+        Err(e) => {
+            log::error!("Error obtaining recent blocks: {e}");
+            text_response(500, "Error obtaining recent blocks")
+        }
+    }
+}
+
+/// Parse a query string like `format=csv`, returning whether recent blocks should be
+/// returned as CSV rather than JSON.
+fn parse_recent_blocks_format(query: &str) -> Result<bool, String> {
+    let mut csv = false;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match (&*key, &*value) {
+            ("format", "csv") => csv = true,
+            ("format", "json") => csv = false,
+            ("format", _) => return Err(format!("Invalid value for 'format': {value}")),
+            // Checked along with the chain being asked about:
+            ("token", _) => {}
+            _ => return Err(format!("Unknown query parameter '{key}'")),
+        }
+    }
+    Ok(csv)
+}
+
+/// Write out recent blocks as CSV, one per line after a header. Times we don't know
+/// are left empty.
+fn recent_blocks_csv(recent_blocks: &[RecentBlock]) -> String {
+    let optional = |time: Option<u64>| time.map(|t| t.to_string()).unwrap_or_default();
+    let mut csv = String::from("height,timestamp,block_time_ms,propagation_ms\n");
+    for block in recent_blocks {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            block.height,
+            block.timestamp,
+            optional(block.block_time),
+            optional(block.propagation_time),
+        ));
+    }
+    csv
+}
+
+/// Handle requests to `/chains`, returning a summary of every chain as JSON. Parachains
+/// list the relay chain that they belong to, and relay chains list their parachains.
+async fn return_chains(aggregator: AggregatorSet) -> Response<Body> {
+    match aggregator.gather_chains().await {
+        Ok(chains) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&chains).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            log::error!("Error obtaining chains: {e}");
+            text_response(500, "Error obtaining chains")
+        }
+    }
+}
+
+/// Handle requests to `/info`, returning the version and uptime of this core, and
+/// how many shards, feeds, chains and nodes it knows about now and at most, as JSON.
+async fn return_info(aggregator: AggregatorSet, started_unix_ms: u64) -> Response<Body> {
+    let connections = match aggregator.gather_connection_info().await {
+        Ok(connections) => connections,
+        Err(e) => {
+            log::error!("Error obtaining connection info: {e}");
+            return text_response(500, "Error obtaining connection info");
+        }
+    };
+    let now_unix_ms = common::time::now();
+    let info = serde_json::json!({
+        "version": VERSION,
+        "git_hash": GIT_HASH,
+        "started_unix_ms": started_unix_ms,
+        "uptime_secs": now_unix_ms.saturating_sub(started_unix_ms) / 1000,
+        "current": connections.current,
+        "peak": connections.peak,
+    });
+    Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(info.to_string().into())
+        .unwrap()
+}
+
+/// The token given in the `Authorization: Bearer <token>` header of a request, if any.
+fn bearer_token(req: &Request<Body>) -> Option<&str> {
+    req.headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| val.strip_prefix("Bearer "))
+}
+
+/// The token that a feed provided, either in an 'Authorization: Bearer' header or (since
+/// browsers can't set headers on websocket connections) a 'token' query parameter.
+fn feed_token(req: &Request<Body>) -> Option<String> {
+    if let Some(token) = bearer_token(req) {
+        return Some(token.to_owned());
+    }
+    form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+}
+
+fn forbidden(e: AdminTokenError) -> Response<Body> {
+    Response::builder()
+        .status(403)
+        .body(e.to_string().into())
+        .unwrap()
+}
+
+fn text_response(status: u16, body: impl Into<Body>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(body.into())
+        .unwrap()
+}
+
+/// Refuse a feed connection; a full core is a temporary problem that's worth retrying
+/// later, whereas too many connections from one address is down to the client.
+fn connection_refused_response(refused: ConnectionRefused) -> Response<Body> {
+    match refused {
+        ConnectionRefused::TooManyFromAddr => text_response(429, "Too many connections"),
+        ConnectionRefused::TooManyOverall => Response::builder()
+            .status(503)
+            .header(http::header::RETRY_AFTER, "30")
+            .body("Too many feed subscribers; try again later".into())
+            .unwrap(),
+    }
+}
+
+async fn return_prometheus_metrics(
+    aggregator: AggregatorSet,
+    feed_connection_limits: &ConnectionLimits,
+    format: MetricsFormat,
+) -> Response<hyper::Body> {
     let metrics = aggregator.latest_metrics();
 
-    // Instead of using the rust prometheus library (which is optimised around global variables updated across a codebase),
-    // we just split out the text format that prometheus expects ourselves, and use the latest metrics that we've
-    // captured so far from the aggregators. See:
-    //
-    // https://github.com/prometheus/docs/blob/master/content/docs/instrumenting/exposition_formats.md#text-format-details
-    //
-    // For an example and explanation of this text based format. The minimal output we produce here seems to
-    // be handled correctly when pointing a current version of prometheus at it.
-    //
-    // Note: '{{' and '}}' are just escaped versions of '{' and '}' in Rust fmt strings.
-    use std::fmt::Write;
-    let mut s = String::new();
+    // We use the latest metrics that we've captured so far from the aggregators.
+    let mut w = MetricsWriter::new();
     for (idx, m) in metrics.iter().enumerate() {
-        let _ = write!(
-            &mut s,
-            "telemetry_core_connected_feeds{{aggregator=\"{}\"}} {} {}\n",
-            idx, m.connected_feeds, m.timestamp_unix_ms
+        let labels = format!("aggregator=\"{}\"", idx);
+        let ts = m.timestamp_unix_ms;
+        w.sample(
+            "telemetry_core_connected_feeds",
+            &labels,
+            m.connected_feeds,
+            ts,
+        );
+        w.sample(
+            "telemetry_core_connected_nodes",
+            &labels,
+            m.connected_nodes,
+            ts,
+        );
+        w.sample("telemetry_core_pending_nodes", &labels, m.pending_nodes, ts);
+        w.sample(
+            "telemetry_core_connected_shards",
+            &labels,
+            m.connected_shards,
+            ts,
         );
-        let _ = write!(
-            &mut s,
-            "telemetry_core_connected_nodes{{aggregator=\"{}\"}} {} {}\n",
-            idx, m.connected_nodes, m.timestamp_unix_ms
+        w.sample(
+            "telemetry_core_chains_subscribed_to",
+            &labels,
+            m.chains_subscribed_to,
+            ts,
         );
-        let _ = write!(
-            &mut s,
-            "telemetry_core_connected_shards{{aggregator=\"{}\"}} {} {}\n",
-            idx, m.connected_shards, m.timestamp_unix_ms
+        w.sample(
+            "telemetry_core_subscribed_feeds",
+            &labels,
+            m.subscribed_feeds,
+            ts,
         );
-        let _ = write!(
-            &mut s,
-            "telemetry_core_chains_subscribed_to{{aggregator=\"{}\"}} {} {}\n",
-            idx, m.chains_subscribed_to, m.timestamp_unix_ms
+        w.sample(
+            "telemetry_core_total_messages_to_feeds",
+            &labels,
+            m.total_messages_to_feeds,
+            ts,
         );
-        let _ = write!(
-            &mut s,
-            "telemetry_core_subscribed_feeds{{aggregator=\"{}\"}} {} {}\n",
-            idx, m.subscribed_feeds, m.timestamp_unix_ms
+        w.sample(
+            "telemetry_core_current_messages_to_aggregator",
+            &labels,
+            m.current_messages_to_aggregator,
+            ts,
         );
-        let _ = write!(
-            &mut s,
-            "telemetry_core_total_messages_to_feeds{{aggregator=\"{}\"}} {} {}\n",
-            idx, m.total_messages_to_feeds, m.timestamp_unix_ms
+        w.sample(
+            "telemetry_core_total_messages_to_aggregator",
+            &labels,
+            m.total_messages_to_aggregator,
+            ts,
         );
-        let _ = write!(
-            &mut s,
-            "telemetry_core_current_messages_to_aggregator{{aggregator=\"{}\"}} {} {}\n\n",
-            idx, m.current_messages_to_aggregator, m.timestamp_unix_ms
+        w.sample(
+            "telemetry_core_dropped_messages_to_aggregator",
+            &labels,
+            m.dropped_messages_to_aggregator,
+            ts,
         );
-        let _ = write!(
-            &mut s,
-            "telemetry_core_total_messages_to_aggregator{{aggregator=\"{}\"}} {} {}\n\n",
-            idx, m.total_messages_to_aggregator, m.timestamp_unix_ms
+        w.sample(
+            "telemetry_core_dropped_location_lookups",
+            &labels,
+            m.dropped_location_lookups,
+            ts,
+        );
+        for chain in &m.chains {
+            let labels = format!(
+                "aggregator=\"{}\",genesis_hash=\"{:?}\",chain=\"{}\"",
+                idx,
+                chain.genesis_hash,
+                escape_prometheus_label(&chain.label)
+            );
+            w.sample(
+                "telemetry_core_chain_messages_per_second",
+                &labels,
+                chain.messages_per_second,
+                ts,
+            );
+            w.histogram(
+                "telemetry_core_chain_stats_regeneration_seconds",
+                &labels,
+                &chain.stats_regeneration_times,
+                1_000_000.0,
+                ts,
+                None,
+            );
+            w.sample(
+                "telemetry_core_chain_malformed_node_messages",
+                &labels,
+                chain.malformed_messages,
+                ts,
+            );
+            w.sample("telemetry_core_chain_nodes", &labels, chain.node_count, ts);
+            w.sample(
+                "telemetry_core_chain_finality_gap_blocks",
+                &labels,
+                chain.best_finalized_gap,
+                ts,
+            );
+            // Scrapers asking for OpenMetrics can see which block the latest block time was for:
+            let exemplar = chain.latest_block.and_then(|block| {
+                Some(Exemplar {
+                    labels: format!(
+                        "block_hash=\"{:?}\",block_height=\"{}\"",
+                        block.hash, block.height
+                    ),
+                    value: block.block_time?,
+                    timestamp_unix_ms: block.timestamp,
+                })
+            });
+            w.histogram(
+                "telemetry_core_chain_block_time_seconds",
+                &labels,
+                &chain.block_times,
+                1_000.0,
+                ts,
+                exemplar,
+            );
+        }
+        for shard in &m.shards {
+            let labels = format!(
+                "aggregator=\"{}\",shard=\"{}\"",
+                idx,
+                u64::from(shard.conn_id)
+            );
+            w.sample(
+                "telemetry_core_shard_malformed_node_messages",
+                &labels,
+                shard.malformed_messages,
+                ts,
+            );
+        }
+    }
+
+    // How many of each type of message have been sent to feeds, across every aggregator:
+    let now = common::time::now();
+    for (name, count) in feed_message::message_counts() {
+        w.sample(
+            "telemetry_feed_messages_total",
+            &format!("type=\"{name}\""),
+            count,
+            now,
         );
-        let _ = write!(
-            &mut s,
-            "telemetry_core_dropped_messages_to_aggregator{{aggregator=\"{}\"}} {} {}\n\n",
-            idx, m.dropped_messages_to_aggregator, m.timestamp_unix_ms
+    }
+
+    // How long serializing the feed messages that were timed took, across every aggregator:
+    w.histogram(
+        "telemetry_feed_message_serialization_seconds",
+        "",
+        &feed_message::serialization_times(),
+        1_000_000_000.0,
+        now,
+        None,
+    );
+
+    // The feeds open right now, as counted when admitting new ones, and the most allowed:
+    w.sample(
+        "telemetry_core_feed_subscribers",
+        "",
+        feed_connection_limits.open_count(),
+        now,
+    );
+    if feed_connection_limits.max_total() != usize::MAX {
+        w.sample(
+            "telemetry_core_max_feed_subscribers",
+            "",
+            feed_connection_limits.max_total(),
+            now,
         );
     }
 
     Response::builder()
-        // The version number here tells prometheus which version of the text format we're using:
-        .header(http::header::CONTENT_TYPE, "text/plain; version=0.0.4")
-        .body(s.into())
+        .header(http::header::CONTENT_TYPE, format.content_type())
+        .body(w.render(format).into())
         .unwrap()
 }
+
+/// Label values in the prometheus text format need backslashes, quotes and newlines escaped.
+fn escape_prometheus_label(val: &str) -> String {
+    val.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn chain_colors_and_icons_are_validated() {
+        let hash = format!("{:?}", BlockHash::from_low_u64_be(1));
+        for color in ["#e6007a", "#FFF"] {
+            let c: ChainColor = format!("{hash}={color}").parse().unwrap();
+            assert_eq!(&*c.color, color);
+        }
+        for color in ["e6007a", "#e6007", "#gggggg", "red", ""] {
+            assert!(format!("{hash}={color}").parse::<ChainColor>().is_err());
+        }
+
+        for icon in [
+            "polkadot",
+            "kusama_2-new",
+            "https://example.com/icon.svg?v=2",
+        ] {
+            let i: ChainIcon = format!("{hash}={icon}").parse().unwrap();
+            assert_eq!(&*i.icon, icon);
+        }
+        let long_icon = "a".repeat(257);
+        for icon in [
+            "",
+            "two words",
+            "ftp://example.com/icon.svg",
+            "https://a b",
+            &long_icon,
+        ] {
+            assert!(format!("{hash}={icon}").parse::<ChainIcon>().is_err());
+        }
+
+        let opts = Opts::from_iter([
+            "telemetry_core",
+            "--chain-color",
+            &format!("{hash}=#e6007a"),
+            "--chain-icon",
+            &format!("{hash}=polkadot"),
+        ]);
+        assert_eq!(
+            chain_themes(&opts)[&BlockHash::from_low_u64_be(1)],
+            Theme {
+                color: Some("#e6007a".into()),
+                icon: Some("polkadot".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn node_filters_are_parsed_from_queries() {
+        assert_eq!(
+            parse_node_filter("").unwrap(),
+            NodeFilter {
+                limit: DEFAULT_NODE_PAGE_SIZE,
+                ..NodeFilter::default()
+            }
+        );
+        assert_eq!(
+            parse_node_filter(
+                "name=Alice%27s+node&min_height=100&validators_only=true&country=DE&min_uptime_secs=600&offset=20&limit=5000"
+            )
+            .unwrap(),
+            NodeFilter {
+                name: Some("Alice's node".into()),
+                min_height: Some(100),
+                validators_only: true,
+                country: Some("DE".into()),
+                min_uptime_secs: Some(600),
+                offset: 20,
+                limit: MAX_NODE_PAGE_SIZE,
+            }
+        );
+        assert!(parse_node_filter("min_height=tall").is_err());
+        assert!(parse_node_filter("min_uptime_secs=-1").is_err());
+        assert!(parse_node_filter("colour=blue").is_err());
+        assert!(parse_node_filter("token=secret").is_ok());
+    }
+
+    #[test]
+    fn max_nodes_are_parsed_from_queries() {
+        assert_eq!(parse_max_nodes("value=500").unwrap(), (500, false));
+        assert_eq!(parse_max_nodes("value=10&evict=true").unwrap(), (10, true));
+        assert!(parse_max_nodes("").is_err());
+        assert!(parse_max_nodes("value=lots").is_err());
+        assert!(parse_max_nodes("value=10&evict=maybe").is_err());
+    }
+
+    #[test]
+    fn node_annotations_are_parsed_from_queries() {
+        let now = common::time::now();
+        let annotation =
+            parse_node_annotation("text=Under+maintenance&severity=warning&ttl_secs=60").unwrap();
+        assert_eq!(&*annotation.text, "Under maintenance");
+        assert_eq!(annotation.severity, AnnotationSeverity::Warning);
+        assert!((now + 60_000..now + 61_000).contains(&annotation.expires_at));
+
+        // Annotations can't last for ever:
+        let annotation = parse_node_annotation("text=Gone&ttl_secs=999999999").unwrap();
+        assert_eq!(annotation.severity, AnnotationSeverity::Info);
+        assert!(
+            annotation.expires_at
+                <= common::time::now() + MAX_NODE_ANNOTATION_TTL.as_millis() as u64
+        );
+
+        assert!(parse_node_annotation("").is_err());
+        assert!(parse_node_annotation("text=+").is_err());
+        assert!(parse_node_annotation("text=a&severity=dire").is_err());
+        let too_long = format!("text={}", "a".repeat(MAX_NODE_ANNOTATION_LEN + 1));
+        assert!(parse_node_annotation(&too_long).is_err());
+    }
+
+    #[test]
+    fn recent_blocks_are_written_as_csv() {
+        assert_eq!(parse_recent_blocks_format(""), Ok(false));
+        assert_eq!(parse_recent_blocks_format("format=csv"), Ok(true));
+        assert!(parse_recent_blocks_format("format=xml").is_err());
+        assert_eq!(parse_recent_blocks_format("token=secret"), Ok(false));
+
+        let block = |height, block_time, propagation_time| RecentBlock {
+            height,
+            hash: BlockHash::from_low_u64_be(height),
+            timestamp: height * 6000,
+            block_time,
+            propagation_time,
+        };
+        assert_eq!(
+            recent_blocks_csv(&[block(1, None, None), block(2, Some(6000), Some(250))]),
+            "height,timestamp,block_time_ms,propagation_ms\n\
+             1,6000,,\n\
+             2,12000,6000,250\n"
+        );
+    }
+
+    #[test]
+    fn shards_and_feeds_share_the_listen_address_by_default() {
+        let l = listeners(addr("127.0.0.1:8000"), None, None).unwrap();
+        assert_eq!(l, vec![(addr("127.0.0.1:8000"), Routes::All)]);
+
+        let l = listeners(addr("127.0.0.1:8000"), Some(addr("127.0.0.1:8000")), None).unwrap();
+        assert_eq!(l, vec![(addr("127.0.0.1:8000"), Routes::All)]);
+    }
+
+    #[test]
+    fn shards_and_feeds_can_be_split() {
+        let l = listeners(addr("127.0.0.1:8000"), Some(addr("10.0.0.1:8001")), None).unwrap();
+        assert_eq!(
+            l,
+            vec![
+                (addr("10.0.0.1:8001"), Routes::Shard),
+                (addr("127.0.0.1:8000"), Routes::Feed)
+            ]
+        );
+    }
+
+    #[test]
+    fn clashing_listen_addresses_are_rejected() {
+        assert!(listeners(addr("0.0.0.0:8000"), Some(addr("127.0.0.1:8000")), None).is_err());
+        // Different IPs or ports are fine:
+        assert!(listeners(addr("10.0.0.2:8000"), Some(addr("10.0.0.1:8000")), None).is_ok());
+        assert!(listeners(addr("0.0.0.0:8000"), Some(addr("0.0.0.0:8001")), None).is_ok());
+    }
+
+    #[test]
+    fn namespaces_need_their_own_names_and_addresses() {
+        let namespace = |s: &str| s.parse::<Namespace>().unwrap();
+        let listen = listeners(addr("127.0.0.1:8000"), None, None).unwrap();
+
+        let namespaces = [
+            namespace("staging=127.0.0.1:9000"),
+            namespace("prod=127.0.0.1:9001"),
+        ];
+        assert!(check_namespaces(&namespaces, &listen, &[]).is_ok());
+
+        let namespaces = [
+            namespace("staging=127.0.0.1:9000"),
+            namespace("staging=127.0.0.1:9001"),
+        ];
+        assert!(check_namespaces(&namespaces, &listen, &[]).is_err());
+        let namespaces = [
+            namespace("staging=127.0.0.1:9000"),
+            namespace("prod=0.0.0.0:9000"),
+        ];
+        assert!(check_namespaces(&namespaces, &listen, &[]).is_err());
+        assert!(check_namespaces(&[namespace("staging=127.0.0.1:8000")], &listen, &[]).is_err());
+        // Options that only apply to the default namespace can't be used alongside others:
+        let namespaces = [namespace("staging=127.0.0.1:9000")];
+        assert!(check_namespaces(&namespaces, &listen, &["--webhook"]).is_err());
+        assert!(check_namespaces(&[], &listen, &["--webhook"]).is_ok());
+        let default_only_opts = |args: &[&str]| {
+            let opts = Opts::from_iter(["telemetry_core"].iter().chain(args));
+            default_namespace_only_opts(&opts)
+        };
+        assert!(default_only_opts(&[]).is_empty());
+        assert_eq!(
+            default_only_opts(&["--statsd", "127.0.0.1:8125"]),
+            ["--statsd"]
+        );
+        assert_eq!(
+            default_only_opts(&[
+                "--record",
+                "feed.rec",
+                "--record-chain",
+                &format!("{:?}", BlockHash::zero())
+            ]),
+            ["--record"]
+        );
+        assert_eq!(
+            default_only_opts(&["--webhook", "finality_gap=http://localhost/alert"]),
+            ["--webhook"]
+        );
+
+        assert!("staging".parse::<Namespace>().is_err());
+        assert!("=127.0.0.1:9000".parse::<Namespace>().is_err());
+        assert!("a/b=127.0.0.1:9000".parse::<Namespace>().is_err());
+        assert!("staging=nowhere".parse::<Namespace>().is_err());
+    }
+
+    #[test]
+    fn config_problems_are_all_reported() {
+        let path = std::env::temp_dir().join(format!(
+            "check_config_test_{}_{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, "not-a-genesis-hash\n").unwrap();
+        let hash = |n| format!("{:?}", BlockHash::from_low_u64_be(n));
+
+        let opts = Opts::from_iter([
+            "telemetry_core".to_owned(),
+            "--check-config".to_owned(),
+            "--deny-networks".to_owned(),
+            path.to_string_lossy().into_owned(),
+            "--hardware-tier-mid-score".to_owned(),
+            "200".to_owned(),
+            "--chain-label-override".to_owned(),
+            format!("{}=One", hash(1)),
+            format!("{}=Two", hash(2)),
+            format!("{}=Uno", hash(1)),
+        ]);
+        let problems = check_config(&opts);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(problems.len(), 3, "{problems:?}");
+        assert!(problems[0].starts_with("tiers: --hardware-tier-mid-score"));
+        assert!(problems[1].starts_with("--deny-networks: "));
+        assert_eq!(
+            problems[2],
+            format!(
+                "--chain-label-override: {} is given both \"One\" and \"Uno\"",
+                hash(1)
+            )
+        );
+
+        let opts = Opts::from_iter(["telemetry_core", "--check-config"]);
+        assert!(check_config(&opts).is_empty());
+    }
+
+    #[test]
+    fn recent_blocks_are_bounded() {
+        let hash = format!("{:?}", BlockHash::from_low_u64_be(1));
+        let check = |args: &[&str]| {
+            let opts = Opts::from_iter(["telemetry_core"].iter().chain(args));
+            check_recent_blocks(&opts)
+        };
+
+        assert!(check(&["--recent-blocks", "1000"]).is_ok());
+        assert!(check(&["--recent-blocks", "1001"]).is_err());
+        assert!(check(&["--chain-recent-blocks", &format!("{hash}=500")]).is_ok());
+        assert!(check(&["--chain-recent-blocks", &format!("{hash}=5000")]).is_err());
+    }
+}
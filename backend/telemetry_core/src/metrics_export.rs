@@ -0,0 +1,343 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Metrics are written out in the Prometheus text format, or in OpenMetrics for
+//! scrapers that ask for it, which also lets us attach exemplars to samples.
+//!
+//! Instead of using the rust prometheus library (which is optimised around global variables
+//! updated across a codebase), we just write out the text formats ourselves. See:
+//!
+//! - <https://github.com/prometheus/docs/blob/master/content/docs/instrumenting/exposition_formats.md#text-format-details>
+//! - <https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md>
+
+use common::Histogram;
+use std::fmt::{Display, Write};
+
+/// The text formats that we can write metrics out in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsFormat {
+    Prometheus,
+    OpenMetrics,
+}
+
+impl MetricsFormat {
+    /// Pick a format given the `Accept` header of a request. We stick with the
+    /// Prometheus format unless OpenMetrics is asked for.
+    pub fn from_accept_header(accept: Option<&str>) -> MetricsFormat {
+        let wants_open_metrics = accept.unwrap_or_default().split(',').any(|media_range| {
+            let mut parts = media_range.split(';').map(str::trim);
+            let is_open_metrics = parts
+                .next()
+                .is_some_and(|media| media.eq_ignore_ascii_case("application/openmetrics-text"));
+            // A quality of 0 means that the media type isn't acceptable:
+            let refused = parts.any(|param| {
+                param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+            });
+            is_open_metrics && !refused
+        });
+        match wants_open_metrics {
+            true => MetricsFormat::OpenMetrics,
+            false => MetricsFormat::Prometheus,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            // The version number here tells prometheus which version of the text format we're using:
+            MetricsFormat::Prometheus => "text/plain; version=0.0.4",
+            MetricsFormat::OpenMetrics => {
+                "application/openmetrics-text; version=1.0.0; charset=utf-8"
+            }
+        }
+    }
+
+    fn write_timestamp(self, s: &mut String, timestamp_unix_ms: u64) {
+        let _ = match self {
+            MetricsFormat::Prometheus => write!(s, "{timestamp_unix_ms}"),
+            // OpenMetrics timestamps are in seconds:
+            MetricsFormat::OpenMetrics => write!(
+                s,
+                "{}.{:03}",
+                timestamp_unix_ms / 1000,
+                timestamp_unix_ms % 1000
+            ),
+        };
+    }
+}
+
+/// Labels and a value to attach to a histogram bucket in OpenMetrics, such as the
+/// hash of the block that a block time was measured for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    /// Label pairs, eg `block_hash="0x1234"`.
+    pub labels: String,
+    /// The value that was recorded in the histogram, in the same units.
+    pub value: u64,
+    pub timestamp_unix_ms: u64,
+}
+
+enum MetricType {
+    /// Anything that isn't a histogram is left untyped.
+    Untyped,
+    Histogram,
+}
+
+struct Sample {
+    /// Appended to the name of the metric that the sample belongs to, eg `_bucket`.
+    suffix: &'static str,
+    labels: String,
+    value: String,
+    timestamp_unix_ms: u64,
+    /// Exemplars are scaled like the values they belong with.
+    exemplar: Option<(String, String, u64)>,
+}
+
+struct Family {
+    name: &'static str,
+    metric_type: MetricType,
+    samples: Vec<Sample>,
+}
+
+/// Collects samples, keeping those of each metric together as both formats require,
+/// and then writes them all out.
+#[derive(Default)]
+pub struct MetricsWriter {
+    families: Vec<Family>,
+}
+
+impl MetricsWriter {
+    pub fn new() -> Self {
+        MetricsWriter::default()
+    }
+
+    fn family(&mut self, name: &'static str, metric_type: MetricType) -> &mut Vec<Sample> {
+        let idx = match self.families.iter().position(|f| f.name == name) {
+            Some(idx) => idx,
+            None => {
+                self.families.push(Family {
+                    name,
+                    metric_type,
+                    samples: Vec::new(),
+                });
+                self.families.len() - 1
+            }
+        };
+        &mut self.families[idx].samples
+    }
+
+    /// Add a sample of an untyped metric. `labels` are label pairs separated by
+    /// commas, eg `aggregator="0",chain="Polkadot"`.
+    pub fn sample(
+        &mut self,
+        name: &'static str,
+        labels: &str,
+        value: impl Display,
+        timestamp_unix_ms: u64,
+    ) {
+        self.family(name, MetricType::Untyped).push(Sample {
+            suffix: "",
+            labels: labels.to_owned(),
+            value: value.to_string(),
+            timestamp_unix_ms,
+            exemplar: None,
+        });
+    }
+
+    /// Add the buckets, sum and count of a histogram, dividing its values by `per_second`
+    /// to get seconds. Any exemplar given is attached to the bucket that its value is in.
+    pub fn histogram(
+        &mut self,
+        name: &'static str,
+        labels: &str,
+        histogram: &Histogram,
+        per_second: f64,
+        timestamp_unix_ms: u64,
+        exemplar: Option<Exemplar>,
+    ) {
+        let seconds = |val: u64| (val as f64 / per_second).to_string();
+        let with_le = |le: &str| match labels {
+            "" => format!("le=\"{le}\""),
+            labels => format!("{labels},le=\"{le}\""),
+        };
+        let mut exemplar = exemplar;
+        let mut exemplar_if = |fits: &dyn Fn(&Exemplar) -> bool| {
+            exemplar
+                .take_if(|e| fits(e))
+                .map(|e| (e.labels, seconds(e.value), e.timestamp_unix_ms))
+        };
+
+        let mut samples = Vec::new();
+        for (bound, count) in histogram.cumulative_buckets() {
+            samples.push(Sample {
+                suffix: "_bucket",
+                labels: with_le(&seconds(bound)),
+                value: count.to_string(),
+                timestamp_unix_ms,
+                exemplar: exemplar_if(&|e| e.value <= bound),
+            });
+        }
+        samples.push(Sample {
+            suffix: "_bucket",
+            labels: with_le("+Inf"),
+            value: histogram.count().to_string(),
+            timestamp_unix_ms,
+            exemplar: exemplar_if(&|_| true),
+        });
+        samples.push(Sample {
+            suffix: "_sum",
+            labels: labels.to_owned(),
+            value: seconds(histogram.sum()),
+            timestamp_unix_ms,
+            exemplar: None,
+        });
+        samples.push(Sample {
+            suffix: "_count",
+            labels: labels.to_owned(),
+            value: histogram.count().to_string(),
+            timestamp_unix_ms,
+            exemplar: None,
+        });
+
+        self.family(name, MetricType::Histogram).extend(samples);
+    }
+
+    /// Write out everything in the format given. Exemplars are only written in OpenMetrics.
+    pub fn render(&self, format: MetricsFormat) -> String {
+        let mut s = String::new();
+        for family in &self.families {
+            if let MetricType::Histogram = family.metric_type {
+                let _ = writeln!(s, "# TYPE {} histogram", family.name);
+            }
+            for sample in &family.samples {
+                // Note: '{{' and '}}' are just escaped versions of '{' and '}' in Rust fmt strings.
+                let _ = write!(
+                    s,
+                    "{}{}{{{}}} {} ",
+                    family.name, sample.suffix, sample.labels, sample.value
+                );
+                format.write_timestamp(&mut s, sample.timestamp_unix_ms);
+                if let (MetricsFormat::OpenMetrics, Some((labels, value, timestamp_unix_ms))) =
+                    (format, &sample.exemplar)
+                {
+                    let _ = write!(s, " # {{{labels}}} {value} ");
+                    format.write_timestamp(&mut s, *timestamp_unix_ms);
+                }
+                s.push('\n');
+            }
+        }
+        if format == MetricsFormat::OpenMetrics {
+            s.push_str("# EOF\n");
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn open_metrics_is_only_used_if_asked_for() {
+        assert_eq!(
+            MetricsFormat::from_accept_header(None),
+            MetricsFormat::Prometheus
+        );
+        assert_eq!(
+            MetricsFormat::from_accept_header(Some("text/plain;version=0.0.4")),
+            MetricsFormat::Prometheus
+        );
+        assert_eq!(
+            MetricsFormat::from_accept_header(Some(
+                "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5"
+            )),
+            MetricsFormat::OpenMetrics
+        );
+        assert_eq!(
+            MetricsFormat::from_accept_header(Some("application/openmetrics-text; q=0")),
+            MetricsFormat::Prometheus
+        );
+    }
+
+    #[test]
+    fn samples_of_a_metric_are_kept_together() {
+        let mut writer = MetricsWriter::new();
+        writer.sample("a", "aggregator=\"0\"", 1, 1500);
+        writer.sample("b", "aggregator=\"0\"", 2, 1500);
+        writer.sample("a", "aggregator=\"1\"", 3, 1500);
+
+        assert_eq!(
+            writer.render(MetricsFormat::Prometheus),
+            "a{aggregator=\"0\"} 1 1500\n\
+             a{aggregator=\"1\"} 3 1500\n\
+             b{aggregator=\"0\"} 2 1500\n"
+        );
+        assert_eq!(
+            writer.render(MetricsFormat::OpenMetrics),
+            "a{aggregator=\"0\"} 1 1.500\n\
+             a{aggregator=\"1\"} 3 1.500\n\
+             b{aggregator=\"0\"} 2 1.500\n\
+             # EOF\n"
+        );
+    }
+
+    #[test]
+    fn exemplars_are_attached_to_the_bucket_of_their_value() {
+        let mut histogram = Histogram::new(&[1000, 6000]);
+        histogram.record(500);
+        histogram.record(5500);
+        let exemplar = Exemplar {
+            labels: "block_hash=\"0x01\"".to_owned(),
+            value: 5500,
+            timestamp_unix_ms: 2000,
+        };
+
+        let mut writer = MetricsWriter::new();
+        writer.histogram("t", "c=\"x\"", &histogram, 1000.0, 3000, Some(exemplar));
+
+        assert_eq!(
+            writer.render(MetricsFormat::OpenMetrics),
+            "# TYPE t histogram\n\
+             t_bucket{c=\"x\",le=\"1\"} 1 3.000\n\
+             t_bucket{c=\"x\",le=\"6\"} 2 3.000 # {block_hash=\"0x01\"} 5.5 2.000\n\
+             t_bucket{c=\"x\",le=\"+Inf\"} 2 3.000\n\
+             t_sum{c=\"x\"} 6 3.000\n\
+             t_count{c=\"x\"} 2 3.000\n\
+             # EOF\n"
+        );
+        // No exemplars in the Prometheus format:
+        assert!(writer
+            .render(MetricsFormat::Prometheus)
+            .contains("t_bucket{c=\"x\",le=\"6\"} 2 3000\n"));
+    }
+
+    #[test]
+    fn histograms_can_have_no_labels() {
+        let mut histogram = Histogram::new(&[1000]);
+        histogram.record(500);
+
+        let mut writer = MetricsWriter::new();
+        writer.histogram("t", "", &histogram, 1000.0, 3000, None);
+
+        assert_eq!(
+            writer.render(MetricsFormat::Prometheus),
+            "# TYPE t histogram\n\
+             t_bucket{le=\"1\"} 1 3000\n\
+             t_bucket{le=\"+Inf\"} 1 3000\n\
+             t_sum{} 0.5 3000\n\
+             t_count{} 1 3000\n"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use common::node_types::Timestamp;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// How many distinct block authors we keep track of per chain. Once we're full,
+/// the author that went longest without producing a block is forgotten.
+pub const MAX_BLOCK_AUTHORS: usize = 1000;
+
+/// How many of the most productive authors are shown in the chain stats.
+pub const TOP_BLOCK_AUTHORS: usize = 10;
+
+/// A block author, as shown in the chain stats.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockAuthor {
+    pub author: Box<str>,
+    /// How many best blocks we've seen this author produce.
+    pub blocks: u64,
+    /// When we last saw this author produce a best block.
+    pub last_authored: Timestamp,
+    /// Hasn't produced a block for a while.
+    pub inactive: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AuthorRecord {
+    blocks: u64,
+    last_authored: Timestamp,
+}
+
+/// Counts the best blocks produced by each author that nodes tell us about.
+#[derive(Debug, Clone)]
+pub struct BlockAuthors {
+    authors: HashMap<Box<str>, AuthorRecord>,
+    capacity: usize,
+}
+
+impl Default for BlockAuthors {
+    fn default() -> Self {
+        BlockAuthors::with_capacity(MAX_BLOCK_AUTHORS)
+    }
+}
+
+impl BlockAuthors {
+    pub fn with_capacity(capacity: usize) -> Self {
+        BlockAuthors {
+            authors: HashMap::new(),
+            capacity,
+        }
+    }
+
+    /// Record that `author` produced a block at `now`.
+    pub fn record(&mut self, author: &str, now: Timestamp) {
+        if let Some(record) = self.authors.get_mut(author) {
+            record.blocks += 1;
+            record.last_authored = now;
+            return;
+        }
+
+        if self.authors.len() >= self.capacity {
+            let oldest = self
+                .authors
+                .iter()
+                .min_by_key(|(_, record)| record.last_authored)
+                .map(|(author, _)| author.clone());
+            match oldest {
+                Some(oldest) => {
+                    self.authors.remove(&oldest);
+                }
+                None => return,
+            }
+        }
+
+        self.authors.insert(
+            author.into(),
+            AuthorRecord {
+                blocks: 1,
+                last_authored: now,
+            },
+        );
+    }
+
+    /// The authors that have produced the most blocks, most productive first, along
+    /// with how many authors haven't produced a block in the last `inactive_after` ms.
+    pub fn summary(&self, now: Timestamp, inactive_after: u64) -> (Vec<BlockAuthor>, u64) {
+        let is_inactive =
+            |record: &AuthorRecord| now.saturating_sub(record.last_authored) > inactive_after;
+
+        let mut top: Vec<_> = self
+            .authors
+            .iter()
+            .map(|(author, record)| BlockAuthor {
+                author: author.clone(),
+                blocks: record.blocks,
+                last_authored: record.last_authored,
+                inactive: is_inactive(record),
+            })
+            .collect();
+        top.sort_by(|a, b| {
+            b.blocks
+                .cmp(&a.blocks)
+                .then_with(|| a.author.cmp(&b.author))
+        });
+        top.truncate(TOP_BLOCK_AUTHORS);
+
+        let inactive = self.authors.values().filter(|r| is_inactive(r)).count() as u64;
+        (top, inactive)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn authors_are_ranked_by_blocks_produced() {
+        let mut authors = BlockAuthors::default();
+        authors.record("alice", 1000);
+        authors.record("bob", 1000);
+        authors.record("bob", 2000);
+
+        let (top, inactive) = authors.summary(2000, 60_000);
+        let names: Vec<_> = top.iter().map(|a| (&*a.author, a.blocks)).collect();
+        assert_eq!(names, vec![("bob", 2), ("alice", 1)]);
+        assert_eq!(top[0].last_authored, 2000);
+        assert_eq!(inactive, 0);
+    }
+
+    #[test]
+    fn authors_that_stop_producing_blocks_are_inactive() {
+        let mut authors = BlockAuthors::default();
+        authors.record("alice", 1000);
+        authors.record("bob", 5000);
+
+        let (top, inactive) = authors.summary(7000, 3000);
+        assert_eq!(inactive, 1);
+        assert!(top.iter().any(|a| &*a.author == "alice" && a.inactive));
+        assert!(top.iter().any(|a| &*a.author == "bob" && !a.inactive));
+    }
+
+    #[test]
+    fn least_recent_author_is_forgotten_when_full() {
+        let mut authors = BlockAuthors::with_capacity(2);
+        authors.record("alice", 1000);
+        authors.record("alice", 1500);
+        authors.record("bob", 2000);
+        authors.record("carol", 3000);
+
+        let (top, _) = authors.summary(3000, 60_000);
+        let mut names: Vec<_> = top.iter().map(|a| &*a.author).collect();
+        names.sort();
+        assert_eq!(names, vec!["bob", "carol"]);
+    }
+}
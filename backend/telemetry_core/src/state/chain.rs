@@ -16,19 +16,29 @@
 
 use common::node_message::Payload;
 use common::node_types::BlockHash;
-use common::node_types::{Block, Timestamp};
-use common::{id_type, time, DenseMap, MostSeen, NumStats};
+use common::node_types::{Block, NetworkId, NodeDetails, NodeLocation, Timestamp};
+use common::rolling_total::{RollingTotal, RollingTotalBuilder};
+use common::{id_type, time, DenseMap, DenseMapStats, Histogram, MostSeen, NumStats};
 use once_cell::sync::Lazy;
-use std::collections::HashSet;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::feed_message::{self, ChainStats, FeedMessageSerializer};
+use crate::feed_message::{
+    self, ChainStats, FeedMessageSerializer, NodeDetailField, NodeDetailFields,
+};
 use crate::find_location;
 
-use super::chain_stats::ChainStatsCollator;
+use super::block_authors::BlockAuthors;
+use super::chain_stats::{
+    CardinalityLimits, ChainStatsCollator, ConnectionTiers, HardwareTiers, NodeOperatorPattern,
+    UnreportedNodeRole,
+};
 use super::counter::CounterValue;
-use super::node::Node;
+use super::feed_rate_limiter::FeedRateLimiter;
+use super::node::{HardwareChanges, Node, NodeAnnotation, StatsUpdate};
 
 id_type! {
     /// A Node ID that is unique to the chain it's in.
@@ -37,25 +47,782 @@ id_type! {
 
 pub type Label = Box<str>;
 
-const STALE_TIMEOUT: u64 = 2 * 60 * 1000; // 2 minutes
-const STATS_UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+/// How often chains regenerate their stats, when they aren't under load.
+pub const STATS_UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+/// How often, in milliseconds, we look for nodes that have been stale for long enough to remove.
+const STALE_NODE_EXPIRY_INTERVAL: u64 = 10 * 1000;
+/// The smallest windows that rolling metrics can be worked out over.
+const MIN_WINDOW_SAMPLES: usize = 1;
+const MIN_WINDOW_DURATION: Duration = Duration::from_secs(1);
+/// Bounds on the number of block times we'll average over when scaling
+/// the window to cover some duration.
+const MIN_BLOCK_TIME_WINDOW: usize = 10;
+const MAX_BLOCK_TIME_WINDOW: usize = 1000;
+/// Histogram buckets for how long regenerating the chain stats takes, in microseconds.
+const STATS_REGENERATION_BUCKETS: &[u64] = &[
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 1_000_000,
+];
+/// Histogram buckets for the time between best blocks, in milliseconds.
+const BLOCK_TIME_BUCKETS: &[u64] = &[
+    500, 1_000, 2_000, 3_000, 4_000, 6_000, 8_000, 12_000, 18_000, 24_000, 60_000, 120_000,
+];
+/// How far below the best block we keep count of the imported blocks sent to feeds
+/// at each height, when sampling them.
+const IMPORTED_BLOCK_SAMPLE_DEPTH: u64 = 64;
+/// Chains that had fewer nodes than this are too small for us to alert about
+/// them losing lots of nodes.
+const MIN_NODE_COUNT_DROP_NODES: usize = 10;
+/// How many block times we need to have seen before inferring how long blocks are
+/// expected to take on chains that we aren't told about.
+const MIN_INFERRED_BLOCK_TIMES: u64 = 100;
+
+/// What happens to nodes that have been stale for a long time?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleNodePolicy {
+    /// Keep showing them (greyed out) for as long as they're connected.
+    Keep,
+    /// Remove them once they've been stale for [`StaleTimeouts::removal`].
+    Remove,
+}
+
+impl FromStr for StaleNodePolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(StaleNodePolicy::Keep),
+            "remove" => Ok(StaleNodePolicy::Remove),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'keep' or 'remove', but got '{s}'"
+            )),
+        }
+    }
+}
+
+/// How long before nodes are considered stale, how quickly must they report
+/// blocks to be considered fresh again, and what happens to them if they don't?
+/// Times are in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaleTimeouts {
+    /// A node that hasn't reported a new block for this long becomes stale,
+    /// if the chain also hasn't seen a new best block for this long.
+    pub stale: u64,
+    /// A stale node becomes fresh again once it reports two blocks within this
+    /// time of each other. This should be less than `stale`, but longer than the
+    /// time between blocks.
+    pub recover: u64,
+    /// Are nodes that stay stale kept or removed?
+    pub policy: StaleNodePolicy,
+    /// With [`StaleNodePolicy::Remove`], a stale node that hasn't reported a new block
+    /// for this long is removed. This should be more than `stale`.
+    pub removal: u64,
+}
+
+impl Default for StaleTimeouts {
+    fn default() -> Self {
+        StaleTimeouts {
+            stale: 2 * 60 * 1000, // 2 minutes
+            recover: 60 * 1000,   // 1 minute
+            policy: StaleNodePolicy::Keep,
+            removal: 10 * 60 * 1000, // 10 minutes
+        }
+    }
+}
+
+/// How often are the chain stats worked on, and how long should that take?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsTimings {
+    /// Regenerating the stats taking longer than this is logged.
+    pub regeneration_budget: Duration,
+    /// The counters that the stats are generated from are kept up to date as nodes
+    /// come and go, but are also recounted from scratch this often to correct any drift.
+    pub reconcile_interval: Duration,
+    /// While the aggregator is falling behind on messages, the stats are regenerated this
+    /// often rather than every few seconds.
+    pub under_load_interval: Duration,
+}
+
+impl Default for StatsTimings {
+    fn default() -> Self {
+        StatsTimings {
+            regeneration_budget: Duration::from_millis(50),
+            reconcile_interval: Duration::from_secs(10 * 60),
+            under_load_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// After lots of nodes disconnect at once (eg because a shard restarted), how much over its
+/// maximum number of nodes should a chain let in while they reconnect, and for how long?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaBurst {
+    /// How many more nodes than the maximum can connect during a burst, as a percentage
+    /// of the maximum. Zero disables bursts.
+    pub allowance_percent: usize,
+    /// How long a burst lasts for after the disconnects.
+    pub window: Duration,
+    /// At least this percentage of the maximum number of nodes disconnecting within
+    /// a few seconds of each other counts as a mass disconnect.
+    pub mass_disconnect_percent: usize,
+}
+
+impl Default for QuotaBurst {
+    fn default() -> Self {
+        QuotaBurst {
+            allowance_percent: 10,
+            window: Duration::from_secs(2 * 60),
+            mass_disconnect_percent: 10,
+        }
+    }
+}
+
+/// How many nodes any one operator (going by the node names) can have on a chain, so
+/// that no one operator dominates the node list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperatorCap {
+    /// 0 means that operators can have any number of nodes.
+    pub max_nodes: usize,
+    /// Operators that can have any number of nodes regardless.
+    pub exempt: Arc<HashSet<Box<str>>>,
+}
+
+/// When should feeds be alerted that a chain has suddenly lost lots of its nodes?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeCountDrops {
+    /// Alert when the node count falls by at least this percentage of the highest
+    /// count seen within the node count drop window. Zero disables the alerts.
+    pub percent: usize,
+}
+
+impl Default for NodeCountDrops {
+    fn default() -> Self {
+        NodeCountDrops { percent: 50 }
+    }
+}
+
+/// How far ahead of the label shown for a chain, and for how long, another label that
+/// nodes use has to be before it's shown instead. This stops the label flapping between
+/// two that about as many nodes use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LabelDebounce {
+    /// How many more nodes (counting validators as their label weight) have to use the
+    /// new label than the one shown.
+    pub margin: usize,
+    /// How long the new label has to stay that far ahead.
+    pub window: Duration,
+}
+
+/// When are nodes counted as syncing, and when is a chain mostly syncing?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncingMajority {
+    /// Nodes more than this many blocks behind the best block of the chain are
+    /// counted as syncing. Nodes that are stale, or haven't reported a best block
+    /// yet, aren't.
+    pub blocks_behind: u64,
+    /// The chain is mostly syncing once more than this percentage of its nodes are
+    /// syncing.
+    pub percent: u64,
+}
+
+impl Default for SyncingMajority {
+    fn default() -> Self {
+        SyncingMajority {
+            blocks_behind: 10,
+            percent: 50,
+        }
+    }
+}
+
+/// How much each signal counts towards the health score of a chain. Each signal is a
+/// penalty from 0 (all is well) to 1 (as bad as it gets):
+///
+/// - `finality_gap`: the gap between the best and finalized blocks, as a fraction of the
+///   finality gap that feeds are alerted about.
+/// - `stale_nodes`: the fraction of nodes that are stale.
+/// - `syncing_nodes`: the fraction of nodes that are syncing.
+/// - `block_time`: how far the average block time is from the expected block time, as a
+///   fraction of the expected block time.
+/// - `validator_shortfall`: how many fewer validators there are than expected, as a
+///   fraction of those expected.
+///
+/// Fractions above 1 count as 1, and signals that we can't work out (like the block time,
+/// before we know what to expect) count as 0. The score is then 100 times one less the
+/// weighted average of the penalties, so 100 is perfectly healthy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthWeights {
+    pub finality_gap: u32,
+    pub stale_nodes: u32,
+    pub syncing_nodes: u32,
+    pub block_time: u32,
+    pub validator_shortfall: u32,
+}
+
+impl Default for HealthWeights {
+    fn default() -> Self {
+        HealthWeights {
+            finality_gap: 3,
+            stale_nodes: 2,
+            syncing_nodes: 2,
+            block_time: 2,
+            validator_shortfall: 1,
+        }
+    }
+}
+
+/// Something that feeds are alerted about, which is worth passing on to people who
+/// aren't watching a feed too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainAlert {
+    /// The finalized block has fallen too far behind the best block, or has caught up again.
+    FinalityGap { gap: u64, recovered: bool },
+    /// The chain has too few validators compared to how many are expected, or has enough again.
+    ValidatorShortfall {
+        expected: u64,
+        observed: u64,
+        recovered: bool,
+    },
+    /// The chain has suddenly lost lots of nodes.
+    NodeCountDrop(NodeCountDrop),
+}
+
+/// A sudden fall in the number of nodes on a chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeCountDrop {
+    /// The highest node count seen recently.
+    pub from: usize,
+    /// The node count now.
+    pub to: usize,
+    /// How far back we looked for the highest node count.
+    pub window: Duration,
+}
+
+/// The highest node count of a chain for each second of some recent window.
+#[derive(Debug, Clone)]
+struct NodeCountHistory {
+    window: Duration,
+    peaks: VecDeque<(Instant, usize)>,
+    /// The most recently recorded count.
+    current: usize,
+}
+
+impl NodeCountHistory {
+    fn new(window: Duration) -> Self {
+        NodeCountHistory {
+            window,
+            peaks: VecDeque::new(),
+            current: 0,
+        }
+    }
+
+    /// Note that the node count has changed. The previous count lasted until now,
+    /// so it's noted too in case it's been longer than the window since it was.
+    fn record(&mut self, now: Instant, count: usize) {
+        self.push(now, self.current);
+        self.push(now, count);
+        self.current = count;
+    }
+
+    fn push(&mut self, now: Instant, count: usize) {
+        while matches!(self.peaks.front(), Some(&(at, _)) if now.duration_since(at) > self.window) {
+            self.peaks.pop_front();
+        }
+        match self.peaks.back_mut() {
+            Some((at, peak)) if now.duration_since(*at) < Duration::from_secs(1) => {
+                *peak = (*peak).max(count)
+            }
+            _ => self.peaks.push_back((now, count)),
+        }
+    }
+
+    /// The highest node count seen within the window.
+    fn peak(&self) -> usize {
+        self.peaks.iter().map(|&(_, peak)| peak).max().unwrap_or(0)
+    }
+
+    /// Forget about the counts seen so far, starting again from the count now.
+    fn reset(&mut self, now: Instant, count: usize) {
+        self.peaks.clear();
+        self.peaks.push_back((now, count));
+        self.current = count;
+    }
+}
+
+/// How many block times should we average over?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockTimeWindow {
+    /// The number of block times to average over. If a `duration` is given,
+    /// this is only the size that we start with.
+    pub size: usize,
+    /// If given, once the window has filled up we resize it so that it covers
+    /// roughly this much time, given the current average block time.
+    pub duration: Option<Duration>,
+}
+
+impl Default for BlockTimeWindow {
+    fn default() -> Self {
+        BlockTimeWindow {
+            size: 50,
+            duration: None,
+        }
+    }
+}
+
+/// How much history each of the rolling metrics that chains keep is worked out over, so
+/// that they can be made more responsive or smoother in one place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollingWindows {
+    /// How many block times (and finalization times) to average over.
+    pub block_times: BlockTimeWindow,
+    /// How many finalized block propagation times to average over.
+    pub propagation_times: usize,
+    /// How long to count messages from nodes over to work out the message rate.
+    pub message_rate: Duration,
+    /// How long to count disconnects over to spot a mass disconnect.
+    pub mass_disconnect: Duration,
+    /// How far back to look for the highest node count to spot sudden drops.
+    pub node_count_drop: Duration,
+}
+
+impl Default for RollingWindows {
+    fn default() -> Self {
+        RollingWindows {
+            block_times: BlockTimeWindow::default(),
+            propagation_times: 50,
+            message_rate: Duration::from_secs(10),
+            mass_disconnect: Duration::from_secs(10),
+            node_count_drop: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RollingWindows {
+    /// Check that no window is too small to be meaningful. Counts of messages and
+    /// disconnects are kept per second, so those windows must be whole seconds.
+    pub fn check(&self) -> anyhow::Result<()> {
+        let samples = [
+            ("block time", self.block_times.size),
+            ("propagation time", self.propagation_times),
+        ];
+        for (name, size) in samples {
+            if size < MIN_WINDOW_SAMPLES {
+                anyhow::bail!(
+                    "The {name} window must be at least {MIN_WINDOW_SAMPLES} samples, but is {size}"
+                );
+            }
+        }
+        let durations = [
+            ("block time", self.block_times.duration),
+            ("message rate", Some(self.message_rate)),
+            ("mass disconnect", Some(self.mass_disconnect)),
+            ("node count drop", Some(self.node_count_drop)),
+        ];
+        for (name, duration) in durations {
+            let Some(duration) = duration else {
+                continue;
+            };
+            if duration < MIN_WINDOW_DURATION || duration.subsec_nanos() != 0 {
+                anyhow::bail!(
+                    "The {name} window must be a whole number of seconds, and at least \
+                     {MIN_WINDOW_DURATION:?}, but is {duration:?}"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Settings that new chains are created with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainConfig {
+    /// How much history each rolling metric is worked out over.
+    pub windows: RollingWindows,
+    /// How many block times have to be seen (since the chain was created or came back
+    /// from being stale) before the average block time is given out.
+    pub block_time_warm_up: usize,
+    /// The most blocks that the best block can advance by at once for the time it took to
+    /// count as a block time. Bigger jumps are treated as catching up. `None` for no limit.
+    pub max_block_time_step: Option<u64>,
+    /// When nodes become stale and fresh again.
+    pub stale_timeouts: StaleTimeouts,
+    /// How many recent best blocks to keep hold of.
+    pub recent_blocks_len: usize,
+    /// How many recent average block times to keep hold of.
+    pub block_time_history_len: usize,
+    /// How long regenerating the stats should take, and how often to reconcile them.
+    pub stats_timings: StatsTimings,
+    /// Feeds are alerted once the finalized block falls more than this many
+    /// blocks behind the best block.
+    pub finality_gap_threshold: u64,
+    /// Nodes whose finalized block is more than this many blocks behind the chain's are
+    /// counted as lagging finality.
+    pub finality_lag_threshold: u64,
+    /// Nodes whose clock is more than this many ms ahead of or behind ours are counted
+    /// as having clock skew.
+    pub clock_skew_threshold: u64,
+    /// Nodes whose location moves less than this many metres keep their old location, so
+    /// that feeds aren't told about it again. 0 means that every new location is kept.
+    pub min_location_change: u64,
+    /// Nodes with fewer than this many bytes free on the disk that their database is on
+    /// are counted as low on disk space.
+    pub low_disk_threshold: u64,
+    /// What nodes that don't say whether they're full nodes or light clients count as.
+    pub unreported_node_role: UnreportedNodeRole,
+    /// The scores that nodes are split into hardware tiers by.
+    pub hardware_tiers: HardwareTiers,
+    /// How long nodes need to have been connected for to move up a connection tier.
+    pub connection_tiers: ConnectionTiers,
+    /// How node operators are found in node names.
+    pub operator_pattern: NodeOperatorPattern,
+    /// Should feeds be sent the IP address, sysinfo and hwbench of nodes?
+    pub expose_node_details: bool,
+    /// Which details of nodes feeds are allowed to be sent. The IP address, sysinfo,
+    /// hwbench and custom fields also need node details to be exposed.
+    pub node_detail_fields: NodeDetailFields,
+    /// How far over quota chains go while nodes reconnect after a mass disconnect.
+    pub quota_burst: QuotaBurst,
+    /// Only advance the best block along the branch that the finalized block is on,
+    /// as far as we can tell from the blocks that nodes have reported.
+    pub prefer_finalized_branch: bool,
+    /// Only tell feeds about the first this many nodes to import a block at each
+    /// height. 0 means that feeds are told about every imported block.
+    pub imported_block_sample: usize,
+    /// When feeds are alerted about the chain suddenly losing lots of nodes.
+    pub node_count_drops: NodeCountDrops,
+    /// How much the bandwidth of nodes has to change by before feeds are told.
+    pub hardware_changes: HardwareChanges,
+    /// How many nodes the chain needs before feeds are told about it. First party
+    /// chains are listed however many nodes they have.
+    pub min_listed_nodes: usize,
+    /// How high the best block of the chain has to get before feeds are told about it.
+    /// First party chains are listed whatever their best block is.
+    pub min_listed_height: u64,
+    /// Private chains are never listed, so only feeds that know their genesis hash (and
+    /// have a token that grants them access) can subscribe to them.
+    pub private: bool,
+    /// When the label shown for the chain follows the one that nodes most commonly use.
+    pub label_debounce: LabelDebounce,
+    /// When nodes are syncing, and when feeds are told that most of them are.
+    pub syncing_majority: SyncingMajority,
+    /// How much each signal counts towards the health score of the chain.
+    pub health_weights: HealthWeights,
+    /// How many validators the chain is expected to have, if we know.
+    pub expected_validators: Option<u64>,
+    /// How long (in ms) blocks are expected to take on the chain, if we're told.
+    pub expected_block_time: Option<u64>,
+    /// How frontends should show the chain.
+    pub theme: Theme,
+    /// Feeds are alerted once the chain has more than this many fewer validators
+    /// than expected.
+    pub validator_shortfall_threshold: u64,
+    /// How many hardware, stats and IO messages per second feeds are sent about the
+    /// chain's nodes, at most. 0 means that there's no limit.
+    pub feed_rate_limit: u32,
+    /// A node's import queue counts as backed up once it keeps reporting at least this
+    /// many blocks in it. 0 means that they never do.
+    pub import_queue_backed_up_depth: u64,
+    /// Nodes that have imported less than this percentage of the blocks that they've
+    /// tried to are counted as unhealthy. 0 means that none are.
+    pub min_import_success_percent: u64,
+    /// How many votes towards the chain label each validator gets, where every other
+    /// node gets one.
+    pub validator_label_weight: usize,
+    /// How many nodes any one operator can have on the chain.
+    pub operator_cap: OperatorCap,
+    /// The label used while nodes haven't reported a useful one, where `{hash}` is
+    /// replaced by the start of the genesis hash and `{genesis_hash}` by all of it.
+    pub unlabeled_format: Box<str>,
+    /// How many distinct values of each stat are counted separately.
+    pub cardinality_limits: CardinalityLimits,
+    /// Block authors that haven't produced a block for this long (in ms) are counted
+    /// as inactive.
+    pub block_author_inactive_after: u64,
+    /// Best blocks reported more than this many blocks ahead of the chain's best block
+    /// are ignored. 0 means that none are.
+    pub max_best_block_lead: u64,
+    /// Feeds are told about the average peer count of the chain's nodes when it changes
+    /// by at least this percentage since they were last told.
+    pub average_peers_change_percent: u64,
+    /// How many recently removed nodes to keep track of.
+    pub removed_nodes_len: usize,
+    /// How many recent significant events to keep track of.
+    pub recent_events_len: usize,
+    /// Nodes whose process started less than this long ago (in ms) count as recently
+    /// restarted.
+    pub recent_restart_window: u64,
+    /// Should feeds be told when nodes are added with the same name as others on the chain?
+    pub flag_name_collisions: bool,
+    /// Only nodes added while the chain has at most this many nodes are located. 0 means
+    /// that every node is.
+    pub max_located_nodes: usize,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        ChainConfig {
+            windows: RollingWindows::default(),
+            block_time_warm_up: 0,
+            max_block_time_step: None,
+            stale_timeouts: StaleTimeouts::default(),
+            recent_blocks_len: 20,
+            block_time_history_len: 300,
+            stats_timings: StatsTimings::default(),
+            finality_gap_threshold: 50,
+            finality_lag_threshold: 10,
+            clock_skew_threshold: 1000,
+            min_location_change: 0,
+            low_disk_threshold: 10 * 1024 * 1024 * 1024,
+            unreported_node_role: UnreportedNodeRole::default(),
+            hardware_tiers: HardwareTiers::default(),
+            connection_tiers: ConnectionTiers::default(),
+            operator_pattern: NodeOperatorPattern::default(),
+            expose_node_details: false,
+            node_detail_fields: NodeDetailFields::default(),
+            quota_burst: QuotaBurst::default(),
+            prefer_finalized_branch: false,
+            imported_block_sample: 0,
+            node_count_drops: NodeCountDrops::default(),
+            hardware_changes: HardwareChanges::default(),
+            min_listed_nodes: 1,
+            private: false,
+            min_listed_height: 0,
+            label_debounce: LabelDebounce::default(),
+            syncing_majority: SyncingMajority::default(),
+            health_weights: HealthWeights::default(),
+            expected_validators: None,
+            expected_block_time: None,
+            theme: Theme::default(),
+            validator_shortfall_threshold: 0,
+            import_queue_backed_up_depth: 0,
+            min_import_success_percent: 0,
+            feed_rate_limit: 0,
+            validator_label_weight: 1,
+            operator_cap: OperatorCap::default(),
+            unlabeled_format: "chain-{hash}".into(),
+            cardinality_limits: CardinalityLimits::default(),
+            block_author_inactive_after: 60 * 60 * 1000,
+            max_best_block_lead: 0,
+            average_peers_change_percent: 10,
+            removed_nodes_len: 100,
+            recent_events_len: 100,
+            recent_restart_window: 10 * 60 * 1000,
+            flag_name_collisions: false,
+            max_located_nodes: 0,
+        }
+    }
+}
+
+/// Is a best block that a node reported plausible? It can't be below the node's own
+/// finalized block, or (if we have a best block and a limit) too far ahead of our best.
+fn is_plausible_best(block: &Block, node_finalized: &Block, best: &Block, max_lead: u64) -> bool {
+    if block.height < node_finalized.height {
+        return false;
+    }
+    max_lead == 0 || best.height == 0 || block.height.saturating_sub(best.height) <= max_lead
+}
+
+/// Fill in the label that a chain uses while it has no useful one of its own.
+fn unlabeled_label(format: &str, genesis_hash: &BlockHash) -> Label {
+    let hash = format!("0x{}", hex::encode(&genesis_hash.as_bytes()[..3]));
+    format
+        .replace("{genesis_hash}", &format!("{genesis_hash:?}"))
+        .replace("{hash}", &hash)
+        .into()
+}
+
+/// A best block that the chain has seen recently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RecentBlock {
+    pub height: u64,
+    pub hash: BlockHash,
+    /// When the block first arrived.
+    pub timestamp: Timestamp,
+    /// Time (in ms) since the previous best block arrived, if we saw it.
+    pub block_time: Option<u64>,
+    /// Time (in ms) after the block first arrived that the last node to import it
+    /// did so, if any others have.
+    pub propagation_time: Option<u64>,
+}
+
+/// The average block time of the chain as it was when a new best block arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BlockTimePoint {
+    /// When the best block arrived.
+    pub timestamp: Timestamp,
+    /// The average block time (in ms) including that block.
+    pub average_block_time: u64,
+}
+
+/// Why a node was removed from a chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalReason {
+    /// The node disconnected from its shard.
+    Disconnected,
+    /// The shard that the node was connected to disconnected.
+    ShardDisconnected,
+    /// The node was stale for too long.
+    Stale,
+    /// Another node with the same network ID took its place.
+    Replaced,
+    /// The chain's node limit was lowered below how many nodes it had.
+    Evicted,
+}
+
+/// A node that was removed from the chain recently, kept for auditing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RemovedNodeRecord {
+    pub network_id: NetworkId,
+    pub name: Box<str>,
+    /// The height of the node's best block when it was removed.
+    pub best_height: u64,
+    pub reason: RemovalReason,
+    /// When the node was removed.
+    pub removed_at: Timestamp,
+}
+
+/// Something significant that happened to a chain, kept for reviewing afterwards.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainEvent {
+    /// The best block wasn't on the finalized branch, so a block that is replaced it.
+    Reorg { from: Block, to: Block },
+    /// The finalized block fell too far behind the best block.
+    FinalityStalled { gap: u64 },
+    /// The finalized block caught up with the best block again.
+    FinalityRecovered { gap: u64 },
+    /// The chain suddenly lost lots of nodes.
+    NodeCountDrop { from: usize, to: usize },
+    /// No new best block arrived for long enough that the chain went back to the best
+    /// and finalized blocks of the nodes that aren't stale.
+    StaleRecovery {
+        best_height: u64,
+        finalized_height: u64,
+    },
+    /// The label shown for the chain changed.
+    LabelChanged { from: Label, to: Label },
+}
+
+/// A [`ChainEvent`], and when (unix time in ms) it happened.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainEventRecord {
+    pub at: Timestamp,
+    #[serde(flatten)]
+    pub event: ChainEvent,
+}
+
+/// What compacting a chain did, from [`Chain::compact`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CompactSummary {
+    /// How many nodes are on the chain.
+    pub nodes: usize,
+    /// How many empty node slots, left behind by nodes that have gone, were freed.
+    pub slots_freed: usize,
+    /// The stats that had drifted from what the nodes add up to, and were corrected.
+    pub corrected_stats: Vec<String>,
+}
+
+/// The relay chain that a parachain belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RelayParent {
+    /// Genesis hash of the relay chain.
+    pub genesis_hash: BlockHash,
+    /// The para ID of the parachain on its relay chain, if nodes reported one.
+    pub para_id: Option<u32>,
+}
+
+/// How frontends should show a chain, for dashboards branded per network.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Theme {
+    /// A hex color, like `#e6007a`.
+    pub color: Option<Box<str>>,
+    /// The URL or ID of an icon.
+    pub icon: Option<Box<str>>,
+}
+
+impl Theme {
+    pub fn is_empty(&self) -> bool {
+        self.color.is_none() && self.icon.is_none()
+    }
+}
 
 pub struct Chain {
     /// Labels that nodes use for this chain. We keep track of
     /// the most commonly used label as nodes are added/removed.
     labels: MostSeen<Label>,
+    /// If given, this is always used as the label for the chain
+    /// instead of the one that nodes most commonly use.
+    label_override: Option<Label>,
+    /// Used as the label while the one that nodes most commonly use is blank.
+    unlabeled_label: Label,
+    /// The label shown for the chain, which follows the one that nodes most commonly
+    /// use once that's been far enough ahead for long enough.
+    displayed_label: Label,
+    /// How far ahead, and for how long, another label has to be before it's shown.
+    label_debounce: LabelDebounce,
+    /// The label that's been far enough ahead of the one shown, and since when.
+    pending_label: Option<(Label, Instant)>,
     /// Set of nodes that are in this chain
     nodes: DenseMap<ChainNodeId, Node>,
+    /// How many best blocks each author that nodes tell us about has produced.
+    block_authors: BlockAuthors,
+    /// The height of the last best block that we attributed to an author, so that
+    /// each one is only counted once.
+    last_authored_height: u64,
+    /// Authors that haven't produced a block for this long (in ms) are inactive.
+    block_author_inactive_after: u64,
+    /// Best blocks further than this ahead of ours are ignored; 0 means none are.
+    max_best_block_lead: u64,
+    /// Find the ID of a node given the network ID that it reported
+    nodes_by_network_id: HashMap<NetworkId, ChainNodeId>,
     /// Best block
     best: Block,
     /// Finalized block
     finalized: Block,
+    /// If this is a parachain, the relay chain that it belongs to
+    relay_parent: Option<RelayParent>,
     /// Block times history, stored so we can calculate averages
     block_times: NumStats<u64>,
+    /// How long (if at all) we'd like the block times history to cover
+    block_time_window_duration: Option<Duration>,
+    /// How many block times we need before giving out an average
+    block_time_warm_up: usize,
+    /// The most the best block can advance by at once to count as a block time
+    max_block_time_step: Option<u64>,
+    /// When nodes become stale and fresh again
+    stale_timeouts: StaleTimeouts,
     /// Calculated average block time
     average_block_time: Option<u64>,
+    /// Every block time seen, for metrics
+    block_times_histogram: Histogram,
     /// When the best block first arrived
     timestamp: Option<Timestamp>,
+    /// The most recent best blocks, oldest first
+    recent_blocks: VecDeque<RecentBlock>,
+    /// How many recent best blocks we keep hold of
+    recent_blocks_len: usize,
+    /// The average block time as of each recent best block, oldest first
+    block_time_history: VecDeque<BlockTimePoint>,
+    /// How many recent average block times we keep hold of
+    block_time_history_len: usize,
+    /// The most recently removed nodes, oldest first
+    removed_nodes: VecDeque<RemovedNodeRecord>,
+    /// How many recently removed nodes we keep track of
+    removed_nodes_len: usize,
+    /// The most recent significant events, oldest first
+    recent_events: VecDeque<ChainEventRecord>,
+    /// How many recent significant events we keep track of
+    recent_events_len: usize,
+    /// Nodes whose process started less than this long ago (in ms) were recently restarted
+    recent_restart_window: u64,
+    /// Finalized block propagation times history, stored so we can calculate averages
+    finalized_propagation_times: NumStats<u64>,
+    /// Calculated average finalized block propagation time
+    average_finalized_propagation_time: Option<u64>,
+    /// When the first node reported the current finalized block
+    finalized_timestamp: Option<Timestamp>,
+    /// Times between the finalized block advancing, stored so we can calculate averages
+    finalization_times: NumStats<u64>,
+    /// Calculated average time between the finalized block advancing
+    average_finalization_time: Option<u64>,
     /// Genesis hash of this chain
     genesis_hash: BlockHash,
     /// Maximum number of nodes allowed to connect from this chain
@@ -66,6 +833,133 @@ pub struct Chain {
     stats: ChainStats,
     /// Timestamp of when the stats were last regenerated.
     stats_last_regenerated: Instant,
+    /// How long regenerating the stats has taken, in microseconds.
+    stats_regeneration_times: Histogram,
+    /// How long regenerating the stats should take, and how often to reconcile them.
+    stats_timings: StatsTimings,
+    /// When the stats counters were last recounted from scratch.
+    stats_last_reconciled: Timestamp,
+    /// When the chain was created, ie when its first node was added.
+    first_seen: Timestamp,
+    /// How many messages about nodes on this chain have we seen recently.
+    messages: RollingTotal<u64>,
+    /// How long `messages` counts them over.
+    message_rate_window: Duration,
+    /// How many messages from nodes on this chain couldn't be deserialized.
+    malformed_messages: u64,
+    /// If frozen, updates from nodes on this chain are ignored.
+    frozen: bool,
+    /// Is the aggregator falling behind, so that stats should be regenerated less often?
+    under_load: bool,
+    /// How many blocks the finalized block is behind the best block
+    best_finalized_gap: u64,
+    /// Feeds are alerted when the gap between best and finalized exceeds this
+    finality_gap_threshold: u64,
+    /// Nodes with a finalized block more than this far behind the chain's are lagging finality
+    finality_lag_threshold: u64,
+    /// Nodes with a clock more than this many ms off ours are counted as having clock skew
+    clock_skew_threshold: u64,
+    /// Node locations that move less than this many metres are ignored
+    min_location_change: u64,
+    /// Nodes with fewer than this many bytes of disk free are counted as low on disk space
+    low_disk_threshold: u64,
+    /// What nodes that don't say whether they're full nodes or light clients count as
+    unreported_node_role: UnreportedNodeRole,
+    /// Have we alerted feeds about the finality gap, and not yet told them it's recovered?
+    finality_gap_alerted: bool,
+    /// Has a node told us that nothing past genesis has been finalized yet? Otherwise, a
+    /// chain with nothing finalized is one we've only just heard about, rather than one
+    /// that finality is yet to start on.
+    awaiting_finality: bool,
+    /// Have we told feeds that the chain has started finalizing blocks? This is only
+    /// ever done once, even if the finalized block falls back to genesis.
+    finality_started: bool,
+    /// When nodes are syncing, and when most of them are.
+    syncing_majority: SyncingMajority,
+    /// How much each signal counts towards the health score
+    health_weights: HealthWeights,
+    /// Have we told feeds that most nodes are syncing, and not yet that they've caught up?
+    mostly_syncing: bool,
+    /// How many validators the chain is expected to have, if we know.
+    expected_validators: Option<u64>,
+    /// How long (in ms) blocks are expected to take on the chain, if we were told
+    expected_block_time: Option<u64>,
+    /// How frontends should show the chain
+    theme: Theme,
+    /// Feeds are alerted when the validators fall more than this short of those expected.
+    validator_shortfall_threshold: u64,
+    /// Have we alerted feeds about a validator shortfall, and not yet told them it's recovered?
+    validator_shortfall_alerted: bool,
+    /// How much (in percent) the average peer count has to change before feeds are told.
+    average_peers_change_percent: u64,
+    /// The average peer count that feeds were last told about.
+    reported_average_peers: Option<f64>,
+    /// Caps how many non-critical messages about nodes are sent to feeds.
+    feed_rate_limiter: FeedRateLimiter,
+    /// Import queues with at least this many blocks in them for a while are backed up (0 for never)
+    import_queue_backed_up_depth: u64,
+    /// Nodes importing less than this percentage of the blocks they try to are unhealthy (0 for never)
+    min_import_success_percent: u64,
+    /// How long nodes need to have been connected for to move up a connection tier
+    connection_tiers: ConnectionTiers,
+    /// Are feeds sent the IP address, sysinfo and hwbench of nodes on this chain?
+    expose_node_details: bool,
+    /// Which details of nodes feeds are allowed to be sent, if exposed.
+    node_detail_fields: NodeDetailFields,
+    /// How far over quota we go while nodes reconnect after a mass disconnect.
+    quota_burst: QuotaBurst,
+    /// How many nodes on this chain have disconnected recently.
+    disconnects: RollingTotal<usize>,
+    /// Until when we're letting in more than `max_nodes` nodes, if we are.
+    quota_burst_until: Option<Instant>,
+    /// When we last told feeds when nodes were last seen.
+    last_seen_reported: Timestamp,
+    /// Do we ignore best blocks from nodes that are on a different branch to the finalized block?
+    prefer_finalized_branch: bool,
+    /// How many nodes per block height feeds are told have imported a block (0 for all of them)
+    imported_block_sample: usize,
+    /// How many nodes feeds have been told about at each recent block height
+    imported_blocks_sent: BTreeMap<u64, usize>,
+    /// Feeds are alerted when the node count drops by at least this percentage (0 to never alert)
+    node_count_drop_percent: usize,
+    /// Recent node counts, to spot sudden drops in
+    node_count_history: NodeCountHistory,
+    /// How much the bandwidth of nodes has to change by before feeds are told
+    hardware_changes: HardwareChanges,
+    /// How many nodes the chain needs before feeds are told about it
+    min_listed_nodes: usize,
+    /// How high the best block needs to get before feeds are told about the chain
+    min_listed_height: u64,
+    /// Has the best block ever been high enough for feeds to be told about the chain?
+    reached_listed_height: bool,
+    /// Private chains are never listed
+    private: bool,
+    /// Has the chain become listed by its best block getting high enough, without feeds being told?
+    listed_by_height: bool,
+    /// Nodes that have been stale for long enough to be removed, waiting to be taken
+    expired_nodes: Vec<ChainNodeId>,
+    /// When we last looked for nodes that have been stale for long enough to be removed
+    stale_nodes_last_expired: Timestamp,
+    /// How many votes towards the chain label each validator gets (other nodes get 1)
+    validator_label_weight: usize,
+    /// Has the label changed outside of adding or removing nodes, without feeds being told?
+    label_changed: bool,
+    /// Alerts that feeds have been sent, waiting to be taken and passed on elsewhere
+    alerts: Vec<ChainAlert>,
+    /// How many nodes any one operator can have on the chain
+    operator_cap: OperatorCap,
+    /// How many nodes each operator has on the chain, if operators are capped
+    nodes_per_operator: HashMap<Box<str>, usize>,
+    /// Do we notice nodes being added with the same name as others?
+    flag_name_collisions: bool,
+    /// How many nodes have each name, if we're noticing name collisions
+    nodes_per_name: HashMap<Box<str>, usize>,
+    /// How many nodes report being in each session
+    sessions: MostSeen<u64>,
+    /// The session that most nodes are in, which only ever moves forwards
+    session: Option<u64>,
+    /// Nodes added while the chain has more nodes than this aren't located (0 to locate all)
+    max_located_nodes: usize,
 }
 
 pub enum AddNodeResult {
@@ -73,11 +967,24 @@ pub enum AddNodeResult {
     Added {
         id: ChainNodeId,
         chain_renamed: bool,
+        relay_parent_changed: bool,
+        /// An existing node that reports the same network ID as the one added.
+        duplicate_of: Option<ChainNodeId>,
+        /// Is the duplicate the same node connecting again (see [`Chain::get_reconnect_of`])?
+        is_reconnect: bool,
+        /// How many other nodes on the chain have the same name as the one added, if
+        /// we're noticing name collisions.
+        name_collisions: usize,
+        /// Has the chain's best or finalized block moved on because the node added was
+        /// already ahead of it (see [`Chain::catch_up_with`])?
+        blocks_changed: bool,
     },
 }
 
 pub struct RemoveNodeResult {
     pub chain_renamed: bool,
+    /// Set if removing the node means that the chain has suddenly lost lots of nodes.
+    pub node_count_drop: Option<NodeCountDrop>,
 }
 
 /// Genesis hashes of chains we consider "first party". These chains allow any
@@ -102,28 +1009,313 @@ pub fn is_first_party_network(genesis_hash: &BlockHash) -> bool {
     FIRST_PARTY_NETWORKS.contains(genesis_hash)
 }
 
+/// The genesis hashes of every chain that we consider "first party", in order.
+pub fn first_party_networks() -> Vec<BlockHash> {
+    let mut genesis_hashes: Vec<_> = FIRST_PARTY_NETWORKS.iter().copied().collect();
+    genesis_hashes.sort();
+    genesis_hashes
+}
+
+/// Should feeds be told that a node has imported a block at the height given? If we're
+/// sampling, only the first `sample` nodes to import a block at each height are sent.
+fn sample_imported_block(
+    sent: &mut BTreeMap<u64, usize>,
+    sample: usize,
+    height: u64,
+    best_height: u64,
+) -> bool {
+    if sample == 0 {
+        return true;
+    }
+
+    // Forget about heights that are well behind the best block:
+    let oldest = best_height.saturating_sub(IMPORTED_BLOCK_SAMPLE_DEPTH);
+    while let Some(entry) = sent.first_entry() {
+        if *entry.key() >= oldest {
+            break;
+        }
+        entry.remove();
+    }
+    // Nodes far behind have nothing to be counted against, so we let them through:
+    if height < oldest {
+        return true;
+    }
+
+    let count = sent.entry(height).or_default();
+    if *count >= sample {
+        return false;
+    }
+    *count += 1;
+    true
+}
+
+/// How many whole seconds (at least one) a window covers, for counts kept per second.
+fn whole_secs(window: Duration) -> usize {
+    (window.as_secs() as usize).max(1)
+}
+
+/// If we've been asked to average block times over some duration, resize the
+/// block time window to cover that duration once it has filled up.
+fn scale_block_time_window(block_times: &mut NumStats<u64>, duration: Option<Duration>) {
+    let duration = match duration {
+        Some(duration) => duration,
+        None => return,
+    };
+    if !block_times.is_full() {
+        return;
+    }
+
+    let average = block_times.average().max(1);
+    let size = (duration.as_millis() as u64 / average) as usize;
+    let size = size.clamp(MIN_BLOCK_TIME_WINDOW, MAX_BLOCK_TIME_WINDOW);
+
+    // Ignore small changes so that we aren't resizing on every block:
+    let current = block_times.size();
+    if size.abs_diff(current) * 10 > current {
+        block_times.resize(size);
+    }
+}
+
+/// The distance between two locations along the surface of the Earth, in metres.
+fn distance_in_metres(a: &NodeLocation, b: &NodeLocation) -> f64 {
+    const EARTH_RADIUS_IN_METRES: f64 = 6_371_000.0;
+    let (lat_a, lat_b) = (
+        (a.latitude as f64).to_radians(),
+        (b.latitude as f64).to_radians(),
+    );
+    let d_lat = lat_b - lat_a;
+    let d_lon = (b.longitude as f64 - a.longitude as f64).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_IN_METRES * h.sqrt().min(1.0).asin()
+}
+
+/// `percent`% of `value`, rounded down.
+fn percent_of(value: usize, percent: usize) -> usize {
+    (value as u128 * percent as u128 / 100).min(usize::MAX as u128) as usize
+}
+
+/// The median of some values, or `None` if there aren't any. The values are reordered.
+fn median(values: &mut [u64]) -> Option<u64> {
+    if values.is_empty() {
+        return None;
+    }
+    let len = values.len();
+    let (lower, &mut upper_mid, _) = values.select_nth_unstable(len / 2);
+    if len.is_multiple_of(2) {
+        let lower_mid = *lower.iter().max().expect("non-empty when length is even");
+        Some(lower_mid + (upper_mid - lower_mid) / 2)
+    } else {
+        Some(upper_mid)
+    }
+}
+
+/// Remember a new best block, forgetting the oldest one if we have too many. If the best
+/// block has gone backwards (because the chain went stale), anything at or above the new
+/// height is forgotten too, so that the recent blocks are always in height order.
+fn push_recent_block(recent_blocks: &mut VecDeque<RecentBlock>, len: usize, block: RecentBlock) {
+    if len == 0 {
+        return;
+    }
+    while recent_blocks
+        .back()
+        .is_some_and(|last| last.height >= block.height)
+    {
+        recent_blocks.pop_back();
+    }
+    if recent_blocks.len() >= len {
+        recent_blocks.pop_front();
+    }
+    recent_blocks.push_back(block);
+}
+
+/// Move the chain on to the session that most of its nodes are in, if that's a later one
+/// than it was in, telling feeds about the rotation. Validator counts are expected to
+/// change around rotations, so feeds can take any alerts then with a pinch of salt.
+fn rotate_session(
+    sessions: &MostSeen<u64>,
+    session: &mut Option<u64>,
+    feed: &mut FeedMessageSerializer,
+) {
+    if sessions.best_count() == 0 {
+        return;
+    }
+    let consensus = *sessions.best();
+    match *session {
+        Some(from) if consensus > from => {
+            feed.push(feed_message::SessionRotation(from, consensus));
+            *session = Some(consensus);
+        }
+        None => *session = Some(consensus),
+        _ => {}
+    }
+}
+
 impl Chain {
     /// Create a new chain with an initial label.
-    pub fn new(genesis_hash: BlockHash, max_nodes: usize) -> Self {
+    pub fn new(
+        genesis_hash: BlockHash,
+        max_nodes: usize,
+        label_override: Option<Label>,
+        config: ChainConfig,
+    ) -> Self {
         Chain {
             labels: MostSeen::default(),
+            label_override,
+            unlabeled_label: unlabeled_label(&config.unlabeled_format, &genesis_hash),
+            displayed_label: Label::default(),
+            label_debounce: config.label_debounce,
+            pending_label: None,
             nodes: DenseMap::new(),
+            block_authors: BlockAuthors::default(),
+            last_authored_height: 0,
+            block_author_inactive_after: config.block_author_inactive_after,
+            max_best_block_lead: config.max_best_block_lead,
+            nodes_by_network_id: HashMap::new(),
             best: Block::zero(),
             finalized: Block::zero(),
-            block_times: NumStats::new(50),
+            relay_parent: None,
+            block_times: NumStats::new(config.windows.block_times.size.max(1)),
+            block_time_window_duration: config.windows.block_times.duration,
+            block_time_warm_up: config.block_time_warm_up,
+            max_block_time_step: config.max_block_time_step,
+            stale_timeouts: config.stale_timeouts,
             average_block_time: None,
+            block_times_histogram: Histogram::new(BLOCK_TIME_BUCKETS),
             timestamp: None,
+            recent_blocks: VecDeque::with_capacity(config.recent_blocks_len),
+            recent_blocks_len: config.recent_blocks_len,
+            block_time_history: VecDeque::with_capacity(config.block_time_history_len),
+            block_time_history_len: config.block_time_history_len,
+            removed_nodes: VecDeque::new(),
+            removed_nodes_len: config.removed_nodes_len,
+            recent_events: VecDeque::new(),
+            recent_events_len: config.recent_events_len,
+            recent_restart_window: config.recent_restart_window,
+            finalized_propagation_times: NumStats::new(config.windows.propagation_times.max(1)),
+            average_finalized_propagation_time: None,
+            finalized_timestamp: None,
+            finalization_times: NumStats::new(config.windows.block_times.size.max(1)),
+            average_finalization_time: None,
             genesis_hash,
             max_nodes,
-            stats_collator: Default::default(),
+            stats_collator: ChainStatsCollator::new(
+                config.hardware_tiers,
+                config.operator_pattern,
+                config.cardinality_limits,
+            ),
             stats: Default::default(),
             stats_last_regenerated: Instant::now(),
+            stats_regeneration_times: Histogram::new(STATS_REGENERATION_BUCKETS),
+            stats_timings: config.stats_timings,
+            // A new chain has no nodes to miscount:
+            stats_last_reconciled: time::now(),
+            first_seen: time::now(),
+            messages: RollingTotalBuilder::new()
+                .granularity(Duration::from_secs(1))
+                .window_size_multiple(whole_secs(config.windows.message_rate))
+                .start(),
+            message_rate_window: config.windows.message_rate,
+            malformed_messages: 0,
+            frozen: false,
+            under_load: false,
+            best_finalized_gap: 0,
+            finality_gap_threshold: config.finality_gap_threshold,
+            finality_lag_threshold: config.finality_lag_threshold,
+            clock_skew_threshold: config.clock_skew_threshold,
+            min_location_change: config.min_location_change,
+            low_disk_threshold: config.low_disk_threshold,
+            unreported_node_role: config.unreported_node_role,
+            finality_gap_alerted: false,
+            awaiting_finality: false,
+            finality_started: false,
+            syncing_majority: config.syncing_majority,
+            health_weights: config.health_weights,
+            mostly_syncing: false,
+            expected_validators: config.expected_validators,
+            expected_block_time: config.expected_block_time,
+            theme: config.theme,
+            validator_shortfall_threshold: config.validator_shortfall_threshold,
+            validator_shortfall_alerted: false,
+            average_peers_change_percent: config.average_peers_change_percent,
+            reported_average_peers: None,
+            feed_rate_limiter: FeedRateLimiter::new(config.feed_rate_limit),
+            import_queue_backed_up_depth: config.import_queue_backed_up_depth,
+            min_import_success_percent: config.min_import_success_percent,
+            connection_tiers: config.connection_tiers,
+            expose_node_details: config.expose_node_details,
+            node_detail_fields: config.node_detail_fields,
+            quota_burst: config.quota_burst,
+            disconnects: RollingTotalBuilder::new()
+                .granularity(Duration::from_secs(1))
+                .window_size_multiple(whole_secs(config.windows.mass_disconnect))
+                .start(),
+            quota_burst_until: None,
+            last_seen_reported: time::now(),
+            prefer_finalized_branch: config.prefer_finalized_branch,
+            imported_block_sample: config.imported_block_sample,
+            imported_blocks_sent: BTreeMap::new(),
+            node_count_drop_percent: config.node_count_drops.percent,
+            node_count_history: NodeCountHistory::new(config.windows.node_count_drop),
+            hardware_changes: config.hardware_changes,
+            min_listed_nodes: config.min_listed_nodes,
+            min_listed_height: config.min_listed_height,
+            reached_listed_height: config.min_listed_height == 0,
+            private: config.private,
+            listed_by_height: false,
+            expired_nodes: Vec::new(),
+            stale_nodes_last_expired: 0,
+            validator_label_weight: config.validator_label_weight.max(1),
+            label_changed: false,
+            alerts: Vec::new(),
+            operator_cap: config.operator_cap,
+            nodes_per_operator: HashMap::new(),
+            flag_name_collisions: config.flag_name_collisions,
+            nodes_per_name: HashMap::new(),
+            sessions: MostSeen::default(),
+            session: None,
+            max_located_nodes: config.max_located_nodes,
         }
     }
 
-    /// Is the chain the node belongs to overquota?
+    /// Is the chain the node belongs to overquota? Shortly after a mass disconnect,
+    /// we let in a few more nodes than usual so that they can all reconnect.
     pub fn is_overquota(&self) -> bool {
-        self.nodes.len() >= self.max_nodes
+        let max_nodes = if self.in_quota_burst() {
+            self.max_nodes.saturating_add(percent_of(
+                self.max_nodes,
+                self.quota_burst.allowance_percent,
+            ))
+        } else {
+            self.max_nodes
+        };
+        self.nodes.len() >= max_nodes
+    }
+
+    fn in_quota_burst(&self) -> bool {
+        self.quota_burst_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Keep track of nodes disconnecting, starting a quota burst if lots have at once.
+    fn note_disconnect(&mut self) {
+        self.disconnects.push(1);
+        if self.quota_burst.allowance_percent == 0 {
+            return;
+        }
+        let mass_disconnect =
+            percent_of(self.max_nodes, self.quota_burst.mass_disconnect_percent).max(1);
+        if self.disconnects.total() >= mass_disconnect {
+            if !self.in_quota_burst() {
+                log::info!(
+                    "[{}] {} nodes disconnected recently; allowing {}% more nodes for {:?}",
+                    self.label(),
+                    self.disconnects.total(),
+                    self.quota_burst.allowance_percent,
+                    self.quota_burst.window,
+                );
+            }
+            self.quota_burst_until = Some(Instant::now() + self.quota_burst.window);
+        }
     }
 
     /// Assign a node to this chain.
@@ -133,108 +1325,578 @@ impl Chain {
         }
 
         let details = node.details();
-        self.stats_collator
-            .add_or_remove_node(details, None, CounterValue::Increment);
+        self.stats_collator.add_or_remove_node(
+            details,
+            node.hwbench(),
+            node.location(),
+            CounterValue::Increment,
+        );
+        // Nodes moved here from another chain may already be in a bad way:
+        if node.import_queue_backed_up() {
+            self.stats_collator
+                .update_backed_up_import_queues(CounterValue::Increment);
+        }
+        if node.import_unhealthy(self.min_import_success_percent) {
+            self.stats_collator
+                .update_unhealthy_imports(CounterValue::Increment);
+        }
+
+        if self.operator_cap.max_nodes > 0 {
+            let operator = self
+                .stats_collator
+                .operator_pattern()
+                .operator(&details.name);
+            *self.nodes_per_operator.entry(operator.into()).or_default() += 1;
+        }
+
+        let name_collisions = if self.flag_name_collisions {
+            let count = self.nodes_per_name.entry(details.name.clone()).or_default();
+            *count += 1;
+            *count - 1
+        } else {
+            0
+        };
 
         let node_chain_label = &details.chain;
-        let label_result = self.labels.insert(node_chain_label);
+        self.labels
+            .insert_weighted(node_chain_label, self.label_weight(details));
+        if let Some(session) = node.session() {
+            self.sessions.insert(&session);
+        }
+        let label_changed = self.update_displayed_label(Instant::now());
+        let network_id = details.network_id;
+        let is_reconnect = self.get_reconnect_of(&node).is_some();
+        let relay_parent_changed = self.update_relay_parent(details);
+        let blocks_changed = self.catch_up_with(&node);
         let node_id = self.nodes.add(node);
+        self.node_count_history
+            .record(Instant::now(), self.nodes.len());
+
+        // If more than one node reports the same network ID, the index points
+        // at the most recently added one.
+        let duplicate_of = if network_id.is_empty() {
+            None
+        } else {
+            self.nodes_by_network_id.insert(network_id, node_id)
+        };
 
         AddNodeResult::Added {
             id: node_id,
-            chain_renamed: label_result.has_changed(),
+            chain_renamed: self.label_override.is_none() && label_changed,
+            relay_parent_changed,
+            is_reconnect: is_reconnect && duplicate_of.is_some(),
+            duplicate_of,
+            name_collisions,
+            blocks_changed,
         }
     }
 
-    /// Remove a node from this chain.
-    pub fn remove_node(&mut self, node_id: ChainNodeId) -> RemoveNodeResult {
-        let node = match self.nodes.remove(node_id) {
-            Some(node) => node,
-            None => {
-                return RemoveNodeResult {
-                    chain_renamed: false,
-                }
+    /// Nodes moved over from another chain arrive knowing about blocks already. If the
+    /// node is ahead of the chain, take its best and finalized blocks as the chain's own,
+    /// as if it had just reported them. Returns `true` if either changed.
+    fn catch_up_with(&mut self, node: &Node) -> bool {
+        let mut changed = false;
+        if node.best().height > self.best.height {
+            self.best = *node.best();
+            self.timestamp = Some(node.best_timestamp());
+            if self.best.height >= self.min_listed_height {
+                self.reached_listed_height = true;
             }
+            changed = true;
+        }
+        if node.finalized().height > self.finalized.height {
+            self.finalized = *node.finalized();
+            self.finalized_timestamp = node.finalized_at();
+            changed = true;
+        }
+        if changed {
+            self.best_finalized_gap = self.best.height.saturating_sub(self.finalized.height);
+        }
+        changed
+    }
+
+    /// The first node to report a relay chain decides which relay chain this chain
+    /// belongs to. Nodes can also fill in a para ID that wasn't known before. Returns
+    /// `true` if the relay parent changed.
+    fn update_relay_parent(&mut self, details: &NodeDetails) -> bool {
+        let genesis_hash = match details.relay_chain_genesis_hash {
+            // A chain can't be its own relay chain:
+            Some(hash) if hash != self.genesis_hash && !hash.is_zero() => hash,
+            _ => return false,
         };
 
-        let details = node.details();
-        self.stats_collator
-            .add_or_remove_node(details, node.hwbench(), CounterValue::Decrement);
+        match &mut self.relay_parent {
+            None => {
+                self.relay_parent = Some(RelayParent {
+                    genesis_hash,
+                    para_id: details.para_id,
+                });
+                true
+            }
+            Some(relay_parent)
+                if relay_parent.genesis_hash == genesis_hash
+                    && relay_parent.para_id.is_none()
+                    && details.para_id.is_some() =>
+            {
+                relay_parent.para_id = details.para_id;
+                true
+            }
+            Some(_) => false,
+        }
+    }
 
-        let node_chain_label = &node.details().chain;
-        let label_result = self.labels.remove(node_chain_label);
+    /// Does the operator of a node with the given name already have as many nodes on
+    /// the chain as operators are allowed?
+    pub fn is_operator_at_cap(&self, node_name: &str) -> bool {
+        if self.operator_cap.max_nodes == 0 {
+            return false;
+        }
+        let operator = self.stats_collator.operator_pattern().operator(node_name);
+        !self.operator_cap.exempt.contains(operator)
+            && self.nodes_per_operator.get(operator).copied().unwrap_or(0)
+                >= self.operator_cap.max_nodes
+    }
 
-        RemoveNodeResult {
-            chain_renamed: label_result.has_changed(),
+    /// Has the label changed since this was last called, other than by adding or
+    /// removing nodes? Feeds need telling if so.
+    pub fn take_label_changed(&mut self) -> bool {
+        std::mem::take(&mut self.label_changed)
+    }
+
+    /// Has the chain become listed since this was last called, because its best block
+    /// got high enough? Feeds need telling if so.
+    pub fn take_listed_by_height(&mut self) -> bool {
+        std::mem::take(&mut self.listed_by_height)
+    }
+
+    /// Hand back the alerts that feeds have been sent since this was last called.
+    pub fn take_alerts(&mut self) -> Vec<ChainAlert> {
+        std::mem::take(&mut self.alerts)
+    }
+
+    /// How many votes towards the chain label a node with these details gets.
+    fn label_weight(&self, details: &NodeDetails) -> usize {
+        if details.validator.is_some() {
+            self.validator_label_weight
+        } else {
+            1
         }
     }
 
-    /// Attempt to update the best block seen in this chain.
-    pub fn update_node(
-        &mut self,
-        nid: ChainNodeId,
-        payload: Payload,
-        feed: &mut FeedMessageSerializer,
-        expose_node_details: bool,
-    ) {
-        if let Some(block) = payload.best_block() {
-            self.handle_block(block, nid, feed);
+    /// Show the label that nodes most commonly use instead of the one shown, if it's been
+    /// far enough ahead for long enough. Returns whether the label shown changed.
+    fn update_displayed_label(&mut self, now: Instant) -> bool {
+        let best = self.labels.best();
+        if *best == self.displayed_label {
+            self.pending_label = None;
+            return false;
         }
 
-        if let Some(node) = self.nodes.get_mut(nid) {
-            match payload {
-                Payload::SystemInterval(ref interval) => {
-                    // Send a feed message if any of the relevant node details change:
-                    if node.update_hardware(interval) {
-                        feed.push(feed_message::Hardware(nid.into(), node.hardware()));
-                    }
-                    if let Some(stats) = node.update_stats(interval) {
-                        feed.push(feed_message::NodeStatsUpdate(nid.into(), stats));
-                    }
-                    if let Some(io) = node.update_io(interval) {
-                        feed.push(feed_message::NodeIOUpdate(nid.into(), io));
-                    }
-                }
-                Payload::AfgAuthoritySet(authority) => {
-                    // If our node validator address (and thus details) change, send an
-                    // updated "add node" feed message:
-                    if node.set_validator_address(authority.authority_id.clone()) {
-                        feed.push(feed_message::AddedNode(
-                            nid.into(),
-                            &node,
-                            expose_node_details,
-                        ));
-                    }
-                    return;
+        // Labels that no node uses any more are replaced straight away:
+        let displayed_count = self.labels.count(&self.displayed_label);
+        let lead = self.labels.best_count().saturating_sub(displayed_count);
+        let show = if displayed_count == 0 {
+            true
+        } else if lead < self.label_debounce.margin {
+            self.pending_label = None;
+            false
+        } else {
+            let since = match &self.pending_label {
+                Some((label, since)) if label == best => *since,
+                _ => {
+                    self.pending_label = Some((best.clone(), now));
+                    now
                 }
-                Payload::HwBench(ref hwbench) => {
-                    let new_hwbench = common::node_types::NodeHwBench {
-                        cpu_hashrate_score: hwbench.cpu_hashrate_score,
-                        memory_memcpy_score: hwbench.memory_memcpy_score,
-                        disk_sequential_write_score: hwbench.disk_sequential_write_score,
-                        disk_random_write_score: hwbench.disk_random_write_score,
-                    };
-                    let old_hwbench = node.update_hwbench(new_hwbench);
-                    // The `hwbench` for this node has changed, send an updated "add node".
-                    // Note: There is no need to send this message if the details
-                    // will not be serialized over the wire.
-                    if expose_node_details {
-                        feed.push(feed_message::AddedNode(
-                            nid.into(),
-                            &node,
-                            expose_node_details,
-                        ));
-                    }
+            };
+            now.duration_since(since) >= self.label_debounce.window
+        };
 
-                    self.stats_collator
-                        .update_hwbench(old_hwbench.as_ref(), CounterValue::Decrement);
-                    self.stats_collator
-                        .update_hwbench(node.hwbench(), CounterValue::Increment);
+        if show {
+            let from = std::mem::replace(&mut self.displayed_label, best.clone());
+            self.pending_label = None;
+            // A chain being given its first label isn't worth noting:
+            if self.label_override.is_none() && !from.is_empty() {
+                self.record_event(ChainEvent::LabelChanged {
+                    from,
+                    to: self.displayed_label.clone(),
+                });
+            }
+        }
+        show
+    }
+
+    /// Hand back the nodes that have been stale for long enough to be removed. They're
+    /// left in place, so that whoever takes them can remove them as usual.
+    pub fn take_expired_nodes(&mut self) -> Vec<ChainNodeId> {
+        std::mem::take(&mut self.expired_nodes)
+    }
+
+    /// Remove a node from this chain.
+    pub fn remove_node(&mut self, node_id: ChainNodeId, reason: RemovalReason) -> RemoveNodeResult {
+        let old_label: Label = self.label().into();
+        let Some(node) = self.extract_node(node_id) else {
+            return RemoveNodeResult {
+                chain_renamed: false,
+                node_count_drop: None,
+            };
+        };
+
+        self.note_disconnect();
+        self.record_removed_node(&node, reason);
+
+        let node_count_drop = self.check_node_count_drop();
+        if let Some(drop) = node_count_drop {
+            self.record_event(ChainEvent::NodeCountDrop {
+                from: drop.from,
+                to: drop.to,
+            });
+        }
+        RemoveNodeResult {
+            chain_renamed: self.label() != &*old_label,
+            node_count_drop,
+        }
+    }
+
+    /// Keep a summary of a node that's been removed, forgetting the oldest one if we
+    /// already have as many as we keep.
+    fn record_removed_node(&mut self, node: &Node, reason: RemovalReason) {
+        if self.removed_nodes_len == 0 {
+            return;
+        }
+        if self.removed_nodes.len() >= self.removed_nodes_len {
+            self.removed_nodes.pop_front();
+        }
+        self.removed_nodes.push_back(RemovedNodeRecord {
+            network_id: node.details().network_id,
+            name: node.details().name.clone(),
+            best_height: node.best().height,
+            reason,
+            removed_at: time::now(),
+        });
+    }
+
+    /// The nodes that have been removed from the chain most recently, oldest first.
+    pub fn removed_nodes(&self) -> &VecDeque<RemovedNodeRecord> {
+        &self.removed_nodes
+    }
+
+    /// Keep hold of a significant event, forgetting the oldest one if we already have
+    /// as many as we keep.
+    fn record_event(&mut self, event: ChainEvent) {
+        if self.recent_events_len == 0 {
+            return;
+        }
+        if self.recent_events.len() >= self.recent_events_len {
+            self.recent_events.pop_front();
+        }
+        self.recent_events.push_back(ChainEventRecord {
+            at: time::now(),
+            event,
+        });
+    }
+
+    /// The most recent significant events on the chain, oldest first.
+    pub fn recent_events(&self) -> &VecDeque<ChainEventRecord> {
+        &self.recent_events
+    }
+
+    /// Has the node count fallen far enough below its recent peak to alert feeds? Once
+    /// we have alerted, only another big drop from here will do so again.
+    fn check_node_count_drop(&mut self) -> Option<NodeCountDrop> {
+        if self.node_count_drop_percent == 0 {
+            return None;
+        }
+        let from = self.node_count_history.peak();
+        let to = self.nodes.len();
+        let drop = from.saturating_sub(to);
+        if from < MIN_NODE_COUNT_DROP_NODES || drop < percent_of(from, self.node_count_drop_percent)
+        {
+            return None;
+        }
+
+        log::info!(
+            "[{}] node count dropped from {} to {} within {:?}",
+            self.label(),
+            from,
+            to,
+            self.node_count_history.window,
+        );
+        self.node_count_history.reset(Instant::now(), to);
+        Some(NodeCountDrop {
+            from,
+            to,
+            window: self.node_count_history.window,
+        })
+    }
+
+    /// Remove a node from this chain without counting it as a disconnect, handing it
+    /// back with its state intact so that it can be added to another chain.
+    pub fn extract_node(&mut self, node_id: ChainNodeId) -> Option<Node> {
+        let node = self.nodes.remove(node_id)?;
+        self.node_count_history
+            .record(Instant::now(), self.nodes.len());
+
+        let details = node.details();
+        self.stats_collator.add_or_remove_node(
+            details,
+            node.hwbench(),
+            node.location(),
+            CounterValue::Decrement,
+        );
+        if node.import_queue_backed_up() {
+            self.stats_collator
+                .update_backed_up_import_queues(CounterValue::Decrement);
+        }
+        if node.import_unhealthy(self.min_import_success_percent) {
+            self.stats_collator
+                .update_unhealthy_imports(CounterValue::Decrement);
+        }
+
+        self.labels
+            .remove_weighted(&node.details().chain, self.label_weight(node.details()));
+        self.update_displayed_label(Instant::now());
+        if let Some(session) = node.session() {
+            self.sessions.remove(&session);
+        }
+
+        if self.operator_cap.max_nodes > 0 {
+            let operator = self
+                .stats_collator
+                .operator_pattern()
+                .operator(&details.name);
+            if let Some(count) = self.nodes_per_operator.get_mut(operator) {
+                *count -= 1;
+                if *count == 0 {
+                    self.nodes_per_operator.remove(operator);
+                }
+            }
+        }
+
+        if self.flag_name_collisions {
+            if let Some(count) = self.nodes_per_name.get_mut(&*details.name) {
+                *count -= 1;
+                if *count == 0 {
+                    self.nodes_per_name.remove(&*details.name);
+                }
+            }
+        }
+
+        // If another node reports the same network ID, the index points at that instead:
+        let network_id = &node.details().network_id;
+        if self.nodes_by_network_id.get(network_id.as_str()) == Some(&node_id) {
+            let duplicate = self
+                .nodes
+                .iter()
+                .find(|(_, other)| other.details().network_id == *network_id)
+                .map(|(id, _)| id);
+            match duplicate {
+                Some(id) => self.nodes_by_network_id.insert(*network_id, id),
+                None => self.nodes_by_network_id.remove(network_id.as_str()),
+            };
+        }
+
+        Some(node)
+    }
+
+    /// Attempt to update the best block seen in this chain.
+    pub fn update_node(
+        &mut self,
+        nid: ChainNodeId,
+        payload: Payload,
+        feed: &mut FeedMessageSerializer,
+    ) {
+        if self.frozen {
+            return;
+        }
+
+        // Pings come from the shard rather than the node, so don't count as hearing from it:
+        if let Payload::PingLatency(latency) = payload {
+            if let Some(node) = self.nodes.get_mut(nid) {
+                let latency = node.update_ping_latency(latency);
+                feed.push(feed_message::NodePingLatency(nid.into(), latency));
+            }
+            return;
+        }
+
+        self.messages.push(1);
+        if let Some(node) = self.nodes.get_mut(nid) {
+            node.update_last_seen(time::now());
+        }
+
+        // A label waiting to be shown may have been ahead for long enough by now:
+        if self.pending_label.is_some()
+            && self.update_displayed_label(Instant::now())
+            && self.label_override.is_none()
+        {
+            self.label_changed = true;
+        }
+
+        if let Some(block) = payload.best_block() {
+            self.handle_block(block, payload.block_author(), nid, feed);
+        }
+
+        let mut finalized_changed = false;
+        let node_detail_fields = self.node_detail_fields();
+        if let Some(node) = self.nodes.get_mut(nid) {
+            match payload {
+                Payload::SystemInterval(ref interval) => {
+                    if let Some(depth) = interval.import_queue_depth {
+                        let backed_up_depth = self.import_queue_backed_up_depth;
+                        match node.update_import_queue(depth, backed_up_depth) {
+                            Some(true) => self
+                                .stats_collator
+                                .update_backed_up_import_queues(CounterValue::Increment),
+                            Some(false) => self
+                                .stats_collator
+                                .update_backed_up_import_queues(CounterValue::Decrement),
+                            None => {}
+                        }
+                    }
+
+                    node.update_block_txcount(interval);
+                    node.update_clock_skew(interval);
+
+                    let min_percent = self.min_import_success_percent;
+                    let was_unhealthy = node.import_unhealthy(min_percent);
+                    if let Some(ratio) = node.update_import_success(interval) {
+                        feed.push(feed_message::NodeImportSuccess(nid.into(), ratio));
+                    }
+                    if let Some(started_at) = node.update_process_uptime(interval, time::now()) {
+                        feed.push(feed_message::ProcessStarted(nid.into(), started_at));
+                    }
+                    if let Some(previous) = node.update_session(interval) {
+                        if let Some(previous) = previous {
+                            self.sessions.remove(&previous);
+                        }
+                        if let Some(session) = node.session() {
+                            self.sessions.insert(&session);
+                        }
+                        rotate_session(&self.sessions, &mut self.session, feed);
+                    }
+                    match (was_unhealthy, node.import_unhealthy(min_percent)) {
+                        (false, true) => self
+                            .stats_collator
+                            .update_unhealthy_imports(CounterValue::Increment),
+                        (true, false) => self
+                            .stats_collator
+                            .update_unhealthy_imports(CounterValue::Decrement),
+                        _ => {}
+                    }
+
+                    // None of these are critical, so they're dropped if the chain is
+                    // sending feeds too many messages:
+                    let now = Instant::now();
+                    let limiter = &mut self.feed_rate_limiter;
+                    // Send a feed message if any of the relevant node details change:
+                    if node.update_hardware(interval, self.hardware_changes) && limiter.allow(now) {
+                        feed.push(feed_message::Hardware(nid.into(), node.hardware()));
+                    }
+                    match node.update_stats(interval) {
+                        Some(StatsUpdate::Full(stats)) if limiter.allow(now) => {
+                            feed.push(feed_message::NodeStatsUpdate(nid.into(), stats));
+                        }
+                        Some(StatsUpdate::Partial(stats)) if limiter.allow(now) => {
+                            feed.push(feed_message::NodeStatsDelta(nid.into(), stats));
+                        }
+                        _ => {}
+                    }
+                    if let Some(io) = node.update_io(interval).filter(|_| limiter.allow(now)) {
+                        feed.push(feed_message::NodeIOUpdate(nid.into(), io));
+                    }
+                }
+                Payload::AfgAuthoritySet(authority) => {
+                    // Nodes that aren't in the authority set any more report an empty
+                    // authority ID, which clears their validator address:
+                    let addr = Some(authority.authority_id.clone()).filter(|a| !a.is_empty());
+                    // If our node validator address (and thus details) change, send an
+                    // updated "add node" feed message:
+                    let was_validator = node.details().validator.is_some();
+                    if node.set_validator_address(addr) {
+                        let is_validator = node.details().validator.is_some();
+                        // Its vote towards the chain label counts for more while it's
+                        // a validator:
+                        let extra_weight = self.validator_label_weight - 1;
+                        let label = &node.details().chain;
+                        match (was_validator, is_validator) {
+                            (false, true) => {
+                                self.stats_collator
+                                    .update_validators(CounterValue::Increment);
+                                if extra_weight > 0 {
+                                    self.labels.insert_weighted(label, extra_weight);
+                                }
+                            }
+                            (true, false) => {
+                                self.stats_collator
+                                    .update_validators(CounterValue::Decrement);
+                                if extra_weight > 0 {
+                                    self.labels.remove_weighted(label, extra_weight);
+                                }
+                            }
+                            _ => {}
+                        }
+                        feed.push(feed_message::AddedNode(
+                            nid.into(),
+                            node,
+                            node_detail_fields,
+                        ));
+                        if self.update_displayed_label(Instant::now())
+                            && self.label_override.is_none()
+                        {
+                            self.label_changed = true;
+                        }
+                    }
+                    return;
+                }
+                Payload::HwBench(ref hwbench) => {
+                    let new_hwbench = common::node_types::NodeHwBench {
+                        cpu_hashrate_score: hwbench.cpu_hashrate_score,
+                        memory_memcpy_score: hwbench.memory_memcpy_score,
+                        disk_sequential_write_score: hwbench.disk_sequential_write_score,
+                        disk_random_write_score: hwbench.disk_random_write_score,
+                    };
+                    let old_hwbench = node.update_hwbench(new_hwbench);
+                    // The `hwbench` for this node has changed, send an updated "add node".
+                    // Note: There is no need to send this message if the details
+                    // will not be serialized over the wire.
+                    if node_detail_fields.contains(NodeDetailField::Hwbench) {
+                        feed.push(feed_message::AddedNode(
+                            nid.into(),
+                            node,
+                            node_detail_fields,
+                        ));
+                    }
+
+                    self.stats_collator
+                        .update_hwbench(old_hwbench.as_ref(), CounterValue::Decrement);
+                    self.stats_collator
+                        .update_hwbench(node.hwbench(), CounterValue::Increment);
                 }
                 _ => {}
             }
 
-            if let Some(block) = payload.finalized_block() {
+            if let Some(mut block) = payload.finalized_block() {
+                if block.height == 0 && self.finalized.height == 0 {
+                    self.awaiting_finality = true;
+                }
+                // Nodes can't have finalized past their best block, so one that says it
+                // has is broken somehow; go no further than its best block instead:
+                if block.height > node.best().height {
+                    log::debug!(
+                        "[{}] finalized block={} from node={:?} is ahead of its best block={}",
+                        self.labels.best(),
+                        block.height,
+                        nid,
+                        node.best().height,
+                    );
+                    if node.flag_finalized_ahead_of_best() {
+                        feed.push(feed_message::FinalizedAheadOfBest(
+                            nid.into(),
+                            block.height,
+                            node.best().height,
+                        ));
+                    }
+                    block = *node.best();
+                }
                 if let Some(finalized) = node.update_finalized(block) {
                     feed.push(feed_message::FinalizedBlock(
                         nid.into(),
@@ -242,20 +1904,54 @@ impl Chain {
                         finalized.hash,
                     ));
 
+                    let now = time::now();
                     if finalized.height > self.finalized.height {
+                        if let Some(timestamp) = self.finalized_timestamp {
+                            self.finalization_times.push(now.saturating_sub(timestamp));
+                            self.average_finalization_time =
+                                Some(self.finalization_times.average());
+                        }
                         self.finalized = *finalized;
+                        self.finalized_timestamp = Some(now);
                         feed.push(feed_message::BestFinalized(
                             finalized.height,
                             finalized.hash,
                         ));
+                        if self.awaiting_finality && !self.finality_started {
+                            self.finality_started = true;
+                            feed.push(feed_message::FinalityStarted(
+                                self.genesis_hash,
+                                finalized.height,
+                            ));
+                        }
+                        self.update_finality_gap(feed);
+                        finalized_changed = true;
+                    } else if *finalized == self.finalized {
+                        if let Some(timestamp) = self.finalized_timestamp {
+                            self.finalized_propagation_times
+                                .push(now.saturating_sub(timestamp));
+                            self.average_finalized_propagation_time =
+                                Some(self.finalized_propagation_times.average());
+                        }
                     }
                 }
             }
         }
+
+        if finalized_changed && self.prefer_finalized_branch {
+            self.reselect_best_on_finalized_branch(feed);
+        }
     }
 
-    fn handle_block(&mut self, block: &Block, nid: ChainNodeId, feed: &mut FeedMessageSerializer) {
+    fn handle_block(
+        &mut self,
+        block: &Block,
+        author: Option<&str>,
+        nid: ChainNodeId,
+        feed: &mut FeedMessageSerializer,
+    ) {
         let mut propagation_time = None;
+        let mut new_best = false;
         let now = time::now();
         let nodes_len = self.nodes.len();
 
@@ -267,9 +1963,53 @@ impl Chain {
             None => return,
         };
 
-        if node.update_block(*block) {
-            if block.height > self.best.height {
+        // A buggy node could otherwise drag our best block wherever it likes:
+        if !is_plausible_best(
+            block,
+            node.finalized(),
+            &self.best,
+            self.max_best_block_lead,
+        ) {
+            log::debug!(
+                "[{}] ignoring implausible best block={} from node={:?}",
+                self.labels.best(),
+                block.height,
+                nid,
+            );
+            if node.flag_implausible_block() {
+                feed.push(feed_message::ImplausibleBlock(nid.into(), block.height));
+            }
+            return;
+        }
+
+        let recover_threshold = now.saturating_sub(self.stale_timeouts.recover);
+        let awaiting_first_block = node.time_to_first_block().is_none();
+        if node.update_block(*block, recover_threshold) {
+            if self.prefer_finalized_branch {
+                node.remember_best(*block);
+            }
+            let on_finalized_branch =
+                !self.prefer_finalized_branch || !node.conflicts_with(&self.finalized);
+
+            if let Some(time_to_first_block) =
+                node.time_to_first_block().filter(|_| awaiting_first_block)
+            {
+                feed.push(feed_message::TimeToFirstBlock(
+                    nid.into(),
+                    time_to_first_block,
+                ));
+            }
+
+            if block.height > self.best.height && on_finalized_branch {
+                let step = block.height - self.best.height;
                 self.best = *block;
+                if !self.reached_listed_height && self.best.height >= self.min_listed_height {
+                    self.reached_listed_height = true;
+                    // First party chains were listed already, whatever their best block:
+                    self.listed_by_height = nodes_len >= self.min_listed_nodes
+                        && !is_first_party_network(&self.genesis_hash)
+                        && !self.private;
+                }
                 log::debug!(
                     "[{}] [nodes={}] new best block={}/{:?}",
                     self.labels.best(),
@@ -277,33 +2017,140 @@ impl Chain {
                     self.best.height,
                     self.best.hash,
                 );
-                if let Some(timestamp) = self.timestamp {
-                    self.block_times.push(now.saturating_sub(timestamp));
-                    self.average_block_time = Some(self.block_times.average());
+                // Jumping ahead several blocks at once is catching up rather than a block
+                // time, and would drag the average up:
+                let block_time = self
+                    .timestamp
+                    .filter(|_| self.max_block_time_step.is_none_or(|max| step <= max))
+                    .map(|timestamp| now.saturating_sub(timestamp));
+                if let Some(block_time) = block_time {
+                    self.block_times_histogram.record(block_time);
+                    self.block_times.push(block_time);
+                    // The first few block times after a reset give a jumpy average, so hold
+                    // off until we have enough of them (or as many as the window holds):
+                    let warmed_up = self.block_times.len()
+                        >= self.block_time_warm_up.min(self.block_times.size());
+                    self.average_block_time = warmed_up.then(|| self.block_times.average());
+                    scale_block_time_window(&mut self.block_times, self.block_time_window_duration);
+                    if warmed_up && self.block_time_history_len > 0 {
+                        if self.block_time_history.len() >= self.block_time_history_len {
+                            self.block_time_history.pop_front();
+                        }
+                        self.block_time_history.push_back(BlockTimePoint {
+                            timestamp: now,
+                            average_block_time: self.block_times.average(),
+                        });
+                    }
                 }
                 self.timestamp = Some(now);
+                push_recent_block(
+                    &mut self.recent_blocks,
+                    self.recent_blocks_len,
+                    RecentBlock {
+                        height: block.height,
+                        hash: block.hash,
+                        timestamp: now,
+                        block_time,
+                        propagation_time: None,
+                    },
+                );
                 feed.push(feed_message::BestBlock(
                     self.best.height,
                     now,
                     self.average_block_time,
+                    self.best.hash,
                 ));
                 propagation_time = Some(0);
+                new_best = true;
             } else if block.height == self.best.height {
                 if let Some(timestamp) = self.timestamp {
-                    propagation_time = Some(now.saturating_sub(timestamp));
+                    let time = now.saturating_sub(timestamp);
+                    propagation_time = Some(time);
+                    if let Some(recent) = self
+                        .recent_blocks
+                        .back_mut()
+                        .filter(|recent| recent.hash == block.hash)
+                    {
+                        recent.propagation_time = recent.propagation_time.max(Some(time));
+                    }
                 }
             }
 
             if let Some(details) = node.update_details(now, propagation_time) {
-                feed.push(feed_message::ImportedBlock(nid.into(), details));
+                if sample_imported_block(
+                    &mut self.imported_blocks_sent,
+                    self.imported_block_sample,
+                    block.height,
+                    self.best.height,
+                ) {
+                    feed.push(feed_message::ImportedBlock(nid.into(), details));
+                    // Feeds consider a node fresh when it imports a block, so tell them
+                    // straight away if it's still stale:
+                    if node.stale() {
+                        feed.push(feed_message::StaleNode(nid.into()));
+                    }
+                }
+            }
+
+            // The first node to tell us about a best block may not say who authored it,
+            // so attribute it to the first author that we're told about instead:
+            if let Some(author) = author {
+                if block.hash == self.best.hash && block.height > self.last_authored_height {
+                    self.block_authors.record(author, now);
+                    self.last_authored_height = block.height;
+                }
+            }
+
+            if new_best {
+                self.update_finality_gap(feed);
             }
         }
     }
 
+    /// The best block may have come from a node on a branch that has now lost out to
+    /// the finalized block. If so, fall back to the highest block reported by a node
+    /// that agrees with the finalized block.
+    fn reselect_best_on_finalized_branch(&mut self, feed: &mut FeedMessageSerializer) {
+        let best = self
+            .nodes
+            .iter()
+            .map(|(_, node)| node)
+            .filter(|node| !node.conflicts_with(&self.finalized))
+            .map(|node| *node.best())
+            .max_by_key(|block| block.height);
+
+        let best = match best {
+            Some(best) if best != self.best && best.height <= self.best.height => best,
+            _ => return,
+        };
+        log::debug!(
+            "[{}] best block {}/{:?} is not on the finalized branch; using {}/{:?}",
+            self.labels.best(),
+            self.best.height,
+            self.best.hash,
+            best.height,
+            best.hash,
+        );
+        self.record_event(ChainEvent::Reorg {
+            from: self.best,
+            to: best,
+        });
+        self.best = best;
+        feed.push(feed_message::BestBlock(
+            self.best.height,
+            self.timestamp.unwrap_or_else(time::now),
+            self.average_block_time,
+            self.best.hash,
+        ));
+        self.update_finality_gap(feed);
+    }
+
     /// Check if the chain is stale (has not received a new best block in a while).
     /// If so, find a new best block, ignoring any stale nodes and marking them as such.
     fn update_stale_nodes(&mut self, now: u64, feed: &mut FeedMessageSerializer) {
-        let threshold = now - STALE_TIMEOUT;
+        self.expire_stale_nodes(now);
+
+        let threshold = now.saturating_sub(self.stale_timeouts.stale);
         let timestamp = match self.timestamp {
             Some(ts) => ts,
             None => return,
@@ -319,6 +2166,7 @@ impl Chain {
         let mut timestamp = None;
 
         for (nid, node) in self.nodes.iter_mut() {
+            let was_stale = node.stale();
             if !node.update_stale(threshold) {
                 if node.best().height > best.height {
                     best = *node.best();
@@ -328,7 +2176,7 @@ impl Chain {
                 if node.finalized().height > finalized.height {
                     finalized = *node.finalized();
                 }
-            } else {
+            } else if !was_stale {
                 feed.push(feed_message::StaleNode(nid.into()));
             }
         }
@@ -337,76 +2185,3963 @@ impl Chain {
             self.best = best;
             self.finalized = finalized;
             self.block_times.reset();
+            self.finalization_times.reset();
+            self.finalized_propagation_times.reset();
             self.timestamp = timestamp;
+            self.finalized_timestamp = None;
+            self.record_event(ChainEvent::StaleRecovery {
+                best_height: best.height,
+                finalized_height: finalized.height,
+            });
 
             feed.push(feed_message::BestBlock(
                 self.best.height,
                 timestamp.unwrap_or(now),
                 None,
+                self.best.hash,
             ));
             feed.push(feed_message::BestFinalized(
                 finalized.height,
                 finalized.hash,
             ));
+            self.update_finality_gap(feed);
+        }
+    }
+
+    /// With [`StaleNodePolicy::Remove`], find the nodes that have been stale for long enough
+    /// to be removed, so that they can be taken with [`Chain::take_expired_nodes`]. Nodes can
+    /// stay stale after the chain recovers, so we look every so often regardless.
+    fn expire_stale_nodes(&mut self, now: u64) {
+        if self.stale_timeouts.policy == StaleNodePolicy::Keep
+            || now < self.stale_nodes_last_expired + STALE_NODE_EXPIRY_INTERVAL
+        {
+            return;
+        }
+        self.stale_nodes_last_expired = now;
+
+        let threshold = now.saturating_sub(self.stale_timeouts.removal);
+        self.expired_nodes = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.stale() && node.best_timestamp() < threshold)
+            .map(|(nid, _)| nid)
+            .collect();
+    }
+
+    /// Work out how far the finalized block is behind the best block, alerting feeds when
+    /// it falls too far behind and again when it recovers.
+    fn update_finality_gap(&mut self, feed: &mut FeedMessageSerializer) {
+        // Finalized may briefly be ahead of best if nodes report it first:
+        self.best_finalized_gap = self.best.height.saturating_sub(self.finalized.height);
+
+        let exceeded = self.best_finalized_gap > self.finality_gap_threshold;
+        if exceeded && !self.finality_gap_alerted {
+            self.finality_gap_alerted = true;
+            feed.push(feed_message::FinalityGapAlert(self.best_finalized_gap));
+            self.record_event(ChainEvent::FinalityStalled {
+                gap: self.best_finalized_gap,
+            });
+            self.alerts.push(ChainAlert::FinalityGap {
+                gap: self.best_finalized_gap,
+                recovered: false,
+            });
+        } else if !exceeded && self.finality_gap_alerted {
+            self.finality_gap_alerted = false;
+            feed.push(feed_message::FinalityGapRecovered(self.best_finalized_gap));
+            self.record_event(ChainEvent::FinalityRecovered {
+                gap: self.best_finalized_gap,
+            });
+            self.alerts.push(ChainAlert::FinalityGap {
+                gap: self.best_finalized_gap,
+                recovered: true,
+            });
         }
     }
 
     fn regenerate_stats_if_necessary(&mut self, feed: &mut FeedMessageSerializer) {
         let now = Instant::now();
         let elapsed = now - self.stats_last_regenerated;
-        if elapsed < STATS_UPDATE_INTERVAL {
+        let interval = match self.under_load {
+            true => STATS_UPDATE_INTERVAL.max(self.stats_timings.under_load_interval),
+            false => STATS_UPDATE_INTERVAL,
+        };
+        if elapsed < interval {
             return;
         }
+        self.regenerate_stats(feed);
+    }
 
+    fn regenerate_stats(&mut self, feed: &mut FeedMessageSerializer) {
+        let now = Instant::now();
         self.stats_last_regenerated = now;
-        let new_stats = self.stats_collator.generate();
+        let dropped = self.feed_rate_limiter.take_dropped();
+        if dropped > 0 {
+            log::warn!(
+                "[{}] [nodes={}] feed rate limit reached; dropped {} node updates",
+                self.label(),
+                self.nodes.len(),
+                dropped,
+            );
+        }
+        // Feeds hear about when nodes were last seen as often as they hear about stats,
+        // rather than on every message:
+        self.report_last_seen(feed);
+
+        let reconcile_interval = self.stats_timings.reconcile_interval.as_millis() as u64;
+        if time::now().saturating_sub(self.stats_last_reconciled) >= reconcile_interval {
+            self.reconcile_stats();
+        }
+        let mut new_stats = self.stats_collator.generate();
+
+        let generate_time = now.elapsed();
+        self.stats_regeneration_times
+            .record(generate_time.as_micros() as u64);
+        if generate_time > self.stats_timings.regeneration_budget {
+            log::warn!(
+                "[{}] [nodes={}] regenerating chain stats took {:?}",
+                self.label(),
+                self.nodes.len(),
+                generate_time,
+            );
+        }
+
+        new_stats.average_finalized_propagation_time = self.average_finalized_propagation_time;
+        new_stats.average_finalization_time = self.average_finalization_time;
+        new_stats.block_time_window = self.block_times.size();
+        new_stats.block_time_variation = self.block_time_variation();
+        new_stats.expected_block_time = self.expected_block_time();
+        new_stats.session = self.session();
+        new_stats.messages_per_second = self.messages_per_second();
+        new_stats.label_confidence = self.labels.best_share();
+        new_stats.frozen = self.frozen;
+        new_stats.last_reconciled = self.stats_last_reconciled;
+        new_stats.first_seen = self.first_seen;
+        new_stats.chain_age = self.chain_age();
+        new_stats.best_finalized_gap = self.best_finalized_gap;
+        new_stats.node_roles = new_stats
+            .node_roles
+            .with_unknown_as(self.unreported_node_role);
+        new_stats.full_to_light_ratio = new_stats.node_roles.full_to_light_ratio();
+        let mut times_to_first_block = Vec::with_capacity(self.nodes.len());
+        let mut stale_nodes = 0;
+        let mut best_heights = Vec::with_capacity(self.nodes.len());
+        let (mut total_block_txcount, mut block_txcount_nodes) = (0, 0);
+        let (mut total_peers, mut peer_nodes) = (0, 0);
+        let mut disk_used = Vec::new();
+        let now_ms = time::now();
+        for (_, node) in self.nodes.iter() {
+            self.connection_tiers.count(
+                &mut new_stats.connection_durations,
+                now_ms.saturating_sub(node.connected_at()),
+            );
+            match node.time_to_first_block() {
+                Some(time_to_first_block) => times_to_first_block.push(time_to_first_block),
+                None => new_stats.pending_first_block += 1,
+            }
+            if node.time_to_first_block().is_some() && !node.stale() {
+                best_heights.push(node.best().height);
+            }
+            if node.stale() {
+                stale_nodes += 1;
+            }
+            // Nodes that haven't told us about any finalized block yet aren't lagging:
+            let finalized_height = node.finalized().height;
+            if finalized_height > 0
+                && !node.stale()
+                && self.finalized.height.saturating_sub(finalized_height)
+                    > self.finality_lag_threshold
+            {
+                new_stats.nodes_lagging_finality += 1;
+            }
+            if let Some(clock_skew) = node.clock_skew().filter(|_| !node.stale()) {
+                if clock_skew > self.clock_skew_threshold as i64 {
+                    new_stats.nodes_with_clock_skew.ahead += 1;
+                } else if clock_skew < -(self.clock_skew_threshold as i64) {
+                    new_stats.nodes_with_clock_skew.behind += 1;
+                }
+            }
+            if let Some(finalized_at) = node.finalized_at().filter(|_| !node.stale()) {
+                let age = now_ms.saturating_sub(finalized_at);
+                new_stats.min_finalized_age =
+                    Some(new_stats.min_finalized_age.map_or(age, |min| min.min(age)));
+                new_stats.max_finalized_age =
+                    Some(new_stats.max_finalized_age.map_or(age, |max| max.max(age)));
+            }
+            if !node.stale() {
+                total_peers += node.stats().peers;
+                peer_nodes += 1;
+            }
+            if let Some(started_at) = node.process_started_at().filter(|_| !node.stale()) {
+                if now_ms.saturating_sub(started_at) < self.recent_restart_window {
+                    new_stats.recently_restarted += 1;
+                }
+            }
+            if node.implausible_blocks() > 0 {
+                new_stats.implausible_block_nodes += 1;
+            }
+            if let Some((upload, download)) = node.bandwidth().filter(|_| !node.stale()) {
+                new_stats.bandwidth.upload += upload;
+                new_stats.bandwidth.download += download;
+            }
+            if let Some(block_txcount) = node.block_txcount().filter(|_| !node.stale()) {
+                total_block_txcount += block_txcount;
+                block_txcount_nodes += 1;
+            }
+            if !node.stale() {
+                disk_used.extend(node.io().disk_used);
+                if node.low_on_disk(self.low_disk_threshold) == Some(true) {
+                    new_stats.nodes_low_on_disk += 1;
+                }
+            }
+        }
+        if peer_nodes > 0 {
+            new_stats.average_peers = Some(total_peers as f64 / peer_nodes as f64);
+        }
+        // Nodes that don't tell us how many transactions are in their blocks are left out:
+        if block_txcount_nodes > 0 {
+            let average_block_txcount = total_block_txcount as f64 / block_txcount_nodes as f64;
+            new_stats.average_block_txcount = Some(total_block_txcount / block_txcount_nodes);
+            new_stats.transactions_per_second = self
+                .average_block_time
+                .filter(|&block_time| block_time > 0)
+                .map(|block_time| average_block_txcount * 1000.0 / block_time as f64);
+        }
+        new_stats.median_time_to_first_block = median(&mut times_to_first_block);
+        new_stats.syncing_nodes = best_heights
+            .iter()
+            .filter(|&&height| {
+                self.best.height.saturating_sub(height) > self.syncing_majority.blocks_behind
+            })
+            .count() as u64;
+        new_stats.median_best_block = median(&mut best_heights);
+        new_stats.median_disk_used = median(&mut disk_used);
+        new_stats.oldest_last_seen = self.nodes.iter().map(|(_, node)| node.last_seen()).min();
+        (
+            new_stats.top_block_authors,
+            new_stats.inactive_block_authors,
+        ) = self
+            .block_authors
+            .summary(now_ms, self.block_author_inactive_after);
+        self.update_mostly_syncing(new_stats.syncing_nodes, feed);
+        new_stats.mostly_syncing = self.mostly_syncing;
+        self.update_validator_shortfall(new_stats.validators, feed);
+        self.update_average_peers(new_stats.average_peers, feed);
+        new_stats.health_score = self.health_score(&new_stats, stale_nodes);
         if new_stats != self.stats {
             self.stats = new_stats;
             feed.push(feed_message::ChainStatsUpdate(&self.stats));
         }
     }
 
-    pub fn update_node_location(
-        &mut self,
-        node_id: ChainNodeId,
-        location: find_location::Location,
-    ) -> bool {
-        if let Some(node) = self.nodes.get_mut(node_id) {
-            node.update_location(location);
-            true
+    /// Combine the signals given by the stats into a score from 0 to 100, as described
+    /// by [`HealthWeights`].
+    fn health_score(&self, stats: &ChainStats, stale_nodes: u64) -> u8 {
+        let fraction = |part: u64, whole: u64| match whole {
+            0 => 0.0,
+            _ => (part as f64 / whole as f64).min(1.0),
+        };
+        let node_count = self.nodes.len() as u64;
+        let finality_gap = match self.finality_gap_threshold {
+            0 => (self.best_finalized_gap > 0) as u64 as f64,
+            threshold => fraction(self.best_finalized_gap, threshold),
+        };
+        let block_time = match (self.average_block_time, stats.expected_block_time) {
+            (Some(average), Some(expected)) => fraction(average.abs_diff(expected), expected),
+            _ => 0.0,
+        };
+        let validator_shortfall = self.expected_validators.map_or(0.0, |expected| {
+            fraction(expected.saturating_sub(stats.validators), expected)
+        });
+
+        let weights = &self.health_weights;
+        let penalties = [
+            (weights.finality_gap, finality_gap),
+            (weights.stale_nodes, fraction(stale_nodes, node_count)),
+            (
+                weights.syncing_nodes,
+                fraction(stats.syncing_nodes, node_count),
+            ),
+            (weights.block_time, block_time),
+            (weights.validator_shortfall, validator_shortfall),
+        ];
+        let total_weight: u32 = penalties.iter().map(|(weight, _)| weight).sum();
+        if total_weight == 0 {
+            return 100;
+        }
+        let penalty: f64 = penalties
+            .iter()
+            .map(|&(weight, penalty)| weight as f64 * penalty)
+            .sum::<f64>()
+            / total_weight as f64;
+        (100.0 * (1.0 - penalty)).round() as u8
+    }
+
+    /// Work out whether most of the nodes are syncing, telling feeds when the chain
+    /// goes from mostly synced to mostly syncing and back.
+    fn update_mostly_syncing(&mut self, syncing_nodes: u64, feed: &mut FeedMessageSerializer) {
+        let node_count = self.nodes.len() as u64;
+        let mostly_syncing =
+            node_count > 0 && syncing_nodes * 100 > node_count * self.syncing_majority.percent;
+        if mostly_syncing == self.mostly_syncing {
+            return;
+        }
+
+        self.mostly_syncing = mostly_syncing;
+        if mostly_syncing {
+            feed.push(feed_message::MostlySyncing(syncing_nodes, node_count));
         } else {
-            false
+            feed.push(feed_message::MostlySynced(syncing_nodes, node_count));
         }
     }
 
-    pub fn get_node(&self, id: ChainNodeId) -> Option<&Node> {
-        self.nodes.get(id)
+    /// Tell feeds about the average peer count of the chain's nodes when it's changed enough
+    /// since they were last told. A falling average can be an early sign of connectivity
+    /// trouble, like a bootnode going down.
+    fn update_average_peers(&mut self, average: Option<f64>, feed: &mut FeedMessageSerializer) {
+        let Some(average) = average else {
+            return;
+        };
+        let percent = self.average_peers_change_percent as f64;
+        let changed = self.reported_average_peers.is_none_or(|reported| {
+            average != reported && (average - reported).abs() * 100.0 >= reported * percent
+        });
+        if changed {
+            feed.push(feed_message::AveragePeers(
+                average,
+                self.reported_average_peers,
+            ));
+            self.reported_average_peers = Some(average);
+        }
     }
-    pub fn nodes_slice(&self) -> &[Option<Node>] {
-        self.nodes.as_slice()
+
+    /// Alert feeds when the chain has too few validators compared to how many are expected,
+    /// and again once enough are back.
+    fn update_validator_shortfall(&mut self, validators: u64, feed: &mut FeedMessageSerializer) {
+        let Some(expected) = self.expected_validators else {
+            return;
+        };
+        let exceeded = expected.saturating_sub(validators) > self.validator_shortfall_threshold;
+        if exceeded && !self.validator_shortfall_alerted {
+            self.validator_shortfall_alerted = true;
+            feed.push(feed_message::ValidatorShortfall(expected, validators));
+            self.alerts.push(ChainAlert::ValidatorShortfall {
+                expected,
+                observed: validators,
+                recovered: false,
+            });
+        } else if !exceeded && self.validator_shortfall_alerted {
+            self.validator_shortfall_alerted = false;
+            feed.push(feed_message::ValidatorShortfallRecovered(
+                expected, validators,
+            ));
+            self.alerts.push(ChainAlert::ValidatorShortfall {
+                expected,
+                observed: validators,
+                recovered: true,
+            });
+        }
     }
-    pub fn label(&self) -> &str {
-        &self.labels.best()
+
+    /// Tell feeds when each node that we've heard from since the last report was last seen.
+    fn report_last_seen(&mut self, feed: &mut FeedMessageSerializer) {
+        let since = self.last_seen_reported;
+        let last_seen: Vec<_> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.last_seen() > since)
+            .map(|(nid, node)| (nid.into(), node.last_seen()))
+            .collect();
+
+        self.last_seen_reported = time::now();
+        if !last_seen.is_empty() {
+            feed.push(feed_message::NodesLastSeen(last_seen));
+        }
     }
-    pub fn node_count(&self) -> usize {
-        self.nodes.len()
+
+    /// Recount the stats counters from the nodes that are actually connected,
+    /// correcting any drift from nodes that weren't counted properly.
+    fn reconcile_stats(&mut self) {
+        let mut stats_collator = ChainStatsCollator::unlimited(
+            self.stats_collator.hardware_tiers(),
+            self.stats_collator.operator_pattern().clone(),
+        );
+        for (_, node) in self.nodes.iter() {
+            stats_collator.add_or_remove_node(
+                node.details(),
+                node.hwbench(),
+                node.location(),
+                CounterValue::Increment,
+            );
+            if node.import_queue_backed_up() {
+                stats_collator.update_backed_up_import_queues(CounterValue::Increment);
+            }
+            if node.import_unhealthy(self.min_import_success_percent) {
+                stats_collator.update_unhealthy_imports(CounterValue::Increment);
+            }
+        }
+        // Only the most common values are kept once everything has been counted:
+        stats_collator.set_cardinality_limits(self.stats_collator.cardinality_limits().clone());
+        self.stats_collator = stats_collator;
+        self.stats_last_reconciled = time::now();
     }
-    pub fn best_block(&self) -> &Block {
-        &self.best
+
+    /// Recount the stats from the nodes that are connected, free memory left behind by
+    /// nodes that have gone, and regenerate the stats now rather than at the next interval.
+    pub fn compact(&mut self, feed: &mut FeedMessageSerializer) -> CompactSummary {
+        let before = serde_json::to_value(self.stats_collator.generate());
+        self.reconcile_stats();
+        let after = serde_json::to_value(self.stats_collator.generate());
+        let corrected_stats = match (before, after) {
+            (Ok(serde_json::Value::Object(before)), Ok(serde_json::Value::Object(after))) => after
+                .into_iter()
+                .filter(|(name, value)| before.get(name) != Some(value))
+                .map(|(name, _)| name)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let slots_freed = self.nodes.compact();
+        self.nodes_by_network_id.shrink_to_fit();
+        self.nodes_per_operator.shrink_to_fit();
+        self.nodes_per_name.shrink_to_fit();
+
+        self.regenerate_stats(feed);
+        CompactSummary {
+            nodes: self.nodes.len(),
+            slots_freed,
+            corrected_stats,
+        }
     }
-    pub fn timestamp(&self) -> Option<Timestamp> {
-        self.timestamp
+
+    /// Change how many nodes the chain can have. Lowering it below how many nodes the
+    /// chain has doesn't remove any of them, but stops more from being added until
+    /// enough have gone.
+    pub fn set_max_nodes(&mut self, max_nodes: usize) {
+        self.max_nodes = max_nodes;
     }
-    pub fn average_block_time(&self) -> Option<u64> {
-        self.average_block_time
+
+    /// The nodes that would need to go for the chain to be within its node limit, most
+    /// recently connected first.
+    pub fn nodes_over_quota(&self) -> Vec<ChainNodeId> {
+        let excess = self.nodes.len().saturating_sub(self.max_nodes);
+        if excess == 0 {
+            return Vec::new();
+        }
+        let mut nodes: Vec<_> = self.nodes.iter().collect();
+        nodes.sort_by_key(|&(id, node)| std::cmp::Reverse((node.connected_at(), usize::from(id))));
+        nodes.into_iter().take(excess).map(|(id, _)| id).collect()
     }
-    pub fn finalized_block(&self) -> &Block {
-        &self.finalized
+
+    /// Freeze or unfreeze the chain. While frozen, updates from nodes are ignored so that the
+    /// state of the chain can be inspected. Returns `false` if nothing changed.
+    pub fn set_frozen(&mut self, frozen: bool) -> bool {
+        if self.frozen == frozen {
+            return false;
+        }
+        self.frozen = frozen;
+        self.stats.frozen = frozen;
+        true
     }
-    pub fn genesis_hash(&self) -> BlockHash {
+
+    /// Let the chain know whether the aggregator is falling behind on messages, in which
+    /// case it regenerates its stats less often to save time.
+    pub fn set_under_load(&mut self, under_load: bool) {
+        self.under_load = under_load;
+    }
+
+    /// Update the location of a node, returning whether it changed. Locations that are
+    /// less than `min_location_change` metres from the node's current one are ignored,
+    /// since they're just jitter from the location database.
+    pub fn update_node_location(
+        &mut self,
+        node_id: ChainNodeId,
+        location: find_location::Location,
+    ) -> bool {
+        let Some(node) = self.nodes.get_mut(node_id) else {
+            return false;
+        };
+        if let (Some(old), Some(new)) = (node.location(), &location) {
+            if self.min_location_change > 0
+                && distance_in_metres(old, new) < self.min_location_change as f64
+            {
+                return false;
+            }
+        }
+        let old_location = node.update_location(location);
+        self.stats_collator
+            .update_location(old_location.as_deref(), CounterValue::Decrement);
+        self.stats_collator
+            .update_location(node.location(), CounterValue::Increment);
+        true
+    }
+
+    /// How many messages per second about nodes on this chain have we seen recently,
+    /// to one decimal place.
+    pub fn messages_per_second(&mut self) -> f64 {
+        // The total only moves on when something is pushed:
+        self.messages.push(0);
+        let rate = self.messages.total() as f64 / whole_secs(self.message_rate_window) as f64;
+        (rate * 10.0).round() / 10.0
+    }
+
+    /// Make a note that a node on this chain sent a message that couldn't be deserialized.
+    pub fn note_malformed_message(&mut self) {
+        self.malformed_messages += 1;
+    }
+
+    /// How many messages from nodes on this chain couldn't be deserialized.
+    pub fn malformed_messages(&self) -> u64 {
+        self.malformed_messages
+    }
+    /// When (unix time in ms) the chain was created.
+    pub fn first_seen(&self) -> Timestamp {
+        self.first_seen
+    }
+    /// How long (in ms) we've been watching the chain for.
+    pub fn chain_age(&self) -> u64 {
+        time::now().saturating_sub(self.first_seen)
+    }
+    /// Attach an annotation to a node, or clear it, telling feeds. Returns `false` if the
+    /// node can't be found.
+    pub fn annotate_node(
+        &mut self,
+        id: ChainNodeId,
+        annotation: Option<NodeAnnotation>,
+        feed: &mut FeedMessageSerializer,
+    ) -> bool {
+        let Some(node) = self.nodes.get_mut(id) else {
+            return false;
+        };
+        match &annotation {
+            Some(annotation) => feed.push(feed_message::NodeAnnotated(
+                id.into(),
+                &annotation.text,
+                annotation.severity,
+                annotation.expires_at,
+            )),
+            None => feed.push(feed_message::NodeAnnotationCleared(id.into())),
+        }
+        node.set_annotation(annotation);
+        true
+    }
+
+    /// Clear the annotations of nodes that expired at or before `now`, telling feeds.
+    pub fn expire_node_annotations(&mut self, now: Timestamp, feed: &mut FeedMessageSerializer) {
+        for (id, node) in self.nodes.iter_mut() {
+            if node.annotation().is_some_and(|a| a.expires_at <= now) {
+                node.set_annotation(None);
+                feed.push(feed_message::NodeAnnotationCleared(id.into()));
+            }
+        }
+    }
+
+    pub fn get_node(&self, id: ChainNodeId) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+    pub fn get_node_by_network_id(&self, network_id: &str) -> Option<&Node> {
+        self.get_node_id_by_network_id(network_id)
+            .and_then(|id| self.nodes.get(id))
+    }
+    pub fn get_node_id_by_network_id(&self, network_id: &str) -> Option<ChainNodeId> {
+        self.nodes_by_network_id.get(network_id).copied()
+    }
+    /// The existing node that the node given looks to be a second connection from,
+    /// because its old connection hasn't gone away yet: one with the same network ID,
+    /// name and startup time. Nodes with copied network keys won't have started at the
+    /// same time. Nodes that don't report a startup time are never reconnects.
+    pub fn get_reconnect_of(&self, node: &Node) -> Option<ChainNodeId> {
+        let details = node.details();
+        let startup_time = node.startup_time();
+        if details.network_id.is_empty() || startup_time.is_none() {
+            return None;
+        }
+        let id = self.get_node_id_by_network_id(&details.network_id)?;
+        let existing = self.nodes.get(id)?;
+        (existing.details().name == details.name && existing.startup_time() == startup_time)
+            .then_some(id)
+    }
+    pub fn nodes_slice(&self) -> &[Option<Node>] {
+        self.nodes.as_slice()
+    }
+    /// How the slots that nodes are stored in are being used, to see whether
+    /// compacting the chain would free much.
+    pub fn node_slots(&self) -> DenseMapStats {
+        self.nodes.stats()
+    }
+    pub fn label(&self) -> &str {
+        match &self.label_override {
+            Some(label) => label,
+            None if self.displayed_label.trim().is_empty() => &self.unlabeled_label,
+            None => &self.displayed_label,
+        }
+    }
+    /// How many blocks the best block is ahead of the finalized block.
+    pub fn best_finalized_gap(&self) -> u64 {
+        self.best_finalized_gap
+    }
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+    /// Should the location of the node added most recently be looked up? On big chains,
+    /// a sample of the nodes gives much the same picture as all of them.
+    pub fn should_locate_added_node(&self) -> bool {
+        self.max_located_nodes == 0 || self.nodes.len() <= self.max_located_nodes
+    }
+    /// Should feeds be told about the chain? Chains with no nodes never are, and
+    /// first party chains always are otherwise. Once the best block has been high
+    /// enough, the chain stays listed even if it drops back down again.
+    pub fn is_listed(&self) -> bool {
+        let node_count = self.node_count();
+        node_count > 0
+            && !self.private
+            && (is_first_party_network(&self.genesis_hash)
+                || (node_count >= self.min_listed_nodes && self.reached_listed_height))
+    }
+    /// Private chains can only be seen by feeds with a token that grants them access.
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+    pub fn best_block(&self) -> &Block {
+        &self.best
+    }
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+    pub fn average_block_time(&self) -> Option<u64> {
+        self.average_block_time
+    }
+    /// The coefficient of variation (standard deviation over mean) of the block times that
+    /// the average block time is worked out from, once that's known.
+    pub fn block_time_variation(&self) -> Option<f64> {
+        let average = self.average_block_time.filter(|&average| average > 0)?;
+        Some(self.block_times.std_dev() / average as f64)
+    }
+    pub fn finalized_block(&self) -> &Block {
+        &self.finalized
+    }
+    pub fn relay_parent(&self) -> Option<&RelayParent> {
+        self.relay_parent.as_ref()
+    }
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+    pub fn recent_blocks(&self) -> &VecDeque<RecentBlock> {
+        &self.recent_blocks
+    }
+    /// The session that most nodes on the chain are in, if they report one.
+    pub fn session(&self) -> Option<u64> {
+        self.session
+    }
+    /// The average block time as of each recent best block, oldest first.
+    pub fn block_time_history(&self) -> &VecDeque<BlockTimePoint> {
+        &self.block_time_history
+    }
+    /// The block times (in ms) that the average block time is worked out from, oldest first.
+    pub fn block_time_samples(&self) -> Vec<u64> {
+        self.block_times.samples()
+    }
+    pub fn genesis_hash(&self) -> BlockHash {
         self.genesis_hash
     }
+    pub fn expose_node_details(&self) -> bool {
+        self.expose_node_details
+    }
+    /// Which details of nodes feeds are sent, leaving out the private ones unless node
+    /// details are exposed.
+    pub fn node_detail_fields(&self) -> NodeDetailFields {
+        match self.expose_node_details {
+            true => self.node_detail_fields,
+            false => self.node_detail_fields.public(),
+        }
+    }
     pub fn stats(&self) -> &ChainStats {
         &self.stats
     }
+    /// How long (in ms) blocks are expected to take on the chain. Unless we were told, this
+    /// is the average of every block time seen, once there have been enough of them.
+    pub fn expected_block_time(&self) -> Option<u64> {
+        if self.expected_block_time.is_some() {
+            return self.expected_block_time;
+        }
+        let count = self.block_times_histogram.count();
+        (count >= MIN_INFERRED_BLOCK_TIMES).then(|| self.block_times_histogram.sum() / count)
+    }
+    pub fn block_times_histogram(&self) -> &Histogram {
+        &self.block_times_histogram
+    }
+    pub fn stats_regeneration_times(&self) -> &Histogram {
+        &self.stats_regeneration_times
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::{ChainBandwidth, ClockSkewCounts, NodeRoleCounts};
+    use common::node_types::NodeRole;
+
+    fn node(name: &str, network_id: &str) -> Node {
+        Node::new(NodeDetails {
+            chain: "Chain One".into(),
+            name: name.into(),
+            implementation: "Bar".into(),
+            target_arch: None,
+            target_os: None,
+            target_env: None,
+            version: "0.1".into(),
+            validator: None,
+            network_id: NetworkId::from(network_id).unwrap(),
+            startup_time: None,
+            sysinfo: None,
+            ip: None,
+            protocol_version: None,
+            relay_chain_genesis_hash: None,
+            para_id: None,
+            role: None,
+            custom: Default::default(),
+        })
+    }
+
+    fn added_id(result: AddNodeResult) -> ChainNodeId {
+        match result {
+            AddNodeResult::Added { id, .. } => id,
+            AddNodeResult::Overquota => panic!("Chain not Overquota"),
+        }
+    }
+
+    #[test]
+    fn block_time_window_scales_to_cover_duration() {
+        let mut block_times = NumStats::new(10);
+        let duration = Some(Duration::from_secs(60));
+
+        // Not resized until the window has filled up:
+        for _ in 0..9 {
+            block_times.push(2000);
+            scale_block_time_window(&mut block_times, duration);
+        }
+        assert_eq!(block_times.size(), 10);
+
+        // 2s blocks; 30 of them cover a minute:
+        block_times.push(2000);
+        scale_block_time_window(&mut block_times, duration);
+        assert_eq!(block_times.size(), 30);
+        assert_eq!(block_times.average(), 2000);
+    }
+
+    #[test]
+    fn median_averages_the_middle_values() {
+        assert_eq!(median(&mut []), None);
+        assert_eq!(median(&mut [5]), Some(5));
+        assert_eq!(median(&mut [9, 1, 5]), Some(5));
+        assert_eq!(median(&mut [9, 1, 4, 6]), Some(5));
+    }
+
+    #[test]
+    fn chain_age_is_counted_from_creation() {
+        let before = time::now();
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let first_seen = chain.first_seen();
+        assert!(first_seen >= before && first_seen <= time::now());
+
+        chain.first_seen -= 60_000;
+        assert!(chain.chain_age() >= 60_000);
+
+        chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+        chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+        assert_eq!(chain.stats().first_seen, first_seen - 60_000);
+        assert!(chain.stats().chain_age >= 60_000);
+    }
+
+    #[test]
+    fn stats_are_regenerated_less_often_under_load() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let regenerated = |chain: &mut Chain, ago: Duration| {
+            chain.stats_last_regenerated = Instant::now() - ago;
+            let before = chain.stats_last_regenerated;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain.stats_last_regenerated != before
+        };
+        let under_load_interval = StatsTimings::default().under_load_interval;
+
+        assert!(regenerated(&mut chain, STATS_UPDATE_INTERVAL));
+
+        chain.set_under_load(true);
+        assert!(!regenerated(&mut chain, STATS_UPDATE_INTERVAL));
+        assert!(regenerated(&mut chain, under_load_interval));
+
+        chain.set_under_load(false);
+        assert!(regenerated(&mut chain, STATS_UPDATE_INTERVAL));
+    }
+
+    #[test]
+    fn first_party_networks_are_listed_in_order() {
+        let genesis_hashes = first_party_networks();
+        assert_eq!(genesis_hashes.len(), 4);
+        assert!(genesis_hashes.windows(2).all(|w| w[0] < w[1]));
+        assert!(genesis_hashes.iter().all(is_first_party_network));
+        let polkadot = "0x91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3";
+        assert!(genesis_hashes.contains(&polkadot.parse().unwrap()));
+    }
+
+    #[test]
+    fn chains_are_listed_once_they_have_enough_nodes() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                min_listed_nodes: 2,
+                ..ChainConfig::default()
+            },
+        );
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        assert!(!chain.is_listed());
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        assert!(chain.is_listed());
+        chain.remove_node(b, RemovalReason::Disconnected);
+        assert!(!chain.is_listed());
+        chain.remove_node(a, RemovalReason::Disconnected);
+        assert!(!chain.is_listed());
+
+        // First party chains are listed as soon as they have a node:
+        let polkadot = "0x91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3";
+        let mut chain = Chain::new(
+            polkadot.parse().unwrap(),
+            usize::MAX,
+            None,
+            ChainConfig {
+                min_listed_nodes: 2,
+                ..ChainConfig::default()
+            },
+        );
+        assert!(!chain.is_listed());
+        chain.add_node(node("A", "network-a"));
+        assert!(chain.is_listed());
+    }
+
+    #[test]
+    fn private_chains_are_never_listed() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                private: true,
+                ..ChainConfig::default()
+            },
+        );
+        chain.add_node(node("A", "network-a"));
+        chain.add_node(node("B", "network-b"));
+        assert!(!chain.is_listed());
+    }
+
+    #[test]
+    fn chains_are_listed_once_their_best_block_is_high_enough() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                min_listed_height: 100,
+                ..ChainConfig::default()
+            },
+        );
+        let mut feed = FeedMessageSerializer::new();
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        assert!(!chain.is_listed());
+
+        let block = |height| {
+            Payload::BlockImport(
+                Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                }
+                .into(),
+            )
+        };
+        chain.update_node(a, block(99), &mut feed);
+        assert!(!chain.is_listed());
+        assert!(!chain.take_listed_by_height());
+
+        chain.update_node(a, block(100), &mut feed);
+        assert!(chain.is_listed());
+        assert!(chain.take_listed_by_height());
+        assert!(!chain.take_listed_by_height());
+
+        // Chains stay listed even if their best block goes back down:
+        chain.best = Block::zero();
+        assert!(chain.is_listed());
+
+        // First party chains are listed whatever their best block is:
+        let polkadot = "0x91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3";
+        let mut chain = Chain::new(
+            polkadot.parse().unwrap(),
+            usize::MAX,
+            None,
+            ChainConfig {
+                min_listed_height: 100,
+                ..ChainConfig::default()
+            },
+        );
+        chain.add_node(node("A", "network-a"));
+        assert!(chain.is_listed());
+    }
+
+    #[test]
+    fn recent_blocks_are_bounded() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                recent_blocks_len: 3,
+                ..ChainConfig::default()
+            },
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+
+        let mut feed = FeedMessageSerializer::new();
+        for height in 1..=5 {
+            let payload = Payload::BlockImport(
+                Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                }
+                .into(),
+            );
+            chain.update_node(id, payload, &mut feed);
+        }
+
+        let heights: Vec<_> = chain.recent_blocks().iter().map(|b| b.height).collect();
+        assert_eq!(heights, vec![3, 4, 5]);
+        assert!(chain.recent_blocks().iter().all(|b| b.block_time.is_some()));
+    }
+
+    #[test]
+    fn block_time_history_is_bounded() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                block_time_history_len: 3,
+                ..ChainConfig::default()
+            },
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+
+        let mut feed = FeedMessageSerializer::new();
+        for height in 1..=5 {
+            let payload = Payload::BlockImport(
+                Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                }
+                .into(),
+            );
+            chain.update_node(id, payload, &mut feed);
+        }
+
+        let history = chain.block_time_history();
+        assert_eq!(history.len(), 3);
+        assert!(history
+            .iter()
+            .zip(history.iter().skip(1))
+            .all(|(a, b)| a.timestamp <= b.timestamp));
+        assert_eq!(
+            history.back().map(|p| p.average_block_time),
+            chain.average_block_time()
+        );
+    }
+
+    #[test]
+    fn average_block_time_waits_for_enough_block_times() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                block_time_warm_up: 3,
+                ..ChainConfig::default()
+            },
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+
+        let mut feed = FeedMessageSerializer::new();
+        let mut averages = Vec::new();
+        for height in 1..=5 {
+            let payload = Payload::BlockImport(
+                Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                }
+                .into(),
+            );
+            chain.update_node(id, payload, &mut feed);
+            averages.push(chain.average_block_time().is_some());
+        }
+
+        // The first block has no block time, and the next two aren't enough:
+        assert_eq!(averages, vec![false, false, false, true, true]);
+        assert_eq!(chain.block_time_history().len(), 2);
+    }
+
+    #[test]
+    fn block_time_variation_waits_for_an_average_block_time() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                block_time_warm_up: 3,
+                ..ChainConfig::default()
+            },
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+
+        let mut feed = FeedMessageSerializer::new();
+        let mut variations = Vec::new();
+        for height in 1..=5 {
+            let payload = Payload::BlockImport(
+                Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                }
+                .into(),
+            );
+            chain.update_node(id, payload, &mut feed);
+            variations.push(chain.block_time_variation());
+        }
+
+        assert!(variations[..3].iter().all(Option::is_none));
+        // Block times this close together may all round to zero, in which case
+        // there's no average to compare the variation against:
+        if chain.average_block_time().is_some_and(|avg| avg > 0) {
+            assert!(variations[4].is_some_and(|v| v >= 0.0));
+        }
+    }
+
+    #[test]
+    fn big_jumps_in_best_block_are_not_block_times() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                max_block_time_step: Some(2),
+                ..ChainConfig::default()
+            },
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+
+        let mut feed = FeedMessageSerializer::new();
+        for height in [1, 2, 4, 9, 10] {
+            let payload = Payload::BlockImport(
+                Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                }
+                .into(),
+            );
+            chain.update_node(id, payload, &mut feed);
+        }
+
+        // The first block has no block time, and the jump from 4 to 9 is catching up:
+        let timed: Vec<_> = chain
+            .recent_blocks()
+            .iter()
+            .map(|b| (b.height, b.block_time.is_some()))
+            .collect();
+        assert_eq!(
+            timed,
+            vec![(1, false), (2, true), (4, true), (9, false), (10, true)]
+        );
+        assert_eq!(chain.block_time_samples().len(), 3);
+    }
+
+    #[test]
+    fn recent_blocks_record_how_long_they_took_to_propagate() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let import = |chain: &mut Chain, id, height| {
+            let payload = Payload::BlockImport(
+                Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                }
+                .into(),
+            );
+            chain.update_node(id, payload, &mut FeedMessageSerializer::new());
+        };
+
+        import(&mut chain, a, 1);
+        assert_eq!(chain.recent_blocks().back().unwrap().propagation_time, None);
+
+        // Pretend that the block arrived a second ago:
+        chain.timestamp = chain.timestamp.map(|t| t - 1000);
+        import(&mut chain, b, 1);
+        let propagation_time = chain.recent_blocks().back().unwrap().propagation_time;
+        assert!(propagation_time.is_some_and(|t| t >= 1000));
+    }
+
+    #[test]
+    fn finalized_blocks_record_how_long_they_took_to_propagate() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+        let finalize = |chain: &mut Chain, id, height, hash| {
+            let block = Block {
+                hash: BlockHash::from_low_u64_be(hash),
+                height,
+            };
+            let finalized = common::node_message::Finalized {
+                hash: block.hash,
+                height: height.to_string().into(),
+            };
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, Payload::BlockImport(block.into()), &mut feed);
+            chain.update_node(id, Payload::NotifyFinalized(finalized), &mut feed);
+        };
+
+        finalize(&mut chain, a, 1, 1);
+        assert_eq!(chain.average_finalized_propagation_time, None);
+
+        // Pretend that the block was finalized a second ago. Finalizing a different block
+        // at the same height doesn't count:
+        chain.finalized_timestamp = chain.finalized_timestamp.map(|t| t - 1000);
+        finalize(&mut chain, b, 1, 2);
+        assert_eq!(chain.average_finalized_propagation_time, None);
+
+        finalize(&mut chain, c, 1, 1);
+        let propagation_time = chain.average_finalized_propagation_time;
+        assert!(propagation_time.is_some_and(|t| t >= 1000));
+    }
+
+    #[test]
+    fn recent_blocks_stay_in_height_order() {
+        let block = |height| RecentBlock {
+            height,
+            hash: BlockHash::from_low_u64_be(height),
+            timestamp: 0,
+            block_time: None,
+            propagation_time: None,
+        };
+        let mut recent_blocks = VecDeque::new();
+        for height in [1, 2, 3, 4] {
+            push_recent_block(&mut recent_blocks, 10, block(height));
+        }
+
+        // The best block went backwards, so forget what came after it:
+        push_recent_block(&mut recent_blocks, 10, block(3));
+
+        let heights: Vec<_> = recent_blocks.iter().map(|b| b.height).collect();
+        assert_eq!(heights, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn only_nodes_seen_since_the_last_report_are_reported() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-a")));
+
+        let report = |chain: &mut Chain| {
+            let mut feed = FeedMessageSerializer::new();
+            chain.report_last_seen(&mut feed);
+            feed.into_finalized()
+                .map(|bytes| FeedMessage::from_bytes(&bytes).unwrap())
+                .unwrap_or_default()
+        };
+
+        chain.last_seen_reported = 0;
+        let seen_at = |id| chain.get_node(id).unwrap().last_seen();
+        let expected = vec![(a.into(), seen_at(a)), (b.into(), seen_at(b))];
+        assert_eq!(
+            report(&mut chain),
+            vec![FeedMessage::NodesLastSeen {
+                last_seen: expected
+            }]
+        );
+
+        // Nothing new to report:
+        assert_eq!(report(&mut chain), vec![]);
+
+        let later = chain.last_seen_reported + 1000;
+        chain.nodes.get_mut(b).unwrap().update_last_seen(later);
+        assert_eq!(
+            report(&mut chain),
+            vec![FeedMessage::NodesLastSeen {
+                last_seen: vec![(b.into(), later)]
+            }]
+        );
+    }
+
+    #[test]
+    fn feeds_are_alerted_about_the_finality_gap() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                finality_gap_threshold: 2,
+                ..ChainConfig::default()
+            },
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+
+        let update = |chain: &mut Chain, payload| {
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, payload, &mut feed);
+            feed.into_finalized()
+                .map(|bytes| FeedMessage::from_bytes(&bytes).unwrap())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|msg| {
+                    matches!(
+                        msg,
+                        FeedMessage::FinalityGapAlert { .. }
+                            | FeedMessage::FinalityGapRecovered { .. }
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let import = |height| {
+            Payload::BlockImport(
+                Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                }
+                .into(),
+            )
+        };
+        let finalize = |height: u64| {
+            Payload::NotifyFinalized(common::node_message::Finalized {
+                hash: BlockHash::from_low_u64_be(height),
+                height: height.to_string().into(),
+            })
+        };
+
+        assert_eq!(update(&mut chain, import(2)), vec![]);
+        assert_eq!(
+            update(&mut chain, import(3)),
+            vec![FeedMessage::FinalityGapAlert { gap: 3 }]
+        );
+        // Only alerted once:
+        assert_eq!(update(&mut chain, import(5)), vec![]);
+        assert_eq!(chain.best_finalized_gap, 5);
+
+        assert_eq!(
+            update(&mut chain, finalize(3)),
+            vec![FeedMessage::FinalityGapRecovered { gap: 2 }]
+        );
+        assert_eq!(chain.best_finalized_gap, 2);
+
+        // The same alerts are waiting to be passed on elsewhere:
+        assert_eq!(
+            chain.take_alerts(),
+            vec![
+                ChainAlert::FinalityGap {
+                    gap: 3,
+                    recovered: false
+                },
+                ChainAlert::FinalityGap {
+                    gap: 2,
+                    recovered: true
+                },
+            ]
+        );
+        assert_eq!(chain.take_alerts(), vec![]);
+
+        // A finalized block ahead of the best block doesn't make the gap negative:
+        assert_eq!(update(&mut chain, finalize(9)), vec![]);
+        assert_eq!(chain.best_finalized_gap, 0);
+    }
+
+    #[test]
+    fn best_block_can_prefer_the_finalized_branch() {
+        let block = |height, hash| Block {
+            hash: BlockHash::from_low_u64_be(hash),
+            height,
+        };
+        let best_after_fork = |prefer_finalized_branch| {
+            let mut chain = Chain::new(
+                BlockHash::from_low_u64_be(1),
+                usize::MAX,
+                None,
+                ChainConfig {
+                    prefer_finalized_branch,
+                    ..ChainConfig::default()
+                },
+            );
+            let a = added_id(chain.add_node(node("A", "network-a")));
+            let b = added_id(chain.add_node(node("B", "network-b")));
+
+            let mut feed = FeedMessageSerializer::new();
+            // A and B fork at height 5, and A's block is seen first:
+            chain.update_node(a, Payload::BlockImport(block(5, 1005).into()), &mut feed);
+            chain.update_node(b, Payload::BlockImport(block(5, 5).into()), &mut feed);
+            assert_eq!(chain.best, block(5, 1005));
+
+            // B's branch is finalized, but A carries on along its own branch:
+            let finalized = common::node_message::Finalized {
+                hash: BlockHash::from_low_u64_be(5),
+                height: "5".into(),
+            };
+            chain.update_node(b, Payload::NotifyFinalized(finalized), &mut feed);
+            let best_after_finality = chain.best;
+            chain.update_node(a, Payload::BlockImport(block(7, 1007).into()), &mut feed);
+            chain.update_node(b, Payload::BlockImport(block(6, 6).into()), &mut feed);
+            (best_after_finality, chain.best)
+        };
+
+        assert_eq!(best_after_fork(false), (block(5, 1005), block(7, 1007)));
+        assert_eq!(best_after_fork(true), (block(5, 5), block(6, 6)));
+    }
+
+    #[test]
+    fn imported_blocks_can_be_sampled() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                imported_block_sample: 2,
+                ..ChainConfig::default()
+            },
+        );
+        let ids: Vec<_> = ["A", "B", "C"]
+            .into_iter()
+            .map(|name| added_id(chain.add_node(node(name, name))))
+            .collect();
+
+        let mut feed = FeedMessageSerializer::new();
+        for &id in &ids {
+            let block = Block {
+                hash: BlockHash::from_low_u64_be(1),
+                height: 1,
+            };
+            chain.update_node(id, Payload::BlockImport(block.into()), &mut feed);
+        }
+        let msgs = FeedMessage::from_bytes(&feed.into_finalized().unwrap()).unwrap();
+        let imported: Vec<_> = msgs
+            .iter()
+            .filter_map(|msg| match msg {
+                FeedMessage::ImportedBlock { node_id, .. } => Some(ChainNodeId::from(*node_id)),
+                _ => None,
+            })
+            .collect();
+
+        // Feeds are only told about the first two nodes, but still get the best block,
+        // and every node's block is still kept track of:
+        assert_eq!(imported, ids[..2]);
+        assert!(msgs.iter().any(|msg| matches!(
+            msg,
+            FeedMessage::BestBlock {
+                block_number: 1,
+                ..
+            }
+        )));
+        assert!(chain.nodes.iter().all(|(_, n)| n.best().height == 1));
+    }
+
+    #[test]
+    fn imported_block_samples_are_forgotten_behind_the_best_block() {
+        let mut sent = BTreeMap::new();
+        assert!(sample_imported_block(&mut sent, 1, 10, 10));
+        assert!(!sample_imported_block(&mut sent, 1, 10, 10));
+
+        // Once the best block moves well past height 10, we stop counting it:
+        let best = 10 + IMPORTED_BLOCK_SAMPLE_DEPTH + 1;
+        assert!(sample_imported_block(&mut sent, 1, best, best));
+        assert!(!sent.contains_key(&10));
+        assert!(sample_imported_block(&mut sent, 1, 10, best));
+
+        // No sampling at all:
+        assert!(sample_imported_block(&mut sent, 0, best, best));
+    }
+
+    #[test]
+    fn first_reported_relay_chain_is_kept() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let parachain_node = |name: &str, relay: u64, para_id: Option<u32>| {
+            let mut details = node(name, name).details().clone();
+            details.relay_chain_genesis_hash = Some(BlockHash::from_low_u64_be(relay));
+            details.para_id = para_id;
+            Node::new(details)
+        };
+        let relay_parent_changed = |result| match result {
+            AddNodeResult::Added {
+                relay_parent_changed,
+                ..
+            } => relay_parent_changed,
+            AddNodeResult::Overquota => panic!("Chain not Overquota"),
+        };
+
+        // Nodes without a relay chain don't change anything:
+        assert!(!relay_parent_changed(
+            chain.add_node(node("A", "network-a"))
+        ));
+        assert_eq!(chain.relay_parent(), None);
+
+        assert!(relay_parent_changed(
+            chain.add_node(parachain_node("B", 2, None))
+        ));
+        // The para ID can be filled in later, but the relay chain can't change:
+        assert!(!relay_parent_changed(chain.add_node(parachain_node(
+            "C",
+            3,
+            Some(1000)
+        ))));
+        assert!(relay_parent_changed(chain.add_node(parachain_node(
+            "D",
+            2,
+            Some(1000)
+        ))));
+        assert_eq!(
+            chain.relay_parent(),
+            Some(&RelayParent {
+                genesis_hash: BlockHash::from_low_u64_be(2),
+                para_id: Some(1000),
+            })
+        );
+    }
+
+    #[test]
+    fn countries_are_counted_as_nodes_are_located() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let location = |country: Option<&str>| {
+            Some(std::sync::Arc::new(common::node_types::NodeLocation {
+                latitude: 0.0,
+                longitude: 0.0,
+                city: "City".into(),
+                country: country.map(|country| country.into()),
+                asn: None,
+                provider: None,
+            }))
+        };
+
+        let a = added_id(chain.add_node(node("A", "network")));
+        let b = added_id(chain.add_node(node("B", "network")));
+        let c = added_id(chain.add_node(node("C", "network")));
+        assert_eq!(
+            chain.stats_collator.generate().geo_nakamoto_coefficient,
+            None
+        );
+
+        chain.update_node_location(a, location(Some("DE")));
+        chain.update_node_location(b, location(Some("FR")));
+        chain.update_node_location(c, location(None));
+        let stats = chain.stats_collator.generate();
+        assert_eq!(stats.country.unknown, 1);
+        assert_eq!(stats.geo_nakamoto_coefficient, Some(2));
+
+        // Moving a node updates the counts rather than adding to them:
+        chain.update_node_location(b, location(Some("DE")));
+        let stats = chain.stats_collator.generate();
+        assert_eq!(stats.country.list, vec![("DE".to_owned(), 2)]);
+        assert_eq!(stats.geo_nakamoto_coefficient, Some(1));
+    }
+
+    #[test]
+    fn small_location_changes_are_ignored() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                min_location_change: 1000,
+                ..ChainConfig::default()
+            },
+        );
+        let location = |latitude: f32, city: &str| {
+            Some(std::sync::Arc::new(common::node_types::NodeLocation {
+                latitude,
+                longitude: 0.0,
+                city: city.into(),
+                country: None,
+                asn: None,
+                provider: None,
+            }))
+        };
+        let a = added_id(chain.add_node(node("A", "network")));
+        let city = |chain: &Chain| {
+            chain
+                .get_node(a)
+                .unwrap()
+                .location()
+                .unwrap()
+                .city
+                .to_string()
+        };
+
+        assert!(chain.update_node_location(a, location(50.0, "First")));
+        // A hundredth of a degree of latitude is about 1.1km, and a thousandth about 110m:
+        assert!(!chain.update_node_location(a, location(50.001, "Jitter")));
+        assert_eq!(city(&chain), "First");
+        assert!(chain.update_node_location(a, location(50.01, "Moved")));
+        assert_eq!(city(&chain), "Moved");
+
+        // Losing the location altogether always counts:
+        assert!(chain.update_node_location(a, None));
+    }
+
+    #[test]
+    fn providers_are_counted_as_nodes_are_located() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let location = |provider: Option<&str>| {
+            Some(std::sync::Arc::new(common::node_types::NodeLocation {
+                latitude: 0.0,
+                longitude: 0.0,
+                city: "City".into(),
+                country: None,
+                asn: provider.map(|_| 16509),
+                provider: provider.map(|provider| provider.into()),
+            }))
+        };
+
+        let a = added_id(chain.add_node(node("A", "network")));
+        let b = added_id(chain.add_node(node("B", "network")));
+        chain.update_node_location(a, location(Some("AMAZON-02")));
+        chain.update_node_location(b, location(None));
+        let stats = chain.stats_collator.generate();
+        assert_eq!(stats.provider.list, vec![("AMAZON-02".to_owned(), 1)]);
+        assert_eq!(stats.provider.unknown, 1);
+    }
+
+    #[test]
+    fn distinct_operators_are_counted_from_node_names() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let distinct_operators = |chain: &Chain| chain.stats_collator.generate().distinct_operators;
+
+        let a1 = added_id(chain.add_node(node("A/1", "network-a1")));
+        let a2 = added_id(chain.add_node(node("A/2", "network-a2")));
+        added_id(chain.add_node(node("B/1", "network-b1")));
+        added_id(chain.add_node(node("solo", "network-solo")));
+        assert_eq!(distinct_operators(&chain), 3);
+
+        chain.remove_node(a1, RemovalReason::Disconnected);
+        assert_eq!(distinct_operators(&chain), 3);
+        chain.remove_node(a2, RemovalReason::Disconnected);
+        assert_eq!(distinct_operators(&chain), 2);
+    }
+
+    #[test]
+    fn median_best_block_ignores_outliers() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let mut feed = FeedMessageSerializer::new();
+        let mut import = |chain: &mut Chain, id, height| {
+            let block = Block {
+                hash: BlockHash::from_low_u64_be(height),
+                height,
+            };
+            chain.update_node(id, Payload::BlockImport(block.into()), &mut feed);
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain.stats.median_best_block
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+        // Nodes that haven't reported a block yet aren't counted:
+        let _d = added_id(chain.add_node(node("D", "network-d")));
+        assert_eq!(regenerate(&mut chain), None);
+
+        import(&mut chain, a, 10);
+        import(&mut chain, b, 11);
+        import(&mut chain, c, 1000);
+        assert_eq!(chain.best.height, 1000);
+        assert_eq!(regenerate(&mut chain), Some(11));
+
+        // Stale nodes aren't counted either:
+        chain.nodes.get_mut(c).unwrap().update_stale(u64::MAX);
+        assert_eq!(regenerate(&mut chain), Some(10));
+    }
+
+    #[test]
+    fn feeds_are_told_when_most_nodes_are_syncing() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                syncing_majority: SyncingMajority {
+                    blocks_behind: 10,
+                    percent: 50,
+                },
+                ..ChainConfig::default()
+            },
+        );
+        let import = |chain: &mut Chain, id, height| {
+            let block = Block {
+                hash: BlockHash::from_low_u64_be(height),
+                height,
+            };
+            chain.update_node(
+                id,
+                Payload::BlockImport(block.into()),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let regenerate = |chain: &mut Chain| {
+            let mut feed = FeedMessageSerializer::new();
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut feed);
+            feed.into_finalized()
+                .map(|bytes| FeedMessage::from_bytes(&bytes).unwrap())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|msg| {
+                    matches!(
+                        msg,
+                        FeedMessage::MostlySyncing { .. } | FeedMessage::MostlySynced { .. }
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+        import(&mut chain, a, 100);
+        import(&mut chain, b, 95);
+        import(&mut chain, c, 50);
+        assert_eq!(regenerate(&mut chain), vec![]);
+        assert_eq!(chain.stats.syncing_nodes, 1);
+        assert!(!chain.stats.mostly_syncing);
+
+        // Half isn't a majority:
+        let d = added_id(chain.add_node(node("D", "network-d")));
+        import(&mut chain, d, 1);
+        assert_eq!(regenerate(&mut chain), vec![]);
+
+        // The best block moving on leaves B behind too:
+        import(&mut chain, a, 120);
+        assert_eq!(
+            regenerate(&mut chain),
+            vec![FeedMessage::MostlySyncing {
+                syncing: 3,
+                nodes: 4
+            }]
+        );
+        assert!(chain.stats.mostly_syncing);
+        // Only told once:
+        assert_eq!(regenerate(&mut chain), vec![]);
+
+        import(&mut chain, c, 120);
+        import(&mut chain, d, 120);
+        assert_eq!(
+            regenerate(&mut chain),
+            vec![FeedMessage::MostlySynced {
+                syncing: 1,
+                nodes: 4
+            }]
+        );
+        assert!(!chain.stats.mostly_syncing);
+    }
+
+    #[test]
+    fn nodes_that_stay_stale_can_be_removed() {
+        let stale_chain = |policy| {
+            let mut chain = Chain::new(
+                BlockHash::from_low_u64_be(1),
+                usize::MAX,
+                None,
+                ChainConfig {
+                    stale_timeouts: StaleTimeouts {
+                        stale: 1000,
+                        recover: 500,
+                        policy,
+                        removal: 60_000,
+                    },
+                    ..ChainConfig::default()
+                },
+            );
+            let a = added_id(chain.add_node(node("A", "network-a")));
+            let block = Block {
+                hash: BlockHash::from_low_u64_be(1),
+                height: 1,
+            };
+            chain.update_node(
+                a,
+                Payload::BlockImport(block.into()),
+                &mut FeedMessageSerializer::new(),
+            );
+            (chain, a)
+        };
+
+        let (mut chain, a) = stale_chain(StaleNodePolicy::Remove);
+        let now = time::now();
+        chain.update_stale_nodes(now + 2000, &mut FeedMessageSerializer::new());
+        assert!(chain.nodes.get(a).unwrap().stale());
+        assert!(chain.take_expired_nodes().is_empty());
+
+        // Still stale, but not for long enough:
+        chain.update_stale_nodes(now + 30_000, &mut FeedMessageSerializer::new());
+        assert!(chain.take_expired_nodes().is_empty());
+
+        // Long enough now, but we only hand nodes back once:
+        chain.update_stale_nodes(now + 70_000, &mut FeedMessageSerializer::new());
+        assert_eq!(chain.take_expired_nodes(), vec![a]);
+        assert!(chain.take_expired_nodes().is_empty());
+
+        // Nodes are kept indefinitely by default:
+        let (mut chain, a) = stale_chain(StaleNodePolicy::Keep);
+        chain.update_stale_nodes(now + 2000, &mut FeedMessageSerializer::new());
+        chain.update_stale_nodes(now + 70_000, &mut FeedMessageSerializer::new());
+        assert!(chain.nodes.get(a).unwrap().stale());
+        assert!(chain.take_expired_nodes().is_empty());
+    }
+
+    #[test]
+    fn nodes_with_backed_up_import_queues_are_counted() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                import_queue_backed_up_depth: 10,
+                ..ChainConfig::default()
+            },
+        );
+        let report = |chain: &mut Chain, id, depth| {
+            let interval = common::node_message::SystemInterval {
+                peers: None,
+                txcount: None,
+                bandwidth_upload: None,
+                bandwidth_download: None,
+                finalized_height: None,
+                finalized_hash: None,
+                block: None,
+                used_state_cache_size: None,
+                import_queue_depth: Some(depth),
+                block_txcount: None,
+                block_imports: None,
+                block_import_failures: None,
+                uptime: None,
+                session: None,
+                disk_used: None,
+                disk_free: None,
+                clock_skew: None,
+            };
+            chain.update_node(
+                id,
+                Payload::SystemInterval(interval),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let backed_up = |chain: &Chain| chain.stats_collator.generate().backed_up_import_queues;
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        for _ in 0..5 {
+            report(&mut chain, a, 100);
+            report(&mut chain, b, 1);
+        }
+        assert_eq!(backed_up(&chain), 1);
+        assert_eq!(chain.nodes.get(a).unwrap().stats().import_queue, 100);
+
+        // Recounting agrees:
+        chain.reconcile_stats();
+        assert_eq!(backed_up(&chain), 1);
+
+        // Backed up nodes that go away aren't counted any more:
+        chain.remove_node(a, RemovalReason::Disconnected);
+        assert_eq!(backed_up(&chain), 0);
+    }
+
+    #[test]
+    fn nodes_with_unhealthy_imports_are_counted() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                min_import_success_percent: 90,
+                ..ChainConfig::default()
+            },
+        );
+        let report = |chain: &mut Chain, id, imports, failures| {
+            let interval = common::node_message::SystemInterval {
+                peers: None,
+                txcount: None,
+                bandwidth_upload: None,
+                bandwidth_download: None,
+                finalized_height: None,
+                finalized_hash: None,
+                block: None,
+                used_state_cache_size: None,
+                import_queue_depth: None,
+                block_txcount: None,
+                block_imports: Some(imports),
+                block_import_failures: Some(failures),
+                uptime: None,
+                session: None,
+                disk_used: None,
+                disk_free: None,
+                clock_skew: None,
+            };
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, Payload::SystemInterval(interval), &mut feed);
+            feed.into_finalized()
+                .map(|bytes| FeedMessage::from_bytes(&bytes).unwrap())
+                .unwrap_or_default()
+        };
+        let unhealthy = |chain: &Chain| chain.stats_collator.generate().unhealthy_imports;
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        assert_eq!(
+            report(&mut chain, a, 50, 50),
+            vec![FeedMessage::NodeImportSuccess {
+                node_id: a.into(),
+                ratio: 0.5
+            }]
+        );
+        report(&mut chain, b, 100, 0);
+        assert_eq!(unhealthy(&chain), 1);
+
+        // Feeds aren't told about small changes:
+        assert_eq!(report(&mut chain, a, 502, 498), vec![]);
+
+        // Recounting agrees:
+        chain.reconcile_stats();
+        assert_eq!(unhealthy(&chain), 1);
+
+        // Nodes recover once they import enough blocks:
+        report(&mut chain, a, 1000, 50);
+        assert_eq!(unhealthy(&chain), 0);
+        report(&mut chain, b, 100, 100);
+        assert_eq!(unhealthy(&chain), 1);
+
+        // Unhealthy nodes that go away aren't counted any more:
+        chain.remove_node(b, RemovalReason::Disconnected);
+        assert_eq!(unhealthy(&chain), 0);
+    }
+
+    #[test]
+    fn moved_nodes_keep_counting_towards_import_problems() {
+        let config = || ChainConfig {
+            import_queue_backed_up_depth: 10,
+            min_import_success_percent: 90,
+            ..ChainConfig::default()
+        };
+        let mut from = Chain::new(BlockHash::from_low_u64_be(1), usize::MAX, None, config());
+        let mut to = Chain::new(BlockHash::from_low_u64_be(2), usize::MAX, None, config());
+        let report = |chain: &mut Chain, id, depth, imports, failures| {
+            let interval = common::node_message::SystemInterval {
+                peers: None,
+                txcount: None,
+                bandwidth_upload: None,
+                bandwidth_download: None,
+                finalized_height: None,
+                finalized_hash: None,
+                block: None,
+                used_state_cache_size: None,
+                import_queue_depth: Some(depth),
+                block_txcount: None,
+                block_imports: Some(imports),
+                block_import_failures: Some(failures),
+                uptime: None,
+                session: None,
+                disk_used: None,
+                disk_free: None,
+                clock_skew: None,
+            };
+            chain.update_node(
+                id,
+                Payload::SystemInterval(interval),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let counts = |chain: &Chain| {
+            let stats = chain.stats_collator.generate();
+            (stats.backed_up_import_queues, stats.unhealthy_imports)
+        };
+
+        let a = added_id(from.add_node(node("A", "network-a")));
+        for _ in 0..5 {
+            report(&mut from, a, 100, 50, 50);
+        }
+        assert_eq!(counts(&from), (1, 1));
+
+        // The node's problems move with it:
+        let node = from.extract_node(a).unwrap();
+        assert_eq!(counts(&from), (0, 0));
+        let a = added_id(to.add_node(node));
+        assert_eq!(counts(&to), (1, 1));
+
+        // And stop being counted once they clear up:
+        report(&mut to, a, 1, 1000, 50);
+        assert_eq!(counts(&to), (0, 0));
+    }
+
+    #[test]
+    fn nodes_are_counted_by_how_long_they_have_been_connected() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain.stats.connection_durations
+        };
+
+        chain.add_node(node("A", "network-a"));
+        chain.add_node(node("B", "network-b"));
+        let durations = regenerate(&mut chain);
+        assert_eq!(
+            (durations.fresh, durations.medium, durations.long),
+            (2, 0, 0)
+        );
+
+        // Nodes move up a tier once they've been connected for long enough:
+        chain.connection_tiers = ConnectionTiers {
+            medium: 0,
+            long: u64::MAX,
+        };
+        let durations = regenerate(&mut chain);
+        assert_eq!(
+            (durations.fresh, durations.medium, durations.long),
+            (0, 2, 0)
+        );
+    }
+
+    #[test]
+    fn nodes_can_stop_being_validators() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+        let mut authority = |authority_id: &str| {
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(
+                id,
+                Payload::AfgAuthoritySet(common::node_message::AfgAuthoritySet {
+                    authority_id: authority_id.into(),
+                }),
+                &mut feed,
+            );
+            let validators = chain.stats_collator.generate().validators;
+            let validator = feed
+                .into_finalized()
+                .map(|bytes| FeedMessage::from_bytes(&bytes).unwrap())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|msg| match msg {
+                    FeedMessage::AddedNode { node, .. } => node.validator,
+                    msg => panic!("Unexpected feed message {msg:?}"),
+                })
+                .collect::<Vec<_>>();
+            (validators, validator)
+        };
+
+        assert_eq!(authority("validator"), (1, vec![Some("validator".into())]));
+        // Feeds are told when the address is cleared, and it's no longer counted:
+        assert_eq!(authority(""), (0, vec![None]));
+        assert_eq!(authority(""), (0, vec![]));
+        assert_eq!(authority("validator"), (1, vec![Some("validator".into())]));
+    }
+
+    #[test]
+    fn feeds_are_alerted_about_a_validator_shortfall() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                expected_validators: Some(4),
+                validator_shortfall_threshold: 1,
+                ..ChainConfig::default()
+            },
+        );
+        let authority = |chain: &mut Chain, id, authority_id: &str| {
+            chain.update_node(
+                id,
+                Payload::AfgAuthoritySet(common::node_message::AfgAuthoritySet {
+                    authority_id: authority_id.into(),
+                }),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let regenerate = |chain: &mut Chain| {
+            let mut feed = FeedMessageSerializer::new();
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut feed);
+            feed.into_finalized()
+                .map(|bytes| FeedMessage::from_bytes(&bytes).unwrap())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|msg| {
+                    matches!(
+                        msg,
+                        FeedMessage::ValidatorShortfall { .. }
+                            | FeedMessage::ValidatorShortfallRecovered { .. }
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+        authority(&mut chain, a, "validator-a");
+        authority(&mut chain, b, "validator-b");
+        assert_eq!(
+            regenerate(&mut chain),
+            vec![FeedMessage::ValidatorShortfall {
+                expected: 4,
+                observed: 2
+            }]
+        );
+        assert_eq!(chain.stats.validators, 2);
+        // Only alerted once, and changing authority doesn't count twice:
+        authority(&mut chain, b, "validator-b2");
+        assert_eq!(regenerate(&mut chain), vec![]);
+        assert_eq!(chain.stats.validators, 2);
+
+        // One short is within the threshold:
+        authority(&mut chain, c, "validator-c");
+        assert_eq!(
+            regenerate(&mut chain),
+            vec![FeedMessage::ValidatorShortfallRecovered {
+                expected: 4,
+                observed: 3
+            }]
+        );
+
+        chain.remove_node(a, RemovalReason::Disconnected);
+        assert_eq!(
+            regenerate(&mut chain),
+            vec![FeedMessage::ValidatorShortfall {
+                expected: 4,
+                observed: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn node_updates_over_the_feed_rate_limit_are_dropped() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                feed_rate_limit: 2,
+                ..ChainConfig::default()
+            },
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+
+        let mut feed = FeedMessageSerializer::new();
+        for height in 1..=5 {
+            let interval = common::node_message::SystemInterval {
+                peers: Some(height),
+                txcount: Some(height),
+                bandwidth_upload: None,
+                bandwidth_download: None,
+                finalized_height: None,
+                finalized_hash: None,
+                block: Some(Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                }),
+                used_state_cache_size: None,
+                import_queue_depth: None,
+                block_txcount: None,
+                block_imports: None,
+                block_import_failures: None,
+                uptime: None,
+                session: None,
+                disk_used: None,
+                disk_free: None,
+                clock_skew: None,
+            };
+            chain.update_node(id, Payload::SystemInterval(interval), &mut feed);
+        }
+        let msgs = FeedMessage::from_bytes(&feed.into_finalized().unwrap()).unwrap();
+
+        // Best blocks are always sent, but stats updates stop at the limit:
+        let best_blocks = msgs
+            .iter()
+            .filter(|msg| matches!(msg, FeedMessage::BestBlock { .. }))
+            .count();
+        let stats_updates = msgs
+            .iter()
+            .filter(|msg| {
+                matches!(
+                    msg,
+                    FeedMessage::NodeStatsUpdate { .. } | FeedMessage::NodeStatsDelta { .. }
+                )
+            })
+            .count();
+        assert_eq!(best_blocks, 5);
+        assert_eq!(stats_updates, 2);
+        assert_eq!(chain.feed_rate_limiter.take_dropped(), 3);
+    }
+
+    #[test]
+    fn finalized_blocks_reported_out_of_order_are_not_sent_to_feeds() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+        let mut update = |payload| {
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, payload, &mut feed);
+            feed.into_finalized()
+                .map(|bytes| FeedMessage::from_bytes(&bytes).unwrap())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|msg| matches!(msg, FeedMessage::FinalizedBlock { .. }))
+                .count()
+        };
+        let finalize = |height: u64| {
+            Payload::NotifyFinalized(common::node_message::Finalized {
+                hash: BlockHash::from_low_u64_be(height),
+                height: height.to_string().into(),
+            })
+        };
+        update(Payload::BlockImport(
+            Block {
+                hash: BlockHash::from_low_u64_be(10),
+                height: 10,
+            }
+            .into(),
+        ));
+
+        assert_eq!(update(finalize(10)), 1);
+        // A late report of an older finalized block is ignored:
+        assert_eq!(update(finalize(5)), 0);
+        assert_eq!(chain.get_node(id).unwrap().finalized().height, 10);
+        assert_eq!(chain.finalized_block().height, 10);
+    }
+
+    #[test]
+    fn feeds_are_sent_the_average_ping_latency_of_nodes() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+        let last_seen = chain.get_node(id).unwrap().last_seen();
+        assert_eq!(chain.get_node(id).unwrap().ping_latency(), None);
+
+        let mut ping = |latency| {
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, Payload::PingLatency(latency), &mut feed);
+            FeedMessage::from_bytes(&feed.into_finalized().unwrap()).unwrap()
+        };
+        assert_eq!(
+            ping(100),
+            vec![FeedMessage::NodePingLatency {
+                node_id: id.into(),
+                latency: 100
+            }]
+        );
+        assert_eq!(
+            ping(200),
+            vec![FeedMessage::NodePingLatency {
+                node_id: id.into(),
+                latency: 150
+            }]
+        );
+
+        // Pings come from the shard, so they aren't counted as hearing from the node:
+        assert_eq!(chain.get_node(id).unwrap().last_seen(), last_seen);
+        assert_eq!(chain.messages_per_second(), 0.0);
+    }
+
+    #[test]
+    fn quota_bursts_after_a_mass_disconnect() {
+        let new_chain = |quota_burst| {
+            Chain::new(
+                BlockHash::from_low_u64_be(1),
+                10,
+                None,
+                ChainConfig {
+                    quota_burst,
+                    ..ChainConfig::default()
+                },
+            )
+        };
+        let fill = |chain: &mut Chain| -> Vec<ChainNodeId> {
+            let mut ids = Vec::new();
+            while let AddNodeResult::Added { id, .. } = chain.add_node(node("A", "network")) {
+                ids.push(id);
+            }
+            ids
+        };
+
+        let mut chain = new_chain(QuotaBurst {
+            allowance_percent: 50,
+            window: Duration::from_secs(60),
+            mass_disconnect_percent: 30,
+        });
+        let ids = fill(&mut chain);
+        assert_eq!(ids.len(), 10);
+
+        // A couple of disconnects aren't enough to let more in:
+        chain.remove_node(ids[0], RemovalReason::Disconnected);
+        chain.remove_node(ids[1], RemovalReason::Disconnected);
+        assert_eq!(fill(&mut chain).len(), 2);
+        assert!(chain.is_overquota());
+
+        // But a third is, until the window has passed:
+        chain.remove_node(ids[2], RemovalReason::Disconnected);
+        assert_eq!(fill(&mut chain).len(), 1 + 5);
+        chain.quota_burst_until = Some(Instant::now());
+        assert!(chain.is_overquota());
+
+        // Bursts can be disabled entirely:
+        let mut chain = new_chain(QuotaBurst {
+            allowance_percent: 0,
+            ..QuotaBurst::default()
+        });
+        let ids = fill(&mut chain);
+        for &id in &ids {
+            chain.remove_node(id, RemovalReason::Disconnected);
+        }
+        assert_eq!(fill(&mut chain).len(), 10);
+    }
+
+    #[test]
+    fn node_limits_can_be_changed() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            3,
+            None,
+            ChainConfig::default(),
+        );
+        let fill = |chain: &mut Chain| -> Vec<ChainNodeId> {
+            let mut ids = Vec::new();
+            while let AddNodeResult::Added { id, .. } = chain.add_node(node("A", "")) {
+                ids.push(id);
+            }
+            ids
+        };
+        assert_eq!(fill(&mut chain).len(), 3);
+
+        chain.set_max_nodes(5);
+        let mut ids = fill(&mut chain);
+        assert_eq!(ids.len(), 2);
+        assert!(chain.nodes_over_quota().is_empty());
+
+        // Lowering the limit keeps every node, but the newest ones are over it:
+        chain.set_max_nodes(2);
+        assert!(chain.is_overquota());
+        assert_eq!(chain.node_count(), 5);
+        ids.reverse();
+        assert_eq!(&chain.nodes_over_quota()[..2], &ids[..]);
+        assert_eq!(chain.nodes_over_quota().len(), 3);
+    }
+
+    #[test]
+    fn sudden_node_count_drops_are_spotted() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let ids: Vec<_> = (0..12)
+            .map(|n| {
+                let name = n.to_string();
+                added_id(chain.add_node(node(&name, &name)))
+            })
+            .collect();
+
+        let drops: Vec<_> = ids
+            .iter()
+            .map(|&id| {
+                chain
+                    .remove_node(id, RemovalReason::Disconnected)
+                    .node_count_drop
+            })
+            .collect();
+        // Only alerted once half of the nodes have gone; the rest are too few to alert about:
+        assert_eq!(
+            drops[5],
+            Some(NodeCountDrop {
+                from: 12,
+                to: 6,
+                window: RollingWindows::default().node_count_drop,
+            })
+        );
+        assert_eq!(drops.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn node_counts_last_until_they_change() {
+        let mut history = NodeCountHistory::new(Duration::from_secs(30));
+        let start = Instant::now();
+        history.record(start, 20);
+
+        // Nothing has changed for longer than the window, but we still had 20 nodes until now:
+        history.record(start + Duration::from_secs(60), 19);
+        assert_eq!(history.peak(), 20);
+
+        history.record(start + Duration::from_secs(91), 18);
+        assert_eq!(history.peak(), 19);
+    }
+
+    #[test]
+    fn reconciling_stats_corrects_drift() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let expected = chain.stats_collator.generate();
+
+        // A node that was never removed properly still counts towards the stats:
+        let ghost = node("Ghost", "network-c");
+        chain.stats_collator.add_or_remove_node(
+            ghost.details(),
+            None,
+            None,
+            CounterValue::Increment,
+        );
+        assert!(chain.stats_collator.generate() != expected);
+
+        chain.reconcile_stats();
+        assert!(chain.stats_collator.generate() == expected);
+
+        // Only the nodes that are still connected are counted:
+        chain.remove_node(b, RemovalReason::Disconnected);
+        let expected = chain.stats_collator.generate();
+        chain.reconcile_stats();
+        assert!(chain.stats_collator.generate() == expected);
+    }
+
+    #[test]
+    fn frozen_chains_ignore_node_updates() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+        let import = |height| {
+            Payload::BlockImport(
+                Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                }
+                .into(),
+            )
+        };
+
+        let mut feed = FeedMessageSerializer::new();
+        chain.update_node(id, import(1), &mut feed);
+        assert_eq!(chain.best_block().height, 1);
+
+        assert!(chain.set_frozen(true));
+        assert!(!chain.set_frozen(true));
+        assert!(chain.stats().frozen);
+        chain.update_node(id, import(2), &mut feed);
+        assert_eq!(chain.best_block().height, 1);
+        assert_eq!(chain.get_node(id).unwrap().best().height, 1);
+
+        assert!(chain.set_frozen(false));
+        chain.update_node(id, import(3), &mut feed);
+        assert_eq!(chain.best_block().height, 3);
+    }
+
+    #[test]
+    fn messages_per_second_counts_node_updates() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                windows: RollingWindows {
+                    message_rate: Duration::from_secs(5),
+                    ..RollingWindows::default()
+                },
+                ..ChainConfig::default()
+            },
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+        assert_eq!(chain.messages_per_second(), 0.0);
+
+        let mut feed = FeedMessageSerializer::new();
+        for height in 1..=5 {
+            let payload = Payload::NotifyFinalized(common::node_message::Finalized {
+                hash: BlockHash::from_low_u64_be(height),
+                height: height.to_string().into(),
+            });
+            chain.update_node(id, payload, &mut feed);
+        }
+
+        assert_eq!(chain.messages_per_second(), 1.0);
+    }
+
+    #[test]
+    fn rolling_windows_must_not_be_too_small() {
+        assert!(RollingWindows::default().check().is_ok());
+
+        let windows = |f: fn(&mut RollingWindows)| {
+            let mut windows = RollingWindows::default();
+            f(&mut windows);
+            windows.check()
+        };
+        assert!(windows(|w| w.block_times.size = 0).is_err());
+        assert!(windows(|w| w.propagation_times = 0).is_err());
+        assert!(windows(|w| w.block_times.duration = Some(Duration::ZERO)).is_err());
+        assert!(windows(|w| w.message_rate = Duration::from_millis(500)).is_err());
+        assert!(windows(|w| w.mass_disconnect = Duration::from_millis(1500)).is_err());
+        assert!(windows(|w| w.node_count_drop = Duration::ZERO).is_err());
+        assert!(windows(|w| w.message_rate = Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn chains_without_a_useful_label_get_a_placeholder() {
+        let genesis_hash = BlockHash::from_slice(&[0x14, 0x59, 0xb0, 0xff].repeat(8));
+        let labelled = |name: &str, label: &str| {
+            let mut details = node(name, "").details().clone();
+            details.chain = label.into();
+            Node::new(details)
+        };
+
+        let mut chain = Chain::new(genesis_hash, usize::MAX, None, ChainConfig::default());
+        assert_eq!(chain.label(), "chain-0x1459b0");
+        added_id(chain.add_node(labelled("A", "  ")));
+        assert_eq!(chain.label(), "chain-0x1459b0");
+        // A useful label is used as soon as nodes report one:
+        added_id(chain.add_node(labelled("B", "Chain One")));
+        added_id(chain.add_node(labelled("C", "Chain One")));
+        assert_eq!(chain.label(), "Chain One");
+
+        let chain = Chain::new(
+            genesis_hash,
+            usize::MAX,
+            None,
+            ChainConfig {
+                unlabeled_format: "Unknown ({genesis_hash})".into(),
+                ..ChainConfig::default()
+            },
+        );
+        assert_eq!(chain.label(), format!("Unknown ({genesis_hash:?})"));
+    }
+
+    #[test]
+    fn validators_can_outvote_other_nodes_on_the_label() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                validator_label_weight: 3,
+                ..ChainConfig::default()
+            },
+        );
+        let labelled = |name: &str, label: &str| {
+            let mut details = node(name, "").details().clone();
+            details.chain = label.into();
+            Node::new(details)
+        };
+
+        added_id(chain.add_node(labelled("A", "Full Nodes")));
+        added_id(chain.add_node(labelled("B", "Full Nodes")));
+        let v = added_id(chain.add_node(labelled("V", "Validators")));
+        assert_eq!(chain.label(), "Full Nodes");
+
+        // Once the node turns out to be a validator, its vote counts for three:
+        chain.update_node(
+            v,
+            Payload::AfgAuthoritySet(common::node_message::AfgAuthoritySet {
+                authority_id: "validator".into(),
+            }),
+            &mut FeedMessageSerializer::new(),
+        );
+        assert_eq!(chain.label(), "Validators");
+        assert!(chain.take_label_changed());
+        assert!(!chain.take_label_changed());
+
+        // They go back to counting for one if it stops being a validator:
+        let set_authority = |chain: &mut Chain, authority_id: &str| {
+            chain.update_node(
+                v,
+                Payload::AfgAuthoritySet(common::node_message::AfgAuthoritySet {
+                    authority_id: authority_id.into(),
+                }),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        set_authority(&mut chain, "");
+        assert_eq!(chain.label(), "Full Nodes");
+        assert!(chain.take_label_changed());
+        set_authority(&mut chain, "validator");
+        assert_eq!(chain.label(), "Validators");
+        assert!(chain.take_label_changed());
+
+        // And all three votes go when it does:
+        let removed = chain.remove_node(v, RemovalReason::Disconnected);
+        assert!(removed.chain_renamed);
+        assert_eq!(chain.label(), "Full Nodes");
+    }
+
+    #[test]
+    fn compacting_corrects_drifted_stats_and_frees_slots() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+        chain.remove_node(b, RemovalReason::Disconnected);
+        chain.remove_node(c, RemovalReason::Disconnected);
+
+        // Pretend that a counter has drifted from the nodes:
+        chain
+            .stats_collator
+            .update_validators(CounterValue::Increment);
+
+        let mut feed = FeedMessageSerializer::new();
+        let summary = chain.compact(&mut feed);
+        assert_eq!(
+            summary,
+            CompactSummary {
+                nodes: 1,
+                slots_freed: 2,
+                corrected_stats: vec!["validators".to_owned()],
+            }
+        );
+        assert_eq!(chain.stats.validators, 0);
+        // The stats were regenerated straight away:
+        assert!(feed.into_finalized().is_some());
+
+        // Nothing's changed since:
+        let summary = chain.compact(&mut FeedMessageSerializer::new());
+        assert_eq!(summary.slots_freed, 0);
+        assert!(summary.corrected_stats.is_empty());
+    }
+
+    #[test]
+    fn finalized_ages_only_count_nodes_that_have_finalized() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let finalize = |chain: &mut Chain, id, height: u64| {
+            // Nodes can't finalize past their best block:
+            let best = Block {
+                hash: BlockHash::from_low_u64_be(height),
+                height,
+            };
+            chain.update_node(
+                id,
+                Payload::BlockImport(best.into()),
+                &mut FeedMessageSerializer::new(),
+            );
+            let finalized = common::node_message::Finalized {
+                hash: BlockHash::from_low_u64_be(height),
+                height: height.to_string().into(),
+            };
+            chain.update_node(
+                id,
+                Payload::NotifyFinalized(finalized),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            (chain.stats.min_finalized_age, chain.stats.max_finalized_age)
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        assert_eq!(regenerate(&mut chain), (None, None));
+
+        finalize(&mut chain, a, 1);
+        let (min, max) = regenerate(&mut chain);
+        assert_eq!(min, max);
+        assert!(min.is_some());
+
+        finalize(&mut chain, b, 2);
+        let (min, max) = regenerate(&mut chain);
+        assert!(min.unwrap() <= max.unwrap());
+
+        // Stale nodes are left out:
+        chain.nodes.get_mut(a).unwrap().update_stale(u64::MAX);
+        chain.nodes.get_mut(b).unwrap().update_stale(u64::MAX);
+        assert_eq!(regenerate(&mut chain), (None, None));
+    }
+
+    #[test]
+    fn best_blocks_are_attributed_to_their_author_once() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let import = |chain: &mut Chain, id, height: u64, author: Option<&str>| {
+            let import = common::node_message::BlockImport {
+                block: Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                },
+                author: author.map(Into::into),
+            };
+            chain.update_node(
+                id,
+                Payload::BlockImport(import),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain
+                .stats
+                .top_block_authors
+                .iter()
+                .map(|author| (author.author.to_string(), author.blocks))
+                .collect::<Vec<_>>()
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+
+        // A doesn't say who authored block 1, but B does:
+        import(&mut chain, a, 1, None);
+        import(&mut chain, b, 1, Some("alice"));
+        // Both say who authored block 2, but it's only counted once:
+        import(&mut chain, a, 2, Some("bob"));
+        import(&mut chain, b, 2, Some("bob"));
+        import(&mut chain, a, 3, Some("bob"));
+        // Nobody says who authored block 4:
+        import(&mut chain, a, 4, None);
+
+        assert_eq!(
+            regenerate(&mut chain),
+            vec![("bob".to_string(), 2), ("alice".to_string(), 1)]
+        );
+        assert_eq!(chain.stats.inactive_block_authors, 0);
+
+        // Authors are inactive once they haven't produced a block for a while:
+        chain.block_author_inactive_after = 0;
+        chain.block_authors = BlockAuthors::default();
+        chain.block_authors.record("carol", 0);
+        regenerate(&mut chain);
+        assert_eq!(chain.stats.inactive_block_authors, 1);
+        assert!(chain.stats.top_block_authors[0].inactive);
+    }
+
+    #[test]
+    fn implausible_best_blocks_are_ignored() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                max_best_block_lead: 100,
+                ..ChainConfig::default()
+            },
+        );
+        let import = |chain: &mut Chain, id, height: u64| {
+            let block = Block {
+                hash: BlockHash::from_low_u64_be(height),
+                height,
+            };
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, Payload::BlockImport(block.into()), &mut feed);
+            let msgs = match feed.into_finalized() {
+                Some(bytes) => FeedMessage::from_bytes(&bytes).unwrap(),
+                None => Vec::new(),
+            };
+            msgs.iter()
+                .any(|msg| matches!(msg, FeedMessage::ImplausibleBlock { height: h, .. } if *h == height))
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+
+        // Anything goes until we have a best block:
+        assert!(!import(&mut chain, a, 1000));
+        assert_eq!(chain.best.height, 1000);
+
+        // Blocks too far ahead are ignored, and feeds are told the first time:
+        assert!(import(&mut chain, b, 5000));
+        assert!(!import(&mut chain, b, 6000));
+        assert_eq!(chain.best.height, 1000);
+        assert_eq!(chain.nodes.get(b).unwrap().best().height, 0);
+        assert_eq!(chain.nodes.get(b).unwrap().implausible_blocks(), 2);
+
+        // Blocks close enough to the best block move it along:
+        assert!(!import(&mut chain, b, 1100));
+        assert_eq!(chain.best.height, 1100);
+
+        // A node can't report a best block below its own finalized block:
+        let finalized = common::node_message::Finalized {
+            hash: BlockHash::from_low_u64_be(1100),
+            height: "1100".into(),
+        };
+        chain.update_node(
+            b,
+            Payload::NotifyFinalized(finalized),
+            &mut FeedMessageSerializer::new(),
+        );
+        import(&mut chain, b, 0);
+        assert_eq!(chain.nodes.get(b).unwrap().best().height, 1100);
+        assert_eq!(chain.nodes.get(b).unwrap().implausible_blocks(), 3);
+
+        chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+        chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+        assert_eq!(chain.stats.implausible_block_nodes, 1);
+    }
+
+    #[test]
+    fn best_blocks_behind_the_chain_are_plausible() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                max_best_block_lead: 100,
+                ..ChainConfig::default()
+            },
+        );
+        let import = |chain: &mut Chain, id, height: u64| {
+            let block = Block {
+                hash: BlockHash::from_low_u64_be(height),
+                height,
+            };
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, Payload::BlockImport(block.into()), &mut feed);
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        import(&mut chain, a, 1000);
+
+        // A node that's still catching up is well behind the best block:
+        import(&mut chain, b, 10);
+        assert_eq!(chain.nodes.get(b).unwrap().best().height, 10);
+        assert_eq!(chain.nodes.get(b).unwrap().implausible_blocks(), 0);
+        assert_eq!(chain.best.height, 1000);
+    }
+
+    #[test]
+    fn feeds_are_told_when_the_average_peer_count_changes_enough() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let report = |chain: &mut Chain, id, peers| {
+            let interval = common::node_message::SystemInterval {
+                peers: Some(peers),
+                txcount: None,
+                bandwidth_upload: None,
+                bandwidth_download: None,
+                finalized_height: None,
+                finalized_hash: None,
+                block: None,
+                used_state_cache_size: None,
+                import_queue_depth: None,
+                block_txcount: None,
+                block_imports: None,
+                block_import_failures: None,
+                uptime: None,
+                session: None,
+                disk_used: None,
+                disk_free: None,
+                clock_skew: None,
+            };
+            chain.update_node(
+                id,
+                Payload::SystemInterval(interval),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            let mut feed = FeedMessageSerializer::new();
+            chain.regenerate_stats_if_necessary(&mut feed);
+            let msgs = match feed.into_finalized() {
+                Some(bytes) => FeedMessage::from_bytes(&bytes).unwrap(),
+                None => Vec::new(),
+            };
+            let told = msgs.into_iter().find_map(|msg| match msg {
+                FeedMessage::AveragePeers { average, previous } => Some((average, previous)),
+                _ => None,
+            });
+            (chain.stats.average_peers, told)
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        report(&mut chain, a, 20);
+        report(&mut chain, b, 40);
+        assert_eq!(regenerate(&mut chain), (Some(30.0), Some((30.0, None))));
+
+        // A small change is shown in the stats, but feeds aren't told about it:
+        report(&mut chain, b, 42);
+        assert_eq!(regenerate(&mut chain), (Some(31.0), None));
+
+        // A big drop is:
+        report(&mut chain, a, 2);
+        report(&mut chain, b, 4);
+        assert_eq!(regenerate(&mut chain), (Some(3.0), Some((3.0, Some(30.0)))));
+
+        // Stale nodes are left out:
+        chain.nodes.get_mut(a).unwrap().update_stale(u64::MAX);
+        assert_eq!(regenerate(&mut chain).0, Some(4.0));
+    }
+
+    #[test]
+    fn recently_removed_nodes_are_kept_for_auditing() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                removed_nodes_len: 2,
+                ..ChainConfig::default()
+            },
+        );
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+        chain.update_node(
+            b,
+            Payload::BlockImport(Block::zero().into()),
+            &mut FeedMessageSerializer::new(),
+        );
+
+        chain.remove_node(a, RemovalReason::Disconnected);
+        chain.remove_node(b, RemovalReason::Stale);
+        chain.remove_node(c, RemovalReason::ShardDisconnected);
+        // Removing a node that's already gone isn't recorded again:
+        chain.remove_node(c, RemovalReason::Replaced);
+
+        let removed: Vec<_> = chain
+            .removed_nodes()
+            .iter()
+            .map(|removed| (&*removed.name, removed.network_id.as_str(), removed.reason))
+            .collect();
+        assert_eq!(
+            removed,
+            vec![
+                ("B", "network-b", RemovalReason::Stale),
+                ("C", "network-c", RemovalReason::ShardDisconnected),
+            ]
+        );
+    }
+
+    #[test]
+    fn node_annotations_are_sent_to_feeds_until_they_expire() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let annotation = |text: &str, expires_at| NodeAnnotation {
+            text: text.into(),
+            severity: crate::state::AnnotationSeverity::Warning,
+            expires_at,
+        };
+
+        let mut feed = FeedMessageSerializer::new();
+        assert!(chain.annotate_node(a, Some(annotation("Maintenance", 1000)), &mut feed));
+        assert!(chain.annotate_node(b, Some(annotation("Upgrading", 2000)), &mut feed));
+        assert!(!chain.annotate_node(ChainNodeId::from(99), None, &mut feed));
+        let sent = feed.into_finalized().unwrap();
+        assert_eq!(
+            &*sent,
+            br#"[48,[0,"Maintenance","warning",1000],48,[1,"Upgrading","warning",2000]]"#
+        );
+
+        // Only the annotations that have expired are cleared:
+        let mut feed = FeedMessageSerializer::new();
+        chain.expire_node_annotations(1500, &mut feed);
+        assert_eq!(&*feed.into_finalized().unwrap(), b"[49,0]");
+        assert!(chain.get_node(a).unwrap().annotation().is_none());
+        assert_eq!(
+            chain.get_node(b).unwrap().annotation(),
+            Some(&annotation("Upgrading", 2000))
+        );
+    }
+
+    #[test]
+    fn recent_events_are_kept_for_reviewing() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                finality_gap_threshold: 5,
+                recent_events_len: 2,
+                ..ChainConfig::default()
+            },
+        );
+        let mut feed = FeedMessageSerializer::new();
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let import = |chain: &mut Chain, feed: &mut FeedMessageSerializer, height: u64| {
+            let best = Block {
+                hash: BlockHash::from_low_u64_be(height),
+                height,
+            };
+            chain.update_node(a, Payload::BlockImport(best.into()), feed);
+        };
+        let finalize = |chain: &mut Chain, feed: &mut FeedMessageSerializer, height: u64| {
+            let payload = Payload::NotifyFinalized(common::node_message::Finalized {
+                hash: BlockHash::from_low_u64_be(height),
+                height: height.to_string().into(),
+            });
+            chain.update_node(a, payload, feed);
+        };
+
+        import(&mut chain, &mut feed, 10);
+        finalize(&mut chain, &mut feed, 10);
+        import(&mut chain, &mut feed, 20);
+
+        // Only the most recent events are kept:
+        let events: Vec<_> = chain.recent_events().iter().map(|e| &e.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                &ChainEvent::FinalityRecovered { gap: 0 },
+                &ChainEvent::FinalityStalled { gap: 10 },
+            ]
+        );
+    }
+
+    #[test]
+    fn nodes_whose_process_started_recently_are_counted() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                recent_restart_window: 60_000,
+                ..ChainConfig::default()
+            },
+        );
+        let report = |chain: &mut Chain, id, uptime| {
+            let interval = common::node_message::SystemInterval {
+                peers: None,
+                txcount: None,
+                bandwidth_upload: None,
+                bandwidth_download: None,
+                finalized_height: None,
+                finalized_hash: None,
+                block: None,
+                used_state_cache_size: None,
+                import_queue_depth: None,
+                block_txcount: None,
+                block_imports: None,
+                block_import_failures: None,
+                uptime: Some(uptime),
+                session: None,
+                disk_used: None,
+                disk_free: None,
+                clock_skew: None,
+            };
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, Payload::SystemInterval(interval), &mut feed);
+            let msgs = match feed.into_finalized() {
+                Some(bytes) => FeedMessage::from_bytes(&bytes).unwrap(),
+                None => Vec::new(),
+            };
+            msgs.iter()
+                .any(|msg| matches!(msg, FeedMessage::ProcessStarted { .. }))
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain.stats.recently_restarted
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        // C never reports its uptime, and is left out:
+        added_id(chain.add_node(node("C", "network-c")));
+
+        assert!(report(&mut chain, a, 24 * 60 * 60));
+        assert!(report(&mut chain, b, 5));
+        assert_eq!(regenerate(&mut chain), 1);
+
+        // Feeds are only told again if the node restarts:
+        assert!(!report(&mut chain, a, 24 * 60 * 60 + 10));
+        assert!(report(&mut chain, a, 1));
+        assert_eq!(regenerate(&mut chain), 2);
+    }
+
+    #[test]
+    fn sessions_rotate_once_most_nodes_move_on() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let report = |chain: &mut Chain, id, session| {
+            let interval = common::node_message::SystemInterval {
+                peers: None,
+                txcount: None,
+                bandwidth_upload: None,
+                bandwidth_download: None,
+                finalized_height: None,
+                finalized_hash: None,
+                block: None,
+                used_state_cache_size: None,
+                import_queue_depth: None,
+                block_txcount: None,
+                block_imports: None,
+                block_import_failures: None,
+                uptime: None,
+                session: Some(session),
+                disk_used: None,
+                disk_free: None,
+                clock_skew: None,
+            };
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, Payload::SystemInterval(interval), &mut feed);
+            let msgs = match feed.into_finalized() {
+                Some(bytes) => FeedMessage::from_bytes(&bytes).unwrap(),
+                None => Vec::new(),
+            };
+            msgs.into_iter()
+                .filter(|msg| matches!(msg, FeedMessage::SessionRotation { .. }))
+                .collect::<Vec<_>>()
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+        assert_eq!(chain.session(), None);
+
+        // The first session reported isn't a rotation:
+        for id in [a, b, c] {
+            assert!(report(&mut chain, id, 10).is_empty());
+        }
+        assert_eq!(chain.session(), Some(10));
+
+        // One node moving on isn't enough, but most of them are:
+        assert!(report(&mut chain, a, 11).is_empty());
+        assert_eq!(chain.session(), Some(10));
+        assert_eq!(
+            report(&mut chain, b, 11),
+            vec![FeedMessage::SessionRotation { from: 10, to: 11 }]
+        );
+        assert_eq!(chain.session(), Some(11));
+
+        // Sessions never go backwards, even if the nodes that moved on leave:
+        chain.remove_node(a, RemovalReason::Disconnected);
+        chain.remove_node(b, RemovalReason::Disconnected);
+        assert!(report(&mut chain, c, 10).is_empty());
+        assert_eq!(chain.session(), Some(11));
+    }
+
+    #[test]
+    fn finalized_blocks_ahead_of_the_best_block_are_clamped() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let finalize = |chain: &mut Chain, id, height: u64| {
+            let finalized = common::node_message::Finalized {
+                hash: BlockHash::from_low_u64_be(height),
+                height: height.to_string().into(),
+            };
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, Payload::NotifyFinalized(finalized), &mut feed);
+            match feed.into_finalized() {
+                Some(bytes) => FeedMessage::from_bytes(&bytes).unwrap(),
+                None => Vec::new(),
+            }
+        };
+        let flagged = |msgs: &[FeedMessage]| {
+            msgs.iter()
+                .any(|msg| matches!(msg, FeedMessage::FinalizedAheadOfBest { .. }))
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let best = Block {
+            hash: BlockHash::from_low_u64_be(100),
+            height: 100,
+        };
+        chain.update_node(
+            a,
+            Payload::BlockImport(best.into()),
+            &mut FeedMessageSerializer::new(),
+        );
+
+        // Finalized blocks up to the best block are fine:
+        assert!(!flagged(&finalize(&mut chain, a, 90)));
+        assert_eq!(chain.finalized.height, 90);
+
+        // Those beyond it are clamped to it, and feeds are told the first time:
+        let msgs = finalize(&mut chain, a, 150);
+        assert!(msgs.contains(&FeedMessage::FinalizedAheadOfBest {
+            node_id: 0,
+            finalized_height: 150,
+            best_height: 100,
+        }));
+        assert_eq!(*chain.nodes.get(a).unwrap().finalized(), best);
+        assert_eq!(chain.finalized, best);
+        assert!(chain.finalized.height <= chain.best.height);
+
+        assert!(!flagged(&finalize(&mut chain, a, 200)));
+        assert_eq!(chain.finalized.height, 100);
+    }
+
+    #[test]
+    fn feeds_are_told_once_when_finality_starts() {
+        use test_utils::feed_message_de::FeedMessage;
+
+        let genesis_hash = BlockHash::from_low_u64_be(1);
+        let mut chain = Chain::new(genesis_hash, usize::MAX, None, ChainConfig::default());
+        let finalize = |chain: &mut Chain, id, height: u64| {
+            let block = Block {
+                hash: BlockHash::from_low_u64_be(height),
+                height,
+            };
+            chain.update_node(
+                id,
+                Payload::BlockImport(block.into()),
+                &mut FeedMessageSerializer::new(),
+            );
+            let finalized = common::node_message::Finalized {
+                hash: block.hash,
+                height: height.to_string().into(),
+            };
+            let mut feed = FeedMessageSerializer::new();
+            chain.update_node(id, Payload::NotifyFinalized(finalized), &mut feed);
+            let msgs = match feed.into_finalized() {
+                Some(bytes) => FeedMessage::from_bytes(&bytes).unwrap(),
+                None => Vec::new(),
+            };
+            msgs.into_iter()
+                .filter(|msg| matches!(msg, FeedMessage::FinalityStarted { .. }))
+                .collect::<Vec<_>>()
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        assert!(finalize(&mut chain, a, 0).is_empty());
+        assert_eq!(
+            finalize(&mut chain, a, 3),
+            vec![FeedMessage::FinalityStarted {
+                genesis_hash,
+                height: 3
+            }]
+        );
+        assert!(finalize(&mut chain, a, 4).is_empty());
+
+        // Even if the chain's finalized block is reset, it's only ever said once:
+        chain.finalized = Block::zero();
+        assert!(finalize(&mut chain, a, 0).is_empty());
+        assert!(finalize(&mut chain, a, 5).is_empty());
+
+        // A chain that's finalizing blocks by the time we hear about it (for instance
+        // after a restart) didn't just start:
+        let mut chain = Chain::new(genesis_hash, usize::MAX, None, ChainConfig::default());
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        assert!(finalize(&mut chain, a, 1000).is_empty());
+    }
+
+    #[test]
+    fn label_changes_are_debounced() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                label_debounce: LabelDebounce {
+                    margin: 2,
+                    window: Duration::from_secs(60),
+                },
+                ..ChainConfig::default()
+            },
+        );
+        let labelled = |name: &str, label: &str| {
+            let mut details = node(name, "").details().clone();
+            details.chain = label.into();
+            Node::new(details)
+        };
+        let renamed = |result| match result {
+            AddNodeResult::Added { chain_renamed, .. } => chain_renamed,
+            AddNodeResult::Overquota => panic!("Chain not Overquota"),
+        };
+
+        // The first label is shown straight away:
+        assert!(renamed(chain.add_node(labelled("A", "Alpha"))));
+        assert_eq!(chain.label(), "Alpha");
+
+        // Another label isn't shown until it's far enough ahead...
+        assert!(!renamed(chain.add_node(labelled("B", "Beta"))));
+        assert!(!renamed(chain.add_node(labelled("C", "Beta"))));
+        assert!(chain.pending_label.is_none());
+        let d = added_id(chain.add_node(labelled("D", "Beta")));
+        assert_eq!(chain.label(), "Alpha");
+
+        // ...for long enough:
+        let block = Block {
+            hash: BlockHash::from_low_u64_be(1),
+            height: 1,
+        };
+        let mut feed = FeedMessageSerializer::new();
+        chain.update_node(d, Payload::BlockImport(block.into()), &mut feed);
+        assert_eq!(chain.label(), "Alpha");
+        assert!(!chain.take_label_changed());
+
+        chain.pending_label.as_mut().unwrap().1 -= Duration::from_secs(60);
+        chain.update_node(d, Payload::BlockImport(block.into()), &mut feed);
+        assert_eq!(chain.label(), "Beta");
+        assert!(chain.take_label_changed());
+
+        // Falling back within the margin starts the wait over again:
+        let e = added_id(chain.add_node(labelled("E", "Alpha")));
+        for name in ["F", "G", "H"] {
+            chain.add_node(labelled(name, "Alpha"));
+        }
+        assert!(chain.pending_label.is_some());
+        chain.pending_label.as_mut().unwrap().1 -= Duration::from_secs(30);
+        chain.remove_node(e, RemovalReason::Disconnected);
+        assert!(chain.pending_label.is_none());
+        chain.add_node(labelled("I", "Alpha"));
+        chain.pending_label.as_mut().unwrap().1 -= Duration::from_secs(30);
+        chain.update_node(d, Payload::BlockImport(block.into()), &mut feed);
+        assert_eq!(chain.label(), "Beta");
+    }
+
+    #[test]
+    fn expected_block_times_are_configured_or_inferred() {
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain.stats.expected_block_time
+        };
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                expected_block_time: Some(6000),
+                ..ChainConfig::default()
+            },
+        );
+        assert_eq!(regenerate(&mut chain), Some(6000));
+        chain.block_times_histogram.record(12000);
+        assert_eq!(regenerate(&mut chain), Some(6000));
+
+        // Otherwise, it's the average block time once we've seen enough blocks:
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        for _ in 1..MIN_INFERRED_BLOCK_TIMES {
+            chain.block_times_histogram.record(5000);
+        }
+        assert_eq!(regenerate(&mut chain), None);
+        chain.block_times_histogram.record(105000);
+        assert_eq!(regenerate(&mut chain), Some(6000));
+    }
+
+    #[test]
+    fn chain_bandwidth_is_the_total_of_its_nodes() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let report = |chain: &mut Chain, id, upload, download| {
+            let interval = common::node_message::SystemInterval {
+                peers: None,
+                txcount: None,
+                bandwidth_upload: Some(upload),
+                bandwidth_download: Some(download),
+                finalized_height: None,
+                finalized_hash: None,
+                block: None,
+                used_state_cache_size: None,
+                import_queue_depth: None,
+                block_txcount: None,
+                block_imports: None,
+                block_import_failures: None,
+                uptime: None,
+                session: None,
+                disk_used: None,
+                disk_free: None,
+                clock_skew: None,
+            };
+            chain.update_node(
+                id,
+                Payload::SystemInterval(interval),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain.stats.bandwidth
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        // C never reports its bandwidth, and is left out:
+        added_id(chain.add_node(node("C", "network-c")));
+        assert_eq!(regenerate(&mut chain), ChainBandwidth::default());
+
+        report(&mut chain, a, 1000.0, 4000.0);
+        report(&mut chain, b, 500.0, 2000.0);
+        assert_eq!(
+            regenerate(&mut chain),
+            ChainBandwidth {
+                upload: 1500.0,
+                download: 6000.0
+            }
+        );
+
+        chain.remove_node(b, RemovalReason::Disconnected);
+        assert_eq!(
+            regenerate(&mut chain),
+            ChainBandwidth {
+                upload: 1000.0,
+                download: 4000.0
+            }
+        );
+    }
+
+    #[test]
+    fn health_score_combines_weighted_signals() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                health_weights: HealthWeights {
+                    finality_gap: 0,
+                    stale_nodes: 0,
+                    syncing_nodes: 1,
+                    block_time: 0,
+                    validator_shortfall: 1,
+                },
+                expected_validators: Some(2),
+                ..ChainConfig::default()
+            },
+        );
+        let import = |chain: &mut Chain, id, height: u64| {
+            let block = Block {
+                hash: BlockHash::from_low_u64_be(height),
+                height,
+            };
+            chain.update_node(
+                id,
+                Payload::BlockImport(block.into()),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain.stats.health_score
+        };
+
+        // Neither node is a validator, though two are expected:
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        assert_eq!(regenerate(&mut chain), 50);
+
+        // Half of the nodes are syncing too:
+        import(&mut chain, a, 100);
+        import(&mut chain, b, 50);
+        assert_eq!(regenerate(&mut chain), 25);
+
+        import(&mut chain, b, 100);
+        assert_eq!(regenerate(&mut chain), 50);
+    }
+
+    #[test]
+    fn nodes_with_clock_skew_are_counted() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                clock_skew_threshold: 1000,
+                min_location_change: 0,
+                ..ChainConfig::default()
+            },
+        );
+        let report = |chain: &mut Chain, id, clock_skew| {
+            let interval = common::node_message::SystemInterval {
+                peers: None,
+                txcount: None,
+                bandwidth_upload: None,
+                bandwidth_download: None,
+                finalized_height: None,
+                finalized_hash: None,
+                block: None,
+                used_state_cache_size: None,
+                import_queue_depth: None,
+                block_txcount: None,
+                block_imports: None,
+                block_import_failures: None,
+                uptime: None,
+                session: None,
+                disk_used: None,
+                disk_free: None,
+                clock_skew,
+            };
+            chain.update_node(
+                id,
+                Payload::SystemInterval(interval),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain.stats.nodes_with_clock_skew
+        };
+        let counts = |ahead, behind| ClockSkewCounts { ahead, behind };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+        report(&mut chain, a, Some(5000));
+        report(&mut chain, b, Some(-2500));
+        report(&mut chain, c, Some(-400));
+        assert_eq!(regenerate(&mut chain), counts(1, 1));
+
+        // Intervals that don't say when they were sent leave the last estimate be:
+        report(&mut chain, a, None);
+        report(&mut chain, c, Some(-1500));
+        assert_eq!(regenerate(&mut chain), counts(1, 2));
+
+        report(&mut chain, a, Some(200));
+        chain.remove_node(b, RemovalReason::Disconnected);
+        assert_eq!(regenerate(&mut chain), counts(0, 1));
+    }
+
+    #[test]
+    fn full_nodes_and_light_clients_are_counted() {
+        let role_node = |name: &str, role| {
+            let mut details = node(name, name).details().clone();
+            details.role = role;
+            Node::new(details)
+        };
+        let add_nodes = |chain: &mut Chain| {
+            chain.add_node(role_node("A", Some(NodeRole::Full)));
+            chain.add_node(role_node("B", Some(NodeRole::Light)));
+            chain.add_node(role_node("C", Some(NodeRole::Light)));
+            chain.add_node(role_node("D", None));
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+        };
+
+        // By default, nodes that don't say are counted as full nodes:
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        add_nodes(&mut chain);
+        let counts = |full, light, unknown| NodeRoleCounts {
+            full,
+            light,
+            unknown,
+        };
+        assert_eq!(chain.stats.node_roles, counts(2, 2, 0));
+        assert_eq!(chain.stats.full_to_light_ratio, Some(1.0));
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                unreported_node_role: UnreportedNodeRole(None),
+                ..ChainConfig::default()
+            },
+        );
+        add_nodes(&mut chain);
+        assert_eq!(chain.stats.node_roles, counts(1, 2, 1));
+        assert_eq!(chain.stats.full_to_light_ratio, Some(0.5));
+    }
+
+    #[test]
+    fn disk_usage_is_aggregated() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                low_disk_threshold: 100,
+                ..ChainConfig::default()
+            },
+        );
+        let report = |chain: &mut Chain, id, disk_used, disk_free| {
+            let interval = common::node_message::SystemInterval {
+                peers: None,
+                txcount: None,
+                bandwidth_upload: None,
+                bandwidth_download: None,
+                finalized_height: None,
+                finalized_hash: None,
+                block: None,
+                used_state_cache_size: None,
+                import_queue_depth: None,
+                block_txcount: None,
+                block_imports: None,
+                block_import_failures: None,
+                uptime: None,
+                session: None,
+                disk_used,
+                disk_free,
+                clock_skew: None,
+            };
+            chain.update_node(
+                id,
+                Payload::SystemInterval(interval),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            (chain.stats.median_disk_used, chain.stats.nodes_low_on_disk)
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+        assert_eq!(regenerate(&mut chain), (None, 0));
+
+        report(&mut chain, a, Some(1000), Some(50));
+        report(&mut chain, b, Some(3000), Some(500));
+        // Nodes that don't report their disk usage are left out:
+        report(&mut chain, c, None, None);
+        assert_eq!(regenerate(&mut chain), (Some(2000), 1));
+
+        // Intervals without disk details leave the last ones be:
+        report(&mut chain, a, None, None);
+        report(&mut chain, b, Some(5000), Some(20));
+        assert_eq!(regenerate(&mut chain), (Some(3000), 2));
+    }
+
+    #[test]
+    fn nodes_lagging_finality_are_counted() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                finality_lag_threshold: 10,
+                ..ChainConfig::default()
+            },
+        );
+        let finalize = |chain: &mut Chain, id, height: u64| {
+            // Nodes can't finalize past their best block:
+            let best = Block {
+                hash: BlockHash::from_low_u64_be(height),
+                height,
+            };
+            chain.update_node(
+                id,
+                Payload::BlockImport(best.into()),
+                &mut FeedMessageSerializer::new(),
+            );
+            let payload = Payload::NotifyFinalized(common::node_message::Finalized {
+                hash: BlockHash::from_low_u64_be(height),
+                height: height.to_string().into(),
+            });
+            chain.update_node(id, payload, &mut FeedMessageSerializer::new());
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain.stats.nodes_lagging_finality
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+        finalize(&mut chain, a, 100);
+        finalize(&mut chain, b, 90);
+        // C hasn't reported a finalized block, so isn't counted yet:
+        assert_eq!(regenerate(&mut chain), 0);
+
+        finalize(&mut chain, c, 50);
+        assert_eq!(regenerate(&mut chain), 1);
+        finalize(&mut chain, a, 101);
+        assert_eq!(regenerate(&mut chain), 2);
+        finalize(&mut chain, b, 101);
+        finalize(&mut chain, c, 101);
+        assert_eq!(regenerate(&mut chain), 0);
+    }
+
+    #[test]
+    fn finalization_times_are_averaged() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let id = added_id(chain.add_node(node("A", "network-a")));
+        let finalize = |chain: &mut Chain, height: u64| {
+            // Nodes can't finalize past their best block:
+            let best = Block {
+                hash: BlockHash::from_low_u64_be(height),
+                height,
+            };
+            chain.update_node(
+                id,
+                Payload::BlockImport(best.into()),
+                &mut FeedMessageSerializer::new(),
+            );
+            let payload = Payload::NotifyFinalized(common::node_message::Finalized {
+                hash: BlockHash::from_low_u64_be(height),
+                height: height.to_string().into(),
+            });
+            chain.update_node(id, payload, &mut FeedMessageSerializer::new());
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            chain.stats.average_finalization_time
+        };
+
+        // Nothing to go on until the finalized block has advanced twice:
+        finalize(&mut chain, 1);
+        assert_eq!(regenerate(&mut chain), None);
+
+        // Pretend that the first finalized block arrived 6 seconds ago:
+        chain.finalized_timestamp = chain.finalized_timestamp.map(|t| t - 6000);
+        finalize(&mut chain, 2);
+        let average = regenerate(&mut chain).unwrap();
+        assert!((6000..7000).contains(&average));
+
+        // Reporting the same finalized block again doesn't count:
+        finalize(&mut chain, 2);
+        assert_eq!(chain.finalization_times.samples().len(), 1);
+    }
+
+    #[test]
+    fn transactions_per_second_are_estimated() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let report = |chain: &mut Chain, id, block_txcount| {
+            let interval = common::node_message::SystemInterval {
+                peers: None,
+                txcount: None,
+                bandwidth_upload: None,
+                bandwidth_download: None,
+                finalized_height: None,
+                finalized_hash: None,
+                block: None,
+                used_state_cache_size: None,
+                import_queue_depth: None,
+                block_txcount,
+                block_imports: None,
+                block_import_failures: None,
+                uptime: None,
+                session: None,
+                disk_used: None,
+                disk_free: None,
+                clock_skew: None,
+            };
+            chain.update_node(
+                id,
+                Payload::SystemInterval(interval),
+                &mut FeedMessageSerializer::new(),
+            );
+        };
+        let regenerate = |chain: &mut Chain| {
+            chain.stats_last_regenerated -= STATS_UPDATE_INTERVAL;
+            chain.regenerate_stats_if_necessary(&mut FeedMessageSerializer::new());
+            (
+                chain.stats.average_block_txcount,
+                chain.stats.transactions_per_second,
+            )
+        };
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        let b = added_id(chain.add_node(node("B", "network-b")));
+        let c = added_id(chain.add_node(node("C", "network-c")));
+
+        // Nothing to go on until a node reports how many transactions are in its blocks:
+        report(&mut chain, a, None);
+        assert_eq!(regenerate(&mut chain), (None, None));
+
+        // Nodes that don't report it are left out of the average:
+        report(&mut chain, a, Some(10));
+        report(&mut chain, b, Some(20));
+        report(&mut chain, c, None);
+        assert_eq!(regenerate(&mut chain), (Some(15), None));
+
+        chain.average_block_time = Some(6000);
+        assert_eq!(regenerate(&mut chain), (Some(15), Some(2.5)));
+    }
+
+    #[test]
+    fn nodes_can_be_found_by_network_id() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+
+        let a = added_id(chain.add_node(node("A", "network-a")));
+        added_id(chain.add_node(node("B", "network-b")));
+
+        assert_eq!(
+            &*chain
+                .get_node_by_network_id("network-a")
+                .unwrap()
+                .details()
+                .name,
+            "A"
+        );
+        assert_eq!(
+            &*chain
+                .get_node_by_network_id("network-b")
+                .unwrap()
+                .details()
+                .name,
+            "B"
+        );
+        assert!(chain.get_node_by_network_id("network-c").is_none());
+
+        chain.remove_node(a, RemovalReason::Disconnected);
+        assert!(chain.get_node_by_network_id("network-a").is_none());
+        assert!(chain.get_node_by_network_id("network-b").is_some());
+    }
+
+    #[test]
+    fn removing_an_older_duplicate_keeps_the_newer_node_indexed() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+
+        let old = added_id(chain.add_node(node("Old", "network-a")));
+        added_id(chain.add_node(node("New", "network-a")));
+
+        chain.remove_node(old, RemovalReason::Disconnected);
+        assert_eq!(
+            &*chain
+                .get_node_by_network_id("network-a")
+                .unwrap()
+                .details()
+                .name,
+            "New"
+        );
+    }
+
+    #[test]
+    fn nodes_reporting_the_same_network_id_are_noticed() {
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        let duplicate_of = |result| match result {
+            AddNodeResult::Added { duplicate_of, .. } => duplicate_of,
+            AddNodeResult::Overquota => panic!("Chain not Overquota"),
+        };
+
+        assert_eq!(duplicate_of(chain.add_node(node("A", "network-a"))), None);
+        assert_eq!(duplicate_of(chain.add_node(node("B", ""))), None);
+        assert_eq!(duplicate_of(chain.add_node(node("C", ""))), None);
+
+        let a = chain.get_node_id_by_network_id("network-a").unwrap();
+        let new = chain.add_node(node("A again", "network-a"));
+        assert!(matches!(
+            new,
+            AddNodeResult::Added {
+                is_reconnect: false,
+                ..
+            }
+        ));
+        assert_eq!(duplicate_of(new), Some(a));
+
+        // Removing the newer duplicate leaves the older one indexed:
+        let new = chain.get_node_id_by_network_id("network-a").unwrap();
+        assert_ne!(new, a);
+        chain.remove_node(new, RemovalReason::Disconnected);
+        assert_eq!(chain.get_node_id_by_network_id("network-a"), Some(a));
+    }
+
+    #[test]
+    fn nodes_with_the_same_name_are_noticed_if_asked() {
+        let name_collisions = |result| match result {
+            AddNodeResult::Added {
+                name_collisions, ..
+            } => name_collisions,
+            AddNodeResult::Overquota => panic!("Chain not Overquota"),
+        };
+
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig {
+                flag_name_collisions: true,
+                ..ChainConfig::default()
+            },
+        );
+        let a = chain.add_node(node("validator-1", "network-a"));
+        let a = added_id(a);
+        assert_eq!(
+            name_collisions(chain.add_node(node("validator-2", "network-b"))),
+            0
+        );
+        assert_eq!(
+            name_collisions(chain.add_node(node("validator-1", "network-c"))),
+            1
+        );
+        assert_eq!(
+            name_collisions(chain.add_node(node("validator-1", "network-d"))),
+            2
+        );
+
+        // Removed nodes no longer count:
+        chain.remove_node(a, RemovalReason::Disconnected);
+        assert_eq!(
+            name_collisions(chain.add_node(node("validator-1", "network-e"))),
+            2
+        );
+
+        // Nodes can share names if we're not asked to notice:
+        let mut chain = Chain::new(
+            BlockHash::from_low_u64_be(1),
+            usize::MAX,
+            None,
+            ChainConfig::default(),
+        );
+        chain.add_node(node("validator-1", "network-a"));
+        assert_eq!(
+            name_collisions(chain.add_node(node("validator-1", "network-b"))),
+            0
+        );
+    }
 }
@@ -16,6 +16,12 @@
 
 use super::counter::{Counter, CounterValue};
 use crate::feed_message::ChainStats;
+use common::node_types::{NodeHwBench, NodeLocation, NodeRole};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
 
 // These are the benchmark scores generated on our reference hardware.
 const REFERENCE_CPU_SCORE: u64 = 1028;
@@ -23,6 +29,47 @@ const REFERENCE_MEMORY_SCORE: u64 = 14899;
 const REFERENCE_DISK_SEQUENTIAL_WRITE_SCORE: u64 = 485;
 const REFERENCE_DISK_RANDOM_WRITE_SCORE: u64 = 222;
 
+// How many distinct major.minor client versions we list; the rest are counted as "other".
+const MAX_MAJOR_MINOR_VERSIONS: usize = 20;
+
+/// Calls the given macro with the name of every counter in [`ChainStatsCollator`].
+macro_rules! with_counters {
+    ($mac:ident) => {
+        $mac!(
+            version,
+            major_minor_version,
+            target_os,
+            target_arch,
+            platform,
+            protocol_version,
+            cpu,
+            memory,
+            core_count,
+            linux_kernel,
+            linux_distro,
+            is_virtual_machine,
+            cpu_hashrate_score,
+            memory_memcpy_score,
+            disk_sequential_write_score,
+            disk_random_write_score,
+            cpu_vendor,
+            country,
+            provider,
+            hardware_tier,
+            operator
+        )
+    };
+}
+
+macro_rules! names {
+    ($($name:ident),*) => {
+        &[$(stringify!($name)),*]
+    };
+}
+
+/// The names of the stats that [`CardinalityLimits`] can be given for.
+pub const CARDINALITY_DIMENSIONS: &[&str] = with_counters!(names);
+
 macro_rules! buckets {
     (@try $value:expr, $bucket_min:expr, $bucket_max:expr,) => {
         if $value < $bucket_max {
@@ -100,7 +147,7 @@ fn bucket_memory(memory: u64) -> (u32, Option<u32>) {
     }
 }
 
-fn kernel_version_number(version: &Box<str>) -> &str {
+fn kernel_version_number(version: &str) -> &str {
     let index = version
         .find("-")
         .or_else(|| version.find("+"))
@@ -111,14 +158,396 @@ fn kernel_version_number(version: &Box<str>) -> &str {
 
 #[test]
 fn test_kernel_version_number() {
-    assert_eq!(kernel_version_number(&"5.10.0-8-amd64".into()), "5.10.0");
+    assert_eq!(kernel_version_number("5.10.0-8-amd64"), "5.10.0");
     // Plus sign indicates that the kernel was built from modified sources.
     // This should only appear at the end of the version string.
-    assert_eq!(kernel_version_number(&"5.10.0+82453".into()), "5.10.0");
-    assert_eq!(kernel_version_number(&"5.10.0".into()), "5.10.0");
+    assert_eq!(kernel_version_number("5.10.0+82453"), "5.10.0");
+    assert_eq!(kernel_version_number("5.10.0"), "5.10.0");
+}
+
+/// The OS and architecture that a node was built for, like "linux/x86_64", or `None`
+/// if it didn't tell us both.
+fn platform(details: &common::node_types::NodeDetails) -> Option<String> {
+    let os = details.target_os.as_deref()?;
+    let arch = details.target_arch.as_deref()?;
+    Some(format!("{os}/{arch}"))
+}
+
+/// Parse the major and minor parts out of a node version like "0.9.17-75dd6c7d0",
+/// returning `None` if it doesn't look like a semver version.
+pub fn major_minor_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.trim().trim_start_matches('v').split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?;
+    let minor_len = minor
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(minor.len());
+    let minor = minor[..minor_len].parse().ok()?;
+    Some((major, minor))
+}
+
+#[test]
+fn test_major_minor_version() {
+    assert_eq!(major_minor_version("0.9.17-75dd6c7d0"), Some((0, 9)));
+    assert_eq!(major_minor_version("v1.10.0"), Some((1, 10)));
+    assert_eq!(major_minor_version("2.0-rc1"), Some((2, 0)));
+    assert_eq!(major_minor_version("1"), None);
+    assert_eq!(major_minor_version("unknown"), None);
+    assert_eq!(major_minor_version("1.x"), None);
+}
+
+/// A rough grading of how good a node's hardware is, going by its benchmark scores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HardwareTier {
+    Low,
+    Mid,
+    High,
+}
+
+/// The scores at which nodes move up a [`HardwareTier`]. Scores are the average of a node's
+/// CPU, memory and disk benchmark scores, each as a percentage of our reference hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardwareTiers {
+    /// Nodes scoring at least this are in the mid tier.
+    pub mid: u32,
+    /// Nodes scoring at least this are in the high tier.
+    pub high: u32,
+}
+
+impl Default for HardwareTiers {
+    fn default() -> Self {
+        HardwareTiers { mid: 50, high: 100 }
+    }
+}
+
+impl HardwareTiers {
+    fn classify(&self, hwbench: &NodeHwBench) -> HardwareTier {
+        let mut scores = vec![
+            (hwbench.cpu_hashrate_score, REFERENCE_CPU_SCORE),
+            (hwbench.memory_memcpy_score, REFERENCE_MEMORY_SCORE),
+        ];
+        // Nodes can be asked not to benchmark their disks:
+        if let Some(score) = hwbench.disk_sequential_write_score {
+            scores.push((score, REFERENCE_DISK_SEQUENTIAL_WRITE_SCORE));
+        }
+        if let Some(score) = hwbench.disk_random_write_score {
+            scores.push((score, REFERENCE_DISK_RANDOM_WRITE_SCORE));
+        }
+
+        let total: f64 = scores
+            .iter()
+            .map(|&(score, reference_score)| score as f64 / reference_score as f64 * 100.0)
+            .sum();
+        let score = (total / scores.len() as f64) as u32;
+
+        if score >= self.high {
+            HardwareTier::High
+        } else if score >= self.mid {
+            HardwareTier::Mid
+        } else {
+            HardwareTier::Low
+        }
+    }
+}
+
+/// How many distinct values of each stat we count separately. Nodes with any other values
+/// are counted together as "other", so that lots of different values (by accident or
+/// otherwise) can't use up lots of memory. The most common values are always kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CardinalityLimits {
+    /// The limit for any stat not given one of its own.
+    pub default: usize,
+    /// Limits for particular stats, by name (see [`CARDINALITY_DIMENSIONS`]).
+    pub dimensions: BTreeMap<String, usize>,
+}
+
+impl Default for CardinalityLimits {
+    fn default() -> Self {
+        CardinalityLimits {
+            default: 1000,
+            // Nodes of any more operators than this are each counted as having an
+            // operator of their own. Odd builds shouldn't crowd out the platforms we care about,
+            // so there are only ever a few of those worth listing:
+            dimensions: [("operator".to_owned(), 10_000), ("platform".to_owned(), 50)]
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl CardinalityLimits {
+    /// No limits, for counting everything before deciding what to keep.
+    fn unlimited() -> Self {
+        CardinalityLimits {
+            default: usize::MAX,
+            dimensions: BTreeMap::new(),
+        }
+    }
+
+    /// The limit for the stat with the given name.
+    pub fn limit(&self, dimension: &str) -> usize {
+        self.dimensions
+            .get(dimension)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// How many nodes have been connected for how long, split up by [`ConnectionTiers`]. Lots
+/// of freshly connected nodes suggests that nodes on the chain keep dropping out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ConnectionDurations {
+    pub fresh: u64,
+    pub medium: u64,
+    pub long: u64,
+}
+
+/// The total bandwidth (in bytes per second) that the nodes on a chain are using,
+/// going by the latest average rates that they've reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ChainBandwidth {
+    pub upload: f64,
+    pub download: f64,
+}
+
+/// How many nodes have a clock that's well ahead of, or well behind, ours.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ClockSkewCounts {
+    pub ahead: u64,
+    pub behind: u64,
+}
+
+/// How many nodes are full nodes, and how many are light clients.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct NodeRoleCounts {
+    pub full: u64,
+    pub light: u64,
+    /// Nodes that haven't said what they are, unless they're counted as a role.
+    pub unknown: u64,
+}
+
+impl NodeRoleCounts {
+    fn modify(&mut self, role: Option<NodeRole>, op: CounterValue) {
+        let count = match role {
+            Some(NodeRole::Full) => &mut self.full,
+            Some(NodeRole::Light) => &mut self.light,
+            None => &mut self.unknown,
+        };
+        match op {
+            CounterValue::Increment => *count += 1,
+            CounterValue::Decrement => *count -= 1,
+        }
+    }
+
+    /// Count the nodes that haven't said what they are as the role given, if any.
+    pub fn with_unknown_as(mut self, role: UnreportedNodeRole) -> NodeRoleCounts {
+        let unknown = std::mem::take(&mut self.unknown);
+        match role.0 {
+            Some(NodeRole::Full) => self.full += unknown,
+            Some(NodeRole::Light) => self.light += unknown,
+            None => self.unknown = unknown,
+        }
+        self
+    }
+
+    /// How many full nodes there are for each light client, if there are any light clients.
+    pub fn full_to_light_ratio(&self) -> Option<f64> {
+        (self.light > 0).then(|| self.full as f64 / self.light as f64)
+    }
+}
+
+/// What nodes that don't say whether they're full nodes or light clients are counted as.
+/// Light clients rarely send telemetry, so by default they're counted as full nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnreportedNodeRole(pub Option<NodeRole>);
+
+impl Default for UnreportedNodeRole {
+    fn default() -> Self {
+        UnreportedNodeRole(Some(NodeRole::Full))
+    }
+}
+
+impl FromStr for UnreportedNodeRole {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(UnreportedNodeRole(Some(NodeRole::Full))),
+            "light" => Ok(UnreportedNodeRole(Some(NodeRole::Light))),
+            "unknown" => Ok(UnreportedNodeRole(None)),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'full', 'light' or 'unknown', but got '{s}'"
+            )),
+        }
+    }
+}
+
+/// How long (in ms) nodes need to have been connected for to count as having been
+/// connected for a medium or long time, rather than being freshly connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionTiers {
+    /// Nodes connected for at least this long are counted as medium.
+    pub medium: u64,
+    /// Nodes connected for at least this long are counted as long.
+    pub long: u64,
+}
+
+impl Default for ConnectionTiers {
+    fn default() -> Self {
+        ConnectionTiers {
+            medium: 60 * 60 * 1000,
+            long: 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+impl ConnectionTiers {
+    /// Count a node that's been connected for the given time (in ms) in its tier.
+    pub fn count(&self, durations: &mut ConnectionDurations, connected_for: u64) {
+        if connected_for >= self.long {
+            durations.long += 1;
+        } else if connected_for >= self.medium {
+            durations.medium += 1;
+        } else {
+            durations.fresh += 1;
+        }
+    }
+}
+
+/// Finds the operator of a node in its name, eg "Operator" in "Operator/Node-1". The
+/// operator is the first capture group of the pattern if it has one, or else the whole
+/// match. Names that don't match are their own operator.
+#[derive(Debug, Clone)]
+pub struct NodeOperatorPattern(Arc<Regex>);
+
+impl NodeOperatorPattern {
+    pub fn new(pattern: &str) -> anyhow::Result<Self> {
+        let regex = Regex::new(pattern)
+            .map_err(|e| anyhow::anyhow!("Invalid node operator pattern '{pattern}': {e}"))?;
+        Ok(NodeOperatorPattern(Arc::new(regex)))
+    }
+
+    pub fn operator<'a>(&self, name: &'a str) -> &'a str {
+        match self.0.captures(name) {
+            Some(captures) => captures
+                .get(1)
+                .or_else(|| captures.get(0))
+                .map_or(name, |m| m.as_str()),
+            None => name,
+        }
+    }
+}
+
+impl Default for NodeOperatorPattern {
+    fn default() -> Self {
+        NodeOperatorPattern::new("^([^/]+)/").expect("default pattern is valid")
+    }
+}
+
+impl PartialEq for NodeOperatorPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for NodeOperatorPattern {}
+
+impl std::str::FromStr for NodeOperatorPattern {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NodeOperatorPattern::new(s)
+    }
 }
 
-fn cpu_vendor(cpu: &Box<str>) -> &str {
+#[test]
+fn test_node_operator_pattern() {
+    let pattern = NodeOperatorPattern::default();
+    assert_eq!(pattern.operator("Operator/Node-1"), "Operator");
+    assert_eq!(pattern.operator("Operator/eu/Node-2"), "Operator");
+    assert_eq!(pattern.operator("lonely-node"), "lonely-node");
+
+    // Without a capture group, the whole match is used:
+    let pattern = NodeOperatorPattern::new(r"^\w+").unwrap();
+    assert_eq!(pattern.operator("acme-1"), "acme");
+}
+
+#[test]
+fn test_hardware_tiers() {
+    let tiers = HardwareTiers::default();
+    let hwbench = |cpu, memory, disk| NodeHwBench {
+        cpu_hashrate_score: cpu,
+        memory_memcpy_score: memory,
+        disk_sequential_write_score: disk,
+        disk_random_write_score: None,
+    };
+
+    let reference = hwbench(
+        REFERENCE_CPU_SCORE,
+        REFERENCE_MEMORY_SCORE,
+        Some(REFERENCE_DISK_SEQUENTIAL_WRITE_SCORE),
+    );
+    assert_eq!(tiers.classify(&reference), HardwareTier::High);
+    // A slow disk drags the average down:
+    let slow_disk = hwbench(REFERENCE_CPU_SCORE, REFERENCE_MEMORY_SCORE, Some(0));
+    assert_eq!(tiers.classify(&slow_disk), HardwareTier::Mid);
+    // Disk scores are left out of the average if they're missing:
+    let no_disk = hwbench(REFERENCE_CPU_SCORE, REFERENCE_MEMORY_SCORE, None);
+    assert_eq!(tiers.classify(&no_disk), HardwareTier::High);
+    assert_eq!(tiers.classify(&hwbench(0, 0, None)), HardwareTier::Low);
+
+    let tiers = HardwareTiers { mid: 10, high: 20 };
+    assert_eq!(tiers.classify(&slow_disk), HardwareTier::High);
+}
+
+#[test]
+fn test_connection_tiers() {
+    let tiers = ConnectionTiers {
+        medium: 100,
+        long: 1000,
+    };
+    let mut durations = ConnectionDurations::default();
+    for connected_for in [0, 99, 100, 999, 1000, 5000] {
+        tiers.count(&mut durations, connected_for);
+    }
+    assert_eq!(
+        durations,
+        ConnectionDurations {
+            fresh: 2,
+            medium: 2,
+            long: 2,
+        }
+    );
+}
+
+/// The smallest number of groups that between them have more than half of the total,
+/// given the number in each group, or `None` if there's nothing in any group.
+fn nakamoto_coefficient(counts: impl Iterator<Item = u64>) -> Option<u32> {
+    let mut counts: Vec<u64> = counts.collect();
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+    let total: u64 = counts.iter().sum();
+
+    let mut sum = 0;
+    for (idx, count) in counts.into_iter().enumerate() {
+        sum += count;
+        if sum * 2 > total {
+            return Some(idx as u32 + 1);
+        }
+    }
+    None
+}
+
+#[test]
+fn test_nakamoto_coefficient() {
+    assert_eq!(nakamoto_coefficient([].into_iter()), None);
+    assert_eq!(nakamoto_coefficient([5].into_iter()), Some(1));
+    // Exactly half isn't enough:
+    assert_eq!(nakamoto_coefficient([5, 5].into_iter()), Some(2));
+    assert_eq!(nakamoto_coefficient([1, 6, 2, 2].into_iter()), Some(1));
+    assert_eq!(nakamoto_coefficient([3, 1, 3, 3].into_iter()), Some(2));
+    assert_eq!(nakamoto_coefficient([1, 1, 1, 1, 1].into_iter()), Some(3));
+}
+
+fn cpu_vendor(cpu: &str) -> &str {
     let lowercase_cpu = cpu.to_ascii_lowercase();
 
     if lowercase_cpu.contains("intel") {
@@ -137,8 +566,13 @@ fn cpu_vendor(cpu: &Box<str>) -> &str {
 #[derive(Default)]
 pub struct ChainStatsCollator {
     version: Counter<String>,
+    major_minor_version: Counter<(u32, u32)>,
     target_os: Counter<String>,
     target_arch: Counter<String>,
+    /// Nodes by OS and architecture together, like "linux/aarch64".
+    platform: Counter<String>,
+    /// Nodes by the version of the telemetry protocol they send messages in.
+    protocol_version: Counter<String>,
     cpu: Counter<String>,
     memory: Counter<(u32, Option<u32>)>,
     core_count: Counter<u32>,
@@ -150,22 +584,115 @@ pub struct ChainStatsCollator {
     disk_sequential_write_score: Counter<(u32, Option<u32>)>,
     disk_random_write_score: Counter<(u32, Option<u32>)>,
     cpu_vendor: Counter<String>,
+    country: Counter<String>,
+    provider: Counter<String>,
+    hardware_tier: Counter<HardwareTier>,
+    hardware_tiers: HardwareTiers,
+    /// Nodes of operators beyond the limit are folded, and each count as a distinct operator.
+    operator: Counter<String>,
+    operator_pattern: NodeOperatorPattern,
+    cardinality_limits: CardinalityLimits,
+    /// How many nodes have reported a validator address.
+    validators: u64,
+    /// How many nodes are full nodes or light clients.
+    node_roles: NodeRoleCounts,
+    /// How many nodes have had a deep import queue for a while.
+    backed_up_import_queues: u64,
+    /// How many nodes have failed to import too many of the blocks they tried to.
+    unhealthy_imports: u64,
 }
 
 impl ChainStatsCollator {
+    pub fn new(
+        hardware_tiers: HardwareTiers,
+        operator_pattern: NodeOperatorPattern,
+        cardinality_limits: CardinalityLimits,
+    ) -> Self {
+        let mut collator = ChainStatsCollator {
+            hardware_tiers,
+            operator_pattern,
+            ..Default::default()
+        };
+        collator.set_cardinality_limits(cardinality_limits);
+        collator
+    }
+
+    /// Like [`ChainStatsCollator::new`], but counting every value separately until
+    /// [`ChainStatsCollator::set_cardinality_limits`] is called. Counting everything
+    /// first means that the most common values are the ones that are kept.
+    pub fn unlimited(hardware_tiers: HardwareTiers, operator_pattern: NodeOperatorPattern) -> Self {
+        Self::new(
+            hardware_tiers,
+            operator_pattern,
+            CardinalityLimits::unlimited(),
+        )
+    }
+
+    /// The scores that nodes are split into hardware tiers by.
+    pub fn hardware_tiers(&self) -> HardwareTiers {
+        self.hardware_tiers
+    }
+
+    /// The pattern that node operators are found in node names with.
+    pub fn operator_pattern(&self) -> &NodeOperatorPattern {
+        &self.operator_pattern
+    }
+
+    /// How many distinct values of each stat are counted separately.
+    pub fn cardinality_limits(&self) -> &CardinalityLimits {
+        &self.cardinality_limits
+    }
+
+    /// Limit how many distinct values of each stat are counted separately, folding the
+    /// least common values of any that are already over their limit.
+    pub fn set_cardinality_limits(&mut self, cardinality_limits: CardinalityLimits) {
+        macro_rules! set_limits {
+            ($($name:ident),*) => {
+                $(self.$name.set_limit(cardinality_limits.limit(stringify!($name)));)*
+            };
+        }
+        with_counters!(set_limits);
+        self.cardinality_limits = cardinality_limits;
+    }
+
+    /// How many nodes have values of each stat that are folded into "other" because of
+    /// the limits, leaving out stats that don't have any.
+    fn folded_values(&self) -> BTreeMap<&'static str, u64> {
+        let mut folded_values = BTreeMap::new();
+        macro_rules! count_folded {
+            ($($name:ident),*) => {
+                $(if self.$name.folded() > 0 {
+                    folded_values.insert(stringify!($name), self.$name.folded());
+                })*
+            };
+        }
+        with_counters!(count_folded);
+        folded_values
+    }
+
     pub fn add_or_remove_node(
         &mut self,
         details: &common::node_types::NodeDetails,
-        hwbench: Option<&common::node_types::NodeHwBench>,
+        hwbench: Option<&NodeHwBench>,
+        location: Option<&NodeLocation>,
         op: CounterValue,
     ) {
         self.version.modify(Some(&*details.version), op);
 
-        self.target_os
-            .modify(details.target_os.as_ref().map(|value| &**value), op);
+        let operator = self.operator_pattern.operator(&details.name);
+        self.operator.modify(Some(operator), op);
+
+        self.major_minor_version
+            .modify(major_minor_version(&details.version).as_ref(), op);
+
+        self.target_os.modify(details.target_os.as_deref(), op);
 
-        self.target_arch
-            .modify(details.target_arch.as_ref().map(|value| &**value), op);
+        self.target_arch.modify(details.target_arch.as_deref(), op);
+
+        self.platform.modify(platform(details).as_ref(), op);
+
+        self.protocol_version
+            .modify(details.protocol_version.as_deref(), op);
 
         let sysinfo = details.sysinfo.as_ref();
         self.cpu.modify(
@@ -183,7 +710,7 @@ impl ChainStatsCollator {
 
         self.linux_kernel.modify(
             sysinfo
-                .and_then(|sysinfo| sysinfo.linux_kernel.as_ref())
+                .and_then(|sysinfo| sysinfo.linux_kernel.as_deref())
                 .map(kernel_version_number),
             op,
         );
@@ -201,18 +728,55 @@ impl ChainStatsCollator {
         );
 
         self.cpu_vendor.modify(
-            sysinfo.and_then(|sysinfo| sysinfo.cpu.as_ref().map(cpu_vendor)),
+            sysinfo.and_then(|sysinfo| sysinfo.cpu.as_deref().map(cpu_vendor)),
             op,
         );
 
+        if details.validator.is_some() {
+            self.update_validators(op);
+        }
+        self.node_roles.modify(details.role, op);
+
         self.update_hwbench(hwbench, op);
+        self.update_location(location, op);
     }
 
-    pub fn update_hwbench(
-        &mut self,
-        hwbench: Option<&common::node_types::NodeHwBench>,
-        op: CounterValue,
-    ) {
+    /// Count one more or one fewer node as a validator.
+    pub fn update_validators(&mut self, op: CounterValue) {
+        match op {
+            CounterValue::Increment => self.validators += 1,
+            CounterValue::Decrement => self.validators -= 1,
+        }
+    }
+
+    /// Count one more or one fewer node as having a backed up import queue.
+    pub fn update_backed_up_import_queues(&mut self, op: CounterValue) {
+        match op {
+            CounterValue::Increment => self.backed_up_import_queues += 1,
+            CounterValue::Decrement => self.backed_up_import_queues -= 1,
+        }
+    }
+
+    /// Count one more or one fewer node as failing to import too many blocks.
+    pub fn update_unhealthy_imports(&mut self, op: CounterValue) {
+        match op {
+            CounterValue::Increment => self.unhealthy_imports += 1,
+            CounterValue::Decrement => self.unhealthy_imports -= 1,
+        }
+    }
+
+    pub fn update_location(&mut self, location: Option<&NodeLocation>, op: CounterValue) {
+        self.country.modify(
+            location.and_then(|location| location.country.as_deref()),
+            op,
+        );
+        self.provider.modify(
+            location.and_then(|location| location.provider.as_deref()),
+            op,
+        );
+    }
+
+    pub fn update_hwbench(&mut self, hwbench: Option<&NodeHwBench>, op: CounterValue) {
         self.cpu_hashrate_score.modify(
             hwbench
                 .map(|hwbench| bucket_score(hwbench.cpu_hashrate_score, REFERENCE_CPU_SCORE))
@@ -242,13 +806,26 @@ impl ChainStatsCollator {
                 .as_ref(),
             op,
         );
+
+        // Nodes that haven't sent any benchmarks are counted as unknown:
+        self.hardware_tier.modify(
+            hwbench
+                .map(|hwbench| self.hardware_tiers.classify(hwbench))
+                .as_ref(),
+            op,
+        );
     }
 
     pub fn generate(&self) -> ChainStats {
         ChainStats {
             version: self.version.generate_ranking_top(10),
+            major_minor_version: self
+                .major_minor_version
+                .generate_ranking_top_ordered(MAX_MAJOR_MINOR_VERSIONS),
             target_os: self.target_os.generate_ranking_top(10),
             target_arch: self.target_arch.generate_ranking_top(10),
+            platform: self.platform.generate_ranking_top(10),
+            protocol_version: self.protocol_version.generate_ranking_top(10),
             cpu: self.cpu.generate_ranking_top(10),
             memory: self.memory.generate_ranking_ordered(),
             core_count: self.core_count.generate_ranking_top(10),
@@ -262,6 +839,225 @@ impl ChainStatsCollator {
                 .generate_ranking_ordered(),
             disk_random_write_score: self.disk_random_write_score.generate_ranking_ordered(),
             cpu_vendor: self.cpu_vendor.generate_ranking_top(10),
+            country: self.country.generate_ranking_top(10),
+            provider: self.provider.generate_ranking_top(10),
+            // Nodes in unknown countries are left out, rather than lumped together as one:
+            geo_nakamoto_coefficient: nakamoto_coefficient(self.country.counts()),
+            hardware_tier: self.hardware_tier.generate_ranking_ordered(),
+            connection_durations: ConnectionDurations::default(),
+            bandwidth: ChainBandwidth::default(),
+            average_finalized_propagation_time: None,
+            average_finalization_time: None,
+            block_time_window: 0,
+            block_time_variation: None,
+            expected_block_time: None,
+            session: None,
+            average_block_txcount: None,
+            transactions_per_second: None,
+            average_peers: None,
+            messages_per_second: 0.0,
+            frozen: false,
+            last_reconciled: 0,
+            first_seen: 0,
+            chain_age: 0,
+            best_finalized_gap: 0,
+            median_time_to_first_block: None,
+            pending_first_block: 0,
+            oldest_last_seen: None,
+            median_best_block: None,
+            // Nodes of operators that have been folded still count towards this:
+            distinct_operators: self.operator.len() as u64 + self.operator.folded(),
+            syncing_nodes: 0,
+            mostly_syncing: false,
+            health_score: 100,
+            nodes_lagging_finality: 0,
+            nodes_with_clock_skew: ClockSkewCounts::default(),
+            median_disk_used: None,
+            nodes_low_on_disk: 0,
+            min_finalized_age: None,
+            max_finalized_age: None,
+            top_block_authors: Vec::new(),
+            inactive_block_authors: 0,
+            implausible_block_nodes: 0,
+            recently_restarted: 0,
+            validators: self.validators,
+            node_roles: self.node_roles,
+            full_to_light_ratio: None,
+            backed_up_import_queues: self.backed_up_import_queues,
+            unhealthy_imports: self.unhealthy_imports,
+            label_confidence: 0.0,
+            folded_values: self.folded_values(),
         }
     }
 }
+
+#[test]
+fn test_cardinality_limits() {
+    let details = |version: &str| common::node_types::NodeDetails {
+        chain: "".into(),
+        name: "".into(),
+        implementation: "".into(),
+        target_arch: None,
+        target_os: None,
+        target_env: None,
+        version: version.into(),
+        validator: None,
+        network_id: Default::default(),
+        startup_time: None,
+        sysinfo: None,
+        ip: None,
+        protocol_version: None,
+        relay_chain_genesis_hash: None,
+        para_id: None,
+        role: None,
+        custom: Default::default(),
+    };
+    let limits = CardinalityLimits {
+        default: usize::MAX,
+        dimensions: [("version".to_owned(), 2)].into_iter().collect(),
+    };
+    let versions = ["1.0", "2.0", "3.0", "3.0", "3.0", "2.0"];
+    let add = |collator: &mut ChainStatsCollator| {
+        for version in versions {
+            collator.add_or_remove_node(&details(version), None, None, CounterValue::Increment);
+        }
+    };
+
+    // Values beyond the limit are folded as they're seen:
+    let mut collator = ChainStatsCollator::new(
+        HardwareTiers::default(),
+        NodeOperatorPattern::default(),
+        limits.clone(),
+    );
+    add(&mut collator);
+    let stats = collator.generate();
+    assert_eq!(
+        stats.version.list,
+        vec![("2.0".to_owned(), 2), ("1.0".to_owned(), 1)]
+    );
+    assert_eq!(stats.version.other, 3);
+    assert_eq!(stats.folded_values.get("version"), Some(&3));
+    assert_eq!(stats.folded_values.get("target_os"), None);
+
+    // Removing a node with a folded value takes it back out of the folded values:
+    collator.add_or_remove_node(&details("3.0"), None, None, CounterValue::Decrement);
+    assert_eq!(collator.generate().folded_values.get("version"), Some(&2));
+
+    // Counting everything before limiting keeps the most common values:
+    let mut collator =
+        ChainStatsCollator::unlimited(HardwareTiers::default(), NodeOperatorPattern::default());
+    add(&mut collator);
+    collator.set_cardinality_limits(limits);
+    let stats = collator.generate();
+    assert_eq!(
+        stats.version.list,
+        vec![("3.0".to_owned(), 3), ("2.0".to_owned(), 2)]
+    );
+    assert_eq!(stats.folded_values.get("version"), Some(&1));
+}
+
+#[test]
+fn test_platforms() {
+    let details = |os: Option<&str>, arch: Option<&str>| common::node_types::NodeDetails {
+        chain: "".into(),
+        name: "".into(),
+        implementation: "".into(),
+        target_arch: arch.map(Into::into),
+        target_os: os.map(Into::into),
+        target_env: None,
+        version: "1.0".into(),
+        validator: None,
+        network_id: Default::default(),
+        startup_time: None,
+        sysinfo: None,
+        ip: None,
+        protocol_version: None,
+        relay_chain_genesis_hash: None,
+        para_id: None,
+        role: None,
+        custom: Default::default(),
+    };
+    let limits = CardinalityLimits {
+        dimensions: [("platform".to_owned(), 2)].into_iter().collect(),
+        ..CardinalityLimits::default()
+    };
+    let mut collator = ChainStatsCollator::new(
+        HardwareTiers::default(),
+        NodeOperatorPattern::default(),
+        limits,
+    );
+    let nodes = [
+        details(Some("linux"), Some("x86_64")),
+        details(Some("linux"), Some("x86_64")),
+        details(Some("macos"), Some("aarch64")),
+        details(Some("linux"), Some("riscv64")),
+        details(Some("linux"), None),
+    ];
+    for node in &nodes {
+        collator.add_or_remove_node(node, None, None, CounterValue::Increment);
+    }
+
+    let stats = collator.generate();
+    assert_eq!(
+        stats.platform.list,
+        vec![
+            ("linux/x86_64".to_owned(), 2),
+            ("macos/aarch64".to_owned(), 1)
+        ]
+    );
+    assert_eq!(stats.platform.other, 1);
+    assert_eq!(stats.platform.unknown, 1);
+    assert_eq!(stats.folded_values.get("platform"), Some(&1));
+
+    collator.add_or_remove_node(&nodes[0], None, None, CounterValue::Decrement);
+    let stats = collator.generate();
+    assert!(stats
+        .platform
+        .list
+        .contains(&("linux/x86_64".to_owned(), 1)));
+}
+
+#[test]
+fn test_protocol_versions() {
+    let details = |protocol_version: Option<&str>| common::node_types::NodeDetails {
+        chain: "".into(),
+        name: "".into(),
+        implementation: "".into(),
+        target_arch: None,
+        target_os: None,
+        target_env: None,
+        version: "1.0".into(),
+        validator: None,
+        network_id: Default::default(),
+        startup_time: None,
+        sysinfo: None,
+        ip: None,
+        protocol_version: protocol_version.map(Into::into),
+        relay_chain_genesis_hash: None,
+        para_id: None,
+        role: None,
+        custom: Default::default(),
+    };
+    let mut collator =
+        ChainStatsCollator::unlimited(HardwareTiers::default(), NodeOperatorPattern::default());
+    let nodes = [
+        details(Some("v2")),
+        details(Some("v1")),
+        details(Some("v2")),
+        details(None),
+    ];
+    for node in &nodes {
+        collator.add_or_remove_node(node, None, None, CounterValue::Increment);
+    }
+
+    let stats = collator.generate();
+    assert_eq!(
+        stats.protocol_version.list,
+        vec![("v2".to_owned(), 2), ("v1".to_owned(), 1)]
+    );
+    assert_eq!(stats.protocol_version.unknown, 1);
+
+    collator.add_or_remove_node(&nodes[0], None, None, CounterValue::Decrement);
+    let stats = collator.generate();
+    assert!(stats.protocol_version.list.contains(&("v2".to_owned(), 1)));
+}
@@ -18,7 +18,6 @@ use crate::feed_message::Ranking;
 use std::collections::HashMap;
 
 /// A data structure which counts how many occurrences of a given key we've seen.
-#[derive(Default)]
 pub struct Counter<K> {
     /// A map containing the number of occurrences of a given key.
     ///
@@ -27,6 +26,24 @@ pub struct Counter<K> {
 
     /// The number of occurrences where the key is `None`.
     empty: u64,
+
+    /// The most distinct keys that we'll count occurrences of separately.
+    limit: usize,
+
+    /// The number of occurrences of keys that didn't fit within the limit.
+    folded: u64,
+}
+
+// Derived, this would needlessly require `K: Default`.
+impl<K> Default for Counter<K> {
+    fn default() -> Self {
+        Counter {
+            map: HashMap::new(),
+            empty: 0,
+            limit: usize::MAX,
+            folded: 0,
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -40,7 +57,7 @@ where
     K: Sized + std::hash::Hash + Eq,
 {
     /// Either adds or removes a single occurence of a given `key`.
-    pub fn modify<'a, Q>(&mut self, key: Option<&'a Q>, op: CounterValue)
+    pub fn modify<Q>(&mut self, key: Option<&Q>, op: CounterValue)
     where
         Q: ?Sized + std::hash::Hash + Eq,
         K: std::borrow::Borrow<Q>,
@@ -61,8 +78,19 @@ where
                     }
                 }
             } else {
-                assert_eq!(op, CounterValue::Increment);
-                self.map.insert(key.to_owned(), 1);
+                match op {
+                    CounterValue::Increment if self.map.len() < self.limit => {
+                        self.map.insert(key.to_owned(), 1);
+                    }
+                    CounterValue::Increment => {
+                        self.folded += 1;
+                    }
+                    // Keys that we aren't counting separately must have been folded:
+                    CounterValue::Decrement => {
+                        assert!(self.folded > 0);
+                        self.folded -= 1;
+                    }
+                }
             }
         } else {
             match op {
@@ -76,6 +104,43 @@ where
         }
     }
 
+    /// The number of distinct keys that we've seen occurrences of.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// The number of occurrences of keys beyond the limit, which aren't counted separately.
+    pub fn folded(&self) -> u64 {
+        self.folded
+    }
+
+    /// Count occurrences of at most `limit` distinct keys separately. If we're already
+    /// counting more than that, the least common keys are folded together to make room.
+    /// New keys seen once we're at the limit are folded as well.
+    pub fn set_limit(&mut self, limit: usize)
+    where
+        K: Clone,
+    {
+        self.limit = limit;
+        if self.map.len() <= limit {
+            return;
+        }
+
+        let mut all: Vec<(&K, u64)> = self.map.iter().map(|(key, count)| (key, *count)).collect();
+        all.sort_unstable_by_key(|&(_, count)| !count);
+        let least_common: Vec<K> = all[limit..].iter().map(|&(key, _)| key.clone()).collect();
+        for key in least_common {
+            if let Some(count) = self.map.remove(&key) {
+                self.folded += count;
+            }
+        }
+    }
+
+    /// The number of occurrences of each key, in no particular order.
+    pub fn counts(&self) -> impl Iterator<Item = u64> + '_ {
+        self.map.values().copied()
+    }
+
     /// Generates a top-N table of the most common keys.
     pub fn generate_ranking_top(&self, max_count: usize) -> Ranking<K>
     where
@@ -93,7 +158,7 @@ where
         let other = all
             .iter()
             .skip(max_count)
-            .fold(0, |sum, (_, count)| sum + *count);
+            .fold(self.folded, |sum, (_, count)| sum + *count);
 
         Ranking {
             list,
@@ -102,6 +167,16 @@ where
         }
     }
 
+    /// Generates a table of the most common keys, sorted by key.
+    pub fn generate_ranking_top_ordered(&self, max_count: usize) -> Ranking<K>
+    where
+        K: Clone + Ord,
+    {
+        let mut ranking = self.generate_ranking_top(max_count);
+        ranking.list.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        ranking
+    }
+
     /// Generates a sorted table of all of the keys.
     pub fn generate_ranking_ordered(&self) -> Ranking<K>
     where
@@ -112,7 +187,7 @@ where
 
         Ranking {
             list,
-            other: 0,
+            other: self.folded,
             unknown: self.empty,
         }
     }
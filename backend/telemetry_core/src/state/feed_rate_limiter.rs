@@ -0,0 +1,102 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::Instant;
+
+/// A token bucket that caps how many non-critical messages a chain sends to feeds
+/// each second. Up to a second's worth of messages can be sent in a burst.
+#[derive(Debug, Clone)]
+pub struct FeedRateLimiter {
+    /// Messages allowed per second; 0 means that there's no limit.
+    per_second: u32,
+    /// How many messages we can send right now.
+    tokens: f64,
+    /// When `tokens` was last topped up.
+    last_refill: Instant,
+    /// How many messages have been dropped since this was last asked.
+    dropped: u64,
+}
+
+impl FeedRateLimiter {
+    pub fn new(per_second: u32) -> Self {
+        FeedRateLimiter {
+            per_second,
+            tokens: per_second as f64,
+            last_refill: Instant::now(),
+            dropped: 0,
+        }
+    }
+
+    /// Can another message be sent at `now`? If not, it's counted as dropped.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        if self.per_second == 0 {
+            return true;
+        }
+
+        let per_second = self.per_second as f64;
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * per_second).min(per_second);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            self.dropped += 1;
+            false
+        }
+    }
+
+    /// How many messages have been dropped since this was last called.
+    pub fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.dropped)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn no_limit_allows_everything() {
+        let mut limiter = FeedRateLimiter::new(0);
+        let now = Instant::now();
+        assert!((0..1000).all(|_| limiter.allow(now)));
+        assert_eq!(limiter.take_dropped(), 0);
+    }
+
+    #[test]
+    fn messages_over_the_rate_are_dropped_until_tokens_refill() {
+        let mut limiter = FeedRateLimiter::new(10);
+        let now = Instant::now();
+
+        assert!((0..10).all(|_| limiter.allow(now)));
+        assert!(!limiter.allow(now));
+        assert!(!limiter.allow(now));
+        assert_eq!(limiter.take_dropped(), 2);
+        assert_eq!(limiter.take_dropped(), 0);
+
+        // A tenth of a second later, one more message can go:
+        let now = now + Duration::from_millis(100);
+        assert!(limiter.allow(now));
+        assert!(!limiter.allow(now));
+
+        // Waiting a long time doesn't allow more than a second's worth at once:
+        let now = now + Duration::from_secs(60);
+        assert_eq!((0..20).filter(|_| limiter.allow(now)).count(), 10);
+    }
+}
@@ -0,0 +1,166 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use common::node_types::Timestamp;
+use serde::{Deserialize, Serialize};
+
+/// When a node was first and last seen, going by its stable ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Sighting {
+    first_seen: Timestamp,
+    last_seen: Timestamp,
+}
+
+/// When each node was first ever seen, by its stable ID, kept on disk so that it
+/// survives restarts. Once there are too many nodes, those that were last seen the
+/// longest ago are forgotten.
+#[derive(Debug)]
+pub struct FirstSeenStore {
+    max_len: usize,
+    sightings: HashMap<Box<str>, Sighting>,
+    /// The nodes, oldest last seen first, to know which to forget.
+    by_last_seen: BTreeSet<(Timestamp, Box<str>)>,
+    /// Has anything changed since the contents were last taken to be saved?
+    dirty: bool,
+}
+
+impl FirstSeenStore {
+    /// Load the store from `path`, or start a new one if there's no file there yet.
+    pub fn load(path: &Path, max_len: usize) -> anyhow::Result<FirstSeenStore> {
+        let sightings: HashMap<Box<str>, Sighting> = match std::fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| anyhow::anyhow!("Cannot parse first seen store {path:?}: {e}"))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => anyhow::bail!("Cannot read first seen store {path:?}: {e}"),
+        };
+        let by_last_seen = sightings
+            .iter()
+            .map(|(id, s)| (s.last_seen, id.clone()))
+            .collect();
+        let mut store = FirstSeenStore {
+            max_len: max_len.max(1),
+            sightings,
+            by_last_seen,
+            dirty: false,
+        };
+        store.evict();
+        Ok(store)
+    }
+
+    /// Note that the node with the given stable ID was seen at `now`, returning
+    /// when it was first seen.
+    pub fn record(&mut self, stable_id: &str, now: Timestamp) -> Timestamp {
+        let sighting = match self.sightings.get_mut(stable_id) {
+            Some(sighting) => {
+                self.by_last_seen
+                    .remove(&(sighting.last_seen, stable_id.into()));
+                sighting.last_seen = now;
+                *sighting
+            }
+            None => {
+                let sighting = Sighting {
+                    first_seen: now,
+                    last_seen: now,
+                };
+                self.sightings.insert(stable_id.into(), sighting);
+                sighting
+            }
+        };
+        self.by_last_seen.insert((now, stable_id.into()));
+        self.dirty = true;
+        self.evict();
+        sighting.first_seen
+    }
+
+    /// Forget the nodes last seen the longest ago until there aren't too many.
+    fn evict(&mut self) {
+        while self.sightings.len() > self.max_len {
+            let Some((_, id)) = self.by_last_seen.pop_first() else {
+                break;
+            };
+            self.sightings.remove(&id);
+            self.dirty = true;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sightings.len()
+    }
+
+    /// What to write to disk with [`save_first_seen`], if anything has changed since this was
+    /// last asked for.
+    pub fn take_changed_contents(&mut self) -> Option<Vec<u8>> {
+        if !self.dirty {
+            return None;
+        }
+        self.dirty = false;
+        Some(serde_json::to_vec(&self.sightings).expect("sightings serialize to JSON"))
+    }
+}
+
+/// Write the contents of a store to `path`. The file is replaced in one go, so that
+/// it's never left half written.
+pub fn save_first_seen(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "first_seen_test_{name}_{}_{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ))
+    }
+
+    #[test]
+    fn first_seen_survives_being_saved_and_loaded() {
+        let path = temp_path("reload");
+        let mut store = FirstSeenStore::load(&path, 10).unwrap();
+        assert_eq!(store.record("a", 100), 100);
+        assert_eq!(store.record("a", 200), 100);
+        save_first_seen(&path, &store.take_changed_contents().unwrap()).unwrap();
+        assert_eq!(store.take_changed_contents(), None);
+
+        let mut store = FirstSeenStore::load(&path, 10).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(store.record("a", 300), 100);
+        assert_eq!(store.record("b", 300), 300);
+    }
+
+    #[test]
+    fn nodes_last_seen_longest_ago_are_forgotten() {
+        let mut store = FirstSeenStore::load(&temp_path("evict"), 2).unwrap();
+        store.record("a", 100);
+        store.record("b", 200);
+        // Seeing "a" again means that "b" is now the one last seen longest ago:
+        store.record("a", 300);
+        store.record("c", 400);
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.record("a", 500), 100);
+        assert_eq!(store.record("b", 600), 600);
+    }
+}
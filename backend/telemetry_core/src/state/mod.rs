@@ -14,12 +14,36 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+mod block_authors;
 mod chain;
 mod chain_stats;
 mod counter;
+mod feed_rate_limiter;
+mod first_seen;
+mod network_denylist;
 mod node;
+mod node_groups;
+mod node_name_blocklist;
 
+#[allow(clippy::module_inception)]
 mod state;
 
-pub use node::Node;
+pub use block_authors::BlockAuthor;
+pub use chain::{
+    first_party_networks, is_first_party_network, BlockTimePoint, BlockTimeWindow, ChainAlert,
+    ChainEventRecord, ChainNodeId, CompactSummary, HealthWeights, LabelDebounce, NodeCountDrop,
+    NodeCountDrops, OperatorCap, QuotaBurst, RecentBlock, RelayParent, RemovalReason,
+    RemovedNodeRecord, RollingWindows, StaleNodePolicy, StaleTimeouts, StatsTimings,
+    SyncingMajority, Theme, STATS_UPDATE_INTERVAL,
+};
+pub use chain_stats::{
+    CardinalityLimits, ChainBandwidth, ClockSkewCounts, ConnectionDurations, ConnectionTiers,
+    HardwareTier, HardwareTiers, NodeOperatorPattern, NodeRoleCounts, UnreportedNodeRole,
+    CARDINALITY_DIMENSIONS,
+};
+pub use first_seen::{save_first_seen, FirstSeenStore};
+pub use network_denylist::NetworkDenylist;
+pub use node::{AnnotationSeverity, HardwareChanges, Node, NodeAnnotation};
+pub use node_groups::NodeGroups;
+pub use node_name_blocklist::{BlockedNodeNameAction, NodeNameBlocklist};
 pub use state::*;
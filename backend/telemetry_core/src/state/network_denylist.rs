@@ -0,0 +1,85 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use common::node_types::BlockHash;
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Genesis hashes of networks that we never want to track, such as
+/// known spam networks or test harnesses.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkDenylist {
+    genesis_hashes: HashSet<BlockHash>,
+}
+
+impl NetworkDenylist {
+    /// Load a denylist from a file containing one genesis hash per line. Blank
+    /// lines and lines starting with `#` are ignored.
+    pub fn from_file(path: &Path) -> anyhow::Result<NetworkDenylist> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read network denylist {path:?}: {e}"))?;
+        let genesis_hashes = contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        NetworkDenylist::from_genesis_hashes(genesis_hashes)
+    }
+
+    /// Build a denylist from the genesis hashes provided.
+    pub fn from_genesis_hashes<I, S>(genesis_hashes: I) -> anyhow::Result<NetworkDenylist>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let genesis_hashes = genesis_hashes
+            .into_iter()
+            .map(|h| {
+                let h = h.as_ref();
+                BlockHash::from_str(h)
+                    .map_err(|e| anyhow::anyhow!("Invalid genesis hash '{h}': {e}"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(NetworkDenylist { genesis_hashes })
+    }
+
+    /// Is the network with the genesis hash given on the list?
+    pub fn is_denied(&self, genesis_hash: &BlockHash) -> bool {
+        self.genesis_hashes.contains(genesis_hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_listed_networks_are_denied() {
+        let denylist = NetworkDenylist::from_genesis_hashes([
+            "0x0000000000000000000000000000000000000000000000000000000000000001",
+        ])
+        .unwrap();
+
+        assert!(denylist.is_denied(&BlockHash::from_low_u64_be(1)));
+        assert!(!denylist.is_denied(&BlockHash::from_low_u64_be(2)));
+    }
+
+    #[test]
+    fn invalid_genesis_hashes_are_an_error() {
+        assert!(NetworkDenylist::from_genesis_hashes(["0x1234"]).is_err());
+        assert!(NetworkDenylist::from_genesis_hashes(["polkadot"]).is_err());
+    }
+}
@@ -18,30 +18,119 @@ use crate::find_location;
 use common::node_message::SystemInterval;
 use common::node_types::{
     Block, BlockDetails, NodeDetails, NodeHardware, NodeHwBench, NodeIO, NodeLocation, NodeStats,
-    Timestamp,
+    PartialNodeStats, Timestamp,
 };
-use common::time;
+use common::{time, NumStats};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::str::FromStr;
 
 /// Minimum time between block below broadcasting updates to the browser gets throttled, in ms.
 const THROTTLE_THRESHOLD: u64 = 100;
 /// Minimum time of intervals for block updates sent to the browser when throttled, in ms.
 const THROTTLE_INTERVAL: u64 = 1000;
+/// How many of its recent best blocks we remember for a node, if asked to.
+const RECENT_BEST_LEN: usize = 64;
+/// Every this many stats updates, feeds are sent all of a node's stats rather than only
+/// those that changed, so that they can't stay out of step for long.
+const FULL_STATS_EVERY: usize = 10;
+/// How many of the latest pings the ping latency of a node is averaged over.
+const PING_LATENCY_SAMPLES: usize = 5;
+/// How many reports in a row of a deep import queue before a node's counts as backed up.
+const IMPORT_QUEUE_BACKED_UP_REPORTS: u32 = 3;
+/// How many blocks a node has to have tried to import before we work out what fraction
+/// of them it managed to, so that one early failure doesn't make it look unhealthy.
+const IMPORT_SUCCESS_MIN_ATTEMPTS: u64 = 20;
+/// How much the import success ratio of a node has to change by before feeds are told.
+const IMPORT_SUCCESS_REPORT_STEP: f64 = 0.01;
+/// How far (in ms) the start time of a node's process, worked out from the uptime that it
+/// reports, can wander before we take it to have restarted. Uptimes are only in seconds and
+/// take a while to reach us, so the start time wobbles a little between reports.
+const PROCESS_START_SLACK: u64 = 30_000;
+
+/// How a node's stats changed, from [`Node::update_stats`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum StatsUpdate<'a> {
+    /// All of the stats, sent every [`FULL_STATS_EVERY`] updates.
+    Full(&'a NodeStats),
+    /// Only the stats that changed.
+    Partial(PartialNodeStats),
+}
+
+/// How much the bandwidth that a node reports has to change by before feeds are told.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HardwareChanges {
+    /// Report a change once the upload or download rate moves by more than this
+    /// percentage of the rate that feeds were last told about. Zero reports every change.
+    pub percent: u64,
+    /// Always report a change once a rate moves by at least this many bytes per second,
+    /// however small that is relative to the last rate. Zero disables this.
+    pub bytes_per_second: u64,
+}
+
+impl HardwareChanges {
+    fn is_meaningful(&self, reported: f64, latest: f64) -> bool {
+        let change = (latest - reported).abs();
+        self.percent == 0
+            || change > reported.abs() * self.percent as f64 / 100.0
+            || (self.bytes_per_second > 0 && change >= self.bytes_per_second as f64)
+    }
+}
+
+/// How serious a [`NodeAnnotation`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl FromStr for AnnotationSeverity {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "info" => Ok(AnnotationSeverity::Info),
+            "warning" => Ok(AnnotationSeverity::Warning),
+            "critical" => Ok(AnnotationSeverity::Critical),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'info', 'warning' or 'critical', but got '{s}'"
+            )),
+        }
+    }
+}
+
+/// A note attached to a node by some external system (eg "under maintenance"), which
+/// feeds are shown until it expires.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NodeAnnotation {
+    pub text: Box<str>,
+    pub severity: AnnotationSeverity,
+    /// When (unix time in ms) the annotation expires.
+    pub expires_at: Timestamp,
+}
 
 pub struct Node {
     /// Static details
     details: NodeDetails,
     /// Basic stats
     stats: NodeStats,
+    /// How many stats updates there have been since all of the stats were last sent
+    stats_updates: usize,
     /// Node IO stats
     io: NodeIO,
     /// Best block
     best: BlockDetails,
     /// Finalized block
     finalized: Block,
+    /// Unix timestamp for when the node's finalized block last advanced
+    finalized_at: Option<Timestamp>,
     /// Timer for throttling block updates
     throttle: u64,
     /// Hardware stats over time
     hardware: NodeHardware,
+    /// The upload and download rates that feeds were last told about
+    reported_bandwidth: Option<(f64, f64)>,
     /// Physical location details
     location: find_location::Location,
     /// Flag marking if the node is stale (not syncing or producing blocks)
@@ -50,6 +139,41 @@ pub struct Node {
     startup_time: Option<Timestamp>,
     /// Hardware benchmark results for the node
     hwbench: Option<NodeHwBench>,
+    /// Unix timestamp for when the node connected
+    connected_at: Timestamp,
+    /// How long (in ms) after connecting the node reported its first best block
+    time_to_first_block: Option<u64>,
+    /// Unix timestamp for when we last heard anything from the node
+    last_seen: Timestamp,
+    /// The most recent best blocks that the node reported, oldest first, if we're remembering them
+    recent_best: VecDeque<Block>,
+    /// Round trip times (in ms) of recent pings from the shard to the node's connection
+    ping_latency: NumStats<u64>,
+    /// How many reports in a row have had a deep import queue
+    deep_import_queue_reports: u32,
+    /// How many transactions were in the best block, if the node tells us
+    block_txcount: Option<u64>,
+    /// How far (in ms) the node's clock seems to be ahead of ours; negative if it's behind
+    clock_skew: Option<i64>,
+    /// The fraction of the blocks it's tried to import that the node has imported
+    import_success: Option<f64>,
+    /// The import success ratio that feeds were last told about
+    reported_import_success: Option<f64>,
+    /// An ID that stays the same when the node reconnects, if it's been given one
+    stable_id: Option<Box<str>>,
+    /// Unix timestamp for when a node with the same stable ID was first ever seen, if
+    /// we're keeping track
+    first_seen_ever: Option<Timestamp>,
+    /// How many best blocks the node has reported that were ignored for being implausible
+    implausible_blocks: u64,
+    /// How many finalized blocks the node has reported that were ahead of its best block
+    finalized_ahead_of_best: u64,
+    /// Unix timestamp for when the node's process started, going by the uptime it reports
+    process_started_at: Option<Timestamp>,
+    /// The session that the node's best block is in, if it reports one
+    session: Option<u64>,
+    /// A note attached to the node by some external system, until it expires
+    annotation: Option<NodeAnnotation>,
 }
 
 impl Node {
@@ -59,18 +183,39 @@ impl Node {
             .take()
             .and_then(|time| time.parse().ok());
 
+        let now = time::now();
         Node {
             details,
             stats: NodeStats::default(),
+            stats_updates: 0,
             io: NodeIO::default(),
             best: BlockDetails::default(),
             finalized: Block::zero(),
+            finalized_at: None,
             throttle: 0,
             hardware: NodeHardware::default(),
+            reported_bandwidth: None,
             location: None,
             stale: false,
             startup_time,
             hwbench: None,
+            connected_at: now,
+            time_to_first_block: None,
+            last_seen: now,
+            recent_best: VecDeque::new(),
+            ping_latency: NumStats::new(PING_LATENCY_SAMPLES),
+            deep_import_queue_reports: 0,
+            block_txcount: None,
+            clock_skew: None,
+            import_success: None,
+            reported_import_success: None,
+            stable_id: None,
+            first_seen_ever: None,
+            implausible_blocks: 0,
+            finalized_ahead_of_best: 0,
+            process_started_at: None,
+            session: None,
+            annotation: None,
         }
     }
 
@@ -98,34 +243,126 @@ impl Node {
         &self.finalized
     }
 
+    /// When (unix time in ms) the node's finalized block last advanced, if it has.
+    pub fn finalized_at(&self) -> Option<Timestamp> {
+        self.finalized_at
+    }
+
     pub fn hardware(&self) -> &NodeHardware {
         &self.hardware
     }
 
+    /// The latest average upload and download rates (in bytes per second) that the
+    /// node has reported, if it's reported any.
+    pub fn bandwidth(&self) -> Option<(f64, f64)> {
+        let upload = self.hardware.upload.slice().last()?;
+        let download = self.hardware.download.slice().last()?;
+        Some((*upload, *download))
+    }
+
     pub fn location(&self) -> Option<&NodeLocation> {
         self.location.as_deref()
     }
 
-    pub fn update_location(&mut self, location: find_location::Location) {
-        self.location = location;
+    /// Update the location of the node, returning the previous one.
+    pub fn update_location(
+        &mut self,
+        location: find_location::Location,
+    ) -> find_location::Location {
+        std::mem::replace(&mut self.location, location)
     }
 
     pub fn block_details(&self) -> &BlockDetails {
         &self.best
     }
 
+    /// When (unix time in ms) the node connected.
+    pub fn connected_at(&self) -> Timestamp {
+        self.connected_at
+    }
+
     pub fn hwbench(&self) -> Option<&NodeHwBench> {
         self.hwbench.as_ref()
     }
 
+    /// Everything we know about this node, for debugging. Unlike the feed
+    /// messages, which pack details into tuples to save bytes, every value
+    /// here is named.
+    pub fn full_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "details": &self.details,
+            "stats": {
+                "peers": self.stats.peers,
+                "txcount": self.stats.txcount,
+                "import_queue": self.stats.import_queue,
+            },
+            "io": {
+                "used_state_cache_size": self.io.used_state_cache_size.slice(),
+                "disk_used": self.io.disk_used,
+                "disk_free": self.io.disk_free,
+            },
+            "best": {
+                "height": self.best.block.height,
+                "hash": self.best.block.hash,
+                "block_time": self.best.block_time,
+                "block_timestamp": self.best.block_timestamp,
+                "propagation_time": self.best.propagation_time,
+            },
+            "finalized": &self.finalized,
+            "throttle": self.throttle,
+            "hardware": {
+                "upload": self.hardware.upload.slice(),
+                "download": self.hardware.download.slice(),
+                "chart_stamps": self.hardware.chart_stamps.slice(),
+            },
+            "location": self.location.as_deref().map(|location| serde_json::json!({
+                "latitude": location.latitude,
+                "longitude": location.longitude,
+                "city": &location.city,
+                "country": &location.country,
+                "asn": location.asn,
+                "provider": &location.provider,
+            })),
+            "stale": self.stale,
+            "startup_time": self.startup_time,
+            "hwbench": &self.hwbench,
+            "connected_at": self.connected_at,
+            "clock_skew": self.clock_skew,
+            "stable_id": &self.stable_id,
+            "first_seen_ever": self.first_seen_ever,
+            "time_to_first_block": self.time_to_first_block,
+            "last_seen": self.last_seen,
+            "ping_latency": self.ping_latency(),
+            "import_success": self.import_success,
+            "annotation": &self.annotation,
+        })
+    }
+
+    pub fn annotation(&self) -> Option<&NodeAnnotation> {
+        self.annotation.as_ref()
+    }
+
+    /// Attach an annotation to the node, replacing any it already had, or clear it.
+    pub fn set_annotation(&mut self, annotation: Option<NodeAnnotation>) {
+        self.annotation = annotation;
+    }
+
     pub fn update_hwbench(&mut self, hwbench: NodeHwBench) -> Option<NodeHwBench> {
         self.hwbench.replace(hwbench)
     }
 
-    pub fn update_block(&mut self, block: Block) -> bool {
+    /// Update the best block of the node. A stale node is only considered fresh again
+    /// if its previous block was reported after `recover_threshold`, so that nodes which
+    /// report blocks only occasionally don't flap between being stale and not.
+    pub fn update_block(&mut self, block: Block, recover_threshold: u64) -> bool {
         if block.height > self.best.block.height {
-            self.stale = false;
+            if self.best.block_timestamp >= recover_threshold {
+                self.stale = false;
+            }
             self.best.block = block;
+            if self.time_to_first_block.is_none() {
+                self.time_to_first_block = Some(time::now().saturating_sub(self.connected_at));
+            }
 
             true
         } else {
@@ -153,49 +390,184 @@ impl Node {
         }
     }
 
-    pub fn update_hardware(&mut self, interval: &SystemInterval) -> bool {
-        let mut changed = false;
+    /// Record the bandwidth reported by a node, returning whether feeds should be told
+    /// about its hardware again, because the bandwidth has changed by enough since they
+    /// were last told.
+    pub fn update_hardware(&mut self, interval: &SystemInterval, changes: HardwareChanges) -> bool {
+        let mut new_mean = false;
 
         if let Some(upload) = interval.bandwidth_upload {
-            changed |= self.hardware.upload.push(upload);
+            new_mean |= self.hardware.upload.push(upload);
         }
         if let Some(download) = interval.bandwidth_download {
-            changed |= self.hardware.download.push(download);
+            new_mean |= self.hardware.download.push(download);
         }
         self.hardware.chart_stamps.push(time::now() as f64);
 
+        if !new_mean {
+            return false;
+        }
+
+        let latest = |means: &[f64]| means.last().copied().unwrap_or_default();
+        let upload = latest(self.hardware.upload.slice());
+        let download = latest(self.hardware.download.slice());
+        let changed = self
+            .reported_bandwidth
+            .is_none_or(|(reported_up, reported_down)| {
+                changes.is_meaningful(reported_up, upload)
+                    || changes.is_meaningful(reported_down, download)
+            });
+        if changed {
+            self.reported_bandwidth = Some((upload, download));
+        }
         changed
     }
 
-    pub fn update_stats(&mut self, interval: &SystemInterval) -> Option<&NodeStats> {
-        let mut changed = false;
+    pub fn update_stats(&mut self, interval: &SystemInterval) -> Option<StatsUpdate<'_>> {
+        let mut changed = PartialNodeStats::default();
 
         if let Some(peers) = interval.peers {
             if peers != self.stats.peers {
                 self.stats.peers = peers;
-                changed = true;
+                changed.peers = Some(peers);
             }
         }
         if let Some(txcount) = interval.txcount {
             if txcount != self.stats.txcount {
                 self.stats.txcount = txcount;
-                changed = true;
+                changed.txcount = Some(txcount);
+            }
+        }
+        if let Some(import_queue) = interval.import_queue_depth {
+            if import_queue != self.stats.import_queue {
+                self.stats.import_queue = import_queue;
+                changed.import_queue = Some(import_queue);
             }
         }
 
-        if changed {
-            Some(&self.stats)
+        if changed == PartialNodeStats::default() {
+            return None;
+        }
+
+        self.stats_updates += 1;
+        if self.stats_updates >= FULL_STATS_EVERY {
+            self.stats_updates = 0;
+            Some(StatsUpdate::Full(&self.stats))
         } else {
-            None
+            Some(StatsUpdate::Partial(changed))
         }
     }
 
+    /// How many transactions were in the node's best block, if it's told us.
+    pub fn block_txcount(&self) -> Option<u64> {
+        self.block_txcount
+    }
+
+    pub fn update_block_txcount(&mut self, interval: &SystemInterval) {
+        if let Some(block_txcount) = interval.block_txcount {
+            self.block_txcount = Some(block_txcount);
+        }
+    }
+
+    /// How far (in ms) the node's clock seems to be ahead of ours, going by its
+    /// latest interval; negative if it's behind.
+    pub fn clock_skew(&self) -> Option<i64> {
+        self.clock_skew
+    }
+
+    /// Is the disk that the node's database is on running out of space? This is `None`
+    /// until the node tells us how much space is free.
+    pub fn low_on_disk(&self, low_disk_threshold: u64) -> Option<bool> {
+        self.io.disk_free.map(|free| free < low_disk_threshold)
+    }
+
+    pub fn update_clock_skew(&mut self, interval: &SystemInterval) {
+        if let Some(clock_skew) = interval.clock_skew {
+            self.clock_skew = Some(clock_skew);
+        }
+    }
+
+    /// Note how many blocks the node has imported and failed to import. Returns the new
+    /// fraction of blocks that it's imported if that's changed enough to tell feeds about.
+    pub fn update_import_success(&mut self, interval: &SystemInterval) -> Option<f64> {
+        let (Some(imports), Some(failures)) =
+            (interval.block_imports, interval.block_import_failures)
+        else {
+            return None;
+        };
+        let attempts = imports.saturating_add(failures);
+        if attempts < IMPORT_SUCCESS_MIN_ATTEMPTS {
+            return None;
+        }
+
+        let ratio = imports as f64 / attempts as f64;
+        self.import_success = Some(ratio);
+        let changed = self
+            .reported_import_success
+            .is_none_or(|reported| (ratio - reported).abs() >= IMPORT_SUCCESS_REPORT_STEP);
+        if changed {
+            self.reported_import_success = Some(ratio);
+        }
+        changed.then_some(ratio)
+    }
+
+    /// Has the node imported less than `min_percent` of the blocks it's tried to? 0 means
+    /// that no node has.
+    pub fn import_unhealthy(&self, min_percent: u64) -> bool {
+        self.import_success
+            .is_some_and(|ratio| ratio * 100.0 < min_percent as f64)
+    }
+
+    /// Work out when the node's process started from the uptime that it reports. Returns the
+    /// start time if it's new to us, which it is the first time and whenever the node restarts
+    /// (even if its telemetry connection stays up while it does).
+    pub fn update_process_uptime(
+        &mut self,
+        interval: &SystemInterval,
+        now: Timestamp,
+    ) -> Option<Timestamp> {
+        let started_at = now.saturating_sub(interval.uptime?.saturating_mul(1000));
+        let restarted = self
+            .process_started_at
+            .is_none_or(|known| known.abs_diff(started_at) > PROCESS_START_SLACK);
+        if restarted {
+            self.process_started_at = Some(started_at);
+        }
+        restarted.then_some(started_at)
+    }
+
+    /// When (unix time in ms) the node's process started, if it's reported its uptime.
+    pub fn process_started_at(&self) -> Option<Timestamp> {
+        self.process_started_at
+    }
+
+    /// Note the session that the node reports being in, returning the one that it was in
+    /// before if that's changed.
+    pub fn update_session(&mut self, interval: &SystemInterval) -> Option<Option<u64>> {
+        let session = interval.session?;
+        let previous = self.session.replace(session);
+        (previous != Some(session)).then_some(previous)
+    }
+
+    /// The session that the node's best block is in, if it's reported one.
+    pub fn session(&self) -> Option<u64> {
+        self.session
+    }
+
     pub fn update_io(&mut self, interval: &SystemInterval) -> Option<&NodeIO> {
         let mut changed = false;
 
         if let Some(size) = interval.used_state_cache_size {
             changed |= self.io.used_state_cache_size.push(size);
         }
+        if interval.disk_used.is_some() && interval.disk_used != self.io.disk_used {
+            self.io.disk_used = interval.disk_used;
+            changed = true;
+        }
+        if interval.disk_free.is_some() && interval.disk_free != self.io.disk_free {
+            self.io.disk_free = interval.disk_free;
+            changed = true;
+        }
 
         if changed {
             Some(&self.io)
@@ -204,9 +576,13 @@ impl Node {
         }
     }
 
+    /// Update the node's finalized block, handing it back if it changed. The finalized
+    /// block never goes backwards, so reports of blocks no higher than the one we know
+    /// about (eg old messages arriving late) are ignored.
     pub fn update_finalized(&mut self, block: Block) -> Option<&Block> {
         if block.height > self.finalized.height {
             self.finalized = block;
+            self.finalized_at = Some(time::now());
             Some(self.finalized())
         } else {
             None
@@ -225,16 +601,456 @@ impl Node {
         self.stale
     }
 
-    pub fn set_validator_address(&mut self, addr: Box<str>) -> bool {
-        if self.details.validator.as_ref() == Some(&addr) {
+    /// Set the validator address of the node, or clear it if `None`. Returns whether
+    /// that changed anything.
+    pub fn set_validator_address(&mut self, addr: Option<Box<str>>) -> bool {
+        if self.details.validator == addr {
             false
         } else {
-            self.details.validator = Some(addr);
+            self.details.validator = addr;
             true
         }
     }
 
+    /// Give the node an ID that, unlike the one that feeds know it by, stays the same
+    /// when it reconnects, by hashing its network ID. Nodes that don't report a network
+    /// ID aren't given one.
+    pub fn assign_stable_id(&mut self) {
+        use sha1::{Digest, Sha1};
+        if self.details.network_id.is_empty() {
+            return;
+        }
+        let digest = Sha1::digest(self.details.network_id.as_bytes());
+        self.stable_id = Some(hex::encode(&digest[..8]).into());
+    }
+
+    pub fn stable_id(&self) -> Option<&str> {
+        self.stable_id.as_deref()
+    }
+
+    /// Set when (unix time in ms) a node with the same stable ID was first ever seen.
+    pub fn set_first_seen_ever(&mut self, first_seen_ever: Timestamp) {
+        self.first_seen_ever = Some(first_seen_ever);
+    }
+
     pub fn startup_time(&self) -> Option<Timestamp> {
         self.startup_time
     }
+
+    /// How long (in ms) after connecting the node reported its first best block,
+    /// or `None` if it hasn't reported one yet.
+    pub fn time_to_first_block(&self) -> Option<u64> {
+        self.time_to_first_block
+    }
+
+    /// Unix timestamp for when we last heard anything from the node.
+    pub fn last_seen(&self) -> Timestamp {
+        self.last_seen
+    }
+
+    pub fn update_last_seen(&mut self, now: Timestamp) {
+        self.last_seen = now;
+    }
+
+    /// Record that a best block the node reported was ignored for being implausible,
+    /// returning true if it's the first one.
+    pub fn flag_implausible_block(&mut self) -> bool {
+        self.implausible_blocks += 1;
+        self.implausible_blocks == 1
+    }
+
+    /// How many best blocks the node has reported that were ignored for being implausible.
+    pub fn implausible_blocks(&self) -> u64 {
+        self.implausible_blocks
+    }
+
+    /// Record that the node reported a finalized block ahead of its best block, returning
+    /// true if it's the first time.
+    pub fn flag_finalized_ahead_of_best(&mut self) -> bool {
+        self.finalized_ahead_of_best += 1;
+        self.finalized_ahead_of_best == 1
+    }
+
+    /// Record how long a ping from the shard took to come back, returning the new
+    /// average ping latency.
+    pub fn update_ping_latency(&mut self, latency: u64) -> u64 {
+        self.ping_latency.push(latency);
+        self.ping_latency.average()
+    }
+
+    /// The average round trip time (in ms) of recent pings from the shard to the node's
+    /// connection, or `None` if there haven't been any.
+    pub fn ping_latency(&self) -> Option<u64> {
+        (!self.ping_latency.is_empty()).then(|| self.ping_latency.average())
+    }
+
+    /// Note how many blocks are in the node's import queue. Returns whether the queue is
+    /// now backed up, if that changed. It's backed up once it's had at least
+    /// `backed_up_depth` blocks in it several reports in a row; 0 means never.
+    pub fn update_import_queue(&mut self, depth: u64, backed_up_depth: u64) -> Option<bool> {
+        let was_backed_up = self.import_queue_backed_up();
+        if backed_up_depth > 0 && depth >= backed_up_depth {
+            self.deep_import_queue_reports = self.deep_import_queue_reports.saturating_add(1);
+        } else {
+            self.deep_import_queue_reports = 0;
+        }
+        let backed_up = self.import_queue_backed_up();
+        (backed_up != was_backed_up).then_some(backed_up)
+    }
+
+    /// Has the node's import queue been deep for a while?
+    pub fn import_queue_backed_up(&self) -> bool {
+        self.deep_import_queue_reports >= IMPORT_QUEUE_BACKED_UP_REPORTS
+    }
+
+    /// Remember the best block that the node just reported, so that we can
+    /// tell later on which branch it was following.
+    pub fn remember_best(&mut self, block: Block) {
+        if self.recent_best.len() >= RECENT_BEST_LEN {
+            self.recent_best.pop_front();
+        }
+        self.recent_best.push_back(block);
+    }
+
+    /// Has the node reported a different block than the one given at the same height? If
+    /// we don't know what it had at that height, we give it the benefit of the doubt.
+    pub fn conflicts_with(&self, block: &Block) -> bool {
+        if block.height == 0 {
+            return false;
+        }
+        if self.finalized.height == block.height {
+            return self.finalized.hash != block.hash;
+        }
+        self.recent_best
+            .iter()
+            .find(|b| b.height == block.height)
+            .is_some_and(|b| b.hash != block.hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::node_types::{BlockHash, NetworkId};
+
+    fn node() -> Node {
+        Node::new(NodeDetails {
+            chain: "Chain One".into(),
+            name: "A".into(),
+            implementation: "Bar".into(),
+            target_arch: None,
+            target_os: None,
+            target_env: None,
+            version: "0.1".into(),
+            validator: None,
+            network_id: NetworkId::new(),
+            startup_time: None,
+            sysinfo: None,
+            ip: None,
+            protocol_version: None,
+            relay_chain_genesis_hash: None,
+            para_id: None,
+            role: None,
+            custom: Default::default(),
+        })
+    }
+
+    fn block(height: u64) -> Block {
+        Block {
+            hash: BlockHash::from_low_u64_be(height),
+            height,
+        }
+    }
+
+    fn bandwidth(upload: f64, download: f64) -> SystemInterval {
+        SystemInterval {
+            peers: None,
+            txcount: None,
+            bandwidth_upload: Some(upload),
+            bandwidth_download: Some(download),
+            finalized_height: None,
+            finalized_hash: None,
+            block: None,
+            used_state_cache_size: None,
+            import_queue_depth: None,
+            block_txcount: None,
+            block_imports: None,
+            block_import_failures: None,
+            uptime: None,
+            session: None,
+            disk_used: None,
+            disk_free: None,
+            clock_skew: None,
+        }
+    }
+
+    #[test]
+    fn stable_ids_come_from_the_network_id() {
+        // Nodes without a network ID can't be recognised again:
+        let mut anonymous = node();
+        anonymous.assign_stable_id();
+        assert_eq!(anonymous.stable_id(), None);
+
+        let with_network_id = |network_id: &str| {
+            let mut node = node();
+            node.details.network_id = NetworkId::from(network_id).unwrap();
+            node.assign_stable_id();
+            node.stable_id.unwrap()
+        };
+        let a = with_network_id("12D3KooWA");
+        assert_eq!(a.len(), 16);
+        assert_eq!(a, with_network_id("12D3KooWA"));
+        assert_ne!(a, with_network_id("12D3KooWB"));
+    }
+
+    #[test]
+    fn every_hardware_change_is_reported_by_default() {
+        let mut node = node();
+        let changes = HardwareChanges::default();
+        assert!(node.update_hardware(&bandwidth(1000.0, 1000.0), changes));
+        assert!(node.update_hardware(&bandwidth(1000.0, 1001.0), changes));
+    }
+
+    #[test]
+    fn small_hardware_changes_are_not_reported() {
+        let mut node = node();
+        let changes = HardwareChanges {
+            percent: 10,
+            bytes_per_second: 500,
+        };
+        // The first bandwidth is always reported:
+        assert!(node.update_hardware(&bandwidth(1000.0, 1000.0), changes));
+        // Jitter either way isn't:
+        assert!(!node.update_hardware(&bandwidth(1050.0, 950.0), changes));
+        assert!(!node.update_hardware(&bandwidth(1100.0, 1000.0), changes));
+        // Changes are measured against what was last reported, so drifting adds up:
+        assert!(node.update_hardware(&bandwidth(1101.0, 1000.0), changes));
+        assert!(!node.update_hardware(&bandwidth(1101.0, 1000.0), changes));
+    }
+
+    #[test]
+    fn large_absolute_hardware_changes_are_reported() {
+        let mut node = node();
+        let changes = HardwareChanges {
+            percent: 50,
+            bytes_per_second: 1000,
+        };
+        assert!(node.update_hardware(&bandwidth(10_000.0, 0.0), changes));
+        assert!(!node.update_hardware(&bandwidth(10_999.0, 0.0), changes));
+        assert!(node.update_hardware(&bandwidth(11_000.0, 0.0), changes));
+        // Anything from nothing is a big change:
+        assert!(node.update_hardware(&bandwidth(11_000.0, 1.0), changes));
+    }
+
+    #[test]
+    fn stats_updates_only_contain_changes_until_a_full_update_is_due() {
+        let stats = |peers, txcount| SystemInterval {
+            peers,
+            txcount,
+            ..bandwidth(0.0, 0.0)
+        };
+        let mut node = node();
+
+        assert_eq!(
+            node.update_stats(&stats(Some(5), Some(0))),
+            Some(StatsUpdate::Partial(PartialNodeStats {
+                peers: Some(5),
+                txcount: None,
+                import_queue: None,
+            }))
+        );
+        // Nothing changed, so nothing to send:
+        assert_eq!(node.update_stats(&stats(Some(5), None)), None);
+
+        for peers in (6..).take(FULL_STATS_EVERY - 2) {
+            assert!(matches!(
+                node.update_stats(&stats(Some(peers), Some(1))),
+                Some(StatsUpdate::Partial(_))
+            ));
+        }
+        assert_eq!(
+            node.update_stats(&stats(Some(100), Some(1))),
+            Some(StatsUpdate::Full(&NodeStats {
+                peers: 100,
+                txcount: 1,
+                import_queue: 0,
+            }))
+        );
+        assert!(matches!(
+            node.update_stats(&stats(Some(101), Some(1))),
+            Some(StatsUpdate::Partial(_))
+        ));
+    }
+
+    #[test]
+    fn import_queues_are_backed_up_once_they_stay_deep() {
+        let mut node = node();
+
+        assert_eq!(node.update_import_queue(100, 50), None);
+        assert_eq!(node.update_import_queue(100, 50), None);
+        // A shallow queue starts the count again:
+        assert_eq!(node.update_import_queue(10, 50), None);
+        assert_eq!(node.update_import_queue(50, 50), None);
+        assert_eq!(node.update_import_queue(60, 50), None);
+        assert_eq!(node.update_import_queue(70, 50), Some(true));
+        assert!(node.import_queue_backed_up());
+        assert_eq!(node.update_import_queue(80, 50), None);
+        assert_eq!(node.update_import_queue(0, 50), Some(false));
+        assert!(!node.import_queue_backed_up());
+
+        // A depth of 0 never counts as backed up:
+        for _ in 0..10 {
+            assert_eq!(node.update_import_queue(1000, 0), None);
+        }
+    }
+
+    #[test]
+    fn finalized_at_only_moves_when_the_finalized_block_advances() {
+        let mut node = node();
+        assert_eq!(node.finalized_at(), None);
+
+        node.update_finalized(block(2));
+        let finalized_at = node.finalized_at();
+        assert!(finalized_at.is_some());
+
+        node.finalized_at = Some(0);
+        node.update_finalized(block(1));
+        node.update_finalized(block(2));
+        assert_eq!(node.finalized_at(), Some(0));
+
+        node.update_finalized(block(3));
+        assert!(node.finalized_at() >= finalized_at);
+    }
+
+    #[test]
+    fn finalized_blocks_reported_out_of_order_are_ignored() {
+        let mut node = node();
+        assert_eq!(node.update_finalized(block(10)), Some(&block(10)));
+
+        // Late reports of older (or the same) finalized blocks don't move it backwards:
+        assert_eq!(node.update_finalized(block(5)), None);
+        assert_eq!(node.update_finalized(block(10)), None);
+        assert_eq!(node.finalized(), &block(10));
+
+        assert_eq!(node.update_finalized(block(11)), Some(&block(11)));
+    }
+
+    #[test]
+    fn time_to_first_block_is_only_recorded_once() {
+        let mut node = node();
+        assert_eq!(node.time_to_first_block(), None);
+
+        node.update_block(block(1), 0);
+        let time_to_first_block = node.time_to_first_block();
+        assert!(time_to_first_block.is_some());
+
+        node.connected_at = 0;
+        node.update_block(block(2), 0);
+        assert_eq!(node.time_to_first_block(), time_to_first_block);
+    }
+
+    #[test]
+    fn conflicts_are_spotted_from_remembered_blocks() {
+        let block = |height, hash| Block {
+            height,
+            hash: BlockHash::from_low_u64_be(hash),
+        };
+        let mut node = node();
+        node.remember_best(block(1, 1));
+        node.remember_best(block(2, 2));
+
+        assert!(!node.conflicts_with(&block(2, 2)));
+        assert!(node.conflicts_with(&block(2, 20)));
+        // Nothing known about this height:
+        assert!(!node.conflicts_with(&block(3, 30)));
+
+        // Only so many blocks are remembered:
+        for height in 3..(3 + RECENT_BEST_LEN as u64) {
+            node.remember_best(block(height, height));
+        }
+        assert!(!node.conflicts_with(&block(2, 20)));
+
+        // The node's own finalized block counts too:
+        node.update_finalized(block(2, 2));
+        assert!(node.conflicts_with(&block(2, 20)));
+    }
+
+    #[test]
+    fn stale_nodes_must_report_blocks_quickly_to_recover() {
+        let mut node = node();
+        let start = node.best_timestamp();
+
+        node.update_block(block(1), start);
+        node.update_details(start, None);
+
+        // It's been a while since the last block; node becomes stale:
+        assert!(node.update_stale(start + 1));
+
+        // Reporting one block after a long gap isn't enough to recover:
+        let now = start + 120_000;
+        assert!(node.update_block(block(2), now - 60_000));
+        node.update_details(now, None);
+        assert!(node.stale());
+
+        // But reporting another block soon after is:
+        let now = now + 6_000;
+        assert!(node.update_block(block(3), now - 60_000));
+        node.update_details(now, None);
+        assert!(!node.stale());
+    }
+
+    #[test]
+    fn only_big_changes_in_import_success_are_reported() {
+        let imports = |imports, failures| SystemInterval {
+            block_imports: Some(imports),
+            block_import_failures: Some(failures),
+            ..bandwidth(0.0, 0.0)
+        };
+        let mut node = node();
+
+        // Too few attempts to go on yet:
+        assert_eq!(node.update_import_success(&imports(5, 5)), None);
+        assert_eq!(node.import_success, None);
+        assert!(!node.import_unhealthy(90));
+
+        assert_eq!(node.update_import_success(&imports(99, 1)), Some(0.99));
+        assert!(!node.import_unhealthy(90));
+        // Less than a percentage point of change isn't reported:
+        assert_eq!(node.update_import_success(&imports(995, 5)), None);
+        assert_eq!(node.import_success, Some(0.995));
+
+        assert_eq!(node.update_import_success(&imports(80, 20)), Some(0.8));
+        assert!(node.import_unhealthy(90));
+        assert!(!node.import_unhealthy(0));
+    }
+
+    #[test]
+    fn restarts_are_noticed_from_the_reported_uptime() {
+        let uptime = |secs| SystemInterval {
+            uptime: Some(secs),
+            session: None,
+            ..bandwidth(0.0, 0.0)
+        };
+        let mut node = node();
+        assert_eq!(
+            node.update_process_uptime(&bandwidth(0.0, 0.0), 1_000_000),
+            None
+        );
+        assert_eq!(node.process_started_at(), None);
+
+        assert_eq!(
+            node.update_process_uptime(&uptime(600), 1_000_000),
+            Some(400_000)
+        );
+        // A little wobble in when the process seems to have started isn't a restart:
+        assert_eq!(node.update_process_uptime(&uptime(605), 1_010_000), None);
+        assert_eq!(node.process_started_at(), Some(400_000));
+
+        // The uptime going back down is:
+        assert_eq!(
+            node.update_process_uptime(&uptime(10), 1_020_000),
+            Some(1_010_000)
+        );
+        assert_eq!(node.process_started_at(), Some(1_010_000));
+    }
 }
@@ -0,0 +1,126 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::Node;
+
+/// The groups (eg teams sharing one telemetry core) that nodes belong to, by their
+/// network ID. Feeds can ask to only be told about the nodes in one group.
+#[derive(Debug, Clone, Default)]
+pub struct NodeGroups {
+    groups_by_network_id: HashMap<Box<str>, Box<str>>,
+    /// The group that nodes not in any other group belong to. If there isn't one,
+    /// those nodes can be seen by every group.
+    untagged_group: Option<Box<str>>,
+}
+
+impl NodeGroups {
+    /// Load groups from a file containing a network ID and then a group on each line,
+    /// separated by whitespace. Blank lines and lines starting with `#` are ignored.
+    pub fn from_file(path: &Path) -> anyhow::Result<NodeGroups> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read node groups {path:?}: {e}"))?;
+        let mut groups = NodeGroups::default();
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (network_id, group) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Line {} of node groups {path:?}: expecting format `<network_id> <group>`",
+                    idx + 1
+                )
+            })?;
+            groups.insert(network_id, group.trim());
+        }
+        Ok(groups)
+    }
+
+    /// Put the node with the given network ID in the given group.
+    pub fn insert(&mut self, network_id: &str, group: &str) {
+        self.groups_by_network_id
+            .insert(network_id.into(), group.into());
+    }
+
+    /// Put nodes that aren't in any other group in the group given, or if `None`, let
+    /// every group see them.
+    pub fn set_untagged_group(&mut self, group: Option<Box<str>>) {
+        self.untagged_group = group;
+    }
+
+    /// The group that the node belongs to, if any.
+    pub fn group_of(&self, node: &Node) -> Option<&str> {
+        self.groups_by_network_id
+            .get(node.details().network_id.as_str())
+            .or(self.untagged_group.as_ref())
+            .map(|group| &**group)
+    }
+
+    /// Can feeds that only want to hear about the given group see the node?
+    pub fn can_see(&self, group: &str, node: &Node) -> bool {
+        self.group_of(node).is_none_or(|g| g == group)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::node_types::{NetworkId, NodeDetails};
+
+    fn node(network_id: &str) -> Node {
+        Node::new(NodeDetails {
+            chain: "Chain One".into(),
+            name: "Node".into(),
+            implementation: "Bar".into(),
+            target_arch: None,
+            target_os: None,
+            target_env: None,
+            version: "0.1".into(),
+            validator: None,
+            network_id: NetworkId::from(network_id).unwrap(),
+            startup_time: None,
+            sysinfo: None,
+            ip: None,
+            protocol_version: None,
+            relay_chain_genesis_hash: None,
+            para_id: None,
+            role: None,
+            custom: Default::default(),
+        })
+    }
+
+    #[test]
+    fn nodes_can_only_be_seen_by_their_own_group() {
+        let mut groups = NodeGroups::default();
+        groups.insert("a", "team-a");
+        groups.insert("b", "team-b");
+
+        assert!(groups.can_see("team-a", &node("a")));
+        assert!(!groups.can_see("team-b", &node("a")));
+        assert!(groups.can_see("team-b", &node("b")));
+
+        // Nodes in no group can be seen by everybody, unless they're given one:
+        assert_eq!(groups.group_of(&node("c")), None);
+        assert!(groups.can_see("team-a", &node("c")));
+        groups.set_untagged_group(Some("public".into()));
+        assert_eq!(groups.group_of(&node("c")), Some("public"));
+        assert!(!groups.can_see("team-a", &node("c")));
+        assert!(groups.can_see("public", &node("c")));
+    }
+}
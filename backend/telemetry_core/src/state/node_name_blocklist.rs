@@ -0,0 +1,124 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use regex::{Regex, RegexBuilder};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The name given to nodes whose real name matches the blocklist,
+/// if [`BlockedNodeNameAction::Replace`] is in effect.
+pub const BLOCKED_NODE_NAME_PLACEHOLDER: &str = "[name hidden]";
+
+/// What should we do with a node whose name is on the blocklist?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockedNodeNameAction {
+    /// Don't add the node at all, and mute it.
+    Reject,
+    /// Add the node, but hide its name behind a placeholder.
+    Replace,
+}
+
+impl FromStr for BlockedNodeNameAction {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(BlockedNodeNameAction::Reject),
+            "replace" => Ok(BlockedNodeNameAction::Replace),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'reject' or 'replace', but got '{s}'"
+            )),
+        }
+    }
+}
+
+/// A list of case insensitive patterns that node names are checked against.
+/// Each pattern is a regular expression which can match anywhere in the
+/// name, so a plain word acts as a substring match.
+#[derive(Debug, Clone, Default)]
+pub struct NodeNameBlocklist {
+    patterns: Vec<Regex>,
+}
+
+impl NodeNameBlocklist {
+    /// Load a blocklist from a file containing one pattern per line. Blank
+    /// lines and lines starting with `#` are ignored.
+    pub fn from_file(path: &Path) -> anyhow::Result<NodeNameBlocklist> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read node name blocklist {path:?}: {e}"))?;
+        let patterns = contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        NodeNameBlocklist::from_patterns(patterns)
+    }
+
+    /// Build a blocklist from the patterns provided.
+    pub fn from_patterns<I, S>(patterns: I) -> anyhow::Result<NodeNameBlocklist>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let patterns = patterns
+            .into_iter()
+            .map(|p| {
+                let p = p.as_ref();
+                RegexBuilder::new(p)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| anyhow::anyhow!("Invalid node name pattern '{p}': {e}"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(NodeNameBlocklist { patterns })
+    }
+
+    /// Does the name given match any of the patterns on the list?
+    pub fn is_blocked(&self, name: &str) -> bool {
+        self.patterns.iter().any(|p| p.is_match(name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_substrings_case_insensitively() {
+        let blocklist = NodeNameBlocklist::from_patterns(["buy-tokens", "spam"]).unwrap();
+
+        assert!(blocklist.is_blocked("BUY-TOKENS.example"));
+        assert!(blocklist.is_blocked("my spammy node"));
+        assert!(!blocklist.is_blocked("Alice"));
+    }
+
+    #[test]
+    fn matches_regex_patterns() {
+        let blocklist = NodeNameBlocklist::from_patterns([r"^\d+$"]).unwrap();
+
+        assert!(blocklist.is_blocked("12345"));
+        assert!(!blocklist.is_blocked("node-12345"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_an_error() {
+        assert!(NodeNameBlocklist::from_patterns(["valid", "(unclosed"]).is_err());
+    }
+
+    #[test]
+    fn empty_blocklist_blocks_nothing() {
+        let blocklist = NodeNameBlocklist::default();
+        assert!(!blocklist.is_blocked("anything"));
+    }
+}
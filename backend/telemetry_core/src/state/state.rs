@@ -14,16 +14,36 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use super::node::Node;
-use crate::feed_message::{ChainStats, FeedMessageSerializer};
+use super::chain_stats::{
+    CardinalityLimits, ConnectionTiers, HardwareTiers, NodeOperatorPattern, UnreportedNodeRole,
+};
+use super::node::{HardwareChanges, Node};
+use crate::feed_message::{ChainStats, FeedMessageSerializer, NodeDetailFields};
 use crate::find_location;
 use common::node_message::Payload;
 use common::node_types::{Block, BlockHash, NodeDetails, Timestamp};
-use common::{id_type, DenseMap};
-use std::collections::{HashMap, HashSet};
+use common::{id_type, time, DenseMap, DenseMapStats, Histogram};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::iter::IntoIterator;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use super::chain::{self, Chain, ChainNodeId};
+use super::chain::{
+    self, BlockTimePoint, Chain, ChainAlert, ChainConfig, ChainEventRecord, ChainNodeId,
+    CompactSummary, HealthWeights, LabelDebounce, NodeCountDrop, NodeCountDrops, OperatorCap,
+    QuotaBurst, RecentBlock, RelayParent, RemovalReason, RemovedNodeRecord, RollingWindows,
+    StaleTimeouts, StatsTimings, SyncingMajority, Theme,
+};
+use super::chain_stats::major_minor_version;
+use super::first_seen::FirstSeenStore;
+use super::network_denylist::NetworkDenylist;
+use super::node::NodeAnnotation;
+use super::node_groups::NodeGroups;
+use super::node_name_blocklist::{
+    BlockedNodeNameAction, NodeNameBlocklist, BLOCKED_NODE_NAME_PLACEHOLDER,
+};
 
 id_type! {
     /// A globally unique Chain ID.
@@ -41,6 +61,105 @@ impl NodeId {
     }
 }
 
+/// The label of the chain that nodes without a genesis hash are put on, if they're
+/// not rejected.
+pub const UNIDENTIFIED_CHAIN_LABEL: &str = "[unidentified: no genesis hash]";
+
+/// What should we do with a node that reports the same network ID as a node that's
+/// already on its chain?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateNodePolicy {
+    /// Add the node, and tell feeds that it's a duplicate of the existing node.
+    Flag,
+    /// Don't add the node at all, and mute it.
+    Reject,
+    /// Add the node, and remove (and mute) the existing node.
+    Replace,
+}
+
+impl FromStr for DuplicateNodePolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flag" => Ok(DuplicateNodePolicy::Flag),
+            "reject" => Ok(DuplicateNodePolicy::Reject),
+            "replace" => Ok(DuplicateNodePolicy::Replace),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'flag', 'reject' or 'replace', but got '{s}'"
+            )),
+        }
+    }
+}
+
+/// What should we do with a node that connects again while its old connection is still
+/// on its chain (see [`Chain::get_reconnect_of`])?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconnectPolicy {
+    /// Don't add the new connection at all, and mute it.
+    Reject,
+    /// Add the new connection, and remove (and mute) the old one.
+    Replace,
+}
+
+impl FromStr for ReconnectPolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(ReconnectPolicy::Reject),
+            "replace" => Ok(ReconnectPolicy::Replace),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'reject' or 'replace', but got '{s}'"
+            )),
+        }
+    }
+}
+
+/// What should we do with a node running an older client version than the minimum
+/// for its chain?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutdatedVersionPolicy {
+    /// Add the node, and tell feeds that its version is outdated.
+    Flag,
+    /// Don't add the node at all, and mute it.
+    Reject,
+}
+
+impl FromStr for OutdatedVersionPolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flag" => Ok(OutdatedVersionPolicy::Flag),
+            "reject" => Ok(OutdatedVersionPolicy::Reject),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'flag' or 'reject', but got '{s}'"
+            )),
+        }
+    }
+}
+
+/// How should we treat a node whose version we can't make sense of, on a chain with
+/// a minimum client version?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnparseableVersionPolicy {
+    /// Treat the node as though its version is recent enough.
+    Allow,
+    /// Treat the node as though its version is outdated.
+    Outdated,
+}
+
+impl FromStr for UnparseableVersionPolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(UnparseableVersionPolicy::Allow),
+            "outdated" => Ok(UnparseableVersionPolicy::Outdated),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'allow' or 'outdated', but got '{s}'"
+            )),
+        }
+    }
+}
+
 /// Our state contains node and chain information
 pub struct State {
     chains: DenseMap<ChainId, Chain>,
@@ -51,9 +170,94 @@ pub struct State {
     /// Chain labels that we do not want to allow connecting.
     denylist: HashSet<String>,
 
+    /// Genesis hashes of networks that we do not want to allow connecting.
+    network_denylist: Arc<NetworkDenylist>,
+
     /// How many nodes from third party chains are allowed to connect
     /// before we prevent connections from them.
     max_third_party_nodes: usize,
+
+    /// How many nodes without a genesis hash are put on the unidentified chain. 0 means
+    /// that they're rejected instead.
+    max_unidentified_nodes: usize,
+
+    /// Node names that we do not want to show.
+    node_name_blocklist: Arc<NodeNameBlocklist>,
+
+    /// What to do with nodes whose name is on the blocklist.
+    blocked_node_name_action: BlockedNodeNameAction,
+
+    /// What to do with nodes that report the same network ID as another node on their chain.
+    duplicate_node_policy: DuplicateNodePolicy,
+
+    /// What to do with nodes that connect again while their old connection is still around.
+    reconnect_policy: ReconnectPolicy,
+
+    /// The oldest (major, minor) client version that nodes on the chains with these
+    /// genesis hashes can run without being outdated.
+    chain_min_versions: HashMap<BlockHash, (u32, u32)>,
+
+    /// What to do with nodes running an older version than the minimum for their chain.
+    outdated_version_policy: OutdatedVersionPolicy,
+
+    /// How to treat nodes whose version can't be parsed, on chains with a minimum version.
+    unparseable_version_policy: UnparseableVersionPolicy,
+
+    /// Settings that new chains are created with.
+    chain_config: ChainConfig,
+
+    /// Chains with these genesis hashes always use the given label.
+    chain_label_overrides: HashMap<BlockHash, Box<str>>,
+    /// How frontends should show the chains with these genesis hashes.
+    chain_themes: HashMap<BlockHash, Theme>,
+
+    /// Chains with these genesis hashes expose node details (or not) regardless
+    /// of the default in `chain_config`.
+    chain_expose_node_details: HashMap<BlockHash, bool>,
+
+    /// Chains with these genesis hashes allow feeds to be sent different node details
+    /// to the default in `chain_config`.
+    chain_node_detail_fields: HashMap<BlockHash, NodeDetailFields>,
+
+    /// Chains with these genesis hashes sample imported blocks differently
+    /// to the default in `chain_config`.
+    chain_imported_block_sample: HashMap<BlockHash, usize>,
+
+    /// Chains with these genesis hashes locate a different number of nodes
+    /// to the default in `chain_config`.
+    chain_max_located_nodes: HashMap<BlockHash, usize>,
+
+    /// Chains with these genesis hashes keep hold of a different number of recent
+    /// best blocks to the default in `chain_config`.
+    chain_recent_blocks_len: HashMap<BlockHash, usize>,
+
+    /// How many validators the chains with these genesis hashes are expected to have.
+    chain_expected_validators: HashMap<BlockHash, u64>,
+
+    /// Chains with these genesis hashes are private, and so never listed.
+    private_chains: HashSet<BlockHash>,
+
+    /// How long (in ms) blocks are expected to take on the chains with these genesis hashes.
+    chain_expected_block_times: HashMap<BlockHash, u64>,
+
+    /// How long chains are kept hold of once their last node leaves, in case nodes
+    /// come back. Zero removes them straight away.
+    empty_chain_ttl: Duration,
+
+    /// When each chain that has no nodes left became empty.
+    empty_chains: HashMap<ChainId, Instant>,
+
+    /// Is the aggregator falling behind on messages, so that chains should do less work?
+    under_load: bool,
+
+    /// The groups that nodes belong to, so that feeds can be told about only one group.
+    node_groups: Arc<NodeGroups>,
+
+    /// Are nodes given IDs that stay the same when they reconnect, for feeds to see?
+    stable_node_ids: bool,
+
+    /// If given, when nodes were first ever seen is kept track of here by their stable IDs.
+    first_seen: Option<Arc<Mutex<FirstSeenStore>>>,
 }
 
 /// Adding a node to a chain leads to this result.
@@ -62,6 +266,17 @@ pub enum AddNodeResult<'a> {
     ChainOnDenyList,
     /// The chain is over quota (too many nodes connected), so can't add the node
     ChainOverQuota,
+    /// The node name is on the blocklist, and such nodes are rejected
+    NodeNameOnBlocklist,
+    /// The genesis hash reported by the node can't belong to a real chain
+    InvalidGenesisHash,
+    /// Another node on the chain reports the same network ID, and duplicates are rejected
+    DuplicateNode,
+    /// The operator of the node already has as many nodes on the chain as it's allowed
+    OperatorOverCap,
+    /// The node runs an older version than the minimum for the chain, and such nodes
+    /// are rejected
+    OutdatedVersion,
     /// The node was added to the chain
     NodeAddedToChain(NodeAddedToChain<'a>),
 }
@@ -80,6 +295,7 @@ pub struct NodeAddedToChain<'a> {
     /// The ID assigned to this node.
     pub id: NodeId,
     /// The old label of the chain.
+    #[allow(dead_code)]
     pub old_chain_label: Box<str>,
     /// The new label of the chain.
     pub new_chain_label: &'a str,
@@ -89,20 +305,96 @@ pub struct NodeAddedToChain<'a> {
     pub chain_node_count: usize,
     /// Has the chain label been updated?
     pub has_chain_label_changed: bool,
+    /// If the chain is a parachain, the relay chain that it belongs to.
+    pub relay_parent: Option<RelayParent>,
+    /// Has the relay chain that the chain belongs to been updated?
+    pub has_relay_parent_changed: bool,
+    /// How frontends should show the chain.
+    pub chain_theme: &'a Theme,
+    /// Were feeds being told about the chain before the node was added?
+    pub was_chain_listed: bool,
+    /// Should feeds be told about the chain now?
+    pub is_chain_listed: bool,
+    /// An existing node on the chain that reports the same network ID as this one.
+    pub duplicate_of: Option<NodeId>,
+    /// Is this the same node as the duplicate, connecting again before its old
+    /// connection has gone away?
+    pub is_reconnect: bool,
+    /// How many other nodes on the chain have the same name as this one, if we're
+    /// noticing name collisions.
+    pub name_collisions: usize,
+    /// Should the location of the node be looked up?
+    pub should_locate: bool,
+    /// Does the node run an older version than the minimum for the chain?
+    pub is_outdated: bool,
+    /// Has the chain's best or finalized block changed because the node was ahead of it?
+    pub has_chain_blocks_changed: bool,
+}
+
+/// Moving a node to another chain leads to this result.
+#[allow(clippy::large_enum_variant)]
+pub enum MoveNodeResult<'a> {
+    /// There's no node with the ID given
+    NodeNotFound,
+    /// The node is already on the chain that it was to be moved to
+    AlreadyOnChain,
+    /// The chain is on the "deny list", so we can't move the node to it
+    ChainOnDenyList,
+    /// The chain is over quota (too many nodes connected), so can't move the node to it
+    ChainOverQuota,
+    /// The genesis hash can't belong to a real chain
+    InvalidGenesisHash,
+    /// The node was moved to the chain
+    NodeMoved(NodeMoved<'a>),
+}
+
+/// If moving a node is successful, we get this information back.
+pub struct NodeMoved<'a> {
+    /// How the chain that the node was on changed.
+    pub removed: RemovedNode,
+    /// How the chain that the node is now on changed, including its new ID.
+    pub added: NodeAddedToChain<'a>,
 }
 
 /// if removing a node is successful, we get this information back.
 pub struct RemovedNode {
-    /// How many nodes remain on the chain (0 if the chain was removed)
+    /// How many nodes remain on the chain (0 if the chain is now empty)
     pub chain_node_count: usize,
     /// Has the chain label been updated?
     pub has_chain_label_changed: bool,
     /// The old label of the chain.
+    #[allow(dead_code)]
     pub old_chain_label: Box<str>,
     /// Genesis hash of the chain to be updated.
     pub chain_genesis_hash: BlockHash,
     /// The new label of the chain.
     pub new_chain_label: Box<str>,
+    /// Set if the chain has suddenly lost lots of nodes.
+    pub node_count_drop: Option<NodeCountDrop>,
+    /// Were feeds being told about the chain before the node was removed?
+    pub was_chain_listed: bool,
+    /// Should feeds be told about the chain now?
+    pub is_chain_listed: bool,
+}
+
+/// Metrics that we expose about each chain.
+pub struct ChainMetrics<'a> {
+    pub genesis_hash: BlockHash,
+    pub label: &'a str,
+    /// How many messages per second the chain is receiving.
+    pub messages_per_second: f64,
+    /// How long regenerating the chain stats has taken, in microseconds.
+    pub stats_regeneration_times: &'a Histogram,
+    /// How many messages from nodes on the chain couldn't be deserialized.
+    pub malformed_messages: u64,
+    /// How many nodes are on the chain.
+    pub node_count: usize,
+    /// How many blocks the best block is ahead of the finalized block.
+    pub best_finalized_gap: u64,
+    /// Every time between best blocks seen, in milliseconds.
+    pub block_times: &'a Histogram,
+    /// The most recent best block, if we're keeping hold of any.
+    pub latest_block: Option<RecentBlock>,
 }
 
 impl State {
@@ -111,310 +403,1956 @@ impl State {
             chains: DenseMap::new(),
             chains_by_genesis_hash: HashMap::new(),
             denylist: denylist.into_iter().collect(),
+            network_denylist: Arc::new(NetworkDenylist::default()),
             max_third_party_nodes,
+            max_unidentified_nodes: 0,
+            node_name_blocklist: Arc::new(NodeNameBlocklist::default()),
+            blocked_node_name_action: BlockedNodeNameAction::Replace,
+            duplicate_node_policy: DuplicateNodePolicy::Flag,
+            reconnect_policy: ReconnectPolicy::Replace,
+            chain_min_versions: HashMap::new(),
+            outdated_version_policy: OutdatedVersionPolicy::Flag,
+            unparseable_version_policy: UnparseableVersionPolicy::Allow,
+            chain_config: ChainConfig::default(),
+            chain_label_overrides: HashMap::new(),
+            chain_themes: HashMap::new(),
+            chain_expose_node_details: HashMap::new(),
+            chain_node_detail_fields: HashMap::new(),
+            chain_imported_block_sample: HashMap::new(),
+            chain_max_located_nodes: HashMap::new(),
+            chain_recent_blocks_len: HashMap::new(),
+            chain_expected_validators: HashMap::new(),
+            private_chains: HashSet::new(),
+            chain_expected_block_times: HashMap::new(),
+            empty_chain_ttl: Duration::ZERO,
+            empty_chains: HashMap::new(),
+            under_load: false,
+            node_groups: Arc::new(NodeGroups::default()),
+            stable_node_ids: false,
+            first_seen: None,
         }
     }
 
-    pub fn iter_chains(&self) -> impl Iterator<Item = StateChain<'_>> {
-        self.chains
-            .iter()
-            .map(move |(_, chain)| StateChain { chain })
+    /// Set how long chains are kept hold of once their last node leaves, so that their
+    /// history survives nodes briefly disconnecting. Zero removes them straight away.
+    pub fn set_empty_chain_ttl(&mut self, empty_chain_ttl: Duration) {
+        self.empty_chain_ttl = empty_chain_ttl;
     }
 
-    pub fn get_chain_by_node_id(&self, node_id: NodeId) -> Option<StateChain<'_>> {
-        self.chains.get(node_id.0).map(|chain| StateChain { chain })
+    /// Set how many nodes that report no (or a zero) genesis hash, but are otherwise fine,
+    /// are put on a chain of their own so that they can be seen. 0 rejects them instead.
+    pub fn set_max_unidentified_nodes(&mut self, max_unidentified_nodes: usize) {
+        self.max_unidentified_nodes = max_unidentified_nodes;
     }
 
-    pub fn get_chain_by_genesis_hash(&self, genesis_hash: &BlockHash) -> Option<StateChain<'_>> {
-        self.chains_by_genesis_hash
+    /// Set the labels that newly created chains with the given genesis hashes
+    /// will always use, regardless of the labels that their nodes report.
+    pub fn set_chain_label_overrides(&mut self, overrides: HashMap<BlockHash, Box<str>>) {
+        self.chain_label_overrides = overrides;
+    }
+
+    /// Set how frontends should show newly created chains with the given genesis hashes.
+    pub fn set_chain_themes(&mut self, chain_themes: HashMap<BlockHash, Theme>) {
+        self.chain_themes = chain_themes;
+    }
+
+    /// Set whether newly created chains expose the IP address, sysinfo and hwbench of their
+    /// nodes to feeds, either by default or for the chains with the given genesis hashes.
+    pub fn set_expose_node_details(
+        &mut self,
+        expose_node_details: bool,
+        chain_expose_node_details: HashMap<BlockHash, bool>,
+    ) {
+        self.chain_config.expose_node_details = expose_node_details;
+        self.chain_expose_node_details = chain_expose_node_details;
+    }
+
+    /// Are the IP address, sysinfo and hwbench of nodes on the chain with the given
+    /// genesis hash exposed to feeds (or will they be, once the chain exists)?
+    pub fn expose_node_details(&self, genesis_hash: &BlockHash) -> bool {
+        if let Some(chain) = self.get_chain_by_genesis_hash(genesis_hash) {
+            return chain.expose_node_details();
+        }
+        self.chain_expose_node_details
             .get(genesis_hash)
-            .and_then(|&chain_id| self.chains.get(chain_id))
-            .map(|chain| StateChain { chain })
+            .copied()
+            .unwrap_or(self.chain_config.expose_node_details)
     }
 
-    pub fn add_node(
+    /// Set which details of nodes on newly created chains feeds are allowed to be sent,
+    /// either by default or for the chains with the given genesis hashes.
+    pub fn set_node_detail_fields(
         &mut self,
-        genesis_hash: BlockHash,
-        node_details: NodeDetails,
-    ) -> AddNodeResult<'_> {
-        if self.denylist.contains(&*node_details.chain) {
-            return AddNodeResult::ChainOnDenyList;
+        node_detail_fields: NodeDetailFields,
+        chain_node_detail_fields: HashMap<BlockHash, NodeDetailFields>,
+    ) {
+        self.chain_config.node_detail_fields = node_detail_fields;
+        self.chain_node_detail_fields = chain_node_detail_fields;
+    }
+
+    /// Which details of nodes on the chain with the given genesis hash are feeds sent
+    /// (or will they be, once the chain exists)?
+    pub fn node_detail_fields(&self, genesis_hash: &BlockHash) -> NodeDetailFields {
+        if let Some(chain) = self.get_chain_by_genesis_hash(genesis_hash) {
+            return chain.node_detail_fields();
         }
+        let fields = self.allowed_node_detail_fields(genesis_hash);
+        match self.expose_node_details(genesis_hash) {
+            true => fields,
+            false => fields.public(),
+        }
+    }
 
-        // Get the chain ID, creating a new empty chain if one doesn't exist.
-        // If we create a chain here, we are expecting that it will allow at
-        // least this node to be added, because we don't currently try and clean it up
-        // if the add fails.
-        let chain_id = match self.chains_by_genesis_hash.get(&genesis_hash) {
-            Some(id) => *id,
-            None => {
-                let max_nodes = match chain::is_first_party_network(&genesis_hash) {
-                    true => usize::MAX,
-                    false => self.max_third_party_nodes,
-                };
-                let chain_id = self.chains.add(Chain::new(genesis_hash, max_nodes));
-                self.chains_by_genesis_hash.insert(genesis_hash, chain_id);
-                chain_id
-            }
-        };
+    /// Which details of nodes new chains with the given genesis hash allow feeds to be
+    /// sent, if node details are exposed.
+    fn allowed_node_detail_fields(&self, genesis_hash: &BlockHash) -> NodeDetailFields {
+        self.chain_node_detail_fields
+            .get(genesis_hash)
+            .copied()
+            .unwrap_or(self.chain_config.node_detail_fields)
+    }
 
-        // Get the chain.
-        let chain = self.chains.get_mut(chain_id).expect(
-            "should be known to exist after the above (unless chains_by_genesis_hash out of sync)",
-        );
+    /// Set how many nodes per block height feeds are told have imported a block on newly
+    /// created chains, either by default or for the chains with the given genesis hashes.
+    /// 0 means that feeds are told about every imported block.
+    pub fn set_imported_block_sample(
+        &mut self,
+        imported_block_sample: usize,
+        chain_imported_block_sample: HashMap<BlockHash, usize>,
+    ) {
+        self.chain_config.imported_block_sample = imported_block_sample;
+        self.chain_imported_block_sample = chain_imported_block_sample;
+    }
 
-        let node = Node::new(node_details);
-        let old_chain_label = chain.label().into();
+    /// Set how many nodes newly created chains can have for those added to be located,
+    /// either by default or for the chains with the given genesis hashes. 0 means that
+    /// every node is located.
+    pub fn set_max_located_nodes(
+        &mut self,
+        max_located_nodes: usize,
+        chain_max_located_nodes: HashMap<BlockHash, usize>,
+    ) {
+        self.chain_config.max_located_nodes = max_located_nodes;
+        self.chain_max_located_nodes = chain_max_located_nodes;
+    }
 
-        match chain.add_node(node) {
-            chain::AddNodeResult::Overquota => AddNodeResult::ChainOverQuota,
-            chain::AddNodeResult::Added { id, chain_renamed } => {
-                let chain = &*chain;
+    /// Set how long regenerating the stats of newly created chains can take before it's
+    /// logged, and how often their stats are reconciled.
+    pub fn set_stats_timings(&mut self, stats_timings: StatsTimings) {
+        self.chain_config.stats_timings = stats_timings;
+    }
 
-                AddNodeResult::NodeAddedToChain(NodeAddedToChain {
-                    id: NodeId(chain_id, id),
-                    node: chain.get_node(id).expect("node added above"),
-                    old_chain_label,
-                    new_chain_label: chain.label(),
-                    chain_node_count: chain.node_count(),
-                    has_chain_label_changed: chain_renamed,
-                })
-            }
-        }
+    /// Set how far the finalized block of newly created chains can fall behind
+    /// their best block before feeds are alerted.
+    pub fn set_finality_gap_threshold(&mut self, finality_gap_threshold: u64) {
+        self.chain_config.finality_gap_threshold = finality_gap_threshold;
     }
 
-    /// Remove a node
-    pub fn remove_node(&mut self, NodeId(chain_id, chain_node_id): NodeId) -> Option<RemovedNode> {
-        let chain = self.chains.get_mut(chain_id)?;
-        let old_chain_label = chain.label().into();
+    /// Set how far the finalized block of nodes on newly created chains can fall behind
+    /// that of their chain before they're counted as lagging finality.
+    pub fn set_finality_lag_threshold(&mut self, finality_lag_threshold: u64) {
+        self.chain_config.finality_lag_threshold = finality_lag_threshold;
+    }
 
-        // Actually remove the node
-        let remove_result = chain.remove_node(chain_node_id);
+    /// Set how far (in ms) the clocks of nodes on newly created chains can be ahead of
+    /// or behind ours before they're counted as having clock skew.
+    pub fn set_clock_skew_threshold(&mut self, clock_skew_threshold: u64) {
+        self.chain_config.clock_skew_threshold = clock_skew_threshold;
+    }
 
-        // Get updated chain details.
-        let new_chain_label: Box<str> = chain.label().into();
-        let chain_node_count = chain.node_count();
-        let chain_genesis_hash = chain.genesis_hash();
+    /// Set how far (in metres) the locations of nodes on newly created chains need to
+    /// move for the new location to be kept, rather than ignored as jitter.
+    pub fn set_min_location_change(&mut self, min_location_change: u64) {
+        self.chain_config.min_location_change = min_location_change;
+    }
 
-        // Is the chain empty? Remove if so and clean up indexes to it
-        if chain_node_count == 0 {
-            let genesis_hash = chain.genesis_hash();
-            self.chains_by_genesis_hash.remove(&genesis_hash);
-            self.chains.remove(chain_id);
-        }
+    /// Set how few bytes can be free on the disks of nodes on newly created chains before
+    /// they're counted as low on disk space.
+    pub fn set_low_disk_threshold(&mut self, low_disk_threshold: u64) {
+        self.chain_config.low_disk_threshold = low_disk_threshold;
+    }
 
-        Some(RemovedNode {
-            old_chain_label,
-            new_chain_label,
-            chain_node_count,
-            chain_genesis_hash,
-            has_chain_label_changed: remove_result.chain_renamed,
-        })
+    /// Set what nodes on newly created chains that don't say whether they're full nodes
+    /// or light clients are counted as.
+    pub fn set_unreported_node_role(&mut self, unreported_node_role: UnreportedNodeRole) {
+        self.chain_config.unreported_node_role = unreported_node_role;
     }
 
-    /// Attempt to update the best block seen, given a node and block.
-    pub fn update_node(
+    /// Set how many validators the chains with the given genesis hashes are expected to
+    /// have, and how far short of that they can fall before feeds are alerted.
+    pub fn set_expected_validators(
         &mut self,
-        NodeId(chain_id, chain_node_id): NodeId,
-        payload: Payload,
-        feed: &mut FeedMessageSerializer,
-        expose_node_details: bool,
+        chain_expected_validators: HashMap<BlockHash, u64>,
+        validator_shortfall_threshold: u64,
     ) {
-        let chain = match self.chains.get_mut(chain_id) {
-            Some(chain) => chain,
-            None => {
-                log::error!("Cannot find chain for node with ID {:?}", chain_id);
-                return;
-            }
-        };
+        self.chain_expected_validators = chain_expected_validators;
+        self.chain_config.validator_shortfall_threshold = validator_shortfall_threshold;
+    }
 
-        chain.update_node(chain_node_id, payload, feed, expose_node_details)
+    /// Make the chains with the given genesis hashes private, so that feeds aren't told
+    /// about them unless they subscribe to them directly.
+    pub fn set_private_chains(&mut self, private_chains: HashSet<BlockHash>) {
+        self.private_chains = private_chains;
     }
 
-    /// Update the location for a node. Return `false` if the node was not found.
-    pub fn update_node_location(
+    /// Set how long (in ms) blocks are expected to take on the chains with the given
+    /// genesis hashes.
+    pub fn set_expected_block_times(
         &mut self,
-        NodeId(chain_id, chain_node_id): NodeId,
-        location: find_location::Location,
-    ) -> bool {
-        if let Some(chain) = self.chains.get_mut(chain_id) {
-            chain.update_node_location(chain_node_id, location)
-        } else {
-            false
-        }
+        chain_expected_block_times: HashMap<BlockHash, u64>,
+    ) {
+        self.chain_expected_block_times = chain_expected_block_times;
     }
-}
 
-/// When we ask for a chain, we get this struct back. This ensures that we have
-/// a consistent public interface, and don't expose methods on [`Chain`] that
-/// aren't really intended for use outside of [`State`] methods. Any modification
-/// of a chain needs to go through [`State`].
-pub struct StateChain<'a> {
-    chain: &'a Chain,
-}
+    /// Set how many hardware, stats and IO messages about their nodes newly created chains
+    /// can send feeds each second. 0 means that there's no limit.
+    pub fn set_feed_rate_limit(&mut self, feed_rate_limit: u32) {
+        self.chain_config.feed_rate_limit = feed_rate_limit;
+    }
 
-impl<'a> StateChain<'a> {
-    pub fn label(&self) -> &'a str {
-        self.chain.label()
+    /// Set how many blocks the import queues of nodes on newly created chains need to keep
+    /// having in them to count as backed up. 0 means that they never do.
+    pub fn set_import_queue_backed_up_depth(&mut self, import_queue_backed_up_depth: u64) {
+        self.chain_config.import_queue_backed_up_depth = import_queue_backed_up_depth;
     }
-    pub fn genesis_hash(&self) -> BlockHash {
-        self.chain.genesis_hash()
+
+    /// Set what percentage of the blocks they try to import nodes on newly created chains
+    /// need to import to not count as unhealthy. 0 means that none do.
+    pub fn set_min_import_success_percent(&mut self, min_import_success_percent: u64) {
+        self.chain_config.min_import_success_percent = min_import_success_percent;
     }
-    pub fn node_count(&self) -> usize {
-        self.chain.node_count()
+
+    /// Set the label that newly created chains use while their nodes haven't reported a
+    /// useful one, where `{hash}` is replaced by the start of the chain's genesis hash and
+    /// `{genesis_hash}` by all of it.
+    pub fn set_unlabeled_chain_format(&mut self, format: Box<str>) {
+        self.chain_config.unlabeled_format = format;
     }
-    pub fn best_block(&self) -> &'a Block {
-        self.chain.best_block()
+
+    /// Set how long (in ms) a block author can go without producing a block before it's
+    /// counted as inactive.
+    pub fn set_block_author_inactive_after(&mut self, inactive_after: u64) {
+        self.chain_config.block_author_inactive_after = inactive_after;
     }
-    pub fn timestamp(&self) -> Timestamp {
-        self.chain.timestamp().unwrap_or(0)
+
+    /// Set how far ahead of a chain's best block a node's best block can be before it's
+    /// ignored as implausible. 0 means that they never are.
+    pub fn set_max_best_block_lead(&mut self, max_best_block_lead: u64) {
+        self.chain_config.max_best_block_lead = max_best_block_lead;
     }
-    pub fn average_block_time(&self) -> Option<u64> {
-        self.chain.average_block_time()
+
+    /// Set how much (in percent) the average peer count of a chain's nodes has to change
+    /// before feeds are told about it.
+    pub fn set_average_peers_change_percent(&mut self, percent: u64) {
+        self.chain_config.average_peers_change_percent = percent;
     }
-    pub fn finalized_block(&self) -> &'a Block {
-        self.chain.finalized_block()
+
+    /// Set whether nodes that are added are given IDs, derived from their network IDs,
+    /// that feeds can use to recognise them when they reconnect.
+    pub fn set_stable_node_ids(&mut self, stable_node_ids: bool) {
+        self.stable_node_ids = stable_node_ids;
     }
-    pub fn nodes_slice(&self) -> &[Option<Node>] {
-        self.chain.nodes_slice()
+
+    /// Set where to keep track of when nodes were first ever seen, by their stable IDs.
+    /// Nodes are only looked up if they're given stable IDs.
+    pub fn set_first_seen_store(&mut self, first_seen: Option<Arc<Mutex<FirstSeenStore>>>) {
+        self.first_seen = first_seen;
     }
-    pub fn stats(&self) -> &ChainStats {
-        self.chain.stats()
+
+    /// Set how many votes towards the label of newly created chains each validator gets,
+    /// where every other node gets one.
+    pub fn set_validator_label_weight(&mut self, validator_label_weight: usize) {
+        self.chain_config.validator_label_weight = validator_label_weight;
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use common::node_types::NetworkId;
+    /// Set when nodes on newly created chains count as syncing, and when feeds are told
+    /// that most of them are.
+    pub fn set_syncing_majority(&mut self, syncing_majority: SyncingMajority) {
+        self.chain_config.syncing_majority = syncing_majority;
+    }
 
-    fn node(name: &str, chain: &str) -> NodeDetails {
-        NodeDetails {
-            chain: chain.into(),
-            name: name.into(),
-            implementation: "Bar".into(),
-            target_arch: Some("x86_64".into()),
-            target_os: Some("linux".into()),
-            target_env: Some("env".into()),
-            version: "0.1".into(),
-            validator: None,
-            network_id: NetworkId::new(),
-            startup_time: None,
-            sysinfo: None,
-            ip: None,
-        }
+    /// Set how much each signal counts towards the health scores of newly created chains.
+    pub fn set_health_weights(&mut self, health_weights: HealthWeights) {
+        self.chain_config.health_weights = health_weights;
     }
 
-    #[test]
-    fn adding_a_node_returns_expected_response() {
-        let mut state = State::new(None, 1000);
+    /// Set when feeds are alerted about newly created chains suddenly losing lots of nodes.
+    pub fn set_node_count_drops(&mut self, node_count_drops: NodeCountDrops) {
+        self.chain_config.node_count_drops = node_count_drops;
+    }
 
-        let chain1_genesis = BlockHash::from_low_u64_be(1);
+    /// Set when the label shown for newly created chains follows the one that their nodes
+    /// most commonly use.
+    pub fn set_label_debounce(&mut self, label_debounce: LabelDebounce) {
+        self.chain_config.label_debounce = label_debounce;
+    }
 
-        let add_result = state.add_node(chain1_genesis, node("A", "Chain One"));
+    /// Set how many nodes newly created chains need before feeds are told about them.
+    pub fn set_min_listed_nodes(&mut self, min_listed_nodes: usize) {
+        self.chain_config.min_listed_nodes = min_listed_nodes;
+    }
 
-        let add_node_result = match add_result {
-            AddNodeResult::ChainOnDenyList => panic!("Chain not on deny list"),
-            AddNodeResult::ChainOverQuota => panic!("Chain not Overquota"),
-            AddNodeResult::NodeAddedToChain(details) => details,
-        };
+    /// Set how high the best block of newly created chains has to get before feeds are
+    /// told about them.
+    pub fn set_min_listed_height(&mut self, min_listed_height: u64) {
+        self.chain_config.min_listed_height = min_listed_height;
+    }
 
-        assert_eq!(add_node_result.id, NodeId(0.into(), 0.into()));
-        assert_eq!(&*add_node_result.old_chain_label, "");
-        assert_eq!(&*add_node_result.new_chain_label, "Chain One");
-        assert_eq!(add_node_result.chain_node_count, 1);
-        assert_eq!(add_node_result.has_chain_label_changed, true);
+    /// Set how much the bandwidth of nodes on newly created chains has to change by
+    /// before feeds are told.
+    pub fn set_hardware_changes(&mut self, hardware_changes: HardwareChanges) {
+        self.chain_config.hardware_changes = hardware_changes;
+    }
 
-        let add_result = state.add_node(chain1_genesis, node("A", "Chain One"));
+    /// Set how far over quota newly created chains go while nodes reconnect after
+    /// a mass disconnect.
+    pub fn set_quota_burst(&mut self, quota_burst: QuotaBurst) {
+        self.chain_config.quota_burst = quota_burst;
+    }
 
-        let add_node_result = match add_result {
-            AddNodeResult::ChainOnDenyList => panic!("Chain not on deny list"),
-            AddNodeResult::ChainOverQuota => panic!("Chain not Overquota"),
-            AddNodeResult::NodeAddedToChain(details) => details,
-        };
+    /// Set how many nodes any one operator can have on each newly created chain.
+    pub fn set_operator_cap(&mut self, operator_cap: OperatorCap) {
+        self.chain_config.operator_cap = operator_cap;
+    }
 
-        assert_eq!(add_node_result.id, NodeId(0.into(), 1.into()));
-        assert_eq!(&*add_node_result.old_chain_label, "Chain One");
-        assert_eq!(&*add_node_result.new_chain_label, "Chain One");
-        assert_eq!(add_node_result.chain_node_count, 2);
-        assert_eq!(add_node_result.has_chain_label_changed, false);
+    /// Set whether newly created chains only advance their best block along the
+    /// branch that their finalized block is on.
+    pub fn set_prefer_finalized_branch(&mut self, prefer_finalized_branch: bool) {
+        self.chain_config.prefer_finalized_branch = prefer_finalized_branch;
     }
 
-    #[test]
-    fn adding_and_removing_nodes_updates_chain_label_mapping() {
-        let mut state = State::new(None, 1000);
+    /// Set how the operators of nodes on newly created chains are found in their names.
+    pub fn set_operator_pattern(&mut self, operator_pattern: NodeOperatorPattern) {
+        self.chain_config.operator_pattern = operator_pattern;
+    }
+
+    /// Set how many distinct values of each stat newly created chains count separately.
+    pub fn set_cardinality_limits(&mut self, cardinality_limits: CardinalityLimits) {
+        self.chain_config.cardinality_limits = cardinality_limits;
+    }
+
+    /// Set the scores that nodes on newly created chains are split into hardware tiers by.
+    pub fn set_hardware_tiers(&mut self, hardware_tiers: HardwareTiers) {
+        self.chain_config.hardware_tiers = hardware_tiers;
+    }
+
+    /// Set how long nodes on newly created chains need to have been connected for to move
+    /// up a connection tier.
+    pub fn set_connection_tiers(&mut self, connection_tiers: ConnectionTiers) {
+        self.chain_config.connection_tiers = connection_tiers;
+    }
+
+    /// Set how many of their most recent best blocks newly created chains keep hold of,
+    /// either by default or for the chains with the given genesis hashes.
+    pub fn set_recent_blocks_len(
+        &mut self,
+        recent_blocks_len: usize,
+        chain_recent_blocks_len: HashMap<BlockHash, usize>,
+    ) {
+        self.chain_config.recent_blocks_len = recent_blocks_len;
+        self.chain_recent_blocks_len = chain_recent_blocks_len;
+    }
+
+    /// Set how many of their most recent average block times newly created chains keep
+    /// hold of.
+    pub fn set_block_time_history_len(&mut self, block_time_history_len: usize) {
+        self.chain_config.block_time_history_len = block_time_history_len;
+    }
+
+    /// Set how many of the nodes removed from them most recently newly created chains
+    /// keep track of.
+    pub fn set_removed_nodes_len(&mut self, removed_nodes_len: usize) {
+        self.chain_config.removed_nodes_len = removed_nodes_len;
+    }
+
+    /// Set how many of their most recent significant events newly created chains keep
+    /// track of.
+    pub fn set_recent_events_len(&mut self, recent_events_len: usize) {
+        self.chain_config.recent_events_len = recent_events_len;
+    }
+
+    /// Set whether newly created chains notice nodes being added with the same name as
+    /// others on them.
+    pub fn set_flag_name_collisions(&mut self, flag_name_collisions: bool) {
+        self.chain_config.flag_name_collisions = flag_name_collisions;
+    }
+
+    /// Set how recently (in ms) the process of a node has to have started for it to count
+    /// as recently restarted.
+    pub fn set_recent_restart_window(&mut self, recent_restart_window: u64) {
+        self.chain_config.recent_restart_window = recent_restart_window;
+    }
+
+    /// Set when nodes on newly created chains become stale and fresh again.
+    pub fn set_stale_timeouts(&mut self, stale_timeouts: StaleTimeouts) {
+        self.chain_config.stale_timeouts = stale_timeouts;
+    }
+
+    /// Set how much history each rolling metric of newly created chains is worked out over.
+    pub fn set_rolling_windows(&mut self, windows: RollingWindows) {
+        self.chain_config.windows = windows;
+    }
+
+    /// Set how many block times newly created chains need before they give out an average
+    /// block time.
+    pub fn set_block_time_warm_up(&mut self, block_time_warm_up: usize) {
+        self.chain_config.block_time_warm_up = block_time_warm_up;
+    }
+
+    /// Set the most that the best block of newly created chains can advance by at once
+    /// for the time it took to count as a block time.
+    pub fn set_max_block_time_step(&mut self, max_block_time_step: Option<u64>) {
+        self.chain_config.max_block_time_step = max_block_time_step;
+    }
+
+    /// Set the blocklist that the names of newly added nodes are checked against,
+    /// and what to do with matching nodes. Nodes that have already been added
+    /// are not affected.
+    pub fn set_node_name_blocklist(
+        &mut self,
+        blocklist: Arc<NodeNameBlocklist>,
+        action: BlockedNodeNameAction,
+    ) {
+        self.node_name_blocklist = blocklist;
+        self.blocked_node_name_action = action;
+    }
+
+    /// Set what to do with newly added nodes that report the same network ID as
+    /// another node on their chain.
+    pub fn set_duplicate_node_policy(&mut self, policy: DuplicateNodePolicy) {
+        self.duplicate_node_policy = policy;
+    }
+
+    /// What's done with newly added nodes that report the same network ID as
+    /// another node on their chain?
+    pub fn duplicate_node_policy(&self) -> DuplicateNodePolicy {
+        self.duplicate_node_policy
+    }
+
+    /// Set what to do with newly added nodes that look to be the same node as one already
+    /// on their chain, connecting again before its old connection has gone away. These
+    /// aren't subject to the duplicate node policy.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect_policy = policy;
+    }
+
+    /// Set the oldest (major, minor) client version that newly added nodes on the chains
+    /// with the given genesis hashes can run, what to do with nodes running older versions,
+    /// and how to treat nodes whose version can't be parsed.
+    pub fn set_min_versions(
+        &mut self,
+        chain_min_versions: HashMap<BlockHash, (u32, u32)>,
+        outdated_version_policy: OutdatedVersionPolicy,
+        unparseable_version_policy: UnparseableVersionPolicy,
+    ) {
+        self.chain_min_versions = chain_min_versions;
+        self.outdated_version_policy = outdated_version_policy;
+        self.unparseable_version_policy = unparseable_version_policy;
+    }
+
+    /// Is the version given older than the minimum for the chain with the given genesis
+    /// hash? Versions are compared on their major and minor parts, as in the chain stats.
+    fn is_outdated_version(&self, genesis_hash: &BlockHash, version: &str) -> bool {
+        let Some(min_version) = self.chain_min_versions.get(genesis_hash) else {
+            return false;
+        };
+        match major_minor_version(version) {
+            Some(version) => version < *min_version,
+            None => self.unparseable_version_policy == UnparseableVersionPolicy::Outdated,
+        }
+    }
+
+    /// Set the genesis hashes of networks that newly added nodes are not allowed
+    /// to be on. Nodes that have already been added are not affected.
+    pub fn set_network_denylist(&mut self, denylist: Arc<NetworkDenylist>) {
+        self.network_denylist = denylist;
+    }
+
+    /// How many chains are we keeping track of?
+    pub fn chain_count(&self) -> usize {
+        self.chains.len()
+    }
+
+    pub fn iter_chains(&self) -> impl Iterator<Item = StateChain<'_>> {
+        self.chains
+            .iter()
+            .map(move |(_, chain)| StateChain { chain })
+    }
+
+    pub fn get_chain_by_node_id(&self, node_id: NodeId) -> Option<StateChain<'_>> {
+        self.chains.get(node_id.0).map(|chain| StateChain { chain })
+    }
+
+    pub fn get_chain_by_genesis_hash(&self, genesis_hash: &BlockHash) -> Option<StateChain<'_>> {
+        self.chains_by_genesis_hash
+            .get(genesis_hash)
+            .and_then(|&chain_id| self.chains.get(chain_id))
+            .map(|chain| StateChain { chain })
+    }
+
+    pub fn add_node(
+        &mut self,
+        genesis_hash: BlockHash,
+        mut node_details: NodeDetails,
+    ) -> AddNodeResult<'_> {
+        // Malformed hashes are rejected when parsing node messages, but a zero
+        // hash parses fine and would otherwise create a junk chain:
+        if genesis_hash.is_zero() && self.max_unidentified_nodes == 0 {
+            return AddNodeResult::InvalidGenesisHash;
+        }
+
+        if self.denylist.contains(&*node_details.chain) {
+            return AddNodeResult::ChainOnDenyList;
+        }
+
+        if self.network_denylist.is_denied(&genesis_hash) {
+            log::debug!("Rejecting node on denied network {genesis_hash:?}");
+            return AddNodeResult::ChainOnDenyList;
+        }
+
+        if self.node_name_blocklist.is_blocked(&node_details.name) {
+            match self.blocked_node_name_action {
+                BlockedNodeNameAction::Reject => return AddNodeResult::NodeNameOnBlocklist,
+                BlockedNodeNameAction::Replace => {
+                    node_details.name = BLOCKED_NODE_NAME_PLACEHOLDER.into()
+                }
+            }
+        }
+
+        if self.outdated_version_policy == OutdatedVersionPolicy::Reject
+            && self.is_outdated_version(&genesis_hash, &node_details.version)
+        {
+            log::debug!(
+                "Rejecting node '{}' on {genesis_hash:?}: version {} is outdated",
+                node_details.name,
+                node_details.version
+            );
+            return AddNodeResult::OutdatedVersion;
+        }
+
+        let mut node = Node::new(node_details);
+        let existing_chain_id = self.chains_by_genesis_hash.get(&genesis_hash).copied();
+        if let Some(chain) = existing_chain_id.and_then(|id| self.chains.get(id)) {
+            let details = node.details();
+            if chain.get_reconnect_of(&node).is_some() {
+                if self.reconnect_policy == ReconnectPolicy::Reject {
+                    log::info!(
+                        "Rejecting node '{}' on {genesis_hash:?}: it's already connected",
+                        details.name
+                    );
+                    return AddNodeResult::DuplicateNode;
+                }
+            } else if self.duplicate_node_policy == DuplicateNodePolicy::Reject
+                && !details.network_id.is_empty()
+                && chain
+                    .get_node_id_by_network_id(&details.network_id)
+                    .is_some()
+            {
+                return AddNodeResult::DuplicateNode;
+            }
+
+            if chain.is_operator_at_cap(&details.name) {
+                log::info!(
+                    "Rejecting node '{}' on {genesis_hash:?}: its operator has too many nodes",
+                    details.name
+                );
+                return AddNodeResult::OperatorOverCap;
+            }
+        }
+
+        // Get the chain ID, creating a new empty chain if one doesn't exist.
+        // If we create a chain here, we are expecting that it will allow at
+        // least this node to be added, because we don't currently try and clean it up
+        // if the add fails.
+        let chain_id = match existing_chain_id {
+            Some(id) => id,
+            None => self.add_chain(self.new_chain(genesis_hash)),
+        };
+
+        if self.stable_node_ids {
+            node.assign_stable_id();
+        }
+        if let (Some(first_seen), Some(stable_id)) = (&self.first_seen, node.stable_id()) {
+            let first_seen_ever = first_seen.lock().record(stable_id, time::now());
+            node.set_first_seen_ever(first_seen_ever);
+        }
+        match self.add_node_to_chain(chain_id, node) {
+            Some(added) => AddNodeResult::NodeAddedToChain(added),
+            None => AddNodeResult::ChainOverQuota,
+        }
+    }
+
+    /// Move a node to the chain with the given genesis hash, keeping hold of its
+    /// state (when it connected, when it was last seen and so on) rather than
+    /// removing it and adding it again.
+    pub fn move_node(
+        &mut self,
+        NodeId(chain_id, chain_node_id): NodeId,
+        genesis_hash: BlockHash,
+    ) -> MoveNodeResult<'_> {
+        if genesis_hash.is_zero() && self.max_unidentified_nodes == 0 {
+            return MoveNodeResult::InvalidGenesisHash;
+        }
+
+        if self.network_denylist.is_denied(&genesis_hash) {
+            return MoveNodeResult::ChainOnDenyList;
+        }
+
+        match self.chains.get(chain_id) {
+            Some(chain) if chain.get_node(chain_node_id).is_some() => {
+                if chain.genesis_hash() == genesis_hash {
+                    return MoveNodeResult::AlreadyOnChain;
+                }
+            }
+            _ => return MoveNodeResult::NodeNotFound,
+        }
+
+        // Make sure that the chain has room before taking the node off of its
+        // current chain, so that we never end up with nowhere to put it.
+        let new_chain = match self.chains_by_genesis_hash.get(&genesis_hash) {
+            Some(&id) => {
+                if self.chains.get(id).is_none_or(|chain| chain.is_overquota()) {
+                    return MoveNodeResult::ChainOverQuota;
+                }
+                None
+            }
+            None => {
+                let chain = self.new_chain(genesis_hash);
+                if chain.is_overquota() {
+                    return MoveNodeResult::ChainOverQuota;
+                }
+                Some(chain)
+            }
+        };
+
+        let chain = self
+            .chains
+            .get_mut(chain_id)
+            .expect("chain exists; checked above");
+        let old_chain_label: Box<str> = chain.label().into();
+        let was_chain_listed = chain.is_listed();
+        let node = chain
+            .extract_node(chain_node_id)
+            .expect("node exists; checked above");
+        let new_chain_label: Box<str> = chain.label().into();
+        let removed = RemovedNode {
+            chain_node_count: chain.node_count(),
+            has_chain_label_changed: old_chain_label != new_chain_label,
+            old_chain_label,
+            chain_genesis_hash: chain.genesis_hash(),
+            new_chain_label,
+            node_count_drop: None,
+            was_chain_listed,
+            is_chain_listed: chain.is_listed(),
+        };
+        if removed.chain_node_count == 0 {
+            self.chain_emptied(chain_id);
+        }
+
+        let new_chain_id = match new_chain {
+            Some(chain) => self.add_chain(chain),
+            None => self.chains_by_genesis_hash[&genesis_hash],
+        };
+        let added = self
+            .add_node_to_chain(new_chain_id, node)
+            .expect("chain has room; checked above");
+
+        MoveNodeResult::NodeMoved(NodeMoved { removed, added })
+    }
+
+    /// Create (but don't add) a new, empty chain with the given genesis hash.
+    fn new_chain(&self, genesis_hash: BlockHash) -> Chain {
+        let max_nodes = if genesis_hash.is_zero() {
+            self.max_unidentified_nodes
+        } else if chain::is_first_party_network(&genesis_hash) {
+            usize::MAX
+        } else {
+            self.max_third_party_nodes
+        };
+        let config = ChainConfig {
+            expose_node_details: self.expose_node_details(&genesis_hash),
+            node_detail_fields: self.allowed_node_detail_fields(&genesis_hash),
+            imported_block_sample: self
+                .chain_imported_block_sample
+                .get(&genesis_hash)
+                .copied()
+                .unwrap_or(self.chain_config.imported_block_sample),
+            max_located_nodes: self
+                .chain_max_located_nodes
+                .get(&genesis_hash)
+                .copied()
+                .unwrap_or(self.chain_config.max_located_nodes),
+            recent_blocks_len: self
+                .chain_recent_blocks_len
+                .get(&genesis_hash)
+                .copied()
+                .unwrap_or(self.chain_config.recent_blocks_len),
+            expected_validators: self.chain_expected_validators.get(&genesis_hash).copied(),
+            expected_block_time: self.chain_expected_block_times.get(&genesis_hash).copied(),
+            private: self.private_chains.contains(&genesis_hash),
+            theme: self
+                .chain_themes
+                .get(&genesis_hash)
+                .cloned()
+                .unwrap_or_default(),
+            ..self.chain_config.clone()
+        };
+        // Make it obvious that the unidentified chain isn't a real one:
+        let label_override = match self.chain_label_overrides.get(&genesis_hash) {
+            Some(label) => Some(label.clone()),
+            None if genesis_hash.is_zero() => Some(UNIDENTIFIED_CHAIN_LABEL.into()),
+            None => None,
+        };
+        let mut chain = Chain::new(genesis_hash, max_nodes, label_override, config);
+        chain.set_under_load(self.under_load);
+        chain
+    }
+
+    /// Start keeping track of a chain, returning its ID.
+    fn add_chain(&mut self, chain: Chain) -> ChainId {
+        let genesis_hash = chain.genesis_hash();
+        let chain_id = self.chains.add(chain);
+        self.chains_by_genesis_hash.insert(genesis_hash, chain_id);
+        chain_id
+    }
+
+    /// Remove a chain that has no nodes left, or keep hold of it for a while if
+    /// there's an empty chain TTL.
+    fn chain_emptied(&mut self, chain_id: ChainId) {
+        if self.empty_chain_ttl.is_zero() {
+            self.remove_chain(chain_id);
+        } else {
+            self.empty_chains.insert(chain_id, Instant::now());
+        }
+    }
+
+    /// Stop keeping track of a chain, returning its genesis hash.
+    fn remove_chain(&mut self, chain_id: ChainId) -> Option<BlockHash> {
+        let chain = self.chains.remove(chain_id)?;
+        let genesis_hash = chain.genesis_hash();
+        self.chains_by_genesis_hash.remove(&genesis_hash);
+        self.empty_chains.remove(&chain_id);
+        Some(genesis_hash)
+    }
+
+    /// Remove the chains that have had no nodes for longer than the empty chain TTL,
+    /// returning their genesis hashes.
+    pub fn remove_expired_empty_chains(&mut self, now: Instant) -> Vec<BlockHash> {
+        let expired: Vec<ChainId> = self
+            .empty_chains
+            .iter()
+            .filter(|(_, &empty_since)| {
+                now.saturating_duration_since(empty_since) >= self.empty_chain_ttl
+            })
+            .map(|(&chain_id, _)| chain_id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|chain_id| self.remove_chain(chain_id))
+            .collect()
+    }
+
+    /// Add a node to the chain with the given ID, returning `None` if it's over quota.
+    fn add_node_to_chain(&mut self, chain_id: ChainId, node: Node) -> Option<NodeAddedToChain<'_>> {
+        let is_outdated = self.chains.get(chain_id).is_some_and(|chain| {
+            self.is_outdated_version(&chain.genesis_hash(), &node.details().version)
+        });
+        let chain = self.chains.get_mut(chain_id).expect(
+            "should be known to exist by the caller (unless chains_by_genesis_hash out of sync)",
+        );
+        let old_chain_label = chain.label().into();
+        let was_chain_listed = chain.is_listed();
+
+        match chain.add_node(node) {
+            chain::AddNodeResult::Overquota => None,
+            chain::AddNodeResult::Added {
+                id,
+                chain_renamed,
+                relay_parent_changed,
+                duplicate_of,
+                is_reconnect,
+                name_collisions,
+                blocks_changed,
+            } => {
+                let chain = &*chain;
+                self.empty_chains.remove(&chain_id);
+
+                Some(NodeAddedToChain {
+                    id: NodeId(chain_id, id),
+                    node: chain.get_node(id).expect("node added above"),
+                    old_chain_label,
+                    new_chain_label: chain.label(),
+                    chain_node_count: chain.node_count(),
+                    has_chain_label_changed: chain_renamed,
+                    relay_parent: chain.relay_parent().copied(),
+                    has_relay_parent_changed: relay_parent_changed,
+                    chain_theme: chain.theme(),
+                    was_chain_listed,
+                    is_chain_listed: chain.is_listed(),
+                    duplicate_of: duplicate_of.map(|id| NodeId(chain_id, id)),
+                    is_reconnect,
+                    name_collisions,
+                    should_locate: chain.should_locate_added_node(),
+                    is_outdated,
+                    has_chain_blocks_changed: blocks_changed,
+                })
+            }
+        }
+    }
+
+    /// Find the ID of a node given the chain that it's on and its chain local ID.
+    pub fn get_node_id(&self, genesis_hash: &BlockHash, id: ChainNodeId) -> Option<NodeId> {
+        let chain_id = *self.chains_by_genesis_hash.get(genesis_hash)?;
+        let chain = self.chains.get(chain_id)?;
+        chain.get_node(id).map(|_| NodeId(chain_id, id))
+    }
+
+    /// Remove a node
+    pub fn remove_node(
+        &mut self,
+        NodeId(chain_id, chain_node_id): NodeId,
+        reason: RemovalReason,
+    ) -> Option<RemovedNode> {
+        let chain = self.chains.get_mut(chain_id)?;
+        let old_chain_label = chain.label().into();
+        let was_chain_listed = chain.is_listed();
+
+        // Actually remove the node
+        let remove_result = chain.remove_node(chain_node_id, reason);
+        let is_chain_listed = chain.is_listed();
+
+        // Get updated chain details.
+        let new_chain_label: Box<str> = chain.label().into();
+        let chain_node_count = chain.node_count();
+        let chain_genesis_hash = chain.genesis_hash();
+
+        // Is the chain empty? Remove it (now or later) if so
+        if chain_node_count == 0 {
+            self.chain_emptied(chain_id);
+        }
+
+        Some(RemovedNode {
+            old_chain_label,
+            new_chain_label,
+            chain_node_count,
+            chain_genesis_hash,
+            has_chain_label_changed: remove_result.chain_renamed,
+            node_count_drop: remove_result.node_count_drop,
+            was_chain_listed,
+            is_chain_listed,
+        })
+    }
+
+    /// Attempt to update the best block seen, given a node and block.
+    pub fn update_node(
+        &mut self,
+        NodeId(chain_id, chain_node_id): NodeId,
+        payload: Payload,
+        feed: &mut FeedMessageSerializer,
+    ) {
+        let chain = match self.chains.get_mut(chain_id) {
+            Some(chain) => chain,
+            None => {
+                log::error!("Cannot find chain for node with ID {:?}", chain_id);
+                return;
+            }
+        };
+
+        chain.update_node(chain_node_id, payload, feed)
+    }
+
+    /// Has the label of the chain that the node given is on changed since this was last
+    /// called, other than by adding or removing nodes?
+    pub fn take_chain_label_changed(&mut self, NodeId(chain_id, _): NodeId) -> bool {
+        self.chains
+            .get_mut(chain_id)
+            .is_some_and(|chain| chain.take_label_changed())
+    }
+
+    /// Has the chain that the node given is on become listed since this was last called,
+    /// because its best block got high enough?
+    pub fn take_chain_listed_by_height(&mut self, NodeId(chain_id, _): NodeId) -> bool {
+        self.chains
+            .get_mut(chain_id)
+            .is_some_and(|chain| chain.take_listed_by_height())
+    }
+
+    /// Hand back the alerts that feeds have been sent about the chain that the node given
+    /// is on since this was last called.
+    pub fn take_chain_alerts(&mut self, NodeId(chain_id, _): NodeId) -> Vec<ChainAlert> {
+        match self.chains.get_mut(chain_id) {
+            Some(chain) => chain.take_alerts(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Hand back the nodes on the same chain as the node given that have been stale
+    /// for long enough to be removed. They still need removing.
+    pub fn take_expired_nodes(&mut self, NodeId(chain_id, _): NodeId) -> Vec<NodeId> {
+        match self.chains.get_mut(chain_id) {
+            Some(chain) => chain
+                .take_expired_nodes()
+                .into_iter()
+                .map(|id| NodeId(chain_id, id))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Make a note that a node sent a message that couldn't be deserialized.
+    pub fn note_malformed_message(&mut self, NodeId(chain_id, _): NodeId) {
+        if let Some(chain) = self.chains.get_mut(chain_id) {
+            chain.note_malformed_message();
+        }
+    }
+
+    /// Set the groups that nodes belong to.
+    pub fn set_node_groups(&mut self, node_groups: Arc<NodeGroups>) {
+        self.node_groups = node_groups;
+    }
+
+    /// The groups that nodes belong to.
+    pub fn node_groups(&self) -> &NodeGroups {
+        &self.node_groups
+    }
+
+    /// Is the aggregator falling behind on messages?
+    pub fn is_under_load(&self) -> bool {
+        self.under_load
+    }
+
+    /// Let every chain know whether the aggregator is falling behind on messages, so that
+    /// they can regenerate their stats less often while it is. Returns `false` if nothing
+    /// changed.
+    pub fn set_under_load(&mut self, under_load: bool) -> bool {
+        if self.under_load == under_load {
+            return false;
+        }
+        self.under_load = under_load;
+        for (_, chain) in self.chains.iter_mut() {
+            chain.set_under_load(under_load);
+        }
+        true
+    }
+
+    /// Freeze or unfreeze the chain with the given genesis hash. Returns `None` if the
+    /// chain can't be found, or whether anything changed otherwise.
+    pub fn set_chain_frozen(&mut self, genesis_hash: &BlockHash, frozen: bool) -> Option<bool> {
+        let chain_id = self.chains_by_genesis_hash.get(genesis_hash)?;
+        let chain = self.chains.get_mut(*chain_id)?;
+        Some(chain.set_frozen(frozen))
+    }
+
+    /// Change how many nodes the chain with the given genesis hash can have. Returns `None`
+    /// if the chain can't be found, or the nodes now over its limit otherwise, most
+    /// recently connected first. Those nodes aren't removed.
+    pub fn set_chain_max_nodes(
+        &mut self,
+        genesis_hash: &BlockHash,
+        max_nodes: usize,
+    ) -> Option<Vec<NodeId>> {
+        let chain_id = *self.chains_by_genesis_hash.get(genesis_hash)?;
+        let chain = self.chains.get_mut(chain_id)?;
+        chain.set_max_nodes(max_nodes);
+        let over_quota = chain.nodes_over_quota();
+        Some(
+            over_quota
+                .into_iter()
+                .map(|id| NodeId(chain_id, id))
+                .collect(),
+        )
+    }
+
+    /// Compact a chain, returning `None` if it can't be found.
+    pub fn compact_chain(
+        &mut self,
+        genesis_hash: &BlockHash,
+        feed: &mut FeedMessageSerializer,
+    ) -> Option<CompactSummary> {
+        let chain_id = self.chains_by_genesis_hash.get(genesis_hash)?;
+        let chain = self.chains.get_mut(*chain_id)?;
+        Some(chain.compact(feed))
+    }
+
+    /// Metrics about each chain. Private chains are left out, since anyone can see metrics.
+    pub fn chain_metrics(&mut self) -> impl Iterator<Item = ChainMetrics<'_>> + '_ {
+        let chains = self
+            .chains
+            .iter_mut()
+            .filter(|(_, chain)| !chain.is_private());
+        chains.map(|(_, chain)| {
+            let messages_per_second = chain.messages_per_second();
+            ChainMetrics {
+                genesis_hash: chain.genesis_hash(),
+                label: chain.label(),
+                messages_per_second,
+                stats_regeneration_times: chain.stats_regeneration_times(),
+                malformed_messages: chain.malformed_messages(),
+                node_count: chain.node_count(),
+                best_finalized_gap: chain.best_finalized_gap(),
+                block_times: chain.block_times_histogram(),
+                latest_block: chain.recent_blocks().back().copied(),
+            }
+        })
+    }
+
+    /// Attach an annotation to a node, or clear it, telling feeds of its chain. Returns
+    /// `false` if the node can't be found.
+    pub fn annotate_node(
+        &mut self,
+        NodeId(chain_id, chain_node_id): NodeId,
+        annotation: Option<NodeAnnotation>,
+        feed: &mut FeedMessageSerializer,
+    ) -> bool {
+        self.chains
+            .get_mut(chain_id)
+            .is_some_and(|chain| chain.annotate_node(chain_node_id, annotation, feed))
+    }
+
+    /// Clear the annotations that have expired on a chain's nodes, telling its feeds.
+    pub fn expire_node_annotations(
+        &mut self,
+        genesis_hash: &BlockHash,
+        now: Timestamp,
+        feed: &mut FeedMessageSerializer,
+    ) {
+        let chain_id = self.chains_by_genesis_hash.get(genesis_hash).copied();
+        if let Some(chain) = chain_id.and_then(|id| self.chains.get_mut(id)) {
+            chain.expire_node_annotations(now, feed);
+        }
+    }
+
+    /// Update the location of a node, returning whether it changed.
+    pub fn update_node_location(
+        &mut self,
+        NodeId(chain_id, chain_node_id): NodeId,
+        location: find_location::Location,
+    ) -> bool {
+        if let Some(chain) = self.chains.get_mut(chain_id) {
+            chain.update_node_location(chain_node_id, location)
+        } else {
+            false
+        }
+    }
+}
+
+/// When we ask for a chain, we get this struct back. This ensures that we have
+/// a consistent public interface, and don't expose methods on [`Chain`] that
+/// aren't really intended for use outside of [`State`] methods. Any modification
+/// of a chain needs to go through [`State`].
+pub struct StateChain<'a> {
+    chain: &'a Chain,
+}
+
+impl<'a> StateChain<'a> {
+    pub fn label(&self) -> &'a str {
+        self.chain.label()
+    }
+    pub fn node_detail_fields(&self) -> NodeDetailFields {
+        self.chain.node_detail_fields()
+    }
+    pub fn genesis_hash(&self) -> BlockHash {
+        self.chain.genesis_hash()
+    }
+    pub fn node_count(&self) -> usize {
+        self.chain.node_count()
+    }
+    pub fn best_block(&self) -> &'a Block {
+        self.chain.best_block()
+    }
+    pub fn timestamp(&self) -> Timestamp {
+        self.chain.timestamp().unwrap_or(0)
+    }
+    pub fn average_block_time(&self) -> Option<u64> {
+        self.chain.average_block_time()
+    }
+    pub fn finalized_block(&self) -> &'a Block {
+        self.chain.finalized_block()
+    }
+    pub fn relay_parent(&self) -> Option<&'a RelayParent> {
+        self.chain.relay_parent()
+    }
+    pub fn theme(&self) -> &'a Theme {
+        self.chain.theme()
+    }
+    pub fn expose_node_details(&self) -> bool {
+        self.chain.expose_node_details()
+    }
+    pub fn recent_blocks(&self) -> &'a VecDeque<RecentBlock> {
+        self.chain.recent_blocks()
+    }
+    pub fn block_time_history(&self) -> &'a VecDeque<BlockTimePoint> {
+        self.chain.block_time_history()
+    }
+    pub fn removed_nodes(&self) -> &'a VecDeque<RemovedNodeRecord> {
+        self.chain.removed_nodes()
+    }
+    pub fn recent_events(&self) -> &'a VecDeque<ChainEventRecord> {
+        self.chain.recent_events()
+    }
+    pub fn block_time_samples(&self) -> Vec<u64> {
+        self.chain.block_time_samples()
+    }
+    pub fn first_seen(&self) -> Timestamp {
+        self.chain.first_seen()
+    }
+    pub fn is_listed(&self) -> bool {
+        self.chain.is_listed()
+    }
+    pub fn is_private(&self) -> bool {
+        self.chain.is_private()
+    }
+    pub fn get_node(&self, id: ChainNodeId) -> Option<&'a Node> {
+        self.chain.get_node(id)
+    }
+    pub fn get_node_by_network_id(&self, network_id: &str) -> Option<&'a Node> {
+        self.chain.get_node_by_network_id(network_id)
+    }
+    pub fn get_node_id_by_network_id(&self, network_id: &str) -> Option<ChainNodeId> {
+        self.chain.get_node_id_by_network_id(network_id)
+    }
+    pub fn nodes_slice(&self) -> &[Option<Node>] {
+        self.chain.nodes_slice()
+    }
+    pub fn node_slots(&self) -> DenseMapStats {
+        self.chain.node_slots()
+    }
+    pub fn stats(&self) -> &ChainStats {
+        self.chain.stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::node_types::NetworkId;
+
+    fn node(name: &str, chain: &str) -> NodeDetails {
+        NodeDetails {
+            chain: chain.into(),
+            name: name.into(),
+            implementation: "Bar".into(),
+            target_arch: Some("x86_64".into()),
+            target_os: Some("linux".into()),
+            target_env: Some("env".into()),
+            version: "0.1".into(),
+            validator: None,
+            network_id: NetworkId::new(),
+            startup_time: None,
+            sysinfo: None,
+            ip: None,
+            protocol_version: None,
+            relay_chain_genesis_hash: None,
+            para_id: None,
+            role: None,
+            custom: Default::default(),
+        }
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison, clippy::borrow_deref_ref)]
+    fn adding_a_node_returns_expected_response() {
+        let mut state = State::new(None, 1000);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+
+        let add_result = state.add_node(chain1_genesis, node("A", "Chain One"));
+
+        let add_node_result = match add_result {
+            AddNodeResult::ChainOnDenyList => panic!("Chain not on deny list"),
+            AddNodeResult::ChainOverQuota => panic!("Chain not Overquota"),
+            AddNodeResult::NodeNameOnBlocklist => panic!("Node name not on blocklist"),
+            AddNodeResult::InvalidGenesisHash => panic!("Genesis hash not invalid"),
+            AddNodeResult::DuplicateNode => panic!("Node not a duplicate"),
+            AddNodeResult::OperatorOverCap => panic!("Operator not over cap"),
+            AddNodeResult::OutdatedVersion => panic!("Version not outdated"),
+            AddNodeResult::NodeAddedToChain(details) => details,
+        };
+
+        assert_eq!(add_node_result.id, NodeId(0.into(), 0.into()));
+        assert_eq!(&*add_node_result.old_chain_label, "chain-0x000000");
+        assert_eq!(&*add_node_result.new_chain_label, "Chain One");
+        assert_eq!(add_node_result.chain_node_count, 1);
+        assert_eq!(add_node_result.has_chain_label_changed, true);
+
+        let add_result = state.add_node(chain1_genesis, node("A", "Chain One"));
+
+        let add_node_result = match add_result {
+            AddNodeResult::ChainOnDenyList => panic!("Chain not on deny list"),
+            AddNodeResult::ChainOverQuota => panic!("Chain not Overquota"),
+            AddNodeResult::NodeNameOnBlocklist => panic!("Node name not on blocklist"),
+            AddNodeResult::InvalidGenesisHash => panic!("Genesis hash not invalid"),
+            AddNodeResult::DuplicateNode => panic!("Node not a duplicate"),
+            AddNodeResult::OperatorOverCap => panic!("Operator not over cap"),
+            AddNodeResult::OutdatedVersion => panic!("Version not outdated"),
+            AddNodeResult::NodeAddedToChain(details) => details,
+        };
+
+        assert_eq!(add_node_result.id, NodeId(0.into(), 1.into()));
+        assert_eq!(&*add_node_result.old_chain_label, "Chain One");
+        assert_eq!(&*add_node_result.new_chain_label, "Chain One");
+        assert_eq!(add_node_result.chain_node_count, 2);
+        assert_eq!(add_node_result.has_chain_label_changed, false);
+    }
+
+    #[test]
+    fn adding_and_removing_nodes_updates_chain_label_mapping() {
+        let mut state = State::new(None, 1000);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let node_id0 = state
+            .add_node(chain1_genesis, node("A", "Chain One")) // 0
+            .unwrap_id();
+
+        assert_eq!(
+            state
+                .get_chain_by_node_id(node_id0)
+                .expect("Chain should exist")
+                .label(),
+            "Chain One"
+        );
+        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_some());
+
+        let node_id1 = state
+            .add_node(chain1_genesis, node("B", "Chain Two")) // 1
+            .unwrap_id();
+
+        // Chain name hasn't changed yet; "Chain One" as common as "Chain Two"..
+        assert_eq!(
+            state
+                .get_chain_by_node_id(node_id0)
+                .expect("Chain should exist")
+                .label(),
+            "Chain One"
+        );
+        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_some());
+
+        let node_id2 = state
+            .add_node(chain1_genesis, node("B", "Chain Two"))
+            .unwrap_id(); // 2
+
+        // Chain name has changed; "Chain Two" the winner now..
+        assert_eq!(
+            state
+                .get_chain_by_node_id(node_id0)
+                .expect("Chain should exist")
+                .label(),
+            "Chain Two"
+        );
+        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_some());
+
+        state
+            .remove_node(node_id1, RemovalReason::Disconnected)
+            .expect("Removal OK (id: 1)");
+        state
+            .remove_node(node_id2, RemovalReason::Disconnected)
+            .expect("Removal OK (id: 2)");
+
+        // Removed both "Chain Two" nodes; dominant name now "Chain One" again..
+        assert_eq!(
+            state
+                .get_chain_by_node_id(node_id0)
+                .expect("Chain should exist")
+                .label(),
+            "Chain One"
+        );
+        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_some());
+    }
+
+    #[test]
+    fn chain_removed_when_last_node_is() {
+        let mut state = State::new(None, 1000);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let node_id = state
+            .add_node(chain1_genesis, node("A", "Chain One")) // 0
+            .unwrap_id();
+
+        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_some());
+        assert_eq!(state.iter_chains().count(), 1);
+
+        state.remove_node(node_id, RemovalReason::Disconnected);
+
+        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_none());
+        assert_eq!(state.iter_chains().count(), 0);
+    }
+
+    #[test]
+    fn empty_chains_are_kept_until_their_ttl_expires() {
+        let mut state = State::new(None, 1000);
+        state.set_empty_chain_ttl(Duration::from_secs(30));
+
+        let genesis_hash = BlockHash::from_low_u64_be(1);
+        let node_id = state
+            .add_node(genesis_hash, node("A", "Chain One"))
+            .unwrap_id();
+        state.remove_node(node_id, RemovalReason::Disconnected);
+
+        // The chain hangs around, but isn't listed:
+        let chain = state.get_chain_by_genesis_hash(&genesis_hash).unwrap();
+        assert!(!chain.is_listed());
+        let now = Instant::now();
+        assert!(state.remove_expired_empty_chains(now).is_empty());
+
+        // A node coming back keeps the chain for good:
+        let node_id = state
+            .add_node(genesis_hash, node("A", "Chain One"))
+            .unwrap_id();
+        let later = now + Duration::from_secs(60);
+        assert!(state.remove_expired_empty_chains(later).is_empty());
+
+        // Until it's empty for long enough again:
+        state.remove_node(node_id, RemovalReason::Disconnected);
+        assert_eq!(
+            state.remove_expired_empty_chains(Instant::now() + Duration::from_secs(30)),
+            vec![genesis_hash]
+        );
+        assert!(state.get_chain_by_genesis_hash(&genesis_hash).is_none());
+        assert_eq!(state.chain_count(), 0);
+    }
+
+    #[test]
+    fn chain_label_can_be_overridden() {
+        let mut state = State::new(None, 1000);
 
         let chain1_genesis = BlockHash::from_low_u64_be(1);
-        let node_id0 = state
-            .add_node(chain1_genesis, node("A", "Chain One")) // 0
+        state.set_chain_label_overrides([(chain1_genesis, "Override".into())].into());
+
+        let add_result = state.add_node(chain1_genesis, node("A", "Chain One"));
+        let add_node_result = match add_result {
+            AddNodeResult::NodeAddedToChain(details) => details,
+            _ => panic!("Node should have been added"),
+        };
+        assert_eq!(&*add_node_result.old_chain_label, "Override");
+        assert_eq!(add_node_result.new_chain_label, "Override");
+        assert!(!add_node_result.has_chain_label_changed);
+
+        // More nodes with another label would normally rename the chain:
+        state
+            .add_node(chain1_genesis, node("B", "Chain Two"))
             .unwrap_id();
+        let add_result = state.add_node(chain1_genesis, node("C", "Chain Two"));
+        let add_node_result = match add_result {
+            AddNodeResult::NodeAddedToChain(details) => details,
+            _ => panic!("Node should have been added"),
+        };
+        assert_eq!(add_node_result.new_chain_label, "Override");
+        assert!(!add_node_result.has_chain_label_changed);
 
+        // Other chains are unaffected:
+        let chain2_genesis = BlockHash::from_low_u64_be(2);
+        let node_id = state
+            .add_node(chain2_genesis, node("D", "Chain Two"))
+            .unwrap_id();
         assert_eq!(
-            state
-                .get_chain_by_node_id(node_id0)
-                .expect("Chain should exist")
-                .label(),
-            "Chain One"
+            state.get_chain_by_node_id(node_id).unwrap().label(),
+            "Chain Two"
         );
-        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_some());
+    }
 
-        let node_id1 = state
-            .add_node(chain1_genesis, node("B", "Chain Two")) // 1
+    #[test]
+    fn node_details_can_be_exposed_per_chain() {
+        let mut state = State::new(None, 1000);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let chain2_genesis = BlockHash::from_low_u64_be(2);
+        state.set_expose_node_details(true, [(chain2_genesis, false)].into());
+
+        let node1 = state
+            .add_node(chain1_genesis, node("A", "Chain One"))
             .unwrap_id();
+        let node2 = state
+            .add_node(chain2_genesis, node("B", "Chain Two"))
+            .unwrap_id();
+        assert!(state
+            .get_chain_by_node_id(node1)
+            .unwrap()
+            .expose_node_details());
+        assert!(!state
+            .get_chain_by_node_id(node2)
+            .unwrap()
+            .expose_node_details());
 
-        // Chain name hasn't changed yet; "Chain One" as common as "Chain Two"..
+        // Chains keep what they were created with:
+        state.set_expose_node_details(false, HashMap::new());
+        assert!(state.expose_node_details(&chain1_genesis));
+        assert!(!state.expose_node_details(&BlockHash::from_low_u64_be(3)));
+    }
+
+    #[test]
+    fn only_so_many_nodes_are_located_per_chain() {
+        let mut state = State::new(None, 1000);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let chain2_genesis = BlockHash::from_low_u64_be(2);
+        let chain3_genesis = BlockHash::from_low_u64_be(3);
+        state.set_max_located_nodes(2, [(chain2_genesis, 1), (chain3_genesis, 0)].into());
+
+        let mut should_locate =
+            |genesis_hash, name| match state.add_node(genesis_hash, node(name, "Chain")) {
+                AddNodeResult::NodeAddedToChain(details) => details.should_locate,
+                _ => panic!("Node should be added"),
+            };
+        let located: Vec<_> = ["A", "B", "C"]
+            .into_iter()
+            .map(|name| should_locate(chain1_genesis, name))
+            .collect();
+        assert_eq!(located, vec![true, true, false]);
+
+        let located: Vec<_> = ["A", "B"]
+            .into_iter()
+            .map(|name| should_locate(chain2_genesis, name))
+            .collect();
+        assert_eq!(located, vec![true, false]);
+
+        // 0 locates every node:
+        let located: Vec<_> = ["A", "B", "C"]
+            .into_iter()
+            .map(|name| should_locate(chain3_genesis, name))
+            .collect();
+        assert_eq!(located, vec![true, true, true]);
+    }
+
+    #[test]
+    fn recent_blocks_len_can_be_given_per_chain() {
+        let mut state = State::new(None, 1000);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let chain2_genesis = BlockHash::from_low_u64_be(2);
+        state.set_recent_blocks_len(2, [(chain2_genesis, 4)].into());
+
+        let mut recent_blocks = |genesis_hash| {
+            let node_id = state.add_node(genesis_hash, node("A", "Chain")).unwrap_id();
+            for height in 1..=10 {
+                let block = Block {
+                    hash: BlockHash::from_low_u64_be(height),
+                    height,
+                };
+                state.update_node(
+                    node_id,
+                    Payload::BlockImport(block.into()),
+                    &mut FeedMessageSerializer::new(),
+                );
+            }
+            state
+                .get_chain_by_genesis_hash(&genesis_hash)
+                .unwrap()
+                .recent_blocks()
+                .len()
+        };
+        assert_eq!(recent_blocks(chain1_genesis), 2);
+        assert_eq!(recent_blocks(chain2_genesis), 4);
+    }
+
+    #[test]
+    fn node_detail_fields_can_be_given_per_chain() {
+        let mut state = State::new(None, 1000);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let chain2_genesis = BlockHash::from_low_u64_be(2);
+        let version_and_ip: NodeDetailFields = "version,ip".parse().unwrap();
+        state.set_node_detail_fields(
+            NodeDetailFields::default(),
+            [(chain2_genesis, version_and_ip)].into(),
+        );
+        assert_eq!(
+            state.node_detail_fields(&chain1_genesis),
+            NodeDetailFields::default().public()
+        );
+        // Private fields still need node details to be exposed:
+        assert_eq!(
+            state.node_detail_fields(&chain2_genesis),
+            "version".parse().unwrap()
+        );
+
+        state.set_expose_node_details(true, HashMap::new());
+        let node2 = state
+            .add_node(chain2_genesis, node("B", "Chain Two"))
+            .unwrap_id();
         assert_eq!(
             state
-                .get_chain_by_node_id(node_id0)
-                .expect("Chain should exist")
-                .label(),
-            "Chain One"
+                .get_chain_by_node_id(node2)
+                .unwrap()
+                .node_detail_fields(),
+            version_and_ip
         );
-        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_some());
+        assert_eq!(
+            state.node_detail_fields(&chain1_genesis),
+            NodeDetailFields::default()
+        );
+    }
 
-        let node_id2 = state
-            .add_node(chain1_genesis, node("B", "Chain Two"))
-            .unwrap_id(); // 2
+    #[test]
+    fn blocked_node_names_are_replaced() {
+        let mut state = State::new(None, 1000);
+        let blocklist = NodeNameBlocklist::from_patterns(["spam"]).unwrap();
+        state.set_node_name_blocklist(Arc::new(blocklist), BlockedNodeNameAction::Replace);
 
-        // Chain name has changed; "Chain Two" the winner now..
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let add_result = state.add_node(chain1_genesis, node("Spam Node", "Chain One"));
+
+        let add_node_result = match add_result {
+            AddNodeResult::NodeAddedToChain(details) => details,
+            _ => panic!("Node should have been added"),
+        };
         assert_eq!(
-            state
-                .get_chain_by_node_id(node_id0)
-                .expect("Chain should exist")
-                .label(),
-            "Chain Two"
+            add_node_result.node.details().name.as_ref(),
+            BLOCKED_NODE_NAME_PLACEHOLDER
         );
+    }
+
+    #[test]
+    fn blocked_node_names_are_rejected() {
+        let mut state = State::new(None, 1000);
+        let blocklist = NodeNameBlocklist::from_patterns(["spam"]).unwrap();
+        state.set_node_name_blocklist(Arc::new(blocklist), BlockedNodeNameAction::Reject);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let add_result = state.add_node(chain1_genesis, node("Spam Node", "Chain One"));
+        assert!(matches!(add_result, AddNodeResult::NodeNameOnBlocklist));
+        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_none());
+
+        state
+            .add_node(chain1_genesis, node("Alice", "Chain One"))
+            .unwrap_id();
         assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_some());
+    }
+
+    #[test]
+    fn duplicate_nodes_are_flagged_or_rejected() {
+        let mut state = State::new(None, 1000);
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let with_network_id = |name: &str, network_id: &str| NodeDetails {
+            network_id: NetworkId::from(network_id).unwrap(),
+            ..node(name, "Chain One")
+        };
 
-        state.remove_node(node_id1).expect("Removal OK (id: 1)");
-        state.remove_node(node_id2).expect("Removal OK (id: 2)");
+        let a = state
+            .add_node(chain1_genesis, with_network_id("A", "network-a"))
+            .unwrap_id();
 
-        // Removed both "Chain Two" nodes; dominant name now "Chain One" again..
+        // By default, duplicates are added and flagged:
+        let duplicate_of = match state.add_node(chain1_genesis, with_network_id("B", "network-a")) {
+            AddNodeResult::NodeAddedToChain(details) => details.duplicate_of,
+            _ => panic!("Node should have been added"),
+        };
+        assert_eq!(duplicate_of, Some(a));
+
+        state.set_duplicate_node_policy(DuplicateNodePolicy::Reject);
+        let add_result = state.add_node(chain1_genesis, with_network_id("C", "network-a"));
+        assert!(matches!(add_result, AddNodeResult::DuplicateNode));
         assert_eq!(
             state
-                .get_chain_by_node_id(node_id0)
-                .expect("Chain should exist")
-                .label(),
-            "Chain One"
+                .get_chain_by_genesis_hash(&chain1_genesis)
+                .unwrap()
+                .node_count(),
+            2
         );
-        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_some());
+
+        // Nodes without a network ID are never duplicates:
+        state
+            .add_node(chain1_genesis, node("D", "Chain One"))
+            .unwrap_id();
+        state
+            .add_node(chain1_genesis, node("E", "Chain One"))
+            .unwrap_id();
     }
 
     #[test]
-    fn chain_removed_when_last_node_is() {
+    fn reconnecting_nodes_replace_or_are_rejected() {
         let mut state = State::new(None, 1000);
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let started_at = |startup_time: &str| NodeDetails {
+            network_id: NetworkId::from("network-a").unwrap(),
+            startup_time: Some(startup_time.into()),
+            ..node("A", "Chain One")
+        };
+
+        let a = state
+            .add_node(chain1_genesis, started_at("1000"))
+            .unwrap_id();
+
+        // Reconnects aren't rejected as duplicates, and replace the old connection by default:
+        state.set_duplicate_node_policy(DuplicateNodePolicy::Reject);
+        match state.add_node(chain1_genesis, started_at("1000")) {
+            AddNodeResult::NodeAddedToChain(details) => {
+                assert_eq!(details.duplicate_of, Some(a));
+                assert!(details.is_reconnect);
+            }
+            _ => panic!("Node should have been added"),
+        }
 
+        // Another node with the same network ID isn't a reconnect:
+        let add_result = state.add_node(chain1_genesis, started_at("2000"));
+        assert!(matches!(add_result, AddNodeResult::DuplicateNode));
+
+        state.set_reconnect_policy(ReconnectPolicy::Reject);
+        let add_result = state.add_node(chain1_genesis, started_at("1000"));
+        assert!(matches!(add_result, AddNodeResult::DuplicateNode));
+
+        // Nodes that don't report a startup time can't be told apart, so aren't reconnects:
+        state.set_reconnect_policy(ReconnectPolicy::Replace);
+        let b = |name| NodeDetails {
+            network_id: NetworkId::from("network-b").unwrap(),
+            ..node(name, "Chain One")
+        };
+        state.add_node(chain1_genesis, b("B")).unwrap_id();
+        let add_result = state.add_node(chain1_genesis, b("B"));
+        assert!(matches!(add_result, AddNodeResult::DuplicateNode));
+    }
+
+    #[test]
+    fn outdated_versions_are_flagged_or_rejected() {
+        let mut state = State::new(None, 1000);
         let chain1_genesis = BlockHash::from_low_u64_be(1);
-        let node_id = state
-            .add_node(chain1_genesis, node("A", "Chain One")) // 0
+        let chain2_genesis = BlockHash::from_low_u64_be(2);
+        let with_version = |name: &str, version: &str| NodeDetails {
+            version: version.into(),
+            ..node(name, "Chain One")
+        };
+        state.set_min_versions(
+            [(chain1_genesis, (1, 2))].into_iter().collect(),
+            OutdatedVersionPolicy::Flag,
+            UnparseableVersionPolicy::Allow,
+        );
+        let is_outdated = |state: &mut State, genesis_hash, details| match state
+            .add_node(genesis_hash, details)
+        {
+            AddNodeResult::NodeAddedToChain(details) => details.is_outdated,
+            _ => panic!("Node should have been added"),
+        };
+
+        assert!(is_outdated(
+            &mut state,
+            chain1_genesis,
+            with_version("A", "1.1.9-abc")
+        ));
+        assert!(!is_outdated(
+            &mut state,
+            chain1_genesis,
+            with_version("B", "1.2.0-abc")
+        ));
+        assert!(!is_outdated(
+            &mut state,
+            chain1_genesis,
+            with_version("C", "v2.0")
+        ));
+        assert!(!is_outdated(
+            &mut state,
+            chain1_genesis,
+            with_version("D", "unknown")
+        ));
+        // Chains without a minimum version don't mind:
+        assert!(!is_outdated(
+            &mut state,
+            chain2_genesis,
+            with_version("E", "0.1")
+        ));
+
+        state.set_min_versions(
+            [(chain1_genesis, (1, 2))].into_iter().collect(),
+            OutdatedVersionPolicy::Reject,
+            UnparseableVersionPolicy::Outdated,
+        );
+        for version in ["1.1.9-abc", "unknown"] {
+            let add_result = state.add_node(chain1_genesis, with_version("F", version));
+            assert!(matches!(add_result, AddNodeResult::OutdatedVersion));
+        }
+        state
+            .add_node(chain1_genesis, with_version("G", "1.2.0"))
             .unwrap_id();
+    }
 
-        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_some());
+    #[test]
+    fn operators_can_only_have_so_many_nodes() {
+        let mut state = State::new(None, 1000);
+        state.set_operator_cap(OperatorCap {
+            max_nodes: 2,
+            exempt: Arc::new(["Trusted".into()].into_iter().collect()),
+        });
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+
+        let a1 = state
+            .add_node(chain1_genesis, node("Acme/1", "Chain One"))
+            .unwrap_id();
+        state
+            .add_node(chain1_genesis, node("Acme/2", "Chain One"))
+            .unwrap_id();
+        let add_result = state.add_node(chain1_genesis, node("Acme/3", "Chain One"));
+        assert!(matches!(add_result, AddNodeResult::OperatorOverCap));
+
+        // Other operators, and exempt ones, aren't affected:
+        state
+            .add_node(chain1_genesis, node("Other/1", "Chain One"))
+            .unwrap_id();
+        for n in 1..=3 {
+            state
+                .add_node(chain1_genesis, node(&format!("Trusted/{n}"), "Chain One"))
+                .unwrap_id();
+        }
+
+        // Once a node goes, the operator can add another:
+        state.remove_node(a1, RemovalReason::Disconnected);
+        state
+            .add_node(chain1_genesis, node("Acme/3", "Chain One"))
+            .unwrap_id();
+    }
+
+    #[test]
+    fn nodes_on_denied_networks_are_rejected() {
+        let mut state = State::new(None, 1000);
+        let denied_genesis = BlockHash::from_low_u64_be(1);
+        let denylist =
+            NetworkDenylist::from_genesis_hashes([format!("{denied_genesis:?}")]).unwrap();
+        state.set_network_denylist(Arc::new(denylist));
+
+        let add_result = state.add_node(denied_genesis, node("A", "Chain One"));
+        assert!(matches!(add_result, AddNodeResult::ChainOnDenyList));
+        assert!(state.get_chain_by_genesis_hash(&denied_genesis).is_none());
+
+        state
+            .add_node(BlockHash::from_low_u64_be(2), node("B", "Chain Two"))
+            .unwrap_id();
         assert_eq!(state.iter_chains().count(), 1);
+    }
 
-        state.remove_node(node_id);
+    #[test]
+    fn zero_genesis_hashes_are_rejected() {
+        let mut state = State::new(None, 1000);
 
-        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_none());
+        let add_result = state.add_node(BlockHash::zero(), node("A", "Chain One"));
+        assert!(matches!(add_result, AddNodeResult::InvalidGenesisHash));
+        assert!(state
+            .get_chain_by_genesis_hash(&BlockHash::zero())
+            .is_none());
         assert_eq!(state.iter_chains().count(), 0);
     }
+
+    #[test]
+    fn zero_genesis_hashes_can_go_on_the_unidentified_chain() {
+        let mut state = State::new(None, 1000);
+        state.set_max_unidentified_nodes(2);
+
+        state
+            .add_node(BlockHash::zero(), node("A", "Chain One"))
+            .unwrap_id();
+        state
+            .add_node(BlockHash::zero(), node("B", "Chain Two"))
+            .unwrap_id();
+        let add_result = state.add_node(BlockHash::zero(), node("C", "Chain One"));
+        assert!(matches!(add_result, AddNodeResult::ChainOverQuota));
+
+        let chain = state.get_chain_by_genesis_hash(&BlockHash::zero()).unwrap();
+        assert_eq!(chain.label(), UNIDENTIFIED_CHAIN_LABEL);
+        assert_eq!(chain.node_count(), 2);
+    }
+
+    #[test]
+    fn nodes_are_given_the_time_they_were_first_ever_seen() {
+        let mut state = State::new(None, 1000);
+        let genesis_hash = BlockHash::from_low_u64_be(1);
+        let path = std::env::temp_dir().join("first_seen_state_test_does_not_exist");
+        let store = FirstSeenStore::load(&path, 10).unwrap();
+        state.set_first_seen_store(Some(Arc::new(Mutex::new(store))));
+        let with_network_id = |name: &str| NodeDetails {
+            network_id: NetworkId::from("network-a").unwrap(),
+            ..node(name, "Chain One")
+        };
+        let first_seen_ever = |state: &State, node_id: NodeId| {
+            state
+                .get_chain_by_genesis_hash(&genesis_hash)
+                .unwrap()
+                .get_node(node_id.get_chain_node_id())
+                .unwrap()
+                .full_state()["first_seen_ever"]
+                .as_u64()
+        };
+
+        // Without stable IDs, nodes can't be recognised:
+        let a = state
+            .add_node(genesis_hash, with_network_id("A"))
+            .unwrap_id();
+        assert_eq!(first_seen_ever(&state, a), None);
+        state.remove_node(a, RemovalReason::Disconnected);
+
+        state.set_stable_node_ids(true);
+        let a = state
+            .add_node(genesis_hash, with_network_id("A"))
+            .unwrap_id();
+        let first_seen = first_seen_ever(&state, a);
+        assert!(first_seen.is_some());
+        state.remove_node(a, RemovalReason::Disconnected);
+
+        std::thread::sleep(Duration::from_millis(5));
+        let a = state
+            .add_node(genesis_hash, with_network_id("A again"))
+            .unwrap_id();
+        assert_eq!(first_seen_ever(&state, a), first_seen);
+    }
+
+    #[test]
+    fn moving_a_node_keeps_its_state() {
+        let mut state = State::new(None, 1000);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let chain2_genesis = BlockHash::from_low_u64_be(2);
+        let node_id = state
+            .add_node(chain1_genesis, node("A", "Chain One"))
+            .unwrap_id();
+        let block = Block {
+            hash: BlockHash::from_low_u64_be(10),
+            height: 10,
+        };
+        state.update_node(
+            node_id,
+            Payload::BlockImport(block.into()),
+            &mut FeedMessageSerializer::new(),
+        );
+        let last_seen = state
+            .get_chain_by_genesis_hash(&chain1_genesis)
+            .and_then(|chain| chain.get_node(node_id.get_chain_node_id()))
+            .unwrap()
+            .last_seen();
+
+        let moved = match state.move_node(node_id, chain2_genesis) {
+            MoveNodeResult::NodeMoved(moved) => moved,
+            _ => panic!("Node should have been moved"),
+        };
+        assert_eq!(moved.removed.chain_node_count, 0);
+        assert_eq!(moved.removed.chain_genesis_hash, chain1_genesis);
+        assert_eq!(moved.added.chain_node_count, 1);
+        assert_eq!(moved.added.node.best(), &block);
+        assert_eq!(moved.added.node.last_seen(), last_seen);
+
+        // The now empty chain is removed:
+        assert!(state.get_chain_by_genesis_hash(&chain1_genesis).is_none());
+        assert_eq!(
+            state
+                .get_chain_by_genesis_hash(&chain2_genesis)
+                .unwrap()
+                .node_count(),
+            1
+        );
+    }
+
+    #[test]
+    fn moving_a_node_brings_the_new_chain_up_to_date() {
+        let mut state = State::new(None, 1000);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let chain2_genesis = BlockHash::from_low_u64_be(2);
+        let a = state
+            .add_node(chain1_genesis, node("A", "Chain One"))
+            .unwrap_id();
+        let b = state
+            .add_node(chain2_genesis, node("B", "Chain Two"))
+            .unwrap_id();
+        let block = |height| Block {
+            hash: BlockHash::from_low_u64_be(height),
+            height,
+        };
+        let finalized = |height: u64| {
+            Payload::NotifyFinalized(common::node_message::Finalized {
+                hash: BlockHash::from_low_u64_be(height),
+                height: height.to_string().into(),
+            })
+        };
+        let mut feed = FeedMessageSerializer::new();
+        state.update_node(a, Payload::BlockImport(block(10).into()), &mut feed);
+        state.update_node(a, finalized(8), &mut feed);
+        state.update_node(b, Payload::BlockImport(block(5).into()), &mut feed);
+        let best_timestamp = state
+            .get_chain_by_genesis_hash(&chain1_genesis)
+            .unwrap()
+            .timestamp();
+
+        let moved = match state.move_node(a, chain2_genesis) {
+            MoveNodeResult::NodeMoved(moved) => moved,
+            _ => panic!("Node should have been moved"),
+        };
+        assert!(moved.added.has_chain_blocks_changed);
+        let chain2 = state.get_chain_by_genesis_hash(&chain2_genesis).unwrap();
+        assert_eq!(chain2.best_block(), &block(10));
+        assert_eq!(chain2.finalized_block(), &block(8));
+        assert_eq!(chain2.timestamp(), best_timestamp);
+
+        // Moving a node that's behind leaves the chain as it was:
+        let c = state
+            .add_node(chain1_genesis, node("C", "Chain One"))
+            .unwrap_id();
+        let moved = match state.move_node(c, chain2_genesis) {
+            MoveNodeResult::NodeMoved(moved) => moved,
+            _ => panic!("Node should have been moved"),
+        };
+        assert!(!moved.added.has_chain_blocks_changed);
+        let chain2 = state.get_chain_by_genesis_hash(&chain2_genesis).unwrap();
+        assert_eq!(chain2.best_block(), &block(10));
+        assert_eq!(chain2.finalized_block(), &block(8));
+    }
+
+    #[test]
+    fn nodes_are_not_moved_to_chains_without_room() {
+        let mut state = State::new(None, 1);
+
+        let chain1_genesis = BlockHash::from_low_u64_be(1);
+        let chain2_genesis = BlockHash::from_low_u64_be(2);
+        let node_id = state
+            .add_node(chain1_genesis, node("A", "Chain One"))
+            .unwrap_id();
+        state
+            .add_node(chain2_genesis, node("B", "Chain Two"))
+            .unwrap_id();
+
+        assert!(matches!(
+            state.move_node(node_id, chain2_genesis),
+            MoveNodeResult::ChainOverQuota
+        ));
+        assert!(matches!(
+            state.move_node(node_id, chain1_genesis),
+            MoveNodeResult::AlreadyOnChain
+        ));
+        // The node stays where it was:
+        assert_eq!(
+            state
+                .get_chain_by_genesis_hash(&chain1_genesis)
+                .unwrap()
+                .node_count(),
+            1
+        );
+    }
+
+    #[test]
+    fn chains_are_listed_as_they_cross_the_minimum_node_count() {
+        let mut state = State::new(None, 1000);
+        state.set_min_listed_nodes(2);
+        let genesis = BlockHash::from_low_u64_be(1);
+
+        let listed = |added: AddNodeResult| match added {
+            AddNodeResult::NodeAddedToChain(added) => {
+                (added.id, added.was_chain_listed, added.is_chain_listed)
+            }
+            _ => panic!("Node should be added"),
+        };
+        let (a, was_listed, is_listed) = listed(state.add_node(genesis, node("A", "Chain One")));
+        assert!(!was_listed && !is_listed);
+        let (_, was_listed, is_listed) = listed(state.add_node(genesis, node("B", "Chain One")));
+        assert!(!was_listed && is_listed);
+
+        let removed = state.remove_node(a, RemovalReason::Disconnected).unwrap();
+        assert!(removed.was_chain_listed && !removed.is_chain_listed);
+    }
+
+    #[test]
+    fn private_chains_are_left_out_of_metrics() {
+        let mut state = State::new(None, 1000);
+        let public = BlockHash::from_low_u64_be(1);
+        let private = BlockHash::from_low_u64_be(2);
+        state.set_private_chains([private].into_iter().collect());
+
+        state.add_node(public, node("A", "Chain One"));
+        state.add_node(private, node("B", "Chain Two"));
+
+        let hashes: Vec<_> = state.chain_metrics().map(|m| m.genesis_hash).collect();
+        assert_eq!(hashes, vec![public]);
+    }
 }
@@ -0,0 +1,335 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Push the metrics that `/metrics` serves to a StatsD server over UDP every so often,
+//! for monitoring stacks that take StatsD rather than scraping. See:
+//!
+//! - <https://github.com/statsd/statsd/blob/master/docs/metric_types.md>
+//! - <https://docs.datadoghq.com/developers/dogstatsd/datagram_shell/>
+//!
+//! Plain StatsD has no tags, so the values of any tags are appended to the metric name
+//! instead, whereas DogStatsD is sent them as tags.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use crate::aggregator::{AggregatorSet, Metrics};
+use crate::feed_message;
+
+/// Lines are batched into datagrams up to this size, which fits in a typical MTU.
+const MAX_DATAGRAM_BYTES: usize = 1432;
+
+/// The flavours of StatsD that we can send metrics in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsdFormat {
+    Statsd,
+    DogStatsd,
+}
+
+impl FromStr for StatsdFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "statsd" => Ok(StatsdFormat::Statsd),
+            "dogstatsd" => Ok(StatsdFormat::DogStatsd),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'statsd' or 'dogstatsd', but got '{s}'"
+            )),
+        }
+    }
+}
+
+pub struct StatsdOpts {
+    /// The `host:port` to send metrics to.
+    pub addr: String,
+    /// Put in front of the name of every metric, followed by a `.`.
+    pub prefix: String,
+    pub format: StatsdFormat,
+    /// How often to send metrics.
+    pub interval: Duration,
+}
+
+/// Writes out metrics as StatsD lines. Counters are sent as how much they've gone up
+/// since the last time they were written, so the writer is kept between flushes.
+pub struct StatsdWriter {
+    prefix: String,
+    format: StatsdFormat,
+    lines: Vec<String>,
+    /// The totals that counters were at when they were last written, by name and tags.
+    last_totals: HashMap<String, u64>,
+}
+
+impl StatsdWriter {
+    pub fn new(prefix: &str, format: StatsdFormat) -> Self {
+        StatsdWriter {
+            prefix: prefix.to_owned(),
+            format,
+            lines: Vec::new(),
+            last_totals: HashMap::new(),
+        }
+    }
+
+    /// The name of a metric, and the tags to put after its value (if any).
+    fn name_and_tags(&self, name: &str, tags: &[(&str, &str)]) -> (String, String) {
+        let mut full_name = match self.prefix.is_empty() {
+            true => name.to_owned(),
+            false => format!("{}.{name}", self.prefix),
+        };
+        match self.format {
+            StatsdFormat::Statsd => {
+                for (_, value) in tags {
+                    full_name.push('.');
+                    full_name.push_str(&sanitize(value));
+                }
+                (full_name, String::new())
+            }
+            StatsdFormat::DogStatsd if tags.is_empty() => (full_name, String::new()),
+            StatsdFormat::DogStatsd => {
+                let tags: Vec<_> = tags
+                    .iter()
+                    .map(|(key, value)| format!("{key}:{}", sanitize(value)))
+                    .collect();
+                (full_name, format!("|#{}", tags.join(",")))
+            }
+        }
+    }
+
+    /// Add a gauge, whose value replaces whatever it was before.
+    pub fn gauge(&mut self, name: &str, tags: &[(&str, &str)], value: impl Display) {
+        let (name, tags) = self.name_and_tags(name, tags);
+        self.lines.push(format!("{name}:{value}|g{tags}"));
+    }
+
+    /// Add a counter given its running total; what's sent is how much it's gone up by since
+    /// it was last written. Nothing is sent the first time, since we don't know that yet.
+    pub fn counter(&mut self, name: &str, tags: &[(&str, &str)], total: u64) {
+        let (name, tags) = self.name_and_tags(name, tags);
+        let key = format!("{name}{tags}");
+        let Some(last_total) = self.last_totals.insert(key, total) else {
+            return;
+        };
+        // Totals can go backwards if whatever they count is removed and comes back:
+        let increase = total.saturating_sub(last_total);
+        self.lines.push(format!("{name}:{increase}|c{tags}"));
+    }
+
+    /// Take the lines written so far, batched into datagrams.
+    pub fn take_datagrams(&mut self) -> Vec<String> {
+        let mut datagrams = Vec::new();
+        let mut datagram = String::new();
+        for line in self.lines.drain(..) {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM_BYTES {
+                datagrams.push(std::mem::take(&mut datagram));
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            datagrams.push(datagram);
+        }
+        datagrams
+    }
+
+    /// Write out the same metrics that `/metrics` serves.
+    pub fn write_metrics(&mut self, metrics: &[Metrics]) {
+        for (idx, m) in metrics.iter().enumerate() {
+            let aggregator = idx.to_string();
+            let tags = [("aggregator", &*aggregator)];
+            self.gauge("connected_feeds", &tags, m.connected_feeds);
+            self.gauge("connected_nodes", &tags, m.connected_nodes);
+            self.gauge("pending_nodes", &tags, m.pending_nodes);
+            self.gauge("connected_shards", &tags, m.connected_shards);
+            self.gauge("chains", &tags, m.chains.len());
+            self.gauge("chains_subscribed_to", &tags, m.chains_subscribed_to);
+            self.gauge("subscribed_feeds", &tags, m.subscribed_feeds);
+            self.gauge("total_messages_to_feeds", &tags, m.total_messages_to_feeds);
+            self.gauge(
+                "current_messages_to_aggregator",
+                &tags,
+                m.current_messages_to_aggregator,
+            );
+            self.counter(
+                "messages_to_aggregator",
+                &tags,
+                m.total_messages_to_aggregator,
+            );
+            self.counter(
+                "dropped_messages_to_aggregator",
+                &tags,
+                m.dropped_messages_to_aggregator,
+            );
+            self.counter(
+                "dropped_location_lookups",
+                &tags,
+                m.dropped_location_lookups,
+            );
+            for chain in &m.chains {
+                let genesis_hash = format!("{:?}", chain.genesis_hash);
+                let tags = [
+                    ("aggregator", &*aggregator),
+                    ("genesis_hash", &*genesis_hash),
+                    ("chain", &*chain.label),
+                ];
+                self.gauge("chain.nodes", &tags, chain.node_count);
+                self.gauge("chain.finality_gap", &tags, chain.best_finalized_gap);
+                self.gauge(
+                    "chain.messages_per_second",
+                    &tags,
+                    chain.messages_per_second,
+                );
+                self.counter(
+                    "chain.malformed_node_messages",
+                    &tags,
+                    chain.malformed_messages,
+                );
+                if let Some(block_time) = chain.latest_block.and_then(|b| b.block_time) {
+                    self.gauge("chain.block_time_ms", &tags, block_time);
+                }
+            }
+        }
+
+        for (name, count) in feed_message::message_counts() {
+            self.counter("feed_messages", &[("type", name)], count);
+        }
+    }
+}
+
+/// Make a name or tag value safe to put in a StatsD line, which uses `:`, `|`, `#`, `,`
+/// and `.` as separators.
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Send the latest metrics from the aggregators to a StatsD server every so often.
+pub async fn spawn_statsd_sender(
+    opts: StatsdOpts,
+    aggregator: AggregatorSet,
+) -> anyhow::Result<()> {
+    let addr = tokio::net::lookup_host(&opts.addr)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Couldn't resolve StatsD address {}", opts.addr))?;
+    let bind_addr = match addr {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(addr).await?;
+
+    log::info!(
+        "Sending metrics to StatsD at {} every {:?}",
+        opts.addr,
+        opts.interval
+    );
+    tokio::spawn(async move {
+        let mut writer = StatsdWriter::new(&opts.prefix, opts.format);
+        let mut interval = tokio::time::interval(opts.interval);
+        loop {
+            interval.tick().await;
+            writer.write_metrics(&aggregator.latest_metrics());
+            for datagram in writer.take_datagrams() {
+                // StatsD is fire and forget, so just make a note of failures:
+                if let Err(e) = socket.send(datagram.as_bytes()).await {
+                    log::debug!("Error sending metrics to StatsD: {e}");
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tags_are_appended_to_names_for_statsd() {
+        let mut w = StatsdWriter::new("telemetry", StatsdFormat::Statsd);
+        w.gauge(
+            "chain.nodes",
+            &[("chain", "Polkadot CC1"), ("x", "a.b")],
+            12,
+        );
+        w.gauge("connected_feeds", &[], 3);
+        assert_eq!(
+            w.take_datagrams(),
+            vec!["telemetry.chain.nodes.Polkadot_CC1.a_b:12|g\ntelemetry.connected_feeds:3|g"]
+        );
+    }
+
+    #[test]
+    fn tags_are_sent_as_tags_for_dogstatsd() {
+        let mut w = StatsdWriter::new("", StatsdFormat::DogStatsd);
+        w.gauge("chain.nodes", &[("aggregator", "0"), ("chain", "a|b")], 12);
+        w.gauge("connected_feeds", &[], 3);
+        assert_eq!(
+            w.take_datagrams(),
+            vec!["chain.nodes:12|g|#aggregator:0,chain:a_b\nconnected_feeds:3|g"]
+        );
+    }
+
+    #[test]
+    fn counters_send_how_much_they_have_gone_up() {
+        let mut w = StatsdWriter::new("t", StatsdFormat::DogStatsd);
+        w.counter("messages", &[("aggregator", "0")], 100);
+        assert!(w.take_datagrams().is_empty());
+
+        w.counter("messages", &[("aggregator", "0")], 150);
+        w.counter("messages", &[("aggregator", "1")], 10);
+        assert_eq!(w.take_datagrams(), vec!["t.messages:50|c|#aggregator:0"]);
+
+        w.counter("messages", &[("aggregator", "0")], 20);
+        assert_eq!(w.take_datagrams(), vec!["t.messages:0|c|#aggregator:0"]);
+    }
+
+    #[test]
+    fn lines_are_batched_into_datagrams() {
+        let mut w = StatsdWriter::new("", StatsdFormat::Statsd);
+        for _ in 0..100 {
+            w.gauge("some_fairly_long_metric_name", &[], 123456);
+        }
+        let datagrams = w.take_datagrams();
+        assert!(datagrams.len() > 1);
+        assert!(datagrams.iter().all(|d| d.len() <= MAX_DATAGRAM_BYTES));
+        let lines: usize = datagrams.iter().map(|d| d.lines().count()).sum();
+        assert_eq!(lines, 100);
+    }
+
+    #[test]
+    fn formats_are_parsed() {
+        assert_eq!(
+            "statsd".parse::<StatsdFormat>().unwrap(),
+            StatsdFormat::Statsd
+        );
+        assert_eq!(
+            "dogstatsd".parse::<StatsdFormat>().unwrap(),
+            StatsdFormat::DogStatsd
+        );
+        assert!("prometheus".parse::<StatsdFormat>().is_err());
+    }
+}
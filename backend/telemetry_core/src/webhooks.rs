@@ -0,0 +1,309 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! POST the alerts that feeds are sent about chains to webhooks as JSON, so that they
+//! can be passed on to incident tooling without anything having to watch a feed.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::node_types::{BlockHash, Timestamp};
+use common::time;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+
+use crate::state::{ChainAlert, NodeCountDrop};
+
+/// The kinds of alert that webhooks can be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    FinalityGap,
+    ValidatorShortfall,
+    NodeCountDrop,
+}
+
+impl WebhookEvent {
+    fn of(alert: &ChainAlert) -> WebhookEvent {
+        match alert {
+            ChainAlert::FinalityGap { .. } => WebhookEvent::FinalityGap,
+            ChainAlert::ValidatorShortfall { .. } => WebhookEvent::ValidatorShortfall,
+            ChainAlert::NodeCountDrop(_) => WebhookEvent::NodeCountDrop,
+        }
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "finality_gap" => Ok(WebhookEvent::FinalityGap),
+            "validator_shortfall" => Ok(WebhookEvent::ValidatorShortfall),
+            "node_count_drop" => Ok(WebhookEvent::NodeCountDrop),
+            _ => Err(anyhow::anyhow!(
+                "Expecting one of 'finality_gap', 'validator_shortfall' or 'node_count_drop', but got '{s}'"
+            )),
+        }
+    }
+}
+
+/// A URL that alerts of the given kind are POSTed to.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub event: WebhookEvent,
+    pub url: reqwest::Url,
+}
+
+impl FromStr for Webhook {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (event, url) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expecting format `<event>=<url>`"))?;
+        Ok(Webhook {
+            event: event.parse()?,
+            url: url.parse()?,
+        })
+    }
+}
+
+/// Where alerts are sent, and how hard we try to get them there.
+#[derive(Debug, Clone)]
+pub struct WebhookOpts {
+    pub webhooks: Vec<Webhook>,
+    /// How many times we try to POST each alert before giving up on it.
+    pub max_attempts: u32,
+    /// How long we wait before trying again the first time. This doubles each attempt.
+    pub initial_backoff: Duration,
+    /// Alerts that we give up on are appended to this file, one JSON object per line.
+    pub dead_letter_log: Option<PathBuf>,
+}
+
+/// An alert about a chain, on its way to the webhooks.
+#[derive(Debug, Clone)]
+pub struct WebhookAlert {
+    pub genesis_hash: BlockHash,
+    pub chain: Box<str>,
+    pub alert: ChainAlert,
+    /// When (unix time in ms) the alert happened.
+    pub timestamp: Timestamp,
+}
+
+impl WebhookAlert {
+    pub fn new(genesis_hash: BlockHash, chain: &str, alert: ChainAlert) -> WebhookAlert {
+        WebhookAlert {
+            genesis_hash,
+            chain: chain.into(),
+            alert,
+            timestamp: time::now(),
+        }
+    }
+
+    /// The JSON body that's POSTed to webhooks.
+    fn to_json(&self) -> serde_json::Value {
+        let mut body = match self.alert {
+            ChainAlert::FinalityGap { gap, recovered } => json!({
+                "event": "finality_gap",
+                "recovered": recovered,
+                "gap": gap,
+            }),
+            ChainAlert::ValidatorShortfall {
+                expected,
+                observed,
+                recovered,
+            } => json!({
+                "event": "validator_shortfall",
+                "recovered": recovered,
+                "expected": expected,
+                "observed": observed,
+            }),
+            ChainAlert::NodeCountDrop(NodeCountDrop { from, to, window }) => json!({
+                "event": "node_count_drop",
+                "from": from,
+                "to": to,
+                "window": window.as_millis() as u64,
+            }),
+        };
+        body["genesis_hash"] = json!(format!("{:?}", self.genesis_hash));
+        body["chain"] = json!(&*self.chain);
+        body["timestamp"] = json!(self.timestamp);
+        body
+    }
+}
+
+/// Spawn a task that POSTs the alerts it's sent to the webhooks that want them, handing
+/// back a channel to send alerts to. Nothing is spawned if there are no webhooks.
+pub fn spawn_webhook_sender(opts: WebhookOpts) -> Option<flume::Sender<WebhookAlert>> {
+    if opts.webhooks.is_empty() {
+        return None;
+    }
+
+    let (tx, rx) = flume::unbounded::<WebhookAlert>();
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("webhook client should build");
+    let opts = Arc::new(opts);
+
+    tokio::spawn(async move {
+        while let Ok(alert) = rx.recv_async().await {
+            let event = WebhookEvent::of(&alert.alert);
+            let body = Arc::new(alert.to_json());
+            for webhook in opts.webhooks.iter().filter(|w| w.event == event) {
+                // Deliver to each webhook separately, so that a slow or failing one
+                // doesn't hold the others up:
+                tokio::spawn(deliver(
+                    client.clone(),
+                    webhook.url.clone(),
+                    Arc::clone(&body),
+                    Arc::clone(&opts),
+                ));
+            }
+        }
+    });
+
+    Some(tx)
+}
+
+/// POST the body to the URL, backing off and trying again if that fails. Once we run out
+/// of attempts, the alert is logged and written to the dead letter log if there is one.
+async fn deliver(
+    client: reqwest::Client,
+    url: reqwest::Url,
+    body: Arc<serde_json::Value>,
+    opts: Arc<WebhookOpts>,
+) {
+    let mut backoff = opts.initial_backoff;
+    let mut attempt = 1;
+    let error = loop {
+        let res = client
+            .post(url.clone())
+            .json(&*body)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        let error = match res {
+            Ok(_) => return,
+            Err(e) => e,
+        };
+        if attempt >= opts.max_attempts {
+            break error;
+        }
+        log::debug!("Webhook {url} failed (attempt {attempt}), retrying in {backoff:?}: {error}");
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    };
+
+    log::error!("Giving up on webhook {url} after {attempt} attempts: {error}; alert was {body}");
+    if let Some(path) = &opts.dead_letter_log {
+        let line = json!({
+            "url": url.as_str(),
+            "error": error.to_string(),
+            "attempts": attempt,
+            "alert": *body,
+        });
+        if let Err(e) = append_line(path, &line).await {
+            log::error!("Cannot write to webhook dead letter log {path:?}: {e}");
+        }
+    }
+}
+
+async fn append_line(path: &PathBuf, line: &serde_json::Value) -> std::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    let mut line = line.to_string().into_bytes();
+    line.push(b'\n');
+    file.write_all(&line).await?;
+    file.flush().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn webhooks_are_parsed_from_event_and_url() {
+        let webhook: Webhook = "finality_gap=https://example.com/hook?a=b".parse().unwrap();
+        assert_eq!(webhook.event, WebhookEvent::FinalityGap);
+        assert_eq!(webhook.url.as_str(), "https://example.com/hook?a=b");
+
+        assert!("stalled=https://example.com".parse::<Webhook>().is_err());
+        assert!("finality_gap=not a url".parse::<Webhook>().is_err());
+        assert!("https://example.com".parse::<Webhook>().is_err());
+    }
+
+    #[test]
+    fn alerts_are_sent_as_json() {
+        let genesis_hash = BlockHash::from_low_u64_be(1);
+        let alert = WebhookAlert::new(
+            genesis_hash,
+            "Chain One",
+            ChainAlert::NodeCountDrop(NodeCountDrop {
+                from: 100,
+                to: 40,
+                window: Duration::from_secs(60),
+            }),
+        );
+
+        let body = alert.to_json();
+        assert_eq!(body["event"], "node_count_drop");
+        assert_eq!(body["from"], 100);
+        assert_eq!(body["to"], 40);
+        assert_eq!(body["window"], 60_000);
+        assert_eq!(body["chain"], "Chain One");
+        assert_eq!(body["genesis_hash"], format!("{genesis_hash:?}"));
+        assert_eq!(body["timestamp"], alert.timestamp);
+    }
+
+    #[tokio::test]
+    async fn undeliverable_alerts_go_to_the_dead_letter_log() {
+        let path = std::env::temp_dir().join(format!(
+            "webhooks_test_{}_{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        let opts = Arc::new(WebhookOpts {
+            webhooks: Vec::new(),
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            dead_letter_log: Some(path.clone()),
+        });
+        let alert = WebhookAlert::new(
+            BlockHash::from_low_u64_be(1),
+            "Chain One",
+            ChainAlert::FinalityGap {
+                gap: 60,
+                recovered: false,
+            },
+        );
+
+        // Nothing listens on port 1, so every attempt fails:
+        let url: reqwest::Url = "http://127.0.0.1:1/hook".parse().unwrap();
+        deliver(reqwest::Client::new(), url, Arc::new(alert.to_json()), opts).await;
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(contents.trim()).unwrap();
+        assert_eq!(line["url"], "http://127.0.0.1:1/hook");
+        assert_eq!(line["attempts"], 3);
+        assert_eq!(line["alert"], alert.to_json());
+    }
+}
@@ -37,12 +37,14 @@ cargo test e2e -- --ignored
 */
 
 use common::node_types::BlockHash;
-use common::ws_client::SentMessage;
+use common::ws_client::{self, RecvMessage, SentMessage};
+use futures::StreamExt;
 use serde_json::json;
 use std::{str::FromStr, time::Duration};
 use test_utils::{
     assert_contains_matches,
     feed_message_de::{FeedMessage, NodeDetails},
+    server::channels::{FeedReceiver, FeedSender},
     workspace::{start_server, start_server_debug, CoreOpts, ServerOpts, ShardOpts},
 };
 
@@ -69,7 +71,7 @@ async fn e2e_feed_sent_version_on_connect() {
     let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
     assert_eq!(
         feed_messages,
-        vec![FeedMessage::Version(32)],
+        vec![FeedMessage::Version(35)],
         "expecting version"
     );
 
@@ -127,7 +129,7 @@ async fn e2e_multiple_feeds_sent_version_on_connect() {
     for feed_messages in responses {
         assert_eq!(
             feed_messages.expect("should have messages"),
-            vec![FeedMessage::Version(32)],
+            vec![FeedMessage::Version(35)],
             "expecting version"
         );
     }
@@ -260,6 +262,128 @@ async fn e2e_feed_add_and_remove_node() {
     server.shutdown().await;
 }
 
+/// Feeds that are already connected are told when a chain is first created, but
+/// not when more nodes join it.
+#[tokio::test]
+async fn e2e_feed_told_about_new_chain_once() {
+    let mut server = start_server_debug().await;
+    let shard_id = server.add_shard().await.unwrap();
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .expect("can connect to shard");
+
+    let (_feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+    feed_rx.recv_feed_messages().await.unwrap();
+
+    let mut new_chain_count = 0;
+    for id in 1..=2 {
+        node_tx
+            .send_json_text(json!(
+                {
+                    "id":id,
+                    "ts":"2021-07-12T10:37:47.714666+01:00",
+                    "payload": {
+                        "authority":true,
+                        "chain":"Local Testnet",
+                        "config":"",
+                        "genesis_hash": ghash(1),
+                        "implementation":"Substrate Node",
+                        "msg":"system.connected",
+                        "name":format!("Alice {}", id),
+                        "network_id":format!("12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDE{}", id),
+                        "startup_time":"1625565542717",
+                        "version":"2.0.0-07a1af348-aarch64-macos"
+                    },
+                }
+            ))
+            .unwrap();
+
+        // Wait until we hear about the node being added:
+        loop {
+            let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+            new_chain_count += feed_messages
+                .iter()
+                .filter(|msg| {
+                    **msg
+                        == FeedMessage::NewChain {
+                            genesis_hash: ghash(1),
+                            name: "Local Testnet".to_owned(),
+                        }
+                })
+                .count();
+            if feed_messages.iter().any(|msg| {
+                matches!(msg, FeedMessage::AddedChain { node_count, .. } if *node_count == id)
+            }) {
+                break;
+            }
+        }
+    }
+    assert_eq!(new_chain_count, 1);
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
+/// Feeds are told which relay chain a parachain belongs to, both as it happens
+/// and when they first connect.
+#[tokio::test]
+async fn e2e_feed_told_about_relay_parent() {
+    let mut server = start_server_debug().await;
+    let shard_id = server.add_shard().await.unwrap();
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .expect("can connect to shard");
+
+    let (_feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+    feed_rx.recv_feed_messages().await.unwrap();
+
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Parachain",
+                    "config":"",
+                    "genesis_hash": ghash(2),
+                    "relay_chain_genesis_hash": ghash(1),
+                    "para_id": 1000,
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name":"Alice",
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDE1",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                },
+            }
+        ))
+        .unwrap();
+
+    let relay_parent = FeedMessage::ChainRelayParent {
+        genesis_hash: ghash(2),
+        relay_chain_genesis_hash: ghash(1),
+        para_id: Some(1000),
+    };
+
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert!(feed_messages.contains(&relay_parent));
+
+    // A newly connected feed is told too:
+    let (_feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert!(feed_messages.contains(&relay_parent));
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
 /// If a node is added, a connecting feed should be told about the new chain.
 /// However, sending a duplicate "system.connected" message from the same node
 /// should not count as a new node but rather the second message should be ignored.
@@ -423,6 +547,67 @@ async fn e2e_feeds_told_about_chain_rename_and_stay_subscribed() {
     );
 }
 
+/// The feed client can subscribe to a chain by its label, and hands back the
+/// messages about it.
+#[tokio::test]
+async fn e2e_feed_client_can_subscribe_by_label() {
+    let mut server = start_server_debug().await;
+    let shard_id = server.add_shard().await.unwrap();
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .expect("can connect to shard");
+    node_tx
+        .send_json_text(json!({
+            "id":1,
+            "ts":"2021-07-12T10:37:47.714666+01:00",
+            "payload": {
+                "authority":true,
+                "chain":"Client chain",
+                "config":"",
+                "genesis_hash": ghash(1),
+                "implementation":"Substrate Node",
+                "msg":"system.connected",
+                "name":"Client node",
+                "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                "startup_time":"1625565542717",
+                "version":"2.0.0-07a1af348-aarch64-macos"
+            },
+        }))
+        .unwrap();
+
+    // Wait a little for the node to reach the core, so that the chain is listed:
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let uri = format!("ws://{}/feed", server.get_core().host())
+        .parse()
+        .unwrap();
+    let mut client = feed_client::FeedClient::connect(&uri).await.unwrap();
+    assert_eq!(client.version(), Some(35));
+    assert!(client.subscribe_by_label("Unknown chain").is_err());
+    assert_eq!(client.subscribe_by_label("Client chain").unwrap(), ghash(1));
+
+    let mut subscribed = false;
+    let added_node = loop {
+        let message = tokio::time::timeout(Duration::from_secs(10), client.next_message())
+            .await
+            .expect("message should arrive")
+            .unwrap();
+        match message {
+            FeedMessage::SubscribedTo { genesis_hash } => subscribed = genesis_hash == ghash(1),
+            FeedMessage::AddedNode { node, .. } => break node,
+            _ => {}
+        }
+    };
+    assert!(subscribed);
+    assert_eq!(added_node.name, "Client node");
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
 /// If we add a couple of shards and a node for each, all feeds should be
 /// told about both node chains. If one shard goes away, we should get a
 /// "removed chain" message only for the node connected to that shard.
@@ -561,7 +746,7 @@ async fn e2e_feed_can_subscribe_and_unsubscribe_from_chain() {
         feed_messages,
         SubscribedTo { genesis_hash } if genesis_hash == ghash(1),
         TimeSync {..},
-        BestBlock { block_number: 0, timestamp: 0, avg_block_time: None },
+        BestBlock { block_number: 0, timestamp: 0, avg_block_time: None, .. },
         BestFinalized { block_number: 0, .. },
         AddedNode { node_id: 0, node: NodeDetails { name, .. }, .. } if name == "Alice 1",
         FinalizedBlock { node_id: 0, block_number: 0, .. }
@@ -617,78 +802,119 @@ async fn e2e_feed_can_subscribe_and_unsubscribe_from_chain() {
     server.shutdown().await;
 }
 
-/// If a node sends more than some rolling average amount of data, it'll be booted.
+/// Feeds that subscribe to a chain are told about the best blocks it has seen recently.
 #[tokio::test]
-async fn e2e_node_banned_if_it_sends_too_much_data() {
-    async fn try_send_data(max_bytes: usize, send_msgs: usize, bytes_per_msg: usize) -> bool {
-        let mut server = start_server(
-            ServerOpts::default(),
-            CoreOpts::default(),
-            ShardOpts {
-                // Remember, this is (currently) averaged over the last 10 seconds,
-                // so we need to send 10x this amount of data for an imemdiate ban:
-                max_node_data_per_second: Some(max_bytes),
-                ..Default::default()
-            },
-        )
-        .await;
+async fn e2e_feed_sent_recent_blocks_on_subscribe() {
+    use FeedMessage::*;
 
-        // Give us a shard to talk to:
-        let shard_id = server.add_shard().await.unwrap();
-        let (node_tx, _node_rx) = server
-            .get_shard(shard_id)
-            .unwrap()
-            .connect_node()
-            .await
-            .unwrap();
+    let mut server = start_server_debug().await;
+    let shard_id = server.add_shard().await.unwrap();
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .unwrap();
 
-        // Send the data requested to the shard:
-        for _ in 0..send_msgs {
-            node_tx
-                .unbounded_send(SentMessage::Binary(vec![1; bytes_per_msg]))
-                .unwrap();
-        }
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Testnet",
+                    "config":"",
+                    "genesis_hash": ghash(1),
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name":"Alice",
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                },
+            }
+        ))
+        .unwrap();
 
-        // Wait a little for the shard to react and cut off the connection (or not):
-        tokio::time::sleep(Duration::from_millis(250)).await;
+    // Connect a feed and subscribe to the chain once it knows about it:
+    let (feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert_contains_matches!(feed_messages, AddedChain { genesis_hash, .. } if genesis_hash == ghash(1));
+
+    let subscribe = || {
+        feed_tx
+            .send_command(
+                "subscribe",
+                "0x0000000000000000000000000000000000000000000000000000000000000001",
+            )
+            .unwrap()
+    };
+    subscribe();
+    feed_rx.recv_feed_messages().await.unwrap();
 
-        // Has the connection been closed?
-        node_tx.is_closed()
+    // Import some blocks, and wait until we know they've all been seen:
+    for height in 1..=3 {
+        node_tx
+            .send_json_text(json!(
+                {
+                    "id":1,
+                    "ts":"2021-07-12T10:37:48.714666+01:00",
+                    "payload": {
+                        "best": ghash(100 + height),
+                        "height": height,
+                        "msg":"block.import",
+                        "origin":"Own"
+                    },
+                }
+            ))
+            .unwrap();
+    }
+    loop {
+        let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+        if feed_messages.iter().any(|msg| {
+            matches!(
+                msg,
+                BestBlock {
+                    block_number: 3,
+                    ..
+                }
+            )
+        }) {
+            break;
+        }
     }
 
+    // Subscribing again gives us the blocks we've just seen:
+    subscribe();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    let blocks = feed_messages
+        .into_iter()
+        .find_map(|msg| match msg {
+            RecentBlocks { blocks } => Some(blocks),
+            _ => None,
+        })
+        .expect("recent blocks sent on subscribe");
+    let blocks: Vec<_> = blocks
+        .iter()
+        .map(|b| (b.block_number, b.block_hash))
+        .collect();
     assert_eq!(
-        try_send_data(1000, 10, 1000).await,
-        false,
-        "shouldn't be closed; we didn't exceed 10x threshold"
-    );
-    assert_eq!(
-        try_send_data(999, 10, 1000).await,
-        true,
-        "should be closed; we sent just over 10x the block threshold"
+        blocks,
+        vec![(1, ghash(101)), (2, ghash(102)), (3, ghash(103))]
     );
+
+    // Tidy up:
+    server.shutdown().await;
 }
 
-/// Feeds will be disconnected if they can't receive messages quickly enough.
+/// Feeds that think they've missed something can ask to be sent everything again,
+/// without having to reconnect.
 #[tokio::test]
-async fn e2e_slow_feeds_are_disconnected() {
-    let mut server = start_server(
-        ServerOpts::default(),
-        // Timeout faster so the test can be quicker:
-        CoreOpts {
-            feed_timeout: Some(1),
-            ..Default::default()
-        },
-        // Allow us to send more messages in more easily:
-        ShardOpts {
-            max_nodes_per_connection: Some(100_000),
-            // Prevent the shard being being banned when it sends a load of data at once:
-            max_node_data_per_second: Some(100_000_000),
-            ..Default::default()
-        },
-    )
-    .await;
+async fn e2e_feed_can_resync() {
+    use FeedMessage::*;
 
-    // Give us a shard to talk to:
+    let mut server = start_server_debug().await;
     let shard_id = server.add_shard().await.unwrap();
     let (mut node_tx, _node_rx) = server
         .get_shard(shard_id)
@@ -697,32 +923,454 @@ async fn e2e_slow_feeds_are_disconnected() {
         .await
         .unwrap();
 
-    // Add a load of nodes from this shard so there's plenty of data to give to a feed.
-    // We want to exhaust any buffers between core and feed (eg BufWriters). If the number
-    // is too low, data will happily be sent into a buffer and the connection won't need to
-    // be closed.
-    for n in 1..100_000 {
-        node_tx
-            .send_json_text(json!({
-                "id":n,
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
                 "ts":"2021-07-12T10:37:47.714666+01:00",
                 "payload": {
                     "authority":true,
-                    "chain":"Polkadot",
+                    "chain":"Local Testnet",
                     "config":"",
-                    "genesis_hash": polkadot_genesis_hash(), // First party node connections aren't limited.
+                    "genesis_hash": ghash(1),
                     "implementation":"Substrate Node",
                     "msg":"system.connected",
-                    "name": format!("Alice {}", n),
+                    "name":"Alice",
                     "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
                     "startup_time":"1625565542717",
                     "version":"2.0.0-07a1af348-aarch64-macos"
-                }
-            }))
-            .unwrap();
-    }
-
-    // Connect a raw feed so that we can control how fast we consume data from the websocket
+                },
+            }
+        ))
+        .unwrap();
+
+    let (feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert_contains_matches!(feed_messages, AddedChain { genesis_hash, .. } if genesis_hash == ghash(1));
+
+    // Resyncing before subscribing to anything does nothing:
+    feed_tx.send_command("resync", "").unwrap();
+    feed_tx.send_command("ping", "before").unwrap();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert_eq!(
+        feed_messages,
+        vec![Pong {
+            msg: "before".to_owned()
+        }]
+    );
+
+    feed_tx
+        .send_command(
+            "subscribe",
+            "0x0000000000000000000000000000000000000000000000000000000000000001",
+        )
+        .unwrap();
+    feed_rx.recv_feed_messages().await.unwrap();
+
+    // Once subscribed, we're sent everything again:
+    feed_tx.send_command("resync", "").unwrap();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert_contains_matches!(
+        feed_messages,
+        SubscribedTo { genesis_hash } if genesis_hash == ghash(1),
+        AddedNode { node, .. } if node.name == "Alice",
+    );
+
+    // But not if we ask again too soon:
+    feed_tx.send_command("resync", "").unwrap();
+    feed_tx.send_command("ping", "after").unwrap();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert_eq!(
+        feed_messages,
+        vec![Pong {
+            msg: "after".to_owned()
+        }]
+    );
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
+/// Feeds can ask for an overview of every chain instead of subscribing to one, which is
+/// sent straight away and then every so often.
+#[tokio::test]
+async fn e2e_feed_can_ask_for_an_overview_of_every_chain() {
+    use FeedMessage::*;
+
+    let mut server = start_server_debug().await;
+    let shard_id = server.add_shard().await.unwrap();
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .unwrap();
+
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Testnet",
+                    "config":"",
+                    "genesis_hash": ghash(1),
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name":"Alice",
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                },
+            }
+        ))
+        .unwrap();
+
+    let (feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert_contains_matches!(feed_messages, AddedChain { genesis_hash, .. } if genesis_hash == ghash(1));
+
+    feed_tx
+        .send_command(
+            "subscribe",
+            "0x0000000000000000000000000000000000000000000000000000000000000001",
+        )
+        .unwrap();
+    feed_rx.recv_feed_messages().await.unwrap();
+
+    // Asking for the overview unsubscribes us from the chain:
+    feed_tx.send_command("overview", "").unwrap();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert_contains_matches!(
+        feed_messages,
+        UnsubscribedFrom { genesis_hash } if genesis_hash == ghash(1),
+        ChainOverviews { chains } if chains.len() == 1
+            && chains[0].genesis_hash == ghash(1)
+            && chains[0].label == "Local Testnet"
+            && chains[0].node_count == 1,
+    );
+
+    // The overview keeps coming without us asking again:
+    let feed_messages = tokio::time::timeout(Duration::from_secs(10), feed_rx.recv_feed_messages())
+        .await
+        .expect("overview should be sent again")
+        .unwrap();
+    assert_contains_matches!(feed_messages, ChainOverviews { .. });
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
+/// If a node sends more than some rolling average amount of data, it'll be booted.
+#[tokio::test]
+#[allow(clippy::bool_assert_comparison)]
+async fn e2e_node_banned_if_it_sends_too_much_data() {
+    async fn try_send_data(max_bytes: usize, send_msgs: usize, bytes_per_msg: usize) -> bool {
+        let mut server = start_server(
+            ServerOpts::default(),
+            CoreOpts::default(),
+            ShardOpts {
+                // Remember, this is (currently) averaged over the last 10 seconds,
+                // so we need to send 10x this amount of data for an imemdiate ban:
+                max_node_data_per_second: Some(max_bytes),
+                // The data we send isn't valid, but that's not what's being tested:
+                max_malformed_messages: Some(0),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // Give us a shard to talk to:
+        let shard_id = server.add_shard().await.unwrap();
+        let (node_tx, _node_rx) = server
+            .get_shard(shard_id)
+            .unwrap()
+            .connect_node()
+            .await
+            .unwrap();
+
+        // Send the data requested to the shard:
+        for _ in 0..send_msgs {
+            node_tx
+                .unbounded_send(SentMessage::Binary(vec![1; bytes_per_msg]))
+                .unwrap();
+        }
+
+        // Wait a little for the shard to react and cut off the connection (or not):
+        tokio::time::sleep(Duration::from_millis(250)).await;
+
+        // Has the connection been closed?
+        node_tx.is_closed()
+    }
+
+    assert_eq!(
+        try_send_data(1000, 10, 1000).await,
+        false,
+        "shouldn't be closed; we didn't exceed 10x threshold"
+    );
+    assert_eq!(
+        try_send_data(999, 10, 1000).await,
+        true,
+        "should be closed; we sent just over 10x the block threshold"
+    );
+}
+
+/// If a node sends a single message that's too large, its connection is dropped.
+#[tokio::test]
+async fn e2e_node_disconnected_if_it_sends_too_large_a_message() {
+    async fn try_send_message(max_message_size: usize, message_size: usize) -> bool {
+        let mut server = start_server(
+            ServerOpts::default(),
+            CoreOpts::default(),
+            ShardOpts {
+                max_node_message_size: Some(max_message_size),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // Give us a shard to talk to:
+        let shard_id = server.add_shard().await.unwrap();
+        let (node_tx, _node_rx) = server
+            .get_shard(shard_id)
+            .unwrap()
+            .connect_node()
+            .await
+            .unwrap();
+
+        node_tx
+            .unbounded_send(SentMessage::Binary(vec![1; message_size]))
+            .unwrap();
+
+        // Wait a little for the shard to react and cut off the connection (or not):
+        tokio::time::sleep(Duration::from_millis(250)).await;
+
+        // Has the connection been closed?
+        node_tx.is_closed()
+    }
+
+    assert!(
+        !try_send_message(1000, 1000).await,
+        "shouldn't be closed; the message wasn't too large"
+    );
+    assert!(
+        try_send_message(1000, 1001).await,
+        "should be closed; the message was just over the limit"
+    );
+}
+
+/// If a node sends too many messages in a row that can't be deserialized, its connection is dropped.
+#[tokio::test]
+async fn e2e_node_disconnected_if_it_sends_too_many_malformed_messages() {
+    async fn try_send_messages(max_malformed_messages: usize, malformed: usize) -> bool {
+        let mut server = start_server(
+            ServerOpts::default(),
+            CoreOpts::default(),
+            ShardOpts {
+                max_malformed_messages: Some(max_malformed_messages),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // Give us a shard to talk to:
+        let shard_id = server.add_shard().await.unwrap();
+        let (node_tx, _node_rx) = server
+            .get_shard(shard_id)
+            .unwrap()
+            .connect_node()
+            .await
+            .unwrap();
+
+        for _ in 0..malformed {
+            node_tx
+                .unbounded_send(SentMessage::Binary(b"not a node message".to_vec()))
+                .unwrap();
+        }
+
+        // Wait a little for the shard to react and cut off the connection (or not):
+        tokio::time::sleep(Duration::from_millis(250)).await;
+
+        // Has the connection been closed?
+        node_tx.is_closed()
+    }
+
+    assert!(
+        !try_send_messages(3, 2).await,
+        "shouldn't be closed; not enough malformed messages were sent"
+    );
+    assert!(
+        try_send_messages(3, 3).await,
+        "should be closed; too many malformed messages were sent"
+    );
+}
+
+/// If a node connection doesn't send anything for long enough, it's closed.
+#[tokio::test]
+async fn e2e_idle_node_connections_are_closed() {
+    async fn is_closed_after(send_every: Option<Duration>) -> bool {
+        let mut server = start_server(
+            ServerOpts::default(),
+            CoreOpts::default(),
+            ShardOpts {
+                node_idle_timeout_secs: Some(1),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // Give us a shard to talk to:
+        let shard_id = server.add_shard().await.unwrap();
+        let (node_tx, _node_rx) = server
+            .get_shard(shard_id)
+            .unwrap()
+            .connect_node()
+            .await
+            .unwrap();
+
+        // Stay quiet (or not) for a couple of seconds:
+        let quiet_for = Duration::from_millis(2000);
+        match send_every {
+            Some(interval) => {
+                let started = tokio::time::Instant::now();
+                while started.elapsed() < quiet_for {
+                    node_tx
+                        .unbounded_send(SentMessage::Binary(b"not a node message".to_vec()))
+                        .unwrap();
+                    tokio::time::sleep(interval).await;
+                }
+            }
+            None => tokio::time::sleep(quiet_for).await,
+        }
+
+        // Has the connection been closed?
+        node_tx.is_closed()
+    }
+
+    assert!(
+        !is_closed_after(Some(Duration::from_millis(300))).await,
+        "shouldn't be closed; messages kept coming in"
+    );
+    assert!(
+        is_closed_after(None).await,
+        "should be closed; nothing was sent"
+    );
+}
+
+/// If asked to, the shard tells a node why it was rejected before closing its connection.
+#[tokio::test]
+async fn e2e_rejected_nodes_are_told_why() {
+    let mut server = start_server(
+        ServerOpts::default(),
+        CoreOpts::default(),
+        ShardOpts {
+            close_rejected_connections: true,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let shard_id = server.add_shard().await.unwrap();
+    let (mut node_tx, mut node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .unwrap();
+
+    // A zero genesis hash is rejected by default:
+    node_tx
+        .send_json_text(json!({
+            "id":1,
+            "ts":"2021-07-12T10:37:47.714666+01:00",
+            "payload": {
+                "authority":true,
+                "chain":"Local Testnet",
+                "config":"",
+                "genesis_hash": ghash(0),
+                "implementation":"Substrate Node",
+                "msg":"system.connected",
+                "name":"Alice",
+                "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                "startup_time":"1625565542717",
+                "version":"2.0.0-07a1af348-aarch64-macos"
+            },
+        }))
+        .unwrap();
+
+    let msg = tokio::time::timeout(Duration::from_secs(5), node_rx.next())
+        .await
+        .expect("should be told why before the timeout")
+        .expect("connection shouldn't end before we're told why")
+        .expect("message should be received");
+    assert!(
+        matches!(&msg, RecvMessage::Text(text) if text == "rejected: invalid genesis hash"),
+        "unexpected message {msg:?}"
+    );
+
+    // Wait a little for the shard to close the connection:
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert!(node_tx.is_closed());
+
+    server.shutdown().await;
+}
+
+/// Feeds will be disconnected if they can't receive messages quickly enough.
+#[tokio::test]
+async fn e2e_slow_feeds_are_disconnected() {
+    let mut server = start_server(
+        ServerOpts::default(),
+        // Timeout faster so the test can be quicker:
+        CoreOpts {
+            feed_timeout: Some(1),
+            ..Default::default()
+        },
+        // Allow us to send more messages in more easily:
+        ShardOpts {
+            max_nodes_per_connection: Some(100_000),
+            // Prevent the shard being being banned when it sends a load of data at once:
+            max_node_data_per_second: Some(100_000_000),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    // Give us a shard to talk to:
+    let shard_id = server.add_shard().await.unwrap();
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .unwrap();
+
+    // Add a load of nodes from this shard so there's plenty of data to give to a feed.
+    // We want to exhaust any buffers between core and feed (eg BufWriters). If the number
+    // is too low, data will happily be sent into a buffer and the connection won't need to
+    // be closed.
+    for n in 1..100_000 {
+        node_tx
+            .send_json_text(json!({
+                "id":n,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Polkadot",
+                    "config":"",
+                    "genesis_hash": polkadot_genesis_hash(), // First party node connections aren't limited.
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name": format!("Alice {}", n),
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                }
+            }))
+            .unwrap();
+    }
+
+    // Connect a raw feed so that we can control how fast we consume data from the websocket
     let (mut raw_feed_tx, mut raw_feed_rx) = server.get_core().connect_feed_raw().await.unwrap();
 
     // Subscribe the feed:
@@ -924,3 +1572,217 @@ async fn e2e_max_nodes_per_connection_is_enforced() {
     // Tidy up:
     server.shutdown().await;
 }
+
+/// Nodes connected to one namespace can't be seen from another, neither by feeds nor
+/// in the list of chains.
+#[tokio::test]
+async fn e2e_namespaces_cant_see_each_others_nodes() {
+    // A loopback address other than the one the default namespace listens on, so that
+    // it's not mistaken for it when working out which port the core is listening on:
+    let namespace_addr = std::net::TcpListener::bind("127.0.0.2:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut server = start_server(
+        ServerOpts::default(),
+        CoreOpts {
+            namespaces: vec![format!("staging={namespace_addr}")],
+            ..Default::default()
+        },
+        ShardOpts::default(),
+    )
+    .await;
+    let shard_id = server.add_shard().await.unwrap();
+
+    let (_feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+    let (namespace_feed_tx, namespace_feed_rx) =
+        ws_client::connect(&format!("http://{namespace_addr}/feed").parse().unwrap())
+            .await
+            .unwrap()
+            .into_channels();
+    let (_namespace_feed_tx, mut namespace_feed_rx) = (
+        FeedSender::from(namespace_feed_tx),
+        FeedReceiver::from(namespace_feed_rx),
+    );
+
+    // Connect a node to the default namespace:
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .expect("can connect to shard");
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Testnet",
+                    "config":"",
+                    "genesis_hash": ghash(1),
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name":"Alice",
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                },
+            }
+        ))
+        .unwrap();
+
+    // Feeds on the default namespace are told about the chain:
+    let is_added_chain = |msg: &FeedMessage| matches!(msg, FeedMessage::AddedChain { genesis_hash, .. } if *genesis_hash == ghash(1));
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !feed_rx
+            .recv_feed_messages_once()
+            .await
+            .unwrap()
+            .iter()
+            .any(is_added_chain)
+        {}
+    })
+    .await
+    .expect("feeds on the default namespace should be told about the chain");
+
+    // But feeds on the other namespace aren't:
+    let namespace_feed_messages = namespace_feed_rx
+        .recv_feed_messages_timeout(Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert!(!namespace_feed_messages.iter().any(is_added_chain));
+
+    // And nor is the chain listed there:
+    let chains = |host: String| async move {
+        reqwest::get(format!("http://{host}/chains"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    };
+    let genesis_hash = format!("{:?}", ghash(1));
+    assert!(chains(server.get_core().host().to_owned())
+        .await
+        .contains(&genesis_hash));
+    assert!(!chains(namespace_addr.to_string())
+        .await
+        .contains(&genesis_hash));
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
+/// Two namespaces running side by side each only see the nodes that connect to them,
+/// both on their feeds and in their lists of chains.
+#[tokio::test]
+async fn e2e_namespaces_side_by_side_are_kept_apart() {
+    // Loopback addresses other than the one the default namespace listens on:
+    let addr = |ip: &str| {
+        std::net::TcpListener::bind((ip, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    };
+    let (addr_a, addr_b) = (addr("127.0.0.2"), addr("127.0.0.3"));
+    let mut server = start_server(
+        ServerOpts::default(),
+        CoreOpts {
+            namespaces: vec![format!("a={addr_a}"), format!("b={addr_b}")],
+            ..Default::default()
+        },
+        ShardOpts::default(),
+    )
+    .await;
+
+    let connect_feed = |addr: std::net::SocketAddr| async move {
+        let (tx, rx) = ws_client::connect(&format!("http://{addr}/feed").parse().unwrap())
+            .await
+            .unwrap()
+            .into_channels();
+        (FeedSender::from(tx), FeedReceiver::from(rx))
+    };
+    let (_feed_tx_a, mut feed_rx_a) = connect_feed(addr_a).await;
+    let (_feed_tx_b, mut feed_rx_b) = connect_feed(addr_b).await;
+
+    // Connect a node on a different chain to each namespace, via shards of their own:
+    let mut node_txs = Vec::new();
+    for (id, addr) in [(1, addr_a), (2, addr_b)] {
+        let shard_id = server.add_shard_for(&addr.to_string()).await.unwrap();
+        let (mut node_tx, node_rx) = server
+            .get_shard(shard_id)
+            .unwrap()
+            .connect_node()
+            .await
+            .expect("can connect to shard");
+        node_tx
+            .send_json_text(json!(
+                {
+                    "id":1,
+                    "ts":"2021-07-12T10:37:47.714666+01:00",
+                    "payload": {
+                        "authority":true,
+                        "chain":format!("Chain {id}"),
+                        "config":"",
+                        "genesis_hash": ghash(id),
+                        "implementation":"Substrate Node",
+                        "msg":"system.connected",
+                        "name":format!("Node {id}"),
+                        "network_id":format!("12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDE{id}"),
+                        "startup_time":"1625565542717",
+                        "version":"2.0.0-07a1af348-aarch64-macos"
+                    },
+                }
+            ))
+            .unwrap();
+        node_txs.push((node_tx, node_rx));
+    }
+
+    // Each feed is told about its own namespace's chain, and never the other's:
+    let added_chain = |msg: &FeedMessage| match msg {
+        FeedMessage::AddedChain { genesis_hash, .. } => Some(*genesis_hash),
+        _ => None,
+    };
+    for (feed_rx, own, other) in [
+        (&mut feed_rx_a, ghash(1), ghash(2)),
+        (&mut feed_rx_b, ghash(2), ghash(1)),
+    ] {
+        let mut chains = Vec::new();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !chains.contains(&own) {
+                let messages = feed_rx.recv_feed_messages_once().await.unwrap();
+                chains.extend(messages.iter().filter_map(added_chain));
+            }
+        })
+        .await
+        .expect("feeds should be told about their own namespace's chain");
+        let messages = feed_rx
+            .recv_feed_messages_timeout(Duration::from_secs(1))
+            .await
+            .unwrap();
+        chains.extend(messages.iter().filter_map(added_chain));
+        assert!(!chains.contains(&other));
+    }
+
+    // Each namespace only lists its own chain, and the default namespace neither:
+    let chains = |host: String| async move {
+        reqwest::get(format!("http://{host}/chains"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    };
+    let (hash_a, hash_b) = (format!("{:?}", ghash(1)), format!("{:?}", ghash(2)));
+    let chains_a = chains(addr_a.to_string()).await;
+    assert!(chains_a.contains(&hash_a) && !chains_a.contains(&hash_b));
+    let chains_b = chains(addr_b.to_string()).await;
+    assert!(chains_b.contains(&hash_b) && !chains_b.contains(&hash_a));
+    let chains_default = chains(server.get_core().host().to_owned()).await;
+    assert!(!chains_default.contains(&hash_a) && !chains_default.contains(&hash_b));
+
+    // Tidy up:
+    server.shutdown().await;
+}
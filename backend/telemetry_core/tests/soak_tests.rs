@@ -78,6 +78,7 @@ pub fn soak_test() {
 /// This test sends realistic messages from connected nodes
 /// so that we can see how things react under more normal
 /// circumstances
+#[allow(clippy::needless_update)]
 async fn run_soak_test(opts: SoakTestOpts) {
     let mut server = start_server(
         ServerOpts {
@@ -228,6 +229,7 @@ async fn run_soak_test(opts: SoakTestOpts) {
 }
 
 /// Return an iterator of `total` unique chain names.
+#[allow(clippy::redundant_static_lifetimes)]
 fn chain_names(total: usize) -> impl Iterator<Item = String> {
     static CHAIN_STARTS: [&'static str; 5] = ["Polkadot", "Kusama", "Khala", "Wibble", "Moonbase"];
     static CHAIN_ENDS: [&'static str; 6] = ["", " Testnet", " Main", "-Dev", "Alpha", "Beta"];
@@ -302,6 +304,7 @@ struct SoakTestOpts {
 }
 
 /// Get soak test args from an envvar and parse them via structopt.
+#[allow(clippy::useless_conversion)]
 fn get_soak_test_opts() -> SoakTestOpts {
     let arg_string = std::env::var("SOAK_TEST_ARGS")
         .expect("Expecting args to be provided in the env var SOAK_TEST_ARGS");
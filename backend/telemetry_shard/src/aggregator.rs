@@ -14,17 +14,23 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::connection::{create_ws_connection_to_core, Message};
+use crate::connection::{create_ws_connection_to_core, Message, ReconnectBackoff};
+use crate::json_message::ProtocolVersion;
 use common::{
-    internal_messages::{self, ShardNodeId},
+    internal_messages::{self, MuteReason, ShardNodeId},
     node_message,
     node_types::BlockHash,
     AssignId,
 };
 use futures::{Sink, SinkExt};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// How often to log which protocol versions the nodes that connected to each chain
+/// have been sending messages in.
+const PROTOCOL_VERSION_LOG_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
 /// A unique Id is assigned per websocket connection (or more accurately,
 /// per thing-that-subscribes-to-the-aggregator). That connection might send
@@ -37,6 +43,7 @@ type ConnId = u64;
 /// external messages are via subscriptions that take
 /// [`FromWebsocket`] instances.
 #[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
 enum ToAggregator {
     /// Sent when the telemetry core is disconnected.
     DisconnectedFromTelemetryCore,
@@ -46,6 +53,19 @@ enum ToAggregator {
     FromWebsocket(ConnId, FromWebsocket),
     /// Send when a message comes in from the telemetry core.
     FromTelemetryCore(internal_messages::FromTelemetryCore),
+    /// Sent periodically to log the protocol versions seen since the last time.
+    LogProtocolVersions,
+}
+
+/// Why the aggregator has asked a connection to close.
+#[derive(Clone, Debug)]
+pub enum CloseReason {
+    /// The telemetry core has (re)connected, so nodes need to introduce themselves again.
+    CoreReconnected,
+    /// The telemetry core muted a node on the connection, for this reason.
+    Muted(MuteReason),
+    /// The connection has ended anyway, so there's nothing more to receive from it.
+    ConnectionEnded,
 }
 
 /// An incoming socket connection can provide these messages.
@@ -59,7 +79,7 @@ pub enum FromWebsocket {
         /// the websocket connection and force the node to reconnect
         /// so that it sends its system info again incase the telemetry
         /// core has restarted.
-        close_connection: flume::Sender<()>,
+        close_connection: flume::Sender<CloseReason>,
     },
     /// Tell the aggregator about a new node.
     Add {
@@ -67,6 +87,7 @@ pub enum FromWebsocket {
         ip: std::net::IpAddr,
         node: common::node_types::NodeDetails,
         genesis_hash: BlockHash,
+        protocol_version: ProtocolVersion,
     },
     /// Update/pass through details about a node.
     Update {
@@ -77,6 +98,8 @@ pub enum FromWebsocket {
     Remove {
         message_id: node_message::NodeMessageId,
     },
+    /// Make a note when the connection sends a message that can't be deserialized.
+    Malformed,
     /// Make a note when the node disconnects.
     Disconnected,
 }
@@ -101,13 +124,19 @@ struct AggregatorInternal {
 }
 
 impl Aggregator {
-    /// Spawn a new Aggregator. This connects to the telemetry backend
-    pub async fn spawn(telemetry_uri: http::Uri) -> anyhow::Result<Aggregator> {
+    /// Spawn a new Aggregator. This connects to the telemetry backend, backing off
+    /// between attempts to reconnect as given. If `close_muted_connections` is true,
+    /// connections are closed when the core rejects any node on them.
+    pub async fn spawn(
+        telemetry_uri: http::Uri,
+        backoff: ReconnectBackoff,
+        close_muted_connections: bool,
+    ) -> anyhow::Result<Aggregator> {
         let (tx_to_aggregator, rx_from_external) = flume::bounded(10);
 
         // Establish a resilient connection to the core (this retries as needed):
         let (tx_to_telemetry_core, rx_from_telemetry_core) =
-            create_ws_connection_to_core(telemetry_uri).await;
+            create_ws_connection_to_core(telemetry_uri, backoff).await;
 
         // Forward messages from the telemetry core into the aggregator:
         let tx_to_aggregator2 = tx_to_aggregator.clone();
@@ -118,17 +147,39 @@ impl Aggregator {
                     Message::Disconnected => ToAggregator::DisconnectedFromTelemetryCore,
                     Message::Data(data) => ToAggregator::FromTelemetryCore(data),
                 };
-                if let Err(_) = tx_to_aggregator2.send_async(msg_to_aggregator).await {
+                if tx_to_aggregator2
+                    .send_async(msg_to_aggregator)
+                    .await
+                    .is_err()
+                {
                     // This will close the ws channels, which themselves log messages.
                     break;
                 }
             }
         });
 
+        // Periodically ask the aggregator to log the protocol versions it's seen:
+        let tx_to_aggregator2 = tx_to_aggregator.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PROTOCOL_VERSION_LOG_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if tx_to_aggregator2
+                    .send_async(ToAggregator::LogProtocolVersions)
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
         // Start our aggregator loop, handling any incoming messages:
         tokio::spawn(Aggregator::handle_messages(
             rx_from_external,
             tx_to_telemetry_core,
+            close_muted_connections,
         ));
 
         // Return a handle to our aggregator so that we can send in messages to it:
@@ -144,6 +195,7 @@ impl Aggregator {
     async fn handle_messages(
         rx_from_external: flume::Receiver<ToAggregator>,
         tx_to_telemetry_core: flume::Sender<FromAggregator>,
+        close_muted_connections: bool,
     ) {
         use internal_messages::{FromShardAggregator, FromTelemetryCore};
 
@@ -151,9 +203,9 @@ impl Aggregator {
         // or not, and ignore incoming messages while we aren't.
         let mut connected_to_telemetry_core = false;
 
-        // A list of close channels for the currently connected substrate nodes. Send an empty
-        // tuple to these to ask the connections to be closed.
-        let mut close_connections: HashMap<ConnId, flume::Sender<()>> = HashMap::new();
+        // A list of close channels for the currently connected substrate nodes. Send a
+        // reason to these to ask the connections to be closed.
+        let mut close_connections: HashMap<ConnId, flume::Sender<CloseReason>> = HashMap::new();
 
         // Maintain mappings from the connection ID and node message ID to the "local ID" which we
         // broadcast to the telemetry core.
@@ -162,6 +214,11 @@ impl Aggregator {
         // Any messages coming from nodes that have been muted are ignored:
         let mut muted: HashSet<ShardNodeId> = HashSet::new();
 
+        // How many nodes have been added in each protocol version, per chain, since we
+        // last logged them:
+        let mut protocol_versions: HashMap<BlockHash, BTreeMap<ProtocolVersion, usize>> =
+            HashMap::new();
+
         // Now, loop and receive messages to handle.
         while let Ok(msg) = rx_from_external.recv_async().await {
             match msg {
@@ -171,7 +228,7 @@ impl Aggregator {
 
                     for (_, closer) in closers {
                         // if this fails, it probably means the connection has died already anyway.
-                        let _ = closer.send_async(()).await;
+                        let _ = closer.send_async(CloseReason::CoreReconnected).await;
                     }
 
                     // We've told everything to disconnect. Now, reset our state:
@@ -200,10 +257,18 @@ impl Aggregator {
                     FromWebsocket::Add {
                         message_id,
                         ip,
-                        node,
+                        mut node,
                         genesis_hash,
+                        protocol_version,
                     },
                 ) => {
+                    node.protocol_version = Some(protocol_version.to_string().into());
+                    *protocol_versions
+                        .entry(genesis_hash)
+                        .or_default()
+                        .entry(protocol_version)
+                        .or_default() += 1;
+
                     // Don't bother doing anything else if we're disconnected, since we'll force the
                     // node to reconnect anyway when the backend does:
                     if !connected_to_telemetry_core {
@@ -270,6 +335,22 @@ impl Aggregator {
                             .await;
                     }
                 }
+                ToAggregator::FromWebsocket(conn_id, FromWebsocket::Malformed) => {
+                    if !connected_to_telemetry_core {
+                        continue;
+                    }
+
+                    // We can't tell which node the message was about, so blame the first
+                    // node on the connection if there is one (usually there's only one):
+                    let local_id = to_local_id
+                        .iter()
+                        .find(|(_, &(this_conn_id, _))| this_conn_id == conn_id)
+                        .map(|(local_id, _)| local_id);
+
+                    let _ = tx_to_telemetry_core
+                        .send_async(FromShardAggregator::MalformedMessage { local_id })
+                        .await;
+                }
                 ToAggregator::FromWebsocket(disconnected_conn_id, FromWebsocket::Disconnected) => {
                     // Find all of the local IDs corresponding to the disconnected connection ID and
                     // remove them, telling Telemetry Core about them too. This could be more efficient,
@@ -295,12 +376,32 @@ impl Aggregator {
                         }
                     }
                 }
-                ToAggregator::FromTelemetryCore(FromTelemetryCore::Mute {
-                    local_id,
-                    reason: _,
-                }) => {
+                ToAggregator::FromTelemetryCore(FromTelemetryCore::Mute { local_id, reason }) => {
                     // Mute the local ID we've been told to:
                     muted.insert(local_id);
+
+                    let conn_id = to_local_id
+                        .get_details(local_id)
+                        .map(|&(conn_id, _)| conn_id);
+                    log::debug!("Muting node {local_id:?} on connection {conn_id:?}: {reason}");
+
+                    // Let the node know why it was rejected by closing its connection, if we've
+                    // been asked to. Nodes muted later on are left be, since the connection is
+                    // likely to be shared with nodes that are still fine. This is best effort;
+                    // if the connection is already closing, so be it.
+                    if close_muted_connections && reason.is_rejection() {
+                        if let Some(closer) = conn_id.and_then(|id| close_connections.get(&id)) {
+                            let _ = closer.try_send(CloseReason::Muted(reason));
+                        }
+                    }
+                }
+                ToAggregator::LogProtocolVersions => {
+                    for (genesis_hash, versions) in protocol_versions.drain() {
+                        log::info!(
+                            "Protocol versions of nodes added for chain {genesis_hash:?}: {}",
+                            describe_protocol_versions(&versions)
+                        );
+                    }
                 }
             }
         }
@@ -325,3 +426,23 @@ impl Aggregator {
         )
     }
 }
+
+/// Describe how many nodes were seen in each protocol version, like "v1: 3, v2: 10".
+fn describe_protocol_versions(versions: &BTreeMap<ProtocolVersion, usize>) -> String {
+    versions
+        .iter()
+        .map(|(version, count)| format!("{version}: {count}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn protocol_versions_are_described_in_order() {
+        let versions = BTreeMap::from([(ProtocolVersion::V2, 10), (ProtocolVersion::V1, 3)]);
+        assert_eq!(describe_protocol_versions(&versions), "v1: 3, v2: 10");
+    }
+}
@@ -17,6 +17,8 @@
 use bincode::Options;
 use common::ws_client;
 use futures::StreamExt;
+use rand::Rng;
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Debug)]
 pub enum Message<Out> {
@@ -25,9 +27,54 @@ pub enum Message<Out> {
     Data(Out),
 }
 
+/// How long to wait before reconnecting to the core. Waits double after each failed
+/// attempt, so that shards don't all hammer a core that's restarting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReconnectBackoff {
+    /// How long to wait after the first failure.
+    pub initial: Duration,
+    /// Waits never get longer than this.
+    pub max: Duration,
+    /// Once a connection has stayed up this long, waits start from `initial` again.
+    pub stable_after: Duration,
+}
+
+/// Keeps track of how long to wait before the next reconnection attempt.
+struct Backoff {
+    config: ReconnectBackoff,
+    next: Duration,
+}
+
+impl Backoff {
+    fn new(config: ReconnectBackoff) -> Self {
+        Backoff {
+            config,
+            next: config.initial,
+        }
+    }
+
+    /// How long to wait before the next attempt: somewhere between half and all of the
+    /// current backoff, so that shards disconnected at the same time spread out.
+    fn next_wait(&mut self) -> Duration {
+        let wait = self.next;
+        self.next = (self.next * 2).min(self.config.max);
+        let half = wait / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=wait - half)
+    }
+
+    /// A connection that was up for `uptime` has ended; if it was stable, start
+    /// backing off from the beginning again.
+    fn disconnected_after(&mut self, uptime: Duration) {
+        if uptime >= self.config.stable_after {
+            self.next = self.config.initial;
+        }
+    }
+}
+
 /// Connect to the telemetry core, retrying the connection if we're disconnected.
 /// - Sends `Message::Connected` and `Message::Disconnected` when the connection goes up/down.
 /// - Returns a channel that allows you to send messages to the connection.
+/// - Reconnection attempts back off (with some jitter) according to `backoff`.
 /// - Messages are all encoded/decoded to/from bincode, and so need to support being (de)serialized from
 ///   a non self-describing encoding.
 ///
@@ -35,6 +82,7 @@ pub enum Message<Out> {
 /// between aggregator and core.
 pub async fn create_ws_connection_to_core<In, Out>(
     telemetry_uri: http::Uri,
+    backoff: ReconnectBackoff,
 ) -> (flume::Sender<In>, flume::Receiver<Message<Out>>)
 where
     In: serde::Serialize + Send + 'static,
@@ -44,13 +92,14 @@ where
     let (tx_out, rx_out) = flume::bounded(10);
 
     let mut is_connected = false;
+    let mut backoff = Backoff::new(backoff);
 
     tokio::spawn(async move {
         loop {
             // Throw away any pending messages from the incoming channel so that it
             // doesn't get filled up and begin blocking while we're looping and waiting
             // for a reconnection.
-            while let Ok(_) = rx_in.try_recv() {}
+            while rx_in.try_recv().is_ok() {}
 
             // Try to connect. If connection established, we serialize and forward messages
             // to/from the core. If the external channels break, we end for good. If the internal
@@ -58,6 +107,7 @@ where
             match ws_client::connect(&telemetry_uri).await {
                 Ok(connection) => {
                     let (tx_to_core, mut rx_from_core) = connection.into_channels();
+                    let connected_at = Instant::now();
                     is_connected = true;
                     let tx_out = tx_out.clone();
 
@@ -114,6 +164,8 @@ where
                             }
                         };
                     }
+
+                    backoff.disconnected_after(connected_at.elapsed());
                 }
                 Err(connect_err) => {
                     // Issue connecting? Wait and try again on the next loop iteration.
@@ -133,9 +185,48 @@ where
             }
 
             // Wait a little before we try to connect again.
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            let wait = backoff.next_wait();
+            log::info!("Reconnecting to core in {:?}", wait);
+            tokio::time::sleep(wait).await;
         }
     });
 
     (tx_in, rx_out)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_max_with_jitter() {
+        let mut backoff = Backoff::new(ReconnectBackoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(5),
+            stable_after: Duration::from_secs(30),
+        });
+        for max_wait in [1, 2, 4, 5, 5] {
+            let wait = backoff.next_wait();
+            let max_wait = Duration::from_secs(max_wait);
+            assert!(wait >= max_wait / 2 && wait <= max_wait, "{wait:?}");
+        }
+    }
+
+    #[test]
+    fn backoff_resets_after_a_stable_connection() {
+        let mut backoff = Backoff::new(ReconnectBackoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            stable_after: Duration::from_secs(30),
+        });
+        backoff.next_wait();
+        backoff.next_wait();
+
+        // A short lived connection doesn't reset anything:
+        backoff.disconnected_after(Duration::from_secs(10));
+        assert!(backoff.next_wait() >= Duration::from_secs(2));
+
+        backoff.disconnected_after(Duration::from_secs(30));
+        assert!(backoff.next_wait() <= Duration::from_secs(1));
+    }
+}
@@ -28,7 +28,7 @@ const HASH_BYTES: usize = 32;
 
 /// Newtype wrapper for 32-byte hash values, implementing readable `Debug` and `serde::Deserialize`.
 /// This can deserialize from a JSON string or array.
-#[derive(Hash, PartialEq, Eq, Clone, Copy)]
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Default)]
 pub struct Hash([u8; HASH_BYTES]);
 
 impl From<Hash> for common::node_types::BlockHash {
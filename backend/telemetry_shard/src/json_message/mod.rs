@@ -18,6 +18,6 @@
 
 mod hash;
 mod node_message;
+mod timestamp;
 
-pub use hash::Hash;
 pub use node_message::*;
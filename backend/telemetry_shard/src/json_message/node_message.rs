@@ -20,13 +20,61 @@
 //! compatibility with the input data when we make changes to our internal data
 //! structures (for example, to support bincode better).
 use super::hash::Hash;
+use super::timestamp;
 use common::node_message as internal;
 use common::node_types;
+use serde::de::IgnoredAny;
 use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Nodes can attach at most this many custom fields; any more are dropped.
+const MAX_CUSTOM_FIELDS: usize = 16;
+/// Custom field keys are cut short to this many characters.
+const MAX_CUSTOM_KEY_LEN: usize = 32;
+/// Custom field values are cut short to this many characters.
+const MAX_CUSTOM_VALUE_LEN: usize = 128;
+
+/// The versions of the wire format that nodes send telemetry messages in. During an
+/// upgrade we'll have nodes sending in more than one of these at once, so each message
+/// is checked for which it is and then handed to the deserializer for that version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolVersion {
+    /// The payload fields are at the top level of the message.
+    V1,
+    /// The payload is nested under a "payload" key, next to an "id" for the node that
+    /// it's about, so that one connection can send messages about several nodes.
+    V2,
+}
+
+impl ProtocolVersion {
+    /// Work out which version a message is in from its shape alone, so that a message
+    /// which fails to parse is reported against the version it was meant to be.
+    pub fn detect(bytes: &[u8]) -> Result<ProtocolVersion, serde_json::Error> {
+        #[derive(Deserialize)]
+        struct Envelope {
+            payload: Option<IgnoredAny>,
+        }
+
+        let envelope: Envelope = serde_json::from_slice(bytes)?;
+        Ok(match envelope.payload {
+            Some(_) => ProtocolVersion::V2,
+            None => ProtocolVersion::V1,
+        })
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolVersion::V1 => f.write_str("v1"),
+            ProtocolVersion::V2 => f.write_str("v2"),
+        }
+    }
+}
 
 /// This struct represents a telemetry message sent from a node as
 /// a JSON payload. Since JSON is self describing, we can use attributes
-/// like serde(untagged) and serde(flatten) without issue.
+/// like serde(tag) and serde(flatten) without issue.
 ///
 /// Internally, we want to minimise the amount of data sent from shards to
 /// the core node. For that reason, we use a non-self-describing serialization
@@ -36,26 +84,81 @@ use serde::Deserialize;
 ///
 /// So, this can be converted fairly cheaply into an enum we'll use internally
 /// which is compatible with formats like bincode.
-#[derive(Deserialize, Debug)]
-#[serde(untagged)]
+///
+/// `sent_at` is the unix time in ms that the node stamped the message with, if any.
+#[derive(Debug)]
 pub enum NodeMessage {
     V1 {
-        #[serde(flatten)]
+        sent_at: Option<u64>,
         payload: Payload,
     },
     V2 {
         id: NodeMessageId,
+        sent_at: Option<u64>,
         payload: Payload,
     },
 }
 
+impl NodeMessage {
+    /// Deserialize a message from JSON, in whichever protocol version it was sent in.
+    pub fn from_json(bytes: &[u8]) -> Result<NodeMessage, serde_json::Error> {
+        #[derive(Deserialize)]
+        struct V1 {
+            #[serde(default, deserialize_with = "timestamp::deserialize_unix_ms")]
+            ts: Option<u64>,
+            #[serde(flatten)]
+            payload: Payload,
+        }
+        #[derive(Deserialize)]
+        struct V2 {
+            id: NodeMessageId,
+            #[serde(default, deserialize_with = "timestamp::deserialize_unix_ms")]
+            ts: Option<u64>,
+            payload: Payload,
+        }
+
+        match ProtocolVersion::detect(bytes)? {
+            ProtocolVersion::V1 => {
+                let V1 { ts, payload } = serde_json::from_slice(bytes)?;
+                Ok(NodeMessage::V1 {
+                    sent_at: ts,
+                    payload,
+                })
+            }
+            ProtocolVersion::V2 => {
+                let V2 { id, ts, payload } = serde_json::from_slice(bytes)?;
+                Ok(NodeMessage::V2 {
+                    id,
+                    sent_at: ts,
+                    payload,
+                })
+            }
+        }
+    }
+
+    /// The protocol version that this message was sent in.
+    pub fn version(&self) -> ProtocolVersion {
+        match self {
+            NodeMessage::V1 { .. } => ProtocolVersion::V1,
+            NodeMessage::V2 { .. } => ProtocolVersion::V2,
+        }
+    }
+
+    /// The unix time in ms that the node says it sent this message at.
+    pub fn sent_at(&self) -> Option<u64> {
+        match self {
+            NodeMessage::V1 { sent_at, .. } | NodeMessage::V2 { sent_at, .. } => *sent_at,
+        }
+    }
+}
+
 impl From<NodeMessage> for internal::NodeMessage {
     fn from(msg: NodeMessage) -> Self {
         match msg {
-            NodeMessage::V1 { payload } => internal::NodeMessage::V1 {
+            NodeMessage::V1 { payload, .. } => internal::NodeMessage::V1 {
                 payload: payload.into(),
             },
-            NodeMessage::V2 { id, payload } => internal::NodeMessage::V2 {
+            NodeMessage::V2 { id, payload, .. } => internal::NodeMessage::V2 {
                 id,
                 payload: payload.into(),
             },
@@ -65,13 +168,14 @@ impl From<NodeMessage> for internal::NodeMessage {
 
 #[derive(Deserialize, Debug)]
 #[serde(tag = "msg")]
+#[allow(clippy::large_enum_variant)]
 pub enum Payload {
     #[serde(rename = "system.connected")]
     SystemConnected(SystemConnected),
     #[serde(rename = "system.interval")]
     SystemInterval(SystemInterval),
     #[serde(rename = "block.import")]
-    BlockImport(Block),
+    BlockImport(BlockImport),
     #[serde(rename = "notify.finalized")]
     NotifyFinalized(Finalized),
     #[serde(rename = "afg.authority_set")]
@@ -95,6 +199,9 @@ impl From<Payload> for internal::Payload {
 
 #[derive(Deserialize, Debug)]
 pub struct SystemConnected {
+    /// Misconfigured nodes sometimes leave this out. It's left as zero for the core to
+    /// decide what to do with them.
+    #[serde(default)]
     pub genesis_hash: Hash,
     #[serde(flatten)]
     pub node: NodeDetails,
@@ -120,6 +227,14 @@ pub struct SystemInterval {
     #[serde(flatten)]
     pub block: Option<Block>,
     pub used_state_cache_size: Option<f32>,
+    pub import_queue_depth: Option<u64>,
+    pub block_txcount: Option<u64>,
+    pub block_imports: Option<u64>,
+    pub block_import_failures: Option<u64>,
+    pub uptime: Option<u64>,
+    pub session: Option<u64>,
+    pub disk_used: Option<u64>,
+    pub disk_free: Option<u64>,
 }
 
 impl From<SystemInterval> for internal::SystemInterval {
@@ -133,6 +248,16 @@ impl From<SystemInterval> for internal::SystemInterval {
             finalized_hash: msg.finalized_hash.map(|h| h.into()),
             block: msg.block.map(|b| b.into()),
             used_state_cache_size: msg.used_state_cache_size,
+            import_queue_depth: msg.import_queue_depth,
+            block_txcount: msg.block_txcount,
+            block_imports: msg.block_imports,
+            block_import_failures: msg.block_import_failures,
+            uptime: msg.uptime,
+            session: msg.session,
+            disk_used: msg.disk_used,
+            disk_free: msg.disk_free,
+            // Worked out once we know when the message arrived:
+            clock_skew: None,
         }
     }
 }
@@ -166,6 +291,23 @@ impl From<AfgAuthoritySet> for internal::AfgAuthoritySet {
     }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct BlockImport {
+    #[serde(flatten)]
+    pub block: Block,
+    /// Who authored the block; only some nodes send this.
+    pub author: Option<Box<str>>,
+}
+
+impl From<BlockImport> for internal::BlockImport {
+    fn from(msg: BlockImport) -> Self {
+        internal::BlockImport {
+            block: msg.block.into(),
+            author: msg.author,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone, Copy)]
 pub struct Block {
     #[serde(rename = "best")]
@@ -249,6 +391,10 @@ pub struct NodeDetails {
     pub target_env: Option<Box<str>>,
     pub sysinfo: Option<NodeSysInfo>,
     pub ip: Option<Box<str>>,
+    pub relay_chain_genesis_hash: Option<Hash>,
+    pub para_id: Option<u32>,
+    pub role: Option<Box<str>>,
+    pub custom: Option<BTreeMap<Box<str>, serde_json::Value>>,
 }
 
 impl From<NodeDetails> for node_types::NodeDetails {
@@ -282,8 +428,52 @@ impl From<NodeDetails> for node_types::NodeDetails {
             target_env: details.target_env,
             sysinfo: details.sysinfo.map(|sysinfo| sysinfo.into()),
             ip: details.ip,
+            protocol_version: None,
+            relay_chain_genesis_hash: details.relay_chain_genesis_hash.map(|h| h.into()),
+            para_id: details.para_id,
+            role: details.role.as_deref().and_then(node_role),
+            custom: details
+                .custom
+                .map(sanitize_custom_fields)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// What sort of node a node is, going by the role it reports. Authorities are full nodes
+/// too, and roles that we don't recognise are left unknown.
+fn node_role(role: &str) -> Option<node_types::NodeRole> {
+    match &*role.trim().to_ascii_lowercase() {
+        "full" | "authority" | "validator" | "collator" => Some(node_types::NodeRole::Full),
+        "light" => Some(node_types::NodeRole::Light),
+        _ => None,
+    }
+}
+
+/// Keep the custom fields whose values are strings, without control characters or
+/// surrounding whitespace, and bound how many there are and how long they are.
+fn sanitize_custom_fields(
+    custom: BTreeMap<Box<str>, serde_json::Value>,
+) -> BTreeMap<Box<str>, Box<str>> {
+    let sanitize = |s: &str, max_len: usize| -> Box<str> {
+        let s: String = s.chars().filter(|c| !c.is_control()).collect();
+        s.trim().chars().take(max_len).collect()
+    };
+
+    let mut fields = BTreeMap::new();
+    for (key, value) in custom {
+        if fields.len() == MAX_CUSTOM_FIELDS {
+            break;
+        }
+        let serde_json::Value::String(value) = value else {
+            continue;
+        };
+        let key = sanitize(&key, MAX_CUSTOM_KEY_LEN);
+        if !key.is_empty() {
+            fields.insert(key, sanitize(&value, MAX_CUSTOM_VALUE_LEN));
         }
     }
+    fields
 }
 
 type NodeMessageId = u64;
@@ -350,8 +540,11 @@ mod tests {
         }"#;
         assert!(
             matches!(
-                serde_json::from_str::<NodeMessage>(json).unwrap(),
-                NodeMessage::V1 { .. },
+                NodeMessage::from_json(json.as_bytes()).unwrap(),
+                NodeMessage::V1 {
+                    sent_at: Some(1_610_537_905_410),
+                    ..
+                },
             ),
             "message did not match variant V1",
         );
@@ -370,8 +563,11 @@ mod tests {
         }"#;
         assert!(
             matches!(
-                serde_json::from_str::<NodeMessage>(json).unwrap(),
-                NodeMessage::V2 { .. },
+                NodeMessage::from_json(json.as_bytes()).unwrap(),
+                NodeMessage::V2 {
+                    sent_at: Some(1_610_536_940_053),
+                    ..
+                },
             ),
             "message did not match variant V2",
         );
@@ -394,9 +590,9 @@ mod tests {
         }"#;
         assert!(
             matches!(
-                serde_json::from_str::<NodeMessage>(json).unwrap(),
+                NodeMessage::from_json(json.as_bytes()).unwrap(),
                 NodeMessage::V2 {
-                    payload: Payload::BlockImport(Block { .. }),
+                    payload: Payload::BlockImport(BlockImport { author: None, .. }),
                     ..
                 },
             ),
@@ -404,6 +600,192 @@ mod tests {
         );
     }
 
+    #[test]
+    fn system_interval_uptime_is_parsed() {
+        let json = r#"{
+            "id":1,
+            "ts":"2021-01-13T12:22:20.053527101+01:00",
+            "payload":{
+                "msg":"system.interval",
+                "peers":12,
+                "uptime":3600
+            }
+        }"#;
+        assert!(
+            matches!(
+                NodeMessage::from_json(json.as_bytes()).unwrap(),
+                NodeMessage::V2 {
+                    payload: Payload::SystemInterval(SystemInterval {
+                        uptime: Some(3600),
+                        ..
+                    }),
+                    ..
+                },
+            ),
+            "message did not match the expected output",
+        );
+    }
+
+    #[test]
+    fn system_interval_session_is_parsed() {
+        let json = r#"{
+            "id":1,
+            "ts":"2021-01-13T12:22:20.053527101+01:00",
+            "payload":{
+                "msg":"system.interval",
+                "peers":12,
+                "session":4021
+            }
+        }"#;
+        assert!(
+            matches!(
+                NodeMessage::from_json(json.as_bytes()).unwrap(),
+                NodeMessage::V2 {
+                    payload: Payload::SystemInterval(SystemInterval {
+                        session: Some(4021),
+                        ..
+                    }),
+                    ..
+                },
+            ),
+            "message did not match the expected output",
+        );
+    }
+
+    #[test]
+    fn block_import_author_is_parsed() {
+        let json = r#"{
+            "id":1,
+            "ts":"2021-01-13T12:22:20.053527101+01:00",
+            "payload":{
+                "msg":"block.import",
+                "best":"0xcc41708573f2acaded9dd75e07dac2d4163d136ca35b3061c558d7a35a09dd8d",
+                "height": 1234,
+                "author":"5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+            }
+        }"#;
+        let author = match NodeMessage::from_json(json.as_bytes()).unwrap() {
+            NodeMessage::V2 {
+                payload: Payload::BlockImport(BlockImport { author, block }),
+                ..
+            } => {
+                assert_eq!(block.height, 1234);
+                author
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        };
+        assert_eq!(
+            author.as_deref(),
+            Some("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY")
+        );
+    }
+
+    #[test]
+    fn versions_are_detected_from_the_message_shape() {
+        let v1 = r#"{"msg":"notify.finalized","best":"0x00","height":"50","version":"1.0.0"}"#;
+        let v2 = r#"{"id":1,"payload":{"msg":"notify.finalized","best":"0x00","height":"50"}}"#;
+        assert_eq!(
+            ProtocolVersion::detect(v1.as_bytes()).unwrap(),
+            ProtocolVersion::V1
+        );
+        assert_eq!(
+            ProtocolVersion::detect(v2.as_bytes()).unwrap(),
+            ProtocolVersion::V2
+        );
+        assert!(ProtocolVersion::detect(b"[1,2]").is_err());
+
+        // A bad payload is reported as such, rather than as matching no version at all:
+        let bad_v2 = r#"{"id":1,"payload":{"msg":"notify.finalized"}}"#;
+        let err = NodeMessage::from_json(bad_v2.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("missing field"), "{err}");
+    }
+
+    #[test]
+    fn custom_fields_are_sanitized_and_bounded() {
+        let json = serde_json::json!({
+            "msg": "system.connected",
+            "genesis_hash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "chain": "Chain",
+            "name": "Node",
+            "implementation": "Substrate",
+            "version": "1.0.0",
+            "network_id": "network",
+            "custom": {
+                "datacenter": "  fra1\n",
+                "role": "collator",
+                "weight": 3,
+                "  ": "blank",
+                "long": "x".repeat(1000),
+            },
+        });
+        let Payload::SystemConnected(connected) = serde_json::from_value(json).unwrap() else {
+            panic!("expected system.connected");
+        };
+        let details: node_types::NodeDetails = connected.node.into();
+
+        let fields: Vec<_> = details
+            .custom
+            .iter()
+            .map(|(k, v)| (&**k, v.len()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("datacenter", 4),
+                ("long", MAX_CUSTOM_VALUE_LEN),
+                ("role", 8)
+            ]
+        );
+        assert_eq!(&*details.custom["datacenter"], "fra1");
+
+        let many: BTreeMap<Box<str>, serde_json::Value> = (0..100)
+            .map(|n| (n.to_string().into(), "value".into()))
+            .collect();
+        assert_eq!(sanitize_custom_fields(many).len(), MAX_CUSTOM_FIELDS);
+    }
+
+    #[test]
+    fn node_roles_are_parsed() {
+        let role = |role: serde_json::Value| {
+            let json = serde_json::json!({
+                "msg": "system.connected",
+                "chain": "Chain",
+                "name": "Node",
+                "implementation": "Substrate",
+                "version": "1.0.0",
+                "network_id": "network",
+                "role": role,
+            });
+            let Payload::SystemConnected(connected) = serde_json::from_value(json).unwrap() else {
+                panic!("expected system.connected");
+            };
+            node_types::NodeDetails::from(connected.node).role
+        };
+        assert_eq!(role("LIGHT".into()), Some(node_types::NodeRole::Light));
+        assert_eq!(role("full".into()), Some(node_types::NodeRole::Full));
+        assert_eq!(role("authority".into()), Some(node_types::NodeRole::Full));
+        assert_eq!(role("archive-ish".into()), None);
+        assert_eq!(role(serde_json::Value::Null), None);
+    }
+
+    #[test]
+    fn missing_genesis_hashes_are_left_as_zero() {
+        let json = serde_json::json!({
+            "msg": "system.connected",
+            "chain": "Chain",
+            "name": "Node",
+            "implementation": "Substrate",
+            "version": "1.0.0",
+            "network_id": "network",
+        });
+        let Payload::SystemConnected(connected) = serde_json::from_value(json).unwrap() else {
+            panic!("expected system.connected");
+        };
+        assert!(internal::SystemConnected::from(connected)
+            .genesis_hash
+            .is_zero());
+    }
+
     #[test]
     fn split_old_style_version_works() {
         let (version, target_arch, target_os, target_env) =
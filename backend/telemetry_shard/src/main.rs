@@ -19,7 +19,7 @@ mod aggregator;
 mod blocked_addrs;
 mod connection;
 mod json_message;
-mod real_ip;
+mod simulate;
 
 use std::{
     collections::HashMap,
@@ -27,17 +27,21 @@ use std::{
     time::{Duration, Instant},
 };
 
-use aggregator::{Aggregator, FromWebsocket};
+use aggregator::{Aggregator, CloseReason, FromWebsocket};
 use blocked_addrs::BlockedAddrs;
 use common::byte_size::ByteSize;
 use common::http_utils;
 use common::node_message;
 use common::node_message::NodeMessageId;
+use common::real_ip;
 use common::rolling_total::RollingTotalBuilder;
+use common::ConnectionLimits;
+use connection::ReconnectBackoff;
 use futures::{SinkExt, StreamExt};
 use http::Uri;
 use hyper::{Method, Response};
 use simple_logger::SimpleLogger;
+use simulate::SimulateOpts;
 use structopt::StructOpt;
 
 #[cfg(not(target_env = "msvc"))]
@@ -88,6 +92,15 @@ struct Opts {
     /// traffic on average (at least initially).
     #[structopt(long, default_value = "256k")]
     max_node_data_per_second: ByteSize,
+    /// The largest single message that a node is allowed to send. A connection that sends
+    /// a larger message is dropped before the message is read into memory.
+    #[structopt(long, default_value = "256k")]
+    max_node_message_size: ByteSize,
+    /// A connection from a node that sends this many messages in a row that can't be
+    /// deserialized is dropped; it's likely running an incompatible version or isn't a
+    /// node at all. 0 means that such connections are never dropped.
+    #[structopt(long, default_value = "10")]
+    max_malformed_messages: usize,
     /// How many seconds is a "/feed" connection that violates the '--max-node-data-per-second'
     /// value prevented from reconnecting to this shard for, in seconds.
     #[structopt(long, default_value = "600")]
@@ -102,6 +115,66 @@ struct Opts {
     /// dropped.
     #[structopt(long, default_value = "60")]
     stale_node_timeout: u64,
+    /// If given, /submit connections from an IP address that already has this many open are
+    /// refused.
+    #[structopt(long)]
+    max_connections_per_ip: Option<usize>,
+    /// Space delimited list of IP addresses that any number of /submit connections are allowed
+    /// from, regardless of --max-connections-per-ip.
+    #[structopt(long, required = false)]
+    connection_limit_exempt_ip: Vec<std::net::IpAddr>,
+    /// How many milliseconds to wait before reconnecting to the core the first time the
+    /// connection fails. Each further failure doubles this (with some jitter), so that
+    /// shards don't all reconnect at once when the core restarts.
+    #[structopt(long, default_value = "1000")]
+    core_reconnect_initial_ms: u64,
+    /// The longest to wait, in seconds, between attempts to reconnect to the core.
+    #[structopt(long, default_value = "60")]
+    core_reconnect_max_secs: u64,
+    /// Once the connection to the core has stayed up for this many seconds, waits between
+    /// reconnection attempts start from '--core-reconnect-initial-ms' again.
+    #[structopt(long, default_value = "30")]
+    core_reconnect_stable_secs: u64,
+    /// Every this many seconds, ping each /submit connection to measure the latency to the
+    /// nodes on it, which is passed on to the core. 0 disables pings.
+    #[structopt(long, default_value = "30")]
+    node_ping_secs: u64,
+    /// Close /submit connections that haven't sent any messages for this many seconds,
+    /// removing their nodes, rather than leaving them open until their nodes go stale.
+    /// Replies to pings don't count. 0 leaves idle connections open.
+    #[structopt(long, default_value = "0")]
+    node_idle_timeout_secs: u64,
+    /// When the core rejects a node (for instance because its chain is overquota), tell it
+    /// why in a final "rejected: <reason>" text message and close its connection, so that
+    /// the reason shows up in the node's logs. This closes the whole connection, so any
+    /// other nodes sharing it will need to reconnect. Nodes that the core stops listening
+    /// to later on, such as stale nodes, don't have their connections closed.
+    #[structopt(long)]
+    close_rejected_connections: bool,
+    /// Simulate this many nodes on a chain of their own, as a self test or to demo the UI
+    /// without any real nodes. They're handed on to the core just like real ones.
+    /// 0 disables the simulation.
+    #[structopt(long, default_value = "0")]
+    simulate_nodes: usize,
+    /// The name of the simulated chain.
+    #[structopt(long, default_value = "Simulated")]
+    simulate_chain: String,
+    /// How many milliseconds each simulated block takes.
+    #[structopt(long, default_value = "6000")]
+    simulate_block_time_ms: u64,
+    /// The percentage of simulated nodes that go quiet for a while or reconnect each minute.
+    #[structopt(long, default_value = "5")]
+    simulate_churn_percent: u32,
+}
+
+/// What the task receiving from a node's websocket connection hands on.
+enum Received {
+    /// A message from the node.
+    Data(Vec<u8>),
+    /// A reply to one of our pings.
+    Pong,
+    /// The aggregator asked us to close the connection, for this reason.
+    Close(CloseReason),
 }
 
 fn main() {
@@ -138,15 +211,42 @@ fn main() {
 /// Declare our routes and start the server.
 async fn start_server(opts: Opts) -> anyhow::Result<()> {
     let block_list = BlockedAddrs::new(Duration::from_secs(opts.node_block_seconds));
-    let aggregator = Aggregator::spawn(opts.core_url).await?;
+    let backoff = ReconnectBackoff {
+        initial: Duration::from_millis(opts.core_reconnect_initial_ms),
+        max: Duration::from_secs(opts.core_reconnect_max_secs),
+        stable_after: Duration::from_secs(opts.core_reconnect_stable_secs),
+    };
+    let aggregator =
+        Aggregator::spawn(opts.core_url, backoff, opts.close_rejected_connections).await?;
+    if opts.simulate_nodes > 0 {
+        simulate::spawn_simulation(
+            SimulateOpts {
+                nodes: opts.simulate_nodes,
+                chain: opts.simulate_chain,
+                block_time: Duration::from_millis(opts.simulate_block_time_ms),
+                churn_percent: opts.simulate_churn_percent,
+            },
+            aggregator.clone(),
+        );
+    }
     let socket_addr = opts.socket;
     let max_nodes_per_connection = opts.max_nodes_per_connection;
     let bytes_per_second = opts.max_node_data_per_second;
+    let max_message_size = opts.max_node_message_size.num_bytes();
+    let max_malformed_messages = opts.max_malformed_messages;
     let stale_node_timeout = Duration::from_secs(opts.stale_node_timeout);
+    let node_ping_interval = Duration::from_secs(opts.node_ping_secs);
+    let node_idle_timeout = Duration::from_secs(opts.node_idle_timeout_secs);
+    let connection_limits = ConnectionLimits::new(
+        opts.max_connections_per_ip.unwrap_or(usize::MAX),
+        usize::MAX,
+        opts.connection_limit_exempt_ip,
+    );
 
     let server = http_utils::start_server(socket_addr, move |addr, req| {
         let aggregator = aggregator.clone();
         let block_list = block_list.clone();
+        let connection_limits = connection_limits.clone();
         async move {
             match (req.method(), req.uri().path().trim_end_matches('/')) {
                 // Check that the server is up and running:
@@ -159,9 +259,26 @@ async fn start_server(opts: Opts) -> anyhow::Result<()> {
                         return Ok(Response::builder().status(403).body(reason.into()).unwrap());
                     }
 
-                    Ok(http_utils::upgrade_to_websocket(
+                    let open_connection = match connection_limits.open(real_addr) {
+                        Ok(open_connection) => open_connection,
+                        Err(_) => {
+                            log::info!(
+                                "Refusing /submit connection from {:?}; too many open",
+                                real_addr
+                            );
+                            return Ok(Response::builder()
+                                .status(429)
+                                .body("Too many connections".into())
+                                .unwrap());
+                        }
+                    };
+
+                    Ok(http_utils::upgrade_to_websocket_with_max_message_size(
                         req,
+                        Some(max_message_size),
                         move |ws_send, ws_recv| async move {
+                            // Counts against the limits until the connection closes:
+                            let _open_connection = open_connection;
                             log::info!(
                                 "Opening /submit connection from {:?} (address source: {})",
                                 real_addr,
@@ -176,8 +293,11 @@ async fn start_server(opts: Opts) -> anyhow::Result<()> {
                                     tx_to_aggregator,
                                     max_nodes_per_connection,
                                     bytes_per_second,
+                                    max_malformed_messages,
                                     block_list,
                                     stale_node_timeout,
+                                    node_ping_interval,
+                                    node_idle_timeout,
                                 )
                                 .await;
                             log::info!(
@@ -205,15 +325,19 @@ async fn start_server(opts: Opts) -> anyhow::Result<()> {
 }
 
 /// This takes care of handling messages from an established socket connection.
+#[allow(clippy::too_many_arguments)]
 async fn handle_node_websocket_connection<S>(
     real_addr: IpAddr,
-    ws_send: http_utils::WsSender,
+    mut ws_send: http_utils::WsSender,
     mut ws_recv: http_utils::WsReceiver,
     mut tx_to_aggregator: S,
     max_nodes_per_connection: usize,
     bytes_per_second: ByteSize,
+    max_malformed_messages: usize,
     block_list: BlockedAddrs,
     stale_node_timeout: Duration,
+    ping_interval: Duration,
+    idle_timeout: Duration,
 ) -> (S, http_utils::WsSender)
 where
     S: futures::Sink<FromWebsocket, Error = anyhow::Error> + Unpin + Send + 'static,
@@ -230,6 +354,9 @@ where
         .window_size_multiple(10)
         .start();
 
+    // How many messages in a row have we failed to deserialize?
+    let mut malformed_messages = 0;
+
     // This could be a oneshot channel, but it's useful to be able to clone
     // messages, and we can't clone oneshot channel senders.
     let (close_connection_tx, close_connection_rx) = flume::bounded(1);
@@ -251,22 +378,37 @@ where
         loop {
             let mut bytes = Vec::new();
             tokio::select! {
-                // The close channel has fired, so end the loop. `ws_recv.receive_data` is
+                // The close channel has fired, so end the loop. `ws_recv.receive` is
                 // *not* cancel safe, but since we're closing the connection we don't care.
-                _ = close_connection_rx.recv_async() => {
+                reason = close_connection_rx.recv_async() => {
                     log::info!("connection to {real_addr:?} being closed");
+                    if let Ok(reason) = reason {
+                        let _ = ws_tx_atomic.unbounded_send(Received::Close(reason));
+                    }
                     break
                 },
-                // Receive data and relay it on to our main select loop below.
-                msg_info = ws_recv.receive_data(&mut bytes) => {
-                    if let Err(soketto::connection::Error::Closed) = msg_info {
+                // Receive data (and replies to our pings) and relay it on to our main
+                // select loop below.
+                msg_info = ws_recv.receive(&mut bytes) => {
+                    if let Err(soketto::connection::Error::Closed) | Ok(soketto::Incoming::Closed(_)) = msg_info {
                         break;
                     }
-                    if let Err(e) = msg_info {
-                        log::error!("Shutting down websocket connection from {real_addr:?}: Failed to receive data: {e}");
+                    if let Err(
+                        e @ (soketto::connection::Error::MessageTooLarge { .. }
+                        | soketto::connection::Error::Codec(soketto::base::Error::PayloadTooLarge { .. }))
+                    ) = msg_info {
+                        log::warn!("Shutting down websocket connection from {real_addr:?}: {e} (see --max-node-message-size)");
                         break;
                     }
-                    if ws_tx_atomic.unbounded_send(bytes).is_err() {
+                    let received = match msg_info {
+                        Ok(soketto::Incoming::Pong(_)) => Received::Pong,
+                        Ok(_) => Received::Data(bytes),
+                        Err(e) => {
+                            log::error!("Shutting down websocket connection from {real_addr:?}: Failed to receive data: {e}");
+                            break;
+                        }
+                    };
+                    if ws_tx_atomic.unbounded_send(received).is_err() {
                         // The other end closed; end this loop.
                         break;
                     }
@@ -278,6 +420,16 @@ where
     // A periodic interval to check for stale nodes.
     let mut stale_interval = tokio::time::interval(stale_node_timeout / 2);
 
+    // A periodic interval to ping the connection, and when the unanswered ping was sent.
+    // The interval isn't used if pings are disabled.
+    let pings_enabled = !ping_interval.is_zero();
+    let mut ping_interval = tokio::time::interval(ping_interval.max(Duration::from_secs(1)));
+    let mut ping_sent_at: Option<Instant> = None;
+
+    // When the connection last sent us a message, to close it if it goes quiet for too long.
+    let idle_timeout_enabled = !idle_timeout.is_zero();
+    let mut last_message_at = Instant::now();
+
     // Our main select loop atomically receives and handles telemetry messages from the node,
     // and periodically checks for stale connections to keep our node state tidy.
     loop {
@@ -303,13 +455,54 @@ where
                     break;
                 }
             },
+            // Close the connection if nothing at all has come in for a while. Closing it
+            // tells the aggregator, which removes any nodes that were on it.
+            _ = tokio::time::sleep_until((last_message_at + idle_timeout).into()), if idle_timeout_enabled => {
+                log::info!("Closing idle connection from {real_addr:?}; nothing received for {idle_timeout:?}");
+                break;
+            },
+            // Ping the connection to find out how far away its nodes are. There's no point
+            // until it's told us about a node, or while an earlier ping is unanswered.
+            _ = ping_interval.tick(), if pings_enabled => {
+                if allowed_message_ids.is_empty() || ping_sent_at.is_some() {
+                    continue;
+                }
+                let payload = soketto::data::ByteSlice125::try_from(&[][..]).expect("empty payloads fit");
+                let ping = ws_send.send_ping(payload).await;
+                if let Err(e) = ping.and(ws_send.flush().await) {
+                    log::error!("Shutting down websocket connection from {real_addr:?}: Failed to send ping: {e}");
+                    break;
+                }
+                ping_sent_at = Some(Instant::now());
+            },
             // Handle messages received by the connected node.
             msg = ws_rx_atomic.next() => {
                 // No more messages? break.
                 let bytes = match msg {
-                    Some(bytes) => bytes,
-                    None => { break; }
+                    Some(Received::Data(bytes)) => bytes,
+                    Some(Received::Pong) => {
+                        // Every node on the connection shares its latency:
+                        if let Some(sent_at) = ping_sent_at.take() {
+                            let latency = sent_at.elapsed().as_millis() as u64;
+                            for &message_id in allowed_message_ids.keys() {
+                                let payload = node_message::Payload::PingLatency(latency);
+                                let _ = tx_to_aggregator.send(FromWebsocket::Update { message_id, payload }).await;
+                            }
+                        }
+                        continue;
+                    }
+                    Some(Received::Close(CloseReason::Muted(reason))) => {
+                        // Tell the node why before hanging up, so that it ends up in its logs:
+                        log::info!("Closing connection from {real_addr:?}: node rejected ({reason})");
+                        let sent = ws_send.send_text(format!("rejected: {reason}")).await;
+                        if let Err(e) = sent.and(ws_send.flush().await) {
+                            log::error!("Failed to tell {real_addr:?} why it was rejected: {e}");
+                        }
+                        break;
+                    }
+                    Some(Received::Close(_)) | None => { break; }
                 };
+                last_message_at = Instant::now();
 
                 // Keep track of total bytes and bail if average over last 10 secs exceeds preference.
                 rolling_total_bytes.push(bytes.len());
@@ -320,26 +513,42 @@ where
                     break;
                 }
 
-                // Deserialize from JSON, warning in debug mode if deserialization fails:
-                let node_message: json_message::NodeMessage = match serde_json::from_slice(&bytes) {
-                    Ok(node_message) => node_message,
-                    #[cfg(debug)]
-                    Err(e) => {
-                        let bytes: &[u8] = bytes.get(..512).unwrap_or_else(|| &bytes);
-                        let msg_start = std::str::from_utf8(bytes).unwrap_or_else(|_| "INVALID UTF8");
-                        log::warn!("Failed to parse node message ({msg_start}): {e}");
-                        continue;
+                // Deserialize from JSON, warning in debug mode if deserialization fails, and
+                // dropping the connection if it keeps on failing:
+                let node_message = match json_message::NodeMessage::from_json(&bytes) {
+                    Ok(node_message) => {
+                        malformed_messages = 0;
+                        node_message
                     },
-                    #[cfg(not(debug))]
-                    Err(_) => {
+                    Err(e) => {
+                        #[cfg(debug)]
+                        {
+                            let bytes: &[u8] = bytes.get(..512).unwrap_or_else(|| &bytes);
+                            let msg_start = std::str::from_utf8(bytes).unwrap_or_else(|_| "INVALID UTF8");
+                            log::warn!("Failed to parse node message ({msg_start}): {e}");
+                        }
+                        let _ = tx_to_aggregator.send(FromWebsocket::Malformed).await;
+                        malformed_messages += 1;
+                        if max_malformed_messages > 0 && malformed_messages >= max_malformed_messages {
+                            log::warn!("Shutting down websocket connection from {real_addr:?}: {malformed_messages} malformed messages in a row (last error: {e})");
+                            break;
+                        }
                         continue;
                     }
                 };
 
                 // Pull relevant details from the message:
+                let protocol_version = node_message.version();
+                let sent_at = node_message.sent_at();
                 let node_message: node_message::NodeMessage = node_message.into();
                 let message_id = node_message.id();
-                let payload = node_message.into_payload();
+                let mut payload = node_message.into_payload();
+
+                // Compare the time the node says it sent the interval at with ours, so that
+                // the core can tell how far off the node's clock is:
+                if let (node_message::Payload::SystemInterval(interval), Some(sent_at)) = (&mut payload, sent_at) {
+                    interval.clock_skew = Some(sent_at as i64 - common::time::now() as i64);
+                }
 
                 // Until the aggregator receives an `Add` message, which we can create once
                 // we see one of these SystemConnected ones, it will ignore messages with
@@ -365,6 +574,7 @@ where
                         ip: real_addr,
                         node: info.node,
                         genesis_hash: info.genesis_hash,
+                        protocol_version,
                     }).await;
                 }
                 // Anything that's not an "Add" is an Update. The aggregator will ignore
@@ -386,7 +596,7 @@ where
     }
 
     // Make sure to kill off the receive-messages task if the main select loop ends:
-    let _ = close_connection_tx.send(());
+    let _ = close_connection_tx.try_send(CloseReason::ConnectionEnded);
 
     // Return what we need to close the connection gracefully:
     (tx_to_aggregator, ws_send)
@@ -0,0 +1,441 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2023 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Simulate a chain of fake nodes, for checking that a deployment works end to end (and
+//! filling its UI with believable data) without any real nodes. The nodes are handed to
+//! the aggregator exactly as if they'd connected to `/submit`, so everything from there
+//! on is the real thing.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use common::node_message::{self, Payload};
+use common::node_types::{Block, BlockHash, NetworkId, NodeDetails, NodeRole, NodeSysInfo};
+use futures::{Sink, SinkExt};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::aggregator::{Aggregator, CloseReason, FromWebsocket};
+use crate::json_message::ProtocolVersion;
+
+/// Every simulated node is on a connection of its own, so they can share a message ID.
+const MESSAGE_ID: node_message::NodeMessageId = 1;
+/// How many blocks behind the best block the finalized block is.
+const FINALITY_LAG: u64 = 2;
+/// Every this many blocks, nodes report their peers, bandwidth and so on.
+const INTERVAL_EVERY_BLOCKS: u64 = 2;
+/// Nodes that go quiet stay quiet for somewhere in this many blocks.
+const QUIET_BLOCKS: std::ops::Range<u64> = 5..30;
+
+#[derive(Debug, Clone)]
+pub struct SimulateOpts {
+    /// How many nodes to simulate.
+    pub nodes: usize,
+    /// The name of the chain that the nodes are on.
+    pub chain: String,
+    /// How long each block takes.
+    pub block_time: Duration,
+    /// The percentage of nodes that go quiet for a while or reconnect each minute.
+    pub churn_percent: u32,
+}
+
+/// Something that a simulated node does.
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum SimEvent {
+    /// The node connects and introduces itself.
+    Connect {
+        genesis_hash: BlockHash,
+        node: NodeDetails,
+    },
+    /// The node sends a message.
+    Update(Payload),
+    /// The node's connection closes.
+    Disconnect,
+}
+
+/// How a simulated node is currently behaving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeState {
+    /// Importing blocks and reporting on itself.
+    Live,
+    /// Connected, but not sending anything until the best block reaches this height.
+    Quiet { until: u64 },
+}
+
+/// A chain of simulated nodes, which is moved on a block at a time.
+pub struct Simulation {
+    opts: SimulateOpts,
+    genesis_hash: BlockHash,
+    best: u64,
+    nodes: Vec<NodeState>,
+    rng: StdRng,
+}
+
+impl Simulation {
+    pub fn new(opts: SimulateOpts, seed: u64) -> Self {
+        let mut hasher = DefaultHasher::new();
+        opts.chain.hash(&mut hasher);
+        // A zero genesis hash is rejected, so make sure that we don't end up with one:
+        let genesis_hash = BlockHash::from_low_u64_be(hasher.finish() | 1);
+        Simulation {
+            nodes: vec![NodeState::Live; opts.nodes],
+            opts,
+            genesis_hash,
+            best: 0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// The hash of the block at the given height, which is the genesis hash at height 0.
+    fn block_hash(&self, height: u64) -> BlockHash {
+        self.genesis_hash ^ BlockHash::from_low_u64_be(height)
+    }
+
+    fn block(&self, height: u64) -> Block {
+        Block {
+            hash: self.block_hash(height),
+            height,
+        }
+    }
+
+    /// Connect every node.
+    pub fn start(&mut self) -> Vec<(usize, SimEvent)> {
+        let mut events = Vec::new();
+        for idx in 0..self.nodes.len() {
+            self.connect(idx, &mut events);
+        }
+        events
+    }
+
+    /// Move the chain on by a block, returning what each node does as a result.
+    pub fn tick(&mut self) -> Vec<(usize, SimEvent)> {
+        self.best += 1;
+        let churn_per_block = (f64::from(self.opts.churn_percent) / 100.0
+            * self.opts.block_time.as_secs_f64()
+            / 60.0)
+            .min(1.0);
+
+        let mut events = Vec::new();
+        for idx in 0..self.nodes.len() {
+            if let NodeState::Quiet { until } = self.nodes[idx] {
+                if self.best < until {
+                    continue;
+                }
+                self.nodes[idx] = NodeState::Live;
+            }
+            if self.rng.gen_bool(churn_per_block) {
+                // Half of the time the node goes quiet (and eventually stale), and
+                // otherwise it reconnects:
+                if self.rng.gen_bool(0.5) {
+                    let until = self.best + self.rng.gen_range(QUIET_BLOCKS);
+                    self.nodes[idx] = NodeState::Quiet { until };
+                } else {
+                    events.push((idx, SimEvent::Disconnect));
+                    self.connect(idx, &mut events);
+                }
+                continue;
+            }
+            self.report(idx, &mut events);
+        }
+        events
+    }
+
+    fn connect(&mut self, idx: usize, events: &mut Vec<(usize, SimEvent)>) {
+        let node = self.node_details(idx);
+        events.push((
+            idx,
+            SimEvent::Connect {
+                genesis_hash: self.genesis_hash,
+                node,
+            },
+        ));
+        let hwbench = node_message::NodeHwBench {
+            cpu_hashrate_score: self.rng.gen_range(800..1600),
+            memory_memcpy_score: self.rng.gen_range(10_000..20_000),
+            disk_sequential_write_score: Some(self.rng.gen_range(300..800)),
+            disk_random_write_score: Some(self.rng.gen_range(150..400)),
+        };
+        events.push((idx, SimEvent::Update(Payload::HwBench(hwbench))));
+        self.nodes[idx] = NodeState::Live;
+        self.report(idx, events);
+    }
+
+    /// What a live node sends for the current best block.
+    fn report(&mut self, idx: usize, events: &mut Vec<(usize, SimEvent)>) {
+        let best = self.block(self.best);
+        let author = Some(format!(
+            "sim-validator-{}",
+            self.best as usize % self.nodes.len()
+        ));
+        events.push((
+            idx,
+            SimEvent::Update(Payload::BlockImport(node_message::BlockImport {
+                block: best,
+                author: author.map(Into::into),
+            })),
+        ));
+
+        let finalized = self.best.saturating_sub(FINALITY_LAG);
+        if finalized > 0 {
+            events.push((
+                idx,
+                SimEvent::Update(Payload::NotifyFinalized(node_message::Finalized {
+                    hash: self.block_hash(finalized),
+                    height: finalized.to_string().into(),
+                })),
+            ));
+        }
+
+        if self.best.is_multiple_of(INTERVAL_EVERY_BLOCKS) {
+            let interval = node_message::SystemInterval {
+                peers: Some(self.rng.gen_range(8..50)),
+                txcount: Some(self.rng.gen_range(0..20)),
+                bandwidth_upload: Some(self.rng.gen_range(10_000.0..500_000.0)),
+                bandwidth_download: Some(self.rng.gen_range(10_000.0..500_000.0)),
+                finalized_height: Some(finalized),
+                finalized_hash: Some(self.block_hash(finalized)),
+                block: Some(best),
+                used_state_cache_size: Some(self.rng.gen_range(1e6..1e8)),
+                import_queue_depth: Some(0),
+                block_txcount: Some(self.rng.gen_range(0..100)),
+                block_imports: Some(self.best),
+                block_import_failures: Some(0),
+                uptime: None,
+                session: Some(self.best / 600),
+                disk_used: Some(50 * 1024 * 1024 * 1024 + self.best * 64 * 1024),
+                disk_free: Some(self.rng.gen_range(5..500) * 1024 * 1024 * 1024),
+                clock_skew: Some(self.rng.gen_range(-200..200)),
+            };
+            events.push((idx, SimEvent::Update(Payload::SystemInterval(interval))));
+        }
+    }
+
+    fn node_details(&self, idx: usize) -> NodeDetails {
+        // Keep the network ID the same across reconnects, as a real node would:
+        let network_id = format!("sim-{}-{idx}", self.opts.chain.replace(' ', "-"));
+        NodeDetails {
+            chain: self.opts.chain.as_str().into(),
+            name: format!("sim-node-{idx}").into(),
+            implementation: "Simulated Node".into(),
+            version: "1.0.0-simulated".into(),
+            // Every fourth node is a validator:
+            validator: idx
+                .is_multiple_of(4)
+                .then(|| format!("sim-validator-{idx}").into()),
+            network_id: NetworkId::from(&network_id[..network_id.len().min(64)])
+                .expect("trimmed to fit"),
+            startup_time: Some(common::time::now().to_string().into()),
+            target_os: Some("linux".into()),
+            target_arch: Some("x86_64".into()),
+            target_env: Some("gnu".into()),
+            sysinfo: Some(NodeSysInfo {
+                cpu: Some("Simulated CPU".into()),
+                memory: Some(16 * 1024 * 1024 * 1024),
+                core_count: Some(8),
+                linux_kernel: Some("6.1.0".into()),
+                linux_distro: Some("Simulated Linux".into()),
+                is_virtual_machine: Some(true),
+            }),
+            ip: None,
+            protocol_version: None,
+            relay_chain_genesis_hash: None,
+            para_id: None,
+            role: Some(NodeRole::Full),
+            custom: [("simulated".into(), "true".into())].into_iter().collect(),
+        }
+    }
+}
+
+/// A simulated node's "connection" to the aggregator.
+struct SimConnection<S> {
+    tx_to_aggregator: S,
+    /// The aggregator asks connections to close when the core restarts, so that nodes
+    /// introduce themselves again (and possibly when the core mutes them).
+    close_connection: flume::Receiver<CloseReason>,
+}
+
+async fn open_connection<S>(
+    mut tx_to_aggregator: S,
+    genesis_hash: BlockHash,
+    node: NodeDetails,
+) -> anyhow::Result<SimConnection<S>>
+where
+    S: Sink<FromWebsocket, Error = anyhow::Error> + Unpin,
+{
+    let (close_tx, close_connection) = flume::bounded(1);
+    tx_to_aggregator
+        .send(FromWebsocket::Initialize {
+            close_connection: close_tx,
+        })
+        .await?;
+    tx_to_aggregator
+        .send(FromWebsocket::Add {
+            message_id: MESSAGE_ID,
+            ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            node,
+            genesis_hash,
+            protocol_version: ProtocolVersion::V2,
+        })
+        .await?;
+    Ok(SimConnection {
+        tx_to_aggregator,
+        close_connection,
+    })
+}
+
+/// Start simulating a chain, feeding its nodes into the aggregator.
+pub fn spawn_simulation(opts: SimulateOpts, aggregator: Aggregator) {
+    log::info!(
+        "Simulating {} nodes on '{}' with a block time of {:?}",
+        opts.nodes,
+        opts.chain,
+        opts.block_time
+    );
+    tokio::spawn(async move {
+        if let Err(e) = run_simulation(opts, aggregator).await {
+            log::error!("Stopping simulation: {e}");
+        }
+    });
+}
+
+async fn run_simulation(opts: SimulateOpts, aggregator: Aggregator) -> anyhow::Result<()> {
+    let block_time = opts.block_time.max(Duration::from_millis(100));
+    let mut simulation = Simulation::new(opts, rand::random());
+    let mut connections: Vec<Option<SimConnection<_>>> =
+        (0..simulation.nodes.len()).map(|_| None).collect();
+
+    let mut events = simulation.start();
+    let mut interval = tokio::time::interval(block_time);
+    interval.tick().await;
+    loop {
+        for (idx, event) in events {
+            match event {
+                SimEvent::Connect { genesis_hash, node } => {
+                    let tx_to_aggregator = aggregator.subscribe_node();
+                    connections[idx] =
+                        Some(open_connection(tx_to_aggregator, genesis_hash, node).await?);
+                }
+                SimEvent::Update(payload) => {
+                    if let Some(conn) = &mut connections[idx] {
+                        conn.tx_to_aggregator
+                            .send(FromWebsocket::Update {
+                                message_id: MESSAGE_ID,
+                                payload,
+                            })
+                            .await?;
+                    }
+                }
+                SimEvent::Disconnect => {
+                    if let Some(mut conn) = connections[idx].take() {
+                        conn.tx_to_aggregator
+                            .send(FromWebsocket::Disconnected)
+                            .await?;
+                    }
+                }
+            }
+        }
+
+        interval.tick().await;
+        events = simulation.tick();
+
+        // Reconnect any nodes that the aggregator asked to, as real ones would:
+        for (idx, conn) in connections.iter().enumerate() {
+            let closed = conn
+                .as_ref()
+                .is_some_and(|conn| conn.close_connection.try_recv().is_ok());
+            if closed {
+                events.push((idx, SimEvent::Disconnect));
+                simulation.connect(idx, &mut events);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn opts(nodes: usize, churn_percent: u32) -> SimulateOpts {
+        SimulateOpts {
+            nodes,
+            chain: "Simulated".to_owned(),
+            block_time: Duration::from_secs(6),
+            churn_percent,
+        }
+    }
+
+    #[test]
+    fn nodes_import_and_finalize_blocks() {
+        let mut sim = Simulation::new(opts(3, 0), 0);
+        let events = sim.start();
+        let connects = events
+            .iter()
+            .filter(|(_, e)| matches!(e, SimEvent::Connect { .. }))
+            .count();
+        assert_eq!(connects, 3);
+
+        for _ in 0..10 {
+            sim.tick();
+        }
+        let events = sim.tick();
+        let imported: Vec<_> = events
+            .iter()
+            .filter_map(|(idx, e)| match e {
+                SimEvent::Update(Payload::BlockImport(import)) => Some((*idx, import.block)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(imported.len(), 3);
+        assert!(imported.iter().all(|(_, block)| block.height == 11));
+        assert_eq!(imported[0].1.hash, sim.block_hash(11));
+
+        let finalized = events.iter().find_map(|(_, e)| match e {
+            SimEvent::Update(Payload::NotifyFinalized(f)) => Some(f.height.clone()),
+            _ => None,
+        });
+        assert_eq!(finalized.as_deref(), Some("9"));
+        assert!(!sim.genesis_hash.is_zero());
+    }
+
+    #[test]
+    fn nodes_churn() {
+        let mut sim = Simulation::new(
+            SimulateOpts {
+                block_time: Duration::from_secs(60),
+                ..opts(20, 100)
+            },
+            0,
+        );
+        sim.start();
+
+        // Every node churns every block, so some go quiet and the rest reconnect:
+        let events = sim.tick();
+        let disconnects = events
+            .iter()
+            .filter(|(_, e)| matches!(e, SimEvent::Disconnect))
+            .count();
+        let quiet = sim
+            .nodes
+            .iter()
+            .filter(|s| matches!(s, NodeState::Quiet { .. }))
+            .count();
+        assert!(disconnects > 0 && quiet > 0);
+        assert_eq!(disconnects + quiet, 20);
+    }
+}
@@ -191,7 +191,7 @@ impl Server {
 
                 let pid = shards.add_with(|id| Process {
                     id,
-                    host: format!("{}", host),
+                    host: host.to_string(),
                     handle: None,
                     _channel_type: PhantomData,
                 });
@@ -199,60 +199,73 @@ impl Server {
                 Ok(pid)
             }
             // Start a new process and return that.
+            ServerMode::ShardAndCoreMode { .. } => {
+                let core_host = self.core.host.clone();
+                self.add_shard_for(&core_host).await
+            }
+        }
+    }
+
+    /// Start a new shard that connects to the core at the host given, for instance
+    /// the address of one of its namespaces rather than the default one. This is only
+    /// possible if we're in charge of spawning processes.
+    pub async fn add_shard_for(&mut self, core_host: &str) -> Result<ProcessId, Error> {
+        let (shard_command, shards) = match &mut self.mode {
             ServerMode::ShardAndCoreMode {
                 shard_command,
                 shards,
-            } => {
-                // Where is the URI we'll want to submit things to?
-                let core_shard_submit_uri = format!("http://{}/shard_submit", self.core.host);
-
-                let mut shard_cmd: TokioCommand = shard_command.clone().into();
-                shard_cmd
-                    .arg("--listen")
-                    .arg("127.0.0.1:0") // 0 to have a port picked by the kernel
-                    .arg("--log")
-                    .arg("info")
-                    .arg("--core")
-                    .arg(core_shard_submit_uri)
-                    .kill_on_drop(true)
-                    .stdout(std::process::Stdio::piped())
-                    .stdin(std::process::Stdio::piped());
-
-                let mut shard_process = shard_cmd.spawn()?;
-                let mut child_stdout = shard_process.stdout.take().expect("shard stdout");
-                let shard_port = utils::get_port(&mut child_stdout)
-                    .await
-                    .map_err(|e| Error::ErrorObtainingPort(e))?;
-
-                // Attempt to wait until we've received word that the shard is connected to the
-                // core before continuing. If we don't wait for this, the connection may happen
-                // after we've attempted to connect node sockets, and they would be booted and
-                // made to reconnect, which we don't want to deal with in general.
-                let _ = utils::wait_for_line_containing(
-                    &mut child_stdout,
-                    |s| s.contains("Connected to telemetry core"),
-                    std::time::Duration::from_secs(5),
-                )
-                .await;
-
-                // Since we're piping stdout from the child process, we need somewhere for it to go
-                // else the process will get stuck when it tries to produce output:
-                if self.log_output {
-                    utils::drain(child_stdout, tokio::io::stderr());
-                } else {
-                    utils::drain(child_stdout, tokio::io::sink());
-                }
+            } => (shard_command, shards),
+            _ => return Err(Error::CannotAddShard),
+        };
 
-                let pid = shards.add_with(|id| Process {
-                    id,
-                    host: format!("127.0.0.1:{}", shard_port),
-                    handle: Some(shard_process),
-                    _channel_type: PhantomData,
-                });
+        // Where is the URI we'll want to submit things to?
+        let core_shard_submit_uri = format!("http://{}/shard_submit", core_host);
 
-                Ok(pid)
-            }
+        let mut shard_cmd: TokioCommand = shard_command.clone().into();
+        shard_cmd
+            .arg("--listen")
+            .arg("127.0.0.1:0") // 0 to have a port picked by the kernel
+            .arg("--log")
+            .arg("info")
+            .arg("--core")
+            .arg(core_shard_submit_uri)
+            .kill_on_drop(true)
+            .stdout(std::process::Stdio::piped())
+            .stdin(std::process::Stdio::piped());
+
+        let mut shard_process = shard_cmd.spawn()?;
+        let mut child_stdout = shard_process.stdout.take().expect("shard stdout");
+        let shard_port = utils::get_port(&mut child_stdout)
+            .await
+            .map_err(Error::ErrorObtainingPort)?;
+
+        // Attempt to wait until we've received word that the shard is connected to the
+        // core before continuing. If we don't wait for this, the connection may happen
+        // after we've attempted to connect node sockets, and they would be booted and
+        // made to reconnect, which we don't want to deal with in general.
+        let _ = utils::wait_for_line_containing(
+            &mut child_stdout,
+            |s| s.contains("Connected to telemetry core"),
+            std::time::Duration::from_secs(5),
+        )
+        .await;
+
+        // Since we're piping stdout from the child process, we need somewhere for it to go
+        // else the process will get stuck when it tries to produce output:
+        if self.log_output {
+            utils::drain(child_stdout, tokio::io::stderr());
+        } else {
+            utils::drain(child_stdout, tokio::io::sink());
         }
+
+        let pid = shards.add_with(|id| Process {
+            id,
+            host: format!("127.0.0.1:{}", shard_port),
+            handle: Some(shard_process),
+            _channel_type: PhantomData,
+        });
+
+        Ok(pid)
     }
 
     /// Start a server.
@@ -332,7 +345,7 @@ impl Server {
         let mut child_stdout = child.stdout.take().expect("core stdout");
         let core_port = utils::get_port(&mut child_stdout)
             .await
-            .map_err(|e| Error::ErrorObtainingPort(e))?;
+            .map_err(Error::ErrorObtainingPort)?;
 
         // Since we're piping stdout from the child process, we need somewhere for it to go
         // else the process will get stuck when it tries to produce output:
@@ -502,10 +515,10 @@ impl Command {
     }
 }
 
-impl Into<TokioCommand> for Command {
-    fn into(self) -> TokioCommand {
-        let mut cmd = TokioCommand::new(self.command);
-        cmd.args(self.args);
+impl From<Command> for TokioCommand {
+    fn from(val: Command) -> Self {
+        let mut cmd = TokioCommand::new(val.command);
+        cmd.args(val.args);
         cmd
     }
 }
@@ -18,54 +18,33 @@ use super::commands;
 use crate::server::{self, Command, Server};
 
 /// Options for the server
+#[derive(Default)]
 pub struct ServerOpts {
     pub release_mode: bool,
     pub log_output: bool,
 }
 
-impl Default for ServerOpts {
-    fn default() -> Self {
-        Self {
-            release_mode: false,
-            log_output: false,
-        }
-    }
-}
-
 /// Additional options to pass to the core command.
+#[derive(Default)]
 pub struct CoreOpts {
     pub feed_timeout: Option<u64>,
     pub worker_threads: Option<usize>,
     pub num_aggregators: Option<usize>,
-}
-
-impl Default for CoreOpts {
-    fn default() -> Self {
-        Self {
-            feed_timeout: None,
-            worker_threads: None,
-            num_aggregators: None,
-        }
-    }
+    /// Namespaces to serve, each in the form `<name>=<address>`.
+    pub namespaces: Vec<String>,
 }
 
 /// Additional options to pass to the shard command.
+#[derive(Default)]
 pub struct ShardOpts {
     pub max_nodes_per_connection: Option<usize>,
     pub max_node_data_per_second: Option<usize>,
+    pub max_node_message_size: Option<usize>,
+    pub max_malformed_messages: Option<usize>,
     pub node_block_seconds: Option<u64>,
     pub worker_threads: Option<usize>,
-}
-
-impl Default for ShardOpts {
-    fn default() -> Self {
-        Self {
-            max_nodes_per_connection: None,
-            max_node_data_per_second: None,
-            node_block_seconds: None,
-            worker_threads: None,
-        }
-    }
+    pub node_idle_timeout_secs: Option<u64>,
+    pub close_rejected_connections: bool,
 }
 
 /// Start a telemetry server. We'll use `cargo run` by default, but you can also provide
@@ -105,7 +84,7 @@ pub async fn start_server(
         let feed_host = feed_host.trim().into();
         let submit_hosts: Vec<_> = std::env::var("TELEMETRY_SUBMIT_HOSTS")
             .map(|var| var.split(",").map(|var| var.trim().into()).collect())
-            .unwrap_or(Vec::new());
+            .unwrap_or_default();
         return Server::start(server::StartOpts::ConnectToExisting {
             feed_host,
             submit_hosts,
@@ -117,7 +96,7 @@ pub async fn start_server(
 
     // Build the shard command
     let mut shard_command = std::env::var("TELEMETRY_SHARD_BIN")
-        .map(|val| Command::new(val))
+        .map(Command::new)
         .unwrap_or_else(|_| {
             commands::cargo_run_telemetry_shard(server_opts.release_mode)
                 .expect("must be in rust workspace to run shard command")
@@ -134,6 +113,16 @@ pub async fn start_server(
             .arg("--max-node-data-per-second")
             .arg(val.to_string());
     }
+    if let Some(val) = shard_opts.max_node_message_size {
+        shard_command = shard_command
+            .arg("--max-node-message-size")
+            .arg(val.to_string());
+    }
+    if let Some(val) = shard_opts.max_malformed_messages {
+        shard_command = shard_command
+            .arg("--max-malformed-messages")
+            .arg(val.to_string());
+    }
     if let Some(val) = shard_opts.node_block_seconds {
         shard_command = shard_command
             .arg("--node-block-seconds")
@@ -142,10 +131,18 @@ pub async fn start_server(
     if let Some(val) = shard_opts.worker_threads {
         shard_command = shard_command.arg("--worker-threads").arg(val.to_string());
     }
+    if let Some(val) = shard_opts.node_idle_timeout_secs {
+        shard_command = shard_command
+            .arg("--node-idle-timeout-secs")
+            .arg(val.to_string());
+    }
+    if shard_opts.close_rejected_connections {
+        shard_command = shard_command.arg("--close-rejected-connections");
+    }
 
     // Build the core command
     let mut core_command = std::env::var("TELEMETRY_CORE_BIN")
-        .map(|val| Command::new(val))
+        .map(Command::new)
         .unwrap_or_else(|_| {
             commands::cargo_run_telemetry_core(server_opts.release_mode)
                 .expect("must be in rust workspace to run core command")
@@ -161,6 +158,9 @@ pub async fn start_server(
     if let Some(val) = core_opts.num_aggregators {
         core_command = core_command.arg("--num-aggregators").arg(val.to_string());
     }
+    for namespace in core_opts.namespaces {
+        core_command = core_command.arg("--namespace").arg(namespace);
+    }
 
     // Start the server
     Server::start(server::StartOpts::ShardAndCore {
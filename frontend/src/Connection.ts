@@ -279,6 +279,19 @@ export class Connection {
           break;
         }
 
+        case ACTIONS.NodeStatsDelta: {
+          const [id, nodeStats] = message.payload;
+
+          nodes.mutAndMaybeSort(
+            id,
+            (node) => node.updatePartialStats(nodeStats),
+            (sortByColumn === PeersColumn && nodeStats[0] != null) ||
+              (sortByColumn === TxsColumn && nodeStats[1] != null)
+          );
+
+          break;
+        }
+
         case ACTIONS.NodeHardware: {
           const [id, nodeHardware] = message.payload;
 
@@ -26,6 +26,7 @@ import {
   NodeCount,
   NodeDetails,
   NodeStats,
+  PartialNodeStats,
   NodeIO,
   NodeHardware,
   NodeLocation,
@@ -64,6 +65,7 @@ export const ACTIONS = {
   StaleNode: 0x14 as const,
   NodeIO: 0x15 as const,
   ChainStatsUpdate: 0x16 as const,
+  NodeStatsDelta: 0x1f as const,
 };
 
 export type Action = typeof ACTIONS[keyof typeof ACTIONS];
@@ -80,7 +82,7 @@ interface FeedVersionMessage extends MessageBase {
 
 interface BestBlockMessage extends MessageBase {
   action: typeof ACTIONS.BestBlock;
-  payload: [BlockNumber, Timestamp, Maybe<Milliseconds>];
+  payload: [BlockNumber, Timestamp, Maybe<Milliseconds>, BlockHash];
 }
 
 interface BestFinalizedBlockMessage extends MessageBase {
@@ -127,6 +129,11 @@ interface NodeStatsMessage extends MessageBase {
   payload: [NodeId, NodeStats];
 }
 
+interface NodeStatsDeltaMessage extends MessageBase {
+  action: typeof ACTIONS.NodeStatsDelta;
+  payload: [NodeId, PartialNodeStats];
+}
+
 interface NodeHardwareMessage extends MessageBase {
   action: typeof ACTIONS.NodeHardware;
   payload: [NodeId, NodeHardware];
@@ -207,6 +214,7 @@ export type Message =
   | ImportedBlockMessage
   | FinalizedBlockMessage
   | NodeStatsMessage
+  | NodeStatsDeltaMessage
   | NodeHardwareMessage
   | TimeSyncMessage
   | AddedChainMessage
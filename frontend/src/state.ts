@@ -70,6 +70,7 @@ export class Node {
   public pinned: boolean;
   public peers: Types.PeerCount;
   public txs: Types.TransactionCount;
+  public importQueue: Types.ImportQueueDepth;
   public upload: Types.BytesPerSecond[];
   public download: Types.BytesPerSecond[];
   public stateCacheSize: Types.Bytes[];
@@ -132,14 +133,25 @@ export class Node {
   }
 
   public updateStats(stats: Types.NodeStats) {
-    const [peers, txs] = stats;
+    const [peers, txs, importQueue] = stats;
 
     this.peers = peers;
     this.txs = txs;
+    this.importQueue = importQueue;
 
     this.trigger();
   }
 
+  public updatePartialStats(stats: Types.PartialNodeStats) {
+    const [peers, txs, importQueue] = stats;
+
+    this.updateStats([
+      peers ?? this.peers,
+      txs ?? this.txs,
+      importQueue ?? this.importQueue,
+    ]);
+  }
+
   public updateIO(io: Types.NodeIO) {
     const [stateCacheSize] = io;
 